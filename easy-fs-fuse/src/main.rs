@@ -1,15 +1,25 @@
 #![feature(assert_matches)]
-use easy_fs::{BlockDevice, EasyFileSystem, BLOCK_SZ};
+use easy_fs::{BlockDevice, EasyFileSystem, EfsError, InodeReader, BLOCK_CACHE_SIZE, BLOCK_SZ};
 use structopt::StructOpt;
 
 use std::{
     assert_matches::assert_matches,
+    collections::BTreeMap,
     fs::{read_dir, File, OpenOptions},
-    io::{Error, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    io::{Error, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// Pages the kernel swaps out to go in this file at the root, 4MiB of them
+const SWAP_FILE: &str = "swap";
+const SWAP_SIZE: usize = 4 << 20;
+
 struct BlockFile(Mutex<File>);
 
 impl BlockDevice for BlockFile {
@@ -35,8 +45,13 @@ impl BlockDevice for BlockFile {
 #[derive(Debug, StructOpt)]
 #[structopt(name = "EasyFileSystem packe")]
 struct Opt {
-    #[structopt(short, long, help = "Executable source dir(with backslash)")]
-    source: PathBuf,
+    #[structopt(
+        short,
+        long,
+        help = "Executable source dir(with backslash)",
+        required_unless = "check"
+    )]
+    source: Option<PathBuf>,
     #[structopt(
         short,
         long,
@@ -44,11 +59,37 @@ struct Opt {
         parse(from_os_str)
     )]
     target: PathBuf,
+    #[structopt(
+        long,
+        help = "Check directories of fs.img in target dir instead of packing"
+    )]
+    check: bool,
+    #[structopt(
+        short,
+        long,
+        help = "Threads reading apps from the host, as many as CPUs if not given"
+    )]
+    jobs: Option<usize>,
+    #[structopt(
+        long,
+        help = "Zero all timestamps, so the same apps always make the same fs.img"
+    )]
+    deterministic: bool,
+    #[structopt(
+        long,
+        help = "Compare the files in fs.img with the apps packed after packing"
+    )]
+    verify: bool,
 }
 
-fn easy_fs_pack() -> std::io::Result<()> {
-    let opt = Opt::from_args();
-    println!("easy-fs-fuse: {opt:?}");
+/// `e` as an io error, to fail packing or checking with
+fn efs_error(e: EfsError) -> Error {
+    Error::other(format!("easy-fs: {e:?}"))
+}
+
+fn easy_fs_pack(opt: &Opt) -> std::io::Result<()> {
+    let source = opt.source.as_ref().unwrap();
+    let start = Instant::now();
 
     let block_file = Arc::new(BlockFile(Mutex::new({
         let path = opt.target.join("fs.img");
@@ -56,15 +97,58 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open(path)?;
         f.set_len(32 * 2048 * 512)?;
         f
     })));
     // 32MiB block dev; bitmap 1 block == at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 32 * 2048, 1);
+    let efs =
+        EasyFileSystem::create(block_file, 32 * 2048, 1, BLOCK_CACHE_SIZE).map_err(efs_error)?;
+    if !opt.deterministic {
+        efs.lock().set_clock(host_clock);
+    }
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
-    let apps = read_dir(opt.source.as_path())?
-        .into_iter()
+    let apps = list_apps(source)?;
+    let jobs = opt
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    println!("easy-fs-use >>>>");
+    let mut size_total = 0;
+    let mut progress = Progress::new(apps.len());
+    read_apps(&opt.target, &apps, jobs, |app, all_data| {
+        progress.clear();
+        println!(
+            "easy-fs-fuse: + {app} {}B {}KB",
+            all_data.len(),
+            all_data.len() / 1024
+        );
+        size_total += all_data.len();
+        // create a file in easy-fs
+        let inode = root_inode.create(app).map_err(efs_error)?;
+        // write data to easy-fs
+        inode.append(&all_data).map_err(efs_error)?;
+        progress.step();
+        Ok(())
+    })?;
+    progress.clear();
+    // room for swap taken now, so it's there however full the fs gets
+    let swap = root_inode.create(SWAP_FILE).map_err(efs_error)?;
+    swap.truncate(SWAP_SIZE).map_err(efs_error)?;
+    swap.chmod(0o600);
+    println!("easy-fs-fuse: + {SWAP_FILE} {}KB", SWAP_SIZE / 1024);
+    println!(
+        "easy-fs-use (total: {}KB in {:.2?}, {jobs} jobs) <<<<",
+        size_total / 1024,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Names of the apps in `source`, sorted so they're packed in the same order, and get the
+/// same inodes and blocks, whatever order the host lists them in
+fn list_apps(source: &Path) -> std::io::Result<Vec<String>> {
+    let mut apps = read_dir(source)?
         .map(|dirent| {
             let mut fname = dirent?
                 .file_name()
@@ -76,64 +160,238 @@ fn easy_fs_pack() -> std::io::Result<()> {
             Ok(fname)
         })
         .collect::<std::io::Result<Vec<_>>>()?;
-    println!("easy-fs-use >>>>");
-    let mut size_total = 0;
+    apps.sort();
+    Ok(apps)
+}
+
+/// Wall time of the host in ns since the epoch, for timestamps of the packed files
+fn host_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Read the built `apps` from `target` by `jobs` threads, each handed to `f` in the order of
+/// `apps` as soon as it and all before it are read; those not built are skipped. Only the
+/// reading goes in parallel, the fs is written by `f` alone on this thread.
+fn read_apps(
+    target: &Path,
+    apps: &[String],
+    jobs: usize,
+    mut f: impl FnMut(&str, Vec<u8>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            let tx = tx.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= apps.len() {
+                    break;
+                }
+                // load built app only from host file system
+                let data =
+                    std::fs::read(target.join(&apps[i]))
+                        .map(Some)
+                        .or_else(|e| match e.kind() {
+                            ErrorKind::NotFound => Ok(None),
+                            _ => Err(e),
+                        });
+                if tx.send((i, data)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        // read ahead of the one to write next wait here
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        let result = rx.iter().try_for_each(|(i, data)| {
+            pending.insert(i, data);
+            while let Some(data) = pending.remove(&written) {
+                if let Some(data) = data? {
+                    f(&apps[written], data)?;
+                }
+                written += 1;
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            // the rest needn't be read
+            next.store(apps.len(), Ordering::Relaxed);
+        }
+        result
+    })
+}
+
+/// Bar of apps packed so far, drawn on stderr when that's a terminal
+struct Progress {
+    done: usize,
+    total: usize,
+    shown: bool,
+}
+
+impl Progress {
+    const WIDTH: usize = 40;
+
+    fn new(total: usize) -> Self {
+        Self {
+            done: 0,
+            total,
+            shown: std::io::stderr().is_terminal(),
+        }
+    }
+
+    fn step(&mut self) {
+        self.done += 1;
+        if self.shown {
+            let filled = Self::WIDTH * self.done / self.total.max(1);
+            eprint!(
+                "[{}{}] {}/{}",
+                "#".repeat(filled),
+                " ".repeat(Self::WIDTH - filled),
+                self.done,
+                self.total
+            );
+        }
+    }
+
+    /// Wipe the bar, for lines printed in its place
+    fn clear(&self) {
+        if self.shown {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// Report what's wrong in the directory tree of an existing fs.img, fails if anything is
+fn easy_fs_check(opt: &Opt) -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(opt.target.join("fs.img"))?,
+    )));
+    let efs = EasyFileSystem::open(block_file, BLOCK_CACHE_SIZE).map_err(efs_error)?;
+    let problems = EasyFileSystem::check_dirs(&efs);
+    for problem in problems.iter() {
+        println!("easy-fs-fuse: ! {problem:?}");
+    }
+    match problems.len() {
+        0 => Ok(()),
+        n => Err(Error::other(format!("{n} problems in directories"))),
+    }
+}
+
+/// Compare the root of fs.img in target dir with the built apps of source dir, fails if any
+/// differs, is missing, or the image has files no app is for
+fn easy_fs_verify(opt: &Opt) -> std::io::Result<()> {
+    let apps = list_apps(opt.source.as_ref().unwrap())?;
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(opt.target.join("fs.img"))?,
+    )));
+    let efs = EasyFileSystem::open(block_file, BLOCK_CACHE_SIZE).map_err(efs_error)?;
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let mut problems = 0;
+    let mut packed = vec![".".to_string(), "..".to_string(), SWAP_FILE.to_string()];
     for app in apps {
-        // load built app only from host file system
         let path = opt.target.join(&app);
-        // skip un-built
         if !std::fs::exists(&path)? {
             continue;
         }
-        let mut host_file = File::open(path)?;
-        let mut all_data = Vec::new();
-        host_file.read_to_end(&mut all_data)?;
-        println!(
-            "easy-fs-fuse: + {app} {}B {}KB",
-            all_data.len(),
-            all_data.len() / 1024
-        );
-        size_total += all_data.len();
-        // create a file in easy-fs
-        let inode = root_inode.create(&app).unwrap();
-        // write data to easy-fs
-        inode.write_at(0, &all_data);
+        let host_data = std::fs::read(path)?;
+        let inode = match root_inode.find(&app) {
+            Ok(inode) => inode,
+            Err(_) => {
+                println!("easy-fs-fuse: ! {app} missing");
+                problems += 1;
+                continue;
+            }
+        };
+        let mut data = Vec::new();
+        InodeReader::new(&inode, 0).read_to_end(&mut data);
+        if data != host_data {
+            println!(
+                "easy-fs-fuse: ! {app} differs, {}B packed, {}B on host",
+                data.len(),
+                host_data.len()
+            );
+            problems += 1;
+        }
+        packed.push(app);
+    }
+    for name in root_inode.ls() {
+        if !packed.contains(&name) {
+            println!("easy-fs-fuse: ! {name} not an app");
+            problems += 1;
+        }
+    }
+    match problems {
+        0 => Ok(()),
+        n => Err(Error::other(format!("{n} files differ from the apps"))),
     }
-    println!("easy-fs-use (total: {}KB) <<<<", size_total / 1024);
-    Ok(())
 }
 
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+    let opt = Opt::from_args();
+    println!("easy-fs-fuse: {opt:?}");
+    if opt.check {
+        easy_fs_check(&opt).expect("Error when checking easy-fs!");
+    } else {
+        easy_fs_pack(&opt).expect("Error when packing easy-fs!");
+        if opt.verify {
+            easy_fs_verify(&opt).expect("Error when verifying easy-fs!");
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use easy_fs::Inode;
+    use easy_fs::{
+        block_cache_stats, dentry_cache_stats, DirProblem, EfsError, Inode, InodeWriter, Quota,
+        DENTRY_CACHE_SIZE, DIRENT_SZ, MAX_FILE_SIZE, QUOTA_GRACE_NS,
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Blank image of 8192 blocks at `target/<name>.img`, one per test as they run in parallel
+    fn test_image(name: &str) -> Arc<BlockFile> {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(format!("target/{name}.img"))
+            .unwrap();
+        f.set_len(8192 * 512).unwrap();
+        Arc::new(BlockFile(Mutex::new(f)))
+    }
+
+    /// Root of a fresh easy-fs of 4096 blocks on `test_image(name)`
+    fn test_fs(name: &str) -> Arc<Inode> {
+        let block_file = test_image(name);
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file, BLOCK_CACHE_SIZE).unwrap();
+        Arc::new(EasyFileSystem::root_inode(&efs))
+    }
 
     #[test]
     fn efs_test() -> std::io::Result<()> {
-        let block_file = Arc::new(BlockFile(Mutex::new({
-            let f = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open("target/fs.img")?;
-            f.set_len(8192 * 512).unwrap();
-            f
-        })));
-        EasyFileSystem::create(block_file.clone(), 4096, 1);
-        let efs = EasyFileSystem::open(block_file.clone());
-        let root_inode = EasyFileSystem::root_inode(&efs);
-        root_inode.create("filea");
-        root_inode.create("fileb");
+        let root_inode = test_fs("efs_test");
+        root_inode.create("filea").unwrap();
+        root_inode.create("fileb").unwrap();
         for name in root_inode.ls() {
             println!("{}", name);
         }
         let filea = root_inode.find("filea").unwrap();
         let greet_str = "Hello, world!";
-        filea.write_at(0, greet_str.as_bytes());
+        filea.write_at(0, greet_str.as_bytes()).unwrap();
         //let mut buffer = [0u8; 512];
         let mut buffer = [0u8; 233];
         let len = filea.read_at(0, &mut buffer);
@@ -146,9 +404,9 @@ mod tests {
             use rand;
             // random digit
             for _ in 0..len {
-                str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
+                str.push(char::from(b'0' + rand::random::<u8>() % 10));
             }
-            filea.write_at(0, str.as_bytes());
+            filea.write_at(0, str.as_bytes()).unwrap();
             let mut read_buffer = [0u8; 127];
             let mut offset = 0usize;
             let mut read_str = String::new();
@@ -175,19 +433,37 @@ mod tests {
         Ok(())
     }
 
-    fn read_string(file: &Arc<Inode>) -> String {
-        let mut read_buffer = [0u8; 512];
-        let mut offset = 0usize;
-        let mut read_str = String::new();
-        loop {
-            let len = file.read_at(offset, &mut read_buffer);
-            if len == 0 {
-                break;
+    /// Drops every write after the first `writes_left`, as if power was cut there
+    struct CrashDevice {
+        file: Arc<BlockFile>,
+        writes_left: Mutex<usize>,
+        crashed: Mutex<bool>,
+    }
+
+    impl BlockDevice for CrashDevice {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            self.file.read_block(block_id, buf);
+        }
+
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            let mut writes_left = self.writes_left.lock().unwrap();
+            if *writes_left == 0 {
+                *self.crashed.lock().unwrap() = true;
+                return;
             }
-            offset += len;
-            read_str.push_str(core::str::from_utf8(&read_buffer[..len]).unwrap());
+            *writes_left -= 1;
+            self.file.write_block(block_id, buf);
         }
-        read_str
+
+        fn handle_irq(&self) {
+            unimplemented!()
+        }
+    }
+
+    fn read_string(file: &Arc<Inode>) -> String {
+        let mut data = Vec::new();
+        InodeReader::new(file, 0).read_to_end(&mut data);
+        String::from_utf8(data).unwrap()
     }
 
     fn tree(inode: &Arc<Inode>, name: &str, depth: usize) {
@@ -214,27 +490,16 @@ mod tests {
             }
             let child = inode
                 .find(&f)
-                .expect(&format!("{f} in `ls {name}`(d={depth}) but cannot find"));
+                .unwrap_or_else(|_| panic!("{f} in `ls {name}`(d={depth}) but cannot find"));
             tree(&child, &f, depth + 1);
         }
     }
 
     #[test]
     fn efs_dir_test() -> std::io::Result<()> {
-        let block_file = Arc::new(BlockFile(Mutex::new({
-            let f = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open("target/fs.img")?;
-            f.set_len(8192 * 512).unwrap();
-            f
-        })));
-        EasyFileSystem::create(block_file.clone(), 4096, 1);
-        let efs = EasyFileSystem::open(block_file.clone());
-        let root = Arc::new(EasyFileSystem::root_inode(&efs));
-        root.create("f1");
-        root.create("f2");
+        let root = test_fs("efs_dir_test");
+        root.create("f1").unwrap();
+        root.create("f2").unwrap();
 
         let d1 = root.create_dir("d1").unwrap();
 
@@ -246,81 +511,1015 @@ mod tests {
 
         let f3_content = "3333333";
         let f4_content = "4444444444444444444";
-        f3.write_at(0, f3_content.as_bytes());
-        f4.write_at(0, f4_content.as_bytes());
+        f3.write_at(0, f3_content.as_bytes()).unwrap();
+        f4.write_at(0, f4_content.as_bytes()).unwrap();
 
         assert_eq!(read_string(&d1.find("f3").unwrap()), f3_content);
         assert_eq!(read_string(&root.find("/d1/f3").unwrap()), f3_content);
         assert_eq!(read_string(&d2.find("f4").unwrap()), f4_content);
         assert_eq!(read_string(&d1.find("d2/f4").unwrap()), f4_content);
         assert_eq!(read_string(&root.find("/d1/d2/f4").unwrap()), f4_content);
-        assert!(f3.find("whatever").is_none());
+        assert!(f3.find("whatever").is_err());
         Ok(())
     }
 
     #[test]
     fn efs_dir_dot_test() -> std::io::Result<()> {
-        let block_file = Arc::new(BlockFile(Mutex::new({
-            let f = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open("target/fs.img")?;
-            f.set_len(8192 * 512).unwrap();
-            f
-        })));
-        EasyFileSystem::create(block_file.clone(), 4096, 1);
-        let efs = EasyFileSystem::open(block_file.clone());
-        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let root = test_fs("efs_dir_dot_test");
 
-        root.create("file0");
-        root.create("file1");
+        root.create("file0").unwrap();
+        root.create("file1").unwrap();
         println!("ls /\n{:?}", root.ls());
 
-        if let Some(root_dot) = root.find(".") {
+        if let Ok(root_dot) = root.find(".") {
             println!("ls /.\n{:?}", root_dot.ls());
         }
-        if let Some(root_ddot) = root.find("..") {
+        if let Ok(root_ddot) = root.find("..") {
             println!("ls /..\n{:?}", root_ddot.ls());
-            if let Some(ddot_of_root_ddot) = root_ddot.find("..") {
+            if let Ok(ddot_of_root_ddot) = root_ddot.find("..") {
                 println!("ls /../..\n{:?}", ddot_of_root_ddot.ls());
             }
         }
-        if let Some(d) = root.find("./.././..") {
+        if let Ok(d) = root.find("./.././..") {
             println!("ls*(at /) ./.././..\n{:?}", d.ls());
         }
 
         println!("\ncreate dir0");
         let dir0 = root.create_dir("dir0").unwrap();
-        dir0.create("dir0_file0");
+        dir0.create("dir0_file0").unwrap();
         println!("ls dir0\n{:?}", dir0.ls());
-        if let Some(dot) = dir0.find(".") {
+        if let Ok(dot) = dir0.find(".") {
             println!("ls dir0/.\n{:?}", dot.ls());
         }
-        if let Some(ddot) = dir0.find("..") {
+        if let Ok(ddot) = dir0.find("..") {
             println!("ls dir0/..\n{:?}", ddot.ls());
-            if let Some(ddot_of_ddot) = ddot.find("..") {
+            if let Ok(ddot_of_ddot) = ddot.find("..") {
                 println!("ls dir0/../..\n{:?}", ddot_of_ddot.ls());
             }
         }
-        if let Some(d) = dir0.find("./.././..") {
+        if let Ok(d) = dir0.find("./.././..") {
             println!("ls*(at dir0) ./.././..\n{:?}", d.ls()); // eqv. ls /
         }
         Ok(())
     }
 
+    #[test]
+    fn efs_symlink_test() -> std::io::Result<()> {
+        let root = test_fs("efs_symlink_test");
+        let d1 = root.create_dir("d1").unwrap();
+        let f1 = d1.create("f1").unwrap();
+        let content = "111111";
+        f1.write_at(0, content.as_bytes()).unwrap();
+
+        // relative to the dir holding the link
+        let rel = d1.symlink("rel", "f1").unwrap();
+        assert!(rel.is_symlink());
+        assert_eq!(rel.readlink().as_deref(), Ok("f1"));
+        assert_eq!(read_string(&root.find("d1/rel").unwrap()), content);
+        // absolute, and a link to a dir in the middle of a path
+        root.symlink("abs", "/d1").unwrap();
+        assert_eq!(read_string(&root.find("abs/f1").unwrap()), content);
+        assert_eq!(read_string(&root.find("/abs/rel").unwrap()), content);
+        // chain
+        root.symlink("chain", "abs/rel").unwrap();
+        assert_eq!(read_string(&root.find("chain").unwrap()), content);
+        assert_eq!(f1.readlink(), Err(EfsError::Invalid));
+        // looked up, not followed
+        let chain = root.lookup_link("chain").unwrap();
+        assert!(chain.is_symlink());
+        assert_eq!(chain.readlink().as_deref(), Ok("abs/rel"));
+        assert_eq!(root.lookup_link("nowhere").err(), Some(EfsError::NotFound));
+        assert_eq!(f1.lookup_link("x").err(), Some(EfsError::NotDir));
+
+        // dangling
+        root.symlink("dangling", "nowhere").unwrap();
+        assert_eq!(root.find("dangling").err(), Some(EfsError::NotFound));
+        // loop
+        root.symlink("loop0", "loop1").unwrap();
+        root.symlink("loop1", "loop0").unwrap();
+        assert_eq!(root.find("loop0").err(), Some(EfsError::Loop));
+        // name taken, empty target
+        assert_eq!(root.symlink("abs", "d1").err(), Some(EfsError::Exists));
+        assert_eq!(root.symlink("empty", "").err(), Some(EfsError::Invalid));
+
+        // removing a link leaves the target alone
+        d1.unlink("rel").unwrap();
+        assert!(root.find("chain").is_err());
+        assert_eq!(read_string(&root.find("abs/f1").unwrap()), content);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_truncate_test() -> std::io::Result<()> {
+        let root = test_fs("efs_truncate_test");
+        let file = root.create("file").unwrap();
+        let data: Vec<u8> = (0..(400 * BLOCK_SZ)).map(|i| (i % 251) as u8 + 1).collect();
+
+        let check = |size: usize, kept: usize| {
+            assert_eq!(file.get_size(), size);
+            let mut buf = vec![0u8; size];
+            assert_eq!(file.read_at(0, &mut buf), size);
+            assert_eq!(&buf[..kept], &data[..kept]);
+            assert!(buf[kept..].iter().all(|&b| b == 0));
+        };
+        // shrink within indirect2, into indirect1, into direct, to nothing
+        for size in [
+            300 * BLOCK_SZ + 3,
+            100 * BLOCK_SZ,
+            10 * BLOCK_SZ + BLOCK_SZ / 2,
+            0,
+        ] {
+            file.clear();
+            file.write_at(0, &data).unwrap();
+            file.truncate(size).unwrap();
+            check(size, size);
+            // grows back with zeros, even where the last block was cut
+            file.truncate(size + 200 * BLOCK_SZ).unwrap();
+            check(size + 200 * BLOCK_SZ, size);
+        }
+
+        // blocks freed are usable again, 20 rounds take way more than the 4096 of the image
+        file.clear();
+        for _ in 0..20 {
+            file.write_at(0, &data).unwrap();
+            file.truncate(BLOCK_SZ / 2).unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn efs_error_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_error_test");
+        // no room for data, nothing on the device
+        assert_eq!(
+            EasyFileSystem::create(block_file.clone(), 64, 1, BLOCK_CACHE_SIZE).err(),
+            Some(EfsError::Invalid)
+        );
+        block_file.write_block(0, &[0u8; BLOCK_SZ]);
+        assert_eq!(
+            EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).err(),
+            Some(EfsError::Corrupt)
+        );
+
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let file = root.create("file").unwrap();
+        assert_eq!(root.create("file").err(), Some(EfsError::Exists));
+        assert_eq!(
+            root.create(&"n".repeat(28)).err(),
+            Some(EfsError::NameTooLong)
+        );
+        assert_eq!(file.create("f").err(), Some(EfsError::NotDir));
+        assert_eq!(file.find("f").err(), Some(EfsError::NotDir));
+        assert_eq!(root.write_at(0, b"x"), Err(EfsError::IsDir));
+        assert_eq!(
+            file.write_at(MAX_FILE_SIZE, b"x"),
+            Err(EfsError::FileTooLarge)
+        );
+        assert_eq!(file.write_at(usize::MAX, b"x"), Err(EfsError::FileTooLarge));
+
+        // more than the image holds: nothing written, and the blocks taken on the way are back
+        let data = vec![1u8; 6000 * BLOCK_SZ];
+        assert_eq!(file.write_at(0, &data), Err(EfsError::NoSpace));
+        assert_eq!(file.get_size(), 0);
+        assert_eq!(file.truncate(data.len()), Err(EfsError::NoSpace));
+        assert_eq!(
+            file.write_at(0, &data[..2000 * BLOCK_SZ]),
+            Ok(2000 * BLOCK_SZ)
+        );
+        // full, even for a dir's first block
+        let filler = root.create("filler").unwrap();
+        while filler.write_at(filler.get_size(), &[2u8; BLOCK_SZ]).is_ok() {}
+        assert_eq!(root.create_dir("d").err(), Some(EfsError::NoSpace));
+        assert!(root.find("d").is_err());
+        root.unlink("filler").unwrap();
+        root.create_dir("d").unwrap();
+        assert_eq!(EasyFileSystem::check_dirs(&efs), []);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_rename_test() -> std::io::Result<()> {
+        let root = test_fs("efs_rename_test");
+        let d1 = root.create_dir("d1").unwrap();
+        let d2 = root.create_dir("d2").unwrap();
+        let f1 = d1.create("f1").unwrap();
+        f1.write_at(0, "111111".as_bytes()).unwrap();
+
+        // same dir
+        d1.rename("f1", &d1, "f2").unwrap();
+        assert!(d1.find("f1").is_err());
+        assert_eq!(read_string(&d1.find("f2").unwrap()), "111111");
+        // cross dir, the inode stays the same
+        d1.rename("f2", &d2, "f3").unwrap();
+        assert!(d1.find("f2").is_err());
+        assert_eq!(d2.find("f3").unwrap().inode_id(), f1.inode_id());
+        assert_eq!(f1.nlink(), 1);
+        // replace an existing file
+        let old = d2.create("old").unwrap();
+        old.write_at(0, "222222".as_bytes()).unwrap();
+        d2.rename("f3", &d2, "old").unwrap();
+        assert!(d2.find("f3").is_err());
+        assert_eq!(read_string(&d2.find("old").unwrap()), "111111");
+        assert_eq!(old.get_size(), 0);
+        // no such file
+        assert_eq!(d2.rename("f3", &d1, "f4"), Err(EfsError::NotFound));
+
+        // a moved dir gets its ".." fixed
+        let sub = d1.create_dir("sub").unwrap();
+        sub.create("f5").unwrap();
+        d1.rename("sub", &d2, "sub").unwrap();
+        assert_eq!(root.find("d2/sub/f5").unwrap().nlink(), 1);
+        assert_eq!(sub.find("..").unwrap().inode_id(), d2.inode_id());
+        assert!(root.find("d2/sub/../old").is_ok());
+        // not under itself, nor over a dir or with a dot name
+        assert_eq!(root.rename("d2", &sub, "d2"), Err(EfsError::Invalid));
+        assert_eq!(root.rename("d1", &root, "d2"), Err(EfsError::IsDir));
+        assert_eq!(d2.rename("old", &d2, "sub"), Err(EfsError::IsDir));
+        assert_eq!(d2.rename("..", &d1, "up"), Err(EfsError::Invalid));
+        assert_eq!(d2.rename("old", &d1, "."), Err(EfsError::Invalid));
+        Ok(())
+    }
+
+    #[test]
+    fn efs_rmdir_test() -> std::io::Result<()> {
+        let root = test_fs("efs_rmdir_test");
+        let d1 = root.create_dir("d1").unwrap();
+        let f1 = d1.create("f1").unwrap();
+        f1.write_at(0, &[1u8; 3 * BLOCK_SZ]).unwrap();
+
+        // not empty, not a dir, not via unlink
+        assert_eq!(root.rmdir("d1"), Err(EfsError::NotEmpty));
+        assert_eq!(d1.rmdir("f1"), Err(EfsError::NotDir));
+        assert_eq!(root.unlink("d1"), Err(EfsError::IsDir));
+        assert_eq!(d1.rmdir("."), Err(EfsError::Invalid));
+        assert_eq!(d1.rmdir(".."), Err(EfsError::Invalid));
+        assert_eq!(root.rmdir("nowhere"), Err(EfsError::NotFound));
+
+        // inode slots and blocks are reused once freed
+        let (d1_id, f1_id) = (d1.inode_id(), f1.inode_id());
+        d1.unlink("f1").unwrap();
+        root.rmdir("d1").unwrap();
+        assert!(root.find("d1").is_err());
+        let d2 = root.create_dir("d2").unwrap();
+        let f2 = d2.create("f2").unwrap();
+        assert_eq!((d2.inode_id(), f2.inode_id()), (d1_id, f1_id));
+        assert_eq!(f2.get_size(), 0);
+        assert_eq!(d2.ls(), vec![".", "..", "f2"]);
+
+        // more rounds than the inode bitmap holds
+        for _ in 0..5000 {
+            root.create("tmp").unwrap();
+            root.unlink("tmp").unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn efs_journal_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_journal_test");
+        // power cut at every write of `create_dir`, after replay it's all there or not at all
+        for writes in 0.. {
+            EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+            let crash = Arc::new(CrashDevice {
+                file: block_file.clone(),
+                writes_left: Mutex::new(writes),
+                crashed: Mutex::new(false),
+            });
+            let efs = EasyFileSystem::open(crash.clone(), BLOCK_CACHE_SIZE).unwrap();
+            let root = Arc::new(EasyFileSystem::root_inode(&efs));
+            root.create_dir("d").unwrap();
+            let crashed = *crash.crashed.lock().unwrap();
+
+            let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+            let root = Arc::new(EasyFileSystem::root_inode(&efs));
+            let created = root.find("d").is_ok();
+            // inode bitmap agrees with the dirents, 1 is the quota file's
+            let f = root.create("f").unwrap();
+            if created {
+                assert_eq!(f.inode_id(), 3);
+                assert_eq!(root.ls(), vec![".", "..", "d", "f"]);
+                assert_eq!(root.find("d/..").unwrap().inode_id(), 0);
+            } else {
+                assert_eq!(f.inode_id(), 2);
+                assert_eq!(root.ls(), vec![".", "..", "f"]);
+            }
+            if !crashed {
+                assert!(created);
+                // commit takes a few writes: cut before the header, after it and after home
+                assert!(writes > 3);
+                break;
+            }
+        }
+
+        // freeing far more blocks than the journal logs is still one commit: power cut at
+        // every write of `clear`, the file is whole or gone and its blocks aren't given twice
+        let data = vec![7u8; 1000 * BLOCK_SZ];
+        for writes in 0.. {
+            EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+            let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+            let root = Arc::new(EasyFileSystem::root_inode(&efs));
+            let file = root.create("big").unwrap();
+            assert_eq!(file.write_at(0, &data), Ok(data.len()));
+            let crash = Arc::new(CrashDevice {
+                file: block_file.clone(),
+                writes_left: Mutex::new(writes),
+                crashed: Mutex::new(false),
+            });
+            let efs = EasyFileSystem::open(crash.clone(), BLOCK_CACHE_SIZE).unwrap();
+            let root = Arc::new(EasyFileSystem::root_inode(&efs));
+            root.find("big").unwrap().clear();
+            let crashed = *crash.crashed.lock().unwrap();
+
+            let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+            let root = Arc::new(EasyFileSystem::root_inode(&efs));
+            let file = root.find("big").unwrap();
+            let cleared = file.get_size() == 0;
+            let again = root.create("again").unwrap();
+            assert_eq!(again.write_at(0, &[9u8; 1000 * BLOCK_SZ]), Ok(data.len()));
+            if !cleared {
+                let mut read = vec![0u8; data.len()];
+                assert_eq!(file.read_at(0, &mut read), data.len());
+                assert!(read == data);
+            }
+            if !crashed {
+                assert!(cleared);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn efs_block_cache_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_block_cache_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), 8).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let small = root.create("small").unwrap();
+        small.write_at(0, b"small").unwrap();
+        let big = root.create("big").unwrap();
+        big.write_at(0, &vec![b'b'; 32 * BLOCK_SZ]).unwrap();
+
+        // read again right away, all cached
+        read_string(&small);
+        let stats = block_cache_stats();
+        assert_eq!(stats.capacity, 8);
+        assert!(stats.cached <= 8);
+        read_string(&small);
+        let again = block_cache_stats();
+        assert_eq!(again.misses, stats.misses);
+        assert!(again.hits > stats.hits);
+
+        // a scan through the big one pushes it out
+        assert_eq!(read_string(&big).len(), 32 * BLOCK_SZ);
+        let scanned = block_cache_stats();
+        assert!(scanned.evictions > again.evictions);
+        assert!(scanned.cached <= 8);
+        read_string(&small);
+        assert!(block_cache_stats().misses > scanned.misses);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_write_back_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_write_back_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        efs.lock().set_write_back(true);
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let f1 = root.create("f1").unwrap();
+        let f2 = root.create("f2").unwrap();
+        assert_eq!(block_cache_stats().dirty, 0);
+
+        // data stays dirty until synced, the size grown already went through the journal:
+        // 4 data blocks and the inode block both are in
+        f1.write_at(0, b"hello").unwrap();
+        f2.write_at(0, &[b'2'; 3 * BLOCK_SZ]).unwrap();
+        assert_eq!(block_cache_stats().dirty, 5);
+        f1.sync();
+        assert_eq!(block_cache_stats().dirty, 3);
+        // overwriting in place is no transaction, nothing goes to disk
+        f1.write_at(0, b"world").unwrap();
+        assert_eq!(block_cache_stats().dirty, 5);
+        assert_eq!(EasyFileSystem::sync_all(), 5);
+        assert_eq!(block_cache_stats().dirty, 0);
+
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        assert_eq!(read_string(&root.find("f1").unwrap()), "world");
+        assert_eq!(read_string(&root.find("f2").unwrap()).len(), 3 * BLOCK_SZ);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_two_fs_test() -> std::io::Result<()> {
+        let open = |disk: &Arc<BlockFile>| {
+            let efs = EasyFileSystem::open(disk.clone(), BLOCK_CACHE_SIZE).unwrap();
+            Arc::new(EasyFileSystem::root_inode(&efs))
+        };
+        let (a, b) = (test_image("efs_two_fs_a"), test_image("efs_two_fs_b"));
+        EasyFileSystem::create(a.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        EasyFileSystem::create(b.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let root_a = open(&a);
+        let root_b = open(&b);
+        assert_ne!(root_a.fs_id(), root_b.fs_id());
+
+        // the same inode ids and blocks on both, cached apart
+        let fa = root_a.create("f").unwrap();
+        let fb = root_b.create("f").unwrap();
+        assert_eq!(fa.inode_id(), fb.inode_id());
+        fa.write_at(0, b"on a").unwrap();
+        fb.write_at(0, b"on b").unwrap();
+        assert_eq!(read_string(&root_a.find("f").unwrap()), "on a");
+        assert_eq!(read_string(&root_b.find("f").unwrap()), "on b");
+
+        // nothing is linked or moved from one to the other
+        assert_eq!(root_b.link("g", &fa).err(), Some(EfsError::CrossDevice));
+        assert_eq!(root_a.rename("f", &root_b, "g"), Err(EfsError::CrossDevice));
+
+        // one going away leaves the other as it is
+        root_b.unmount();
+        assert_eq!(read_string(&fa), "on a");
+        let root_b = open(&b);
+        assert_eq!(read_string(&root_b.find("f").unwrap()), "on b");
+        Ok(())
+    }
+
+    /// Counts the blocks read from the file under it
+    struct CountDevice {
+        file: Arc<BlockFile>,
+        reads: Mutex<usize>,
+    }
+
+    impl BlockDevice for CountDevice {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            *self.reads.lock().unwrap() += 1;
+            self.file.read_block(block_id, buf);
+        }
+
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            self.file.write_block(block_id, buf);
+        }
+
+        fn handle_irq(&self) {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn efs_aligned_write_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_aligned_write_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let device = Arc::new(CountDevice {
+            file: block_file.clone(),
+            reads: Mutex::new(0),
+        });
+        let efs = EasyFileSystem::open(device.clone(), 8).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let big = root.create("big").unwrap();
+
+        // whole blocks aren't read before being overwritten, only the metadata is
+        let reads = *device.reads.lock().unwrap();
+        big.write_at(0, &vec![b'b'; 32 * BLOCK_SZ]).unwrap();
+        assert!(*device.reads.lock().unwrap() - reads < 8);
+
+        // a partial one is still merged with what's there
+        big.write_at(BLOCK_SZ + 3, b"xyz").unwrap();
+        let data = read_string(&big);
+        assert_eq!(data.len(), 32 * BLOCK_SZ);
+        assert_eq!(&data[BLOCK_SZ..BLOCK_SZ + 7], "bbbxyzb");
+        Ok(())
+    }
+
+    #[test]
+    fn efs_stream_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_stream_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let device = Arc::new(CountDevice {
+            file: block_file.clone(),
+            reads: Mutex::new(0),
+        });
+        let efs = EasyFileSystem::open(device.clone(), 8).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let file = root.create("stream").unwrap();
+
+        // small writes reach the file as whole blocks, which aren't read first
+        let data: Vec<u8> = (0..20 * BLOCK_SZ + 100).map(|i| (i % 251) as u8).collect();
+        let reads = *device.reads.lock().unwrap();
+        let mut writer = InodeWriter::new(&file, 0);
+        for piece in data.chunks(100) {
+            assert_eq!(writer.write(piece), Ok(piece.len()));
+        }
+        assert_eq!(writer.offset(), 20 * BLOCK_SZ);
+        assert_eq!(file.get_size(), 20 * BLOCK_SZ);
+        writer.flush().unwrap();
+        assert_eq!(writer.offset(), data.len());
+        drop(writer);
+        assert!(*device.reads.lock().unwrap() - reads < 8);
+
+        // appended at the end, held back until dropped
+        assert_eq!(file.append(b"ab"), Ok(2));
+        let mut writer = InodeWriter::append(&file);
+        writer.write(b"cd").unwrap();
+        assert_eq!(file.get_size(), data.len() + 2);
+        drop(writer);
+
+        let mut reader = InodeReader::new(&file, BLOCK_SZ);
+        let mut head = [0u8; 3];
+        assert_eq!(reader.read(&mut head), 3);
+        assert_eq!(head, data[BLOCK_SZ..BLOCK_SZ + 3]);
+        let mut rest = Vec::new();
+        assert_eq!(reader.read_to_end(&mut rest), data.len() + 4 - BLOCK_SZ - 3);
+        assert_eq!(&rest[..rest.len() - 4], &data[BLOCK_SZ + 3..]);
+        assert_eq!(&rest[rest.len() - 4..], b"abcd");
+        assert_eq!(reader.read(&mut head), 0);
+        Ok(())
+    }
+
     #[test]
     fn check_os_image() -> std::io::Result<()> {
-        let block_file = Arc::new(BlockFile(Mutex::new({
-            let f = OpenOptions::new()
+        let block_file = Arc::new(BlockFile(Mutex::new(
+            OpenOptions::new()
                 .read(true)
                 .write(true)
-                .open("target/os.img")?;
-            f
-        })));
-        let efs = EasyFileSystem::open(block_file);
+                .open("target/os.img")?,
+        )));
+        let efs = EasyFileSystem::open(block_file, BLOCK_CACHE_SIZE).unwrap();
         let root = Arc::new(EasyFileSystem::root_inode(&efs));
         tree(&root, "/", 0);
         Ok(())
     }
+
+    #[test]
+    fn efs_pack_jobs_test() -> std::io::Result<()> {
+        let source = PathBuf::from("target/pack_src");
+        std::fs::create_dir_all(&source)?;
+        let mut images = Vec::new();
+        for jobs in [1, 4] {
+            let target = PathBuf::from(format!("target/pack_{jobs}"));
+            std::fs::create_dir_all(&target)?;
+            for i in 0..16 {
+                std::fs::write(source.join(format!("app{i}.rs")), "")?;
+                std::fs::write(target.join(format!("app{i}")), vec![i as u8; i * 1000])?;
+            }
+            // listed but never built
+            std::fs::write(source.join("unbuilt.rs"), "")?;
+            let opt = Opt {
+                source: Some(source.clone()),
+                target: target.clone(),
+                check: false,
+                jobs: Some(jobs),
+                deterministic: true,
+                verify: true,
+            };
+            easy_fs_pack(&opt)?;
+            easy_fs_verify(&opt)?;
+            images.push(std::fs::read(target.join("fs.img"))?);
+        }
+        // read in parallel, written the same
+        assert!(images[0] == images[1]);
+
+        // what's packed no longer what's built
+        std::fs::write("target/pack_1/app3", "rebuilt")?;
+        let opt = Opt {
+            source: Some(source.clone()),
+            target: PathBuf::from("target/pack_1"),
+            check: false,
+            jobs: None,
+            deterministic: false,
+            verify: true,
+        };
+        assert!(easy_fs_verify(&opt).is_err());
+
+        let block_file = Arc::new(BlockFile(Mutex::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("target/pack_4/fs.img")?,
+        )));
+        let efs = EasyFileSystem::open(block_file, BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        assert!(root.find("unbuilt").is_err());
+        assert_eq!(read_string(&root.find("app7").unwrap()).len(), 7000);
+        Ok(())
+    }
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn fake_clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn efs_timestamps_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_timestamps_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        efs.lock().set_clock(fake_clock);
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+
+        NOW.store(100, Ordering::Relaxed);
+        let f = root.create("f").unwrap();
+        assert_eq!(f.times(), (100, 100, 100));
+        assert_eq!(root.times().1, 100);
+
+        // write moves mtime and ctime, the read after it atime
+        NOW.store(200, Ordering::Relaxed);
+        f.write_at(0, b"hello").unwrap();
+        assert_eq!(f.times(), (100, 200, 200));
+        NOW.store(300, Ordering::Relaxed);
+        read_string(&f);
+        assert_eq!(f.times(), (300, 200, 200));
+        // atime newer than mtime and less than a day old, left alone
+        NOW.store(400, Ordering::Relaxed);
+        read_string(&f);
+        assert_eq!(f.times().0, 300);
+
+        // link count changes are ctime only, the dir gets mtime
+        NOW.store(500, Ordering::Relaxed);
+        root.link("g", &f).unwrap();
+        assert_eq!(f.times(), (300, 200, 500));
+        assert_eq!(root.times().1, 500);
+        NOW.store(600, Ordering::Relaxed);
+        root.unlink("g").unwrap();
+        assert_eq!(f.times().2, 600);
+        assert_eq!(root.times().1, 600);
+
+        NOW.store(700, Ordering::Relaxed);
+        f.set_times(Some(1), None);
+        assert_eq!(f.times(), (1, 200, 700));
+        f.set_times(None, Some(2));
+        assert_eq!(f.times(), (1, 2, 700));
+
+        // survives reopen
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        assert_eq!(root.find("f").unwrap().times(), (1, 2, 700));
+        Ok(())
+    }
+
+    #[test]
+    fn efs_dentry_cache_test() -> std::io::Result<()> {
+        let root = test_fs("efs_dentry_cache_test");
+        assert_eq!(dentry_cache_stats().cached, 0);
+
+        // created names are cached right away
+        let a = root.create_dir("a").unwrap();
+        let b = root.create_dir("b").unwrap();
+        let f = a.create("f").unwrap();
+        let before = dentry_cache_stats();
+        assert_eq!(root.find("a/f").unwrap().inode_id(), f.inode_id());
+        let after = dentry_cache_stats();
+        assert_eq!((after.hits - before.hits, after.misses), (2, before.misses));
+        assert!(root.find("a/g").is_err());
+        assert_eq!(dentry_cache_stats().misses, before.misses + 1);
+
+        // renames and unlinks are seen by the next lookup
+        a.rename("f", &a, "g").unwrap();
+        assert!(root.find("a/f").is_err());
+        assert_eq!(root.find("a/g").unwrap().inode_id(), f.inode_id());
+        a.rename("g", &b, "f").unwrap();
+        assert!(root.find("a/g").is_err());
+        assert_eq!(root.find("b/f").unwrap().inode_id(), f.inode_id());
+        b.unlink("f").unwrap();
+        assert!(root.find("b/f").is_err());
+
+        // ".." of a moved dir follows it
+        let c = a.create_dir("c").unwrap();
+        assert_eq!(root.find("a/c/..").unwrap().inode_id(), a.inode_id());
+        a.rename("c", &b, "c").unwrap();
+        assert_eq!(root.find("b/c/..").unwrap().inode_id(), b.inode_id());
+        // a dir freed and its id taken again is not looked up by the old entries
+        b.rmdir("c").unwrap();
+        let d = root.create_dir("d").unwrap();
+        assert_eq!(d.inode_id(), c.inode_id());
+        assert_eq!(root.find("d/..").unwrap().inode_id(), root.inode_id());
+
+        // bounded, least recently used dropped first
+        for i in 0..DENTRY_CACHE_SIZE {
+            root.create(&format!("f{}", i)).unwrap();
+        }
+        let stats = dentry_cache_stats();
+        assert_eq!(stats.cached, DENTRY_CACHE_SIZE);
+        assert!(stats.evictions > 0);
+        assert!(root.find("a").is_ok());
+        assert_eq!(dentry_cache_stats().misses, stats.misses + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_mode_owner_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_mode_owner_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        assert_eq!((root.mode(), root.owner()), (0o755, (0, 0)));
+        let f = root.create("f").unwrap();
+        let d = root.create_dir("d").unwrap();
+        let l = root.symlink("l", "f").unwrap();
+        assert_eq!((f.mode(), d.mode(), l.mode()), (0o644, 0o755, 0o777));
+
+        f.chmod(0o100600);
+        assert_eq!(f.mode(), 0o600);
+        f.chown(Some(1000), None);
+        assert_eq!(f.owner(), (1000, 0));
+        f.chown(None, Some(100));
+        assert_eq!(f.owner(), (1000, 100));
+
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let f = root.find("f").unwrap();
+        assert_eq!((f.mode(), f.owner()), (0o600, (1000, 100)));
+        Ok(())
+    }
+
+    #[test]
+    fn efs_quota_test() -> std::io::Result<()> {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+        let block_file = test_image("efs_quota_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        efs.lock().set_clock(clock);
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let user = (1000, 100);
+
+        // counted from what it has once given limits, root never is
+        let d = root.create_dir_as("d", user).unwrap();
+        assert_eq!(d.owner(), user);
+        assert_eq!(root.quota(1000), None);
+        let limits = Quota {
+            block_hard: 4,
+            inode_soft: 2,
+            inode_hard: 3,
+            ..Quota::default()
+        };
+        root.set_quota(1000, &limits).unwrap();
+        let usage = |blocks, inodes| Quota {
+            blocks,
+            inodes,
+            ..root.quota(1000).unwrap()
+        };
+        assert_eq!(root.quota(1000), Some(usage(1, 1)));
+        assert_eq!(root.set_quota(0, &limits), Err(EfsError::Invalid));
+
+        // over the soft limit for a grace, never over the hard one
+        NOW.store(100, Ordering::Relaxed);
+        let f = d.create_as("f", user).unwrap();
+        d.create_as("g", user).unwrap();
+        let quota = root.quota(1000).unwrap();
+        assert_eq!((quota.inodes, quota.inode_grace), (3, 100 + QUOTA_GRACE_NS));
+        assert_eq!(d.create_as("h", user).err(), Some(EfsError::QuotaExceeded));
+        assert!(d.find("h").is_err());
+        d.unlink("g").unwrap();
+        assert_eq!(root.quota(1000).unwrap().inode_grace, 0);
+        assert_eq!(f.write_at(0, &[1u8; 3 * BLOCK_SZ]), Ok(3 * BLOCK_SZ));
+        assert_eq!(root.quota(1000), Some(usage(4, 2)));
+        assert_eq!(f.write_at(3 * BLOCK_SZ, b"x"), Err(EfsError::QuotaExceeded));
+        assert_eq!(f.get_size(), 3 * BLOCK_SZ);
+        f.truncate(BLOCK_SZ).unwrap();
+        assert_eq!(root.quota(1000), Some(usage(2, 2)));
+
+        // held to the soft limit once the grace is over
+        let limits = Quota {
+            block_soft: 2,
+            block_hard: 10,
+            ..limits
+        };
+        root.set_quota(1000, &limits).unwrap();
+        f.truncate(2 * BLOCK_SZ).unwrap();
+        assert_eq!(root.quota(1000).unwrap().block_grace, 100 + QUOTA_GRACE_NS);
+        NOW.store(100 + QUOTA_GRACE_NS, Ordering::Relaxed);
+        assert_eq!(f.truncate(3 * BLOCK_SZ), Err(EfsError::QuotaExceeded));
+        f.truncate(0).unwrap();
+        assert_eq!(root.quota(1000).unwrap().block_grace, 0);
+        f.truncate(3 * BLOCK_SZ).unwrap();
+
+        // handed over whatever the limits
+        f.chown(Some(0), None);
+        assert_eq!(root.quota(1000), Some(usage(1, 1)));
+        f.chown(Some(1000), None);
+        assert_eq!(root.quota(1000), Some(usage(4, 2)));
+        assert_ne!(root.quota(1000).unwrap().block_grace, 0);
+
+        // kept on disk, and taken off with no limits
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        assert_eq!(root.quota(1000).unwrap().blocks, 4);
+        root.find("d").unwrap().unlink("f").unwrap();
+        assert_eq!(root.quota(1000).unwrap().blocks, 1);
+        root.set_quota(1000, &Quota::default()).unwrap();
+        assert_eq!(root.quota(1000), None);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_punch_hole_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_punch_hole_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        // blocks held are told by the quota of the owner
+        let f = root.create_as("f", (1000, 0)).unwrap();
+        let limits = Quota {
+            block_hard: 1 << 20,
+            ..Quota::default()
+        };
+        root.set_quota(1000, &limits).unwrap();
+        let blocks = || root.quota(1000).unwrap().blocks;
+        let read_all = |f: &Inode| {
+            let mut buf = vec![0xffu8; f.get_size()];
+            assert_eq!(f.read_at(0, &mut buf), buf.len());
+            buf
+        };
+
+        // across direct blocks and those of indirect1
+        let mut data: Vec<u8> = (0..30 * BLOCK_SZ).map(|i| (i % 251 + 1) as u8).collect();
+        f.write_at(0, &data).unwrap();
+        assert_eq!(blocks(), 31);
+
+        // blocks all in it are freed, the edges zeroed, the size stays
+        f.punch_hole(BLOCK_SZ / 2, 4 * BLOCK_SZ).unwrap();
+        data[BLOCK_SZ / 2..BLOCK_SZ / 2 + 4 * BLOCK_SZ].fill(0);
+        assert_eq!(blocks(), 28);
+        assert_eq!(f.get_size(), 30 * BLOCK_SZ);
+        assert_eq!(read_all(&f), data);
+        f.punch_hole(19 * BLOCK_SZ, 3 * BLOCK_SZ).unwrap();
+        data[19 * BLOCK_SZ..22 * BLOCK_SZ].fill(0);
+        assert_eq!(blocks(), 25);
+        f.punch_hole(19 * BLOCK_SZ, 3 * BLOCK_SZ).unwrap();
+        assert_eq!(blocks(), 25);
+        assert_eq!(read_all(&f), data);
+
+        // written again, a hole gets a block of zeros back
+        f.write_at(2 * BLOCK_SZ + 10, b"back").unwrap();
+        data[2 * BLOCK_SZ + 10..2 * BLOCK_SZ + 14].copy_from_slice(b"back");
+        assert_eq!(blocks(), 26);
+        assert_eq!(read_all(&f), data);
+
+        // the block the file ends in is all in a hole reaching the end
+        f.truncate(29 * BLOCK_SZ + 300).unwrap();
+        data.truncate(29 * BLOCK_SZ + 300);
+        f.punch_hole(29 * BLOCK_SZ, usize::MAX).unwrap();
+        data[29 * BLOCK_SZ..].fill(0);
+        assert_eq!(blocks(), 25);
+        assert_eq!(f.get_size(), 29 * BLOCK_SZ + 300);
+
+        // kept on disk, and the holes aren't freed twice
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let f = root.find("f").unwrap();
+        assert_eq!(read_all(&f), data);
+        f.truncate(5 * BLOCK_SZ).unwrap();
+        assert_eq!(root.quota(1000).unwrap().blocks, 3);
+        root.unlink("f").unwrap();
+        assert_eq!(root.quota(1000).unwrap().blocks, 0);
+        let g = root.create("g").unwrap();
+        let data = vec![7u8; 40 * BLOCK_SZ];
+        g.write_at(0, &data).unwrap();
+        assert_eq!(read_all(&g), data);
+
+        let d = root.create_dir("d").unwrap();
+        assert_eq!(d.punch_hole(0, BLOCK_SZ), Err(EfsError::IsDir));
+        let l = root.symlink("l", "g").unwrap();
+        assert_eq!(l.punch_hole(0, BLOCK_SZ), Err(EfsError::Invalid));
+        Ok(())
+    }
+
+    /// Put direntry `name` -> `inode_id` at `index` of the dir whose disk inode is at
+    /// (`block_id`, `offset`), behind easy-fs' back as nothing but an older or buggy version
+    /// would; `index` may be one past the end. Only the first data block of the dir is reached.
+    fn raw_put_dirent(
+        block_file: &BlockFile,
+        (block_id, offset): (u32, usize),
+        index: usize,
+        name: &str,
+        inode_id: u32,
+    ) {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id as usize, &mut block);
+        let inode = &mut block[offset..offset + 128];
+        let size = u32::from_ne_bytes(inode[..4].try_into().unwrap()) as usize;
+        // size, nlink, 3 times, then direct[0]
+        let data_block = u32::from_ne_bytes(inode[32..36].try_into().unwrap()) as usize;
+        assert!((index + 1) * 32 <= BLOCK_SZ);
+        if index * 32 == size {
+            inode[..4].copy_from_slice(&(size as u32 + 32).to_ne_bytes());
+            block_file.write_block(block_id as usize, &block);
+        }
+        block_file.read_block(data_block, &mut block);
+        let dirent = &mut block[index * 32..(index + 1) * 32];
+        dirent.fill(0);
+        dirent[..name.len()].copy_from_slice(name.as_bytes());
+        dirent[28..].copy_from_slice(&inode_id.to_ne_bytes());
+        block_file.write_block(data_block, &block);
+    }
+
+    #[test]
+    fn efs_check_dirs_test() -> std::io::Result<()> {
+        let block_file = test_image("efs_check_dirs_test");
+        EasyFileSystem::create(block_file.clone(), 4096, 1, BLOCK_CACHE_SIZE).unwrap();
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let a = root.create_dir("a").unwrap();
+        let b = a.create_dir("b").unwrap();
+        let f = root.create("f").unwrap();
+        root.link("g", &f).unwrap();
+        assert_eq!(root.link("h", &a).err(), Some(EfsError::IsDir));
+        assert!(root.find("h").is_err());
+        assert_eq!(EasyFileSystem::check_dirs(&efs), []);
+
+        // what an older link() let through: a/b/up leads back to a
+        let pos = efs.lock().get_disk_inode_pos(b.inode_id());
+        raw_put_dirent(&block_file, pos, 2, "up", a.inode_id());
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        assert_eq!(
+            EasyFileSystem::check_dirs(&efs),
+            [DirProblem::Revisited(a.inode_id())]
+        );
+        // once a's only entry in root points elsewhere, the cycle floats free
+        let root = Arc::new(EasyFileSystem::root_inode(&efs));
+        let i = root.ls().iter().position(|name| name == "a").unwrap();
+        let pos = efs.lock().get_disk_inode_pos(0);
+        raw_put_dirent(&block_file, pos, i, "a", f.inode_id());
+        let efs = EasyFileSystem::open(block_file.clone(), BLOCK_CACHE_SIZE).unwrap();
+        assert_eq!(
+            EasyFileSystem::check_dirs(&efs),
+            [
+                DirProblem::Unreachable(a.inode_id()),
+                DirProblem::Unreachable(b.inode_id())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn efs_parent_test() -> std::io::Result<()> {
+        let root = test_fs("efs_parent_test");
+        let a = root.create_dir("a").unwrap();
+        let b = a.create_dir("b").unwrap();
+        b.create("f").unwrap();
+        root.create_dir("c").unwrap();
+
+        // nothing walked, nothing new
+        for path in ["", ".", "./."] {
+            assert!(Arc::ptr_eq(&b.find(path).unwrap(), &b));
+        }
+        // root is its own
+        assert_eq!(root.parent().unwrap().inode_id(), 0);
+        // the dir a name was found in, `..` otherwise
+        assert_eq!(b.parent().unwrap().inode_id(), a.inode_id());
+        let f = root.find("a/b/f").unwrap();
+        assert_eq!(f.parent().unwrap().inode_id(), b.inode_id());
+        let f = b.find("./f").unwrap();
+        assert_eq!(f.parent().unwrap().inode_id(), b.inode_id());
+        let up = root.find("a/b/..").unwrap();
+        assert_eq!(up.inode_id(), a.inode_id());
+        assert_eq!(up.parent().unwrap().inode_id(), 0);
+        let (_, f) = b.dirents(0).unwrap().pop().unwrap();
+        assert_eq!(f.parent().err(), Some(EfsError::NotDir));
+
+        // moved, found again it's under its new parent
+        let c = root.find("c").unwrap();
+        a.rename("b", &c, "b").unwrap();
+        let b = root.find("c/b").unwrap();
+        assert_eq!(b.parent().unwrap().inode_id(), c.inode_id());
+        assert_eq!(b.parent().unwrap().parent().unwrap().inode_id(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn efs_dirent_slots_test() -> std::io::Result<()> {
+        let root = test_fs("efs_dirent_slots_test");
+        let d = root.create_dir("d").unwrap();
+        for name in ["a", "b", "c"] {
+            d.create(name).unwrap();
+        }
+        assert_eq!(d.ls(), [".", "..", "a", "b", "c"]);
+        assert_eq!(d.create("").err(), Some(EfsError::Invalid));
+
+        // the others stay where they are, the slot is taken by the next one
+        d.unlink("a").unwrap();
+        assert_eq!(d.ls(), [".", "..", "b", "c"]);
+        assert_eq!(d.get_size(), 5 * DIRENT_SZ);
+        let (names, ids): (Vec<_>, Vec<_>) = d.dirents(3).unwrap().into_iter().unzip();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(ids[0].inode_id(), d.find("b").unwrap().inode_id());
+        d.create("e").unwrap();
+        assert_eq!(d.ls(), [".", "..", "e", "b", "c"]);
+
+        // free ones at the end are cut off
+        d.unlink("b").unwrap();
+        d.unlink("c").unwrap();
+        assert_eq!(d.get_size(), 3 * DIRENT_SZ);
+        assert_eq!(root.rmdir("d").err(), Some(EfsError::NotEmpty));
+        d.unlink("e").unwrap();
+        assert_eq!(d.get_size(), 2 * DIRENT_SZ);
+        root.rmdir("d").unwrap();
+        Ok(())
+    }
 }
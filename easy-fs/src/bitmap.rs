@@ -65,6 +65,16 @@ impl Bitmap {
             })
     }
 
+    /// If bit `bit` is allocated
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decompsition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+            })
+    }
+
     pub fn maxmium(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
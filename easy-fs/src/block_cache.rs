@@ -1,4 +1,8 @@
-use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use alloc::{
+    collections::{vec_deque::VecDeque, BTreeMap},
+    sync::Arc,
+    vec::Vec,
+};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -7,6 +11,27 @@ use crate::{block_dev::BlockDevice, BLOCK_SZ};
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
         Mutex::new(BlockCacheManager::new());
+    static ref DIRTY_LIST: Mutex<DirtyList> = Mutex::new(DirtyList {
+        blocks: BTreeMap::new(),
+        clock: 0,
+    });
+}
+
+/// A block of a device: (`BlockDevice::device_id`, block id). Filesystems on different
+/// devices share the cache.
+pub type BlockKey = (usize, usize);
+
+/// Key of `block_id` on `block_device`
+pub fn block_key(block_id: usize, block_device: &Arc<dyn BlockDevice>) -> BlockKey {
+    (block_device.device_id(), block_id)
+}
+
+/// Cached blocks not written back yet
+struct DirtyList {
+    /// block -> `clock` when last modified
+    blocks: BTreeMap<BlockKey, usize>,
+    /// counts modifications
+    clock: usize,
 }
 
 pub struct BlockCache {
@@ -21,10 +46,13 @@ pub struct BlockCache {
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+    /// Load a new BlockCache from disk, or leave it zeroed if it's about to be overwritten
+    /// whole anyway.
+    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>, load: bool) -> Self {
         let mut cache = [0; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        if load {
+            block_device.read_block(block_id, &mut cache);
+        }
         Self {
             cache,
             block_id,
@@ -33,6 +61,10 @@ impl BlockCache {
         }
     }
 
+    fn key(&self) -> BlockKey {
+        block_key(self.block_id, &self.block_device)
+    }
+
     fn add_of_offset(&self, offset: usize) -> usize {
         &self.cache[offset] as *const _ as usize
     }
@@ -54,15 +86,29 @@ impl BlockCache {
         let type_size = core::mem::size_of::<T>();
         assert!(offset + type_size <= BLOCK_SZ);
         self.modified = true;
+        let mut dirty = DIRTY_LIST.lock();
+        dirty.clock += 1;
+        let clock = dirty.clock;
+        dirty.blocks.insert(self.key(), clock);
+        drop(dirty);
         let addr = self.add_of_offset(offset);
         unsafe { &mut *(addr as *mut T) }
     }
 
-    pub fn sync(&mut self) {
-        if self.modified {
-            self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
+    /// Write the block back if modified, whether it was
+    pub fn sync(&mut self) -> bool {
+        if !self.modified {
+            return false;
         }
+        self.block_device.write_block(self.block_id, &self.cache);
+        self.written();
+        true
+    }
+
+    /// The block was written back, along with others
+    fn written(&mut self) {
+        self.modified = false;
+        DIRTY_LIST.lock().blocks.remove(&self.key());
     }
 
     pub fn read<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
@@ -81,36 +127,69 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Blocks cached if not given otherwise on open/create
+pub const BLOCK_CACHE_SIZE: usize = 16;
+
+/// Counters of the block cache since it was created
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockCacheStats {
+    /// blocks it may hold
+    pub capacity: usize,
+    /// blocks it holds now
+    pub cached: usize,
+    /// lookups found cached
+    pub hits: usize,
+    /// lookups read from the device
+    pub misses: usize,
+    /// blocks dropped to make room
+    pub evictions: usize,
+    /// blocks modified but not written back
+    pub dirty: usize,
+}
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// least recently used first
+    queue: VecDeque<(BlockKey, Arc<Mutex<BlockCache>>)>,
+    stats: BlockCacheStats,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            stats: BlockCacheStats {
+                capacity: BLOCK_CACHE_SIZE,
+                ..Default::default()
+            },
         }
     }
 
+    /// Block cache of `block_id`, a block not cached is read from the device if `load`
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
+        load: bool,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some((_, v)) = self.queue.iter().find(|(id, _)| id == &block_id) {
-            Arc::clone(v)
+        let key = block_key(block_id, &block_device);
+        if let Some(idx) = self.queue.iter().position(|(k, _)| k == &key) {
+            self.stats.hits += 1;
+            // most recently used goes last
+            let pair = self.queue.remove(idx).unwrap();
+            let block_cache = Arc::clone(&pair.1);
+            self.queue.push_back(pair);
+            block_cache
         } else {
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // recycle those not referenced
-                if let Some((idx, _)) = self
+            self.stats.misses += 1;
+            if self.queue.len() >= self.stats.capacity {
+                // recycle the least recently used of those not referenced
+                if let Some(idx) = self
                     .queue
                     .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+                    .position(|pair| Arc::strong_count(&pair.1) == 1)
                 {
-                    self.queue.drain(idx..=idx);
+                    self.queue.remove(idx);
+                    self.stats.evictions += 1;
                 } else {
                     panic!("Run out of BlockCache!");
                 }
@@ -119,11 +198,31 @@ impl BlockCacheManager {
             let block_cache = Arc::new(Mutex::new(BlockCache::new(
                 block_id,
                 Arc::clone(&block_device),
+                load,
             )));
-            self.queue.push_back((block_id, block_cache.clone()));
+            self.queue.push_back((key, block_cache.clone()));
             block_cache
         }
     }
+
+    /// Write back and forget all blocks
+    fn drop_all(&mut self) {
+        for (_, cache) in self.queue.iter() {
+            cache.lock().sync();
+        }
+        self.queue.clear();
+    }
+
+    /// Write back and forget the blocks of `block_device`
+    fn drop_device(&mut self, block_device: &Arc<dyn BlockDevice>) {
+        let device = block_device.device_id();
+        self.queue.retain(|((dev, _), cache)| {
+            if *dev == device {
+                cache.lock().sync();
+            }
+            *dev != device
+        });
+    }
 }
 
 /// Get the block cache corresponding to the given block id and block device
@@ -133,13 +232,96 @@ pub fn get_block_cache(
 ) -> Arc<Mutex<BlockCache>> {
     BLOCK_CACHE_MANAGER
         .lock()
-        .get_block_cache(block_id, block_device)
+        .get_block_cache(block_id, block_device, true)
+}
+
+/// Get the block cache of a block the caller overwrites whole, one not cached isn't read
+/// from the block device first
+pub fn get_block_cache_overwrite(
+    block_id: usize,
+    block_device: Arc<dyn BlockDevice>,
+) -> Arc<Mutex<BlockCache>> {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .get_block_cache(block_id, block_device, false)
+}
+
+/// Sync the modified blocks of `keys` if cached, returns how many were written
+pub fn block_cache_sync(keys: &[BlockKey]) -> usize {
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    let mut dirty: Vec<_> = manager
+        .queue
+        .iter()
+        .filter(|(key, _)| keys.contains(key))
+        .map(|(_, cache)| cache.lock())
+        .filter(|cache| cache.modified)
+        .collect();
+    let written = dirty.len();
+    // all blocks of a device at once, so it may merge adjacent ones
+    while let Some(first) = dirty.first() {
+        let device = first.block_device.clone();
+        let (batch, rest): (Vec<_>, Vec<_>) = dirty
+            .into_iter()
+            .partition(|cache| Arc::ptr_eq(&cache.block_device, &device));
+        let blocks: Vec<_> = batch
+            .iter()
+            .map(|cache| (cache.block_id, &cache.cache[..]))
+            .collect();
+        device.write_blocks(&blocks);
+        for mut cache in batch {
+            cache.written();
+        }
+        dirty = rest;
+    }
+    written
+}
+
+/// Sync all block cache to block device, returns how many blocks were written
+pub fn block_cache_sync_all() -> usize {
+    block_cache_sync_since(0)
+}
+
+/// Current count of modifications, for `block_cache_sync_since`
+pub fn block_cache_clock() -> usize {
+    DIRTY_LIST.lock().clock
+}
+
+/// Sync blocks modified after `clock`, those modified before may stay dirty. Returns how many
+/// were written.
+pub fn block_cache_sync_since(clock: usize) -> usize {
+    let keys: Vec<BlockKey> = DIRTY_LIST
+        .lock()
+        .blocks
+        .iter()
+        .filter(|(_, &modified)| modified > clock)
+        .map(|(&key, _)| key)
+        .collect();
+    block_cache_sync(&keys)
+}
+
+/// Sync the block cache of `block_device` and drop it, for a filesystem going away or coming
+/// on it. Other devices keep theirs.
+pub fn block_cache_drop(block_device: &Arc<dyn BlockDevice>) {
+    BLOCK_CACHE_MANAGER.lock().drop_device(block_device);
+}
+
+/// Hold at most `capacity` blocks. If that's not how many it holds already, all block cache
+/// is synced and dropped first, later reads go to the block device again.
+pub fn block_cache_resize(capacity: usize) {
+    assert!(capacity > 0);
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    if manager.stats.capacity != capacity {
+        manager.drop_all();
+        manager.stats.capacity = capacity;
+    }
 }
 
-/// Sync all block cache to block device
-pub fn block_cache_sync_all() {
+/// Hit/miss counters of the block cache
+pub fn block_cache_stats() -> BlockCacheStats {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    BlockCacheStats {
+        cached: manager.queue.len(),
+        dirty: DIRTY_LIST.lock().blocks.len(),
+        ..manager.stats
     }
 }
@@ -1,5 +1,27 @@
 use core::any::Any;
 
+/// Counters of a block device since it was created, all zero if it keeps none
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockDeviceStats {
+    /// requests sent to the device
+    pub requests: usize,
+    /// blocks read or written by them
+    pub blocks: usize,
+    /// blocks sent along with the one before in a single request
+    pub merged: usize,
+    /// requests sent but not completed
+    pub in_flight: usize,
+    /// most requests ever in flight
+    pub max_in_flight: usize,
+    /// requests in flight summed up each time one is sent, the mean queue depth over `requests`
+    pub depth_sum: usize,
+    /// time from sending requests to their completion summed up, in us
+    pub latency_us: usize,
+    /// longest of them
+    pub max_latency_us: usize,
+}
+
 /// Trait for block devices
 /// which reads and writes data in the unit of blocks
 pub trait BlockDevice: Send + Sync + Any {
@@ -7,6 +29,22 @@ pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     /// Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Write `(block_id, buf)` of blocks in whatever order, devices may merge adjacent ones
+    fn write_blocks(&self, blocks: &[(usize, &[u8])]) {
+        for &(block_id, buf) in blocks {
+            self.write_block(block_id, buf);
+        }
+    }
+    /// Make the blocks written so far durable, for devices caching writes themselves
+    fn flush(&self) {}
     /// IRQ handler
     fn handle_irq(&self);
+    /// Identifies the device blocks are stored on, the same for devices wrapping another
+    fn device_id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+    /// Counters kept so far
+    fn stats(&self) -> BlockDeviceStats {
+        BlockDeviceStats::default()
+    }
 }
@@ -0,0 +1,124 @@
+//! Name lookups found in directories, so walking a path doesn't scan each directory on the way.
+//! Only names that exist are cached; whoever changes a direntry keeps the cache in step.
+
+use alloc::{collections::vec_deque::VecDeque, string::String};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    static ref DENTRY_CACHE: Mutex<DentryCache> = Mutex::new(DentryCache::new());
+}
+
+/// Entries cached at most
+pub const DENTRY_CACHE_SIZE: usize = 64;
+
+/// Counters of the dentry cache since it was created
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DentryCacheStats {
+    /// entries it may hold
+    pub capacity: usize,
+    /// entries it holds now
+    pub cached: usize,
+    /// lookups found cached
+    pub hits: usize,
+    /// lookups that scanned the directory
+    pub misses: usize,
+    /// entries dropped to make room
+    pub evictions: usize,
+}
+
+struct DentryCache {
+    /// ((fs id, parent inode id, name), child inode id), least recently used first
+    queue: VecDeque<((usize, u32, String), u32)>,
+    stats: DentryCacheStats,
+}
+
+impl DentryCache {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            stats: DentryCacheStats {
+                capacity: DENTRY_CACHE_SIZE,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn position(&self, fs: usize, parent: u32, name: &str) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|((f, p, n), _)| *f == fs && *p == parent && n == name)
+    }
+}
+
+/// Child `name` of dir `parent` on filesystem `fs` if cached, `lookup` scans the dir otherwise
+pub fn dentry_cache_lookup(
+    fs: usize,
+    parent: u32,
+    name: &str,
+    lookup: impl FnOnce() -> Option<u32>,
+) -> Option<u32> {
+    let mut cache = DENTRY_CACHE.lock();
+    if let Some(idx) = cache.position(fs, parent, name) {
+        cache.stats.hits += 1;
+        // most recently used goes last
+        let entry = cache.queue.remove(idx).unwrap();
+        let child = entry.1;
+        cache.queue.push_back(entry);
+        return Some(child);
+    }
+    cache.stats.misses += 1;
+    drop(cache);
+    let child = lookup()?;
+    dentry_cache_insert(fs, parent, name, child);
+    Some(child)
+}
+
+/// `name` in dir `parent` now refers to `child`
+pub fn dentry_cache_insert(fs: usize, parent: u32, name: &str, child: u32) {
+    let mut cache = DENTRY_CACHE.lock();
+    if let Some(idx) = cache.position(fs, parent, name) {
+        cache.queue.remove(idx);
+    } else if cache.queue.len() >= cache.stats.capacity {
+        cache.queue.pop_front();
+        cache.stats.evictions += 1;
+    }
+    cache
+        .queue
+        .push_back(((fs, parent, String::from(name)), child));
+}
+
+/// `name` in dir `parent` is gone
+pub fn dentry_cache_remove(fs: usize, parent: u32, name: &str) {
+    let mut cache = DENTRY_CACHE.lock();
+    if let Some(idx) = cache.position(fs, parent, name) {
+        cache.queue.remove(idx);
+    }
+}
+
+/// Inode `parent` is freed, its id may come back as another dir
+pub fn dentry_cache_forget_dir(fs: usize, parent: u32) {
+    DENTRY_CACHE
+        .lock()
+        .queue
+        .retain(|((f, p, _), _)| *f != fs || *p != parent);
+}
+
+/// Forget the entries of filesystem `fs`, going away
+pub fn dentry_cache_drop_fs(fs: usize) {
+    DENTRY_CACHE.lock().queue.retain(|((f, _, _), _)| *f != fs);
+}
+
+/// Forget all entries, of a filesystem going away or another one coming
+pub fn dentry_cache_drop_all() {
+    DENTRY_CACHE.lock().queue.clear();
+}
+
+/// Hit/miss counters of the dentry cache
+pub fn dentry_cache_stats() -> DentryCacheStats {
+    let cache = DENTRY_CACHE.lock();
+    DentryCacheStats {
+        cached: cache.queue.len(),
+        ..cache.stats
+    }
+}
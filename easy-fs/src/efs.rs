@@ -1,37 +1,65 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use spin::Mutex;
 
 use crate::{
     bitmap::Bitmap,
-    block_cache::{block_cache_sync_all, get_block_cache},
+    block_cache::{
+        block_cache_drop, block_cache_resize, block_cache_sync_all, get_block_cache,
+        get_block_cache_overwrite,
+    },
     block_dev::BlockDevice,
+    dentry_cache::dentry_cache_drop_all,
+    error::{EfsError, Result},
+    journal::{Journal, Transaction},
     layout::{DiskInode, DiskInodeType, SuperBlock},
+    quota::QUOTA_INODE,
     vfs::Inode,
     BLOCK_SZ,
 };
 
+/// Blocks of the journal area
+const JOURNAL_BLOCKS: u32 = 64;
+
 /// An easy file system on block
 pub struct EasyFileSystem {
-    /// Real device
+    /// Journal over the real device, every block goes through it
     pub block_device: Arc<dyn BlockDevice>,
+    journal: Arc<Journal>,
     /// Inode bitmap
     pub inode_bitmap: Bitmap,
     /// Data bitmap
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    /// blocks of the data area, its bitmap has bits past them
+    data_area_blocks: u32,
+    /// file data stays cached until synced explicitly or evicted
+    write_back: bool,
+    /// wall time in ns since the epoch, for inode timestamps
+    clock: fn() -> u64,
+    /// inode of the quota file, 0 if there's none
+    pub(crate) quota_inode: u32,
+    /// blocks of the quota file, they never change
+    pub(crate) quota_blocks: Vec<u32>,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
-// super_block | inode_bitmap | inode_area | data_bitmap | data_area
+
+/// Clock of a filesystem given none
+fn no_clock() -> u64 {
+    0
+}
+// super_block | journal | inode_bitmap | inode_area | data_bitmap | data_area
 impl EasyFileSystem {
-    /// create efs given device with `total_blocks` & `inode_bitmap_blocks` specified
+    /// create efs given device with `total_blocks` & `inode_bitmap_blocks` specified, caching
+    /// `cache_blocks` blocks at most. `Invalid` if that leaves no room for data.
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
-    ) -> Arc<Mutex<Self>> {
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
+        cache_blocks: usize,
+    ) -> Result<Arc<Mutex<Self>>> {
+        let inode_bitmap = Bitmap::new(1 + JOURNAL_BLOCKS as usize, inode_bitmap_blocks as usize);
         // how many inodes
         let inode_num = inode_bitmap.maxmium();
         // blocks for inodes
@@ -39,7 +67,11 @@ impl EasyFileSystem {
             (((inode_num * core::mem::size_of::<DiskInode>()) + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
 
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks; // `1` stands for super block
+        // `1` stands for super block
+        let data_total_blocks = total_blocks
+            .checked_sub(1 + JOURNAL_BLOCKS + inode_total_blocks)
+            .filter(|&blocks| blocks >= 2)
+            .ok_or(EfsError::Invalid)?;
 
         // Q: 为什么这里是除 4097 而不是 4096？除 4096 不正确吗?
         // A: 希望位图覆盖后面的数据块的前提下数据块尽量多。设数据的位图占据x个块，则该位图能管理的数据块不超过4096x。
@@ -48,19 +80,37 @@ impl EasyFileSystem {
         // 于是取x的最小整数解也就是data_total_blocks/4097上取整，也就是代码中的表达式。
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
-        let data_bitmap = Bitmap::new(1 + inode_total_blocks as usize, data_bitmap_blocks as usize);
+        let data_bitmap = Bitmap::new(
+            (1 + JOURNAL_BLOCKS + inode_total_blocks) as usize,
+            data_bitmap_blocks as usize,
+        );
 
+        let journal = Arc::new(Journal::new(
+            Arc::clone(&block_device),
+            1,
+            JOURNAL_BLOCKS as usize,
+        ));
         let mut efs = Self {
-            block_device: Arc::clone(&block_device),
+            block_device: journal.clone(),
+            journal,
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_area_start_block: 1 + JOURNAL_BLOCKS + inode_bitmap_blocks,
+            data_area_start_block: 1 + JOURNAL_BLOCKS + inode_total_blocks + data_bitmap_blocks,
+            data_area_blocks,
+            write_back: false,
+            clock: no_clock,
+            quota_inode: 0,
+            quota_blocks: Vec::new(),
         };
+        // cached blocks of whatever was on the device before are stale
+        block_cache_resize(cache_blocks);
+        block_cache_drop(&block_device);
+        dentry_cache_drop_all();
 
         // clear all blocks
         for i in 0..total_blocks {
-            get_block_cache(i as usize, Arc::clone(&block_device))
+            get_block_cache_overwrite(i as usize, Arc::clone(&block_device))
                 .lock()
                 .modify(0, |data_block: &mut DataBlock| data_block.fill(0));
         }
@@ -71,17 +121,19 @@ impl EasyFileSystem {
             |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
+                    JOURNAL_BLOCKS,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
-                )
+                );
+                super_block.quota_inode = QUOTA_INODE;
             },
         );
 
         // write back immediately
         // create a inode for root node "/"
-        assert_eq!(efs.alloc_inode(), 0);
+        assert_eq!(efs.alloc_inode()?, 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
         assert_eq!(root_inode_block_id, efs.inode_area_start_block);
         assert_eq!(root_inode_offset, 0);
@@ -89,34 +141,106 @@ impl EasyFileSystem {
             .lock()
             .modify(root_inode_offset, |root_inode: &mut DiskInode| {
                 root_inode.initialize(DiskInodeType::Directory);
-                root_inode.initialize_dir(0, 0, || efs.alloc_data(), &block_device);
-            });
-        block_cache_sync_all();
+                root_inode.initialize_dir(0, 0, || efs.alloc_data(), &block_device)
+            })?;
+        assert_eq!(efs.alloc_inode()?, QUOTA_INODE);
+        efs.create_quota_file(QUOTA_INODE)?;
+        // blocks cached above write to the device directly, not through the journal
+        block_cache_drop(&block_device);
 
-        Arc::new(Mutex::new(efs))
+        Ok(Arc::new(Mutex::new(efs)))
     }
 
-    /// Open a block device as a filesystem
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    /// Open a block device as a filesystem caching `cache_blocks` blocks at most, redoing the
+    /// last commit of journal if cut short. `Corrupt` if there's no filesystem on it.
+    pub fn open(
+        block_device: Arc<dyn BlockDevice>,
+        cache_blocks: usize,
+    ) -> Result<Arc<Mutex<Self>>> {
+        // cached blocks may be of what was there before a crash
+        block_cache_resize(cache_blocks);
+        block_cache_drop(&block_device);
+        dentry_cache_drop_all();
         // read super block
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
-                assert!(super_block.is_valid(), "Error loading EFS!");
+        let (mut efs, quota_inode) = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
+                // the journal needs its header and a block to log
+                if !super_block.is_valid() || super_block.journal_blocks < 2 {
+                    return Err(EfsError::Corrupt);
+                }
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
-                    block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
-                    data_bitmap: Bitmap::new(
-                        1 + inode_total_blocks as usize,
-                        super_block.data_bitmap_blocks as usize,
-                    ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                let journal = Arc::new(Journal::new(
+                    Arc::clone(&block_device),
+                    1,
+                    super_block.journal_blocks as usize,
+                ));
+                let journal_end = 1 + super_block.journal_blocks;
+                Ok((
+                    Self {
+                        block_device: journal.clone(),
+                        journal,
+                        inode_bitmap: Bitmap::new(
+                            journal_end as usize,
+                            super_block.inode_bitmap_blocks as usize,
+                        ),
+                        data_bitmap: Bitmap::new(
+                            (journal_end + inode_total_blocks) as usize,
+                            super_block.data_bitmap_blocks as usize,
+                        ),
+                        inode_area_start_block: journal_end + super_block.inode_bitmap_blocks,
+                        data_area_start_block: journal_end
+                            + inode_total_blocks
+                            + super_block.data_bitmap_blocks,
+                        data_area_blocks: super_block.data_area_blocks,
+                        write_back: false,
+                        clock: no_clock,
+                        quota_inode: 0,
+                        quota_blocks: Vec::new(),
+                    },
+                    super_block.quota_inode,
+                ))
+            },
+        )?;
+        // super block is cached with the device itself, drop it so every block goes through
+        // the journal
+        block_cache_drop(&block_device);
+        efs.journal.replay();
+        efs.load_quota_file(quota_inode);
+        Ok(Arc::new(Mutex::new(efs)))
+    }
+
+    /// Keep file data cached when written, until `sync_all`, `Inode::sync` or evicted.
+    /// Otherwise every write goes to disk before it returns, as by default.
+    pub fn set_write_back(&mut self, write_back: bool) {
+        self.write_back = write_back;
+    }
+
+    /// If file data is written back lazily
+    pub fn write_back(&self) -> bool {
+        self.write_back
+    }
+
+    /// Take inode timestamps from `clock`, in ns since the epoch. They're all 0 otherwise.
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    /// Wall time by the clock
+    pub fn now(&self) -> u64 {
+        (self.clock)()
+    }
+
+    /// Write back all cached blocks, of whatever filesystem they are. Returns how many were
+    /// written.
+    pub fn sync_all() -> usize {
+        block_cache_sync_all()
+    }
+
+    /// Open a transaction, blocks written until it's dropped go to disk as a whole
+    pub(crate) fn transaction(&self) -> Transaction {
+        self.journal.begin()
     }
 
     /// Get the root inode of the filesystem
@@ -151,27 +275,53 @@ impl EasyFileSystem {
         self.data_area_start_block + data_block_id
     }
 
-    /// Allocate a new inode
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    /// Allocate a new inode, `NoSpace` if all are in use
+    pub fn alloc_inode(&mut self) -> Result<u32> {
+        self.inode_bitmap
+            .alloc(&self.block_device)
+            .map(|id| id as u32)
+            .ok_or(EfsError::NoSpace)
     }
 
-    #[allow(unused)]
-    /// Deallocate a new inode (delete)
-    pub fn dealloc_inode(&mut self) {
-        unimplemented!()
+    /// Ids of the inodes in use
+    pub(crate) fn allocated_inodes(&self) -> Vec<u32> {
+        (0..self.inode_bitmap.maxmium())
+            .filter(|&id| self.inode_bitmap.is_allocated(&self.block_device, id))
+            .map(|id| id as u32)
+            .collect()
     }
 
-    /// Output block_id on device, not pos of bit in bitmap
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_area_start_block + self.data_bitmap.alloc(&self.block_device).unwrap() as u32
+    /// Deallocate an inode, its data should have been cleared already
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        assert_ne!(inode_id, 0, "root inode can't be deallocated");
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize);
     }
 
-    /// Input block_id on device, not pos of bit in bitmap
-    pub fn dealloc_data(&mut self, block_id: u32) {
-        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+    /// Output block_id on device, not pos of bit in bitmap, the block reads zeros. `NoSpace` if
+    /// the data area is full.
+    pub fn alloc_data(&mut self) -> Result<u32> {
+        let bit = self
+            .data_bitmap
+            .alloc(&self.block_device)
+            .ok_or(EfsError::NoSpace)?;
+        // lower bits are taken first, this one past the area means all of it is
+        if bit >= self.data_area_blocks as usize {
+            self.data_bitmap.dealloc(&self.block_device, bit);
+            return Err(EfsError::NoSpace);
+        }
+        let block_id = self.data_area_start_block + bit as u32;
+        // zeroed here rather than when freed, so it's not logged (see `Journal::fresh`)
+        self.journal.fresh(block_id as usize);
+        get_block_cache_overwrite(block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(0, |data_block: &mut DataBlock| data_block.fill(0));
+        Ok(block_id)
+    }
+
+    /// Input block_id on device, not pos of bit in bitmap
+    pub fn dealloc_data(&mut self, block_id: u32) {
+        self.journal.freed(block_id as usize);
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
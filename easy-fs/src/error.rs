@@ -0,0 +1,36 @@
+//! Errors of filesystem operations, for bad input or an image full or corrupt. Callers turn
+//! them into errors of their own, the kernel into errnos.
+
+/// What made a filesystem operation fail, nothing is changed when it does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EfsError {
+    /// nothing of the name, or on the way to it
+    NotFound,
+    /// name taken already
+    Exists,
+    /// not a dir where one is needed, e.g. walked through by a path
+    NotDir,
+    /// a dir where it can't be, e.g. written as a file, unlinked or hard linked
+    IsDir,
+    /// dir removed still has entries other than "." and ".."
+    NotEmpty,
+    /// name longer than a direntry holds
+    NameTooLong,
+    /// past `MAX_FILE_SIZE`
+    FileTooLarge,
+    /// out of inodes or data blocks
+    NoSpace,
+    /// too many symlinks followed in one lookup, taken as a loop
+    Loop,
+    /// linked or moved to another filesystem
+    CrossDevice,
+    /// owner would go over its quota of blocks or inodes
+    QuotaExceeded,
+    /// makes no sense, e.g. "." renamed, a dir moved under itself or readlink of a file
+    Invalid,
+    /// what's on disk makes no sense, e.g. no super block
+    Corrupt,
+}
+
+/// Result of a filesystem operation
+pub type Result<T> = core::result::Result<T, EfsError>;
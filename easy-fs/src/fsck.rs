@@ -0,0 +1,69 @@
+//! Consistency checks of a filesystem, reporting what's wrong without fixing anything.
+
+use alloc::{collections::BTreeSet, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
+
+use crate::{efs::EasyFileSystem, vfs::Inode};
+
+/// Something wrong in the directory tree, dirs are given by inode id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirProblem {
+    /// reached a second time, by a hard link to it or an entry leading back up
+    Revisited(u32),
+    /// "." is not the dir itself
+    BadDot(u32),
+    /// (dir, its "..") where ".." is not the dir it was reached from
+    BadDotDot(u32, u32),
+    /// in use but not reachable from the root, e.g. a cycle cut off from it
+    Unreachable(u32),
+}
+
+impl EasyFileSystem {
+    /// Walk the directory tree from the root, every dir must be reached exactly once, and
+    /// through the dir its ".." names
+    pub fn check_dirs(efs: &Arc<Mutex<Self>>) -> Vec<DirProblem> {
+        let mut problems = Vec::new();
+        let mut visited = BTreeSet::from([0]);
+        // (dir, the one it was reached from)
+        let mut stack = vec![(Arc::new(Self::root_inode(efs)), 0)];
+        while let Some((dir, parent)) = stack.pop() {
+            let dir_id = dir.inode_id();
+            for (name, child) in dir.dirents(0).unwrap_or_default() {
+                let child_id = child.inode_id();
+                match name.as_str() {
+                    "." if child_id != dir_id => problems.push(DirProblem::BadDot(dir_id)),
+                    ".." if child_id != parent => {
+                        problems.push(DirProblem::BadDotDot(dir_id, child_id))
+                    }
+                    "." | ".." => {}
+                    _ if !child.is_dir() => {}
+                    _ if !visited.insert(child_id) => {
+                        problems.push(DirProblem::Revisited(child_id))
+                    }
+                    _ => stack.push((child, dir_id)),
+                }
+            }
+        }
+
+        let allocated = efs.lock().allocated_inodes();
+        for inode_id in allocated {
+            if visited.contains(&inode_id) {
+                continue;
+            }
+            let inode = Self::inode(efs, inode_id);
+            if inode.is_dir() {
+                problems.push(DirProblem::Unreachable(inode_id));
+            }
+        }
+        problems
+    }
+
+    /// Vfs inode of `inode_id`
+    fn inode(efs: &Arc<Mutex<Self>>, inode_id: u32) -> Inode {
+        let guard = efs.lock();
+        let (block_id, block_offset) = guard.get_disk_inode_pos(inode_id);
+        let block_device = guard.block_device.clone();
+        drop(guard);
+        Inode::new(inode_id, block_id, block_offset, efs.clone(), block_device)
+    }
+}
@@ -0,0 +1,213 @@
+//! Write-ahead journal, so that one operation on the filesystem reaches the disk as a whole
+//! or not at all.
+//!
+//! While a transaction is open, blocks written back by the cache are held in memory instead
+//! of going to their home. Commit copies them into the journal area, then writes the header
+//! naming their homes, which is the commit point as a block write is atomic. Only then are
+//! they written home and the header cleared. A commit cut short by a power cut is redone by
+//! `replay` on open.
+//!
+//! Blocks allocated in the transaction aren't logged but written home right away: nothing on
+//! disk refers to them before the commit. That leaves the bitmaps, the inodes and the
+//! indirect blocks they had before, far fewer than a commit can log. A transaction that
+//! still outgrows it is committed in pieces, each atomic but not the whole.
+//!
+//! journal area: header | logged blocks, in the order of `targets`
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+use spin::Mutex;
+
+use crate::{
+    block_cache::{block_cache_clock, block_cache_sync_since},
+    block_dev::BlockDevice,
+    BLOCK_SZ,
+};
+
+const JOURNAL_MAGIC: u32 = 0x6a726e6c;
+/// Home block ids a header has room for
+const HEADER_TARGETS: usize = BLOCK_SZ / 4 - 2;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// First block of the journal area
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    /// logged blocks to replay, 0 if none
+    count: u32,
+    targets: [u32; HEADER_TARGETS],
+}
+
+impl JournalHeader {
+    fn new(count: u32) -> Self {
+        Self {
+            magic: JOURNAL_MAGIC,
+            count,
+            targets: [0; HEADER_TARGETS],
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, BLOCK_SZ) }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as *mut u8, BLOCK_SZ) }
+    }
+}
+
+/// Block device of the filesystem, journaling writes to the real one in a transaction
+pub struct Journal {
+    device: Arc<dyn BlockDevice>,
+    /// where the header is, logged blocks follow
+    start_block: usize,
+    /// blocks logged by one commit at most
+    capacity: usize,
+    inner: Mutex<JournalInner>,
+}
+
+struct JournalInner {
+    in_transaction: bool,
+    /// home block id -> content, not written home yet
+    pending: BTreeMap<usize, DataBlock>,
+    /// allocated in the transaction, free on disk until it commits
+    fresh: BTreeSet<usize>,
+    /// freed in the transaction, still in use on disk until it commits
+    freed: BTreeSet<usize>,
+}
+
+impl Journal {
+    /// Journal over `blocks` blocks from `start_block` of `device`
+    pub fn new(device: Arc<dyn BlockDevice>, start_block: usize, blocks: usize) -> Self {
+        assert!(blocks >= 2);
+        Self {
+            device,
+            start_block,
+            capacity: (blocks - 1).min(HEADER_TARGETS),
+            inner: Mutex::new(JournalInner {
+                in_transaction: false,
+                pending: BTreeMap::new(),
+                fresh: BTreeSet::new(),
+                freed: BTreeSet::new(),
+            }),
+        }
+    }
+
+    /// Redo the last commit if it's not known to be home
+    pub fn replay(&self) {
+        let mut header = JournalHeader::new(0);
+        self.device
+            .read_block(self.start_block, header.as_bytes_mut());
+        if header.magic != JOURNAL_MAGIC || header.count == 0 {
+            return;
+        }
+        let count = header.count as usize;
+        let mut data = [0u8; BLOCK_SZ];
+        for (i, &block_id) in header.targets[..count].iter().enumerate() {
+            self.device.read_block(self.start_block + 1 + i, &mut data);
+            self.device.write_block(block_id as usize, &data);
+        }
+        self.device
+            .write_block(self.start_block, JournalHeader::new(0).as_bytes());
+    }
+
+    /// Start holding writes back, until the returned transaction is dropped
+    pub fn begin(self: &Arc<Self>) -> Transaction {
+        let mut inner = self.inner.lock();
+        assert!(!inner.in_transaction, "nested transaction");
+        inner.in_transaction = true;
+        Transaction(self.clone(), block_cache_clock())
+    }
+
+    /// `block_id` was allocated in the open transaction, it's written home without being
+    /// logged unless it was freed in the same transaction and may still be read as it was.
+    pub fn fresh(&self, block_id: usize) {
+        let mut inner = self.inner.lock();
+        if inner.in_transaction && !inner.freed.contains(&block_id) {
+            inner.fresh.insert(block_id);
+        }
+    }
+
+    /// `block_id` was freed in the open transaction
+    pub fn freed(&self, block_id: usize) {
+        let mut inner = self.inner.lock();
+        if inner.in_transaction && !inner.fresh.remove(&block_id) {
+            inner.freed.insert(block_id);
+        }
+    }
+
+    fn commit(&self) {
+        let mut inner = self.inner.lock();
+        self.flush(&mut inner.pending);
+        inner.fresh.clear();
+        inner.freed.clear();
+        inner.in_transaction = false;
+    }
+
+    /// Log `pending`, then write it home
+    fn flush(&self, pending: &mut BTreeMap<usize, DataBlock>) {
+        if pending.is_empty() {
+            return;
+        }
+        let mut header = JournalHeader::new(pending.len() as u32);
+        for (i, (&block_id, data)) in pending.iter().enumerate() {
+            self.device.write_block(self.start_block + 1 + i, data);
+            header.targets[i] = block_id as u32;
+        }
+        // commit point
+        self.device.write_block(self.start_block, header.as_bytes());
+        for (&block_id, data) in pending.iter() {
+            self.device.write_block(block_id, data);
+        }
+        self.device
+            .write_block(self.start_block, JournalHeader::new(0).as_bytes());
+        pending.clear();
+    }
+}
+
+impl BlockDevice for Journal {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        if let Some(data) = self.inner.lock().pending.get(&block_id) {
+            buf.copy_from_slice(data);
+            return;
+        }
+        self.device.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut inner = self.inner.lock();
+        if !inner.in_transaction || inner.fresh.contains(&block_id) {
+            drop(inner);
+            return self.device.write_block(block_id, buf);
+        }
+        // no room left: what's held so far goes as one commit, see the module doc
+        if !inner.pending.contains_key(&block_id) && inner.pending.len() == self.capacity {
+            self.flush(&mut inner.pending);
+        }
+        let mut data = [0u8; BLOCK_SZ];
+        data.copy_from_slice(buf);
+        inner.pending.insert(block_id, data);
+    }
+
+    fn handle_irq(&self) {
+        self.device.handle_irq();
+    }
+
+    fn device_id(&self) -> usize {
+        self.device.device_id()
+    }
+}
+
+/// Open transaction, committed on drop after the blocks modified since it began are written
+/// back. Those left dirty before aren't part of it.
+pub struct Transaction(Arc<Journal>, usize);
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        block_cache_sync_since(self.1);
+        self.0.commit();
+    }
+}
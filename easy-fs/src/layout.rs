@@ -1,11 +1,16 @@
 use alloc::{sync::Arc, vec::Vec};
 
-use crate::{block_cache::get_block_cache, block_dev::BlockDevice, BLOCK_SZ};
+use crate::{
+    block_cache::{get_block_cache, get_block_cache_overwrite},
+    block_dev::BlockDevice,
+    error::{EfsError, Result},
+    BLOCK_SZ,
+};
 
 /// Magic number for sanity check
-const EFS_MAGIC: u32 = 0x3b800001;
+const EFS_MAGIC: u32 = 0x3b800004;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 26;
+const INODE_DIRECT_COUNT: usize = 20;
 /// The max length of inode name
 const NAME_LENGTH_LIMIT: usize = 27;
 /// The max number of indirect1 inodes
@@ -16,16 +21,24 @@ const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COU
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 /// The upper bound of indirect1 inode index
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
+/// The max size of a file in bytes
+pub const MAX_FILE_SIZE: usize = (INDIRECT1_BOUND + INODE_INDIRECT2_COUNT) * BLOCK_SZ;
+/// Data block of a hole punched in a file, it reads zeros. Block 0 is the super block, never
+/// one of data.
+pub const HOLE: u32 = 0;
 
-/// Super block (6*4 = 32B) of a filesystem
+/// Super block (7*4 = 28B) of a filesystem
 #[repr(C)]
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
+    pub journal_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// inode of the quota file, 0 if there's none, as on images made before quotas
+    pub quota_inode: u32,
 }
 
 // just skip `magic`
@@ -33,10 +46,12 @@ impl core::fmt::Debug for SuperBlock {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("SuperBlock")
             .field("total_blocks", &self.total_blocks)
+            .field("journal_blocks", &self.journal_blocks)
             .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("quota_inode", &self.quota_inode)
             .finish()
     }
 }
@@ -45,6 +60,7 @@ impl SuperBlock {
     pub fn initialize(
         &mut self,
         total_blocks: u32,
+        journal_blocks: u32,
         inode_bitmap_blocks: u32,
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
@@ -53,10 +69,12 @@ impl SuperBlock {
         *self = Self {
             magic: EFS_MAGIC,
             total_blocks,
+            journal_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            quota_inode: 0,
         }
     }
 
@@ -74,61 +92,101 @@ type DataBlock = [u8; BLOCK_SZ];
 pub struct DiskInode {
     pub size: u32,
     pub nlink: u32, // nlink taks 4B
-    _rsv: u32,      // reserve make `direct` is even
-    // when file is small, `direct` refs 28-2 data blocks == (28-2)*512 = 14-1KB
+    /// last read, in ns since the epoch as the fs clock tells
+    pub atime: u64,
+    /// last written
+    pub mtime: u64,
+    /// last written or changed otherwise, e.g. linked
+    pub ctime: u64,
+    // when file is small, `direct` refs 20 data blocks == 20*512 = 10KB
     pub direct: [u32; INODE_DIRECT_COUNT],
     // when file is large, `indirect1` refs to L1 index block, every u32 in it refs to
     // data block, so total 512/4*512 = 64KB
     pub indirect1: u32,
     // similar as `indrect1`, `indrect2` refs to L2 index block, so total 512/4*64KB = 8MB
     pub indirect2: u32,
+    /// permission bits, rwx of owner, group and others
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
     type_: DiskInodeType,
 }
 
+const _: () = assert!(core::mem::size_of::<DiskInode>() == 128);
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
+    /// data is the target path
+    SymLink,
 }
 
 impl DiskInode {
     pub fn initialize(&mut self, type_: DiskInodeType) {
         self.size = 0;
         self.nlink = 1;
+        self.atime = 0;
+        self.mtime = 0;
+        self.ctime = 0;
         self.direct.fill(0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.mode = match type_ {
+            DiskInodeType::File => 0o644,
+            DiskInodeType::Directory => 0o755,
+            DiskInodeType::SymLink => 0o777,
+        };
+        self.uid = 0;
+        self.gid = 0;
         self.type_ = type_;
     }
 
-    pub fn initialize_dir<F: FnMut() -> u32>(
+    /// Content changed at `now`
+    pub fn touch(&mut self, now: u64) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+
+    /// If a read at `now` updates `atime`: only if written since the last read or that was a
+    /// day ago, so not every read writes the inode back
+    pub fn atime_stale(&self, now: u64) -> bool {
+        const DAY_NS: u64 = 24 * 3600 * 1_000_000_000;
+        self.atime <= self.mtime || self.atime + DAY_NS <= now
+    }
+
+    /// Put "." and ".." in a dir just created, its first block taken from `data_alloc`. It's
+    /// left empty if that fails.
+    pub fn initialize_dir<F: FnMut() -> Result<u32>>(
         &mut self,
         self_inode: u32,
         parent_inode: u32,
         mut data_alloc: F,
         block_device: &Arc<dyn BlockDevice>,
-    ) {
+    ) -> Result<()> {
         assert_eq!(self.type_, DiskInodeType::Directory);
 
         // increase size
         let file_count = (self.size as usize) / DIRENT_SZ; // should be 0 when create
         let new_size = ((file_count + 2) * DIRENT_SZ) as u32; // "." and ".."
+                                                              // one block, so none is taken unless all are
         let blocks_needed = self.blocks_num_needed(new_size);
         let mut new_blocks = Vec::new();
         for _ in 0..blocks_needed {
-            new_blocks.push(data_alloc());
+            new_blocks.push(data_alloc()?);
         }
         self.increase_size(new_size, new_blocks, block_device);
         // write both dir entry points to self
         let buf = {
             let mut b = [0u8; 2 * DIRENT_SZ];
-            let dir = DirEntry::new(".", self_inode);
+            let dir = DirEntry::new(".", self_inode)?;
             b[..DIRENT_SZ].copy_from_slice(dir.as_bytes());
-            let dir = DirEntry::new("..", parent_inode);
+            let dir = DirEntry::new("..", parent_inode)?;
             b[DIRENT_SZ..].copy_from_slice(dir.as_bytes());
             b
         };
         self.write_at(file_count * DIRENT_SZ, &buf[..], &block_device);
+        Ok(())
     }
 
     pub fn is_dir(&self) -> bool {
@@ -139,6 +197,10 @@ impl DiskInode {
         self.type_ == DiskInodeType::File
     }
 
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::SymLink
+    }
+
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
         let inner_id = inner_id as usize;
         if inner_id < DIRECT_BOUND {
@@ -166,6 +228,94 @@ impl DiskInode {
         }
     }
 
+    /// Point data block `inner_id` at `block_id`, or make it a hole with `HOLE`. Its index
+    /// blocks must be there.
+    fn set_block_id(&mut self, inner_id: u32, block_id: u32, block_device: &Arc<dyn BlockDevice>) {
+        let inner_id = inner_id as usize;
+        if inner_id < DIRECT_BOUND {
+            self.direct[inner_id] = block_id;
+        } else if inner_id < INDIRECT1_BOUND {
+            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |indirect1: &mut IndirectBlock| {
+                    indirect1[inner_id - INODE_DIRECT_COUNT] = block_id;
+                });
+        } else {
+            let last = inner_id - INDIRECT1_BOUND;
+            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    indirect2[last / INODE_INDIRECT1_COUNT]
+                });
+            get_block_cache(indirect1 as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |indirect1: &mut IndirectBlock| {
+                    indirect1[last % INODE_INDIRECT1_COUNT] = block_id;
+                });
+        }
+    }
+
+    /// Inner ids of the holes among data blocks `start` to `end`
+    pub fn holes(&self, start: u32, end: u32, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        (start..end)
+            .filter(|&inner_id| self.get_block_id(inner_id, block_device) == HOLE)
+            .collect()
+    }
+
+    /// Give each of `holes` a block of `new_blocks`, zeroed already
+    pub fn fill_holes(
+        &mut self,
+        holes: &[u32],
+        new_blocks: Vec<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        assert_eq!(holes.len(), new_blocks.len());
+        for (&inner_id, block_id) in holes.iter().zip(new_blocks) {
+            self.set_block_id(inner_id, block_id, block_device);
+        }
+    }
+
+    /// Blocks taken, index blocks included and holes not
+    pub fn blocks_held(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        Self::total_blocks(self.size) - self.holes(0, self.data_blocks(), block_device).len() as u32
+    }
+
+    /// Punch a hole from byte `start` to `end`, and return the data blocks dropped for it to
+    /// be deallocated: those all in it, or in it to where the file ends. What's in it of the
+    /// others is zeroed. Index blocks and the size stay.
+    pub fn punch(
+        &mut self,
+        start: usize,
+        end: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let size = self.size as usize;
+        let end = end.min(size);
+        let mut freed = Vec::new();
+        let mut pos = start;
+        while pos < end {
+            let inner_id = (pos / BLOCK_SZ) as u32;
+            let block_start = pos / BLOCK_SZ * BLOCK_SZ;
+            let block_end = (block_start + BLOCK_SZ).min(end);
+            let block_id = self.get_block_id(inner_id, block_device);
+            if block_id != HOLE {
+                let whole = block_end == block_start + BLOCK_SZ || block_end == size;
+                if pos == block_start && whole {
+                    self.set_block_id(inner_id, HOLE, block_device);
+                    freed.push(block_id);
+                } else {
+                    get_block_cache(block_id as usize, Arc::clone(block_device))
+                        .lock()
+                        .modify(0, |data_block: &mut DataBlock| {
+                            data_block[pos - block_start..block_end - block_start].fill(0)
+                        });
+                }
+            }
+            pos = block_end;
+        }
+        freed
+    }
+
     fn _data_blocks(size: u32) -> u32 {
         (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
     }
@@ -294,9 +444,72 @@ impl DiskInode {
             });
     }
 
-    /// Clear size to zero and return blocks that should be deallocated.
+    /// Shrink to `new_size` and return blocks that should be deallocated, data and index
+    /// blocks alike. Bytes past `new_size` in the last block are zeroed, so growing again
+    /// reads zeros there.
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let old_total = self.data_blocks() as usize;
+        let new_total = Self::_data_blocks(new_size) as usize;
+        let tail = new_size as usize % BLOCK_SZ;
+        let last = match tail {
+            0 => HOLE,
+            _ => self.get_block_id(new_total as u32 - 1, block_device),
+        };
+        if last != HOLE {
+            get_block_cache(last as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| data_block[tail..].fill(0));
+        }
+
+        // data blocks, while the index blocks are still there to look them up
+        let mut v: Vec<u32> = (new_total..old_total)
+            .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+            .filter(|&block_id| block_id != HOLE)
+            .collect();
+        for entry in self.direct.iter_mut().take(old_total).skip(new_total) {
+            *entry = 0;
+        }
+        // indirect1 block
+        if new_total <= DIRECT_BOUND && old_total > DIRECT_BOUND {
+            v.push(self.indirect1);
+            self.indirect1 = 0;
+        }
+        // indirect1 blocks under indirect2, each covers `INODE_INDIRECT1_COUNT` data blocks
+        if old_total > INDIRECT1_BOUND {
+            let in_use = |total: usize| {
+                (total.max(INDIRECT1_BOUND) - INDIRECT1_BOUND).div_ceil(INODE_INDIRECT1_COUNT)
+            };
+            let (old_in_use, new_in_use) = (in_use(old_total), in_use(new_total));
+            get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(&indirect2[new_in_use..old_in_use]);
+                });
+            // indirect2 block
+            if new_total <= INDIRECT1_BOUND {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        self.size = new_size;
+        v
+    }
+
+    /// Clear size to zero and return blocks that should be deallocated, holes aren't among them.
     /// We will clear the block contents to zero later.
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        let mut v = self.clear_all(block_device);
+        v.retain(|&block_id| block_id != HOLE);
+        v
+    }
+
+    /// `clear_size`, holes and all
+    fn clear_all(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
         let mut v = Vec::new();
         let mut data_blocks = self.data_blocks() as usize;
         self.size = 0;
@@ -386,15 +599,15 @@ impl DiskInode {
             let end_of_current_block = ((start / BLOCK_SZ + 1) * BLOCK_SZ).min(end);
             let block_read_size = end_of_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            match self.get_block_id(start_block as u32, block_device) {
+                HOLE => dst.fill(0),
+                block_id => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    }),
+            }
             read_size += block_read_size;
             // to next block
             if end_of_current_block == end {
@@ -407,7 +620,7 @@ impl DiskInode {
     }
 
     /// Write data into current disk inode
-    /// size must be adjusted properly beforehand
+    /// size must be adjusted properly beforehand, and holes written filled
     pub fn write_at(
         &mut self,
         offset: usize,
@@ -417,6 +630,10 @@ impl DiskInode {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
+        // nothing to write, maybe not even a block
+        if start == end {
+            return 0;
+        }
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0;
 
@@ -424,12 +641,15 @@ impl DiskInode {
             let end_of_current_block = ((start / BLOCK_SZ + 1) * BLOCK_SZ).min(end);
             let block_write_size = end_of_current_block - start;
             let src = &buf[write_size..write_size + block_write_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            let block_id = self.get_block_id(start_block as u32, block_device) as usize;
+            assert_ne!(block_id as u32, HOLE, "hole written");
+            // all of it is written, what's on the device needn't be read first
+            let block_cache = if block_write_size == BLOCK_SZ {
+                get_block_cache_overwrite(block_id, Arc::clone(block_device))
+            } else {
+                get_block_cache(block_id, Arc::clone(block_device))
+            };
+            block_cache.lock().modify(0, |data_block: &mut DataBlock| {
                 let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
                 dst.copy_from_slice(src);
             });
@@ -457,6 +677,7 @@ pub struct DirEntry {
 pub const DIRENT_SZ: usize = 32;
 
 impl DirEntry {
+    /// Entry of no name, to read one into
     pub fn new_empty() -> Self {
         Self {
             name: [0; NAME_LENGTH_LIMIT + 1],
@@ -464,32 +685,52 @@ impl DirEntry {
         }
     }
 
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        assert!(name.len() <= NAME_LENGTH_LIMIT);
+    /// Entry of `name` for inode `inode_number`, `NameTooLong` if it doesn't fit. `Invalid` if
+    /// it's empty, that's a slot free.
+    pub fn new(name: &str, inode_number: u32) -> Result<Self> {
+        if name.is_empty() {
+            return Err(EfsError::Invalid);
+        }
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(EfsError::NameTooLong);
+        }
         let mut buf = [0; NAME_LENGTH_LIMIT + 1];
         buf[..name.len()].copy_from_slice(name.as_bytes());
-        Self {
+        Ok(Self {
             name: buf,
             inode_number,
-        }
+        })
     }
 
+    /// Bytes of the entry as on disk
     pub fn as_bytes(&self) -> &[u8] {
         unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
     }
 
+    /// Bytes of the entry to read one from disk into
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
         unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
     }
 
+    /// Name of the entry. One of a corrupt image is cut short where it stops making sense:
+    /// with no nul it's all the bytes there, and it ends before the first one not utf-8.
     pub fn name(&self) -> &str {
         let len = self
             .name
             .iter()
             .position(|c| c == &0)
-            .expect("name not end with \\0!");
-        core::str::from_utf8(&self.name[..len]).unwrap()
+            .unwrap_or(self.name.len());
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap(),
+        }
     }
+    /// Is it a slot free? An entry removed leaves one, so the others never move
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == 0
+    }
+
+    /// Inode the entry names
     pub fn inode_number(&self) -> u32 {
         self.inode_number
     }
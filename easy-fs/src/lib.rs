@@ -9,10 +9,25 @@ pub const BLOCK_SZ: usize = 512;
 mod bitmap;
 mod block_cache;
 mod block_dev;
+mod dentry_cache;
 mod efs;
+mod error;
+mod fsck;
+mod journal;
 mod layout;
+mod quota;
+mod stream;
+#[cfg(feature = "tutorial")]
+pub mod tutorial;
 mod vfs;
 
-pub use block_dev::BlockDevice;
+pub use block_cache::{block_cache_stats, BlockCacheStats, BLOCK_CACHE_SIZE};
+pub use block_dev::{BlockDevice, BlockDeviceStats};
+pub use dentry_cache::{dentry_cache_stats, DentryCacheStats, DENTRY_CACHE_SIZE};
 pub use efs::EasyFileSystem;
+pub use error::EfsError;
+pub use fsck::DirProblem;
+pub use layout::{DirEntry, DIRENT_SZ, MAX_FILE_SIZE};
+pub use quota::{Quota, QUOTA_GRACE_NS};
+pub use stream::{InodeReader, InodeWriter};
 pub use vfs::Inode;
@@ -0,0 +1,298 @@
+//! Per-uid quotas of blocks and inodes. Usage and limits are kept in a file linked in no dir,
+//! of `QUOTA_BLOCKS` blocks taken when the filesystem is made, the super block tells which
+//! inode it is. Only uids given limits are counted, root never is.
+//!
+//! An inode is charged to its owner, for itself and every block it takes, index blocks too.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    block_cache::get_block_cache,
+    efs::EasyFileSystem,
+    error::{EfsError, Result},
+    layout::{DiskInode, DiskInodeType},
+    BLOCK_SZ,
+};
+
+/// Inode of the quota file on a filesystem made with one, the first after the root
+pub(crate) const QUOTA_INODE: u32 = 1;
+/// Blocks of the quota file
+pub(crate) const QUOTA_BLOCKS: u32 = 4;
+/// How long usage may stay over a soft limit before it's held to it, in ns
+pub const QUOTA_GRACE_NS: u64 = 7 * 24 * 3600 * 1_000_000_000;
+
+/// Usage and limits of a uid, a limit of 0 is none
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// blocks of the inodes owned
+    pub blocks: u32,
+    /// inodes owned
+    pub inodes: u32,
+    /// `blocks` may go over it until `block_grace`
+    pub block_soft: u32,
+    /// `blocks` can't go over it
+    pub block_hard: u32,
+    /// `inodes` may go over it until `inode_grace`
+    pub inode_soft: u32,
+    /// `inodes` can't go over it
+    pub inode_hard: u32,
+    /// when `blocks` over `block_soft` stops being let grow, by the fs clock; 0 if not over
+    pub block_grace: u64,
+    /// same as `block_grace`, of `inodes`
+    pub inode_grace: u64,
+}
+
+impl Quota {
+    fn has_limits(&self) -> bool {
+        self.block_soft != 0 || self.block_hard != 0 || self.inode_soft != 0 || self.inode_hard != 0
+    }
+}
+
+/// A uid's slot in the quota file
+#[repr(C)]
+struct QuotaRecord {
+    /// 0 for a slot free
+    uid: u32,
+    _reserved: [u32; 5],
+    quota: Quota,
+}
+
+const QUOTA_RECORD_SZ: usize = 64;
+const _: () = assert!(core::mem::size_of::<QuotaRecord>() == QUOTA_RECORD_SZ);
+const QUOTAS_PER_BLOCK: usize = BLOCK_SZ / QUOTA_RECORD_SZ;
+
+type QuotaBlock = [QuotaRecord; QUOTAS_PER_BLOCK];
+
+/// Grace of a usage about to become `usage` against `soft` and `hard`, one is started when it
+/// goes over `soft`. `QuotaExceeded` if it can't.
+fn check_limits(usage: u32, soft: u32, hard: u32, grace: u64, now: u64) -> Result<u64> {
+    if hard != 0 && usage > hard {
+        return Err(EfsError::QuotaExceeded);
+    }
+    if soft == 0 || usage <= soft {
+        return Ok(0);
+    }
+    match grace {
+        0 => Ok(now + QUOTA_GRACE_NS),
+        grace if now < grace => Ok(grace),
+        _ => Err(EfsError::QuotaExceeded),
+    }
+}
+
+/// Grace of a usage become `usage` other than by growing past its limits, like a limit set or
+/// an inode handed over: started if it's over `soft` now, over if it's not
+fn regrace(usage: u32, soft: u32, grace: u64, now: u64) -> u64 {
+    match grace {
+        _ if soft == 0 || usage <= soft => 0,
+        0 => now + QUOTA_GRACE_NS,
+        grace => grace,
+    }
+}
+
+impl EasyFileSystem {
+    /// Make the quota file, inode `inode_id` just allocated. Its blocks are zeros already.
+    pub(crate) fn create_quota_file(&mut self, inode_id: u32) -> Result<()> {
+        let blocks = (0..QUOTA_BLOCKS)
+            .map(|_| self.alloc_data())
+            .collect::<Result<Vec<_>>>()?;
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.initialize(DiskInodeType::File);
+                disk_inode.mode = 0o600;
+                disk_inode.increase_size(
+                    QUOTA_BLOCKS * BLOCK_SZ as u32,
+                    blocks.clone(),
+                    &self.block_device,
+                );
+            });
+        self.quota_blocks = blocks;
+        self.quota_inode = inode_id;
+        Ok(())
+    }
+
+    /// Find the blocks of quota file `inode_id`, 0 for none
+    pub(crate) fn load_quota_file(&mut self, inode_id: u32) {
+        if inode_id == 0 {
+            return;
+        }
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        self.quota_blocks = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| {
+                (0..disk_inode.data_blocks())
+                    .map(|inner_id| disk_inode.get_block_id(inner_id, &self.block_device))
+                    .collect()
+            });
+        self.quota_inode = inode_id;
+    }
+
+    /// Call `f` on the record of `uid`, or on the first one free if it has none and `insert`.
+    /// `None` if there's neither.
+    fn modify_quota_record<V>(
+        &self,
+        uid: u16,
+        insert: bool,
+        f: impl FnOnce(&mut QuotaRecord) -> V,
+    ) -> Option<V> {
+        let uid = uid as u32;
+        let mut free = None;
+        for &block_id in &self.quota_blocks {
+            let cache = get_block_cache(block_id as usize, Arc::clone(&self.block_device));
+            let found = cache.lock().read(0, |records: &QuotaBlock| {
+                records
+                    .iter()
+                    .position(|r| r.uid == uid)
+                    .ok_or_else(|| records.iter().position(|r| r.uid == 0))
+            });
+            match found {
+                Ok(i) => {
+                    return Some(
+                        cache
+                            .lock()
+                            .modify(0, |records: &mut QuotaBlock| f(&mut records[i])),
+                    )
+                }
+                Err(Some(i)) if free.is_none() => free = Some((cache, i)),
+                Err(_) => {}
+            }
+        }
+        let (cache, i) = free.filter(|_| insert)?;
+        let v = cache.lock().modify(0, |records: &mut QuotaBlock| {
+            let record = &mut records[i];
+            record.uid = uid;
+            record.quota = Quota::default();
+            f(record)
+        });
+        Some(v)
+    }
+
+    /// Quota of `uid`, `None` if it's given no limits
+    pub fn quota(&self, uid: u16) -> Option<Quota> {
+        if uid == 0 {
+            return None;
+        }
+        self.modify_quota_record(uid, false, |record| record.quota)
+    }
+
+    /// Give `uid` the limits of `limits`, the rest of it is ignored. What it uses is counted
+    /// from then on, none at all takes it off. `Invalid` for root, `NoSpace` if the quota file
+    /// is full or there's none.
+    pub fn set_quota(&mut self, uid: u16, limits: &Quota) -> Result<()> {
+        if uid == 0 {
+            return Err(EfsError::Invalid);
+        }
+        if !limits.has_limits() {
+            self.modify_quota_record(uid, false, |record| record.uid = 0);
+            return Ok(());
+        }
+        let now = self.now();
+        // counted anew if it wasn't already
+        let usage = match self.quota(uid) {
+            Some(quota) => (quota.blocks, quota.inodes),
+            None => self.usage_of(uid),
+        };
+        self.modify_quota_record(uid, true, |record| {
+            let (blocks, inodes) = usage;
+            let quota = &record.quota;
+            record.quota = Quota {
+                blocks,
+                inodes,
+                block_grace: regrace(blocks, limits.block_soft, quota.block_grace, now),
+                inode_grace: regrace(inodes, limits.inode_soft, quota.inode_grace, now),
+                ..*limits
+            };
+        })
+        .ok_or(EfsError::NoSpace)
+    }
+
+    /// (blocks, inodes) owned by `uid`, the quota file is no one's
+    fn usage_of(&self, uid: u16) -> (u32, u32) {
+        let mut usage = (0, 0);
+        for inode_id in self.allocated_inodes() {
+            if inode_id == self.quota_inode {
+                continue;
+            }
+            let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+            get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(block_offset, |disk_inode: &DiskInode| {
+                    if disk_inode.uid == uid {
+                        usage.0 += disk_inode.blocks_held(&self.block_device);
+                        usage.1 += 1;
+                    }
+                });
+        }
+        usage
+    }
+
+    /// Count `blocks` and `inodes` more as used by `uid`, `QuotaExceeded` if it can't have them
+    pub(crate) fn charge(&mut self, uid: u16, blocks: u32, inodes: u32) -> Result<()> {
+        if uid == 0 {
+            return Ok(());
+        }
+        let now = self.now();
+        self.modify_quota_record(uid, false, |record| {
+            let quota = &mut record.quota;
+            let (new_blocks, new_inodes) = (quota.blocks + blocks, quota.inodes + inodes);
+            // only what grows is held to its limits
+            let block_grace = match blocks {
+                0 => quota.block_grace,
+                _ => check_limits(
+                    new_blocks,
+                    quota.block_soft,
+                    quota.block_hard,
+                    quota.block_grace,
+                    now,
+                )?,
+            };
+            let inode_grace = match inodes {
+                0 => quota.inode_grace,
+                _ => check_limits(
+                    new_inodes,
+                    quota.inode_soft,
+                    quota.inode_hard,
+                    quota.inode_grace,
+                    now,
+                )?,
+            };
+            (quota.blocks, quota.inodes) = (new_blocks, new_inodes);
+            (quota.block_grace, quota.inode_grace) = (block_grace, inode_grace);
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+    }
+
+    /// Count `blocks` and `inodes` no longer used by `uid`
+    pub(crate) fn credit(&mut self, uid: u16, blocks: u32, inodes: u32) {
+        self.recount(uid, |quota| {
+            quota.blocks = quota.blocks.saturating_sub(blocks);
+            quota.inodes = quota.inodes.saturating_sub(inodes);
+        });
+    }
+
+    /// Move `blocks` and `inodes` used from uid `from` to `to`, whatever the limits of `to`
+    pub(crate) fn transfer(&mut self, from: u16, to: u16, blocks: u32, inodes: u32) {
+        self.credit(from, blocks, inodes);
+        self.recount(to, |quota| {
+            quota.blocks += blocks;
+            quota.inodes += inodes;
+        });
+    }
+
+    /// Change the usage of `uid` by `f`, graces follow it
+    fn recount(&self, uid: u16, f: impl FnOnce(&mut Quota)) {
+        if uid == 0 {
+            return;
+        }
+        let now = self.now();
+        self.modify_quota_record(uid, false, |record| {
+            let quota = &mut record.quota;
+            f(quota);
+            quota.block_grace = regrace(quota.blocks, quota.block_soft, quota.block_grace, now);
+            quota.inode_grace = regrace(quota.inodes, quota.inode_soft, quota.inode_grace, now);
+        });
+    }
+}
@@ -0,0 +1,116 @@
+//! Cursors reading or writing an inode on from where the last call left off, for callers
+//! that would otherwise keep the offset themselves
+
+use alloc::vec::Vec;
+
+use crate::{error::Result, vfs::Inode, BLOCK_SZ};
+
+/// Reads an inode from an offset on
+pub struct InodeReader<'a> {
+    inode: &'a Inode,
+    offset: usize,
+}
+
+impl<'a> InodeReader<'a> {
+    /// Reader of `inode` from `offset`
+    pub fn new(inode: &'a Inode, offset: usize) -> Self {
+        Self { inode, offset }
+    }
+
+    /// Where the next read starts
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Read to `buf` until it's full or the file ends, 0 at the end
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.inode.read_at(self.offset, buf);
+        self.offset += len;
+        len
+    }
+
+    /// Read the rest of the file to the end of `buf`
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> usize {
+        let start = buf.len();
+        buf.resize(start + self.inode.get_size().saturating_sub(self.offset), 0);
+        let len = self.read(&mut buf[start..]);
+        // the file may have shrunk since
+        buf.truncate(start + len);
+        len
+    }
+}
+
+/// Writes an inode from an offset on. What doesn't reach the next block boundary is held
+/// back until more comes or it's flushed, so blocks are written whole when they can be and
+/// aren't read first.
+pub struct InodeWriter<'a> {
+    inode: &'a Inode,
+    /// where `pending` goes, all before it is written
+    offset: usize,
+    pending: Vec<u8>,
+}
+
+impl<'a> InodeWriter<'a> {
+    /// Writer of `inode` from `offset`
+    pub fn new(inode: &'a Inode, offset: usize) -> Self {
+        Self {
+            inode,
+            offset,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Writer of `inode` from its end
+    pub fn append(inode: &'a Inode) -> Self {
+        Self::new(inode, inode.get_size())
+    }
+
+    /// Where what's written so far ends, not counting what's held back
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Write all of `buf`, `Err` if the file can't grow to hold it and `offset` tells how far
+    /// it got. The tail of it short of a block boundary waits for `flush`.
+    pub fn write(&mut self, mut buf: &[u8]) -> Result<usize> {
+        let len = buf.len();
+        let end = self.offset + self.pending.len();
+        if end % BLOCK_SZ != 0 {
+            // up to the boundary first
+            let head = buf.len().min(BLOCK_SZ - end % BLOCK_SZ);
+            self.pending.extend_from_slice(&buf[..head]);
+            buf = &buf[head..];
+            if (end + head) % BLOCK_SZ != 0 {
+                return Ok(len);
+            }
+            if let Err(e) = self.flush() {
+                self.pending.truncate(self.pending.len() - head);
+                return Err(e);
+            }
+        } else if !self.pending.is_empty() {
+            self.flush()?;
+        }
+        let whole = buf.len() / BLOCK_SZ * BLOCK_SZ;
+        if whole > 0 {
+            self.offset += self.inode.write_at(self.offset, &buf[..whole])?;
+        }
+        self.pending.extend_from_slice(&buf[whole..]);
+        Ok(len)
+    }
+
+    /// Write what's held back
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.offset += self.inode.write_at(self.offset, &self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for InodeWriter<'_> {
+    fn drop(&mut self) {
+        // the caller had its chance to see it fail
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,81 @@
+//! The API the tutorial is written against, errors panic as they did before there were any.
+//! Only lookups and creations may miss, with `None`, and what isn't wrapped here is reached
+//! through `Deref`, returning errors.
+
+use alloc::sync::Arc;
+use core::ops::Deref;
+use spin::Mutex;
+
+use crate::{block_dev::BlockDevice, efs, error::EfsError, vfs};
+
+/// Opens and creates an `efs::EasyFileSystem`, panicking if it can't
+pub struct EasyFileSystem;
+
+impl EasyFileSystem {
+    /// `efs::EasyFileSystem::create`, panics if the device is too small
+    pub fn create(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        cache_blocks: usize,
+    ) -> Arc<Mutex<efs::EasyFileSystem>> {
+        efs::EasyFileSystem::create(
+            block_device,
+            total_blocks,
+            inode_bitmap_blocks,
+            cache_blocks,
+        )
+        .expect("Error creating EFS!")
+    }
+
+    /// `efs::EasyFileSystem::open`, panics if there's no filesystem on the device
+    pub fn open(
+        block_device: Arc<dyn BlockDevice>,
+        cache_blocks: usize,
+    ) -> Arc<Mutex<efs::EasyFileSystem>> {
+        efs::EasyFileSystem::open(block_device, cache_blocks).expect("Error loading EFS!")
+    }
+
+    /// Get the root inode of the filesystem
+    pub fn root_inode(efs: &Arc<Mutex<efs::EasyFileSystem>>) -> Inode {
+        Inode(Arc::new(efs::EasyFileSystem::root_inode(efs)))
+    }
+}
+
+/// `vfs::Inode` whose errors panic
+pub struct Inode(Arc<vfs::Inode>);
+
+impl Deref for Inode {
+    type Target = vfs::Inode;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Inode {
+    /// Find inode under current inode by name, `None` if there's none
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        match self.0.find(name) {
+            Ok(inode) => Some(Arc::new(Self(inode))),
+            Err(EfsError::NotFound) => None,
+            Err(e) => panic!("find {}: {:?}", name, e),
+        }
+    }
+
+    /// Create regular file under current inode, `None` if it exists already
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        match self.0.create(name) {
+            Ok(inode) => Some(Arc::new(Self(inode))),
+            Err(EfsError::Exists) => None,
+            Err(e) => panic!("create {}: {:?}", name, e),
+        }
+    }
+
+    /// Write data to current inode, panics if it can't grow to hold it
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.0
+            .write_at(offset, buf)
+            .unwrap_or_else(|e| panic!("write_at {}: {:?}", offset, e))
+    }
+}
@@ -1,13 +1,34 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc, vec::Vec};
-use spin::{Mutex, MutexGuard};
+use alloc::{borrow::ToOwned, string::String, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
 
 use crate::{
-    block_cache::{block_cache_sync_all, get_block_cache},
+    block_cache::{
+        block_cache_drop, block_cache_sync, block_cache_sync_all, block_key, get_block_cache,
+    },
     block_dev::BlockDevice,
+    dentry_cache::{
+        dentry_cache_drop_fs, dentry_cache_forget_dir, dentry_cache_insert, dentry_cache_lookup,
+        dentry_cache_remove,
+    },
     efs::EasyFileSystem,
-    layout::{DirEntry, DiskInode, DiskInodeType, DIRENT_SZ},
+    error::{EfsError, Result},
+    layout::{DirEntry, DiskInode, DiskInodeType, DIRENT_SZ, HOLE, MAX_FILE_SIZE},
+    quota::Quota,
+    BLOCK_SZ,
 };
 
+/// Symlinks followed in one lookup at most, more is taken as a loop
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// Non-empty names of `path` in reverse, so the first one pops first
+fn path_names(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .rev()
+        .map(String::from)
+        .collect()
+}
+
 /// Virtual filesystem layer over easy-fs
 #[derive(Clone)]
 pub struct Inode {
@@ -17,6 +38,9 @@ pub struct Inode {
     block_id: usize,
     block_offset: usize,
 
+    // dir it was found in, as of then: a rename since isn't seen
+    parent_id: Option<u32>,
+
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
 }
@@ -34,6 +58,7 @@ impl Inode {
             inode_id,
             block_id: block_id as usize,
             block_offset,
+            parent_id: None,
             fs,
             block_device,
         }
@@ -53,9 +78,18 @@ impl Inode {
             .modify(self.block_offset, f)
     }
 
-    /// Find inode under a disk inode by name
+    /// Find inode under current inode by name, `disk_inode` is the one of current inode
     fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        assert!(disk_inode.is_dir());
+        dentry_cache_lookup(self.fs_id(), self.inode_id, name, || {
+            self.scan_inode_id(name, disk_inode)
+        })
+    }
+
+    /// Find inode under a disk inode by name, reading through its direntries
+    fn scan_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        if !disk_inode.is_dir() {
+            return None;
+        }
         // data of `disk_inode` should be array of `Dirent`s
         let file_count = (disk_inode.size as usize) / DIRENT_SZ;
         let mut dirent = DirEntry::new_empty();
@@ -64,7 +98,7 @@ impl Inode {
                 disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
                 DIRENT_SZ
             );
-            if dirent.name() == name {
+            if !dirent.is_empty() && dirent.name() == name {
                 return Some(dirent.inode_number());
             }
         }
@@ -77,7 +111,9 @@ impl Inode {
         disk_inode: &DiskInode,
         pred: impl Fn(&DirEntry) -> bool,
     ) -> Option<DirEntry> {
-        assert!(disk_inode.is_dir());
+        if !disk_inode.is_dir() {
+            return None;
+        }
         let file_count = (disk_inode.size as usize) / DIRENT_SZ;
         let mut dirent = DirEntry::new_empty();
         for i in 0..file_count {
@@ -85,13 +121,54 @@ impl Inode {
                 disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
                 DIRENT_SZ
             );
-            if pred(&dirent) {
+            if !dirent.is_empty() && pred(&dirent) {
                 return Some(dirent);
             }
         }
         None
     }
 
+    /// Index of direntry under a disk inode by name
+    fn find_dirent_index(&self, name: &str, disk_inode: &DiskInode) -> Option<usize> {
+        if !disk_inode.is_dir() {
+            return None;
+        }
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::new_empty();
+        (0..file_count).find(|&i| {
+            assert_eq!(
+                disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
+                DIRENT_SZ
+            );
+            !dirent.is_empty() && dirent.name() == name
+        })
+    }
+
+    /// Index of the first slot free under a disk inode, `None` if it's full
+    fn find_free_index(&self, disk_inode: &DiskInode) -> Option<usize> {
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::new_empty();
+        (0..file_count).find(|&i| {
+            assert_eq!(
+                disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
+                DIRENT_SZ
+            );
+            dirent.is_empty()
+        })
+    }
+
+    /// Vfs inode of `inode_id` on the same fs
+    fn inode_of(&self, inode_id: u32, fs: &EasyFileSystem) -> Self {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Self::new(
+            inode_id,
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+        )
+    }
+
     /// Read child direnty by inode_id
     pub fn read_dirent<V>(&self, inode_id: u32, f: impl FnOnce(DirEntry) -> V) -> Option<V> {
         self.read_disk_inode(|disk_inode| {
@@ -100,15 +177,22 @@ impl Inode {
         .map(f)
     }
 
-    /// Get inodes of dir entries
-    pub fn dirents(&self, cursor: u32) -> Vec<(String, Arc<Inode>)> {
+    /// Vfs inode of `inode_id` a direntry of current inode names, without looking it up
+    pub fn child(&self, inode_id: u32) -> Self {
+        self.inode_of(inode_id, &self.fs.lock())
+    }
+
+    /// Get inodes of dir entries from the `cursor`-th on
+    pub fn dirents(&self, cursor: u32) -> Result<Vec<(String, Arc<Inode>)>> {
         let fs = self.fs.lock();
         let cursor = cursor as usize;
         self.read_disk_inode(|disk_inode| {
-            assert!(disk_inode.is_dir());
+            if !disk_inode.is_dir() {
+                return Err(EfsError::NotDir);
+            }
             let file_count = (disk_inode.size as usize) / DIRENT_SZ;
             if cursor >= file_count {
-                return Vec::new();
+                return Ok(Vec::new());
             }
             let mut v = Vec::with_capacity(file_count - cursor);
             let mut dirent = DirEntry::new_empty();
@@ -117,6 +201,9 @@ impl Inode {
                     disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
                     DIRENT_SZ
                 );
+                if dirent.is_empty() {
+                    continue;
+                }
                 let inode_id = dirent.inode_number();
                 let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
                 v.push((
@@ -130,48 +217,118 @@ impl Inode {
                     )),
                 ))
             }
-            v
+            Ok(v)
         })
     }
 
-    /// Find inode under current inode(recursively) by name
-    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
+    /// Find inode under current inode(recursively) by name, symlinks on the way are followed.
+    /// A path naming no other inode, like "" or ".", is current inode itself.
+    pub fn find(self: &Arc<Self>, path: &str) -> Result<Arc<Inode>> {
+        // names left to walk, the next one last
+        let mut names = path_names(path);
+        if names.iter().all(|name| name == ".") {
+            return Ok(self.clone());
+        }
+
         let fs = self.fs.lock();
         let mut inode_id = self.inode_id;
         let mut block_id = self.block_id as u32;
         let mut block_offset = self.block_offset;
-
-        for name in path.split('/').filter(|s| !s.is_empty()) {
-            let opt_inode_id = get_block_cache(block_id as usize, self.block_device.clone())
+        let mut parent_id = self.parent_id;
+        let mut follows = 0;
+        while let Some(name) = names.pop() {
+            let child_id = get_block_cache(block_id as usize, self.block_device.clone())
                 .lock()
                 .read(block_offset, |disk_inode: &DiskInode| {
-                    if disk_inode.is_file() {
-                        return None;
+                    if !disk_inode.is_dir() {
+                        return Err(EfsError::NotDir);
                     }
-                    self.find_inode_id(name, disk_inode)
+                    dentry_cache_lookup(self.fs_id(), inode_id, &name, || {
+                        self.scan_inode_id(&name, disk_inode)
+                    })
+                    .ok_or(EfsError::NotFound)
+                })?;
+            let (child_block_id, child_block_offset) = fs.get_disk_inode_pos(child_id);
+            let target = get_block_cache(child_block_id as usize, self.block_device.clone())
+                .lock()
+                .read(child_block_offset, |disk_inode: &DiskInode| {
+                    disk_inode
+                        .is_symlink()
+                        .then(|| self.link_target(disk_inode))
                 });
-            match opt_inode_id {
-                Some(v) => {
-                    inode_id = v;
-                    (block_id, block_offset) = fs.get_disk_inode_pos(v);
+            match target {
+                Some(target) => {
+                    follows += 1;
+                    if follows > MAX_SYMLINK_FOLLOWS {
+                        return Err(EfsError::Loop);
+                    }
+                    // relative target starts from the dir holding the link
+                    if target.starts_with('/') {
+                        inode_id = 0;
+                        (block_id, block_offset) = fs.get_disk_inode_pos(0);
+                        parent_id = None;
+                    }
+                    names.extend(path_names(&target));
+                }
+                None => {
+                    parent_id = match name.as_str() {
+                        "." => parent_id,
+                        ".." => None,
+                        _ => Some(inode_id),
+                    };
+                    inode_id = child_id;
+                    (block_id, block_offset) = (child_block_id, child_block_offset);
                 }
-                _ => return None,
             }
         }
-        Some(Arc::new(Self::new(
-            inode_id,
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
+        Ok(Arc::new(Self {
+            parent_id,
+            ..Self::new(
+                inode_id,
+                block_id,
+                block_offset,
+                self.fs.clone(),
+                self.block_device.clone(),
+            )
+        }))
+    }
+
+    /// Inode direntry `name` of current inode is, a symlink is the link itself
+    pub fn lookup_link(&self, name: &str) -> Result<Arc<Inode>> {
+        let inode_id = self.lookup(name)?.ok_or(EfsError::NotFound)?;
+        let fs = self.fs.lock();
+        let parent_id = match name {
+            "." => self.parent_id,
+            ".." => None,
+            _ => Some(self.inode_id),
+        };
+        Ok(Arc::new(Self {
+            parent_id,
+            ..self.inode_of(inode_id, &fs)
+        }))
+    }
+
+    /// Dir current inode was found in, or its `..` if that's not known. Root is its own parent.
+    pub fn parent(&self) -> Result<Arc<Inode>> {
+        let fs = self.fs.lock();
+        let parent_id = match self.parent_id {
+            Some(parent_id) => parent_id,
+            None => self.read_disk_inode(|disk_inode| {
+                if !disk_inode.is_dir() {
+                    return Err(EfsError::NotDir);
+                }
+                self.find_inode_id("..", disk_inode)
+                    .ok_or(EfsError::Corrupt)
+            })?,
+        };
+        Ok(Arc::new(self.inode_of(parent_id, &fs)))
     }
 
     /// List inodes under current inode
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            if disk_inode.is_file() {
+            if !disk_inode.is_dir() {
                 return Vec::new();
             }
             let file_count = (disk_inode.size as usize) / DIRENT_SZ;
@@ -182,139 +339,406 @@ impl Inode {
                     disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
                     DIRENT_SZ
                 );
-                v.push(dirent.name().to_owned());
+                if !dirent.is_empty() {
+                    v.push(dirent.name().to_owned());
+                }
             }
             v
         })
     }
 
-    /// Increase the size of a disk inode
+    /// Increase the size of a disk inode, it's left as it was if that fails
     fn increase_size(
         &self,
-        new_size: u32,
+        new_size: usize,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
-    ) {
+        fs: &mut EasyFileSystem,
+    ) -> Result<()> {
+        if new_size > MAX_FILE_SIZE {
+            return Err(EfsError::FileTooLarge);
+        }
+        let new_size = new_size as u32;
         if new_size <= disk_inode.size {
-            return;
+            return Ok(());
         }
 
         let blocks_needed = disk_inode.blocks_num_needed(new_size);
+        let new_blocks = Self::alloc_blocks(disk_inode.uid, blocks_needed, fs)?;
+        disk_inode.increase_size(new_size, new_blocks, &self.block_device);
+        Ok(())
+    }
+
+    /// Give the holes of a disk inode from byte `start` to `end` blocks, so they can be written.
+    /// It's left as it was if that fails.
+    fn fill_holes(
+        &self,
+        start: usize,
+        end: usize,
+        disk_inode: &mut DiskInode,
+        fs: &mut EasyFileSystem,
+    ) -> Result<()> {
+        let end = end.min(disk_inode.size as usize);
+        if start >= end {
+            return Ok(());
+        }
+        let holes = disk_inode.holes(
+            (start / BLOCK_SZ) as u32,
+            end.div_ceil(BLOCK_SZ) as u32,
+            &self.block_device,
+        );
+        let new_blocks = Self::alloc_blocks(disk_inode.uid, holes.len() as u32, fs)?;
+        disk_inode.fill_holes(&holes, new_blocks, &self.block_device);
+        Ok(())
+    }
+
+    /// Allocate `count` data blocks charged to `uid`, all of them or none
+    fn alloc_blocks(uid: u16, count: u32, fs: &mut EasyFileSystem) -> Result<Vec<u32>> {
+        fs.charge(uid, count, 0)?;
         let mut new_blocks = Vec::new();
-        for _ in 0..blocks_needed {
-            new_blocks.push(fs.alloc_data());
+        for _ in 0..count {
+            match fs.alloc_data() {
+                Ok(block) => new_blocks.push(block),
+                Err(e) => {
+                    for block in new_blocks {
+                        fs.dealloc_data(block);
+                    }
+                    fs.credit(uid, count, 0);
+                    return Err(e);
+                }
+            }
         }
-        disk_inode.increase_size(new_size, new_blocks, &self.block_device);
+        Ok(new_blocks)
+    }
+
+    /// Add a direntry of `name` for `inode_id` to current dir, at `now`. It takes the first
+    /// slot free, or goes at the end if there's none.
+    fn append_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        now: u64,
+        fs: &mut EasyFileSystem,
+    ) -> Result<()> {
+        let dirent = DirEntry::new(name, inode_id)?;
+        self.modify_disk_inode(|disk_inode| {
+            let i = match self.find_free_index(disk_inode) {
+                Some(i) => i,
+                None => {
+                    let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+                    self.increase_size((file_count + 1) * DIRENT_SZ, disk_inode, fs)?;
+                    file_count
+                }
+            };
+            disk_inode.write_at(i * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+            disk_inode.touch(now);
+            Ok(())
+        })?;
+        dentry_cache_insert(self.fs_id(), self.inode_id, name, inode_id);
+        Ok(())
+    }
+
+    /// Id of `name` in current dir, `NotDir` if it's not one
+    fn lookup(&self, name: &str) -> Result<Option<u32>> {
+        self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return Err(EfsError::NotDir);
+            }
+            Ok(self.find_inode_id(name, disk_inode))
+        })
     }
 
-    /// Create inode under current inode by name
-    fn create_inode(&self, name: &str, inode_type: DiskInodeType) -> Option<Arc<Inode>> {
+    /// Create inode under current inode by name, owned by `owner`
+    fn create_inode(
+        &self,
+        name: &str,
+        inode_type: DiskInodeType,
+        owner: (u16, u16),
+    ) -> Result<Arc<Inode>> {
         let mut fs = self.fs.lock();
-        let op = |root_inode: &DiskInode| {
-            assert!(root_inode.is_dir());
-            self.find_inode_id(name, root_inode)
-        };
+        let _txn = fs.transaction();
+        self.create_inode_locked(name, inode_type, owner, &mut fs)
+    }
 
+    fn create_inode_locked(
+        &self,
+        name: &str,
+        inode_type: DiskInodeType,
+        owner: (u16, u16),
+        fs: &mut EasyFileSystem,
+    ) -> Result<Arc<Inode>> {
         // exist already
-        if self.read_disk_inode(op).is_some() {
-            return None;
+        if self.lookup(name)?.is_some() {
+            return Err(EfsError::Exists);
         }
 
-        // 1. alloc inode
-        let new_inode_id = fs.alloc_inode();
-        // 2. init inode
+        // 1. alloc inode, and the first block of a dir, so nothing fails once it's linked
+        let now = fs.now();
+        let (uid, gid) = owner;
+        let dir_blocks = (inode_type == DiskInodeType::Directory) as u32;
+        fs.charge(uid, dir_blocks, 1)?;
+        let new_inode_id = match fs.alloc_inode() {
+            Ok(inode_id) => inode_id,
+            Err(e) => {
+                fs.credit(uid, dir_blocks, 1);
+                return Err(e);
+            }
+        };
+        let dir_block = match inode_type {
+            DiskInodeType::Directory => match fs.alloc_data() {
+                Ok(block) => Some(block),
+                Err(e) => {
+                    fs.dealloc_inode(new_inode_id);
+                    fs.credit(uid, dir_blocks, 1);
+                    return Err(e);
+                }
+            },
+            _ => None,
+        };
+        // 2. modify current inode: add one more dirent
+        if let Err(e) = self.append_dirent(name, new_inode_id, now, fs) {
+            if let Some(block) = dir_block {
+                fs.dealloc_data(block);
+            }
+            fs.dealloc_inode(new_inode_id);
+            fs.credit(uid, dir_blocks, 1);
+            return Err(e);
+        }
+        // 3. init inode
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, self.block_device.clone())
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(inode_type)
-            });
-        // 3. modify current inode: add one more dirent
-        self.modify_disk_inode(|root_inode| {
-            // append file in dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
-        let inode = Self::new(
-            new_inode_id,
-            new_inode_block_id,
-            new_inode_block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        );
-        if inode_type == DiskInodeType::Directory {
-            let curr_inode_id = self.inode_id;
-            inode.modify_disk_inode(|curr_inode| {
-                curr_inode.initialize_dir(
+        let inode = Self {
+            parent_id: Some(self.inode_id),
+            ..Self::new(
+                new_inode_id,
+                new_inode_block_id,
+                new_inode_block_offset,
+                self.fs.clone(),
+                self.block_device.clone(),
+            )
+        };
+        let curr_inode_id = self.inode_id;
+        inode.modify_disk_inode(|new_inode| {
+            new_inode.initialize(inode_type);
+            (new_inode.uid, new_inode.gid) = (uid, gid);
+            new_inode.atime = now;
+            new_inode.touch(now);
+            match dir_block {
+                Some(block) => new_inode.initialize_dir(
                     new_inode_id,
                     curr_inode_id,
-                    || fs.alloc_data(),
+                    || Ok(block),
                     &self.block_device,
-                );
-            });
-        }
+                ),
+                None => Ok(()),
+            }
+        })?;
         // 4. return inode
-        block_cache_sync_all();
-        Some(Arc::new(inode))
-        // release efs lock
+        Ok(Arc::new(inode))
     }
 
     /// Create regular file under current inode
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        self.create_inode(name, DiskInodeType::File)
+    pub fn create(&self, name: &str) -> Result<Arc<Inode>> {
+        self.create_as(name, (0, 0))
+    }
+
+    /// Create regular file under current inode owned by `owner`, (uid, gid), and charged to
+    /// the quota of its uid
+    pub fn create_as(&self, name: &str, owner: (u16, u16)) -> Result<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File, owner)
     }
 
     /// Create directory under current inode
-    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
-        self.create_inode(name, DiskInodeType::Directory)
+    pub fn create_dir(&self, name: &str) -> Result<Arc<Inode>> {
+        self.create_dir_as(name, (0, 0))
+    }
+
+    /// Create directory under current inode owned by `owner` like `create_as`
+    pub fn create_dir_as(&self, name: &str, owner: (u16, u16)) -> Result<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory, owner)
+    }
+
+    /// Create symlink `name` to `target` under current inode, `target` needn't exist but
+    /// can't be empty
+    pub fn symlink(&self, name: &str, target: &str) -> Result<Arc<Inode>> {
+        if target.is_empty() {
+            return Err(EfsError::Invalid);
+        }
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        let inode = self.create_inode_locked(name, DiskInodeType::SymLink, (0, 0), &mut fs)?;
+        let written = inode.modify_disk_inode(|disk_inode| {
+            inode.increase_size(target.len(), disk_inode, &mut fs)?;
+            disk_inode.write_at(0, target.as_bytes(), &self.block_device);
+            Ok(())
+        });
+        // no link without its target
+        if let Err(e) = written {
+            self.remove_dirent(name, fs.now())?;
+            inode.drop_link_locked(&mut fs);
+            return Err(e);
+        }
+        Ok(inode)
+    }
+
+    /// Target path of symlink, `Invalid` if current inode isn't one
+    pub fn readlink(&self) -> Result<String> {
+        self.read_disk_inode(|disk_inode| {
+            disk_inode
+                .is_symlink()
+                .then(|| self.link_target(disk_inode))
+                .ok_or(EfsError::Invalid)
+        })
+    }
+
+    fn link_target(&self, disk_inode: &DiskInode) -> String {
+        let mut buf = vec![0u8; disk_inode.size as usize];
+        disk_inode.read_at(0, &mut buf, &self.block_device);
+        String::from_utf8_lossy(&buf).into_owned()
     }
 
     fn clear_locked(&self, fs: &mut EasyFileSystem) {
         self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
+            let held = disk_inode.blocks_held(&self.block_device);
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert_eq!(
-                data_blocks_dealloc.len(),
-                DiskInode::total_blocks(size) as usize
-            );
+            assert_eq!(data_blocks_dealloc.len(), held as usize);
+            fs.credit(disk_inode.uid, data_blocks_dealloc.len() as u32, 0);
             for data_block in data_blocks_dealloc {
                 fs.dealloc_data(data_block);
             }
+            disk_inode.touch(fs.now());
         });
-        block_cache_sync_all();
     }
 
     /// Clear the data in current inode
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
         self.clear_locked(&mut fs);
     }
 
+    /// Resize current inode to `new_size`, blocks no longer needed go back to the fs and
+    /// the part grown reads zeros
+    pub fn truncate(&self, new_size: usize) -> Result<()> {
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        self.modify_disk_inode(|disk_inode| {
+            if new_size < disk_inode.size as usize {
+                let data_blocks_dealloc =
+                    disk_inode.decrease_size(new_size as u32, &self.block_device);
+                fs.credit(disk_inode.uid, data_blocks_dealloc.len() as u32, 0);
+                for data_block in data_blocks_dealloc {
+                    fs.dealloc_data(data_block);
+                }
+            } else {
+                self.increase_size(new_size, disk_inode, &mut fs)?;
+            }
+            disk_inode.touch(fs.now());
+            Ok(())
+        })
+    }
+
+    /// Punch a hole from byte `offset` on for `len` of current inode, which must be a regular
+    /// file: it reads zeros, and the blocks all in it go back to the fs until written again.
+    /// The size stays.
+    pub fn punch_hole(&self, offset: usize, len: usize) -> Result<()> {
+        let mut fs = self.fs.lock();
+        let end = offset.saturating_add(len);
+        {
+            let _txn = fs.transaction();
+            self.modify_disk_inode(|disk_inode| {
+                if disk_inode.is_dir() {
+                    return Err(EfsError::IsDir);
+                }
+                if !disk_inode.is_file() {
+                    return Err(EfsError::Invalid);
+                }
+                let data_blocks_dealloc = disk_inode.punch(offset, end, &self.block_device);
+                fs.credit(disk_inode.uid, data_blocks_dealloc.len() as u32, 0);
+                for data_block in data_blocks_dealloc {
+                    fs.dealloc_data(data_block);
+                }
+                disk_inode.touch(fs.now());
+                Ok(())
+            })?;
+        }
+        // zeros written, as by `write_at`
+        if !fs.write_back() {
+            block_cache_sync_all();
+        }
+        Ok(())
+    }
+
     /// Read data from current inode
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        let now = self.fs.lock().now();
+        let (len, atime_stale) = self.read_disk_inode(|disk_inode| {
+            (
+                disk_inode.read_at(offset, buf, &self.block_device),
+                disk_inode.atime_stale(now),
+            )
+        });
+        if atime_stale {
+            self.modify_disk_inode(|disk_inode| disk_inode.atime = now);
+        }
+        len
+    }
+
+    /// Write data to current inode, which must be a regular file. Nothing is written if it
+    /// can't grow to hold all of it.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.write_at_locked(offset, buf, &mut self.fs.lock())
     }
 
-    /// Write data to current inode
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+    /// Write data to the end of current inode like `write_at`, no other write can get in
+    /// between finding the end and writing there
+    pub fn append(&self, buf: &[u8]) -> Result<usize> {
         let mut fs = self.fs.lock();
+        let offset = self.read_disk_inode(|disk_inode| disk_inode.size as usize);
+        self.write_at_locked(offset, buf, &mut fs)
+    }
+
+    fn write_at_locked(&self, offset: usize, buf: &[u8], fs: &mut EasyFileSystem) -> Result<usize> {
+        let end = offset
+            .checked_add(buf.len())
+            .ok_or(EfsError::FileTooLarge)?;
+        // extend first, as a transaction; data itself isn't journaled
+        {
+            let _txn = fs.transaction();
+            self.modify_disk_inode(|disk_inode| {
+                if disk_inode.is_dir() {
+                    return Err(EfsError::IsDir);
+                }
+                if !disk_inode.is_file() {
+                    return Err(EfsError::Invalid);
+                }
+                self.fill_holes(offset, end, disk_inode, fs)?;
+                self.increase_size(end, disk_inode, fs)
+            })?;
+        }
+        let now = fs.now();
         let size = self.modify_disk_inode(|disk_inode| {
-            assert!(disk_inode.is_file());
-            // extend first
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            disk_inode.touch(now);
             disk_inode.write_at(offset, buf, &self.block_device)
         });
-        block_cache_sync_all(); // MUST sync here to avoid data lost
-        size
+        // MUST sync here to avoid data lost, unless asked to by `sync`
+        if !fs.write_back() {
+            block_cache_sync_all();
+        }
+        Ok(size)
+    }
+
+    /// Write back what's cached of current inode
+    pub fn sync(&self) {
+        let _fs = self.fs.lock();
+        let mut keys = vec![block_key(self.block_id, &self.block_device)];
+        self.read_disk_inode(|disk_inode| {
+            for inner_id in 0..disk_inode.data_blocks() {
+                let block_id = disk_inode.get_block_id(inner_id, &self.block_device);
+                if block_id != HOLE {
+                    keys.push(block_key(block_id as usize, &self.block_device));
+                }
+            }
+        });
+        block_cache_sync(&keys);
     }
 
     /// Get inode id
@@ -322,6 +746,32 @@ impl Inode {
         self.inode_id
     }
 
+    /// Tells the filesystem of current inode from others open at the same time
+    pub fn fs_id(&self) -> usize {
+        Arc::as_ptr(&self.fs) as usize
+    }
+
+    /// Write back all cached of the filesystem of current inode and forget it, for the
+    /// filesystem going away. None of its inodes may be used after.
+    pub fn unmount(&self) {
+        let _fs = self.fs.lock();
+        block_cache_drop(&self.block_device);
+        dentry_cache_drop_fs(self.fs_id());
+    }
+
+    /// Quota of `uid` on the filesystem of current inode, `None` if it's given no limits
+    pub fn quota(&self, uid: u16) -> Option<Quota> {
+        self.fs.lock().quota(uid)
+    }
+
+    /// Set the limits of `uid` on the filesystem of current inode, see
+    /// `EasyFileSystem::set_quota`
+    pub fn set_quota(&self, uid: u16, limits: &Quota) -> Result<()> {
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        fs.set_quota(uid, limits)
+    }
+
     /// Get data size of inode
     pub fn get_size(&self) -> usize {
         self.read_disk_inode(|disk_inode| disk_inode.size as usize)
@@ -337,97 +787,273 @@ impl Inode {
         self.read_disk_inode(|disk_inode| disk_inode.is_file())
     }
 
+    /// Is symlink?
+    pub fn is_symlink(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
+    }
+
     /// Get link number
     pub fn nlink(&self) -> u32 {
         self.read_disk_inode(|disk_inode| disk_inode.nlink)
     }
 
-    /// Create hard link `name` from `src`
-    pub fn link(&self, name: &str, src: &Inode) -> Option<Arc<Inode>> {
+    /// (atime, mtime, ctime) in ns since the epoch
+    pub fn times(&self) -> (u64, u64, u64) {
+        self.read_disk_inode(|disk_inode| (disk_inode.atime, disk_inode.mtime, disk_inode.ctime))
+    }
+
+    /// Set atime and mtime, those `None` are left alone; ctime becomes now
+    pub fn set_times(&self, atime: Option<u64>, mtime: Option<u64>) {
+        self.change_attrs(|disk_inode, _| {
+            if let Some(atime) = atime {
+                disk_inode.atime = atime;
+            }
+            if let Some(mtime) = mtime {
+                disk_inode.mtime = mtime;
+            }
+        });
+    }
+
+    /// Permission bits
+    pub fn mode(&self) -> u16 {
+        self.read_disk_inode(|disk_inode| disk_inode.mode)
+    }
+
+    /// (uid, gid) of the owner
+    pub fn owner(&self) -> (u16, u16) {
+        self.read_disk_inode(|disk_inode| (disk_inode.uid, disk_inode.gid))
+    }
+
+    /// Set permission bits to `mode`, those beyond 0o7777 are ignored
+    pub fn chmod(&self, mode: u16) {
+        self.change_attrs(|disk_inode, _| disk_inode.mode = mode & 0o7777);
+    }
+
+    /// Set owner, those `None` are left alone. What the inode takes goes over to the quota
+    /// of the new uid, over its limits or not.
+    pub fn chown(&self, uid: Option<u16>, gid: Option<u16>) {
+        self.change_attrs(|disk_inode, fs| {
+            if let Some(uid) = uid {
+                let blocks = disk_inode.blocks_held(&self.block_device);
+                fs.transfer(disk_inode.uid, uid, blocks, 1);
+                disk_inode.uid = uid;
+            }
+            if let Some(gid) = gid {
+                disk_inode.gid = gid;
+            }
+        });
+    }
+
+    /// Change what's in the disk inode other than data by `f`, ctime becomes now
+    fn change_attrs(&self, f: impl FnOnce(&mut DiskInode, &mut EasyFileSystem)) {
         let mut fs = self.fs.lock();
+        let now = fs.now();
+        self.modify_disk_inode(|disk_inode| {
+            f(disk_inode, &mut fs);
+            disk_inode.ctime = now;
+        });
+        if !fs.write_back() {
+            block_cache_sync_all();
+        }
+    }
+
+    /// Create hard link `name` from `src`, which can't be a directory: a second way into one
+    /// could lead back above itself
+    pub fn link(&self, name: &str, src: &Inode) -> Result<Arc<Inode>> {
+        if src.is_dir() {
+            return Err(EfsError::IsDir);
+        }
+        if src.fs_id() != self.fs_id() {
+            return Err(EfsError::CrossDevice);
+        }
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        let now = fs.now();
 
-        let op = |root_inode: &DiskInode| {
-            assert!(root_inode.is_dir());
-            self.find_inode_id(name, root_inode)
-        };
         // exist already
-        if self.read_disk_inode(op).is_some() {
-            return None;
+        if self.lookup(name)?.is_some() {
+            return Err(EfsError::Exists);
         }
 
         // add dirent under self
-        self.modify_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            self.increase_size(new_size as u32, disk_inode, &mut fs);
-            let dirent = DirEntry::new(name, src.inode_id);
-            disk_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
+        self.append_dirent(name, src.inode_id, now, &mut fs)?;
         // inc src nlink
-        src.modify_disk_inode(|disk_inode| disk_inode.nlink += 1);
-        Some(Arc::new(Self::clone(src)))
+        src.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.ctime = now;
+        });
+        Ok(Arc::new(Self::clone(src)))
     }
 
-    /// Remove hard link (return if removed successfully)
-    pub fn unlink(&self, name: &str) -> bool {
+    /// Remove hard link `name`, directories go with `rmdir`
+    pub fn unlink(&self, name: &str) -> Result<()> {
         let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
         // self is dir && "name" exists
-        let target = self.modify_disk_inode(|disk_inode| {
-            if !disk_inode.is_dir() {
-                return None;
+        let target_id = self.lookup(name)?.ok_or(EfsError::NotFound)?;
+        let target = self.inode_of(target_id, &fs);
+        if target.is_dir() {
+            return Err(EfsError::IsDir);
+        }
+        self.remove_dirent(name, fs.now())?;
+        target.drop_link_locked(&mut fs);
+        Ok(())
+    }
+
+    /// Remove empty directory `name`
+    pub fn rmdir(&self, name: &str) -> Result<()> {
+        if name == "." || name == ".." {
+            return Err(EfsError::Invalid);
+        }
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        let target_id = self.lookup(name)?.ok_or(EfsError::NotFound)?;
+        let target = self.inode_of(target_id, &fs);
+        if !target.is_dir() {
+            return Err(EfsError::NotDir);
+        }
+        // nothing but "." and "..", slots free at the end are cut off
+        if target.get_size() != 2 * DIRENT_SZ {
+            return Err(EfsError::NotEmpty);
+        }
+        self.remove_dirent(name, fs.now())?;
+        target.drop_link_locked(&mut fs);
+        Ok(())
+    }
+
+    /// Drop one link to current inode, it's cleared and freed once no link left
+    fn drop_link_locked(&self, fs: &mut EasyFileSystem) {
+        let now = fs.now();
+        if self.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink = disk_inode.nlink.saturating_sub(1);
+            disk_inode.ctime = now;
+            disk_inode.nlink
+        }) == 0
+        {
+            self.clear_locked(fs);
+            fs.dealloc_inode(self.inode_id);
+            fs.credit(self.owner().0, 0, 1);
+            dentry_cache_forget_dir(self.fs_id(), self.inode_id);
+            block_cache_sync_all();
+        }
+    }
+
+    /// Move `old_name` under current dir to `new_name` under `new_dir` (may be the same dir).
+    /// An existing file `new_name` is replaced; an existing directory is not, and neither can
+    /// a directory be moved under itself.
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> Result<()> {
+        if [old_name, new_name].iter().any(|&n| n == "." || n == "..") {
+            return Err(EfsError::Invalid);
+        }
+        if !self.is_dir() || !new_dir.is_dir() {
+            return Err(EfsError::NotDir);
+        }
+        if new_dir.fs_id() != self.fs_id() {
+            return Err(EfsError::CrossDevice);
+        }
+        let mut fs = self.fs.lock();
+        let _txn = fs.transaction();
+        let now = fs.now();
+        let src_id = self.lookup(old_name)?.ok_or(EfsError::NotFound)?;
+        let src = self.inode_of(src_id, &fs);
+        let src_is_dir = src.is_dir();
+        let same_dir = self.inode_id == new_dir.inode_id;
+        // walk up from `new_dir`, hitting `src` means it'd be moved under itself
+        if src_is_dir && !same_dir {
+            let mut dir_id = new_dir.inode_id;
+            while dir_id != 0 {
+                if dir_id == src_id {
+                    return Err(EfsError::Invalid);
+                }
+                let dir = self.inode_of(dir_id, &fs);
+                dir_id = dir.lookup("..")?.ok_or(EfsError::Corrupt)?;
             }
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut dirent = DirEntry::new_empty();
-            let mut swap = DirEntry::new_empty();
-            match (0..file_count).position(|i| {
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device),
-                    DIRENT_SZ
-                );
-                dirent.name() == name
-            }) {
-                Some(i) => {
-                    // target
-                    let target_inode_id = dirent.inode_number();
-                    let (target_block_id, target_block_offset) =
-                        fs.get_disk_inode_pos(target_inode_id);
-                    let target = Self::new(
-                        target_inode_id,
-                        target_block_id,
-                        target_block_offset,
-                        self.fs.clone(),
-                        self.block_device.clone(),
-                    );
-                    // we don't actually delete i-th, but swap last to i-th, and decrease disk_inode.size only
-                    // in real world we should decrease data of this dir's actual space (at proper time?)
-                    disk_inode.read_at(
-                        (file_count - 1) * DIRENT_SZ,
-                        swap.as_bytes_mut(),
-                        &self.block_device,
-                    );
-                    disk_inode.write_at(i * DIRENT_SZ, swap.as_bytes(), &self.block_device);
-                    disk_inode.size -= DIRENT_SZ as u32;
-                    Some(target)
+        }
+
+        match new_dir.lookup(new_name)? {
+            // already there, under this name or another hard link
+            Some(dst_id) if dst_id == src_id => return Ok(()),
+            Some(dst_id) => {
+                let dst = self.inode_of(dst_id, &fs);
+                if dst.is_dir() {
+                    return Err(EfsError::IsDir);
+                }
+                if src_is_dir {
+                    return Err(EfsError::NotDir);
                 }
-                _ => None, // no such file
+                // point `new_name` at src, then drop `old_name`
+                new_dir.modify_disk_inode(|disk_inode| {
+                    let i = new_dir
+                        .find_dirent_index(new_name, disk_inode)
+                        .ok_or(EfsError::Corrupt)?;
+                    let dirent = DirEntry::new(new_name, src_id)?;
+                    disk_inode.write_at(i * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+                    disk_inode.touch(now);
+                    Ok(())
+                })?;
+                dentry_cache_insert(self.fs_id(), new_dir.inode_id, new_name, src_id);
+                self.remove_dirent(old_name, now)?;
+                dst.drop_link_locked(&mut fs);
             }
-        });
-        // clear target's data if link decrease to 0
-        if let Some(target) = target {
-            if target.modify_disk_inode(|disk_inode| {
-                disk_inode.nlink -= 1;
-                disk_inode.nlink
-            }) == 0
-            {
-                target.clear_locked(&mut fs);
+            // rewrite the name in place
+            None if same_dir => {
+                self.modify_disk_inode(|disk_inode| {
+                    let i = self
+                        .find_dirent_index(old_name, disk_inode)
+                        .ok_or(EfsError::Corrupt)?;
+                    let dirent = DirEntry::new(new_name, src_id)?;
+                    disk_inode.write_at(i * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+                    disk_inode.touch(now);
+                    Ok(())
+                })?;
+                dentry_cache_remove(self.fs_id(), self.inode_id, old_name);
+                dentry_cache_insert(self.fs_id(), self.inode_id, new_name, src_id);
+            }
+            None => {
+                new_dir.append_dirent(new_name, src_id, now, &mut fs)?;
+                self.remove_dirent(old_name, now)?;
+                if src_is_dir {
+                    let parent_id = new_dir.inode_id;
+                    src.modify_disk_inode(|disk_inode| {
+                        let i = src
+                            .find_dirent_index("..", disk_inode)
+                            .ok_or(EfsError::Corrupt)?;
+                        let dirent = DirEntry::new("..", parent_id)?;
+                        disk_inode.write_at(i * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+                        Ok(())
+                    })?;
+                    dentry_cache_insert(self.fs_id(), src_id, "..", parent_id);
+                }
             }
-            true
-        } else {
-            false
         }
+        src.modify_disk_inode(|disk_inode| disk_inode.ctime = now);
+        block_cache_sync_all();
+        Ok(())
+    }
+
+    /// Drop direntry `name`, at `now`. Its slot is left free for the others to stay where they
+    /// are, those free at the end are cut off.
+    fn remove_dirent(&self, name: &str, now: u64) -> Result<()> {
+        self.modify_disk_inode(|disk_inode| {
+            let i = self
+                .find_dirent_index(name, disk_inode)
+                .ok_or(EfsError::Corrupt)?;
+            let empty = DirEntry::new_empty();
+            disk_inode.write_at(i * DIRENT_SZ, empty.as_bytes(), &self.block_device);
+            // only the size goes down, the blocks stay with the dir
+            let mut last = DirEntry::new_empty();
+            while disk_inode.size as usize >= DIRENT_SZ {
+                let offset = disk_inode.size as usize - DIRENT_SZ;
+                disk_inode.read_at(offset, last.as_bytes_mut(), &self.block_device);
+                if !last.is_empty() {
+                    break;
+                }
+                disk_inode.size -= DIRENT_SZ as u32;
+            }
+            disk_inode.touch(now);
+            Ok(())
+        })?;
+        dentry_cache_remove(self.fs_id(), self.inode_id, name);
+        Ok(())
     }
 }
@@ -0,0 +1,64 @@
+//! Constants used in rCore for K210 (Sipeed Maix series)
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use easy_fs::BlockDevice;
+
+use super::Board;
+
+pub const K210_PLIC: usize = 0x0C00_0000;
+pub const K210_UARTHS: usize = 0x3800_0000;
+
+pub type BlockDeviceImpl = crate::drivers::block::SDCardWrapper;
+pub type CharDeviceImpl = crate::drivers::chardev::UartHs<K210_UARTHS>;
+
+use crate::drivers::{irq, CharDevice, UART};
+
+// irq nums
+const IRQ_UARTHS: usize = 33;
+
+pub struct K210Board;
+
+impl Board for K210Board {
+    const CLOCK_FREQ: usize = 403000000 / 62;
+    /// 6MiB general purpose SRAM, the top 2MiB is the AI SRAM which we leave alone
+    const MEMORY_END: usize = 0x8060_0000;
+    const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+
+    const MMIO: &'static [(usize, usize)] = &[
+        (K210_PLIC, 0x400000), // PLIC
+        (K210_UARTHS, 0x1000), // UARTHS
+        (0x3800_1000, 0x1000), // GPIOHS
+        (0x5020_0000, 0x1000), // GPIO
+        (0x502B_0000, 0x1000), // FPIOA
+        (0x5044_0000, 0x1000), // SYSCTL
+        (0x5200_0000, 0x1000), // SPI0
+    ];
+    const HAS_NET: bool = false;
+
+    fn device_init() {
+        use riscv::register::sie;
+        irq::init(K210_PLIC, 0);
+        irq::register_irq(IRQ_UARTHS, 1, || UART.handle_irq());
+        // S-mode external interrupts are only seen if the SBI forwards them (K210
+        // implements priv spec 1.9.1), drivers don't depend on it and poll instead
+        unsafe {
+            sie::set_sext();
+        }
+    }
+
+    fn irq_handler() {
+        irq::handle_irq();
+    }
+
+    fn consoles() -> Vec<Arc<dyn CharDevice + Send + Sync>> {
+        vec![UART.clone() as Arc<dyn CharDevice + Send + Sync>]
+    }
+
+    fn rtc_ns() -> Option<u64> {
+        None
+    }
+
+    fn disks() -> Vec<Arc<dyn BlockDevice>> {
+        Vec::new()
+    }
+}
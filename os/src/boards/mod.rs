@@ -0,0 +1,73 @@
+//! Board support, one board is selected by cargo feature (`board_qemu` by default).
+//!
+//! A board describes its memory map, MMIO windows, device init and IRQ wiring
+//! through [`Board`], and names the block/char device drivers it uses via the
+//! `BlockDeviceImpl`/`CharDeviceImpl` aliases.
+
+#[cfg(all(feature = "board_qemu", feature = "board_k210"))]
+compile_error!("select exactly one board feature");
+
+#[cfg(feature = "board_k210")]
+mod k210;
+#[cfg(feature = "board_qemu")]
+mod qemu;
+
+#[cfg(feature = "board_k210")]
+pub use k210::{BlockDeviceImpl, CharDeviceImpl, K210Board as CurrentBoard};
+#[cfg(feature = "board_qemu")]
+pub use qemu::{BlockDeviceImpl, CharDeviceImpl, QemuBoard as CurrentBoard};
+
+use alloc::{sync::Arc, vec::Vec};
+use easy_fs::BlockDevice;
+
+use crate::drivers::CharDevice;
+
+pub trait Board {
+    /// timebase frequency (`mtime` ticks per second)
+    const CLOCK_FREQ: usize;
+    /// end of physical memory available to the kernel
+    const MEMORY_END: usize;
+    /// size of the static kernel heap
+    const KERNEL_HEAP_SIZE: usize;
+    /// (base, len) device windows identity mapped into kernel space
+    const MMIO: &'static [(usize, usize)];
+    /// there's a network device for `net::init` to bring up
+    const HAS_NET: bool;
+
+    /// Register IRQs and bring up devices, called after trap init.
+    fn device_init();
+    /// Claim and dispatch one external interrupt.
+    fn irq_handler();
+    /// Terminals init runs a shell on, the first one is the kernel console `UART`.
+    fn consoles() -> Vec<Arc<dyn CharDevice + Send + Sync>>;
+    /// Wall clock in ns since the epoch, `None` if the board has no RTC.
+    fn rtc_ns() -> Option<u64>;
+    /// Disks besides the one root is on, which may be mounted: /dev/vdb, /dev/vdc and on.
+    fn disks() -> Vec<Arc<dyn BlockDevice>>;
+}
+
+pub const CLOCK_FREQ: usize = CurrentBoard::CLOCK_FREQ;
+pub const MEMORY_END: usize = CurrentBoard::MEMORY_END;
+pub const KERNEL_HEAP_SIZE: usize = CurrentBoard::KERNEL_HEAP_SIZE;
+pub const MMIO: &[(usize, usize)] = CurrentBoard::MMIO;
+pub const HAS_NET: bool = CurrentBoard::HAS_NET;
+
+pub fn device_init() {
+    CurrentBoard::device_init();
+}
+
+pub fn irq_handler() {
+    CurrentBoard::irq_handler();
+}
+
+pub fn consoles() -> Vec<Arc<dyn CharDevice + Send + Sync>> {
+    CurrentBoard::consoles()
+}
+
+pub fn rtc_ns() -> Option<u64> {
+    CurrentBoard::rtc_ns()
+}
+
+pub fn disks() -> Vec<Arc<dyn BlockDevice>> {
+    CurrentBoard::disks()
+}
@@ -1,52 +1,103 @@
 //! Constants used in rCore for qemu
 
-pub const CLOCK_FREQ: usize = 12500000;
-pub const MEMORY_END: usize = 0x8800_0000;
+use alloc::{sync::Arc, vec, vec::Vec};
+use easy_fs::BlockDevice;
+use lazy_static::lazy_static;
 
-pub const MMIO: &[(usize, usize)] = &[
-    (0x0010_0000, 0x2000), // VIRT_TEST/RTC  in virt machine
-    (0x0200_0000, 0x10000),
-    (VIRT_PLIC, 0x210000), // VIRT_PLIC in virt machine
-    (VIRT_UART, 0x9000),   // VIRT_UART0 with GPU  in virt machine
-];
+use super::Board;
 
 pub const VIRT_PLIC: usize = 0x0C00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
+/// goldfish RTC, reading TIME_LOW latches TIME_HIGH
+pub const VIRT_RTC: usize = 0x0010_1000;
+
+/// virtio-mmio windows, 0x1000 apart from the one of irq 0
+const VIRTIO_MMIO: usize = 0x1000_0000;
+/// irq of `BLOCK_DEVICE`, the window qemu gives the first virtio device
+const IRQ_BLOCK: usize = 8;
 
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
 pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
 
-use crate::drivers::{
-    plic::{IntrTargetPriority, PLIC},
-    CharDevice, BLOCK_DEVICE, KEYBOARD_DEVICE, UART,
-};
-
-pub fn device_init() {
-    use riscv::register::sie;
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
-    let supervisor = IntrTargetPriority::Supervisor;
-    let machine = IntrTargetPriority::Machine;
-    plic.set_threshold(hart_id, supervisor, 0);
-    plic.set_threshold(hart_id, machine, 1);
-    // irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5, 8, 10] {
-        plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
+use crate::drivers::{irq, CharDevice, BLOCK_DEVICE, KEYBOARD_DEVICE, UART, VIRTIO_CONSOLE};
+
+lazy_static! {
+    /// virtio-blk devices besides `BLOCK_DEVICE` by irq, in the order qemu was given them: it
+    /// fills the windows from the top down
+    static ref DISKS: Vec<(usize, Arc<BlockDeviceImpl>)> = (1..IRQ_BLOCK)
+        .rev()
+        .filter_map(|irq| Some((irq, Arc::new(BlockDeviceImpl::at(VIRTIO_MMIO + irq * 0x1000)?))))
+        .collect();
+}
+
+pub struct QemuBoard;
+
+impl Board for QemuBoard {
+    const CLOCK_FREQ: usize = 12500000;
+    const MEMORY_END: usize = 0x8800_0000;
+    const KERNEL_HEAP_SIZE: usize = 0x100_0000;
+
+    const MMIO: &'static [(usize, usize)] = &[
+        (0x0010_0000, 0x2000), // VIRT_TEST/RTC  in virt machine
+        (0x0200_0000, 0x10000),
+        (VIRT_PLIC, 0x210000), // VIRT_PLIC in virt machine
+        (VIRT_UART, 0x9000),   // VIRT_UART0 with GPU  in virt machine
+    ];
+    const HAS_NET: bool = true;
+
+    fn device_init() {
+        use riscv::register::sie;
+        println!("KERN: init keyboard");
+        let _keyboard = KEYBOARD_DEVICE.clone();
+
+        irq::init(VIRT_PLIC, 0);
+        // irq nums: 3 virtio console, 5 keyboard, 6 mouse, 8 block, 10 uart
+        irq::register_irq(5, 1, || KEYBOARD_DEVICE.handle_irq());
+        irq::register_irq(IRQ_BLOCK, 1, || BLOCK_DEVICE.handle_irq());
+        for (irq, _) in DISKS.iter() {
+            println!("KERN: init disk at irq {}", irq);
+            // a disk with nothing done just has nothing to take
+            irq::register_irq(*irq, 1, || {
+                DISKS.iter().for_each(|(_, disk)| disk.handle_irq())
+            });
+        }
+        irq::register_irq(10, 1, || UART.handle_irq());
+        if VIRTIO_CONSOLE.is_some() {
+            println!("KERN: init virtio console");
+            irq::register_irq(3, 1, || {
+                VIRTIO_CONSOLE.as_ref().unwrap().handle_irq();
+            });
+        }
+        unsafe {
+            sie::set_sext();
+        }
     }
-    unsafe {
-        sie::set_sext();
+
+    fn irq_handler() {
+        irq::handle_irq();
+    }
+
+    fn consoles() -> Vec<Arc<dyn CharDevice + Send + Sync>> {
+        let mut consoles = vec![UART.clone() as Arc<dyn CharDevice + Send + Sync>];
+        if let Some(console) = VIRTIO_CONSOLE.as_ref() {
+            consoles.push(console.clone());
+        }
+        consoles
+    }
+
+    fn rtc_ns() -> Option<u64> {
+        let (low, high) = unsafe {
+            let low = (VIRT_RTC as *const u32).read_volatile();
+            let high = ((VIRT_RTC + 4) as *const u32).read_volatile();
+            (low, high)
+        };
+        Some((high as u64) << 32 | low as u64)
     }
-}
 
-pub fn irq_handler() {
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
-    match intr_src_id {
-        5 => KEYBOARD_DEVICE.handle_irq(),
-        8 => BLOCK_DEVICE.handle_irq(),
-        10 => UART.handle_irq(),
-        _ => panic!("unsupported IRQ {}", intr_src_id),
+    fn disks() -> Vec<Arc<dyn BlockDevice>> {
+        DISKS
+            .iter()
+            .map(|(_, disk)| disk.clone() as Arc<dyn BlockDevice>)
+            .collect()
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
 }
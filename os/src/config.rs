@@ -1,14 +1,33 @@
 //! Constants used in rCore
 
-pub use crate::board::{CLOCK_FREQ, MEMORY_END, MMIO};
+pub use crate::board::{CLOCK_FREQ, KERNEL_HEAP_SIZE, MEMORY_END, MMIO};
 
 // Memory
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
-pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 12;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// frames kept free for the kernel's own use, user pages are swapped out to keep them so
+pub const FRAMES_RESERVED: usize = 64;
 
+/// fds a process may have open at most, RLIMIT_NOFILE can't go past it
+pub const FD_MAX: usize = 1024;
+
+/// blocks the filesystem caches at most
+pub const BLOCK_CACHE_BLOCKS: usize = 64;
+
+/// longest path taken from user, nul excluded
+pub const PATH_MAX: usize = 4096;
+/// most arguments exec takes
+pub const ARGC_MAX: usize = 64;
+
+/// program break starts here, the heap grows up to the mmap area
+pub const BRK_AREA_BASE: usize = 0x0000_0000_8000_0000;
 pub const MMAP_AREA_BASE: usize = 0x0000_0001_0000_0000; // base addr in user_space that nobody use
+pub const MMAP_AREA_END: usize = 0x0000_0020_0000_0000; // 124GiB for mmap, far below trap contexts
+
+// mmap area within the lower half of Sv39, trap contexts & trampoline live in the upper one
+const _: () = assert!(MMAP_AREA_BASE < MMAP_AREA_END && MMAP_AREA_END <= 1 << 38);
+const _: () = assert!(BRK_AREA_BASE % PAGE_SIZE == 0 && BRK_AREA_BASE < MMAP_AREA_BASE);
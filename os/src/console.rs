@@ -1,5 +1,37 @@
+//! Kernel console on the board UART driver.
+//!
+//! The driver lives on the heap, so until `mm::init` has set the heap up (and
+//! calls `console::init`) output is kept in a static buffer and replayed once
+//! the UART is ready. Only a panic before that point falls back to SBI.
+
 use crate::drivers::{CharDevice, UART};
+use crate::sync::UPSafeCellRaw;
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+
+const EARLY_BUF_SIZE: usize = 4096;
+
+static UART_READY: AtomicBool = AtomicBool::new(false);
+/// set on a panic before the UART is ready
+static SBI_FALLBACK: AtomicBool = AtomicBool::new(false);
+
+struct EarlyBuffer {
+    buf: [u8; EARLY_BUF_SIZE],
+    len: usize,
+    /// bytes not fitting in `buf`
+    dropped: usize,
+}
+
+lazy_static! {
+    static ref EARLY_BUFFER: UPSafeCellRaw<EarlyBuffer> = unsafe {
+        UPSafeCellRaw::new(EarlyBuffer {
+            buf: [0; EARLY_BUF_SIZE],
+            len: 0,
+            dropped: 0,
+        })
+    };
+}
 
 struct Stdout;
 impl Write for Stdout {
@@ -11,8 +43,69 @@ impl Write for Stdout {
     }
 }
 
+struct EarlyStdout;
+impl Write for EarlyStdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let early = EARLY_BUFFER.get_mut();
+        let n = s.len().min(EARLY_BUF_SIZE - early.len);
+        early.buf[early.len..early.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        early.len += n;
+        early.dropped += s.len() - n;
+        Ok(())
+    }
+}
+
+struct SbiStdout;
+impl Write for SbiStdout {
+    #[allow(deprecated)]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            sbi_rt::legacy::console_putchar(b as usize);
+        }
+        Ok(())
+    }
+}
+
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    if UART_READY.load(Ordering::Acquire) {
+        Stdout.write_fmt(args).unwrap();
+    } else if SBI_FALLBACK.load(Ordering::Acquire) {
+        SbiStdout.write_fmt(args).unwrap();
+    } else {
+        EarlyStdout.write_fmt(args).unwrap();
+    }
+}
+
+/// Switch output to the UART driver and replay what was buffered so far.
+/// Needs the heap, the UART window is reachable with or without paging.
+pub fn init() {
+    UART.init();
+    let early = EARLY_BUFFER.get_mut();
+    for &b in &early.buf[..early.len] {
+        UART.write(b);
+    }
+    let dropped = early.dropped;
+    early.len = 0;
+    UART_READY.store(true, Ordering::Release);
+    if dropped > 0 {
+        println!("[console] {} bytes of early output lost", dropped);
+    }
+}
+
+/// Panic before `init`: push buffered output out through the SBI console, if
+/// the firmware has one, and send further output there too.
+pub fn emergency_flush() {
+    if UART_READY.load(Ordering::Acquire) || SBI_FALLBACK.load(Ordering::Acquire) {
+        return;
+    }
+    let early = EARLY_BUFFER.get_mut();
+    // buffered bytes came from `&str`s, but may be cut inside a char on overflow
+    for &b in &early.buf[..early.len] {
+        #[allow(deprecated)]
+        sbi_rt::legacy::console_putchar(b as usize);
+    }
+    early.len = 0;
+    SBI_FALLBACK.store(true, Ordering::Release);
 }
 
 // `tt` captures "," also
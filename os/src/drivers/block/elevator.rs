@@ -0,0 +1,37 @@
+//! I/O scheduling of a batch of block requests: they're sorted in one sweep of the disk
+//! (C-SCAN), from where the last request left the head up, then from the start again, and
+//! adjacent blocks merged into single requests.
+
+use alloc::{vec, vec::Vec};
+
+/// Blocks from `block_id` on, sent as a single request
+pub struct Run<'a> {
+    pub block_id: usize,
+    pub bufs: Vec<&'a [u8]>,
+}
+
+impl Run<'_> {
+    /// Block after the last one of the run
+    pub fn end(&self) -> usize {
+        self.block_id + self.bufs.len()
+    }
+}
+
+/// `(block_id, buf)` of `blocks` in the order to send them with the head at `head`, runs of at
+/// most `max_merge` blocks
+pub fn schedule<'a>(head: usize, blocks: &[(usize, &'a [u8])], max_merge: usize) -> Vec<Run<'a>> {
+    let mut sorted = blocks.to_vec();
+    // stable, the same block twice is written in the order given
+    sorted.sort_by_key(|&(block_id, _)| (block_id < head, block_id));
+    let mut runs: Vec<Run> = Vec::new();
+    for (block_id, buf) in sorted {
+        match runs.last_mut() {
+            Some(run) if run.end() == block_id && run.bufs.len() < max_merge => run.bufs.push(buf),
+            _ => runs.push(Run {
+                block_id,
+                bufs: vec![buf],
+            }),
+        }
+    }
+    runs
+}
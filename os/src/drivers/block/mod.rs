@@ -4,7 +4,12 @@ use lazy_static::lazy_static;
 
 use crate::board::BlockDeviceImpl;
 
+mod elevator;
+#[cfg(feature = "board_k210")]
+mod sdcard;
 mod virtio_blk;
+#[cfg(feature = "board_k210")]
+pub use sdcard::SDCardWrapper;
 pub use virtio_blk::VirtIOBlock;
 
 lazy_static! {
@@ -0,0 +1,379 @@
+//! SD card in SPI mode on the K210 SPI0 controller (Maix boards wiring:
+//! SCLK io27, MOSI io28, MISO io26, CS io29 driven as GPIOHS7).
+//!
+//! Polling only, one 512-byte block per command.
+//! Ref: SD Physical Layer Simplified Specification v6.00, chapter 7 "SPI Mode"
+//! Ref: Kendryte K210 datasheet / standalone SDK (fpioa, sysctl, spi)
+
+use super::BlockDevice;
+use crate::sync::UPIntrFreeCell;
+use volatile::Volatile;
+
+const SYSCTL_BASE: usize = 0x5044_0000;
+const FPIOA_BASE: usize = 0x502B_0000;
+const GPIOHS_BASE: usize = 0x3800_1000;
+const SPI0_BASE: usize = 0x5200_0000;
+
+// sysctl clock enables
+const SYSCTL_CLK_EN_CENT: usize = 0x28;
+const SYSCTL_CLK_EN_PERI: usize = 0x2c;
+const CLK_EN_CENT_APB0: u32 = 1 << 3;
+const CLK_EN_PERI_GPIO: u32 = 1 << 5;
+const CLK_EN_PERI_SPI0: u32 = 1 << 6;
+
+// fpioa pins & functions
+const IO_SCLK: usize = 27;
+const IO_MOSI: usize = 28;
+const IO_MISO: usize = 26;
+const IO_CS: usize = 29;
+const FUNC_SPI0_D0: u32 = 4;
+const FUNC_SPI0_D1: u32 = 5;
+const FUNC_SPI0_SCLK: u32 = 17;
+const FUNC_GPIOHS0: u32 = 24;
+const CS_GPIOHS: u32 = 7;
+// fpioa io config bits
+const IO_DS_MAX: u32 = 0xf << 8;
+const IO_OE_EN: u32 = 1 << 12;
+const IO_IE_EN: u32 = 1 << 20;
+const IO_ST: u32 = 1 << 23;
+
+// gpiohs registers
+const GPIOHS_OUTPUT_EN: usize = 0x08;
+const GPIOHS_OUTPUT_VAL: usize = 0x0c;
+
+// spi0 ctrlr0 layout on K210 differs from plain DW_apb_ssi
+const SPI0_DFS_OFFSET: u32 = 16;
+const SPI0_TMOD_OFFSET: u32 = 8;
+const SPI_TMOD_TRANS_RECV: u32 = 0;
+const SPI_SR_BUSY: u32 = 1;
+/// spi0 runs at ~390MHz / baudr
+const SPI_BAUDR_INIT: u32 = 1000; // < 400kHz during init
+const SPI_BAUDR_FAST: u32 = 40; // ~10MHz afterwards
+
+const BLOCK_SIZE: usize = 512;
+const DATA_TOKEN: u8 = 0xfe;
+const R1_IDLE: u8 = 0x01;
+const OCR_CCS: u32 = 1 << 30;
+const INIT_RETRIES: usize = 0x1000;
+
+unsafe fn reg(addr: usize) -> &'static mut Volatile<u32> {
+    &mut *(addr as *mut Volatile<u32>)
+}
+
+fn fpioa_set_function(io: usize, func: u32, flags: u32) {
+    unsafe { reg(FPIOA_BASE + io * 4).write(func | flags) }
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Spi0Regs {
+    ctrlr0: Volatile<u32>,
+    ctrlr1: Volatile<u32>,
+    ssienr: Volatile<u32>,
+    mwcr: Volatile<u32>,
+    ser: Volatile<u32>,
+    baudr: Volatile<u32>,
+    txftlr: Volatile<u32>,
+    rxftlr: Volatile<u32>,
+    txflr: Volatile<u32>,
+    rxflr: Volatile<u32>,
+    sr: Volatile<u32>,
+    imr: Volatile<u32>,
+    isr: Volatile<u32>,
+    risr: Volatile<u32>,
+    txoicr: Volatile<u32>,
+    rxoicr: Volatile<u32>,
+    rxuicr: Volatile<u32>,
+    msticr: Volatile<u32>,
+    icr: Volatile<u32>,
+    dmacr: Volatile<u32>,
+    dmatdlr: Volatile<u32>,
+    dmardlr: Volatile<u32>,
+    idr: Volatile<u32>,
+    ssic_version_id: Volatile<u32>,
+    dr: [Volatile<u32>; 36],
+    rx_sample_delay: Volatile<u32>,
+    spi_ctrlr0: Volatile<u32>,
+}
+
+struct Spi0;
+
+impl Spi0 {
+    fn regs(&mut self) -> &mut Spi0Regs {
+        unsafe { &mut *(SPI0_BASE as *mut Spi0Regs) }
+    }
+
+    fn init(&mut self) {
+        unsafe {
+            let cent = reg(SYSCTL_BASE + SYSCTL_CLK_EN_CENT);
+            cent.write(cent.read() | CLK_EN_CENT_APB0);
+            let peri = reg(SYSCTL_BASE + SYSCTL_CLK_EN_PERI);
+            peri.write(peri.read() | CLK_EN_PERI_SPI0 | CLK_EN_PERI_GPIO);
+        }
+        fpioa_set_function(IO_SCLK, FUNC_SPI0_SCLK, IO_DS_MAX | IO_OE_EN);
+        fpioa_set_function(
+            IO_MOSI,
+            FUNC_SPI0_D0,
+            IO_DS_MAX | IO_OE_EN | IO_IE_EN | IO_ST,
+        );
+        fpioa_set_function(
+            IO_MISO,
+            FUNC_SPI0_D1,
+            IO_DS_MAX | IO_OE_EN | IO_IE_EN | IO_ST,
+        );
+        fpioa_set_function(
+            IO_CS,
+            FUNC_GPIOHS0 + CS_GPIOHS,
+            IO_DS_MAX | IO_OE_EN | IO_IE_EN | IO_ST,
+        );
+        unsafe {
+            let oe = reg(GPIOHS_BASE + GPIOHS_OUTPUT_EN);
+            oe.write(oe.read() | 1 << CS_GPIOHS);
+        }
+        self.cs(false);
+
+        let regs = self.regs();
+        regs.ssienr.write(0);
+        regs.imr.write(0);
+        regs.dmacr.write(0);
+        // mode 0, standard frame format, 8 bit frames, full duplex
+        regs.ctrlr0
+            .write((8 - 1) << SPI0_DFS_OFFSET | SPI_TMOD_TRANS_RECV << SPI0_TMOD_OFFSET);
+        regs.spi_ctrlr0.write(0);
+        regs.baudr.write(SPI_BAUDR_INIT);
+        // hardware SS is not wired, CS goes through gpiohs, but a slave must be selected to clock
+        regs.ser.write(1 << 3);
+        regs.ssienr.write(1);
+    }
+
+    fn set_baudr(&mut self, baudr: u32) {
+        let regs = self.regs();
+        regs.ssienr.write(0);
+        regs.baudr.write(baudr);
+        regs.ssienr.write(1);
+    }
+
+    /// active low
+    fn cs(&mut self, select: bool) {
+        unsafe {
+            let val = reg(GPIOHS_BASE + GPIOHS_OUTPUT_VAL);
+            if select {
+                val.write(val.read() & !(1 << CS_GPIOHS));
+            } else {
+                val.write(val.read() | 1 << CS_GPIOHS);
+            }
+        }
+    }
+
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let regs = self.regs();
+        regs.dr[0].write(byte as u32);
+        while regs.rxflr.read() == 0 {}
+        let r = regs.dr[0].read() as u8;
+        while regs.sr.read() & SPI_SR_BUSY != 0 {}
+        r
+    }
+}
+
+#[derive(Debug)]
+enum SDError {
+    NoResponse,
+    BadResponse(u8),
+    Unsupported,
+    Timeout,
+    WriteRejected(u8),
+}
+
+struct SDCard {
+    spi: Spi0,
+    /// SDHC/SDXC use block addresses, SDSC byte addresses
+    high_capacity: bool,
+}
+
+impl SDCard {
+    fn new() -> Result<Self, SDError> {
+        let mut sd = Self {
+            spi: Spi0,
+            high_capacity: false,
+        };
+        sd.spi.init();
+        sd.init()?;
+        sd.spi.set_baudr(SPI_BAUDR_FAST);
+        Ok(sd)
+    }
+
+    fn init(&mut self) -> Result<(), SDError> {
+        // >= 74 clocks with CS high to enter native mode, then CMD0 with CS low -> SPI mode
+        for _ in 0..10 {
+            self.spi.transfer(0xff);
+        }
+        let r1 = self.cmd(0, 0, 0x95)?;
+        self.end_cmd();
+        if r1 != R1_IDLE {
+            return Err(SDError::BadResponse(r1));
+        }
+
+        // v2 cards echo check pattern, v1 cards (SDSC <= 2GB) are not supported
+        let r1 = self.cmd(8, 0x1aa, 0x87)?;
+        let r7 = self.read_u32();
+        self.end_cmd();
+        if r1 != R1_IDLE || r7 & 0xfff != 0x1aa {
+            return Err(SDError::Unsupported);
+        }
+
+        // ACMD41 with HCS until the card leaves idle state
+        let mut ready = false;
+        for _ in 0..INIT_RETRIES {
+            self.cmd(55, 0, 0x01)?;
+            self.end_cmd();
+            let r1 = self.cmd(41, 1 << 30, 0x01)?;
+            self.end_cmd();
+            if r1 == 0 {
+                ready = true;
+                break;
+            }
+        }
+        if !ready {
+            return Err(SDError::Timeout);
+        }
+
+        let r1 = self.cmd(58, 0, 0x01)?;
+        let ocr = self.read_u32();
+        self.end_cmd();
+        if r1 != 0 {
+            return Err(SDError::BadResponse(r1));
+        }
+        self.high_capacity = ocr & OCR_CCS != 0;
+        if !self.high_capacity {
+            let r1 = self.cmd(16, BLOCK_SIZE as u32, 0x01)?;
+            self.end_cmd();
+            if r1 != 0 {
+                return Err(SDError::BadResponse(r1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Select card, send command and return R1, CS stays low until `end_cmd`.
+    fn cmd(&mut self, cmd: u8, arg: u32, crc: u8) -> Result<u8, SDError> {
+        self.spi.cs(true);
+        self.spi.transfer(0xff);
+        self.spi.transfer(0x40 | cmd);
+        for b in arg.to_be_bytes() {
+            self.spi.transfer(b);
+        }
+        self.spi.transfer(crc);
+        // R1 within 8 bytes, msb clear
+        for _ in 0..8 {
+            let r1 = self.spi.transfer(0xff);
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+        self.end_cmd();
+        Err(SDError::NoResponse)
+    }
+
+    fn end_cmd(&mut self) {
+        self.spi.cs(false);
+        self.spi.transfer(0xff);
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        let mut v = 0;
+        for _ in 0..4 {
+            v = v << 8 | self.spi.transfer(0xff) as u32;
+        }
+        v
+    }
+
+    fn address(&self, block_id: usize) -> u32 {
+        if self.high_capacity {
+            block_id as u32
+        } else {
+            (block_id * BLOCK_SIZE) as u32
+        }
+    }
+
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> Result<(), SDError> {
+        assert_eq!(buf.len(), BLOCK_SIZE);
+        let r1 = self.cmd(17, self.address(block_id), 0x01)?;
+        if r1 != 0 {
+            self.end_cmd();
+            return Err(SDError::BadResponse(r1));
+        }
+        let mut token = 0xff;
+        for _ in 0..INIT_RETRIES {
+            token = self.spi.transfer(0xff);
+            if token != 0xff {
+                break;
+            }
+        }
+        if token != DATA_TOKEN {
+            self.end_cmd();
+            return Err(SDError::BadResponse(token));
+        }
+        for b in buf.iter_mut() {
+            *b = self.spi.transfer(0xff);
+        }
+        // crc ignored
+        self.spi.transfer(0xff);
+        self.spi.transfer(0xff);
+        self.end_cmd();
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> Result<(), SDError> {
+        assert_eq!(buf.len(), BLOCK_SIZE);
+        let r1 = self.cmd(24, self.address(block_id), 0x01)?;
+        if r1 != 0 {
+            self.end_cmd();
+            return Err(SDError::BadResponse(r1));
+        }
+        self.spi.transfer(0xff);
+        self.spi.transfer(DATA_TOKEN);
+        for &b in buf {
+            self.spi.transfer(b);
+        }
+        self.spi.transfer(0xff);
+        self.spi.transfer(0xff);
+        let resp = self.spi.transfer(0xff) & 0x1f;
+        if resp != 0x05 {
+            self.end_cmd();
+            return Err(SDError::WriteRejected(resp));
+        }
+        // busy while the card holds MISO low
+        while self.spi.transfer(0xff) != 0xff {}
+        self.end_cmd();
+        Ok(())
+    }
+}
+
+pub struct SDCardWrapper(UPIntrFreeCell<SDCard>);
+
+impl SDCardWrapper {
+    pub fn new() -> Self {
+        let sd = SDCard::new().expect("can't init sdcard");
+        Self(unsafe { UPIntrFreeCell::new(sd) })
+    }
+}
+
+impl BlockDevice for SDCardWrapper {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.0
+            .exclusive_access()
+            .read_block(block_id, buf)
+            .expect("Error when reading SDCard");
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.0
+            .exclusive_access()
+            .write_block(block_id, buf)
+            .expect("Error when writing SDCard");
+    }
+
+    fn handle_irq(&self) {
+        // polling only
+    }
+}
@@ -1,16 +1,386 @@
+use super::elevator;
 use super::BlockDevice;
-use crate::drivers::bus::virtio::VirtioHal;
+use crate::drivers::bus::{
+    dma::DmaBuffer,
+    mmio::{DeviceType, MmioTransport},
+    virtio::VirtioError,
+    virtqueue::VirtQueue,
+};
 use crate::sync::{Condvar, UPIntrFreeCell};
 use crate::task::schedule;
+use crate::timer::get_time_us;
 use crate::DEV_NON_BLOCKING_ACCESS;
 use alloc::collections::BTreeMap;
-use virtio_drivers::{BlkResp, RespStatus, VirtIOBlk, VirtIOHeader};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use easy_fs::BlockDeviceStats;
 
 #[allow(unused)]
 const VIRTIO0: usize = 0x10008000;
 
+const QUEUE_SIZE: u16 = 16;
+const SECTOR_SIZE: usize = 512;
+
+const BLK_T_IN: u32 = 0;
+const BLK_T_OUT: u32 = 1;
+const BLK_T_FLUSH: u32 = 4;
+
+/// device caches writes, until a `BLK_T_FLUSH`
+const BLK_F_FLUSH: u64 = 1 << 9;
+
+/// request header, device reads it
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct BlkReq {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RespStatus {
+    Ok = 0,
+    IoErr = 1,
+    Unsupported = 2,
+    /// not answered by device yet
+    NotReady = 3,
+}
+
+impl From<u8> for RespStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            0 => RespStatus::Ok,
+            1 => RespStatus::IoErr,
+            2 => RespStatus::Unsupported,
+            _ => RespStatus::NotReady,
+        }
+    }
+}
+
+fn as_bytes<T>(v: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(v as *const T as *const u8, size_of::<T>()) }
+}
+
+/// DMA slot of one request: header, status byte written by device, then the sector
+const SLOT_SIZE: usize = 1024;
+const SLOT_RESP: usize = size_of::<BlkReq>();
+const SLOT_DATA: usize = 512;
+
+/// Blocks merged into one request at most, it takes a slot and a descriptor for each, and
+/// two more descriptors for the header and status
+const MAX_MERGE: usize = 4;
+
+/// virtio-blk device over the shared mmio transport & virtqueue
+pub struct VirtIOBlk {
+    transport: MmioTransport,
+    queue: VirtQueue,
+    /// in sectors
+    capacity: u64,
+    /// one slot per possible in-flight request, data is bounced through them
+    slots: DmaBuffer,
+    free_slots: Vec<usize>,
+    /// writes are cached by the device, and need flushing to be durable
+    write_cache: bool,
+    /// time a request was sent, by the slot of its header, in us
+    issued: [usize; QUEUE_SIZE as usize],
+    /// block after the last request, where the elevator goes on from
+    head: usize,
+    stats: BlockDeviceStats,
+}
+
+impl VirtIOBlk {
+    pub unsafe fn new(base: usize) -> Result<Self, VirtioError> {
+        let mut transport = MmioTransport::new(base)?;
+        if transport.device_type() != DeviceType::Block {
+            return Err(VirtioError::WrongDevice);
+        }
+        // without flush, writes are durable once completed
+        let mut write_cache = false;
+        transport.begin_init(|features| {
+            write_cache = features & BLK_F_FLUSH != 0;
+            features & BLK_F_FLUSH
+        });
+        let capacity = transport.read_config::<u64>(0);
+        let queue = match VirtQueue::new(&mut transport, 0, QUEUE_SIZE) {
+            Ok(q) => q,
+            Err(e) => {
+                transport.fail();
+                return Err(e);
+            }
+        };
+        let Some(slots) = DmaBuffer::new(QUEUE_SIZE as usize * SLOT_SIZE) else {
+            transport.fail();
+            return Err(VirtioError::DmaError);
+        };
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            queue,
+            capacity,
+            slots,
+            free_slots: (0..QUEUE_SIZE as usize).collect(),
+            write_cache,
+            issued: [0; QUEUE_SIZE as usize],
+            head: 0,
+            stats: BlockDeviceStats::default(),
+        })
+    }
+
+    pub fn virt_queue_size(&self) -> u16 {
+        self.queue.size()
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Whether writes need a `flush` to be durable
+    pub fn write_cache(&self) -> bool {
+        self.write_cache
+    }
+
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    pub fn stats(&self) -> BlockDeviceStats {
+        self.stats
+    }
+
+    fn check_buf(buf: &[u8]) -> Result<(), VirtioError> {
+        if buf.len() != SECTOR_SIZE {
+            return Err(VirtioError::BufferTooSmall);
+        }
+        Ok(())
+    }
+
+    /// Fill a free slot and queue the request, `data` is copied in for writes.
+    /// Returns (token, slot), the slot is held until `complete`.
+    fn submit(
+        &mut self,
+        type_: u32,
+        block_id: usize,
+        data: Option<&[u8]>,
+    ) -> Result<(u16, usize), VirtioError> {
+        let blocks = match type_ {
+            BLK_T_FLUSH => 0,
+            _ => 1,
+        };
+        let data = data.as_ref().map_or(&[][..], core::slice::from_ref);
+        let (token, slots) = self.submit_run(type_, block_id, blocks, data)?;
+        Ok((token, slots[0]))
+    }
+
+    /// Queue a request on `blocks` blocks from `block_id` on, each has a slot of its own, the
+    /// first one holds the header too. `data` is copied in for writes.
+    /// Returns (token, slots), the slots are held until `complete_run`.
+    fn submit_run(
+        &mut self,
+        type_: u32,
+        block_id: usize,
+        blocks: usize,
+        data: &[&[u8]],
+    ) -> Result<(u16, Vec<usize>), VirtioError> {
+        let count = blocks.max(1);
+        if self.free_slots.len() < count {
+            return Err(VirtioError::QueueFull);
+        }
+        let slots = self.free_slots.split_off(self.free_slots.len() - count);
+        let first = slots[0];
+        let req = BlkReq {
+            type_,
+            reserved: 0,
+            sector: block_id as u64,
+        };
+        let buf = &mut self.slots.as_mut_slice()[first * SLOT_SIZE..(first + 1) * SLOT_SIZE];
+        buf[..SLOT_RESP].copy_from_slice(as_bytes(&req));
+        buf[SLOT_RESP] = RespStatus::NotReady as u8;
+        for (&slot, data) in slots.iter().zip(data) {
+            let buf = &mut self.slots.as_mut_slice()[slot * SLOT_SIZE..(slot + 1) * SLOT_SIZE];
+            buf[SLOT_DATA..].copy_from_slice(data);
+        }
+        self.slots.sync_for_device();
+
+        let mut bufs: Vec<_> = self
+            .slots
+            .as_mut_slice()
+            .chunks_mut(SLOT_SIZE)
+            .map(Some)
+            .collect();
+        let (header, data) = bufs[first].take().unwrap().split_at_mut(SLOT_DATA);
+        let (req, resp) = header.split_at_mut(SLOT_RESP);
+        let resp = &mut resp[..1];
+        let mut data = vec![data];
+        for &slot in &slots[1..] {
+            data.push(&mut bufs[slot].take().unwrap()[SLOT_DATA..]);
+        }
+        data.truncate(blocks);
+        let res = match type_ {
+            BLK_T_IN => {
+                data.push(resp);
+                self.queue.add(&[req], &data)
+            }
+            _ => {
+                let mut inputs: Vec<&[u8]> = vec![req];
+                inputs.extend(data.iter().map(|data| &**data));
+                self.queue.add(&inputs, &[resp])
+            }
+        };
+        match res {
+            Ok(token) => {
+                self.queue.notify(&mut self.transport);
+                self.issued[first] = get_time_us();
+                self.head = block_id + blocks;
+                let stats = &mut self.stats;
+                stats.requests += 1;
+                stats.blocks += blocks;
+                stats.merged += blocks.saturating_sub(1);
+                stats.in_flight += 1;
+                stats.max_in_flight = stats.max_in_flight.max(stats.in_flight);
+                stats.depth_sum += stats.in_flight;
+                Ok((token, slots))
+            }
+            Err(e) => {
+                self.free_slots.extend(slots);
+                Err(e)
+            }
+        }
+    }
+
+    /// Status of a finished request, copying the sector out for reads. Releases the slot.
+    pub fn complete(&mut self, slot: usize, out: Option<&mut [u8]>) -> RespStatus {
+        self.slots.sync_for_cpu();
+        let buf = &self.slots.as_slice()[slot * SLOT_SIZE..(slot + 1) * SLOT_SIZE];
+        let status = RespStatus::from(unsafe { core::ptr::read_volatile(&buf[SLOT_RESP]) });
+        if let Some(out) = out {
+            out.copy_from_slice(&buf[SLOT_DATA..]);
+        }
+        self.free_slots.push(slot);
+        let latency = get_time_us() - self.issued[slot];
+        let stats = &mut self.stats;
+        stats.in_flight -= 1;
+        stats.latency_us += latency;
+        stats.max_latency_us = stats.max_latency_us.max(latency);
+        status
+    }
+
+    /// Status of a finished request of `submit_run`. Releases the slots.
+    pub fn complete_run(&mut self, slots: &[usize]) -> RespStatus {
+        self.free_slots.extend_from_slice(&slots[1..]);
+        self.complete(slots[0], None)
+    }
+
+    /// Whether the device is done with the request of `slot`, popped or not
+    pub fn done(&self, slot: usize) -> bool {
+        self.slots.sync_for_cpu();
+        let status = unsafe {
+            core::ptr::read_volatile(&self.slots.as_slice()[slot * SLOT_SIZE + SLOT_RESP])
+        };
+        RespStatus::from(status) != RespStatus::NotReady
+    }
+
+    /// Spin until `token` is popped, for polling
+    fn wait_polled(&mut self, token: u16) -> Result<(), VirtioError> {
+        while !self.queue.can_pop() {
+            core::hint::spin_loop();
+        }
+        let (popped, _) = self.queue.pop_used()?;
+        assert_eq!(popped, token, "virtqueue popped unexpected token");
+        Ok(())
+    }
+
+    fn sync_request(
+        &mut self,
+        type_: u32,
+        block_id: usize,
+        data: Option<&[u8]>,
+        out: Option<&mut [u8]>,
+    ) -> Result<(), VirtioError> {
+        let (token, slot) = self.submit(type_, block_id, data)?;
+        self.wait_polled(token)?;
+        match self.complete(slot, out) {
+            RespStatus::Ok => Ok(()),
+            _ => Err(VirtioError::IoError),
+        }
+    }
+
+    pub fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> Result<(), VirtioError> {
+        Self::check_buf(buf)?;
+        self.sync_request(BLK_T_IN, block_id, None, Some(buf))
+    }
+
+    pub fn write_block(&mut self, block_id: usize, buf: &[u8]) -> Result<(), VirtioError> {
+        Self::check_buf(buf)?;
+        self.sync_request(BLK_T_OUT, block_id, Some(buf), None)
+    }
+
+    /// Write adjacent blocks from `block_id` on in a single request
+    pub fn write_run(&mut self, block_id: usize, bufs: &[&[u8]]) -> Result<(), VirtioError> {
+        let (token, slots) = self.write_run_nb(block_id, bufs)?;
+        self.wait_polled(token)?;
+        match self.complete_run(&slots) {
+            RespStatus::Ok => Ok(()),
+            _ => Err(VirtioError::IoError),
+        }
+    }
+
+    /// Make completed writes durable, nothing to do without a write cache
+    pub fn flush(&mut self) -> Result<(), VirtioError> {
+        if !self.write_cache {
+            return Ok(());
+        }
+        self.sync_request(BLK_T_FLUSH, 0, None, None)
+    }
+
+    /// Submit a read and return (token, slot) at once, finish with `complete` after the
+    /// token is popped.
+    pub fn read_block_nb(&mut self, block_id: usize) -> Result<(u16, usize), VirtioError> {
+        self.submit(BLK_T_IN, block_id, None)
+    }
+
+    /// Submit a write and return (token, slot) at once, finish with `complete` after the
+    /// token is popped.
+    pub fn write_block_nb(
+        &mut self,
+        block_id: usize,
+        buf: &[u8],
+    ) -> Result<(u16, usize), VirtioError> {
+        Self::check_buf(buf)?;
+        self.submit(BLK_T_OUT, block_id, Some(buf))
+    }
+
+    /// Submit a write of adjacent blocks from `block_id` on and return (token, slots) at once,
+    /// finish with `complete_run` after the token is popped.
+    pub fn write_run_nb(
+        &mut self,
+        block_id: usize,
+        bufs: &[&[u8]],
+    ) -> Result<(u16, Vec<usize>), VirtioError> {
+        for buf in bufs {
+            Self::check_buf(buf)?;
+        }
+        self.submit_run(BLK_T_OUT, block_id, bufs.len(), bufs)
+    }
+
+    /// Submit a flush and return (token, slot) at once, finish with `complete` after the
+    /// token is popped.
+    pub fn flush_nb(&mut self) -> Result<(u16, usize), VirtioError> {
+        self.submit(BLK_T_FLUSH, 0, None)
+    }
+
+    pub fn ack_interrupt(&mut self) -> bool {
+        self.transport.ack_interrupt()
+    }
+
+    pub fn pop_used(&mut self) -> Result<u16, VirtioError> {
+        self.queue.pop_used().map(|(token, _)| token)
+    }
+}
+
 pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
+    virtio_blk: UPIntrFreeCell<VirtIOBlk>,
     condvars: BTreeMap<u16, Condvar>,
 }
 
@@ -18,14 +388,13 @@ impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
         let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.read_block_nb(block_id, buf, &mut resp).unwrap() };
-                self.condvars.get(&token).unwrap().wait_no_sched()
+            let (task_cx_ptr, slot) = self.virtio_blk.exclusive_session(|blk| {
+                let (token, slot) = blk.read_block_nb(block_id).unwrap();
+                (self.condvars.get(&token).unwrap().wait_no_sched(), slot)
             });
             schedule(task_cx_ptr);
             assert_eq!(
-                resp.status(),
+                self.virtio_blk.exclusive_access().complete(slot, Some(buf)),
                 RespStatus::Ok,
                 "Error when reading VirtIOBlk"
             );
@@ -40,14 +409,13 @@ impl BlockDevice for VirtIOBlock {
     fn write_block(&self, block_id: usize, buf: &[u8]) {
         let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.write_block_nb(block_id, buf, &mut resp).unwrap() };
-                self.condvars.get(&token).unwrap().wait_no_sched()
+            let (task_cx_ptr, slot) = self.virtio_blk.exclusive_session(|blk| {
+                let (token, slot) = blk.write_block_nb(block_id, buf).unwrap();
+                (self.condvars.get(&token).unwrap().wait_no_sched(), slot)
             });
             schedule(task_cx_ptr);
             assert_eq!(
-                resp.status(),
+                self.virtio_blk.exclusive_access().complete(slot, None),
                 RespStatus::Ok,
                 "Error when writing VirtIOBlk"
             );
@@ -59,31 +427,109 @@ impl BlockDevice for VirtIOBlock {
         }
     }
 
+    /// Requests go in elevator order, with adjacent blocks merged. As many are sent at once as
+    /// the queue takes, then waited for.
+    fn write_blocks(&self, blocks: &[(usize, &[u8])]) {
+        let head = self.virtio_blk.exclusive_access().head();
+        let runs = elevator::schedule(head, blocks, MAX_MERGE);
+        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        if !nb {
+            for run in runs {
+                self.virtio_blk
+                    .exclusive_access()
+                    .write_run(run.block_id, &run.bufs)
+                    .expect("Error when writing VirtIOBlk");
+            }
+            return;
+        }
+        let mut runs = runs.into_iter().peekable();
+        while runs.peek().is_some() {
+            let sent = self.virtio_blk.exclusive_session(|blk| {
+                let mut sent = Vec::new();
+                while let Some(run) = runs.peek() {
+                    match blk.write_run_nb(run.block_id, &run.bufs) {
+                        Ok(request) => sent.push(request),
+                        // the rest once these are done
+                        Err(VirtioError::QueueFull) if !sent.is_empty() => break,
+                        Err(e) => panic!("Error when writing VirtIOBlk: {:?}", e),
+                    }
+                    runs.next();
+                }
+                sent
+            });
+            for (token, slots) in sent {
+                // done ones were signaled with nobody waiting yet
+                let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
+                    (!blk.done(slots[0]))
+                        .then(|| self.condvars.get(&token).unwrap().wait_no_sched())
+                });
+                if let Some(task_cx_ptr) = task_cx_ptr {
+                    schedule(task_cx_ptr);
+                }
+                assert_eq!(
+                    self.virtio_blk.exclusive_access().complete_run(&slots),
+                    RespStatus::Ok,
+                    "Error when writing VirtIOBlk"
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if !self.virtio_blk.exclusive_access().write_cache() {
+            return;
+        }
+        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        if nb {
+            let (task_cx_ptr, slot) = self.virtio_blk.exclusive_session(|blk| {
+                let (token, slot) = blk.flush_nb().unwrap();
+                (self.condvars.get(&token).unwrap().wait_no_sched(), slot)
+            });
+            schedule(task_cx_ptr);
+            assert_eq!(
+                self.virtio_blk.exclusive_access().complete(slot, None),
+                RespStatus::Ok,
+                "Error when flushing VirtIOBlk"
+            );
+        } else {
+            self.virtio_blk
+                .exclusive_access()
+                .flush()
+                .expect("Error when flushing VirtIOBlk");
+        }
+    }
+
     fn handle_irq(&self) {
         self.virtio_blk.exclusive_session(|blk| {
+            blk.ack_interrupt();
             while let Ok(token) = blk.pop_used() {
                 self.condvars.get(&token).unwrap().signal();
             }
         });
     }
+
+    fn stats(&self) -> BlockDeviceStats {
+        self.virtio_blk.exclusive_access().stats()
+    }
 }
 
 impl VirtIOBlock {
     pub fn new() -> Self {
-        let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            )
-        };
+        Self::at(VIRTIO0).expect("no virtio-blk device")
+    }
+
+    /// Disk in the mmio window at `base`, `None` if it has another device or none
+    pub fn at(base: usize) -> Option<Self> {
+        let virtio_blk = unsafe { UPIntrFreeCell::new(VirtIOBlk::new(base).ok()?) };
         let mut condvars = BTreeMap::new();
         let channels = virtio_blk.exclusive_access().virt_queue_size();
         for i in 0..channels {
             let condvar = Condvar::new();
             condvars.insert(i, condvar);
         }
-        Self {
+        Some(Self {
             virtio_blk,
             condvars,
-        }
+        })
     }
 }
@@ -0,0 +1,67 @@
+//! Memory shared with devices. Buffers are physically contiguous and page aligned, and
+//! ownership moves between CPU and device explicitly: `sync_for_device` before the device
+//! may read what the CPU wrote, `sync_for_cpu` before the CPU reads what the device wrote.
+
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use crate::{
+    config::PAGE_SIZE,
+    mm::{frame_alloc_contiguous, FrameTracker, PhysAddr},
+};
+
+pub struct DmaBuffer {
+    frames: Vec<FrameTracker>,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Zeroed buffer of at least `len` bytes, `None` if no contiguous run is left. It takes a
+    /// whole buddy block, aligned to its size.
+    pub fn new(len: usize) -> Option<Self> {
+        let pages = len.div_ceil(PAGE_SIZE).max(1);
+        let frames = frame_alloc_contiguous(pages.next_power_of_two().trailing_zeros() as usize)?;
+        Some(Self { frames, len })
+    }
+
+    pub fn paddr(&self) -> usize {
+        PhysAddr::from(self.frames[0].ppn).0
+    }
+
+    /// kernel space maps physical memory identically
+    pub fn vaddr(&self) -> usize {
+        self.paddr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr() as *const u8, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr() as *mut u8, self.len) }
+    }
+
+    /// CPU is done writing, the device may read from now on.
+    pub fn sync_for_device(&self) {
+        // all platforms we run on are cache coherent for DMA, non-coherent ones would
+        // clean the range here (Zicbom `cbo.clean`)
+        barrier();
+    }
+
+    /// Device is done writing, the CPU may read from now on.
+    pub fn sync_for_cpu(&self) {
+        barrier();
+        // non-coherent: invalidate the range here (Zicbom `cbo.inval`)
+    }
+}
+
+/// order memory and device i/o both ways
+pub fn barrier() {
+    unsafe {
+        asm!("fence iorw, iorw");
+    }
+}
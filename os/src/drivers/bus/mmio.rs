@@ -0,0 +1,195 @@
+//! virtio-mmio transport (legacy, version 1 register layout as qemu virt uses by default).
+//! Ref: virtio spec v1.1 4.2.4 "Legacy interface"
+
+use core::mem::size_of;
+use volatile::{ReadOnly, Volatile, WriteOnly};
+
+use crate::config::PAGE_SIZE;
+
+use super::virtio::VirtioError;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const LEGACY_VERSION: u32 = 1;
+const CONFIG_OFFSET: usize = 0x100;
+
+/// device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FAILED: u32 = 128;
+
+/// interrupt status bits
+const INTERRUPT_USED_BUFFER: u32 = 1;
+const INTERRUPT_CONFIG_CHANGE: u32 = 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DeviceType {
+    Invalid = 0,
+    Network = 1,
+    Block = 2,
+    Console = 3,
+    EntropySource = 4,
+    GPU = 16,
+    Input = 18,
+    Unknown,
+}
+
+impl From<u32> for DeviceType {
+    fn from(id: u32) -> Self {
+        match id {
+            0 => Self::Invalid,
+            1 => Self::Network,
+            2 => Self::Block,
+            3 => Self::Console,
+            4 => Self::EntropySource,
+            16 => Self::GPU,
+            18 => Self::Input,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct MmioRegs {
+    /// 0x00
+    magic: ReadOnly<u32>,
+    version: ReadOnly<u32>,
+    device_id: ReadOnly<u32>,
+    vendor_id: ReadOnly<u32>,
+    /// 0x10
+    device_features: ReadOnly<u32>,
+    device_features_sel: WriteOnly<u32>,
+    _r0: [u32; 2],
+    /// 0x20
+    driver_features: WriteOnly<u32>,
+    driver_features_sel: WriteOnly<u32>,
+    guest_page_size: WriteOnly<u32>,
+    _r1: u32,
+    /// 0x30
+    queue_sel: WriteOnly<u32>,
+    queue_num_max: ReadOnly<u32>,
+    queue_num: WriteOnly<u32>,
+    queue_align: WriteOnly<u32>,
+    /// 0x40
+    queue_pfn: Volatile<u32>,
+    _r2: [u32; 3],
+    /// 0x50
+    queue_notify: WriteOnly<u32>,
+    _r3: [u32; 3],
+    /// 0x60
+    interrupt_status: ReadOnly<u32>,
+    interrupt_ack: WriteOnly<u32>,
+    _r4: [u32; 2],
+    /// 0x70
+    status: Volatile<u32>,
+}
+
+/// One virtio-mmio device window.
+pub struct MmioTransport {
+    base: usize,
+}
+
+impl MmioTransport {
+    /// Probe the window at `base`, which must be mapped in kernel space.
+    pub unsafe fn new(base: usize) -> Result<Self, VirtioError> {
+        let transport = Self { base };
+        let regs = transport.regs();
+        if regs.magic.read() != MAGIC {
+            return Err(VirtioError::BadMagic);
+        }
+        if regs.version.read() != LEGACY_VERSION {
+            return Err(VirtioError::UnsupportedVersion);
+        }
+        if regs.device_id.read() == 0 {
+            return Err(VirtioError::NotFound);
+        }
+        Ok(transport)
+    }
+
+    fn regs(&self) -> &'static mut MmioRegs {
+        unsafe { &mut *(self.base as *mut MmioRegs) }
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        self.regs().device_id.read().into()
+    }
+
+    /// Reset, acknowledge and negotiate features: `negotiate` gets what the
+    /// device offers and returns what the driver accepts.
+    pub fn begin_init(&mut self, negotiate: impl FnOnce(u64) -> u64) {
+        let regs = self.regs();
+        regs.status.write(0);
+        regs.status.write(STATUS_ACKNOWLEDGE);
+        regs.status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        regs.device_features_sel.write(0);
+        let mut features = regs.device_features.read() as u64;
+        regs.device_features_sel.write(1);
+        features |= (regs.device_features.read() as u64) << 32;
+
+        let accepted = negotiate(features) & features;
+        regs.driver_features_sel.write(0);
+        regs.driver_features.write(accepted as u32);
+        regs.driver_features_sel.write(1);
+        regs.driver_features.write((accepted >> 32) as u32);
+
+        regs.guest_page_size.write(PAGE_SIZE as u32);
+    }
+
+    /// Queues are set up, device may run now.
+    pub fn finish_init(&mut self) {
+        let regs = self.regs();
+        regs.status.write(regs.status.read() | STATUS_DRIVER_OK);
+    }
+
+    /// Give up on the device.
+    pub fn fail(&mut self) {
+        let regs = self.regs();
+        regs.status.write(regs.status.read() | STATUS_FAILED);
+    }
+
+    pub fn max_queue_size(&mut self, queue: u16) -> u32 {
+        let regs = self.regs();
+        regs.queue_sel.write(queue as u32);
+        regs.queue_num_max.read()
+    }
+
+    pub fn queue_used(&mut self, queue: u16) -> bool {
+        let regs = self.regs();
+        regs.queue_sel.write(queue as u32);
+        regs.queue_pfn.read() != 0
+    }
+
+    /// Hand the queue memory (page aligned, physically contiguous) to the device.
+    pub fn queue_set(&mut self, queue: u16, size: u32, paddr: usize) {
+        let regs = self.regs();
+        regs.queue_sel.write(queue as u32);
+        regs.queue_num.write(size);
+        regs.queue_align.write(PAGE_SIZE as u32);
+        regs.queue_pfn.write((paddr / PAGE_SIZE) as u32);
+    }
+
+    pub fn notify(&mut self, queue: u16) {
+        self.regs().queue_notify.write(queue as u32);
+    }
+
+    /// Acknowledge pending interrupt, true if there was one.
+    pub fn ack_interrupt(&mut self) -> bool {
+        let regs = self.regs();
+        let status = regs.interrupt_status.read();
+        if status & (INTERRUPT_USED_BUFFER | INTERRUPT_CONFIG_CHANGE) != 0 {
+            regs.interrupt_ack.write(status);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Read a device specific config field at `offset`.
+    pub fn read_config<T: Copy>(&self, offset: usize) -> T {
+        assert!(offset % size_of::<u32>() == 0);
+        let ptr = (self.base + CONFIG_OFFSET + offset) as *const T;
+        unsafe { ptr.read_volatile() }
+    }
+}
@@ -1 +1,4 @@
+pub mod dma;
+pub mod mmio;
 pub mod virtio;
+pub mod virtqueue;
@@ -19,7 +19,7 @@ pub struct VirtioHal;
 impl Hal for VirtioHal {
     fn dma_alloc(pages: usize) -> virtio_drivers::PhysAddr {
         let trakcers = frame_alloc_more(pages);
-        let ppn_base = trakcers.as_ref().unwrap().last().unwrap().ppn;
+        let ppn_base = trakcers.as_ref().unwrap().first().unwrap().ppn;
         QUEUE_FRAMES
             .exclusive_access()
             .append(&mut trakcers.unwrap());
@@ -49,3 +49,24 @@ impl Hal for VirtioHal {
             .0
     }
 }
+
+/// Errors of the in-kernel virtio transport, queue and device drivers
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VirtioError {
+    /// no device behind the mmio window
+    NotFound,
+    BadMagic,
+    UnsupportedVersion,
+    /// device of another type than the driver expects
+    WrongDevice,
+    InvalidParam,
+    /// queue already set up
+    AlreadyUsed,
+    QueueFull,
+    /// request not finished yet
+    NotReady,
+    BufferTooSmall,
+    IoError,
+    /// no contiguous memory left for a DMA buffer
+    DmaError,
+}
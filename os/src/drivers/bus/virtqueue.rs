@@ -0,0 +1,226 @@
+//! Split virtqueue in legacy layout: descriptor table + avail ring, then the
+//! used ring on the next page boundary, all in one contiguous DMA region.
+//! Ref: virtio spec v1.1 2.6 "Split Virtqueues"
+
+use core::{
+    mem::size_of,
+    ptr::{read_volatile, write_volatile},
+};
+use virtio_drivers::Hal;
+
+use crate::config::PAGE_SIZE;
+
+use super::{
+    dma::{barrier, DmaBuffer},
+    mmio::MmioTransport,
+    virtio::{VirtioError, VirtioHal},
+};
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+fn align_up(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+pub struct VirtQueue {
+    /// queue index on the device
+    idx: u16,
+    size: u16,
+    /// descriptor table and rings
+    ring: DmaBuffer,
+    /// `ring.vaddr()`
+    base: usize,
+    /// offset of used ring from `base`
+    used_offset: usize,
+    /// free descriptor list
+    free_head: u16,
+    num_free: u16,
+    /// shadow of avail.idx
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Allocate and register queue `idx` with `size` entries (power of 2).
+    pub fn new(transport: &mut MmioTransport, idx: u16, size: u16) -> Result<Self, VirtioError> {
+        if !size.is_power_of_two() || transport.max_queue_size(idx) < size as u32 {
+            return Err(VirtioError::InvalidParam);
+        }
+        if transport.queue_used(idx) {
+            return Err(VirtioError::AlreadyUsed);
+        }
+        let n = size as usize;
+        let desc_avail = n * size_of::<Descriptor>() + size_of::<u16>() * (3 + n);
+        let used = size_of::<u16>() * 3 + n * size_of::<UsedElem>();
+        let used_offset = align_up(desc_avail);
+        let pages = (used_offset + align_up(used)) / PAGE_SIZE;
+
+        let ring = DmaBuffer::new(pages * PAGE_SIZE).ok_or(VirtioError::DmaError)?;
+        let base = ring.vaddr();
+        transport.queue_set(idx, size as u32, ring.paddr());
+
+        let queue = Self {
+            idx,
+            size,
+            ring,
+            base,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            queue.desc(i).next = i + 1;
+        }
+        Ok(queue)
+    }
+
+    fn desc(&self, i: u16) -> &'static mut Descriptor {
+        unsafe { &mut *(self.base as *mut Descriptor).add(i as usize) }
+    }
+
+    /// avail: flags, idx, ring[size], used_event
+    fn avail_ptr(&self, i: usize) -> *mut u16 {
+        let avail = self.base + self.size as usize * size_of::<Descriptor>();
+        (avail as *mut u16).wrapping_add(i)
+    }
+
+    /// used: flags, idx, ring[size], avail_event
+    fn used_idx_ptr(&self) -> *const u16 {
+        (self.base + self.used_offset + size_of::<u16>()) as *const u16
+    }
+
+    fn used_elem_ptr(&self, i: u16) -> *const UsedElem {
+        let ring = self.base + self.used_offset + size_of::<u16>() * 2;
+        (ring as *const UsedElem).wrapping_add(i as usize)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.idx
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn available_desc(&self) -> usize {
+        self.num_free as usize
+    }
+
+    /// Token the next successful `add` will return.
+    pub fn peek_token(&self) -> u16 {
+        self.free_head
+    }
+
+    /// Chain `inputs` (device reads) then `outputs` (device writes) into one
+    /// request. Returns its token, the buffers must stay alive until it is popped.
+    pub fn add(&mut self, inputs: &[&[u8]], outputs: &[&mut [u8]]) -> Result<u16, VirtioError> {
+        let count = inputs.len() + outputs.len();
+        if count == 0 {
+            return Err(VirtioError::InvalidParam);
+        }
+        if count > self.num_free as usize {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut last = head;
+        let bufs = inputs
+            .iter()
+            .map(|b| (b.as_ptr() as usize, b.len(), 0))
+            .chain(
+                outputs
+                    .iter()
+                    .map(|b| (b.as_ptr() as usize, b.len(), DESC_F_WRITE)),
+            );
+        for (va, len, flags) in bufs {
+            let desc = self.desc(self.free_head);
+            desc.addr = VirtioHal::virt_to_phys(va) as u64;
+            desc.len = len as u32;
+            desc.flags = flags | DESC_F_NEXT;
+            last = self.free_head;
+            self.free_head = desc.next;
+        }
+        self.desc(last).flags &= !DESC_F_NEXT;
+        self.num_free -= count as u16;
+
+        let slot = 2 + (self.avail_idx % self.size) as usize;
+        unsafe {
+            write_volatile(self.avail_ptr(slot), head);
+        }
+        // ring entry visible before idx
+        barrier();
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe {
+            write_volatile(self.avail_ptr(1), self.avail_idx);
+        }
+        Ok(head)
+    }
+
+    pub fn notify(&self, transport: &mut MmioTransport) {
+        self.ring.sync_for_device();
+        transport.notify(self.idx);
+    }
+
+    pub fn can_pop(&self) -> bool {
+        self.ring.sync_for_cpu();
+        self.last_used_idx != unsafe { read_volatile(self.used_idx_ptr()) }
+    }
+
+    /// Next finished request as (token, bytes written by device), its
+    /// descriptors go back to the free list.
+    pub fn pop_used(&mut self) -> Result<(u16, u32), VirtioError> {
+        if !self.can_pop() {
+            return Err(VirtioError::NotReady);
+        }
+        let elem = unsafe { read_volatile(self.used_elem_ptr(self.last_used_idx % self.size)) };
+        let head = elem.id as u16;
+
+        let mut tail = head;
+        let mut count = 1;
+        while self.desc(tail).flags & DESC_F_NEXT != 0 {
+            tail = self.desc(tail).next;
+            count += 1;
+        }
+        self.desc(tail).next = self.free_head;
+        self.free_head = head;
+        self.num_free += count;
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Ok((head, elem.len))
+    }
+
+    /// Add, notify and spin until the device is done, for polling drivers.
+    pub fn add_notify_wait_pop(
+        &mut self,
+        transport: &mut MmioTransport,
+        inputs: &[&[u8]],
+        outputs: &[&mut [u8]],
+    ) -> Result<u32, VirtioError> {
+        let token = self.add(inputs, outputs)?;
+        self.notify(transport);
+        while !self.can_pop() {
+            core::hint::spin_loop();
+        }
+        let (popped, len) = self.pop_used()?;
+        assert_eq!(popped, token, "virtqueue popped unexpected token");
+        Ok(len)
+    }
+}
@@ -1,13 +1,23 @@
 mod ns16550a;
+#[cfg(feature = "board_k210")]
+mod uarths;
+#[cfg(feature = "board_qemu")]
+mod virtio_console;
 
 use crate::board::CharDeviceImpl;
 use alloc::sync::Arc;
 use lazy_static::lazy_static;
 pub use ns16550a::NS16550a;
+#[cfg(feature = "board_k210")]
+pub use uarths::UartHs;
+#[cfg(feature = "board_qemu")]
+pub use virtio_console::{VirtIOConsole, VIRTIO_CONSOLE};
 
 pub trait CharDevice {
     fn init(&self);
     fn read(&self) -> u8;
+    /// If `read` would return without waiting
+    fn has_input(&self) -> bool;
     fn write(&self, ch: u8);
     fn handle_irq(&self);
 }
@@ -167,6 +167,9 @@ impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
             }
         }
     }
+    fn has_input(&self) -> bool {
+        !self.read_buffer_is_empty()
+    }
     fn write(&self, ch: u8) {
         let mut inner = self.inner.exclusive_access();
         inner.ns16550a.write(ch);
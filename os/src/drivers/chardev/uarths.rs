@@ -0,0 +1,125 @@
+//! K210 UARTHS (SiFive UART compatible), already set up for console by the SBI.
+//! Ref: SiFive FE310-G000 manual, chapter 13 "UART"
+use super::CharDevice;
+use crate::sync::UPIntrFreeCell;
+use crate::task::suspend_current_and_run_next;
+use alloc::collections::VecDeque;
+use volatile::Volatile;
+
+const TXDATA_FULL: u32 = 1 << 31;
+const RXDATA_EMPTY: u32 = 1 << 31;
+const RXCTRL_RXEN: u32 = 1;
+const IE_RXWM: u32 = 1 << 1;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct UartHsRegs {
+    txdata: Volatile<u32>,
+    rxdata: Volatile<u32>,
+    txctrl: Volatile<u32>,
+    rxctrl: Volatile<u32>,
+    ie: Volatile<u32>,
+    ip: Volatile<u32>,
+    div: Volatile<u32>,
+}
+
+pub struct UartHsRaw {
+    base_addr: usize,
+}
+
+impl UartHsRaw {
+    fn regs(&mut self) -> &mut UartHsRegs {
+        unsafe { &mut *(self.base_addr as *mut UartHsRegs) }
+    }
+
+    pub fn new(base_addr: usize) -> Self {
+        Self { base_addr }
+    }
+
+    pub fn init(&mut self) {
+        let regs = self.regs();
+        // rx watermark 0: interrupt as soon as one byte is there
+        regs.rxctrl.write(RXCTRL_RXEN);
+        regs.ie.write(IE_RXWM);
+    }
+
+    pub fn read(&mut self) -> Option<u8> {
+        let data = self.regs().rxdata.read();
+        if data & RXDATA_EMPTY == 0 {
+            Some(data as u8)
+        } else {
+            None
+        }
+    }
+
+    pub fn write(&mut self, ch: u8) {
+        let regs = self.regs();
+        while regs.txdata.read() & TXDATA_FULL != 0 {}
+        regs.txdata.write(ch as u32);
+    }
+}
+
+struct UartHsInner {
+    uarths: UartHsRaw,
+    read_buffer: VecDeque<u8>,
+}
+
+/// Unlike the ns16550a driver, reads poll the device when the buffer is empty,
+/// since external interrupts only reach S-mode if the SBI forwards them.
+pub struct UartHs<const BASE_ADDR: usize> {
+    inner: UPIntrFreeCell<UartHsInner>,
+}
+
+impl<const BASE_ADDR: usize> UartHs<BASE_ADDR> {
+    pub fn new() -> Self {
+        let inner = UartHsInner {
+            uarths: UartHsRaw::new(BASE_ADDR),
+            read_buffer: VecDeque::new(),
+        };
+        Self {
+            inner: unsafe { UPIntrFreeCell::new(inner) },
+        }
+    }
+}
+
+impl<const BASE_ADDR: usize> CharDevice for UartHs<BASE_ADDR> {
+    fn init(&self) {
+        self.inner.exclusive_access().uarths.init();
+    }
+
+    fn read(&self) -> u8 {
+        loop {
+            let ch = self.inner.exclusive_session(|inner| {
+                inner
+                    .read_buffer
+                    .pop_front()
+                    .or_else(|| inner.uarths.read())
+            });
+            match ch {
+                Some(ch) => return ch,
+                None => suspend_current_and_run_next(),
+            }
+        }
+    }
+
+    fn has_input(&self) -> bool {
+        self.inner.exclusive_session(|inner| {
+            if let Some(ch) = inner.uarths.read() {
+                inner.read_buffer.push_back(ch);
+            }
+            !inner.read_buffer.is_empty()
+        })
+    }
+
+    fn write(&self, ch: u8) {
+        self.inner.exclusive_access().uarths.write(ch);
+    }
+
+    fn handle_irq(&self) {
+        self.inner.exclusive_session(|inner| {
+            while let Some(ch) = inner.uarths.read() {
+                inner.read_buffer.push_back(ch);
+            }
+        });
+    }
+}
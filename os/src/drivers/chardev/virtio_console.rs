@@ -0,0 +1,146 @@
+//! virtio-console with a single port (no MULTIPORT): receiveq 0 and transmitq 1.
+//! One receive buffer is always posted, the irq drains it into `read_buffer`.
+//! Ref: virtio spec v1.1 5.3 "Console Device"
+
+use alloc::{collections::VecDeque, sync::Arc};
+use lazy_static::lazy_static;
+
+use crate::{
+    drivers::bus::{
+        dma::DmaBuffer,
+        mmio::{DeviceType, MmioTransport},
+        virtio::VirtioError,
+        virtqueue::VirtQueue,
+    },
+    sync::{Condvar, UPIntrFreeCell},
+    task::schedule,
+};
+
+use super::CharDevice;
+
+const VIRTIO3: usize = 0x10003000;
+
+const QUEUE_SIZE: u16 = 2;
+const QUEUE_RECEIVE: u16 = 0;
+const QUEUE_TRANSMIT: u16 = 1;
+
+const RECV_BUF_SIZE: usize = 64;
+
+lazy_static! {
+    /// `None` if qemu was started without a `virtconsole`
+    pub static ref VIRTIO_CONSOLE: Option<Arc<VirtIOConsole>> =
+        unsafe { VirtIOConsole::new(VIRTIO3) }.ok().map(Arc::new);
+}
+
+struct VirtIOConsoleInner {
+    transport: MmioTransport,
+    recv_queue: VirtQueue,
+    send_queue: VirtQueue,
+    recv_buf: DmaBuffer,
+    send_buf: DmaBuffer,
+    read_buffer: VecDeque<u8>,
+}
+
+impl VirtIOConsoleInner {
+    /// hand `recv_buf` to the device again
+    fn post_recv(&mut self) -> Result<(), VirtioError> {
+        self.recv_queue.add(&[], &[self.recv_buf.as_mut_slice()])?;
+        self.recv_queue.notify(&mut self.transport);
+        Ok(())
+    }
+}
+
+pub struct VirtIOConsole {
+    inner: UPIntrFreeCell<VirtIOConsoleInner>,
+    condvar: Condvar,
+}
+
+impl VirtIOConsole {
+    pub unsafe fn new(base: usize) -> Result<Self, VirtioError> {
+        let mut transport = MmioTransport::new(base)?;
+        if transport.device_type() != DeviceType::Console {
+            return Err(VirtioError::WrongDevice);
+        }
+        transport.begin_init(|_| 0);
+        let queues = VirtQueue::new(&mut transport, QUEUE_RECEIVE, QUEUE_SIZE).and_then(|rx| {
+            VirtQueue::new(&mut transport, QUEUE_TRANSMIT, QUEUE_SIZE).map(|tx| (rx, tx))
+        });
+        let (recv_queue, send_queue) = match queues {
+            Ok(v) => v,
+            Err(e) => {
+                transport.fail();
+                return Err(e);
+            }
+        };
+        let (recv_buf, send_buf) = match DmaBuffer::new(RECV_BUF_SIZE).zip(DmaBuffer::new(1)) {
+            Some(v) => v,
+            None => {
+                transport.fail();
+                return Err(VirtioError::DmaError);
+            }
+        };
+        transport.finish_init();
+        let mut inner = VirtIOConsoleInner {
+            transport,
+            recv_queue,
+            send_queue,
+            recv_buf,
+            send_buf,
+            read_buffer: VecDeque::new(),
+        };
+        inner.post_recv()?;
+        Ok(Self {
+            inner: UPIntrFreeCell::new(inner),
+            condvar: Condvar::new(),
+        })
+    }
+}
+
+impl CharDevice for VirtIOConsole {
+    fn init(&self) {}
+
+    fn read(&self) -> u8 {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if let Some(ch) = inner.read_buffer.pop_front() {
+                return ch;
+            } else {
+                let task_cx_ptr = self.condvar.wait_no_sched();
+                drop(inner);
+                schedule(task_cx_ptr);
+            }
+        }
+    }
+
+    fn has_input(&self) -> bool {
+        !self.inner.exclusive_access().read_buffer.is_empty()
+    }
+
+    fn write(&self, ch: u8) {
+        let mut inner = self.inner.exclusive_access();
+        let inner = &mut *inner;
+        inner.send_buf.as_mut_slice()[0] = ch;
+        inner.send_buf.sync_for_device();
+        inner
+            .send_queue
+            .add_notify_wait_pop(&mut inner.transport, &[inner.send_buf.as_slice()], &[])
+            .expect("can't write to virtio console");
+    }
+
+    fn handle_irq(&self) {
+        let mut count = 0;
+        self.inner.exclusive_session(|inner| {
+            inner.transport.ack_interrupt();
+            while let Ok((_, len)) = inner.recv_queue.pop_used() {
+                inner.recv_buf.sync_for_cpu();
+                let len = (len as usize).min(RECV_BUF_SIZE);
+                count += len;
+                inner.read_buffer.extend(&inner.recv_buf.as_slice()[..len]);
+                inner.post_recv().expect("can't post virtio console buffer");
+            }
+        });
+        if count > 0 {
+            self.condvar.signal();
+        }
+    }
+}
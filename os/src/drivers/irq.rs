@@ -0,0 +1,113 @@
+//! External interrupt dispatch on top of the PLIC.
+//!
+//! Drivers register a handler for their IRQ line with a priority, the line is
+//! then enabled for supervisor mode on the boot hart. Several handlers may share
+//! a line, all of them run on each claim. Spurious claims and lines nobody
+//! registered are counted and logged instead of panicking.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use lazy_static::lazy_static;
+
+use crate::sync::UPIntrFreeCell;
+
+use super::plic::{IntrTargetPriority, PLIC};
+
+pub type IrqHandler = fn();
+
+/// all external interrupts are taken on hart 0 in supervisor mode
+const HART_ID: usize = 0;
+const TARGET: IntrTargetPriority = IntrTargetPriority::Supervisor;
+
+struct IrqLine {
+    priority: u32,
+    handlers: Vec<IrqHandler>,
+}
+
+pub struct IrqController {
+    plic: Option<PLIC>,
+    lines: BTreeMap<usize, IrqLine>,
+    /// claims returning 0
+    spurious: usize,
+    /// claims on lines without handler
+    unhandled: usize,
+}
+
+lazy_static! {
+    static ref IRQ_CONTROLLER: UPIntrFreeCell<IrqController> = unsafe {
+        UPIntrFreeCell::new(IrqController {
+            plic: None,
+            lines: BTreeMap::new(),
+            spurious: 0,
+            unhandled: 0,
+        })
+    };
+}
+
+/// Take over the PLIC at `base_addr`: supervisor accepts every priority > `threshold`,
+/// machine mode is masked below 1.
+pub fn init(base_addr: usize, threshold: u32) {
+    let mut plic = unsafe { PLIC::new(base_addr) };
+    plic.set_threshold(HART_ID, TARGET, threshold);
+    plic.set_threshold(HART_ID, IntrTargetPriority::Machine, 1);
+    IRQ_CONTROLLER.exclusive_access().plic = Some(plic);
+}
+
+/// Register `handler` for `irq` and enable the line. A shared line keeps the
+/// highest priority asked for.
+pub fn register_irq(irq: usize, priority: u32, handler: IrqHandler) {
+    let mut ctrl = IRQ_CONTROLLER.exclusive_access();
+    let line = ctrl.lines.entry(irq).or_insert(IrqLine {
+        priority: 0,
+        handlers: Vec::new(),
+    });
+    line.priority = line.priority.max(priority);
+    line.handlers.push(handler);
+    let priority = line.priority;
+
+    let plic = ctrl.plic.as_mut().expect("irq controller not initialized");
+    plic.set_priority(irq, priority);
+    plic.enable(HART_ID, TARGET, irq);
+}
+
+/// Claim, dispatch and complete one external interrupt.
+pub fn handle_irq() {
+    let (irq, handlers) = IRQ_CONTROLLER.exclusive_session(|ctrl| {
+        let irq = ctrl.plic.as_mut().unwrap().claim(HART_ID, TARGET);
+        if irq == 0 {
+            ctrl.spurious += 1;
+            return (irq, Vec::new());
+        }
+        let handlers = match ctrl.lines.get(&(irq as usize)) {
+            Some(line) => line.handlers.clone(),
+            None => {
+                ctrl.unhandled += 1;
+                Vec::new()
+            }
+        };
+        (irq, handlers)
+    });
+
+    if irq == 0 {
+        log::debug!("spurious external interrupt");
+        return;
+    }
+    if handlers.is_empty() {
+        log::warn!("unhandled IRQ {}", irq);
+    }
+    // handlers may wake tasks and take other locks, run them unborrowed
+    for handler in handlers {
+        handler();
+    }
+    IRQ_CONTROLLER
+        .exclusive_access()
+        .plic
+        .as_mut()
+        .unwrap()
+        .complete(HART_ID, TARGET, irq);
+}
+
+/// (spurious, unhandled) interrupt counts
+#[allow(unused)]
+pub fn irq_stats() -> (usize, usize) {
+    IRQ_CONTROLLER.exclusive_session(|ctrl| (ctrl.spurious, ctrl.unhandled))
+}
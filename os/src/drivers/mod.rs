@@ -2,6 +2,7 @@ pub mod block;
 pub mod bus;
 pub mod chardev;
 pub mod input;
+pub mod irq;
 pub mod net;
 pub mod plic;
 
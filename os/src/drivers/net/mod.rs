@@ -1,48 +1,238 @@
 use core::any::Any;
+use core::mem::size_of;
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
-use virtio_drivers::{VirtIOHeader, VirtIONet};
 
-use crate::sync::UPIntrFreeCell;
+use crate::{
+    config::PAGE_SIZE,
+    mm::FrameTracker,
+    net::{
+        pcap,
+        rxbuf::{self, RxBuffer},
+    },
+    sync::UPIntrFreeCell,
+};
 
-use super::bus::virtio::VirtioHal;
+use super::bus::{
+    dma::{barrier, DmaBuffer},
+    mmio::{DeviceType, MmioTransport},
+    virtio::VirtioError,
+    virtqueue::VirtQueue,
+};
 
 const VIRTIO8: usize = 0x10004000;
 
+const QUEUE_SIZE: u16 = 16;
+const QUEUE_RECEIVE: u16 = 0;
+const QUEUE_TRANSMIT: u16 = 1;
+/// pooled frames kept posted on the receive queue
+const RX_IN_FLIGHT: usize = 8;
+
+/// device has given mac address in config space
+const NET_F_MAC: u64 = 1 << 5;
+
 lazy_static! {
     pub static ref NET_DEVICE: Arc<dyn NetDevice> = Arc::new(VirtIONetWrapper::new());
 }
 
 pub trait NetDevice: Send + Sync + Any {
     fn transmit(&self, data: &[u8]);
-    fn receive(&self, data: &mut [u8]) -> usize;
+    /// Wait for a frame, `None` if there is no buffer to receive it into.
+    fn receive(&self) -> Option<RxBuffer>;
+    /// Frame if one has arrived, without waiting.
+    fn try_receive(&self) -> Option<RxBuffer>;
+    /// Post pooled frames on the receive queue, up to what it keeps in flight.
+    fn refill(&self);
+}
+
+/// legacy virtio_net_hdr without mergeable rx buffers, all zero: no offloads
+#[repr(C)]
+#[derive(Default)]
+struct NetHeader {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+}
+
+impl NetHeader {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, size_of::<Self>()) }
+    }
+}
+
+/// virtio-net device over the shared mmio transport & virtqueue, polling only
+pub struct VirtIONet {
+    transport: MmioTransport,
+    recv_queue: VirtQueue,
+    send_queue: VirtQueue,
+    /// header + frame, sent frames are bounced through it
+    send_buf: DmaBuffer,
+    /// pooled frames on the receive queue, by token; the device writes header + frame in
+    recv_frames: Vec<Option<FrameTracker>>,
+    recv_posted: usize,
+    mac: [u8; 6],
 }
 
-pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet<'static, VirtioHal>>);
+impl VirtIONet {
+    pub unsafe fn new(base: usize) -> Result<Self, VirtioError> {
+        let mut transport = MmioTransport::new(base)?;
+        if transport.device_type() != DeviceType::Network {
+            return Err(VirtioError::WrongDevice);
+        }
+        transport.begin_init(|features| features & NET_F_MAC);
+        let mac = transport.read_config::<[u8; 6]>(0);
+        let queues = VirtQueue::new(&mut transport, QUEUE_RECEIVE, QUEUE_SIZE).and_then(|rx| {
+            VirtQueue::new(&mut transport, QUEUE_TRANSMIT, QUEUE_SIZE).map(|tx| (rx, tx))
+        });
+        let (recv_queue, send_queue) = match queues {
+            Ok(v) => v,
+            Err(e) => {
+                transport.fail();
+                return Err(e);
+            }
+        };
+        let Some(send_buf) = DmaBuffer::new(PAGE_SIZE) else {
+            transport.fail();
+            return Err(VirtioError::DmaError);
+        };
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            recv_queue,
+            send_queue,
+            send_buf,
+            recv_frames: (0..QUEUE_SIZE).map(|_| None).collect(),
+            recv_posted: 0,
+            mac,
+        })
+    }
+
+    #[allow(unused)]
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<(), VirtioError> {
+        let len = size_of::<NetHeader>() + data.len();
+        if len > self.send_buf.len() {
+            return Err(VirtioError::BufferTooSmall);
+        }
+        let buf = self.send_buf.as_mut_slice();
+        let (header, frame) = buf.split_at_mut(size_of::<NetHeader>());
+        header.copy_from_slice(NetHeader::default().as_bytes());
+        frame[..data.len()].copy_from_slice(data);
+        self.send_buf.sync_for_device();
+        self.send_queue.add_notify_wait_pop(
+            &mut self.transport,
+            &[&self.send_buf.as_slice()[..len]],
+            &[],
+        )?;
+        Ok(())
+    }
+
+    /// Post pooled frames until `RX_IN_FLIGHT` are on the receive queue, or the pool and
+    /// the frame allocator are both out.
+    pub fn refill(&mut self) -> Result<(), VirtioError> {
+        let posted = self.recv_posted;
+        while self.recv_posted < RX_IN_FLIGHT {
+            let Some(frame) = rxbuf::alloc_frame() else {
+                break;
+            };
+            let token = self.recv_queue.add(&[], &[frame.ppn.get_bytes_array()])?;
+            self.recv_frames[token as usize] = Some(frame);
+            self.recv_posted += 1;
+        }
+        if self.recv_posted > posted {
+            self.recv_queue.notify(&mut self.transport);
+        }
+        Ok(())
+    }
+
+    /// Block until a frame arrives, `None` if nothing could be posted to receive it.
+    pub fn recv(&mut self) -> Result<Option<(FrameTracker, usize)>, VirtioError> {
+        loop {
+            if let Some(frame) = self.try_recv()? {
+                return Ok(Some(frame));
+            }
+            if self.recv_posted == 0 {
+                return Ok(None);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Frame the device wrote a packet into, if one has arrived, and the packet's length
+    /// after the header.
+    pub fn try_recv(&mut self) -> Result<Option<(FrameTracker, usize)>, VirtioError> {
+        self.refill()?;
+        if !self.recv_queue.can_pop() {
+            return Ok(None);
+        }
+        let (token, len) = self.recv_queue.pop_used()?;
+        let frame = self.recv_frames[token as usize]
+            .take()
+            .ok_or(VirtioError::IoError)?;
+        self.recv_posted -= 1;
+        // device wrote the frame, same as DmaBuffer::sync_for_cpu
+        barrier();
+        let len = (len as usize)
+            .saturating_sub(size_of::<NetHeader>())
+            .min(PAGE_SIZE - size_of::<NetHeader>());
+        Ok(Some((frame, len)))
+    }
+}
+
+pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet>);
 
 impl NetDevice for VirtIONetWrapper {
     fn transmit(&self, data: &[u8]) {
+        pcap::capture(data);
         self.0
             .exclusive_access()
             .send(data)
             .expect("can't send data")
     }
 
-    fn receive(&self, data: &mut [u8]) -> usize {
+    fn receive(&self) -> Option<RxBuffer> {
+        let (frame, len) = self.0.exclusive_access().recv().expect("can't recv data")?;
+        Some(Self::wrap(frame, len))
+    }
+
+    fn try_receive(&self) -> Option<RxBuffer> {
+        let (frame, len) = self
+            .0
+            .exclusive_access()
+            .try_recv()
+            .expect("can't recv data")?;
+        Some(Self::wrap(frame, len))
+    }
+
+    fn refill(&self) {
         self.0
             .exclusive_access()
-            .recv(data)
-            .expect("can't recv data")
+            .refill()
+            .expect("can't post rx buffers")
     }
 }
 
 impl VirtIONetWrapper {
     pub fn new() -> Self {
         unsafe {
-            let virtio = VirtIONet::<VirtioHal>::new(&mut *(VIRTIO8 as *mut VirtIOHeader))
-                .expect("can't create net device by virtio");
+            let virtio = VirtIONet::new(VIRTIO8).expect("can't create net device by virtio");
             VirtIONetWrapper(UPIntrFreeCell::new(virtio))
         }
     }
+
+    /// Received frame as a buffer for the stack, built once the device is released: dropping
+    /// it posts the frame again.
+    fn wrap(frame: FrameTracker, len: usize) -> RxBuffer {
+        let header = size_of::<NetHeader>();
+        let buf = RxBuffer::new(frame, header..header + len);
+        pcap::capture(&buf);
+        buf
+    }
 }
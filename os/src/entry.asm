@@ -2,7 +2,7 @@
     .globl _start           # globl一个全局符号, 因此可以被其他目标文件使用
 _start:
     la sp, boot_stack_top
-    call rust_main
+    call rust_main          # a0(hartid), a1(dtb) from SBI are passed through
 
     .section .bss.stack             # 下面这块栈空间放在.bss段中
     .globl boot_stack_lower_bound   # 定义lower_bound的位置
@@ -1,11 +1,30 @@
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::sync::Arc;
 use bitflags::bitflags;
-use easy_fs::{EasyFileSystem, Inode};
+use easy_fs::{DirEntry, EasyFileSystem, EfsError, Inode, InodeReader, InodeWriter, DIRENT_SZ};
 use lazy_static::lazy_static;
 
-use crate::{drivers::BLOCK_DEVICE, sync::UPIntrFreeCell};
+use crate::{
+    cast::DowncastArc,
+    config::BLOCK_CACHE_BLOCKS,
+    drivers::BLOCK_DEVICE,
+    sync::UPIntrFreeCell,
+    syscall::errno::{
+        EDQUOT, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ELOOP, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR,
+        ENOTEMPTY, EXDEV,
+    },
+    timer,
+};
 
-use super::File;
+use super::{
+    lock, mount,
+    perm::{self, permitted, read_only, Cred, MAY_READ, MAY_WRITE},
+    File, FsPath, LockKind, VfsNode,
+};
+
+/// `whence` of `OSInode::seek`
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
 
 pub struct OSInode {
     readable: bool,
@@ -16,6 +35,19 @@ pub struct OSInode {
 pub struct OSInodeInner {
     offset: usize,
     inode: Arc<Inode>,
+    /// flock held through this open file
+    lock: Option<LockKind>,
+}
+
+impl OSInodeInner {
+    fn base(&self, whence: usize) -> Option<usize> {
+        match whence {
+            SEEK_SET => Some(0),
+            SEEK_CUR => Some(self.offset),
+            SEEK_END => Some(self.inode.get_size()),
+            _ => None,
+        }
+    }
 }
 
 impl OSInode {
@@ -23,20 +55,16 @@ impl OSInode {
         Self {
             readable,
             writable,
-            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0, inode }) },
+            inner: unsafe {
+                UPIntrFreeCell::new(OSInodeInner {
+                    offset: 0,
+                    inode,
+                    lock: None,
+                })
+            },
         }
     }
 
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
-        let size = inner.inode.get_size();
-        let mut v = alloc::vec![0u8; size];
-        let len = inner.inode.read_at(inner.offset, v.as_mut_slice());
-        assert_eq!(size, len);
-        inner.offset += len;
-        v
-    }
-
     pub fn is_dir(&self) -> bool {
         self.inner.exclusive_access().inode.is_dir()
     }
@@ -45,6 +73,58 @@ impl OSInode {
         self.inner.exclusive_access().inode.is_file()
     }
 
+    /// Next direntry of a directory from the offset on, and the offset past it the offset is
+    /// moved to; `None` when there's none left. So `seek` to 0 reads it from the start again.
+    /// Slots left free by entries removed are skipped.
+    pub fn next_dirent(&self) -> Option<(DirEntry, usize)> {
+        let mut inner = self.inner.exclusive_access();
+        // seeked into the middle of one, start from the next
+        let mut offset = inner.offset.next_multiple_of(DIRENT_SZ);
+        let mut dirent = DirEntry::new_empty();
+        loop {
+            if inner.inode.read_at(offset, dirent.as_bytes_mut()) < DIRENT_SZ {
+                return None;
+            }
+            offset += DIRENT_SZ;
+            if !dirent.is_empty() {
+                break;
+            }
+        }
+        inner.offset = offset;
+        Some((dirent, inner.offset))
+    }
+
+    /// Move the offset by `whence`, past the end is fine but not before the start.
+    /// Returns the new offset, `None` if `whence` is unknown or it'd be negative.
+    pub fn seek(&self, offset: isize, whence: usize) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        inner.offset = inner.base(whence)?.checked_add_signed(offset)?;
+        Some(inner.offset)
+    }
+
+    /// Offset `whence` stands for, `None` if unknown
+    pub fn whence_offset(&self, whence: usize) -> Option<usize> {
+        self.inner.exclusive_access().base(whence)
+    }
+
+    /// Take or convert to an advisory lock of `kind`, false if others hold a conflicting one
+    pub fn try_lock(&self, kind: LockKind) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        if !lock::try_lock(VfsNode::of(&inner.inode), inner.lock, kind) {
+            return false;
+        }
+        inner.lock = Some(kind);
+        true
+    }
+
+    /// Release the advisory lock if any
+    pub fn unlock(&self) {
+        let mut inner = self.inner.exclusive_access();
+        if let Some(held) = inner.lock.take() {
+            lock::unlock(VfsNode::of(&inner.inode), held);
+        }
+    }
+
     pub fn clone_inner_inode(&self) -> Arc<Inode> {
         self.inner.exclusive_access().inode.clone()
     }
@@ -58,19 +138,60 @@ impl OSInode {
                 UPIntrFreeCell::new(OSInodeInner {
                     offset: inner.offset,
                     inode: inner.inode.clone(),
+                    // an open file of its own, not holding the lock
+                    lock: None,
                 })
             },
         }
     }
 }
 
+// the lock goes with the last fd of the open file, be it closed or its process gone
+impl Drop for OSInode {
+    fn drop(&mut self) {
+        self.unlock();
+    }
+}
+
 lazy_static! {
     pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone(), BLOCK_CACHE_BLOCKS)
+            .expect("no easy-fs on the block device");
+        // file data reaches the disk by fsync, eviction or `sync_all` at shutdown
+        efs.lock().set_write_back(true);
+        efs.lock().set_clock(timer::get_wall_time_ns);
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
 }
 
+/// Write back all the filesystem has cached and make it durable, returns how many blocks were
+/// written
+pub fn sync_all() -> usize {
+    let blocks = EasyFileSystem::sync_all();
+    BLOCK_DEVICE.flush();
+    mount::flush_mounted();
+    blocks
+}
+
+/// Errno of a filesystem error
+pub fn efs_errno(e: EfsError) -> isize {
+    match e {
+        EfsError::NotFound => ENOENT,
+        EfsError::Exists => EEXIST,
+        EfsError::NotDir => ENOTDIR,
+        EfsError::IsDir => EISDIR,
+        EfsError::NotEmpty => ENOTEMPTY,
+        EfsError::NameTooLong => ENAMETOOLONG,
+        EfsError::FileTooLarge => EFBIG,
+        EfsError::NoSpace => ENOSPC,
+        EfsError::Loop => ELOOP,
+        EfsError::Invalid => EINVAL,
+        EfsError::Corrupt => EIO,
+        EfsError::CrossDevice => EXDEV,
+        EfsError::QuotaExceeded => EDQUOT,
+    }
+}
+
 bitflags! {
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
@@ -78,16 +199,24 @@ bitflags! {
         const RDRW = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// reads and writes of the fd return EAGAIN instead of blocking
+        const NONBLOCK = 1 << 11;
+        /// the fd is closed on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
 impl OpenFlags {
+    /// Status flags, of an fd rather than of opening it, F_SETFL may change them
+    pub const STATUS: Self = Self::NONBLOCK;
+
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        let flags = *self - Self::STATUS - Self::CLOEXEC;
+        if flags.is_empty() {
             (true, false)
-        } else if self.contains(OpenFlags::WRONLY) {
+        } else if flags.contains(OpenFlags::WRONLY) {
             (false, true)
         } else {
             (true, true)
@@ -95,64 +224,104 @@ impl OpenFlags {
     }
 }
 
-/// Open file with flags
+/// `file` was closed by process `pid`, record locks of the file go with any fd of it
+pub fn file_closed(pid: usize, file: Arc<dyn File>) {
+    if let Some(file) = file.downcast_arc::<OSInode>() {
+        let node = VfsNode::of(&file.clone_inner_inode());
+        lock::release_record_locks(pid, Some(node));
+    }
+}
+
+/// Open file with flags, as the kernel which may access any file
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
-    open_file_at(&ROOT_INODE, name, flags)
+    open_file_at(&FsPath::root(), name, flags, &Cred::ROOT).ok()
 }
 
-/// Open file relative to base
-pub fn open_file_at(base: &Inode, name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Open file relative to `base` on behalf of `cred`: the file must let it read and write as
+/// `flags` asks, a file created needs its parent writable and is owned by it. -1 if it may
+/// not, the errno of what the filesystem says otherwise.
+pub fn open_file_at(
+    base: &FsPath,
+    name: &str,
+    flags: OpenFlags,
+    cred: &Cred,
+) -> Result<Arc<OSInode>, isize> {
     let (readable, writable) = flags.read_write();
-
-    let (path, fname) = match name.rsplit_once('/') {
-        Some(v) => v,
-        _ => (".", name),
+    let mut want = 0;
+    if readable {
+        want |= MAY_READ;
+    }
+    if writable || flags.contains(OpenFlags::TRUNC) {
+        want |= MAY_WRITE;
+    }
+    // nothing opened for writing on a read-only mount
+    if want & MAY_WRITE != 0 && read_only() {
+        return Err(-1);
+    }
+    let open = |inode: Arc<Inode>| {
+        if !permitted(&inode, cred, want) {
+            return Err(-1);
+        }
+        if flags.contains(OpenFlags::TRUNC) {
+            inode.clear();
+        }
+        Ok(Arc::new(OSInode::new(readable, writable, inode)))
     };
 
-    if flags.contains(OpenFlags::CREATE) {
-        // find parent first
-        match base.find(path) {
-            Some(parent) => match parent.find(fname) {
-                Some(inode) => {
-                    if flags.contains(OpenFlags::TRUNC) {
-                        inode.clear();
-                    }
-                    Some(Arc::new(OSInode::new(readable, writable, inode)))
-                }
-                _ => parent
-                    .create(fname)
-                    .map(|inode| Arc::new(OSInode::new(readable, writable, inode))),
-            },
-            _ => None,
-        }
-    } else {
-        base.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
+    // find parent first
+    let (parent, fname) = base.resolve_parent(name)?;
+    match parent.resolve(fname) {
+        Ok(path) => open(path.inode()),
+        Err(ENOENT) if flags.contains(OpenFlags::CREATE) => {
+            let parent = parent.inode();
+            if read_only() || !permitted(&parent, cred, MAY_WRITE) {
+                return Err(-1);
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+            let inode = parent
+                .create_as(fname, (cred.uid, cred.gid))
+                .map_err(efs_errno)?;
+            Ok(Arc::new(OSInode::new(readable, writable, inode)))
+        }
+        Err(e) => Err(e),
     }
 }
 
+/// (parent path, file name) of `name`
+fn split_parent(name: &str) -> (&str, &str) {
+    name.rsplit_once('/').unwrap_or((".", name))
+}
+
 /// Unlink file relative to base TODO move to fs.rs?
-pub fn unlink_file_at(base: &Inode, name: &str) -> bool {
-    let (path, fname) = match name.rsplit_once('/') {
-        Some(v) => v,
-        _ => (".", name),
-    };
+pub fn unlink_file_at(base: &Arc<Inode>, name: &str) -> Result<(), isize> {
+    let (path, fname) = split_parent(name);
+    base.find(path)
+        .and_then(|parent| parent.unlink(fname))
+        .map_err(efs_errno)
+}
 
-    match base.find(path) {
-        Some(parent) => parent.unlink(fname),
-        _ => false,
-    }
+/// Remove empty directory relative to base
+pub fn rmdir_at(base: &Arc<Inode>, name: &str) -> Result<(), isize> {
+    let (path, fname) = split_parent(name);
+    base.find(path)
+        .and_then(|parent| parent.rmdir(fname))
+        .map_err(efs_errno)
 }
 
-pub fn find_file(path: &str) -> Option<Arc<OSInode>> {
-    assert!(path.starts_with('/'));
-    ROOT_INODE
-        .find(path)
-        .map(|inode| Arc::new(OSInode::new(true, true, inode)))
+/// Rename file relative to bases, both parents must exist
+pub fn rename_file_at(
+    oldbase: &Arc<Inode>,
+    oldname: &str,
+    newbase: &Arc<Inode>,
+    newname: &str,
+) -> Result<(), isize> {
+    let (oldpath, oldfname) = split_parent(oldname);
+    let (newpath, newfname) = split_parent(newname);
+
+    let oldparent = oldbase.find(oldpath).map_err(efs_errno)?;
+    let newparent = newbase.find(newpath).map_err(efs_errno)?;
+    oldparent
+        .rename(oldfname, &newparent, newfname)
+        .map_err(efs_errno)
 }
 
 impl File for OSInode {
@@ -164,53 +333,56 @@ impl File for OSInode {
         self.writable
     }
 
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
+    fn check_write(&self) -> Result<(), isize> {
+        perm::check_write(self.writable)
+    }
+
+    fn read(&self, buf: crate::mm::UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0;
-        for slice in buf.buffers.iter_mut() {
-            let len = inner.inode.read_at(inner.offset, *slice);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            total_read_size += len;
-        }
+        let total_read_size = read_buf(&inner.inode, inner.offset, buf);
+        inner.offset += total_read_size;
         total_read_size
     }
 
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let len = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(len, slice.len());
-            inner.offset += len;
-            total_write_size += len;
-        }
+        let total_write_size = write_buf(&inner.inode, inner.offset, buf);
+        inner.offset += total_write_size;
         total_write_size
     }
-}
 
-pub fn name_of_inode(inode: &Inode, parent: &Inode) -> String {
-    parent
-        .read_dirent(inode.inode_id(), |d| String::from(d.name()))
-        .expect("not exist in .. dir?!")
+    fn read_at(&self, offset: usize, buf: crate::mm::UserBuffer) -> Option<usize> {
+        // the offset isn't touched, no need to hold on to it
+        let inode = self.clone_inner_inode();
+        Some(read_buf(&inode, offset, buf))
+    }
+
+    fn write_at(&self, offset: usize, buf: crate::mm::UserBuffer) -> Option<usize> {
+        let inode = self.clone_inner_inode();
+        Some(write_buf(&inode, offset, buf))
+    }
 }
 
-pub fn name_for_inode(inode: &Inode) -> String {
-    fn inner(inode: &Inode) -> String {
-        if inode.inode_id() == ROOT_INODE.inode_id() {
-            return String::new();
+/// Read `inode` from `offset` to `buf` until it's full or the end of file
+fn read_buf(inode: &Inode, offset: usize, mut buf: crate::mm::UserBuffer) -> usize {
+    let mut reader = InodeReader::new(inode, offset);
+    for chunk in buf.chunks_mut() {
+        if reader.read(chunk) == 0 {
+            break;
         }
-
-        let parent = inode.find("..").expect("parent `..' not exist?!");
-        let name = name_of_inode(inode, &parent);
-        let ancestor_name = inner(&parent);
-        ancestor_name + "/" + &name
     }
+    reader.offset() - offset
+}
 
-    match inner(inode) {
-        s if s.is_empty() => String::from("/"), // special case for ROOT
-        s => s,
+/// Write all of `buf` to `inode` from `offset`, or as much of it as the file can grow to hold
+fn write_buf(inode: &Inode, offset: usize, buf: crate::mm::UserBuffer) -> usize {
+    // the buffer is cut at its page boundaries, which needn't be block boundaries of the file
+    let mut writer = InodeWriter::new(inode, offset);
+    for chunk in buf.chunks() {
+        if writer.write(chunk).is_err() {
+            break;
+        }
     }
+    let _ = writer.flush();
+    writer.offset() - offset
 }
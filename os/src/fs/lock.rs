@@ -0,0 +1,217 @@
+//! Advisory locks, keyed by inode. Nothing but other lockers is kept out of a locked file.
+//!
+//! - whole-file locks of flock, held by an open file
+//! - byte-range record locks of fcntl, held by a process
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+
+use crate::sync::UPIntrFreeCell;
+
+use super::VfsNode;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// Holders of the locks on one inode
+#[derive(Default)]
+struct FileLock {
+    shared: usize,
+    exclusive: bool,
+}
+
+lazy_static! {
+    /// inode -> holders, dropped once there's none
+    static ref FILE_LOCKS: UPIntrFreeCell<BTreeMap<VfsNode, FileLock>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// inode -> record locks, sorted by start, a process' own ones neither overlap nor
+    /// touch if of the same kind
+    static ref RECORD_LOCKS: UPIntrFreeCell<BTreeMap<VfsNode, Vec<RecordLock>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// pid -> inode and the lock it's blocked on, the waits-for graph
+    static ref RECORD_WAITERS: UPIntrFreeCell<BTreeMap<usize, (VfsNode, RecordLock)>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+/// Take `kind` on `node` in place of `held`, what the caller holds there already.
+/// Returns false if others hold a conflicting one, `held` is kept then.
+pub fn try_lock(node: VfsNode, held: Option<LockKind>, kind: LockKind) -> bool {
+    let mut locks = FILE_LOCKS.exclusive_access();
+    let lock = locks.entry(node).or_default();
+    // count others only
+    let shared = lock.shared - (held == Some(LockKind::Shared)) as usize;
+    let exclusive = lock.exclusive && held != Some(LockKind::Exclusive);
+    let free = match kind {
+        LockKind::Shared => !exclusive,
+        LockKind::Exclusive => !exclusive && shared == 0,
+    };
+    if free {
+        lock.shared = shared + (kind == LockKind::Shared) as usize;
+        lock.exclusive = kind == LockKind::Exclusive;
+    }
+    free
+}
+
+/// Release `held` on `node`
+pub fn unlock(node: VfsNode, held: LockKind) {
+    let mut locks = FILE_LOCKS.exclusive_access();
+    let lock = locks.get_mut(&node).expect("unlock inode not locked");
+    match held {
+        LockKind::Shared => lock.shared -= 1,
+        LockKind::Exclusive => lock.exclusive = false,
+    }
+    if lock.shared == 0 && !lock.exclusive {
+        locks.remove(&node);
+    }
+}
+
+/// Bytes [start, end) of a file locked by process `pid`, `end` is `usize::MAX` for up to
+/// wherever the file ends
+#[derive(Clone, Copy, Debug)]
+pub struct RecordLock {
+    pub pid: usize,
+    pub start: usize,
+    pub end: usize,
+    pub kind: LockKind,
+}
+
+impl RecordLock {
+    /// if it keeps `other` out
+    fn conflicts(&self, other: &RecordLock) -> bool {
+        self.pid != other.pid
+            && self.start < other.end
+            && other.start < self.end
+            && (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+    }
+}
+
+/// A lock of others on `node` keeping `lock` out
+pub fn record_conflict(node: VfsNode, lock: &RecordLock) -> Option<RecordLock> {
+    RECORD_LOCKS
+        .exclusive_access()
+        .get(&node)?
+        .iter()
+        .find(|v| v.conflicts(lock))
+        .copied()
+}
+
+/// Lock [start, end) for `pid`, or release what it holds there if `kind` is `None`. Ranges
+/// held already are split or merged as they overlap. Returns false if others hold a
+/// conflicting lock.
+pub fn set_record_lock(
+    node: VfsNode,
+    pid: usize,
+    start: usize,
+    end: usize,
+    kind: Option<LockKind>,
+) -> bool {
+    let mut all = RECORD_LOCKS.exclusive_access();
+    let locks = all.entry(node).or_default();
+    let (mut start, mut end) = (start, end);
+    if let Some(kind) = kind {
+        let lock = RecordLock {
+            pid,
+            start,
+            end,
+            kind,
+        };
+        if locks.iter().any(|v| v.conflicts(&lock)) {
+            return false;
+        }
+    }
+
+    let mut kept = Vec::with_capacity(locks.len() + 2);
+    for v in locks.drain(..) {
+        if v.pid != pid || v.end < start || end < v.start {
+            kept.push(v);
+            continue;
+        }
+        // touching or overlapping one of the same kind joins in
+        if Some(v.kind) == kind {
+            start = start.min(v.start);
+            end = end.max(v.end);
+            continue;
+        }
+        if v.end == start || end == v.start {
+            kept.push(v);
+            continue;
+        }
+        // what's left of it either side
+        if v.start < start {
+            kept.push(RecordLock { end: start, ..v });
+        }
+        if end < v.end {
+            kept.push(RecordLock { start: end, ..v });
+        }
+    }
+    if let Some(kind) = kind {
+        kept.push(RecordLock {
+            pid,
+            start,
+            end,
+            kind,
+        });
+    }
+    kept.sort_by_key(|v| v.start);
+    *locks = kept;
+    if locks.is_empty() {
+        all.remove(&node);
+    }
+    true
+}
+
+/// Block `lock.pid` on `lock`, unless that closes a cycle of processes waiting for each
+/// other, then returns false
+pub fn wait_record_lock(node: VfsNode, lock: RecordLock) -> bool {
+    let mut waiters = RECORD_WAITERS.exclusive_access();
+    let all = RECORD_LOCKS.exclusive_access();
+    let holders_of = |node: VfsNode, lock: &RecordLock| -> Vec<usize> {
+        all.get(&node)
+            .map(|locks| {
+                locks
+                    .iter()
+                    .filter(|v| v.conflicts(lock))
+                    .map(|v| v.pid)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    // does anyone we'd wait for wait for us, in the end?
+    let mut visited = BTreeSet::new();
+    let mut stack = holders_of(node, &lock);
+    while let Some(pid) = stack.pop() {
+        if pid == lock.pid {
+            return false;
+        }
+        if !visited.insert(pid) {
+            continue;
+        }
+        if let Some((id, waiting)) = waiters.get(&pid) {
+            stack.extend(holders_of(*id, waiting));
+        }
+    }
+    waiters.insert(lock.pid, (node, lock));
+    true
+}
+
+/// `pid` waits no more
+pub fn unwait_record_lock(pid: usize) {
+    RECORD_WAITERS.exclusive_access().remove(&pid);
+}
+
+/// Release all record locks of `pid`, on `node` only if given
+pub fn release_record_locks(pid: usize, node: Option<VfsNode>) {
+    let mut all = RECORD_LOCKS.exclusive_access();
+    all.retain(|id, locks| {
+        if node.map_or(true, |v| v == *id) {
+            locks.retain(|v| v.pid != pid);
+        }
+        !locks.is_empty()
+    });
+}
@@ -3,21 +3,70 @@ use core::any::Any;
 use crate::{cast::DowncastArc, mm::UserBuffer};
 
 mod inode;
+mod lock;
+mod mount;
+mod path;
+mod perm;
 mod pipe;
+mod poll;
 mod stdio;
+mod tty;
 pub use inode::*;
+pub use lock::{
+    record_conflict, release_record_locks, set_record_lock, unwait_record_lock, wait_record_lock,
+    LockKind, RecordLock,
+};
+pub use mount::{dev_of, mount, mounted_on, umount, VfsNode};
+pub use path::FsPath;
+pub use perm::{check_write, permitted, set_read_only, Cred, MAY_WRITE};
 pub use pipe::*;
+pub use poll::{notify_poll, wait_poll};
 pub use stdio::{Stdin, Stdout};
+pub use tty::{Tty, EOT, TTYS};
 
 pub trait File: Any + Send + Sync {
     /// If readable
     fn readable(&self) -> bool;
     /// If writable
     fn writable(&self) -> bool;
+    /// If it may be written now, the error to return otherwise. Anything that changes the data
+    /// of a file asks this first.
+    fn check_write(&self) -> Result<(), isize> {
+        if self.writable() {
+            Ok(())
+        } else {
+            Err(-1)
+        }
+    }
     /// Read file to `UserBuffer`
     fn read(&self, buf: UserBuffer) -> usize;
     /// Write `UserBuffer` to file
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Read like `read` but return what's there now, EAGAIN (as `usize`) if nothing is
+    fn read_nonblocking(&self, buf: UserBuffer) -> usize {
+        self.read(buf)
+    }
+    /// Write like `write` but only what fits now, EAGAIN (as `usize`) if nothing does
+    fn write_nonblocking(&self, buf: UserBuffer) -> usize {
+        self.write(buf)
+    }
+    /// Read file at `offset` to `UserBuffer`, its own offset stays. `None` if it can't seek
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write `UserBuffer` to file at `offset`, its own offset stays. `None` if it can't seek
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// If a read wouldn't block now. Files that may turn ready later have to `notify_poll`
+    /// once they do.
+    fn poll_read_ready(&self) -> bool {
+        true
+    }
+    /// If a write wouldn't block now
+    fn poll_write_ready(&self) -> bool {
+        true
+    }
 }
 
 impl DowncastArc for dyn File {
@@ -0,0 +1,149 @@
+//! Filesystems of other disks mounted on dirs. The dir mounted on is covered by the root of
+//! the one mounted: paths walked to it go on from that root instead, and `..` of the root goes
+//! back past the dir covered.
+
+use alloc::{sync::Arc, vec::Vec};
+use easy_fs::{BlockDevice, EasyFileSystem, Inode};
+use lazy_static::lazy_static;
+
+use crate::{
+    board,
+    config::BLOCK_CACHE_BLOCKS,
+    sync::UPIntrFreeCell,
+    syscall::errno::{EBUSY, EINVAL, ENOENT, ENOTDIR},
+    timer,
+};
+
+/// An inode of whichever filesystem it's on, inode ids are only unique within one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VfsNode {
+    pub fs: usize,
+    pub ino: u32,
+}
+
+impl VfsNode {
+    pub fn of(inode: &Inode) -> Self {
+        Self {
+            fs: inode.fs_id(),
+            ino: inode.inode_id(),
+        }
+    }
+}
+
+struct Mount {
+    /// dir covered
+    on: Arc<Inode>,
+    root: Arc<Inode>,
+    /// index in `DISKS`
+    disk: usize,
+}
+
+lazy_static! {
+    static ref DISKS: Vec<Arc<dyn BlockDevice>> = board::disks();
+    static ref MOUNTS: UPIntrFreeCell<Vec<Mount>> = unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+/// Index in `DISKS` of the disk `source` names, /dev/vdb is the first
+fn disk_of(source: &str) -> Option<usize> {
+    let &[letter] = source.strip_prefix("/dev/vd")?.as_bytes() else {
+        return None;
+    };
+    let disk = letter.checked_sub(b'b')? as usize;
+    (disk < DISKS.len()).then_some(disk)
+}
+
+/// Mount the easy-fs on disk `source` on dir `on`. EBUSY if either is mounted already, EINVAL
+/// if there's no easy-fs on the disk.
+pub fn mount(source: &str, on: &Arc<Inode>) -> Result<(), isize> {
+    let disk = disk_of(source).ok_or(ENOENT)?;
+    if !on.is_dir() {
+        return Err(ENOTDIR);
+    }
+    // one filesystem on a dir at most, and the root of one is none to mount on
+    let node = VfsNode::of(on);
+    let busy = |mounts: &Vec<Mount>| {
+        node == VfsNode::of(&super::ROOT_INODE)
+            || mounts.iter().any(|m| {
+                m.disk == disk || VfsNode::of(&m.on) == node || VfsNode::of(&m.root) == node
+            })
+    };
+    if busy(&MOUNTS.exclusive_access()) {
+        return Err(EBUSY);
+    }
+    let efs = EasyFileSystem::open(DISKS[disk].clone(), BLOCK_CACHE_BLOCKS).map_err(|_| EINVAL)?;
+    efs.lock().set_write_back(true);
+    efs.lock().set_clock(timer::get_wall_time_ns);
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+    let mut mounts = MOUNTS.exclusive_access();
+    // mounted by someone else while reading it
+    if busy(&mounts) {
+        return Err(EBUSY);
+    }
+    mounts.push(Mount {
+        on: on.clone(),
+        root,
+        disk,
+    });
+    Ok(())
+}
+
+/// Unmount the filesystem of root `root`, once nothing is left open on it as `busy` tells.
+/// EINVAL if it's not one mounted, EBUSY if another is mounted on it.
+pub fn umount(root: &Arc<Inode>, busy: impl FnOnce(usize) -> bool) -> Result<(), isize> {
+    let fs = root.fs_id();
+    let mut mounts = MOUNTS.exclusive_access();
+    let i = mounts
+        .iter()
+        .position(|m| VfsNode::of(&m.root) == VfsNode::of(root))
+        .ok_or(EINVAL)?;
+    if mounts.iter().any(|m| m.on.fs_id() == fs) || busy(fs) {
+        return Err(EBUSY);
+    }
+    let mount = mounts.remove(i);
+    drop(mounts);
+    mount.root.unmount();
+    DISKS[mount.disk].flush();
+    Ok(())
+}
+
+/// Root of the filesystem mounted on `dir`, if any
+pub fn mounted_on(dir: &Inode) -> Option<Arc<Inode>> {
+    let node = VfsNode::of(dir);
+    MOUNTS
+        .exclusive_access()
+        .iter()
+        .find(|m| VfsNode::of(&m.on) == node)
+        .map(|m| m.root.clone())
+}
+
+/// Dir covered by `root` if it's the root of a filesystem mounted
+pub fn covered(root: &Inode) -> Option<Arc<Inode>> {
+    let node = VfsNode::of(root);
+    MOUNTS
+        .exclusive_access()
+        .iter()
+        .find(|m| VfsNode::of(&m.root) == node)
+        .map(|m| m.on.clone())
+}
+
+/// Device number of the filesystem `inode` is on: 0 for root's, the disk mounted from 1 on
+pub fn dev_of(inode: &Inode) -> u64 {
+    let fs = inode.fs_id();
+    MOUNTS
+        .exclusive_access()
+        .iter()
+        .find(|m| m.root.fs_id() == fs)
+        .map_or(0, |m| m.disk as u64 + 1)
+}
+
+/// Make what's written to the disks mounted durable
+pub fn flush_mounted() {
+    let disks: Vec<_> = MOUNTS
+        .exclusive_access()
+        .iter()
+        .map(|m| DISKS[m.disk].clone())
+        .collect();
+    for disk in disks {
+        disk.flush();
+    }
+}
@@ -0,0 +1,143 @@
+//! Paths resolved a name at a time. Every name walked keeps the inode it was found as, so `..`
+//! goes back to the one before instead of to whatever the filesystem says, and where a path
+//! leads is the same list of names however it was spelled: `.`, `..` and symlinks are gone.
+//! A dir mounted on is found as the root mounted there, so `..` of it gets out of the mount.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt;
+
+use easy_fs::{EfsError, Inode};
+
+use crate::syscall::errno::ENOTDIR;
+
+use super::{efs_errno, mount, VfsNode, ROOT_INODE};
+
+/// Symlinks followed at most in resolving one path
+const SYMLOOP_MAX: usize = 8;
+
+/// Absolute path of an inode: the names from root down, each with the inode it leads to
+#[derive(Clone)]
+pub struct FsPath {
+    names: Vec<(String, Arc<Inode>)>,
+}
+
+impl FsPath {
+    pub fn root() -> Self {
+        Self { names: Vec::new() }
+    }
+
+    /// Path of `dir` nothing else is known of, like one opened, by walking its `..` up to root
+    pub fn of_dir(dir: &Arc<Inode>) -> Self {
+        let mut names = Vec::new();
+        let mut inode = dir.clone();
+        loop {
+            // the root of a mount goes by the name of the dir it covers, that of all by none
+            let on = if inode.inode_id() != ROOT_INODE.inode_id() {
+                inode.clone()
+            } else if let Some(on) = mount::covered(&inode) {
+                on
+            } else {
+                break;
+            };
+            let parent = on.parent().expect("parent `..' not exist?!");
+            let name = parent
+                .read_dirent(on.inode_id(), |d| String::from(d.name()))
+                .expect("not exist in .. dir?!");
+            names.push((name, inode));
+            inode = parent;
+        }
+        names.reverse();
+        Self { names }
+    }
+
+    /// Inode it leads to
+    pub fn inode(&self) -> Arc<Inode> {
+        match self.names.last() {
+            Some((_, inode)) => inode.clone(),
+            None => ROOT_INODE.clone(),
+        }
+    }
+
+    /// Where `path` leads from here, symlinks in and at the end of it followed
+    pub fn resolve(&self, path: &str) -> Result<Self, isize> {
+        let mut resolved = self.clone();
+        resolved.walk(path, &mut 0)?;
+        Ok(resolved)
+    }
+
+    /// Dir holding the last name of `path` from here, and that name: it's left to whoever
+    /// creates, removes or looks it up. "." if `path` names no more than a dir.
+    pub fn resolve_parent<'a>(&self, path: &'a str) -> Result<(Self, &'a str), isize> {
+        let trimmed = path.trim_end_matches('/');
+        let (dir, name) = match trimmed.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some(v) => v,
+            None if trimmed.is_empty() => (path, "."),
+            None => (".", trimmed),
+        };
+        let parent = self.resolve(dir)?;
+        if !parent.inode().is_dir() {
+            return Err(ENOTDIR);
+        }
+        Ok((parent, name))
+    }
+
+    /// This path with dir `dir` on it moved to `to`, `None` if it's not on it
+    pub fn moved(&self, dir: VfsNode, to: &FsPath) -> Option<Self> {
+        let i = self
+            .names
+            .iter()
+            .position(|(_, inode)| VfsNode::of(inode) == dir)?;
+        let mut names = to.names.clone();
+        names.extend_from_slice(&self.names[i + 1..]);
+        Some(Self { names })
+    }
+
+    /// Walk `path` on from the end, `follows` counts the symlinks followed so far
+    fn walk(&mut self, path: &str, follows: &mut usize) -> Result<(), isize> {
+        if path.starts_with('/') {
+            self.names.clear();
+        }
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let dir = self.inode();
+            if !dir.is_dir() {
+                return Err(ENOTDIR);
+            }
+            match name {
+                "." => {}
+                // `..` of root is root
+                ".." => {
+                    self.names.pop();
+                }
+                _ => {
+                    let inode = dir.lookup_link(name).map_err(efs_errno)?;
+                    if inode.is_symlink() {
+                        *follows += 1;
+                        if *follows > SYMLOOP_MAX {
+                            return Err(efs_errno(EfsError::Loop));
+                        }
+                        // a relative target goes from the dir holding the link
+                        let target = inode.readlink().map_err(efs_errno)?;
+                        self.walk(&target, follows)?;
+                    } else {
+                        let inode = mount::mounted_on(&inode).unwrap_or(inode);
+                        self.names.push((String::from(name), inode));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.names.is_empty() {
+            return f.write_str("/");
+        }
+        for (name, _) in &self.names {
+            write!(f, "/{}", name)?;
+        }
+        Ok(())
+    }
+}
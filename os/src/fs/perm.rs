@@ -0,0 +1,67 @@
+//! Who a process acts as, and what the mode bits of an inode let it do. uid 0 may do anything.
+//! Changing what's in a file, besides, goes through `check_write`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use easy_fs::Inode;
+
+use crate::syscall::errno::EROFS;
+
+/// bits of `permitted` asks, as in each rwx triple of the mode
+pub const MAY_READ: u16 = 4;
+pub const MAY_WRITE: u16 = 2;
+#[allow(unused)]
+pub const MAY_EXEC: u16 = 1;
+
+/// Credential of a process, inherited on fork
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cred {
+    pub uid: u16,
+    pub gid: u16,
+}
+
+impl Cred {
+    pub const ROOT: Self = Self { uid: 0, gid: 0 };
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+/// If `cred` may access `inode` in all the ways of `want`
+pub fn permitted(inode: &Inode, cred: &Cred, want: u16) -> bool {
+    if cred.is_root() {
+        return true;
+    }
+    let mode = inode.mode();
+    let bits = match inode.owner() {
+        (uid, _) if uid == cred.uid => mode >> 6,
+        (_, gid) if gid == cred.gid => mode >> 3,
+        _ => mode,
+    };
+    bits & want == want
+}
+
+/// root filesystem mounted read-only, by boot arg `ro`
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// If the data of a file may be changed through something opened `writable`, be it by write,
+/// ftruncate, or a shared mapping and writing its pages back. The mode of the inode was
+/// checked when it was opened, that's where `writable` comes from.
+pub fn check_write(writable: bool) -> Result<(), isize> {
+    if read_only() {
+        Err(EROFS)
+    } else if !writable {
+        Err(-1)
+    } else {
+        Ok(())
+    }
+}
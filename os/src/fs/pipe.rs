@@ -1,8 +1,18 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
 
-use crate::{sync::UPIntrFreeCell, task::suspend_current_and_run_next};
+use crate::{
+    config::PAGE_SIZE,
+    mm::UserBuffer,
+    sync::UPIntrFreeCell,
+    syscall::errno::EAGAIN,
+    task::{block_current_and_run_next, current_task, wakeup_tasks, TaskControlBlock},
+};
 
-use super::File;
+use super::{notify_poll, File};
 
 pub struct Pipe {
     readable: bool,
@@ -10,7 +20,10 @@ pub struct Pipe {
     buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>,
 }
 
-const RING_BUFFER_SIZE: usize = 32;
+/// Bytes a pipe buffers unless asked for more
+pub const PIPE_SIZE: usize = PAGE_SIZE;
+/// Most bytes a pipe may be asked to buffer
+pub const PIPE_MAX_SIZE: usize = 16 * PAGE_SIZE;
 
 #[derive(Clone, Copy, PartialEq)]
 enum RingBufferStatus {
@@ -20,45 +33,73 @@ enum RingBufferStatus {
 }
 
 pub struct PipeRingBuffer {
-    arr: [u8; RING_BUFFER_SIZE],
+    arr: Vec<u8>,
     head: usize,
     tail: usize,
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>, // to tell if all write ends been closed
+    /// readers blocked until there's data or no write end is left
+    read_waiters: Vec<Arc<TaskControlBlock>>,
+    /// writers blocked until there's room
+    write_waiters: Vec<Arc<TaskControlBlock>>,
 }
 
 impl PipeRingBuffer {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
-            arr: [0; RING_BUFFER_SIZE],
+            arr: vec![0; capacity],
             head: 0,
             tail: 0,
             status: RingBufferStatus::EMPTY,
             write_end: None,
+            read_waiters: Vec::new(),
+            write_waiters: Vec::new(),
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.arr.len()
+    }
+
     pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
         self.write_end = Some(Arc::downgrade(write_end));
     }
 
-    pub fn read_byte(&mut self) -> u8 {
-        self.status = RingBufferStatus::NORMAL;
-        let c = self.arr[self.head];
-        self.head = (self.head + 1) % RING_BUFFER_SIZE;
-        if self.head == self.tail {
-            self.status = RingBufferStatus::EMPTY;
+    /// Read what's there up to `dst.len()` bytes, how many that is
+    pub fn read_bytes(&mut self, dst: &mut [u8]) -> usize {
+        let n = dst.len().min(self.available_read());
+        if n == 0 {
+            return 0;
         }
-        c
+        // | .. head .. | then wrapped around from 0
+        let first = n.min(self.capacity() - self.head);
+        dst[..first].copy_from_slice(&self.arr[self.head..self.head + first]);
+        dst[first..n].copy_from_slice(&self.arr[..n - first]);
+        self.head = (self.head + n) % self.capacity();
+        self.status = if self.head == self.tail {
+            RingBufferStatus::EMPTY
+        } else {
+            RingBufferStatus::NORMAL
+        };
+        n
     }
 
-    pub fn write_byte(&mut self, byte: u8) {
-        self.status = RingBufferStatus::NORMAL;
-        self.arr[self.tail] = byte;
-        self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
-        if self.head == self.tail {
-            self.status = RingBufferStatus::FULL;
+    /// Write what fits of `src`, how many bytes that is
+    pub fn write_bytes(&mut self, src: &[u8]) -> usize {
+        let n = src.len().min(self.available_write());
+        if n == 0 {
+            return 0;
         }
+        let first = n.min(self.capacity() - self.tail);
+        self.arr[self.tail..self.tail + first].copy_from_slice(&src[..first]);
+        self.arr[..n - first].copy_from_slice(&src[first..n]);
+        self.tail = (self.tail + n) % self.capacity();
+        self.status = if self.head == self.tail {
+            RingBufferStatus::FULL
+        } else {
+            RingBufferStatus::NORMAL
+        };
+        n
     }
 
     pub fn available_read(&self) -> usize {
@@ -73,7 +114,7 @@ impl PipeRingBuffer {
             //  ___________/
             // / tail .. .. |
             else {
-                self.tail + RING_BUFFER_SIZE - self.head
+                self.tail + self.capacity() - self.head
             }
         }
     }
@@ -82,7 +123,7 @@ impl PipeRingBuffer {
         if self.status == RingBufferStatus::FULL {
             0
         } else {
-            RING_BUFFER_SIZE - self.available_read()
+            self.capacity() - self.available_read()
         }
     }
 
@@ -113,88 +154,144 @@ impl Pipe {
     }
 }
 
-impl File for Pipe {
-    fn readable(&self) -> bool {
-        self.readable
-    }
-
-    fn writable(&self) -> bool {
-        self.writable
+impl Pipe {
+    /// Bytes the buffer holds at most
+    pub fn capacity(&self) -> usize {
+        self.buffer.exclusive_access().capacity()
     }
 
-    fn read(&self, buf: crate::mm::UserBuffer) -> usize {
+    /// Read until `buf` is full or all write ends are closed, or only what's there if
+    /// `nonblock`, EAGAIN if that's nothing
+    fn read_buf(&self, mut buf: UserBuffer, nonblock: bool) -> usize {
         assert!(self.readable);
-        let want_to_read = buf.len();
-        let mut buf_iter = buf.into_iter();
         let mut already_read = 0;
-        loop {
-            let mut rb = self.buffer.exclusive_access();
-            // how many bytes allowed to read during this iteration
-            let loop_read = rb.available_read();
-            if loop_read == 0 {
-                // if no more available && all write end closed, that's all we get
-                if rb.all_write_ends_closed() {
-                    return already_read;
-                }
-                // else if write end still alive, we wait for more coming
-                // aka, suspend and run other task(maybe the one holds writer)
-                // to fill ring_buffer, and before that, we must release it
-                // to avoid deadlock (coz task switch will not auto drop it)
-                drop(rb);
-                suspend_current_and_run_next();
-                continue;
-            }
-            // if loop_read bytes can be read
-            for _ in 0..loop_read {
-                // read into buf one-by-one
-                if let Some(byte_ref) = buf_iter.next() {
-                    unsafe {
-                        *byte_ref = rb.read_byte();
+        for chunk in buf.chunks_mut() {
+            let mut pos = 0;
+            while pos < chunk.len() {
+                let mut rb = self.buffer.exclusive_access();
+                if rb.available_read() == 0 {
+                    // if no more available && all write end closed, that's all we get
+                    if rb.all_write_ends_closed() {
+                        return already_read;
                     }
-                    already_read += 1;
-                    // return if reach number we need
-                    if already_read == want_to_read {
-                        return want_to_read;
+                    if nonblock {
+                        return if already_read > 0 {
+                            already_read
+                        } else {
+                            EAGAIN as usize
+                        };
                     }
-                } else {
-                    // I think it's exact same with above return condition ..
-                    return already_read;
+                    // else if write end still alive, we wait for a writer to fill ring_buffer
+                    // or go away, and before that, we must release it to avoid deadlock (coz
+                    // task switch will not auto drop it)
+                    rb.read_waiters.push(current_task().unwrap());
+                    drop(rb);
+                    block_current_and_run_next();
+                    continue;
                 }
+                let n = rb.read_bytes(&mut chunk[pos..]);
+                let writers = core::mem::take(&mut rb.write_waiters);
+                drop(rb);
+                pos += n;
+                already_read += n;
+                // room for writers, blocked or polling
+                wake_all(writers);
+                notify_poll();
             }
         }
+        already_read
     }
 
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
+    /// Write all of `buf`, or only what fits if `nonblock`, EAGAIN if that's nothing
+    fn write_buf(&self, buf: UserBuffer, nonblock: bool) -> usize {
         assert!(self.writable);
-        let want_to_write = buf.len();
-        let mut buf_iter = buf.into_iter();
         let mut already_write = 0;
-        loop {
-            let mut rb = self.buffer.exclusive_access();
-            let loop_write = rb.available_write();
-            if loop_write == 0 {
-                drop(rb);
-                suspend_current_and_run_next();
-                continue;
-            }
-            for _ in 0..loop_write {
-                if let Some(byte_ref) = buf_iter.next() {
-                    rb.write_byte(unsafe { *byte_ref });
-                    already_write += 1;
-                    if already_write == want_to_write {
-                        return want_to_write;
+        for chunk in buf.chunks() {
+            let mut pos = 0;
+            while pos < chunk.len() {
+                let mut rb = self.buffer.exclusive_access();
+                if rb.available_write() == 0 {
+                    if nonblock {
+                        return if already_write > 0 {
+                            already_write
+                        } else {
+                            EAGAIN as usize
+                        };
                     }
-                } else {
-                    return already_write;
+                    rb.write_waiters.push(current_task().unwrap());
+                    drop(rb);
+                    block_current_and_run_next();
+                    continue;
                 }
+                let n = rb.write_bytes(&chunk[pos..]);
+                let readers = core::mem::take(&mut rb.read_waiters);
+                drop(rb);
+                pos += n;
+                already_write += n;
+                wake_all(readers);
+                notify_poll();
             }
         }
+        already_write
+    }
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.read_buf(buf, false)
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.write_buf(buf, false)
+    }
+
+    fn read_nonblocking(&self, buf: UserBuffer) -> usize {
+        self.read_buf(buf, true)
+    }
+
+    fn write_nonblocking(&self, buf: UserBuffer) -> usize {
+        self.write_buf(buf, true)
+    }
+
+    fn poll_read_ready(&self) -> bool {
+        let rb = self.buffer.exclusive_access();
+        rb.available_read() > 0 || rb.all_write_ends_closed()
+    }
+
+    fn poll_write_ready(&self) -> bool {
+        self.buffer.exclusive_access().available_write() > 0
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // readers see the end of file
+        if self.writable {
+            let readers = core::mem::take(&mut self.buffer.exclusive_access().read_waiters);
+            wake_all(readers);
+            notify_poll();
+        }
+    }
+}
+
+/// Make all of `waiters` ready, they look at the buffer again
+fn wake_all(waiters: Vec<Arc<TaskControlBlock>>) {
+    if !waiters.is_empty() {
+        wakeup_tasks(waiters);
     }
 }
 
-/// Return (read_end, write_end)
-pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe { UPIntrFreeCell::new(PipeRingBuffer::new()) });
+/// Return (read_end, write_end), buffering `capacity` bytes
+pub fn make_pipe(capacity: usize) -> (Arc<Pipe>, Arc<Pipe>) {
+    let buffer = Arc::new(unsafe { UPIntrFreeCell::new(PipeRingBuffer::new(capacity)) });
     let r = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let w = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
     buffer.exclusive_access().set_write_end(&w);
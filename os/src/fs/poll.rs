@@ -0,0 +1,40 @@
+//! Tasks blocked in `ppoll`. Whatever may make an fd ready wakes them all by `notify_poll`,
+//! they look at their fds again then.
+
+use alloc::{sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+
+use crate::{
+    sync::UPIntrFreeCell,
+    task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock},
+    timer,
+};
+
+lazy_static! {
+    static ref POLL_WAITERS: UPIntrFreeCell<Vec<Arc<TaskControlBlock>>> =
+        unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+/// Block the current task until `notify_poll`, a signal, or `expire_ms` if any
+pub fn wait_poll(expire_ms: Option<usize>) {
+    let task = current_task().unwrap();
+    POLL_WAITERS.exclusive_access().push(task.clone());
+    // a sleeper to the timer even without timeout, so signals and wakers take it out as usual
+    timer::add_timer(expire_ms.unwrap_or(usize::MAX), task.clone());
+    block_current_and_run_next();
+    // still listed if the timer or a signal woke us
+    POLL_WAITERS
+        .exclusive_access()
+        .retain(|t| !Arc::ptr_eq(t, &task));
+}
+
+/// Wake all tasks in `wait_poll`
+pub fn notify_poll() {
+    let waiters = core::mem::take(&mut *POLL_WAITERS.exclusive_access());
+    for task in waiters {
+        // ours unless the timer or a signal got there first
+        if timer::cancel_timer(&task) {
+            wakeup_task(task);
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use crate::drivers::{CharDevice, UART};
+use alloc::sync::Arc;
 
-use super::File;
+use crate::{
+    syscall::errno::{EINTR, EIO},
+    task::{current_process, current_task, pgid2processes, raise_signal, SignalFlags},
+};
 
-///Standard input
-pub struct Stdin;
-///Standard output
-pub struct Stdout;
+use super::{File, Tty, EOT};
+
+///Standard input, reading from a terminal
+pub struct Stdin(pub Arc<Tty>);
+///Standard output, writing to a terminal
+pub struct Stdout(pub Arc<Tty>);
 
 impl File for Stdin {
     fn readable(&self) -> bool {
@@ -16,18 +21,40 @@ impl File for Stdin {
         false
     }
 
+    /// A byte at a time, none once Ctrl-D is typed. Only the foreground process group may
+    /// read, a background one is stopped by SIGTTIN until the shell hands the terminal over.
     fn read(&self, mut user_buf: crate::mm::UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        let ch = UART.read();
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
+        let pgid = current_process().inner_exclusive_access().pgid;
+        if pgid != self.0.foreground() {
+            let task = current_task().unwrap();
+            let stops = task
+                .inner_exclusive_access()
+                .signal_processor
+                .is_deliverable(SignalFlags::SIGTTIN);
+            // SIGTTIN ignored or blocked
+            if !stops {
+                return EIO as usize;
+            }
+            for process in pgid2processes(pgid) {
+                raise_signal(&process, SignalFlags::SIGTTIN);
+            }
+            // stopped, or it went to a handler: to be read again
+            return EINTR as usize;
+        }
+        match self.0.getchar() {
+            EOT => 0,
+            ch => user_buf.read(&[ch]),
         }
-        1
     }
 
     fn write(&self, _user_buf: crate::mm::UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+
+    /// Input is there, for background jobs too: reading is what stops them
+    fn poll_read_ready(&self) -> bool {
+        self.0.has_input()
+    }
 }
 
 impl File for Stdout {
@@ -44,8 +71,8 @@ impl File for Stdout {
     }
 
     fn write(&self, user_buf: crate::mm::UserBuffer) -> usize {
-        for buf in &user_buf.buffers {
-            print!("{}", core::str::from_utf8(*buf).unwrap());
+        for chunk in user_buf.chunks() {
+            self.0.write(chunk);
         }
         user_buf.len()
     }
@@ -0,0 +1,57 @@
+//! Terminals, one per console the board registers. Init runs a shell on each of them; the
+//! processes started there have it as their controlling terminal, and only its foreground
+//! process group may read from it.
+
+use alloc::{sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+
+use crate::{board, drivers::CharDevice, sync::UPIntrFreeCell};
+
+/// Ctrl-D, the end of input. The terminal has no raw mode, so it always is.
+pub const EOT: u8 = 0x04;
+
+pub struct Tty {
+    device: Arc<dyn CharDevice + Send + Sync>,
+    /// pgid of the process group allowed to read
+    foreground: UPIntrFreeCell<usize>,
+}
+
+lazy_static! {
+    pub static ref TTYS: Vec<Arc<Tty>> = board::consoles()
+        .into_iter()
+        .map(|device| Arc::new(Tty::new(device)))
+        .collect();
+}
+
+impl Tty {
+    fn new(device: Arc<dyn CharDevice + Send + Sync>) -> Self {
+        Self {
+            device,
+            foreground: unsafe { UPIntrFreeCell::new(0) },
+        }
+    }
+
+    pub fn foreground(&self) -> usize {
+        *self.foreground.exclusive_access()
+    }
+
+    pub fn set_foreground(&self, pgid: usize) {
+        *self.foreground.exclusive_access() = pgid;
+    }
+
+    /// Block until a byte arrives.
+    pub fn getchar(&self) -> u8 {
+        self.device.read()
+    }
+
+    /// If `getchar` would return at once
+    pub fn has_input(&self) -> bool {
+        self.device.has_input()
+    }
+
+    pub fn write(&self, bytes: &[u8]) {
+        for &b in bytes {
+            self.device.write(b);
+        }
+    }
+}
@@ -6,6 +6,7 @@ use crate::sbi::shutdown;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    crate::console::emergency_flush();
     if let Some(loc) = info.location() {
         log::error!(
             "Panicked at {}:{} {}",
@@ -16,6 +17,9 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         log::error!("Panicked: {}", info.message());
     }
+    if let Some(task) = crate::task::try_current_task() {
+        log::error!("in thread [{}]", task.label());
+    }
     backtrace();
     shutdown(true)
 }
@@ -8,7 +8,7 @@
 extern crate alloc;
 extern crate bitflags;
 
-#[path = "boards/qemu.rs"]
+#[path = "boards/mod.rs"]
 mod board;
 mod cast;
 mod config;
@@ -29,7 +29,6 @@ mod trace;
 mod trap;
 
 use core::arch::global_asm;
-use drivers::{CharDevice, KEYBOARD_DEVICE, UART};
 use lazy_static::lazy_static;
 use sync::UPIntrFreeCell;
 global_asm!(include_str!("entry.asm"));
@@ -40,21 +39,23 @@ lazy_static! {
 }
 
 #[no_mangle]
-pub fn rust_main() -> ! {
+/// `a0` hart id, `a1` device tree blob, as passed by the SBI
+pub fn rust_main(_hartid: usize, dtb: usize) -> ! {
     clear_bss();
 
-    mm::init();
-    UART.init();
-    println!("KERN: init keyboard");
-    let _keyboard = KEYBOARD_DEVICE.clone();
+    mm::init(dtb);
     println!("KERN: init trap");
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
 
     board::device_init();
+    if board::HAS_NET {
+        net::init();
+    }
+    mm::init_swap();
 
-    task::add_initproc();
+    task::add_initproc(mm::bootargs(dtb));
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
 
     logging::init();
@@ -0,0 +1,153 @@
+//! Just enough of a flattened device tree reader to find the memory firmware keeps for itself,
+//! and the kernel command line.
+//! Ref: Devicetree Specification v0.4, chapter 5 "Flattened Devicetree (DTB) Format"
+//!
+//! S-mode can't read the PMP CSRs, SBI implementations (OpenSBI >= 1.0) instead describe the
+//! regions they protect as children of `/reserved-memory`, so that's what we honor.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::ops::Range;
+
+use crate::config::MEMORY_END;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+fn be32(addr: usize) -> u32 {
+    u32::from_be(unsafe { (addr as *const u32).read_volatile() })
+}
+
+fn be64(addr: usize) -> u64 {
+    (be32(addr) as u64) << 32 | be32(addr + 4) as u64
+}
+
+fn read_cells(addr: usize, cells: usize) -> usize {
+    (0..cells).fold(0, |acc, i| acc << 32 | be32(addr + i * 4) as usize)
+}
+
+fn cstr(addr: usize) -> &'static [u8] {
+    let mut len = 0;
+    while unsafe { *((addr + len) as *const u8) } != 0 {
+        len += 1;
+    }
+    unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
+
+fn align4(addr: usize) -> usize {
+    (addr + 3) & !3
+}
+
+fn valid(dtb: usize) -> bool {
+    dtb != 0 && dtb % 8 == 0 && be32(dtb) == FDT_MAGIC
+}
+
+/// Call `f(top, depth, name, value, len)` for every property in the structure block. Root node
+/// is at depth 1, `top` is the name of the depth 2 node the property sits under.
+fn walk_props(dtb: usize, mut f: impl FnMut(&[u8], usize, &[u8], usize, usize)) {
+    let off_struct = be32(dtb + 8) as usize;
+    let off_strings = be32(dtb + 12) as usize;
+    let mut top: &[u8] = &[];
+    let mut depth = 0;
+    let mut p = dtb + off_struct;
+    loop {
+        let token = be32(p);
+        p += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(p);
+                p = align4(p + name.len() + 1);
+                depth += 1;
+                if depth == 2 {
+                    top = name;
+                }
+            }
+            FDT_END_NODE => {
+                if depth == 2 {
+                    top = &[];
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(p) as usize;
+                let name = cstr(dtb + off_strings + be32(p + 4) as usize);
+                let value = p + 8;
+                p = align4(value + len);
+                f(top, depth, name, value, len);
+            }
+            FDT_NOP => {}
+            // FDT_END, or garbage
+            _ => break,
+        }
+    }
+}
+
+/// Physical byte ranges not to be touched: the dtb blob itself, the memory reservation block
+/// and `reg` of every `/reserved-memory` child. `None` if there is no valid dtb at `dtb`.
+pub fn reserved_regions(dtb: usize) -> Option<Vec<Range<usize>>> {
+    if !valid(dtb) {
+        return None;
+    }
+    let total_size = be32(dtb + 4) as usize;
+    let off_rsvmap = be32(dtb + 16) as usize;
+
+    let mut regions = vec![dtb..dtb + total_size];
+
+    // memory reservation block: (address, size) pairs ending with (0, 0)
+    let mut p = dtb + off_rsvmap;
+    loop {
+        let (addr, size) = (be64(p) as usize, be64(p + 8) as usize);
+        if addr == 0 && size == 0 {
+            break;
+        }
+        regions.push(addr..addr + size);
+        p += 16;
+    }
+
+    let (mut addr_cells, mut size_cells) = (2, 2);
+    walk_props(dtb, |top, depth, name, value, len| {
+        if top != b"reserved-memory" {
+            return;
+        }
+        match (depth, name) {
+            (2, b"#address-cells") => addr_cells = be32(value) as usize,
+            (2, b"#size-cells") => size_cells = be32(value) as usize,
+            (3, b"reg") => {
+                let entry = (addr_cells + size_cells) * 4;
+                for i in 0..len / entry {
+                    let base = read_cells(value + i * entry, addr_cells);
+                    let size = read_cells(value + i * entry + addr_cells * 4, size_cells);
+                    regions.push(base..base + size);
+                }
+            }
+            _ => {}
+        }
+    });
+
+    Some(regions)
+}
+
+/// `/chosen/bootargs`, i.e. the kernel command line. Paging is on by now, so the dtb has to
+/// lie in the identically mapped [ekernel, MEMORY_END).
+pub fn bootargs(dtb: usize) -> Option<String> {
+    extern "C" {
+        fn ekernel();
+    }
+    if dtb < ekernel as usize || dtb >= MEMORY_END || !valid(dtb) {
+        return None;
+    }
+    if dtb + be32(dtb + 4) as usize > MEMORY_END {
+        return None;
+    }
+    let mut args = None;
+    walk_props(dtb, |top, depth, name, value, len| {
+        if top == b"chosen" && depth == 2 && name == b"bootargs" && len > 0 {
+            // nul terminated
+            let bytes = unsafe { core::slice::from_raw_parts(value as *const u8, len - 1) };
+            args = core::str::from_utf8(bytes).ok().map(String::from);
+        }
+    });
+    args
+}
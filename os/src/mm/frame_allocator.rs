@@ -1,28 +1,61 @@
-use alloc::{fmt::Debug, vec::Vec};
+use alloc::{collections::BTreeSet, fmt::Debug, vec::Vec};
 use lazy_static::lazy_static;
 
 use crate::{
-    config::MEMORY_END,
+    config::{MEMORY_END, PAGE_SIZE},
     mm::address::{PhysAddr, PhysPageNum},
     sync::UPIntrFreeCell,
 };
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> =
         unsafe { UPIntrFreeCell::new(FrameAllocatorImpl::new()) };
 }
 
-pub fn init_frame_allocator() {
-    extern "C" {
-        fn ekernel();
+extern "C" {
+    fn skernel();
+    fn ekernel();
+}
+
+/// [ekernel, MEMORY_END) minus what the firmware reserved in device tree at `dtb`
+pub fn init_frame_allocator(dtb: usize) {
+    let start = PhysAddr::from(ekernel as usize).ceil();
+    let end = PhysAddr::from(MEMORY_END).floor();
+    let mut reserved = Vec::new();
+    match super::fdt::reserved_regions(dtb) {
+        Some(regions) => {
+            for region in regions {
+                let l = PhysAddr::from(region.start).floor().max(start);
+                let r = PhysAddr::from(region.end).ceil().min(end);
+                if l < r {
+                    println!(
+                        "KERN: reserve memory [{:#x}, {:#x})",
+                        region.start, region.end
+                    );
+                    reserved.push((l.0, r.0));
+                }
+            }
+        }
+        None => println!(
+            "KERN: no device tree at {:#x}, assume all memory after kernel free",
+            dtb
+        ),
     }
+    reserved.sort();
 
-    // [ekernel, MEMORY_END)
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    // hand out the gaps between reserved ranges
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    let mut l = start.0;
+    for (rl, rr) in reserved {
+        if l < rl {
+            allocator.add_range(l.into(), rl.into());
+        }
+        l = l.max(rr);
+    }
+    if l < end.0 {
+        allocator.add_range(l.into(), end);
+    }
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
@@ -32,6 +65,7 @@ pub fn frame_alloc() -> Option<FrameTracker> {
         .map(FrameTracker::new)
 }
 
+/// `num` physically contiguous frames, in ascending order, each dropped on its own
 pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
     FRAME_ALLOCATOR
         .exclusive_access()
@@ -39,10 +73,26 @@ pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
         .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
 }
 
+/// `2^order` physically contiguous frames, in ascending order
+pub fn frame_alloc_contiguous(order: usize) -> Option<Vec<FrameTracker>> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc_contiguous(order)
+        .map(|base| {
+            (base.0..base.0 + (1 << order))
+                .map(|ppn| FrameTracker::new(ppn.into()))
+                .collect()
+        })
+}
+
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.exclusive_access().stats()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v = Vec::new();
@@ -58,6 +108,18 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+    let free = frame_stats().free;
+    let run = frame_alloc_more(3).unwrap();
+    for w in run.windows(2) {
+        assert_eq!(w[0].ppn.0 + 1, w[1].ppn.0);
+    }
+    drop(run);
+    let block = frame_alloc_contiguous(2).unwrap();
+    assert_eq!(block.len(), 4);
+    assert_eq!(block[0].ppn.0 % 4, 0);
+    drop(block);
+    // all coalesced back
+    assert_eq!(frame_stats().free, free);
     println!("frame_allocator test passed!");
 }
 
@@ -68,6 +130,12 @@ pub struct FrameTracker {
 
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
+        // zeroing part of the kernel image is way harder to track down than this
+        assert!(
+            ppn.0 >= ekernel as usize / PAGE_SIZE || ppn.0 < skernel as usize / PAGE_SIZE,
+            "frame ppn={:#x} inside kernel image",
+            ppn.0
+        );
         let bytes_array = ppn.get_bytes_array();
         // clean page
         bytes_array.fill(0);
@@ -90,68 +158,144 @@ impl Debug for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// contiguous run, in ascending order
     fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>>;
+    /// base of `2^order` contiguous frames
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
-// [current, end) is ppn not allocated
-pub struct StackFrameAllocator {
-    /// start ppn of idle memory
-    current: usize,
-    /// end ppn of idle memory
-    end: usize,
-    /// FILO of recycled ppn
-    recycled: Vec<usize>,
+/// largest block is `2^MAX_ORDER` frames (4MiB)
+const MAX_ORDER: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    /// free blocks per order
+    pub free_blocks: [usize; MAX_ORDER + 1],
+}
+
+impl FrameStats {
+    /// percentage of free frames not in the largest free block, 0 when nothing free
+    #[allow(unused)]
+    pub fn fragmentation(&self) -> usize {
+        match self.free_blocks.iter().rposition(|&n| n > 0) {
+            Some(order) => 100 - (100 << order) / self.free,
+            None => 0,
+        }
+    }
+}
+
+/// Binary buddy allocator, blocks of order `k` are aligned to `2^k` in ppn so the buddy of
+/// block `b` is `b ^ (1 << k)`. Single frames handed out of a larger block (`alloc_more`)
+/// are freed one by one and coalesce back as their buddies come home.
+pub struct BuddyFrameAllocator {
+    /// block base ppns per order
+    free_lists: [BTreeSet<usize>; MAX_ORDER + 1],
+    /// [l, r) of ppn added by `add_range`
+    ranges: Vec<(usize, usize)>,
+    total: usize,
+    free: usize,
 }
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
+impl BuddyFrameAllocator {
+    /// Give frames [l, r) to the allocator, cut into the largest aligned blocks.
+    pub fn add_range(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        let (mut l, r) = (l.0, r.0);
+        self.ranges.push((l, r));
+        self.total += r - l;
+        self.free += r - l;
+        while l < r {
+            let order = (l.trailing_zeros() as usize)
+                .min(MAX_ORDER)
+                .min((r - l).ilog2() as usize);
+            self.free_lists[order].insert(l);
+            l += 1 << order;
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let mut free_blocks = [0; MAX_ORDER + 1];
+        for (order, list) in self.free_lists.iter().enumerate() {
+            free_blocks[order] = list.len();
+        }
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            free_blocks,
+        }
+    }
+
+    fn is_free(&self, ppn: usize) -> bool {
+        (0..=MAX_ORDER).any(|order| {
+            let base = ppn & !((1 << order) - 1);
+            self.free_lists[order].contains(&base)
+        })
+    }
+
+    /// Insert block and merge with its buddy as far as possible.
+    fn free_block(&mut self, mut base: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = base ^ (1 << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            base = base.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(base);
     }
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
+            free_lists: Default::default(),
+            ranges: Vec::new(),
+            total: 0,
+            free: 0,
         }
     }
 
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else {
-            if self.current == self.end {
-                None
-            } else {
-                let ppn = self.current;
-                self.current += 1;
-                Some(ppn.into())
-            }
-        }
+        self.alloc_contiguous(0)
     }
 
     fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>> {
-        if self.current + pages >= self.end {
-            None
-        } else {
-            self.current += pages;
-            let arr: Vec<usize> = (1..pages + 1).collect();
-            let v = arr.iter().map(|x| (self.current - x).into()).collect();
-            Some(v)
+        if pages == 0 {
+            return Some(Vec::new());
+        }
+        let order = pages.next_power_of_two().trailing_zeros() as usize;
+        let base = self.alloc_contiguous(order)?.0;
+        // give back the tail beyond `pages`
+        for ppn in base + pages..base + (1 << order) {
+            self.dealloc(ppn.into());
         }
+        Some((base..base + pages).map(PhysPageNum::from).collect())
+    }
+
+    fn alloc_contiguous(&mut self, order: usize) -> Option<PhysPageNum> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let from = (order..=MAX_ORDER).find(|&k| !self.free_lists[k].is_empty())?;
+        let base = self.free_lists[from].pop_first().unwrap();
+        // split down, upper halves stay free
+        for k in (order..from).rev() {
+            self.free_lists[k].insert(base + (1 << k));
+        }
+        self.free -= 1 << order;
+        Some(base.into())
     }
 
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
-        // validity check: idle OR recycled already?
-        if ppn >= self.current || self.recycled.iter().find(|&v| v == &ppn).is_some() {
+        // validity check: managed AND not free already?
+        if !self.ranges.iter().any(|&(l, r)| (l..r).contains(&ppn)) || self.is_free(ppn) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-
-        self.recycled.push(ppn);
+        self.free += 1;
+        self.free_block(ppn, 0);
     }
 }
@@ -1,10 +1,16 @@
+#[cfg(not(feature = "heap_debug"))]
 use buddy_system_allocator::LockedHeap;
 
 use crate::config::KERNEL_HEAP_SIZE;
 
+#[cfg(not(feature = "heap_debug"))]
 #[global_allocator]
 static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+#[cfg(feature = "heap_debug")]
+#[global_allocator]
+static HEAP_ALLOCATOR: super::heap_debug::CheckedHeap = super::heap_debug::CheckedHeap::empty();
+
 // locate at .bss
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
@@ -20,6 +26,25 @@ pub fn init_heap() {
     }
 }
 
+/// Usage of the kernel heap, in bytes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    /// taken by blocks handed out, rounded up to powers of 2
+    pub allocated: usize,
+    /// of that, asked for
+    pub requested: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats {
+        total: heap.stats_total_bytes(),
+        allocated: heap.stats_alloc_actual(),
+        requested: heap.stats_alloc_user(),
+    }
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::{boxed::Box, vec::Vec};
@@ -0,0 +1,146 @@
+//! Integrity checks for the kernel heap, built with feature `heap_debug`.
+//!
+//! Every block is laid out as `[pad | Header | data | canary]`. The header records the size
+//! asked for and the return addresses of the allocating frames, its magic catches double and
+//! invalid frees, the canary catches overruns; all checked at dealloc, where a violation is
+//! reported and the kernel panics. Fresh and freed data is poisoned so reads of uninitialized
+//! or freed memory show up as an obvious pattern. Note a double free is only caught until the
+//! block gets handed out again.
+
+use buddy_system_allocator::LockedHeap;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    arch::asm,
+    mem::{align_of, size_of},
+    ops::Deref,
+    ptr,
+};
+
+use crate::trace::find_symbol_with_addr;
+
+const MAGIC_ALIVE: usize = 0xa110_ca7e_d00d_f00d;
+const MAGIC_FREED: usize = 0xdead_beef_f4ee_d00d;
+const CANARY: u8 = 0xcd;
+const CANARY_SIZE: usize = 16;
+const POISON_ALLOC: u8 = 0xa5;
+const POISON_FREE: u8 = 0x6b;
+/// nearest frames recorded per block, allocator internals included
+const CALLER_DEPTH: usize = 6;
+
+#[repr(C)]
+struct Header {
+    callers: [usize; CALLER_DEPTH],
+    size: usize,
+    /// last, so the link the inner allocator keeps in the first word of a free block can't hit it
+    magic: usize,
+}
+
+extern "C" {
+    fn skernel();
+    fn ekernel();
+}
+
+pub struct CheckedHeap(LockedHeap);
+
+impl CheckedHeap {
+    pub const fn empty() -> Self {
+        Self(LockedHeap::empty())
+    }
+}
+
+impl Deref for CheckedHeap {
+    type Target = LockedHeap;
+
+    fn deref(&self) -> &LockedHeap {
+        &self.0
+    }
+}
+
+/// layout asked from the inner allocator, and offset of data in it
+fn outer_layout(layout: Layout) -> (Layout, usize) {
+    let align = layout.align().max(align_of::<Header>());
+    let offset = (size_of::<Header>() + align - 1) & !(align - 1);
+    let outer = Layout::from_size_align(offset + layout.size() + CANARY_SIZE, align).unwrap();
+    (outer, offset)
+}
+
+/// Kernel stacks live in the upper half of Sv39, the boot stack in the kernel image. Anything
+/// else is a user frame pointer left over from trap entry, stop there.
+fn is_kernel_stack(fp: usize) -> bool {
+    let upper_half = !((1usize << 38) - 1);
+    (fp >= upper_half || (skernel as usize..ekernel as usize).contains(&fp))
+        && fp % size_of::<usize>() == 0
+}
+
+/// Return addresses of the nearest frames, walked through frame pointers.
+#[inline(always)]
+fn callers() -> [usize; CALLER_DEPTH] {
+    let mut callers = [0; CALLER_DEPTH];
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, fp", out(reg) fp);
+    }
+    for ra in callers.iter_mut() {
+        if !is_kernel_stack(fp) {
+            break;
+        }
+        unsafe {
+            *ra = *((fp - 8) as *const usize);
+            fp = *((fp - 16) as *const usize);
+        }
+    }
+    callers
+}
+
+fn report(what: &str, data: *mut u8, header: &Header) -> ! {
+    println!(
+        "KERN: heap {} at {:p}, size {}, allocated from:",
+        what, data, header.size
+    );
+    for &ra in header.callers.iter().filter(|&&ra| ra != 0) {
+        match find_symbol_with_addr(ra) {
+            Some((func, name)) => println!("[{:#x}] (+{:0>4x}) {}", func, ra - func, name),
+            None => println!("[{:#x}] ?", ra),
+        }
+    }
+    // backtrace of the freeing side follows
+    panic!("heap {}", what);
+}
+
+unsafe impl GlobalAlloc for CheckedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (outer, offset) = outer_layout(layout);
+        let base = self.0.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+        let data = base.add(offset);
+        let header = &mut *(data as *mut Header).sub(1);
+        header.callers = callers();
+        header.size = layout.size();
+        header.magic = MAGIC_ALIVE;
+        ptr::write_bytes(data, POISON_ALLOC, layout.size());
+        ptr::write_bytes(data.add(layout.size()), CANARY, CANARY_SIZE);
+        data
+    }
+
+    unsafe fn dealloc(&self, data: *mut u8, layout: Layout) {
+        let header = &mut *(data as *mut Header).sub(1);
+        match header.magic {
+            MAGIC_ALIVE => {}
+            MAGIC_FREED => report("double free", data, header),
+            _ => report("free of corrupted or foreign block", data, header),
+        }
+        if header.size != layout.size() {
+            report("free with mismatched layout", data, header);
+        }
+        let canary = core::slice::from_raw_parts(data.add(layout.size()), CANARY_SIZE);
+        if canary.iter().any(|&b| b != CANARY) {
+            report("buffer overrun", data, header);
+        }
+        header.magic = MAGIC_FREED;
+        ptr::write_bytes(data, POISON_FREE, layout.size());
+        let (outer, offset) = outer_layout(layout);
+        self.0.dealloc(data.sub(offset), outer);
+    }
+}
@@ -1,20 +1,23 @@
-use core::arch::asm;
+use core::{arch::asm, fmt::Debug, mem::size_of};
 
 use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use bitflags::bitflags;
 use lazy_static::lazy_static;
 use riscv::register::satp;
+use xmas_elf::{header::Class, program::ProgramHeader64};
 
 use crate::{
-    config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE},
+    config::{BRK_AREA_BASE, MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT},
     mm::address::StepByOne,
     sync::UPIntrFreeCell,
+    syscall::errno::ENOEXEC,
 };
 
 use super::{
     address::{PhysAddr, PhysPageNum, VPNRange, VirtAddr, VirtPageNum},
-    frame_allocator::{frame_alloc, FrameTracker},
-    page_table::{PTEFlags, PageTable, PageTableEntry},
+    frame_allocator::{frame_alloc, frame_stats, FrameTracker},
+    page_table::{PTEFlags, PageTable, PageTableEntry, MEGAPAGE_PAGES},
+    swap::SwapSlot,
 };
 
 lazy_static! {
@@ -43,10 +46,22 @@ extern "C" {
 pub struct MapArea {
     vpn_range: VPNRange,
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// of pages swapped out, and of those read back in and not written to since
+    slots: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
     map_type: MapType,
     map_perm: MapPermission,
 }
 
+impl Debug for MapArea {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} {:?}",
+            self.vpn_range, self.map_type, self.map_perm
+        )
+    }
+}
+
 impl MapArea {
     pub fn new(
         start_va: VirtAddr,
@@ -60,6 +75,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(s, e),
             data_frames: BTreeMap::new(),
+            slots: BTreeMap::new(),
             map_type,
             map_perm,
         }
@@ -70,6 +86,7 @@ impl MapArea {
         Self {
             vpn_range: another.vpn_range.clone(),
             data_frames: BTreeMap::new(),
+            slots: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
@@ -96,14 +113,37 @@ impl MapArea {
 
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+            self.slots.remove(&vpn);
+            // swapped out, nothing mapped
+            if self.data_frames.remove(&vpn).is_none() {
+                return;
+            }
         }
         page_table.unmap(vpn);
     }
 
+    /// If its pages may be swapped out: those of its own frames user can reach, so not the
+    /// trap contexts
+    fn swappable(&self) -> bool {
+        self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::U)
+    }
+
+    /// Identical ones take megapages where they're aligned and whole, 4K pages around them
     pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+        let end = self.vpn_range.get_end().0;
+        let mut vpn = self.vpn_range.get_start();
+        while vpn.0 < end {
+            if self.map_type == MapType::Identical
+                && vpn.0 % MEGAPAGE_PAGES == 0
+                && end - vpn.0 >= MEGAPAGE_PAGES
+            {
+                let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+                page_table.map_megapage(vpn, PhysPageNum(vpn.0), pte_flags);
+                vpn = VirtPageNum(vpn.0 + MEGAPAGE_PAGES);
+            } else {
+                self.map_one(page_table, vpn);
+                vpn.step();
+            }
         }
     }
 
@@ -146,6 +186,28 @@ impl MapArea {
     }
 }
 
+/// A LOAD segment of an elf
+pub struct ElfSegment {
+    pub start_va: VirtAddr,
+    pub end_va: VirtAddr,
+    pub perm: MapPermission,
+    /// where its bytes are in the file, those past them up to `end_va` are zeros
+    pub offset: usize,
+    pub file_size: usize,
+}
+
+impl ElfSegment {
+    /// If it can be paged in from the file as a private mapping on demand instead: stores
+    /// never copy it, there are no zeros to fill in, and its pages line up with those of the
+    /// file.
+    pub fn lazy(&self) -> bool {
+        !self.perm.contains(MapPermission::W)
+            && self.start_va.0 + self.file_size == self.end_va.0
+            && self.start_va.page_offset() == 0
+            && self.offset % PAGE_SIZE == 0
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MapType {
     Identical,
@@ -165,14 +227,18 @@ bitflags! {
 
 pub struct MemorySet {
     page_table: PageTable,
-    areas: Vec<MapArea>,
+    /// keyed by start vpn, areas never overlap
+    areas: BTreeMap<VirtPageNum, MapArea>,
+    /// where the clock of `swap_out` goes on from
+    clock: VirtPageNum,
 }
 
 impl MemorySet {
     pub fn new_bare() -> Self {
         Self {
             page_table: PageTable::new(),
-            areas: Vec::new(),
+            areas: BTreeMap::new(),
+            clock: VirtPageNum(0),
         }
     }
 
@@ -254,27 +320,49 @@ impl MemorySet {
         memory_set
     }
 
-    /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
-
-        // map trampoline
-        memory_set.map_trampoline();
-
-        // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+    /// Bytes from the start of an elf file the elf header and the program headers take, read
+    /// from `head`, the start of it. ENOEXEC if it's not a 64-bit elf.
+    pub fn elf_headers_len(head: &[u8]) -> Result<usize, isize> {
+        let elf = xmas_elf::ElfFile::new(head).map_err(|_| ENOEXEC)?;
         let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46]
+            || !matches!(elf_header.pt1.class(), Class::SixtyFour)
+            || elf_header.pt2.ph_entry_size() as usize != size_of::<ProgramHeader64>()
+        {
+            return Err(ENOEXEC);
+        }
+        (elf_header.pt2.ph_count() as usize * size_of::<ProgramHeader64>())
+            .checked_add(elf_header.pt2.ph_offset() as usize)
+            .ok_or(ENOEXEC)
+    }
 
-        let ph_count = elf_header.pt2.ph_count();
+    /// Trampoline only, the LOAD segments of elf are left to the caller: returned along with
+    /// user_sp and entry point. `elf_data` is the start of the file, the program headers and
+    /// all. ENOEXEC if it's no elf that can be run, nothing in it is trusted.
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, Vec<ElfSegment>, usize, usize), isize> {
+        // program headers of elf, with U flag
+        if Self::elf_headers_len(elf_data)? > elf_data.len() {
+            return Err(ENOEXEC);
+        }
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| ENOEXEC)?;
+        let ph_count = elf.header.pt2.ph_count();
+        let mut segments = Vec::new();
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            let ph = elf.program_header(i).map_err(|_| ENOEXEC)?;
+            if ph.get_type().map_err(|_| ENOEXEC)? == xmas_elf::program::Type::Load {
+                // below the program break area, in ascending order, never sharing a page, and
+                // no more bytes from the file than there's room for
+                let end = ph
+                    .virtual_addr()
+                    .checked_add(ph.mem_size())
+                    .filter(|&end| end <= BRK_AREA_BASE as u64)
+                    .ok_or(ENOEXEC)?;
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let end_va: VirtAddr = (end as usize).into();
+                if start_va.floor() < max_end_vpn || ph.file_size() > ph.mem_size() {
+                    return Err(ENOEXEC);
+                }
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -287,7 +375,6 @@ impl MemorySet {
                     map_perm |= MapPermission::X;
                 }
                 // log::info!("elf hdr({}): [{:?}, {:?})", ph_flags, start_va, end_va);
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 // Q: 为什么这里不取max而是直接赋值?
                 // PT_LOAD Specifies a loadable segment, described by p_filesz and p_memsz.
                 // The bytes from the file are mapped to the beginning of the memory segment.
@@ -297,11 +384,14 @@ impl MemorySet {
                 // Loadable segment entries in the program header table appear in *ascending* order,
                 // sorted on the p_vaddr member.
                 // tl;dr segment是按地址升序排放的
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                max_end_vpn = end_va.ceil();
+                segments.push(ElfSegment {
+                    start_va,
+                    end_va,
+                    perm: map_perm,
+                    offset: ph.offset() as usize,
+                    file_size: ph.file_size() as usize,
+                });
             }
         }
         // map user stack with U flags
@@ -309,12 +399,31 @@ impl MemorySet {
         let mut user_stack_bottom: usize = max_end_va.into();
         // guard page
         user_stack_bottom += PAGE_SIZE;
+        // elf image collides with program break area
+        if user_stack_bottom >= BRK_AREA_BASE {
+            return Err(ENOEXEC);
+        }
 
-        (
+        let mut memory_set = Self::new_bare();
+        // map trampoline
+        memory_set.map_trampoline();
+        Ok((
             memory_set,
+            segments,
             user_stack_bottom,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
+    }
+
+    /// Map `segment` with its bytes in the file `data` copied in, zeros past them.
+    pub fn load_segment(&mut self, segment: &ElfSegment, data: &[u8]) {
+        let map_area = MapArea::new(
+            segment.start_va,
+            segment.end_va,
+            MapType::Framed,
+            segment.perm,
+        );
+        self.push(map_area, Some(data));
     }
 
     /// how we `fork` user space
@@ -323,15 +432,24 @@ impl MemorySet {
         // map trampoline
         memory_set.map_trampoline();
         // copy
-        for area in &user_space.areas {
+        for area in user_space.areas.values() {
             // map areas
             let new_area = MapArea::from_another(area);
             memory_set.push(new_area, None);
             // copy data
             for vpn in area.vpn_range {
-                let src = user_space.translate(vpn).unwrap().ppn();
                 let dst = memory_set.translate(vpn).unwrap().ppn();
-                dst.get_bytes_array().copy_from_slice(src.get_bytes_array());
+                match user_space.translate(vpn).filter(|pte| pte.is_valid()) {
+                    Some(src) => dst
+                        .get_bytes_array()
+                        .copy_from_slice(src.ppn().get_bytes_array()),
+                    // swapped out, or released and zero as `dst` is
+                    None => {
+                        if let Some(slot) = area.slots.get(&vpn) {
+                            slot.read(dst);
+                        }
+                    }
+                }
             }
         }
         memory_set
@@ -354,7 +472,7 @@ impl MemorySet {
         if let Some(data) = data {
             map_area.copy_data(&self.page_table, data);
         }
-        self.areas.push(map_area);
+        self.areas.insert(map_area.vpn_range.get_start(), map_area);
     }
 
     pub fn activate(&self) {
@@ -395,19 +513,194 @@ impl MemorySet {
     }
 
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
-        if let Some((idx, area)) = self
-            .areas
-            .iter_mut()
-            .enumerate()
-            .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
-        {
+        if let Some(mut area) = self.areas.remove(&start_vpn) {
             area.unmap(&mut self.page_table);
-            self.areas.remove(idx);
         }
     }
 
+    /// Rewrite the flags of the pages of `vpn_range` mapped so far to `map_perm`, whether
+    /// they've been accessed and written stays.
+    pub fn protect(&mut self, vpn_range: VPNRange, map_perm: MapPermission) {
+        let pte_flags = PTEFlags::from_bits_truncate(map_perm.bits);
+        for vpn in vpn_range {
+            if let Some(pte) = self.page_table.translate(vpn).filter(|pte| pte.is_valid()) {
+                let used = pte.flags() & (PTEFlags::A | PTEFlags::D);
+                self.page_table.remap(vpn, pte.ppn(), pte_flags | used);
+            }
+        }
+    }
+
+    /// Change the permission of the user area spanning exactly `vpn_range` to `map_perm`,
+    /// forks copy it too. False if there's no such area.
+    pub fn protect_area(&mut self, vpn_range: VPNRange, map_perm: MapPermission) -> bool {
+        match self.areas.get_mut(&vpn_range.get_start()) {
+            Some(area)
+                if area.vpn_range == vpn_range && area.map_perm.contains(MapPermission::U) =>
+            {
+                area.map_perm = map_perm;
+            }
+            _ => return false,
+        }
+        self.protect(vpn_range, map_perm);
+        true
+    }
+
+    /// Area containing `vpn`, the trampoline isn't one.
+    pub fn area_of(&self, vpn: VirtPageNum) -> Option<&MapArea> {
+        self.areas
+            .range(..=vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.vpn_range.contains(vpn))
+    }
+
+    /// Pages of the areas, in memory or swapped out: what a fork copies
+    pub fn framed_pages(&self) -> usize {
+        self.areas
+            .values()
+            .filter(|area| area.map_type == MapType::Framed)
+            .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+            .sum()
+    }
+
+    /// User pages mapped to a frame now, whatever they're from: the resident set
+    pub fn resident_pages(&self) -> usize {
+        self.page_table
+            .leaves()
+            .iter()
+            .filter(|(_, pte)| pte.is_user())
+            .count()
+    }
+
+    /// Take a page of the user areas out to swap: the clock goes round those in memory from
+    /// where it stopped last, one accessed since it last passed gets A cleared and is passed
+    /// again. The page is unmapped, the slot returned holds its frame to write unless it's
+    /// still the same as what the slot has. `None` if no page can go.
+    pub fn swap_out(&mut self) -> Option<Arc<SwapSlot>> {
+        let vpn = self.clock_victim()?;
+        let area = self.areas.range_mut(..=vpn).next_back().unwrap().1;
+        let dirty = self.page_table.translate(vpn).unwrap().is_dirty();
+        let slot = match area.slots.get(&vpn) {
+            Some(slot) if !dirty => {
+                area.data_frames.remove(&vpn);
+                slot.clone()
+            }
+            _ => {
+                let slot = Arc::new(SwapSlot::alloc()?);
+                slot.hold(area.data_frames.remove(&vpn).unwrap());
+                area.slots.insert(vpn, slot.clone());
+                slot
+            }
+        };
+        self.page_table.unmap(vpn);
+        self.clock = VirtPageNum(vpn.0 + 1);
+        Some(slot)
+    }
+
+    /// Page in memory of the user areas the clock stops at, going round twice at most as the
+    /// first may only clear A bits
+    fn clock_victim(&mut self) -> Option<VirtPageNum> {
+        let hand = self.clock;
+        let (areas, page_table) = (&self.areas, &mut self.page_table);
+        let resident = move |from: VirtPageNum| {
+            areas
+                .values()
+                .filter(|area| area.swappable())
+                .flat_map(move |area| area.data_frames.range(from..).map(|(vpn, _)| *vpn))
+        };
+        for _ in 0..2 {
+            let round =
+                resident(hand).chain(resident(VirtPageNum(0)).take_while(|vpn| *vpn < hand));
+            for vpn in round {
+                if !page_table.clear_accessed(vpn) {
+                    return Some(vpn);
+                }
+            }
+        }
+        None
+    }
+
+    /// Bring `vpn` back in if it's swapped out, mapped as its area is. Whether it was read from
+    /// swap, rather than taken back before it was written; `None` if it's not swapped out.
+    pub fn swap_in(&mut self, vpn: VirtPageNum) -> Option<bool> {
+        let area = self
+            .areas
+            .range_mut(..=vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.vpn_range.contains(vpn) && !area.data_frames.contains_key(&vpn))?;
+        let slot = area.slots.get(&vpn)?.clone();
+        let (frame, read) = match slot.take_back() {
+            // the slot has nothing yet
+            Some(frame) => {
+                area.slots.remove(&vpn);
+                (frame, false)
+            }
+            None => {
+                let frame = frame_alloc().unwrap();
+                slot.read(frame.ppn);
+                (frame, true)
+            }
+        };
+        let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+        self.page_table.map(vpn, frame.ppn, pte_flags);
+        area.data_frames.insert(vpn, frame);
+        Some(read)
+    }
+
+    /// Free the frames of the pages of `vpn_range` in the area `vpn_range` starts in, and the
+    /// swap slots of those swapped out. They're mapped zeroed again by `map_released` when
+    /// touched.
+    pub fn release(&mut self, vpn_range: VPNRange) {
+        let start = vpn_range.get_start();
+        let area = match self.areas.range_mut(..=start).next_back() {
+            Some((_, area)) if area.vpn_range.contains(start) => area,
+            _ => return,
+        };
+        for vpn in vpn_range {
+            if area.vpn_range.contains(vpn) {
+                area.unmap_one(&mut self.page_table, vpn);
+            }
+        }
+    }
+
+    /// Map a zeroed frame at `vpn` if it was released from its area, false if it wasn't.
+    pub fn map_released(&mut self, vpn: VirtPageNum) -> bool {
+        let area = match self.areas.range_mut(..=vpn).next_back() {
+            Some((_, area))
+                if area.vpn_range.contains(vpn)
+                    && area.map_type == MapType::Framed
+                    && !area.data_frames.contains_key(&vpn)
+                    && !area.slots.contains_key(&vpn) =>
+            {
+                area
+            }
+            _ => return false,
+        };
+        area.map_one(&mut self.page_table, vpn);
+        true
+    }
+
+    /// If any area (or the trampoline) lies in `vpn_range`.
+    pub fn overlaps(&self, vpn_range: VPNRange) -> bool {
+        let trampoline: VirtPageNum = VirtAddr::from(TRAMPOLINE).into();
+        if vpn_range.contains(trampoline) {
+            return true;
+        }
+        // only the last area starting before the end can reach into the range
+        self.areas
+            .range(..vpn_range.get_end())
+            .next_back()
+            .map_or(false, |(_, area)| {
+                area.vpn_range.get_end() > vpn_range.get_start()
+            })
+    }
+
+    /// Release all user frames, and page tables too so only the root frame is left. Frames
+    /// mapped by `map()` directly (file mappings) are owned elsewhere.
     pub fn recycle_data_pages(&mut self) {
         self.areas.clear();
+        self.page_table.clear();
     }
 
     /// Delegate `map()` to page_table
@@ -416,10 +709,59 @@ impl MemorySet {
         self.page_table.map(vpn, ppn, pte_flags);
     }
 
+    /// Delegate `remap()` to page_table
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, map_perm: MapPermission) {
+        let pte_flags = PTEFlags::from_bits_truncate(map_perm.bits);
+        self.page_table.remap(vpn, ppn, pte_flags);
+    }
+
     /// Delegate `unmap()` to page_table
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         self.page_table.unmap(vpn);
     }
+
+    /// No page of the kernel space may be reachable from U-mode.
+    #[allow(unused)]
+    pub fn audit_kernel(&self) {
+        for (vpn, pte) in self.page_table.leaves() {
+            assert!(!pte.is_user(), "kernel page {:?} mapped with U bit", vpn);
+        }
+    }
+
+    /// While in U-mode the only kernel-owned pages are the trampoline and the trap contexts,
+    /// which sit right below it and above every user mapping. Besides the trampoline no page
+    /// may alias the kernel image.
+    #[allow(unused)]
+    pub fn audit_user(&self) {
+        let trampoline: VirtPageNum = VirtAddr::from(TRAMPOLINE).into();
+        let trap_cx_top: VirtPageNum = VirtAddr::from(TRAP_CONTEXT).into();
+        let kernel_image = stext as usize / PAGE_SIZE..ekernel as usize / PAGE_SIZE;
+        let leaves = self.page_table.leaves();
+        let user_top = leaves
+            .iter()
+            .filter(|(_, pte)| pte.is_user())
+            .map(|(vpn, _)| *vpn)
+            .max()
+            .unwrap_or(VirtPageNum(0));
+        for (vpn, pte) in leaves {
+            if vpn == trampoline {
+                assert!(!pte.is_user(), "trampoline mapped with U bit");
+                continue;
+            }
+            assert!(
+                !kernel_image.contains(&pte.ppn().0),
+                "user page {:?} aliases kernel image",
+                vpn
+            );
+            if !pte.is_user() {
+                assert!(
+                    vpn > user_top && vpn <= trap_cx_top,
+                    "non-U page {:?} outside of trap context area",
+                    vpn
+                );
+            }
+        }
+    }
 }
 
 #[allow(unused)]
@@ -454,3 +796,37 @@ pub fn remap_test() {
         false
     );
 }
+
+/// Map & unmap areas all over the address space many times, every frame incl. the page
+/// table ones must come back.
+#[allow(unused)]
+pub fn unmap_reclaim_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let baseline = frame_stats().free;
+    for round in 0..64 {
+        // 3 areas: sharing nothing, sharing a level-1 table, sharing a leaf table
+        let starts = [
+            0x10_0000_0000 + round * 0x4000_0000,
+            0x10_0000_0000 + round * 0x4000_0000 + 0x20_0000,
+            0x10_0000_0000 + round * 0x4000_0000 + 0x20_0000 + 4 * PAGE_SIZE,
+        ];
+        for &start in &starts {
+            memory_set.insert_framed_area(
+                start.into(),
+                (start + 4 * PAGE_SIZE).into(),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            );
+        }
+        assert!(frame_stats().free < baseline);
+        for &start in &starts {
+            memory_set.remove_area_with_start_vpn(VirtAddr::from(start).into());
+        }
+        assert_eq!(
+            frame_stats().free,
+            baseline,
+            "frames leaked in round {}",
+            round
+        );
+    }
+    println!("unmap_reclaim_test passed!");
+}
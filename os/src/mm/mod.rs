@@ -1,18 +1,34 @@
 mod address;
+mod fdt;
 mod frame_allocator;
 mod heap_allocator;
+#[cfg(feature = "heap_debug")]
+mod heap_debug;
 mod memory_set;
 mod page_table;
+mod shm;
+mod swap;
 
 pub use address::*;
-pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, FrameTracker};
-pub use memory_set::{kernel_token, MapPermission, MemorySet, KERNEL_SPACE};
+pub use fdt::bootargs;
+pub use frame_allocator::{
+    frame_alloc, frame_alloc_contiguous, frame_alloc_more, frame_dealloc, frame_stats, FrameStats,
+    FrameTracker,
+};
+pub use heap_allocator::{heap_stats, HeapStats};
+pub use memory_set::{kernel_token, ElfSegment, MapPermission, MemorySet, KERNEL_SPACE};
 pub use page_table::*;
+pub use shm::{shm_open, SharedMemory};
+pub use swap::{init_swap, make_room, SwapSlot};
 
-pub fn init() {
+pub fn init(dtb: usize) {
     heap_allocator::init_heap();
+    // UART driver needs the heap only, switch console over before anything else
+    crate::console::init();
     // must put after init_heap, coz heap_allocator inited in init_heap()
     // frame_allocator's new & init requires heap (also frame_allocator_test())
-    frame_allocator::init_frame_allocator();
+    frame_allocator::init_frame_allocator(dtb);
     KERNEL_SPACE.exclusive_access().activate();
+    #[cfg(feature = "isolation_audit")]
+    KERNEL_SPACE.exclusive_access().audit_kernel();
 }
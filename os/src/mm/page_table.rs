@@ -1,14 +1,20 @@
 use alloc::vec::Vec;
 use alloc::{string::String, vec};
 use bitflags::bitflags;
+use core::mem::{size_of, MaybeUninit};
+
+use crate::syscall::errno::{EFAULT, EINVAL, ENAMETOOLONG};
 
 use super::PhysAddr;
 use super::{
-    address::{PhysPageNum, StepByOne, VirtPageNum, PPN_MASK},
-    frame_allocator::{frame_alloc, FrameTracker},
+    address::{PhysPageNum, StepByOne, VPNRange, VirtPageNum, PPN_MASK},
+    frame_allocator::{frame_alloc, frame_stats, FrameTracker},
     VirtAddr,
 };
 
+/// Pages a megapage maps, 2MiB: a leaf at level 1 in place of a table of 4K ones
+pub const MEGAPAGE_PAGES: usize = 512;
+
 bitflags! {
     pub struct PTEFlags: u8 {
         const V = 1 << 0;
@@ -77,6 +83,15 @@ impl PageTableEntry {
     pub fn is_dirty(&self) -> bool {
         self.flags().contains(PTEFlags::D)
     }
+
+    pub fn is_user(&self) -> bool {
+        self.flags().contains(PTEFlags::U)
+    }
+
+    /// any of R/W/X set means leaf, could be a megapage at upper levels
+    pub fn is_leaf(&self) -> bool {
+        self.readable() || self.writable() || self.executable()
+    }
 }
 
 pub struct PageTable {
@@ -101,17 +116,31 @@ impl PageTable {
         }
     }
 
+    /// Drop every mapping and the tables below root, root itself stays.
+    pub fn clear(&mut self) {
+        let root_ppn = self.root_ppn;
+        root_ppn.get_pte_array().fill(PageTableEntry::empty());
+        self.frames.retain(|f| f.ppn == root_ppn);
+    }
+
     pub fn token(&self) -> usize {
         // MODE = 8, enable paging
         8 << 60 | self.root_ppn.0
     }
 
+    /// Pte of `vpn`, as a 4K one of its own if it's in a megapage
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| pte.clone())
+        self.find_leaf(vpn).map(|(pte, level)| match level {
+            1 => {
+                let ppn = PhysPageNum(pte.ppn().0 + vpn.indexes()[2]);
+                PageTableEntry::new(ppn, pte.flags())
+            }
+            _ => *pte,
+        })
     }
 
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.floor()).map(|pte| {
+        self.translate(va.floor()).map(|pte| {
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.page_offset();
             let pa_usize: usize = aligned_pa.into();
@@ -130,7 +159,10 @@ impl PageTable {
             如果invalid, 则分配一个frame2: ppn = root_ppn + 3(frame_base + 3*0x1000)
         5. 结束, frame2就是对应的pte
     */
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    ///
+    /// Entry of `vpn` at `level`, 2 for a 4K page and 1 for a megapage. A megapage on the way
+    /// is returned instead, it's mapped already.
+    fn find_pte_create(&mut self, vpn: VirtPageNum, level: usize) -> Option<&mut PageTableEntry> {
         let idx = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result = None;
@@ -139,7 +171,7 @@ impl PageTable {
             // 以ppn为基址, 找到第i级, 即vpn[i]的偏移对应的entry
             let pte = &mut ppn.get_pte_array()[idx[i]];
             // 如果是末级, 直接返回
-            if i == 2 {
+            if i == level || pte.is_valid() && pte.is_leaf() {
                 result = Some(pte);
                 break;
             }
@@ -156,7 +188,9 @@ impl PageTable {
         result
     }
 
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Leaf entry of `vpn` with its level: the megapage it's in at level 1, else its own at
+    /// level 2
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let idx = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result = None;
@@ -165,8 +199,8 @@ impl PageTable {
             let pte = &mut ppn.get_pte_array()[idx[i]];
             // 这里在if里面并没有再判断pte是否合法，而是将pte直接包裹起来返回。
             // 所以find_pte可能返回一个不合法（即标志位V为0）的页表项。
-            if i == 2 {
-                result = Some(pte);
+            if i == 2 || pte.is_valid() && pte.is_leaf() {
+                result = Some((pte, i));
                 break;
             }
             if !pte.is_valid() {
@@ -178,26 +212,183 @@ impl PageTable {
         result
     }
 
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| pte)
+    }
+
+    /// Turn the megapage `vpn` is in into a table of 4K pages mapping the same, so one of
+    /// them can change alone. Nothing if it's not in one.
+    fn split_megapage(&mut self, vpn: VirtPageNum) {
+        let pte = match self.find_leaf(vpn) {
+            Some((pte, 1)) => pte,
+            _ => return,
+        };
+        let frame = frame_alloc().unwrap();
+        let (base, flags) = (pte.ppn().0, pte.flags());
+        for (i, entry) in frame.ppn.get_pte_array().iter_mut().enumerate() {
+            *entry = PageTableEntry::new(PhysPageNum(base + i), flags);
+        }
+        *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+        self.frames.push(frame);
+    }
+
     /// insert kv
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        let pte = self.find_pte_create(vpn, 2).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// Map the `MEGAPAGE_PAGES` pages from `vpn` to those from `ppn` with one leaf at level 1,
+    /// both must be aligned to it. None of them may be mapped yet.
+    pub fn map_megapage(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        assert!(
+            vpn.0 % MEGAPAGE_PAGES == 0 && ppn.0 % MEGAPAGE_PAGES == 0,
+            "megapage {:?} -> {:?} not aligned",
+            vpn,
+            ppn
+        );
+        let pte = self.find_pte_create(vpn, 1).unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
 
-    /// remove kv
+    /// Point a mapped `vpn` elsewhere, or just change its flags
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        self.split_megapage(vpn);
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// Set A of the mapped `vpn`, and D too if `dirty`, as an access through it would
+    pub fn set_accessed(&self, vpn: VirtPageNum, dirty: bool) {
+        let pte = self.find_pte(vpn).unwrap();
+        let mut flags = PTEFlags::A;
+        if dirty {
+            flags |= PTEFlags::D;
+        }
+        pte.bits |= flags.bits as usize;
+    }
+
+    /// Clear A of the mapped `vpn`, returns whether it was set
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = self.find_pte(vpn).unwrap();
+        let accessed = pte.flags().contains(PTEFlags::A);
+        pte.bits &= !(PTEFlags::A.bits as usize);
+        accessed
+    }
+
+    /// Clear D of the mapped `vpn`, what it maps was written back
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        pte.bits &= !(PTEFlags::D.bits as usize);
+    }
+
+    /// All valid leaf entries with the (first) vpn they map, for auditing and counting.
+    pub fn leaves(&self) -> Vec<(VirtPageNum, PageTableEntry)> {
+        let mut result = Vec::new();
+        Self::collect_leaves(self.root_ppn, 0, 0, &mut result);
+        result
+    }
+
+    fn collect_leaves(
+        ppn: PhysPageNum,
+        level: usize,
+        prefix: usize,
+        result: &mut Vec<(VirtPageNum, PageTableEntry)>,
+    ) {
+        for (i, pte) in ppn.get_pte_array().iter().enumerate() {
+            if !pte.is_valid() {
+                continue;
+            }
+            let prefix = prefix << 9 | i;
+            if level == 2 || pte.is_leaf() {
+                result.push((VirtPageNum(prefix << (9 * (2 - level))), *pte));
+            } else {
+                Self::collect_leaves(pte.ppn(), level + 1, prefix, result);
+            }
+        }
+    }
+
+    /// remove kv, tables left empty are freed. The rest of a megapage it's in stays mapped.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
+        self.split_megapage(vpn);
         let pte = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        self.reclaim(vpn);
+    }
+
+    /// Free the non-root tables on the path to `vpn` without any valid entry, bottom up.
+    fn reclaim(&mut self, vpn: VirtPageNum) {
+        let idx = vpn.indexes();
+        let mut tables = [self.root_ppn; 3];
+        for i in 0..2 {
+            tables[i + 1] = tables[i].get_pte_array()[idx[i]].ppn();
+        }
+        for i in (1..3).rev() {
+            if tables[i].get_pte_array().iter().any(|pte| pte.is_valid()) {
+                break;
+            }
+            tables[i - 1].get_pte_array()[idx[i - 1]] = PageTableEntry::empty();
+            let pos = self
+                .frames
+                .iter()
+                .position(|frame| frame.ppn == tables[i])
+                .expect("page table frame not owned");
+            // drop the tracker, frame goes back to allocator
+            self.frames.swap_remove(pos);
+        }
+    }
+}
+
+/// Pte of user page `vpn`, paged in first if the process we're in reserved it but didn't
+/// touch it yet or it's swapped out, or copied if it's to be written and still shared since
+/// fork, as the fault of its own access would. It's marked accessed, and written if `write`,
+/// as by the access itself.
+fn translate_user(page_table: &PageTable, vpn: VirtPageNum, write: bool) -> Option<PageTableEntry> {
+    let pte = match page_table.translate(vpn) {
+        Some(pte) if pte.is_valid() && (!write || pte.writable()) => Some(pte),
+        _ if crate::task::fault_in(page_table.token(), vpn, write) => page_table.translate(vpn),
+        pte => pte,
+    };
+    if let Some(pte) = pte.filter(|pte| pte.is_valid()) {
+        page_table.set_accessed(vpn, write && pte.writable());
+    }
+    pte
+}
+
+/// Frame of user page `vpn` the kernel may read, and write if `write`, on behalf of user:
+/// `EFAULT` unless user could itself, so bad pointers from user never get the kernel to fault
+fn user_page(page_table: &PageTable, vpn: VirtPageNum, write: bool) -> Result<PhysPageNum, isize> {
+    match translate_user(page_table, vpn, write) {
+        Some(pte)
+            if pte.is_valid() && pte.is_user() && pte.readable() && (!write || pte.writable()) =>
+        {
+            Ok(pte.ppn())
+        }
+        _ => Err(EFAULT),
     }
 }
 
-/// translate buffer of `[ptr, ptr+len]` in `token` space
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+/// `translate_va` of user `va`, paged in as by `translate_user`
+fn translate_user_va(page_table: &PageTable, va: VirtAddr, write: bool) -> Option<PhysAddr> {
+    translate_user(page_table, va.floor(), write)?;
+    page_table.translate_va(va)
+}
+
+/// translate buffer of `[ptr, ptr+len]` in `token` space, `write` if the kernel stores into it.
+/// `EFAULT` if user can't access all of it so.
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    write: bool,
+) -> Result<Vec<&'static mut [u8]>, isize> {
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.checked_add(len).ok_or(EFAULT)?;
     let mut v = Vec::new();
     // 每个iter获取一段连续的空间, 为什么分段?
     // 因为虚地址连续的一个buffer, 对应的物理地址空间不一定连续, 所以按4K(一个page大小)来获取每一段
@@ -206,7 +397,7 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         // 1. 获取start_va开始的vpn(aligned)
         let mut vpn = start_va.floor();
         // 2. 获取对应ppn
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = user_page(&page_table, vpn, write)?;
         // 3. vpn+1
         vpn.step();
         // 4. 当前连续段的end只能是min { aligned_vpn_addr, end } (其实非end一定是aligned)
@@ -219,23 +410,28 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         }
         start = end_va.into();
     }
-    v
+    Ok(v)
 }
 
-/// translate str of `ptr`(read until '\0') in `token` space
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
+/// translate str of `ptr`(read until '\0') in `token` space, at most `max_len` bytes before
+/// the nul. `EFAULT` if it runs into a page user can't read, `ENAMETOOLONG` if no nul in time.
+pub fn translated_str(token: usize, ptr: *const u8, max_len: usize) -> Result<String, isize> {
     let page_table = PageTable::from_token(token);
     let mut start_va = VirtAddr::from(ptr as usize);
     let mut vpn = start_va.floor();
-    let mut s = String::new();
+    let mut s = Vec::new();
     loop {
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = user_page(&page_table, vpn, false)?;
         let bytes = &ppn.get_bytes_array()[start_va.page_offset()..];
         let slice = match bytes.split_once(|&c| c == 0) {
             Some((v, _)) => v,
             _ => bytes,
         };
-        s.push_str(core::str::from_utf8(slice).unwrap());
+        if s.len() + slice.len() > max_len {
+            return Err(ENAMETOOLONG);
+        }
+        // utf-8 is checked as a whole, a char may straddle pages
+        s.extend_from_slice(slice);
 
         if slice.len() < bytes.len() {
             break;
@@ -243,77 +439,230 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
         vpn.step();
         start_va = vpn.into();
     }
-    s
+    String::from_utf8(s).map_err(|_| EINVAL)
 }
 
-pub fn translate_ref<T>(token: usize, ptr: *const T) -> &'static T {
+/// Whether user can read, or also write if `write`, every page of `[ptr, ptr + len)`.
+pub fn user_accessible(token: usize, ptr: usize, len: usize, write: bool) -> bool {
+    let end = match ptr.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
     let page_table = PageTable::from_token(token);
-    let va = VirtAddr::from(ptr as usize);
-    let pa = page_table.translate_va(va).unwrap();
-    pa.get_ref()
+    let range = VPNRange::new(VirtAddr::from(ptr).floor(), VirtAddr::from(end).ceil());
+    range
+        .into_iter()
+        .all(|vpn| user_page(&page_table, vpn, write).is_ok())
+}
+
+/// Copy user `[ptr, ptr + dst.len())` into `dst`, any page layout. `EFAULT` if user can't
+/// read all of it, nothing is copied then.
+pub fn copy_from_user(token: usize, ptr: *const u8, dst: &mut [u8]) -> Result<(), isize> {
+    let mut offset = 0;
+    for frag in translated_byte_buffer(token, ptr, dst.len(), false)? {
+        dst[offset..offset + frag.len()].copy_from_slice(frag);
+        offset += frag.len();
+    }
+    Ok(())
+}
+
+/// Copy `src` to user `[ptr, ptr + src.len())`, any page layout. `EFAULT` if user can't
+/// write all of it, nothing is copied then.
+pub fn copy_to_user(token: usize, ptr: *mut u8, src: &[u8]) -> Result<(), isize> {
+    let mut offset = 0;
+    for frag in translated_byte_buffer(token, ptr, src.len(), true)? {
+        let len = frag.len();
+        frag.copy_from_slice(&src[offset..offset + len]);
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Read a `T` from user space, it may straddle pages and be misaligned.
+pub fn read_user_obj<T: Copy>(token: usize, ptr: *const T) -> Result<T, isize> {
+    let mut obj = MaybeUninit::<T>::uninit();
+    let dst =
+        unsafe { core::slice::from_raw_parts_mut(obj.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(token, ptr as *const u8, dst)?;
+    Ok(unsafe { obj.assume_init() })
+}
+
+/// Write `obj` to user space, it may straddle pages and be misaligned.
+pub fn write_user_obj<T>(token: usize, ptr: *mut T, obj: &T) -> Result<(), isize> {
+    let src = unsafe { core::slice::from_raw_parts(obj as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(token, ptr as *mut u8, src)
 }
 
-/// for primitive values only
+/// for primitive values only, in a space the kernel set up itself: syscalls go through
+/// `read_user_obj` and `write_user_obj`, a bad pointer of user is `EFAULT` there
 // Q: https://github.com/rcore-os/rCore-Tutorial-Book-v3/issues/55#issuecomment-1568718900
 // A: compiler保证这些值的地址是aligned, 即不会cross page boundary
 // see https://github.com/rcore-os/rCore-Tutorial-v3/pull/80
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
     let page_table = PageTable::from_token(token);
     let va = VirtAddr::from(ptr as usize);
-    let pa = page_table.translate_va(va).unwrap();
+    let pa = translate_user_va(&page_table, va, true).unwrap();
     pa.get_mut()
 }
 
-/// Array of u8 slice that user communicate with os
+/// Array of u8 slice that user communicate with os, one per page touched. Copied to or from
+/// in those chunks, the slices themselves stay private.
 pub struct UserBuffer {
-    pub buffers: Vec<&'static mut [u8]>,
+    buffers: Vec<&'static mut [u8]>,
 }
 impl UserBuffer {
     pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
         Self { buffers }
     }
 
+    /// Buffer of user ranges `(ptr, len)` in `token` space one after another, as readv/writev
+    /// take them, `write` for readv storing into them
+    pub fn from_ranges(
+        token: usize,
+        ranges: &[(usize, usize)],
+        write: bool,
+    ) -> Result<Self, isize> {
+        let mut buffers = Vec::new();
+        for &(ptr, len) in ranges {
+            buffers.extend(translated_byte_buffer(token, ptr as *const u8, len, write)?);
+        }
+        Ok(Self::new(buffers))
+    }
+
     pub fn len(&self) -> usize {
         self.buffers.iter().map(|b| b.len()).sum()
     }
-}
 
-pub struct UserBufferIterator {
-    buffers: Vec<&'static mut [u8]>,
-    curr_buf: usize,
-    pos_in_buf: usize,
-}
+    /// Per-page chunks of the buffer in order
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffers.iter().map(|b| &**b)
+    }
 
-impl IntoIterator for UserBuffer {
-    type Item = *mut u8;
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.buffers.iter_mut().map(|b| &mut **b)
+    }
 
-    type IntoIter = UserBufferIterator;
+    /// Fill the buffer from the start with `src`, as a read hands data to the user. Bytes
+    /// copied, the less of both lengths.
+    pub fn read(&mut self, mut src: &[u8]) -> usize {
+        let mut copied = 0;
+        for chunk in self.chunks_mut() {
+            if src.is_empty() {
+                break;
+            }
+            let n = chunk.len().min(src.len());
+            chunk[..n].copy_from_slice(&src[..n]);
+            src = &src[n..];
+            copied += n;
+        }
+        copied
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        UserBufferIterator {
-            buffers: self.buffers,
-            curr_buf: 0,
-            pos_in_buf: 0,
+    /// Copy the buffer from the start to `dst`, as a write takes data from the user. Bytes
+    /// copied, the less of both lengths.
+    pub fn write_to(&self, mut dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        for chunk in self.chunks() {
+            if dst.is_empty() {
+                break;
+            }
+            let n = chunk.len().min(dst.len());
+            dst[..n].copy_from_slice(&chunk[..n]);
+            dst = &mut dst[n..];
+            copied += n;
         }
+        copied
     }
 }
 
-impl Iterator for UserBufferIterator {
-    type Item = *mut u8;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.curr_buf >= self.buffers.len() {
-            return None;
-        }
+/// Objects and buffers crossing one or two page boundaries at odd offsets.
+#[allow(unused)]
+pub fn user_copy_test() {
+    use super::{MapPermission, MemorySet};
+    use crate::config::PAGE_SIZE;
+
+    let base = 0x1000_0000;
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        base.into(),
+        (base + 3 * PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let token = memory_set.token();
+
+    // array straddling page 0/1, misaligned
+    let mut arr = [0usize; 64];
+    for (i, v) in arr.iter_mut().enumerate() {
+        *v = i * 0x0101_0101;
+    }
+    let ptr = (base + PAGE_SIZE - 101) as *mut [usize; 64];
+    write_user_obj(token, ptr, &arr).unwrap();
+    assert_eq!(read_user_obj(token, ptr as *const [usize; 64]), Ok(arr));
+
+    // buffer spanning all 3 pages
+    let len = 2 * PAGE_SIZE + 300;
+    let src: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let ptr = (base + 123) as *mut u8;
+    copy_to_user(token, ptr, &src).unwrap();
+    let mut dst = vec![0u8; len];
+    copy_from_user(token, ptr, &mut dst).unwrap();
+    assert_eq!(src, dst);
+    // bytes around it untouched
+    let mut edge = [0xffu8; 1];
+    copy_from_user(token, (base + 122) as *const u8, &mut edge).unwrap();
+    assert_eq!(edge[0], 0);
+
+    // running off the area, or wrapping around: nothing copied
+    let mut dst = [0xffu8; 16];
+    let ptr = (base + 3 * PAGE_SIZE - 8) as *const u8;
+    assert_eq!(copy_from_user(token, ptr, &mut dst), Err(EFAULT));
+    assert_eq!(dst, [0xff; 16]);
+    assert_eq!(read_user_obj(token, usize::MAX as *const u64), Err(EFAULT));
+
+    // same through a UserBuffer, chunk by chunk
+    let ptr = (base + PAGE_SIZE - 7) as *const u8;
+    let mut buf =
+        UserBuffer::new(translated_byte_buffer(token, ptr, PAGE_SIZE + 14, true).unwrap());
+    assert_eq!(buf.chunks().count(), 3);
+    assert_eq!(buf.read(&src[..100]), 100);
+    let mut dst = vec![0u8; PAGE_SIZE + 100];
+    assert_eq!(buf.write_to(&mut dst), PAGE_SIZE + 14);
+    assert_eq!(dst[..100], src[..100]);
+
+    println!("user_copy_test passed!");
+}
 
-        let r = &mut self.buffers[self.curr_buf][self.pos_in_buf] as *mut _;
-        // last in curr_buf
-        if self.pos_in_buf + 1 == self.buffers[self.curr_buf].len() {
-            self.pos_in_buf = 0;
-            self.curr_buf += 1;
-        } else {
-            self.pos_in_buf += 1;
-        }
-        Some(r)
+#[allow(unused)]
+pub fn megapage_test() {
+    let free = frame_stats().free;
+    let mut page_table = PageTable::new();
+    let vpn = VirtPageNum(3 * MEGAPAGE_PAGES);
+    let base = 5 * MEGAPAGE_PAGES;
+    page_table.map_megapage(vpn, PhysPageNum(base), PTEFlags::R | PTEFlags::W);
+    let pte = page_table.translate(VirtPageNum(vpn.0 + 7)).unwrap();
+    assert!(pte.is_valid() && pte.writable());
+    assert_eq!(pte.ppn().0, base + 7);
+    assert_eq!(page_table.leaves().len(), 1);
+
+    // the rest stays where it was
+    page_table.unmap(VirtPageNum(vpn.0 + 7));
+    assert!(page_table
+        .translate(VirtPageNum(vpn.0 + 7))
+        .map_or(true, |pte| !pte.is_valid()));
+    assert_eq!(
+        page_table
+            .translate(VirtPageNum(vpn.0 + 8))
+            .unwrap()
+            .ppn()
+            .0,
+        base + 8
+    );
+    assert_eq!(page_table.leaves().len(), MEGAPAGE_PAGES - 1);
+    for i in (0..MEGAPAGE_PAGES).filter(|&i| i != 7) {
+        page_table.unmap(VirtPageNum(vpn.0 + i));
     }
+    assert!(page_table.leaves().is_empty());
+    drop(page_table);
+    assert_eq!(frame_stats().free, free);
+    println!("megapage_test passed!");
 }
@@ -0,0 +1,106 @@
+//! Shared memory: segments of frames any process may map, found by an id they agree on.
+//!
+//! A segment is opened as an fd, which maps it with `shm_map` and keeps it around meanwhile.
+//! The table only knows segments somebody has: once the last fd is closed and the last
+//! mapping unmapped, the frames are freed, and the id opens a new one zeroed.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    config::PAGE_SIZE,
+    fs::File,
+    sync::UPIntrFreeCell,
+    syscall::errno::{EINVAL, ENOMEM},
+};
+
+use super::{
+    frame_allocator::{frame_alloc, frame_stats, FrameTracker},
+    make_room, PhysPageNum, UserBuffer,
+};
+
+pub struct SharedMemory {
+    frames: Vec<FrameTracker>,
+}
+
+lazy_static! {
+    /// segments by id, those nobody has any more are dropped as they're found
+    static ref SHM_TABLE: UPIntrFreeCell<BTreeMap<usize, Weak<SharedMemory>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+impl SharedMemory {
+    /// Bytes of the segment, whole pages
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Frames of the segment in order
+    pub fn ppns(&self) -> impl Iterator<Item = PhysPageNum> + '_ {
+        self.frames.iter().map(|frame| frame.ppn)
+    }
+}
+
+/// Segment `id`, a new one of `len` bytes zeroed if there's none. `EINVAL` if `len` is 0 or
+/// more than the one there has, `ENOMEM` if there aren't enough frames. Nothing may be borrowed
+/// by the caller, room is made for a new one.
+pub fn shm_open(id: usize, len: usize) -> Result<Arc<SharedMemory>, isize> {
+    if len == 0 {
+        return Err(EINVAL);
+    }
+    let mut table = SHM_TABLE.exclusive_access();
+    table.retain(|_, shm| shm.strong_count() > 0);
+    if let Some(shm) = table.get(&id).and_then(Weak::upgrade) {
+        return if len <= shm.size() {
+            Ok(shm)
+        } else {
+            Err(EINVAL)
+        };
+    }
+    drop(table);
+
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    if pages > frame_stats().total {
+        return Err(ENOMEM);
+    }
+    make_room(pages);
+    let frames = (0..pages)
+        .map(|_| frame_alloc())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(ENOMEM)?;
+    // writing pages out may have let somebody else open it meanwhile
+    let mut table = SHM_TABLE.exclusive_access();
+    if let Some(shm) = table.get(&id).and_then(Weak::upgrade) {
+        return if len <= shm.size() {
+            Ok(shm)
+        } else {
+            Err(EINVAL)
+        };
+    }
+    let shm = Arc::new(SharedMemory { frames });
+    table.insert(id, Arc::downgrade(&shm));
+    Ok(shm)
+}
+
+/// The fd of a segment only maps it, it's not read or written through
+impl File for SharedMemory {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
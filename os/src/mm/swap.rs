@@ -0,0 +1,139 @@
+//! Swap: pages of user areas go out to a file under memory pressure and come back in when
+//! touched.
+//!
+//! The swap file is `swap` at the root of the filesystem, made along with the image, each page
+//! of it a slot. Before memory is allocated for user space (page faults, fork, exec)
+//! `make_room` swaps pages out until enough frames are free, picked by a clock over the A bits
+//! of each process in turn (`MemorySet::swap_out`). A page out is unmapped and its area keeps
+//! the slot, the fault of the next access reads it back in. The slot stays with the page while
+//! it's not written to, so it goes out again without being written.
+//!
+//! A page is taken out of its process in one go, and only written once nothing is borrowed:
+//! writing may schedule out, and the process may well run meanwhile. If it faults the page back
+//! in before it's written, the frame is just taken back.
+
+use alloc::{sync::Arc, vec::Vec};
+use easy_fs::Inode;
+use lazy_static::lazy_static;
+
+use crate::{
+    config::{FRAMES_RESERVED, PAGE_SIZE},
+    fs::ROOT_INODE,
+    sync::UPIntrFreeCell,
+};
+
+use super::{
+    address::PhysPageNum,
+    frame_allocator::{frame_stats, FrameTracker},
+};
+
+/// at the root
+const SWAP_FILE: &str = "swap";
+
+struct SwapSpace {
+    file: Option<Arc<Inode>>,
+    /// slots not taken
+    free: Vec<usize>,
+}
+
+lazy_static! {
+    static ref SWAP: UPIntrFreeCell<SwapSpace> = unsafe {
+        UPIntrFreeCell::new(SwapSpace {
+            file: None,
+            free: Vec::new(),
+        })
+    };
+}
+
+/// Take the swap file, as many slots as it has whole pages. Without one pages stay in memory.
+pub fn init_swap() {
+    let file = match ROOT_INODE.find(SWAP_FILE) {
+        Ok(file) if file.is_file() => file,
+        _ => {
+            println!("KERN: no swap file");
+            return;
+        }
+    };
+    let slots = file.get_size() / PAGE_SIZE;
+    println!("KERN: swap on /{}, {} pages", SWAP_FILE, slots);
+    let mut swap = SWAP.exclusive_access();
+    swap.free = (0..slots).rev().collect();
+    swap.file = Some(file);
+}
+
+fn swap_file() -> Arc<Inode> {
+    SWAP.exclusive_access().file.clone().unwrap()
+}
+
+/// A page of the swap file, free again once dropped
+pub struct SwapSlot {
+    index: usize,
+    /// of the page taken out, until it's written
+    frame: UPIntrFreeCell<Option<FrameTracker>>,
+}
+
+impl SwapSlot {
+    /// `None` if swap is full, or there's none
+    pub fn alloc() -> Option<Self> {
+        let index = SWAP.exclusive_access().free.pop()?;
+        Some(Self {
+            index,
+            frame: unsafe { UPIntrFreeCell::new(None) },
+        })
+    }
+
+    /// Hand over `frame` for `write_out` to write
+    pub fn hold(&self, frame: FrameTracker) {
+        *self.frame.exclusive_access() = Some(frame);
+    }
+
+    /// Frame held if it's not written yet, the slot has nothing of the page then
+    pub fn take_back(&self) -> Option<FrameTracker> {
+        self.frame.exclusive_access().take()
+    }
+
+    /// Copy of the page into frame `ppn`, from the frame held if it's not written yet
+    pub fn read(&self, ppn: PhysPageNum) {
+        if let Some(frame) = self.frame.exclusive_access().as_ref() {
+            ppn.get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            return;
+        }
+        swap_file().read_at(self.index * PAGE_SIZE, ppn.get_bytes_array());
+    }
+
+    /// Write the frame held and free it, nothing if it's been taken back meanwhile
+    fn write_out(&self) {
+        let ppn = match self.frame.exclusive_access().as_ref() {
+            Some(frame) => frame.ppn,
+            None => return,
+        };
+        // slots are within the file, it needn't grow, but the frame stays if it can't
+        if swap_file()
+            .write_at(self.index * PAGE_SIZE, ppn.get_bytes_array())
+            .is_ok()
+        {
+            self.frame.exclusive_access().take();
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP.exclusive_access().free.push(self.index);
+    }
+}
+
+/// Swap pages out until `frames` frames are free and `FRAMES_RESERVED` more for the kernel, or
+/// nothing more can go. Nothing may be borrowed by the caller, writing them may schedule out.
+pub fn make_room(frames: usize) {
+    if SWAP.exclusive_access().file.is_none() {
+        return;
+    }
+    while frame_stats().free < frames + FRAMES_RESERVED {
+        match crate::task::swap_out_page() {
+            Some(slot) => slot.write_out(),
+            None => break,
+        }
+    }
+}
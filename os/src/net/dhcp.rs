@@ -0,0 +1,414 @@
+//! Minimal DHCP client (RFC 2131).
+//!
+//! DISCOVER -> OFFER -> REQUEST -> ACK from boot, then REQUEST again at T1 to
+//! renew the lease with its server, and at T2 to rebind it with any server if
+//! that one didn't answer. All messages are broadcast since the stack has no
+//! ARP resolution of its own; that is what QEMU's slirp server expects anyway.
+//!
+//! Nothing here waits: `init` only sends the first DISCOVER, replies come in
+//! through `handle_frame` and timeouts are handled by `check_lease` on timer
+//! ticks. The static address stays in use until an ACK replaces it.
+
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use lose_net_stack::{packets::udp::UDPPacket, IPv4, MacAddress};
+
+use crate::{drivers::NET_DEVICE, sync::UPIntrFreeCell, timer::get_time_ms};
+
+use super::{DEFAULT_IP, LOCAL_MAC, LOSE_NET_STACK};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// fixed bootp header before magic cookie
+const BOOTP_LEN: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+// message types (option 53)
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+// options
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MSG_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAM_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// DISCOVER rounds tried quickly before settling for the static address
+const BOOT_ATTEMPTS: usize = 4;
+/// time a round waits for OFFER/ACK
+const BOOT_WAIT_MS: usize = 1_000;
+/// time between DISCOVER rounds once the quick ones are used up
+const IDLE_RETRY_MS: usize = 60_000;
+/// retransmit interval while renewing or rebinding
+const RETRANSMIT_MS: usize = 5_000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DhcpState {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+/// address configuration handed out by the server
+#[derive(Copy, Clone, Debug)]
+pub struct Lease {
+    pub ip: u32,
+    pub netmask: u32,
+    pub gateway: u32,
+    pub server: u32,
+    /// absolute deadlines in ms
+    pub t1_ms: usize,
+    pub t2_ms: usize,
+    pub expire_ms: usize,
+}
+
+/// options we care about in a reply
+#[derive(Default)]
+struct Reply {
+    msg_type: u8,
+    yiaddr: u32,
+    server: u32,
+    netmask: u32,
+    gateway: u32,
+    lease_secs: Option<u32>,
+    t1_secs: Option<u32>,
+    t2_secs: Option<u32>,
+}
+
+pub struct DhcpClient {
+    state: DhcpState,
+    xid: u32,
+    /// (yiaddr, server id) of the OFFER being requested
+    offer: Option<(u32, u32)>,
+    lease: Option<Lease>,
+    /// next retransmit time in SELECTING/REQUESTING/RENEWING/REBINDING
+    retransmit_ms: usize,
+    /// DISCOVER rounds since the last lease or restart
+    attempts: usize,
+}
+
+lazy_static! {
+    static ref DHCP_CLIENT: UPIntrFreeCell<DhcpClient> =
+        unsafe { UPIntrFreeCell::new(DhcpClient::new()) };
+}
+
+impl DhcpClient {
+    fn new() -> Self {
+        Self {
+            state: DhcpState::Init,
+            xid: 0,
+            offer: None,
+            lease: None,
+            retransmit_ms: 0,
+            attempts: 0,
+        }
+    }
+
+    fn new_xid(&mut self) {
+        let mac = &LOCAL_MAC;
+        let seed = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+        self.xid = seed ^ (crate::timer::get_time() as u32);
+    }
+
+    fn discover(&mut self, now: usize) {
+        self.new_xid();
+        self.offer = None;
+        self.state = DhcpState::Selecting;
+        self.attempts += 1;
+        self.retransmit_ms = now
+            + if self.attempts <= BOOT_ATTEMPTS {
+                BOOT_WAIT_MS
+            } else {
+                IDLE_RETRY_MS
+            };
+        send(build_message(self.xid, DHCPDISCOVER, 0, None, None), 0);
+    }
+
+    /// drop the lease and go back to the static address, then discover again
+    fn restart(&mut self, now: usize) {
+        self.lease = None;
+        self.attempts = 0;
+        set_ip(DEFAULT_IP);
+        self.discover(now);
+    }
+
+    fn request(&mut self, now: usize, yiaddr: u32, server: u32) {
+        self.offer = Some((yiaddr, server));
+        self.state = DhcpState::Requesting;
+        self.retransmit_ms = now + BOOT_WAIT_MS;
+        send(
+            build_message(self.xid, DHCPREQUEST, 0, Some(yiaddr), Some(server)),
+            0,
+        );
+    }
+
+    /// REQUEST in RENEWING/REBINDING only carries ciaddr (RFC 2131 4.3.2), `state` is the
+    /// one of the two to be in
+    fn renew(&mut self, now: usize, state: DhcpState) {
+        let Some(lease) = self.lease else {
+            return;
+        };
+        if self.state != state {
+            self.new_xid();
+            self.state = state;
+        }
+        self.retransmit_ms = now + RETRANSMIT_MS;
+        send(
+            build_message(self.xid, DHCPREQUEST, lease.ip, None, None),
+            lease.ip,
+        );
+    }
+
+    fn on_reply(&mut self, reply: Reply) {
+        let now = get_time_ms();
+        match (self.state, reply.msg_type) {
+            (DhcpState::Selecting, DHCPOFFER) => {
+                if reply.yiaddr != 0 && reply.server != 0 {
+                    self.request(now, reply.yiaddr, reply.server);
+                }
+            }
+            // renewing is with the server of the lease only, rebinding with any (RFC 2131 4.4.5)
+            (DhcpState::Renewing, DHCPACK | DHCPNAK)
+                if self.lease.is_some_and(|lease| lease.server != reply.server) => {}
+            (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, DHCPACK) => {
+                // infinite/missing lease: treat as very long, t1/t2 per RFC defaults
+                let lease_secs = reply.lease_secs.unwrap_or(u32::MAX) as usize;
+                let t1_secs = reply.t1_secs.map_or(lease_secs / 2, |x| x as usize);
+                let t2_secs = reply.t2_secs.map_or(lease_secs / 8 * 7, |x| x as usize);
+                let lease = Lease {
+                    ip: reply.yiaddr,
+                    netmask: reply.netmask,
+                    gateway: reply.gateway,
+                    server: reply.server,
+                    t1_ms: now.saturating_add(t1_secs.saturating_mul(1000)),
+                    t2_ms: now.saturating_add(t2_secs.saturating_mul(1000)),
+                    expire_ms: now.saturating_add(lease_secs.saturating_mul(1000)),
+                };
+                let renewed = matches!(self.state, DhcpState::Renewing | DhcpState::Rebinding);
+                self.lease = Some(lease);
+                self.offer = None;
+                self.state = DhcpState::Bound;
+                self.attempts = 0;
+                apply_lease(&lease, renewed);
+            }
+            (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, DHCPNAK) => {
+                println!("KERN: dhcp NAK, restart discovery");
+                self.restart(now);
+            }
+            _ => {}
+        }
+    }
+
+    fn check_lease(&mut self, now: usize) {
+        if matches!(self.state, DhcpState::Selecting | DhcpState::Requesting)
+            && now >= self.retransmit_ms
+        {
+            if self.attempts == BOOT_ATTEMPTS {
+                println!("KERN: dhcp failed, keep static address and retry");
+            }
+            self.discover(now);
+            return;
+        }
+        let Some(lease) = self.lease else {
+            return;
+        };
+        match self.state {
+            DhcpState::Bound if now >= lease.t1_ms => self.renew(now, DhcpState::Renewing),
+            DhcpState::Renewing | DhcpState::Rebinding if now >= lease.expire_ms => {
+                println!("KERN: dhcp lease expired");
+                self.restart(now);
+            }
+            DhcpState::Renewing if now >= lease.t2_ms => self.renew(now, DhcpState::Rebinding),
+            DhcpState::Renewing | DhcpState::Rebinding if now >= self.retransmit_ms => {
+                self.renew(now, self.state)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn be_u16(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+fn build_message(
+    xid: u32,
+    msg_type: u8,
+    ciaddr: u32,
+    requested_ip: Option<u32>,
+    server: Option<u32>,
+) -> Vec<u8> {
+    let mut msg = alloc::vec![0u8; BOOTP_LEN];
+    msg[0] = BOOTREQUEST;
+    msg[1] = 1; // htype: ethernet
+    msg[2] = 6; // hlen
+    msg[4..8].copy_from_slice(&xid.to_be_bytes());
+    // ask for broadcast replies, we can not receive unicast before being configured
+    if ciaddr == 0 {
+        msg[10] = 0x80;
+    }
+    msg[12..16].copy_from_slice(&ciaddr.to_be_bytes());
+    msg[28..34].copy_from_slice(&LOCAL_MAC);
+
+    msg.extend_from_slice(&MAGIC_COOKIE);
+    msg.extend_from_slice(&[OPT_MSG_TYPE, 1, msg_type]);
+    if let Some(ip) = requested_ip {
+        msg.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+        msg.extend_from_slice(&ip.to_be_bytes());
+    }
+    if let Some(server) = server {
+        msg.extend_from_slice(&[OPT_SERVER_ID, 4]);
+        msg.extend_from_slice(&server.to_be_bytes());
+    }
+    msg.extend_from_slice(&[
+        OPT_PARAM_LIST,
+        3,
+        OPT_SUBNET_MASK,
+        OPT_ROUTER,
+        OPT_LEASE_TIME,
+        OPT_END,
+    ]);
+    msg
+}
+
+fn parse_reply(msg: &[u8], xid: u32) -> Option<Reply> {
+    if msg.len() < BOOTP_LEN + MAGIC_COOKIE.len()
+        || msg[0] != BOOTREPLY
+        || be_u32(&msg[4..8]) != xid
+        || msg[28..34] != LOCAL_MAC
+        || msg[BOOTP_LEN..BOOTP_LEN + 4] != MAGIC_COOKIE
+    {
+        return None;
+    }
+
+    let mut reply = Reply {
+        yiaddr: be_u32(&msg[16..20]),
+        ..Default::default()
+    };
+    let mut opts = &msg[BOOTP_LEN + 4..];
+    while let Some((&code, rest)) = opts.split_first() {
+        match code {
+            OPT_PAD => {
+                opts = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let body = rest.get(..len as usize)?;
+        match (code, len) {
+            (OPT_MSG_TYPE, 1) => reply.msg_type = body[0],
+            (OPT_SERVER_ID, 4) => reply.server = be_u32(body),
+            (OPT_SUBNET_MASK, 4) => reply.netmask = be_u32(body),
+            // first router only
+            (OPT_ROUTER, l) if l >= 4 => reply.gateway = be_u32(body),
+            (OPT_LEASE_TIME, 4) => reply.lease_secs = Some(be_u32(body)),
+            (OPT_RENEWAL_TIME, 4) => reply.t1_secs = Some(be_u32(body)),
+            (OPT_REBINDING_TIME, 4) => reply.t2_secs = Some(be_u32(body)),
+            _ => {}
+        }
+        opts = &rest[len as usize..];
+    }
+    Some(reply)
+}
+
+fn send(msg: Vec<u8>, src_ip: u32) {
+    let udp_packet = UDPPacket::new(
+        IPv4::from_u32(src_ip),
+        MacAddress::new(LOCAL_MAC),
+        CLIENT_PORT,
+        IPv4::new(255, 255, 255, 255),
+        MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        SERVER_PORT,
+        msg.len(),
+        msg.as_ref(),
+    );
+    NET_DEVICE.transmit(&udp_packet.build_data());
+}
+
+fn set_ip(ip: u32) {
+    LOSE_NET_STACK.0.exclusive_access().ip = IPv4::from_u32(ip);
+}
+
+fn apply_lease(lease: &Lease, renewed: bool) {
+    set_ip(lease.ip);
+    if !renewed {
+        let [a, b, c, d] = lease.ip.to_be_bytes();
+        let [ga, gb, gc, gd] = lease.gateway.to_be_bytes();
+        println!(
+            "KERN: dhcp bound {}.{}.{}.{}/{} via {}.{}.{}.{}",
+            a,
+            b,
+            c,
+            d,
+            lease.netmask.count_ones(),
+            ga,
+            gb,
+            gc,
+            gd
+        );
+    }
+}
+
+/// Raw ethernet frame hook, called before the frame reaches the stack: the
+/// replies are addressed to an ip we don't have yet, so the stack would drop them.
+///
+/// Return true if the frame was a DHCP reply and has been consumed.
+pub fn handle_frame(frame: &[u8]) -> bool {
+    const ETH_LEN: usize = 14;
+    // ipv4 + udp
+    if frame.len() < ETH_LEN + 20 + 8 || frame[12..14] != [0x08, 0x00] || frame[ETH_LEN + 9] != 17 {
+        return false;
+    }
+    let udp = ETH_LEN + (frame[ETH_LEN] & 0xf) as usize * 4;
+    if frame.len() < udp + 8 || be_u16(&frame[udp + 2..]) != CLIENT_PORT {
+        return false;
+    }
+    let end = (udp + be_u16(&frame[udp + 4..]) as usize).min(frame.len());
+    let Some(payload) = frame.get(udp + 8..end) else {
+        return true;
+    };
+
+    let mut client = DHCP_CLIENT.exclusive_access();
+    if let Some(reply) = parse_reply(payload, client.xid) {
+        client.on_reply(reply);
+    }
+    true
+}
+
+/// Called on timer ticks to retransmit, and to renew the lease when due.
+pub fn check_lease() {
+    DHCP_CLIENT.exclusive_access().check_lease(get_time_ms());
+}
+
+#[allow(unused)]
+pub fn lease() -> Option<Lease> {
+    DHCP_CLIENT.exclusive_access().lease
+}
+
+/// Start acquiring a lease. Returns right away, the handshake goes on from timer ticks.
+pub fn init() {
+    println!("KERN: init dhcp");
+    DHCP_CLIENT.exclusive_access().discover(get_time_ms());
+}
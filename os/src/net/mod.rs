@@ -1,25 +1,33 @@
+pub mod dhcp;
+pub mod pcap;
 pub mod port_table;
+pub mod rxbuf;
 pub mod socket;
 pub mod tcp;
 pub mod udp;
 
-use alloc::{sync::Arc, vec};
+use alloc::sync::Arc;
 pub use lose_net_stack::IPv4; // re-export
 use lose_net_stack::{results::Packet, LoseStack, MacAddress, TcpFlags};
 use port_table::check_accept;
-use socket::{get_socket, push_data, set_sa_by_index};
+use rxbuf::RxBuffer;
+use socket::{drain_tx, get_socket, push_data, set_sa_by_index, TX_BUDGET};
 
 use crate::{drivers::NET_DEVICE, sync::UPIntrFreeCell};
 
+/// static fallback address 10.0.2.15 when no dhcp lease
+pub const DEFAULT_IP: u32 = 0x0a00_020f;
+pub const LOCAL_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
 pub struct NetStack(UPIntrFreeCell<LoseStack>);
 
 impl NetStack {
     pub fn new() -> Self {
         unsafe {
-            // local always 10.0.2.15 <-> 52:54:00:12:34:56
+            // 10.0.2.15 until dhcp hands out a lease
             NetStack(UPIntrFreeCell::new(LoseStack::new(
-                IPv4::new(10, 0, 2, 15),
-                MacAddress::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
+                IPv4::from_u32(DEFAULT_IP),
+                MacAddress::new(LOCAL_MAC),
             )))
         }
     }
@@ -29,15 +37,57 @@ lazy_static::lazy_static! {
     static ref LOSE_NET_STACK: Arc<NetStack> = Arc::new(NetStack::new());
 }
 
+pub fn init() {
+    rxbuf::init_rx_pool();
+    dhcp::init();
+}
+
+/// Called on timer ticks: lease renewal and paced transmit.
+/// frames taken from the device per timer tick
+const RX_BUDGET: usize = 8;
+
+/// Timer side of the stack: pick up frames nobody waits for (dhcp replies among them),
+/// then run dhcp timeouts and flush queued sends.
+pub fn timer_tick() {
+    if !crate::board::HAS_NET {
+        return;
+    }
+    for _ in 0..RX_BUDGET {
+        if !poll() {
+            break;
+        }
+    }
+    dhcp::check_lease();
+    drain_tx(TX_BUDGET);
+}
+
 pub fn net_interrupt_handler() {
-    let mut recv_buf = vec![0u8; 1024];
+    drain_tx(TX_BUDGET);
 
-    let len = NET_DEVICE.receive(&mut recv_buf);
+    // the device wrote the frame in place, the payload stays there until read();
+    // with no frame to post, packets are dropped until one is recycled
+    if let Some(recv_buf) = NET_DEVICE.receive() {
+        handle_frame(recv_buf);
+    }
+}
 
-    let packet = LOSE_NET_STACK
-        .0
-        .exclusive_access()
-        .analysis(&recv_buf[..len]);
+/// Handle a frame if one has arrived, without waiting for it. Return false if there's none.
+pub fn poll() -> bool {
+    drain_tx(TX_BUDGET);
+
+    let Some(recv_buf) = NET_DEVICE.try_receive() else {
+        return false;
+    };
+    handle_frame(recv_buf);
+    true
+}
+
+fn handle_frame(mut recv_buf: RxBuffer) {
+    if dhcp::handle_frame(&recv_buf) {
+        return;
+    }
+
+    let packet = LOSE_NET_STACK.0.exclusive_access().analysis(&recv_buf);
 
     // println!("[K] receive a packet");
     // hexdump(&recv_buf[..len]);
@@ -57,8 +107,11 @@ pub fn net_interrupt_handler() {
             let lport = udp_packet.dest_port;
             let rport = udp_packet.source_port;
 
+            let payload = recv_buf.range_of(udp_packet.data);
+
             if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, udp_packet.data.to_vec());
+                recv_buf.narrow(payload);
+                push_data(socket_index, recv_buf);
             }
         }
 
@@ -67,6 +120,8 @@ pub fn net_interrupt_handler() {
             let lport = tcp_packet.dest_port;
             let rport = tcp_packet.source_port;
             let flags = tcp_packet.flags;
+            let (seq, ack) = (tcp_packet.seq, tcp_packet.ack);
+            let payload = recv_buf.range_of(tcp_packet.data);
 
             if flags.contains(TcpFlags::S) {
                 // if it has a port to accept, then response the request
@@ -89,8 +144,11 @@ pub fn net_interrupt_handler() {
             }
 
             if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, tcp_packet.data.to_vec());
-                set_sa_by_index(socket_index, tcp_packet.seq, tcp_packet.ack);
+                recv_buf.narrow(payload);
+                // dropped on full rcvbuf: keep seq/ack so the peer resends it
+                if push_data(socket_index, recv_buf) {
+                    set_sa_by_index(socket_index, seq, ack);
+                }
             }
         }
         _ => {}
@@ -0,0 +1,160 @@
+//! Packet capture tap exported in pcap format through `/dev/pcap0`.
+//!
+//! Capturing is on while at least one `/dev/pcap0` is open. Frames seen by the
+//! net device are kept in a bounded ring, oldest records are dropped when full.
+//! Reads drain the ring (single consumer), the first read of each open file
+//! starts with the pcap global header so the output can be saved as-is.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+
+use crate::{fs::File, mm::UserBuffer, sync::UPIntrFreeCell, timer::get_time_us};
+
+pub const PCAP_DEV_PATH: &str = "/dev/pcap0";
+
+/// bytes of records held before dropping the oldest
+const RING_CAPACITY: usize = 64 * 1024;
+/// max bytes kept per frame
+const SNAPLEN: usize = 1514;
+const LINKTYPE_ETHERNET: u32 = 1;
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+struct PcapRing {
+    /// open `/dev/pcap0` count, capture enabled if nonzero
+    readers: usize,
+    records: VecDeque<Vec<u8>>,
+    /// total bytes in `records`
+    size: usize,
+    dropped: usize,
+}
+
+lazy_static! {
+    static ref PCAP_RING: UPIntrFreeCell<PcapRing> = unsafe {
+        UPIntrFreeCell::new(PcapRing {
+            readers: 0,
+            records: VecDeque::new(),
+            size: 0,
+            dropped: 0,
+        })
+    };
+}
+
+/// Tap point for RX/TX frames, no-op unless `/dev/pcap0` is open.
+pub fn capture(frame: &[u8]) {
+    let mut ring = PCAP_RING.exclusive_access();
+    if ring.readers == 0 {
+        return;
+    }
+
+    let us = get_time_us();
+    let incl_len = frame.len().min(SNAPLEN);
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + incl_len);
+    record.extend_from_slice(&((us / 1_000_000) as u32).to_le_bytes());
+    record.extend_from_slice(&((us % 1_000_000) as u32).to_le_bytes());
+    record.extend_from_slice(&(incl_len as u32).to_le_bytes());
+    record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    record.extend_from_slice(&frame[..incl_len]);
+
+    while ring.size + record.len() > RING_CAPACITY {
+        match ring.records.pop_front() {
+            Some(old) => {
+                ring.size -= old.len();
+                ring.dropped += 1;
+            }
+            None => break,
+        }
+    }
+    ring.size += record.len();
+    ring.records.push_back(record);
+}
+
+fn global_header() -> [u8; GLOBAL_HEADER_LEN] {
+    let mut hdr = [0u8; GLOBAL_HEADER_LEN];
+    hdr[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    hdr[4..6].copy_from_slice(&2u16.to_le_bytes()); // version 2.4
+    hdr[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // thiszone & sigfigs stay 0
+    hdr[16..20].copy_from_slice(&(SNAPLEN as u32).to_le_bytes());
+    hdr[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    hdr
+}
+
+pub struct PcapFile {
+    /// bytes taken from the ring but not yet read
+    pending: UPIntrFreeCell<VecDeque<u8>>,
+}
+
+impl PcapFile {
+    pub fn new() -> Self {
+        PCAP_RING.exclusive_access().readers += 1;
+        Self {
+            pending: unsafe { UPIntrFreeCell::new(global_header().into_iter().collect()) },
+        }
+    }
+}
+
+/// Open `/dev/pcap0` if `path` names it.
+pub fn open_pcap(path: &str) -> Option<Arc<PcapFile>> {
+    (path == PCAP_DEV_PATH).then(|| Arc::new(PcapFile::new()))
+}
+
+impl File for PcapFile {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// non-blocking: 0 once everything captured so far has been read
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut pending = self.pending.exclusive_access();
+        let want = buf.len();
+        {
+            let mut ring = PCAP_RING.exclusive_access();
+            while pending.len() < want {
+                match ring.records.pop_front() {
+                    Some(record) => {
+                        ring.size -= record.len();
+                        pending.extend(record);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let mut read_size = 0usize;
+        for b in buf.chunks_mut() {
+            let n = b.len().min(pending.len());
+            for (dst, src) in b[..n].iter_mut().zip(pending.drain(..n)) {
+                *dst = src;
+            }
+            read_size += n;
+            if pending.is_empty() {
+                break;
+            }
+        }
+        read_size
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        panic!("Cannot write to pcap device!");
+    }
+}
+
+impl Drop for PcapFile {
+    fn drop(&mut self) {
+        let mut ring = PCAP_RING.exclusive_access();
+        ring.readers -= 1;
+        if ring.readers == 0 {
+            if ring.dropped > 0 {
+                println!("KERN: pcap dropped {} frames", ring.dropped);
+            }
+            ring.records.clear();
+            ring.size = 0;
+            ring.dropped = 0;
+        }
+    }
+}
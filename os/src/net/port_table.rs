@@ -70,7 +70,14 @@ pub fn check_accept(port: u16, tcp_packet: &TCPPacket) -> Option<()> {
 pub fn accept_connection(_port: u16, tcp_packet: &TCPPacket, task: Arc<TaskControlBlock>) {
     let process = task.process.upgrade().unwrap();
     let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
+    let trap_cx = task.inner_exclusive_access().get_trap_cx();
+    let fd = match inner.alloc_fd() {
+        Ok(fd) => fd,
+        Err(err) => {
+            trap_cx.x[10] = err as usize;
+            return;
+        }
+    };
 
     let tcp_socket = TCP::new(
         tcp_packet.source_ip,
@@ -80,8 +87,6 @@ pub fn accept_connection(_port: u16, tcp_packet: &TCPPacket, task: Arc<TaskContr
         tcp_packet.ack,
     );
     inner.fd_table[fd] = Some(Arc::new(tcp_socket));
-
-    let trap_cx = task.inner_exclusive_access().get_trap_cx();
     trap_cx.x[10] = fd; // return fd
 }
 
@@ -0,0 +1,97 @@
+//! Frame-backed receive buffers.
+//!
+//! Pooled frames are posted on the device's receive queue as they are, the
+//! device writes a packet straight into one and the driver hands it up as an
+//! `RxBuffer`. The stack only records where the payload is, and the socket
+//! keeps the frame until `read()` copies the payload to user space. Dropping
+//! the buffer hands the frame back to the pool and the device posts it again.
+
+use alloc::vec::Vec;
+use core::ops::{Deref, Range};
+use lazy_static::lazy_static;
+
+use crate::{
+    drivers::NET_DEVICE,
+    mm::{frame_alloc, FrameTracker},
+    sync::UPIntrFreeCell,
+};
+
+/// frames kept around for reuse, extras go back to the frame allocator
+const RX_POOL_SIZE: usize = 32;
+
+lazy_static! {
+    static ref RX_POOL: UPIntrFreeCell<Vec<FrameTracker>> =
+        unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+/// Fill the pool ahead so the rx path doesn't hit the frame allocator.
+pub fn init_rx_pool() {
+    let mut pool = RX_POOL.exclusive_access();
+    while pool.len() < RX_POOL_SIZE {
+        match frame_alloc() {
+            Some(frame) => pool.push(frame),
+            None => break,
+        }
+    }
+}
+
+/// Take a frame from the pool for the device to receive into, or a fresh one if the pool ran
+/// dry. `None` when memory is out too: the frame isn't posted and the device drops packets
+/// until a buffer comes back.
+pub fn alloc_frame() -> Option<FrameTracker> {
+    RX_POOL.exclusive_access().pop().or_else(frame_alloc)
+}
+
+/// One received frame, `range` is the part of it still of interest.
+pub struct RxBuffer {
+    frame: Option<FrameTracker>,
+    range: Range<usize>,
+}
+
+impl RxBuffer {
+    /// `frame` as filled by the device, `range` is the packet in it.
+    pub fn new(frame: FrameTracker, range: Range<usize>) -> Self {
+        Self {
+            frame: Some(frame),
+            range,
+        }
+    }
+
+    /// Position of `sub` inside this buffer, `sub` must be a subslice of it
+    /// (e.g. the payload the stack parsed out of it).
+    pub fn range_of(&self, sub: &[u8]) -> Range<usize> {
+        if sub.is_empty() {
+            return 0..0;
+        }
+        let start = sub.as_ptr() as usize - self.deref().as_ptr() as usize;
+        assert!(start + sub.len() <= self.range.len());
+        start..(start + sub.len())
+    }
+
+    /// Narrow down to `range` as returned by `range_of`.
+    pub fn narrow(&mut self, range: Range<usize>) {
+        let base = self.range.start;
+        self.range = (base + range.start)..(base + range.end);
+    }
+}
+
+impl Deref for RxBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.frame.as_ref().unwrap().ppn.get_bytes_array()[self.range.clone()]
+    }
+}
+
+impl Drop for RxBuffer {
+    fn drop(&mut self) {
+        let frame = self.frame.take().unwrap();
+        let mut pool = RX_POOL.exclusive_access();
+        if pool.len() < RX_POOL_SIZE {
+            pool.push(frame);
+        }
+        // else frame dropped -> dealloc
+        drop(pool);
+        NET_DEVICE.refill();
+    }
+}
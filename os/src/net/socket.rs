@@ -2,7 +2,23 @@ use alloc::{collections::VecDeque, vec::Vec};
 use lazy_static::lazy_static;
 use lose_net_stack::IPv4;
 
-use crate::sync::UPIntrFreeCell;
+use crate::{drivers::NET_DEVICE, sync::UPIntrFreeCell, task::suspend_current_and_run_next};
+
+use super::net_interrupt_handler;
+
+use super::rxbuf::RxBuffer;
+
+/// default per-socket queue limits in bytes
+pub const DEFAULT_RCVBUF: usize = 16 * 1024;
+pub const DEFAULT_SNDBUF: usize = 16 * 1024;
+/// frames handed to the device per drain, paces writers against the device
+pub const TX_BUDGET: usize = 4;
+
+// setsockopt/getsockopt options
+pub const SO_SNDBUF: usize = 7;
+pub const SO_RCVBUF: usize = 8;
+/// same value as O_NONBLOCK, sockets carry it until fds have status flags
+pub const SO_NONBLOCK: usize = 0o4000;
 
 pub struct Socket {
     // remote addr
@@ -11,12 +27,22 @@ pub struct Socket {
     pub lport: u16,
     // remote port
     pub rport: u16,
-    // data frames
-    pub buffers: VecDeque<Vec<u8>>,
+    // received frames, payload only
+    pub buffers: VecDeque<RxBuffer>,
     // pack seq
     pub seq: u32,
     // pack ack
     pub ack: u32,
+    // payload bytes in `buffers`
+    pub rx_size: usize,
+    pub rcvbuf: usize,
+    // built frames waiting for the device
+    pub tx_frames: VecDeque<Vec<u8>>,
+    // bytes in `tx_frames`
+    pub tx_size: usize,
+    pub sndbuf: usize,
+    // return EAGAIN instead of blocking
+    pub nonblock: bool,
 }
 
 lazy_static! {
@@ -70,6 +96,12 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
         buffers: VecDeque::new(),
         seq: 0,
         ack: 0,
+        rx_size: 0,
+        rcvbuf: DEFAULT_RCVBUF,
+        tx_frames: VecDeque::new(),
+        tx_size: 0,
+        sndbuf: DEFAULT_SNDBUF,
+        nonblock: false,
     };
     let mut socket_table = SOCKET_TABLE.exclusive_access();
     match socket_table.iter().position(Option::is_none) {
@@ -87,19 +119,156 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
 pub fn remove_socket(idx: usize) {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
     assert!(idx < socket_table.len());
-    socket_table[idx] = None;
+    // don't lose what was written before close
+    let pending = socket_table[idx]
+        .take()
+        .map(|sock| sock.tx_frames)
+        .unwrap_or_default();
+    drop(socket_table);
+    for frame in pending {
+        NET_DEVICE.transmit(&frame);
+    }
 }
 
-pub fn push_data(idx: usize, data: Vec<u8>) {
+/// queue received payload, false if the receive buffer is full and it's dropped
+pub fn push_data(idx: usize, data: RxBuffer) -> bool {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
     assert!(idx < socket_table.len());
     let sock = socket_table[idx].as_mut().expect("sock not exist");
+    if sock.rx_size + data.len() > sock.rcvbuf {
+        return false;
+    }
+    sock.rx_size += data.len();
     sock.buffers.push_back(data);
+    drop(socket_table);
+    crate::fs::notify_poll();
+    true
+}
+
+/// received payload is queued, the device isn't asked for more
+pub fn has_data(idx: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+    assert!(idx < socket_table.len());
+    socket_table[idx]
+        .as_ref()
+        .map_or(false, |sock| !sock.buffers.is_empty())
+}
+
+/// `push_tx` would take a frame
+pub fn can_send(idx: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+    assert!(idx < socket_table.len());
+    socket_table[idx].as_ref().map_or(false, |sock| {
+        sock.tx_frames.is_empty() || sock.tx_size < sock.sndbuf
+    })
 }
 
-pub fn pop_data(idx: usize) -> Option<Vec<u8>> {
+pub fn pop_data(idx: usize) -> Option<RxBuffer> {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
     assert!(idx < socket_table.len());
     let sock = socket_table[idx].as_mut().expect("sock not exist");
-    sock.buffers.pop_front()
+    let data = sock.buffers.pop_front()?;
+    sock.rx_size -= data.len();
+    Some(data)
+}
+
+/// queue a built frame for transmit, give it back if the send buffer is full
+///
+/// an empty queue always takes the frame, so writes larger than `sndbuf` still go out
+pub fn push_tx(idx: usize, frame: Vec<u8>) -> Result<(), Vec<u8>> {
+    let mut socket_table = SOCKET_TABLE.exclusive_access();
+    assert!(idx < socket_table.len());
+    let sock = socket_table[idx].as_mut().expect("sock not exist");
+    if !sock.tx_frames.is_empty() && sock.tx_size + frame.len() > sock.sndbuf {
+        return Err(frame);
+    }
+    sock.tx_size += frame.len();
+    sock.tx_frames.push_back(frame);
+    Ok(())
+}
+
+/// hand at most `budget` queued frames to the device, round robin over sockets
+pub fn drain_tx(budget: usize) {
+    let mut frames = Vec::new();
+    SOCKET_TABLE.exclusive_session(|socket_table| {
+        while frames.len() < budget {
+            let before = frames.len();
+            for sock in socket_table.iter_mut().flatten() {
+                if frames.len() == budget {
+                    break;
+                }
+                if let Some(frame) = sock.tx_frames.pop_front() {
+                    sock.tx_size -= frame.len();
+                    frames.push(frame);
+                }
+            }
+            if frames.len() == before {
+                break;
+            }
+        }
+    });
+    for frame in frames {
+        NET_DEVICE.transmit(&frame);
+    }
+}
+
+pub fn is_nonblocking(idx: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+    assert!(idx < socket_table.len());
+    socket_table[idx]
+        .as_ref()
+        .map_or(false, |sock| sock.nonblock)
+}
+
+pub fn set_sockopt(idx: usize, opt: usize, val: usize) -> Option<()> {
+    let mut socket_table = SOCKET_TABLE.exclusive_access();
+    let sock = socket_table.get_mut(idx)?.as_mut()?;
+    match opt {
+        SO_SNDBUF if val > 0 => sock.sndbuf = val,
+        SO_RCVBUF if val > 0 => sock.rcvbuf = val,
+        SO_NONBLOCK => sock.nonblock = val != 0,
+        _ => return None,
+    }
+    Some(())
+}
+
+pub fn get_sockopt(idx: usize, opt: usize) -> Option<usize> {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+    let sock = socket_table.get(idx)?.as_ref()?;
+    match opt {
+        SO_SNDBUF => Some(sock.sndbuf),
+        SO_RCVBUF => Some(sock.rcvbuf),
+        SO_NONBLOCK => Some(sock.nonblock as usize),
+        _ => None,
+    }
+}
+
+/// next received payload, polling the device while empty; None if non-blocking and empty
+pub fn recv_data(idx: usize) -> Option<RxBuffer> {
+    loop {
+        if let Some(data) = pop_data(idx) {
+            return Some(data);
+        }
+        if is_nonblocking(idx) {
+            return None;
+        }
+        net_interrupt_handler();
+    }
+}
+
+/// queue `frame`, waiting for the send buffer to drain; false if non-blocking and full
+pub fn send_frame(idx: usize, mut frame: Vec<u8>) -> bool {
+    loop {
+        match push_tx(idx, frame) {
+            Ok(()) => return true,
+            Err(f) => {
+                if is_nonblocking(idx) {
+                    return false;
+                }
+                frame = f;
+                drain_tx(TX_BUDGET);
+                suspend_current_and_run_next();
+            }
+        }
+    }
 }
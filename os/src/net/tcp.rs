@@ -1,11 +1,12 @@
 use alloc::vec;
 use lose_net_stack::{packets::tcp::TCPPacket, IPv4, MacAddress, TcpFlags};
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::{fs::File, syscall::errno::EAGAIN};
 
 use super::{
-    net_interrupt_handler,
-    socket::{add_socket, get_sa_by_index, pop_data, remove_socket},
+    socket::{
+        add_socket, can_send, get_sa_by_index, has_data, recv_data, remove_socket, send_frame,
+    },
     LOSE_NET_STACK,
 };
 
@@ -45,37 +46,19 @@ impl File for TCP {
     }
 
     fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.sock_idx) {
-                let total = data.len();
-                let mut copied = 0;
-                for b in buf.buffers.iter_mut() {
-                    let to_copy = b.len().min(total - copied);
-                    b[..to_copy].copy_from_slice(&data[copied..(copied + to_copy)]);
-                    copied += to_copy;
-                    if copied == total {
-                        break;
-                    }
-                }
-                return copied;
-            } else {
-                net_interrupt_handler();
-            }
-        }
+        let Some(data) = recv_data(self.sock_idx) else {
+            return EAGAIN as usize;
+        };
+        buf.read(&data)
     }
 
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
-
         let mut data = vec![0u8; buf.len()];
-
-        let mut copied = 0;
-        for b in buf.buffers.iter() {
-            data[copied..(copied + b.len())].copy_from_slice(b);
-            copied += b.len();
-        }
+        buf.write_to(&mut data);
         let total = data.len();
 
+        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
+
         let (ack, seq) = get_sa_by_index(self.sock_idx).unwrap_or((0, 0));
         let tcp_packet = TCPPacket {
             source_ip: lose_net_stack.ip,
@@ -92,9 +75,22 @@ impl File for TCP {
             urg: 0,
             data: data.as_ref(),
         };
-        NET_DEVICE.transmit(&tcp_packet.build_data());
+        let frame = tcp_packet.build_data();
+        drop(lose_net_stack);
+
+        if !send_frame(self.sock_idx, frame) {
+            return EAGAIN as usize;
+        }
         total
     }
+    /// only what's been received already, nothing polls the device meanwhile
+    fn poll_read_ready(&self) -> bool {
+        has_data(self.sock_idx)
+    }
+
+    fn poll_write_ready(&self) -> bool {
+        can_send(self.sock_idx)
+    }
 }
 
 impl Drop for TCP {
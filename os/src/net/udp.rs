@@ -1,11 +1,10 @@
 use alloc::vec;
 use lose_net_stack::{packets::udp::UDPPacket, IPv4, MacAddress};
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::{fs::File, syscall::errno::EAGAIN};
 
 use super::{
-    net_interrupt_handler,
-    socket::{add_socket, pop_data, remove_socket},
+    socket::{add_socket, can_send, has_data, recv_data, remove_socket, send_frame},
     LOSE_NET_STACK,
 };
 
@@ -38,37 +37,19 @@ impl File for UDP {
     }
 
     fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.sock_idx) {
-                let total = data.len();
-                let mut copied = 0;
-                for b in buf.buffers.iter_mut() {
-                    let to_copy = b.len().min(total - copied);
-                    b[..to_copy].copy_from_slice(&data[copied..(copied + to_copy)]);
-                    copied += to_copy;
-                    if copied == total {
-                        break;
-                    }
-                }
-                return copied;
-            } else {
-                net_interrupt_handler();
-            }
-        }
+        let Some(data) = recv_data(self.sock_idx) else {
+            return EAGAIN as usize;
+        };
+        buf.read(&data)
     }
 
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
-
         let mut data = vec![0u8; buf.len()];
-
-        let mut copied = 0;
-        for b in buf.buffers.iter() {
-            data[copied..(copied + b.len())].copy_from_slice(b);
-            copied += b.len();
-        }
+        buf.write_to(&mut data);
         let total = data.len();
 
+        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
+
         let udp_packet = UDPPacket::new(
             lose_net_stack.ip,
             lose_net_stack.mac,
@@ -79,9 +60,22 @@ impl File for UDP {
             total,
             data.as_ref(),
         );
-        NET_DEVICE.transmit(&udp_packet.build_data());
+        let frame = udp_packet.build_data();
+        drop(lose_net_stack);
+
+        if !send_frame(self.sock_idx, frame) {
+            return EAGAIN as usize;
+        }
         total
     }
+    /// only what's been received already, nothing polls the device meanwhile
+    fn poll_read_ready(&self) -> bool {
+        has_data(self.sock_idx)
+    }
+
+    fn poll_write_ready(&self) -> bool {
+        can_send(self.sock_idx)
+    }
 }
 
 impl Drop for UDP {
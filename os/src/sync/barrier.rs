@@ -0,0 +1,68 @@
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+
+use crate::task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock};
+
+use super::UPIntrFreeCell;
+
+/// Blocks threads until `count` of them have arrived, then releases them all at once and
+/// starts over for the next generation.
+pub struct Barrier {
+    pub inner: UPIntrFreeCell<BarrierInner>,
+}
+
+pub struct BarrierInner {
+    pub count: usize,
+    /// arrived in current generation
+    pub arrived: usize,
+    pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Barrier {
+    pub fn new(count: usize) -> Self {
+        Self {
+            inner: unsafe {
+                UPIntrFreeCell::new(BarrierInner {
+                    count,
+                    arrived: 0,
+                    wait_queue: VecDeque::new(),
+                })
+            },
+        }
+    }
+
+    /// Return true to exactly one thread per generation, the last to arrive.
+    pub fn wait(&self) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        inner.arrived += 1;
+        if inner.arrived == inner.count {
+            inner.arrived = 0;
+            for task in inner.wait_queue.drain(..) {
+                wakeup_task(task);
+            }
+            true
+        } else {
+            inner.wait_queue.push_back(current_task().unwrap());
+            drop(inner);
+            block_current_and_run_next();
+            false
+        }
+    }
+
+    pub fn busy(&self) -> bool {
+        !self.inner.exclusive_access().wait_queue.is_empty()
+    }
+
+    /// forget all waiters, their process is gone
+    pub fn clear_waiters(&self) {
+        self.inner.exclusive_access().wait_queue.clear();
+    }
+
+    /// forget waiter `task`, it's been killed, and no longer counts as arrived
+    pub fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        let mut inner = self.inner.exclusive_access();
+        let len = inner.wait_queue.len();
+        inner.wait_queue.retain(|t| !Arc::ptr_eq(t, task));
+        let removed = len - inner.wait_queue.len();
+        inner.arrived -= removed;
+    }
+}
@@ -33,6 +33,23 @@ impl Condvar {
         }
     }
 
+    pub fn busy(&self) -> bool {
+        !self.inner.exclusive_access().wait_queue.is_empty()
+    }
+
+    /// forget all waiters, their process is gone
+    pub fn clear_waiters(&self) {
+        self.inner.exclusive_access().wait_queue.clear();
+    }
+
+    /// forget waiter `task`, it's been killed
+    pub fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.inner
+            .exclusive_access()
+            .wait_queue
+            .retain(|t| !Arc::ptr_eq(t, task));
+    }
+
     pub fn wait(&self, mutex: Arc<dyn Mutex>) {
         mutex.unlock();
         let mut inner = self.inner.exclusive_access();
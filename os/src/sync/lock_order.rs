@@ -0,0 +1,69 @@
+//! Order the scheduler's `UPIntrFreeCell`s are borrowed in, from the outermost in:
+//!
+//! 1. `Process`: a `ProcessControlBlockInner`. Others of the same level nest, like parent and
+//!    child, or init adopting orphans.
+//! 2. `TaskManager`: the ready queue, which looks into the threads queued
+//! 3. `Task`: a `TaskControlBlockInner`. A `TaskUserRes` dropped borrows its process, so it
+//!    can't go while the thread is borrowed.
+//! 4. `Processor`: borrowed last and briefly, to tell what's running
+//!
+//! Nothing blocks on a single hart, but these cells stand where locks would on more, and paths
+//! taking them in different orders are where one borrow ends up nested in another of the same
+//! cell. With feature `lock_order`, a borrow out of order panics telling where both borrows
+//! were made, backtrace and all. Borrows by `try_exclusive_access` can't panic, they're left
+//! out.
+
+#[cfg(feature = "lock_order")]
+use alloc::vec::Vec;
+#[cfg(feature = "lock_order")]
+use core::panic::Location;
+#[cfg(feature = "lock_order")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "lock_order")]
+use super::up::UPSafeCellRaw;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LockLevel {
+    Process,
+    TaskManager,
+    Task,
+    Processor,
+}
+
+/// A borrow in the order not dropped yet
+#[cfg(feature = "lock_order")]
+struct Held {
+    level: LockLevel,
+    /// address of the cell
+    cell: usize,
+    at: &'static Location<'static>,
+}
+
+#[cfg(feature = "lock_order")]
+lazy_static! {
+    /// in the order borrowed, only touched with interrupts masked
+    static ref HELD: UPSafeCellRaw<Vec<Held>> = unsafe { UPSafeCellRaw::new(Vec::new()) };
+}
+
+/// Record `cell` at `level` borrowed `at`, panic if something later in the order is held.
+#[cfg(feature = "lock_order")]
+pub fn acquire(level: LockLevel, cell: usize, at: &'static Location<'static>) {
+    let held = HELD.get_mut();
+    if let Some(inner) = held.iter().rev().find(|held| held.level > level) {
+        panic!(
+            "lock order: {:?} borrowed at {} while {:?} is, borrowed at {}",
+            level, at, inner.level, inner.at
+        );
+    }
+    held.push(Held { level, cell, at });
+}
+
+/// Forget the borrow of `cell`, the last one if it's nested in itself somehow
+#[cfg(feature = "lock_order")]
+pub fn release(cell: usize) {
+    let held = HELD.get_mut();
+    if let Some(i) = held.iter().rposition(|held| held.cell == cell) {
+        held.remove(i);
+    }
+}
@@ -1,6 +1,9 @@
 mod up;
 pub use up::{UPIntrFreeCell, UPIntrRefMut};
 
+mod lock_order;
+pub use lock_order::LockLevel;
+
 mod mutex;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 
@@ -9,3 +12,6 @@ pub use semaphore::Semaphore;
 
 mod condvar;
 pub use condvar::Condvar;
+
+mod barrier;
+pub use barrier::Barrier;
@@ -10,6 +10,12 @@ use super::UPIntrFreeCell;
 pub trait Mutex: Sync + Send {
     fn lock(&self);
     fn unlock(&self);
+    /// locked or being waited for, can't be destroyed
+    fn busy(&self) -> bool;
+    /// forget all waiters, their process is gone
+    fn clear_waiters(&self) {}
+    /// forget waiter `task`, it's been killed
+    fn remove_waiter(&self, _task: &Arc<TaskControlBlock>) {}
 }
 
 /// based on yield
@@ -44,6 +50,10 @@ impl Mutex for MutexSpin {
         let mut locked = self.locked.exclusive_access();
         *locked = false;
     }
+
+    fn busy(&self) -> bool {
+        *self.locked.exclusive_access()
+    }
 }
 
 /// based on thread blocking
@@ -90,4 +100,20 @@ impl Mutex for MutexBlocking {
             inner.locked = false;
         }
     }
+
+    fn busy(&self) -> bool {
+        let inner = self.inner.exclusive_access();
+        inner.locked || !inner.wait_queue.is_empty()
+    }
+
+    fn clear_waiters(&self) {
+        self.inner.exclusive_access().wait_queue.clear();
+    }
+
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.inner
+            .exclusive_access()
+            .wait_queue
+            .retain(|t| !Arc::ptr_eq(t, task));
+    }
 }
@@ -43,4 +43,22 @@ impl Semaphore {
             block_current_and_run_next();
         }
     }
+
+    pub fn busy(&self) -> bool {
+        !self.inner.exclusive_access().wait_queue.is_empty()
+    }
+
+    /// forget all waiters, their process is gone
+    pub fn clear_waiters(&self) {
+        self.inner.exclusive_access().wait_queue.clear();
+    }
+
+    /// forget waiter `task`, it's been killed, and give back the unit it took in `down`
+    pub fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        let mut inner = self.inner.exclusive_access();
+        let len = inner.wait_queue.len();
+        inner.wait_queue.retain(|t| !Arc::ptr_eq(t, task));
+        let removed = len - inner.wait_queue.len();
+        inner.count += removed as isize;
+    }
 }
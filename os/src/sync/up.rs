@@ -5,6 +5,8 @@ use core::{
 use lazy_static::lazy_static;
 use riscv::register::sstatus;
 
+use super::LockLevel;
+
 pub struct UPSafeCellRaw<T> {
     inner: UnsafeCell<T>,
 }
@@ -63,29 +65,76 @@ impl IntrMaskingInfo {
 
 pub struct UPIntrFreeCell<T> {
     inner: RefCell<T>,
+    /// where it stands in the lock order, if anywhere
+    #[cfg_attr(not(feature = "lock_order"), allow(dead_code))]
+    level: Option<LockLevel>,
 }
 
 unsafe impl<T> Sync for UPIntrFreeCell<T> {}
 
-pub struct UPIntrRefMut<'a, T>(Option<RefMut<'a, T>>);
+pub struct UPIntrRefMut<'a, T> {
+    inner: Option<RefMut<'a, T>>,
+    /// cell borrowed, if it's in the lock order
+    #[cfg(feature = "lock_order")]
+    cell: Option<usize>,
+}
 
 impl<T> UPIntrFreeCell<T> {
     pub unsafe fn new(value: T) -> Self {
         Self {
             inner: RefCell::new(value),
+            level: None,
+        }
+    }
+
+    /// Like `new`, for a cell borrowed at `level` of the lock order
+    pub unsafe fn with_level(value: T, level: LockLevel) -> Self {
+        Self {
+            inner: RefCell::new(value),
+            level: Some(level),
         }
     }
 
-    /// Panic if the data has been borrowed.
+    /// Panic if the data has been borrowed, or with feature `lock_order`, if it's borrowed
+    /// out of order.
+    #[track_caller]
     pub fn exclusive_access(&self) -> UPIntrRefMut<'_, T> {
         INTR_MASKING_INFO.get_mut().enter();
-        UPIntrRefMut(Some(
-            self.inner
-                .try_borrow_mut()
-                .expect(core::any::type_name::<T>()),
-        ))
+        #[cfg(feature = "lock_order")]
+        let cell = self.level.map(|level| {
+            let cell = self as *const Self as usize;
+            super::lock_order::acquire(level, cell, core::panic::Location::caller());
+            cell
+        });
+        UPIntrRefMut {
+            inner: Some(
+                self.inner
+                    .try_borrow_mut()
+                    .expect(core::any::type_name::<T>()),
+            ),
+            #[cfg(feature = "lock_order")]
+            cell,
+        }
     }
 
+    /// `None` if the data has been borrowed, for where panicking over it won't do. Never out
+    /// of the lock order for that matter.
+    pub fn try_exclusive_access(&self) -> Option<UPIntrRefMut<'_, T>> {
+        INTR_MASKING_INFO.get_mut().enter();
+        match self.inner.try_borrow_mut() {
+            Ok(inner) => Some(UPIntrRefMut {
+                inner: Some(inner),
+                #[cfg(feature = "lock_order")]
+                cell: None,
+            }),
+            Err(_) => {
+                INTR_MASKING_INFO.get_mut().exit();
+                None
+            }
+        }
+    }
+
+    #[track_caller]
     pub fn exclusive_session<F, V>(&self, f: F) -> V
     where
         F: FnOnce(&mut T) -> V,
@@ -97,7 +146,11 @@ impl<T> UPIntrFreeCell<T> {
 
 impl<'a, T> Drop for UPIntrRefMut<'a, T> {
     fn drop(&mut self) {
-        self.0 = None;
+        self.inner = None;
+        #[cfg(feature = "lock_order")]
+        if let Some(cell) = self.cell {
+            super::lock_order::release(cell);
+        }
         INTR_MASKING_INFO.get_mut().exit();
     }
 }
@@ -105,11 +158,11 @@ impl<'a, T> Drop for UPIntrRefMut<'a, T> {
 impl<'a, T> Deref for UPIntrRefMut<'a, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref().unwrap().deref()
+        self.inner.as_ref().unwrap().deref()
     }
 }
 impl<'a, T> DerefMut for UPIntrRefMut<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.0.as_mut().unwrap().deref_mut()
+        self.inner.as_mut().unwrap().deref_mut()
     }
 }
@@ -0,0 +1,65 @@
+//! Errnos returned by syscalls, negated as they reach user space. Each is defined here once,
+//! what it means for a given syscall is told where it's returned.
+
+/// Operation not permitted
+pub const EPERM: isize = -1;
+/// No such file or directory
+pub const ENOENT: isize = -2;
+/// No such thread or process
+pub const ESRCH: isize = -3;
+/// Interrupted by a signal
+pub const EINTR: isize = -4;
+/// I/O error
+pub const EIO: isize = -5;
+/// Argument list too long
+pub const E2BIG: isize = -7;
+/// Exec format error
+pub const ENOEXEC: isize = -8;
+/// Bad file descriptor: negative, past the fd table or closed
+pub const EBADF: isize = -9;
+/// Try again, returned (as `usize`) by non-blocking `File::read/write` that would block
+pub const EAGAIN: isize = -11;
+/// Out of memory
+pub const ENOMEM: isize = -12;
+/// Bad user address
+pub const EFAULT: isize = -14;
+/// Device or resource busy
+pub const EBUSY: isize = -16;
+/// File exists
+pub const EEXIST: isize = -17;
+/// Cross-device link
+pub const EXDEV: isize = -18;
+/// No such device
+pub const ENODEV: isize = -19;
+/// Not a directory
+pub const ENOTDIR: isize = -20;
+/// Is a directory
+pub const EISDIR: isize = -21;
+/// Invalid argument
+pub const EINVAL: isize = -22;
+/// Too many open files
+pub const EMFILE: isize = -24;
+/// File too large
+pub const EFBIG: isize = -27;
+/// No space left on device
+pub const ENOSPC: isize = -28;
+/// Illegal seek
+pub const ESPIPE: isize = -29;
+/// Read-only file system
+pub const EROFS: isize = -30;
+/// Result too large
+pub const ERANGE: isize = -34;
+/// Resource deadlock would occur
+pub const EDEADLK: isize = -35;
+/// Name too long
+pub const ENAMETOOLONG: isize = -36;
+/// Function not implemented
+pub const ENOSYS: isize = -38;
+/// Directory not empty
+pub const ENOTEMPTY: isize = -39;
+/// Too many levels of symbolic links
+pub const ELOOP: isize = -40;
+/// Operation not supported
+pub const EOPNOTSUPP: isize = -95;
+/// Disk quota exceeded
+pub const EDQUOT: isize = -122;
@@ -1,98 +1,390 @@
-use alloc::sync::Arc;
+use alloc::{string::ToString, sync::Arc, vec::Vec};
 use bitflags::bitflags;
-use easy_fs::Inode;
+use core::mem::size_of;
+use easy_fs::{BlockDeviceStats, Inode, Quota};
 
 use crate::{
     cast::DowncastArc,
-    fs::{self, make_pipe, name_for_inode, unlink_file_at, File, OSInode, OpenFlags, ROOT_INODE},
+    config::{PAGE_SIZE, PATH_MAX},
+    drivers::BLOCK_DEVICE,
+    fs::{
+        self, make_pipe, permitted, record_conflict, rename_file_at, rmdir_at, set_record_lock,
+        unlink_file_at, unwait_record_lock, wait_record_lock, File, FsPath, LockKind, OSInode,
+        OpenFlags, Pipe, RecordLock, VfsNode, MAY_WRITE,
+    },
     mm::{self, translated_byte_buffer, UserBuffer},
+    net::pcap,
     task::{self, ProcessControlBlock},
+    timer,
 };
 
-use super::bail_exit;
+use super::{
+    bail_exit,
+    errno::{
+        EAGAIN, EBADF, EBUSY, EDEADLK, EFAULT, EINTR, EINVAL, ENODEV, ENOENT, ENOTDIR, EOPNOTSUPP,
+        EPERM, ERANGE, ESPIPE, ESRCH,
+    },
+    syscalls, unpack_args, SyscallEntry,
+};
+
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_DUP: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
+const SYSCALL_FLOCK: usize = 32;
+const SYSCALL_MKDIRAT: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_FTRUNCATE: usize = 46;
+const SYSCALL_FALLOCATE: usize = 47;
+const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_FCHDIR: usize = 50;
+const SYSCALL_CHMOD: usize = 53;
+const SYSCALL_CHOWN: usize = 54;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_QUOTACTL: usize = 60;
+const SYSCALL_GETDENTS: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_READV: usize = 65;
+const SYSCALL_WRITEV: usize = 66;
+const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
+const SYSCALL_FSYNC: usize = 82;
+const SYSCALL_UTIMENSAT: usize = 88;
+/// not Linux's 24, that's dup here
+const SYSCALL_DUP3: usize = 1060;
+const SYSCALL_BLOCK_STATS: usize = 2001;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_GETCWD => sys_getcwd(ptr, len),
+    SYSCALL_DUP => sys_dup(fd),
+    SYSCALL_FCNTL => sys_fcntl(fd, cmd, arg),
+    SYSCALL_FLOCK => sys_flock(fd, operation),
+    SYSCALL_MKDIRAT => sys_mkdirat(fd, path),
+    SYSCALL_UNLINKAT => sys_unlinkat(fd, path, flags),
+    SYSCALL_LINKAT => sys_linkat(fd, oldpath, newpath),
+    SYSCALL_RENAMEAT => sys_renameat(fd, oldpath, newpath),
+    SYSCALL_UMOUNT2 => sys_umount2(target, flags),
+    SYSCALL_MOUNT => sys_mount(source, target, fstype),
+    SYSCALL_FTRUNCATE => sys_ftruncate(fd, len),
+    SYSCALL_FALLOCATE => sys_fallocate(fd, mode, packed) {
+        let [offset, len] = bail_exit!(unpack_args(packed as *const usize));
+        sys_fallocate(fd, mode, offset, len)
+    },
+    SYSCALL_CHDIR => sys_chdir(path),
+    SYSCALL_FCHDIR => sys_fchdir(fd),
+    SYSCALL_CHMOD => sys_chmod(path, mode),
+    SYSCALL_CHOWN => sys_chown(path, uid, gid),
+    SYSCALL_OPENAT => sys_openat(fd, path, flags),
+    SYSCALL_CLOSE => sys_close(fd),
+    SYSCALL_PIPE2 => sys_pipe2(pipe, flags, size),
+    SYSCALL_QUOTACTL => sys_quotactl(cmd, path, packed) {
+        let [id, addr] = bail_exit!(unpack_args(packed as *const usize));
+        sys_quotactl(cmd, path as *const u8, id, addr as *mut Quota)
+    },
+    SYSCALL_GETDENTS => sys_getdents(fd, ptr, len),
+    SYSCALL_LSEEK => sys_lseek(fd, offset, whence),
+    SYSCALL_READ => sys_read(fd, buf, len),
+    SYSCALL_WRITE => sys_write(fd, buf, len),
+    SYSCALL_READV => sys_readv(fd, iov, iovcnt),
+    SYSCALL_WRITEV => sys_writev(fd, iov, iovcnt),
+    SYSCALL_PREAD64 => sys_pread64(fd, buf, packed) {
+        let [len, offset] = bail_exit!(unpack_args(packed as *const usize));
+        sys_pread64(fd, buf as *const u8, len, offset)
+    },
+    SYSCALL_PWRITE64 => sys_pwrite64(fd, buf, packed) {
+        let [len, offset] = bail_exit!(unpack_args(packed as *const usize));
+        sys_pwrite64(fd, buf as *const u8, len, offset)
+    },
+    SYSCALL_PPOLL => sys_ppoll(fds, nfds, timeout),
+    SYSCALL_FSTAT => sys_fstat(fd, ptr),
+    SYSCALL_SYNC => sys_sync(counts),
+    SYSCALL_FSYNC => sys_fsync(fd),
+    SYSCALL_UTIMENSAT => sys_utimensat(fd, path, times),
+    SYSCALL_DUP3 => sys_dup3(old_fd, new_fd, flags),
+    SYSCALL_BLOCK_STATS => sys_block_stats(stats),
+};
 
 /// write buf of length `len` to a file with `fd`
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+pub fn sys_write(fd: isize, buf: *const u8, len: usize) -> isize {
     let proc = task::current_process();
     let inner = proc.inner_exclusive_access();
     let token = inner.get_user_token();
 
-    match inner.fd_table.get(fd) {
-        Some(Some(file)) => {
-            if !file.writable() {
-                return -1;
-            }
-            let file = file.clone();
-            // release current task TCB manually to avoid multi-borrow
-            drop(inner);
-            file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+    let file = bail_exit!(inner.resolve_fd(fd));
+    bail_exit!(file.check_write());
+    let nonblock = inner.fd_status(fd as usize).contains(OpenFlags::NONBLOCK);
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    let buf = UserBuffer::new(bail_exit!(translated_byte_buffer(token, buf, len, false)));
+    write_file(&file, buf, nonblock)
+}
+
+/// read buf of length `len` from a file with `fd`
+pub fn sys_read(fd: isize, buf: *const u8, len: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+
+    let file = bail_exit!(inner.resolve_fd(fd));
+    if !file.readable() {
+        return -1;
+    }
+    let nonblock = inner.fd_status(fd as usize).contains(OpenFlags::NONBLOCK);
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    let buf = UserBuffer::new(bail_exit!(translated_byte_buffer(token, buf, len, true)));
+    read_file(&file, buf, nonblock)
+}
+
+/// Read `file` to `buf`, what's there now if `nonblock`
+fn read_file(file: &Arc<dyn File>, buf: UserBuffer, nonblock: bool) -> isize {
+    if nonblock {
+        file.read_nonblocking(buf) as isize
+    } else {
+        file.read(buf) as isize
+    }
+}
+
+/// Write `buf` to `file`, what fits now if `nonblock`
+fn write_file(file: &Arc<dyn File>, buf: UserBuffer, nonblock: bool) -> isize {
+    if nonblock {
+        file.write_nonblocking(buf) as isize
+    } else {
+        file.write(buf) as isize
+    }
+}
+
+/// `struct iovec` of readv/writev, a user range
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// iovecs taken by one readv/writev at most
+const IOV_MAX: usize = 1024;
+
+/// User ranges of the `iovcnt` iovecs at `iov`, each one user may read, or also `write`
+fn iovec_ranges(
+    token: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    write: bool,
+) -> Result<Vec<(usize, usize)>, isize> {
+    if iovcnt > IOV_MAX {
+        return Err(EINVAL);
+    }
+    if !mm::user_accessible(token, iov as usize, iovcnt * size_of::<IoVec>(), false) {
+        return Err(EFAULT);
+    }
+    let mut ranges = Vec::with_capacity(iovcnt);
+    for i in 0..iovcnt {
+        let v = mm::read_user_obj(token, iov.wrapping_add(i))?;
+        if !mm::user_accessible(token, v.base, v.len, write) {
+            return Err(EFAULT);
         }
-        _ => -1,
+        ranges.push((v.base, v.len));
     }
+    Ok(ranges)
 }
 
-/// read buf of length `len` from a file with `fd`
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+/// read file `fd` to the `iovcnt` buffers of `iov` in order, in one go
+pub fn sys_readv(fd: isize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    if !file.readable() {
+        return -1;
+    }
+    let nonblock = inner.fd_status(fd as usize).contains(OpenFlags::NONBLOCK);
+    drop(inner);
+    let ranges = bail_exit!(iovec_ranges(token, iov, iovcnt, true));
+    read_file(
+        &file,
+        bail_exit!(UserBuffer::from_ranges(token, &ranges, true)),
+        nonblock,
+    )
+}
+
+/// write the `iovcnt` buffers of `iov` to file `fd` in order, in one go
+pub fn sys_writev(fd: isize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    let nonblock = inner.fd_status(fd as usize).contains(OpenFlags::NONBLOCK);
+    drop(inner);
+    bail_exit!(file.check_write());
+    let ranges = bail_exit!(iovec_ranges(token, iov, iovcnt, false));
+    write_file(
+        &file,
+        bail_exit!(UserBuffer::from_ranges(token, &ranges, false)),
+        nonblock,
+    )
+}
+
+/// read buf of length `len` from a file with `fd` at `offset`, the offset of `fd` stays
+pub fn sys_pread64(fd: isize, buf: *const u8, len: usize, offset: usize) -> isize {
     let proc = task::current_process();
     let inner = proc.inner_exclusive_access();
     let token = inner.get_user_token();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    if !file.readable() {
+        return -1;
+    }
+    drop(inner);
+    let buf = UserBuffer::new(bail_exit!(translated_byte_buffer(token, buf, len, true)));
+    file.read_at(offset, buf).map_or(ESPIPE, |n| n as isize)
+}
+
+/// write buf of length `len` to a file with `fd` at `offset`, the offset of `fd` stays
+pub fn sys_pwrite64(fd: isize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    bail_exit!(file.check_write());
+    let buf = UserBuffer::new(bail_exit!(translated_byte_buffer(token, buf, len, false)));
+    file.write_at(offset, buf).map_or(ESPIPE, |n| n as isize)
+}
+
+/// `events` and `revents` of `PollFd`
+const POLLIN: i16 = 0x1;
+const POLLOUT: i16 = 0x4;
+/// fd isn't open, only in `revents`
+const POLLNVAL: i16 = 0x20;
+
+/// fds taken by one ppoll at most
+const POLL_MAX: usize = 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    /// skipped if negative
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
 
-    match inner.fd_table.get(fd) {
-        Some(Some(file)) => {
-            if !file.readable() {
-                return -1;
+/// Wait until any of the `nfds` fds at `fds` is ready for its `events`, or `timeout` passed
+/// unless it's null. The number of fds with `revents` set, 0 on timeout.
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> isize {
+    let proc = task::current_process();
+    let token = proc.inner_exclusive_access().get_user_token();
+    if nfds > POLL_MAX {
+        return EINVAL;
+    }
+    if !mm::user_accessible(token, fds as usize, nfds * size_of::<PollFd>(), true) {
+        return EFAULT;
+    }
+    let expire_ms = if timeout.is_null() {
+        None
+    } else {
+        let spec = bail_exit!(mm::read_user_obj(token, timeout));
+        if spec.nsec >= TimeSpec::NS_PER_SEC {
+            return EINVAL;
+        }
+        // rounded up, never back early
+        let ms = (spec.as_ns() as usize + 999_999) / 1_000_000;
+        Some(timer::get_time_ms() + ms)
+    };
+    let mut polls: Vec<PollFd> = bail_exit!((0..nfds)
+        .map(|i| mm::read_user_obj(token, fds.wrapping_add(i)))
+        .collect());
+    let ret = loop {
+        let ready = poll_fds(&proc, &mut polls);
+        if ready > 0 {
+            break ready as isize;
+        }
+        if task::current_has_deliverable_signal() {
+            break EINTR;
+        }
+        if expire_ms.map_or(false, |expire_ms| timer::get_time_ms() >= expire_ms) {
+            break 0;
+        }
+        fs::wait_poll(expire_ms);
+    };
+    for (i, poll) in polls.iter().enumerate() {
+        bail_exit!(mm::write_user_obj(token, fds.wrapping_add(i), poll));
+    }
+    ret
+}
+
+/// Set `revents` of `polls` by the fds of `proc` as they are now, how many got any
+fn poll_fds(proc: &Arc<ProcessControlBlock>, polls: &mut [PollFd]) -> usize {
+    let mut ready = 0;
+    for poll in polls.iter_mut() {
+        poll.revents = 0;
+        if poll.fd < 0 {
+            continue;
+        }
+        let file = match proc.inner_exclusive_access().resolve_fd(poll.fd as isize) {
+            Ok(file) => file,
+            Err(_) => {
+                poll.revents = POLLNVAL;
+                ready += 1;
+                continue;
             }
-            let file = file.clone();
-            // release current task TCB manually to avoid multi-borrow
-            drop(inner);
-            file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        };
+        if poll.events & POLLIN != 0 && file.readable() && file.poll_read_ready() {
+            poll.revents |= POLLIN;
+        }
+        if poll.events & POLLOUT != 0 && file.writable() && file.poll_write_ready() {
+            poll.revents |= POLLOUT;
+        }
+        if poll.revents != 0 {
+            ready += 1;
         }
-        _ => -1,
     }
+    ready
 }
 
 const AT_FDCWD: isize = -100;
-/// determin base inode for *at_ series
+/// `sys_unlinkat` flag to remove a directory instead
+const AT_REMOVEDIR: usize = 0x200;
+/// determin base path for *at_ series, the path given is resolved from it
 /// 1. `abs_path`: works if it starts with "/"
-/// 2. `open_read/write`: require the fd(dir) to be open with read/write
+/// 2. `open_read/write`: require the fd(dir) to be open with read/write, any other fd than a
+///    dir is ENOTDIR; `..` in the path then walks up from that dir
 /// 3. `curr_task`: need access to TCB inner
-fn base_inode(
+fn base_path(
     fd: isize,
     abs_path: &str,
     open_read: bool,
     open_write: bool,
     curr_proc: &Arc<ProcessControlBlock>,
-) -> Result<Arc<Inode>, isize> {
+) -> Result<FsPath, isize> {
     let base = match (abs_path.starts_with("/"), fd == AT_FDCWD) {
-        (true, _) => ROOT_INODE.clone(),
+        (true, _) => FsPath::root(),
         (_, true) => {
-            // from cwd
-            let cwd = curr_proc.inner_exclusive_access().cwd.clone();
-            let path = name_for_inode(&cwd);
-            // ensure cwd
-            match fs::find_file(&path) {
-                Some(v) if v.is_dir() => cwd, // must be existed dir
-                _ => return Err(-1),
-            }
+            // from cwd, it must not be removed
+            let inner = curr_proc.inner_exclusive_access();
+            inner.cwd.clone().ok_or(ENOENT)?
         }
         (_, false) => {
             // from fd specified, fd must be open
-            match curr_proc.inner_exclusive_access().fd_table.get(fd as usize) {
-                Some(Some(file)) => {
-                    let file_clone = file.clone();
-                    match file_clone.downcast_arc::<OSInode>() {
-                        Some(os_inode) if os_inode.is_dir() => {
-                            if open_read && !os_inode.readable()
-                                || open_write && !os_inode.writable()
-                            {
-                                return Err(-1); // caller should have rx on this dir if read or wx if write
-                            }
-                            os_inode.clone_inner_inode()
-                        }
-                        _ => return Err(-1), // not an efs dir
+            let file = curr_proc.inner_exclusive_access().resolve_fd(fd)?;
+            match file.downcast_arc::<OSInode>() {
+                Some(os_inode) if os_inode.is_dir() => {
+                    if open_read && !os_inode.readable() || open_write && !os_inode.writable() {
+                        return Err(-1); // caller should have rx on this dir if read or wx if write
                     }
+                    FsPath::of_dir(&os_inode.clone_inner_inode())
                 }
-                _ => return Err(-1),
+                _ => return Err(ENOTDIR), // not an efs dir
             }
         }
     };
@@ -105,70 +397,72 @@ pub fn sys_openat(fd: isize, path: *const u8, flags: u32) -> isize {
 
     let proc = task::current_process();
     let token = proc.inner_exclusive_access().get_user_token();
-    let path = mm::translated_str(token, path);
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
 
-    let base = bail_exit!(base_inode(fd, &path, or, ow, &proc));
-    if let Some(inode) = fs::open_file_at(&base, &path, open_flags) {
+    if let Some(dev) = pcap::open_pcap(&path) {
         let mut inner = proc.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
-    } else {
-        -1
+        let fd = bail_exit!(inner.alloc_fd());
+        inner.fd_table[fd] = Some(dev);
+        inner.set_cloexec(fd, open_flags.contains(OpenFlags::CLOEXEC));
+        return fd as isize;
     }
+
+    let base = bail_exit!(base_path(fd, &path, or, ow, &proc));
+    let cred = proc.inner_exclusive_access().cred;
+    let inode = bail_exit!(fs::open_file_at(&base, &path, open_flags, &cred));
+    let mut inner = proc.inner_exclusive_access();
+    let fd = bail_exit!(inner.alloc_fd());
+    inner.fd_table[fd] = Some(inode);
+    inner.set_fd_status(fd, open_flags);
+    inner.set_cloexec(fd, open_flags.contains(OpenFlags::CLOEXEC));
+    fd as isize
 }
 
-pub fn sys_close(fd: usize) -> isize {
+pub fn sys_close(fd: isize) -> isize {
     let proc = task::current_process();
     let mut inner = proc.inner_exclusive_access();
-    if let Some(opt) = inner.fd_table.get_mut(fd) {
-        match opt.take() {
-            Some(_) => 0,
-            _ => -1,
-        }
-    } else {
-        -1
-    }
+    bail_exit!(inner.resolve_fd(fd));
+    let file = inner.close_fd(fd as usize).unwrap();
+    fs::file_closed(proc.getpid(), file);
+    0
 }
 
+/// Copy the absolute path of cwd to `ptr`, ERANGE if it takes more than `len` bytes with the
+/// trailing nul, ENOENT if it's removed
 pub fn sys_getcwd(ptr: *mut u8, len: usize) -> isize {
     let proc = task::current_process();
     let inner = proc.inner_exclusive_access();
     let token = inner.get_user_token();
 
-    let cwd = name_for_inode(&inner.cwd);
+    let cwd = bail_exit!(inner.cwd.as_ref().ok_or(ENOENT)).to_string();
     if cwd.len() + 1 > len {
-        return -1;
-    }
-    let src = cwd.as_bytes();
-
-    let dst_vs = mm::translated_byte_buffer(token, ptr as *const u8, len);
-    for (i, dst) in dst_vs.into_iter().enumerate() {
-        let dst_len = dst.len().min(src.len());
-        let s = i * dst_len;
-        dst[..dst_len].copy_from_slice(&src[s..s + dst_len]);
+        return ERANGE;
     }
+    let mut src = cwd.into_bytes();
+    src.push(0);
+    bail_exit!(mm::copy_to_user(token, ptr, &src));
     0
 }
 
 pub fn sys_mkdirat(fd: isize, path: *const u8) -> isize {
     let proc = task::current_process();
     let token = proc.inner_exclusive_access().get_user_token();
-    let path = mm::translated_str(token, path);
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
 
-    let mut base = bail_exit!(base_inode(fd, &path, true, true, &proc));
+    let mut base = bail_exit!(base_path(fd, &path, true, true, &proc));
+    let cred = proc.inner_exclusive_access().cred;
     for name in path.split("/").filter(|s| !s.is_empty()) {
-        match base.create_dir(name) {
-            Some(created) => base = created,
-            // already exist
-            _ => {
-                // TODO can we avoid `find`?
-                let existed = base.find(name).unwrap();
-                if !existed.is_dir() {
-                    return -1; // intermediate must be dir
-                }
-                base = existed;
-            }
+        let dir = base.inode();
+        let created = if permitted(&dir, &cred, MAY_WRITE) {
+            dir.create_dir_as(name, (cred.uid, cred.gid))
+                .map_err(fs::efs_errno)
+        } else {
+            Err(-1)
+        };
+        // already exist, or not allowed to be created
+        match base.resolve(name) {
+            Ok(existed) if existed.inode().is_dir() => base = existed,
+            _ => return created.err().unwrap_or(ENOTDIR), // intermediate must be dir
         }
     }
     0
@@ -177,15 +471,12 @@ pub fn sys_mkdirat(fd: isize, path: *const u8) -> isize {
 pub fn sys_chdir(path: *const u8) -> isize {
     let curr_proc = task::current_process();
     let token = curr_proc.inner_exclusive_access().get_user_token();
-    let path = mm::translated_str(token, path);
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
 
-    let base = bail_exit!(base_inode(AT_FDCWD, &path, true, true, &curr_proc));
-    match base.find(&path) {
-        Some(d) => {
-            if !d.is_dir() {
-                return -1;
-            }
-            curr_proc.inner_exclusive_access().cwd = d;
+    let base = bail_exit!(base_path(AT_FDCWD, &path, true, true, &curr_proc));
+    match base.resolve(&path) {
+        Ok(dir) if dir.inode().is_dir() => {
+            curr_proc.inner_exclusive_access().cwd = Some(dir);
         }
         _ => {
             return -1;
@@ -194,52 +485,206 @@ pub fn sys_chdir(path: *const u8) -> isize {
     0
 }
 
-pub fn sys_unlinkat(fd: isize, path: *const u8) -> isize {
-    // TODO support actual dirfd
-    if fd != AT_FDCWD {
-        return -1;
-    }
+/// Make dir `fd` cwd, it only needs to be open
+pub fn sys_fchdir(fd: isize) -> isize {
+    let proc = task::current_process();
+    let file = bail_exit!(proc.inner_exclusive_access().resolve_fd(fd));
+    let dir = match file.downcast_arc::<OSInode>() {
+        Some(os_inode) if os_inode.is_dir() => os_inode.clone_inner_inode(),
+        _ => return ENOTDIR,
+    };
+    proc.inner_exclusive_access().cwd = Some(FsPath::of_dir(&dir));
+    0
+}
 
+/// Remove `path`, a dir with `AT_REMOVEDIR` in `flags`. Like all *at syscalls a relative
+/// `path` is from dir `fd`, or cwd if it's `AT_FDCWD`; `..` in it goes up from there.
+pub fn sys_unlinkat(fd: isize, path: *const u8, flags: usize) -> isize {
     let curr_proc = task::current_process();
     let token = curr_proc.inner_exclusive_access().get_user_token();
-    let path = mm::translated_str(token, path);
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
 
-    let base = bail_exit!(base_inode(AT_FDCWD, &path, true, true, &curr_proc));
-    if unlink_file_at(&base, &path) {
-        0
+    let base = bail_exit!(base_path(fd, &path, true, true, &curr_proc));
+    let (parent, name) = bail_exit!(base.resolve_parent(&path));
+    let parent = parent.inode();
+    if flags & AT_REMOVEDIR != 0 {
+        let dir = parent.lookup_link(name).map(|dir| VfsNode::of(&dir));
+        if mount_point(&parent, name) {
+            return EBUSY;
+        }
+        bail_exit!(rmdir_at(&parent, name));
+        if let Ok(dir) = dir {
+            // whoever is in it is nowhere now
+            for proc in task::processes() {
+                let mut inner = proc.inner_exclusive_access();
+                if inner.cwd.as_ref().map(|cwd| VfsNode::of(&cwd.inode())) == Some(dir) {
+                    inner.cwd = None;
+                }
+            }
+        }
     } else {
-        -1
+        bail_exit!(unlink_file_at(&parent, name));
     }
+    0
 }
 
+/// Hard link `newpath` to `oldpath`, both relative to dir `fd` unless absolute
 pub fn sys_linkat(fd: isize, oldpath: *const u8, newpath: *const u8) -> isize {
-    // TODO support actual dirfd
-    if fd != AT_FDCWD {
-        return -1;
-    }
-
     let proc = task::current_process();
     let token = proc.inner_exclusive_access().get_user_token();
-    let oldpath = mm::translated_str(token, oldpath);
-    let newpath = mm::translated_str(token, newpath);
+    let oldpath = bail_exit!(mm::translated_str(token, oldpath, PATH_MAX));
+    let newpath = bail_exit!(mm::translated_str(token, newpath, PATH_MAX));
 
-    let oldbase = bail_exit!(base_inode(AT_FDCWD, &oldpath, true, true, &proc));
-    let newbase = bail_exit!(base_inode(AT_FDCWD, &newpath, true, true, &proc));
+    let oldbase = bail_exit!(base_path(fd, &oldpath, true, true, &proc));
+    let newbase = bail_exit!(base_path(fd, &newpath, true, true, &proc));
 
     // parent.link(name, old_inode)
-    // old must exist
-    let old_inode = bail_exit!(oldbase.find(&oldpath).ok_or(-1));
-    let (path, fname) = match newpath.rsplit_once('/') {
-        Some(v) => v,
-        _ => (".", newpath.as_str()),
-    };
+    // old must exist, and not be a dir
+    let old_inode = bail_exit!(oldbase.resolve(&oldpath)).inode();
+    if old_inode.is_dir() {
+        return EPERM;
+    }
     // parent must exist
-    let parent = bail_exit!(newbase.find(path).ok_or(-1));
-    if parent.link(fname, &old_inode).is_some() {
-        0
-    } else {
-        -1
+    let (parent, fname) = bail_exit!(newbase.resolve_parent(&newpath));
+    bail_exit!(parent
+        .inode()
+        .link(fname, &old_inode)
+        .map_err(fs::efs_errno));
+    0
+}
+
+/// Move `oldpath` to `newpath`, both relative to dir `fd` unless absolute
+pub fn sys_renameat(fd: isize, oldpath: *const u8, newpath: *const u8) -> isize {
+    let proc = task::current_process();
+    let token = proc.inner_exclusive_access().get_user_token();
+    let oldpath = bail_exit!(mm::translated_str(token, oldpath, PATH_MAX));
+    let newpath = bail_exit!(mm::translated_str(token, newpath, PATH_MAX));
+
+    let oldbase = bail_exit!(base_path(fd, &oldpath, true, true, &proc));
+    let newbase = bail_exit!(base_path(fd, &newpath, true, true, &proc));
+    let (oldparent, oldname) = bail_exit!(oldbase.resolve_parent(&oldpath));
+    let (newparent, newname) = bail_exit!(newbase.resolve_parent(&newpath));
+    if mount_point(&oldparent.inode(), oldname) || mount_point(&newparent.inode(), newname) {
+        return EBUSY;
     }
+    // a dir moved takes the cwds in and under it along
+    let moved_dir = oldparent
+        .inode()
+        .lookup_link(oldname)
+        .ok()
+        .filter(|inode| inode.is_dir())
+        .map(|dir| VfsNode::of(&dir));
+    bail_exit!(rename_file_at(
+        &oldparent.inode(),
+        oldname,
+        &newparent.inode(),
+        newname
+    ));
+    if let Some(dir) = moved_dir {
+        if let Ok(to) = newparent.resolve(newname) {
+            move_cwds(dir, &to);
+        }
+    }
+    0
+}
+
+/// If `name` in `dir` is mounted on, it stays where it is then
+fn mount_point(dir: &Inode, name: &str) -> bool {
+    dir.lookup_link(name)
+        .map_or(false, |inode| fs::mounted_on(&inode).is_some())
+}
+
+/// Dir `dir` is moved to `to`, so are the cwds of processes in or under it
+fn move_cwds(dir: VfsNode, to: &FsPath) {
+    for proc in task::processes() {
+        let mut inner = proc.inner_exclusive_access();
+        let moved = inner.cwd.as_ref().and_then(|cwd| cwd.moved(dir, to));
+        if moved.is_some() {
+            inner.cwd = moved;
+        }
+    }
+}
+
+/// Mount the easy-fs on disk `source`, /dev/vdb or on, on dir `target`. Only root may, and
+/// `fstype` must be "easy-fs"; the flags and data of Linux after it aren't taken.
+pub fn sys_mount(source: *const u8, target: *const u8, fstype: *const u8) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let (token, cred) = (inner.get_user_token(), inner.cred);
+    drop(inner);
+    if !cred.is_root() {
+        return EPERM;
+    }
+    let source = bail_exit!(mm::translated_str(token, source, PATH_MAX));
+    let target = bail_exit!(mm::translated_str(token, target, PATH_MAX));
+    let fstype = bail_exit!(mm::translated_str(token, fstype, PATH_MAX));
+    if fstype != "easy-fs" {
+        return ENODEV;
+    }
+
+    let base = bail_exit!(base_path(AT_FDCWD, &target, true, false, &proc));
+    let on = bail_exit!(base.resolve(&target)).inode();
+    bail_exit!(fs::mount(&source, &on));
+    0
+}
+
+/// Unmount the filesystem mounted on `target`, EBUSY while any process has something on it.
+/// No `flags` are known.
+pub fn sys_umount2(target: *const u8, flags: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let (token, cred) = (inner.get_user_token(), inner.cred);
+    drop(inner);
+    if !cred.is_root() {
+        return EPERM;
+    }
+    if flags != 0 {
+        return EINVAL;
+    }
+    let target = bail_exit!(mm::translated_str(token, target, PATH_MAX));
+
+    let base = bail_exit!(base_path(AT_FDCWD, &target, true, false, &proc));
+    let root = bail_exit!(base.resolve(&target)).inode();
+    bail_exit!(fs::umount(&root, |fs| {
+        task::processes()
+            .iter()
+            .any(|proc| proc.inner_exclusive_access().uses_fs(fs))
+    }));
+    0
+}
+
+/// `sys_quotactl` commands, as `QCMD(cmd, USRQUOTA)` of Linux
+const Q_GETQUOTA: usize = 0x800007 << 8;
+const Q_SETQUOTA: usize = 0x800008 << 8;
+
+/// Quota of user `id` on the filesystem of `path`: Q_GETQUOTA writes it to `addr`, anyone may
+/// get its own and root anyone's, ESRCH if `id` has none. Q_SETQUOTA gives `id` the limits in
+/// `addr`, only root may.
+pub fn sys_quotactl(cmd: usize, path: *const u8, id: usize, addr: *mut Quota) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let (token, cred) = (inner.get_user_token(), inner.cred);
+    drop(inner);
+    let uid = bail_exit!(u16::try_from(id).map_err(|_| EINVAL));
+    let inode = bail_exit!(inode_at_cwd(path, &proc));
+    match cmd {
+        Q_GETQUOTA => {
+            if !cred.is_root() && cred.uid != uid {
+                return EPERM;
+            }
+            let quota = bail_exit!(inode.quota(uid).ok_or(ESRCH));
+            bail_exit!(mm::write_user_obj(token, addr, &quota));
+        }
+        Q_SETQUOTA => {
+            if !cred.is_root() {
+                return EPERM;
+            }
+            let limits = bail_exit!(mm::read_user_obj(token, addr as *const Quota));
+            bail_exit!(inode.set_quota(uid, &limits).map_err(fs::efs_errno));
+        }
+        _ => return EINVAL,
+    }
+    0
 }
 
 #[repr(C)]
@@ -249,23 +694,61 @@ pub struct Stat {
     pub ino: u64,
     pub mode: StatMode,
     pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
     pub size: u64, // added
-    pad: [u64; 6],
+    pub atime: TimeSpec,
+    pub mtime: TimeSpec,
+    pub ctime: TimeSpec,
 }
 
 impl Stat {
-    pub fn new(ino: u64, mode: StatMode, nlink: u32, size: u64) -> Self {
+    pub fn new(
+        dev: u64,
+        ino: u64,
+        mode: StatMode,
+        nlink: u32,
+        (uid, gid): (u16, u16),
+        size: u64,
+        times: (u64, u64, u64),
+    ) -> Self {
         Self {
-            dev: 0,
+            dev,
             ino,
             mode,
             nlink,
+            uid: uid as u32,
+            gid: gid as u32,
             size,
-            pad: [0; 6],
+            atime: TimeSpec::from_ns(times.0),
+            mtime: TimeSpec::from_ns(times.1),
+            ctime: TimeSpec::from_ns(times.2),
         }
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+impl TimeSpec {
+    const NS_PER_SEC: u64 = 1_000_000_000;
+
+    pub(super) fn from_ns(ns: u64) -> Self {
+        Self {
+            sec: ns / Self::NS_PER_SEC,
+            nsec: ns % Self::NS_PER_SEC,
+        }
+    }
+
+    fn as_ns(&self) -> u64 {
+        self.sec * Self::NS_PER_SEC + self.nsec
+    }
+}
+
 bitflags! {
     #[derive(Default)]
     pub struct StatMode: u32 {
@@ -274,21 +757,372 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// permission bits, as many of them as are set
+        const PERM  = 0o7777;
     }
 }
 
-pub fn sys_fstat(fd: usize, ptr: *mut Stat) -> isize {
+/// Reposition the offset of file `fd`, returns the new one
+pub fn sys_lseek(fd: isize, offset: isize, whence: usize) -> isize {
     let proc = task::current_process();
-    let task_inner = proc.inner_exclusive_access();
+    let inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    let file = bail_exit!(file.downcast_arc::<OSInode>().ok_or(ESPIPE));
+    match file.seek(offset, whence) {
+        Some(offset) => offset as isize,
+        None => EINVAL,
+    }
+}
 
-    // fd must exist
-    let inode = match task_inner.fd_table.get(fd) {
-        Some(Some(file)) => {
-            let file_clone = file.clone();
-            bail_exit!(file_clone.downcast_arc::<OSInode>().ok_or(-1)).clone_inner_inode()
+/// Resize regular file `fd` to `len` bytes, it must be open for writing
+pub fn sys_ftruncate(fd: isize, len: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    bail_exit!(file.check_write());
+    let inode = bail_exit!(file.downcast_arc::<OSInode>().ok_or(-1)).clone_inner_inode();
+    if !inode.is_file() {
+        return EINVAL;
+    }
+    bail_exit!(inode.truncate(len).map_err(fs::efs_errno));
+    0
+}
+
+/// `sys_fallocate` modes, only punching a hole is done, and that keeps the size
+const FALLOC_FL_KEEP_SIZE: usize = 0x01;
+const FALLOC_FL_PUNCH_HOLE: usize = 0x02;
+
+/// Punch a hole of `len` bytes at `offset` in regular file `fd`, open for writing: the blocks
+/// all in it are freed, the rest of it zeroed, and it reads as zeros. `mode` must be
+/// FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, EOPNOTSUPP otherwise. File mappings keep what
+/// they have of it until dropped.
+pub fn sys_fallocate(fd: isize, mode: usize, offset: usize, len: usize) -> isize {
+    if mode != FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE {
+        return EOPNOTSUPP;
+    }
+    if (offset as isize) < 0 || len as isize <= 0 {
+        return EINVAL;
+    }
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    bail_exit!(file.check_write());
+    let inode = bail_exit!(file.downcast_arc::<OSInode>().ok_or(ESPIPE)).clone_inner_inode();
+    bail_exit!(inode.punch_hole(offset, len).map_err(fs::efs_errno));
+    0
+}
+
+/// Write back what's cached of file `fd`, it's on disk once this returns
+pub fn sys_fsync(fd: isize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    let inode = bail_exit!(file.downcast_arc::<OSInode>().ok_or(EINVAL)).clone_inner_inode();
+    inode.sync();
+    0
+}
+
+/// Write back everything cached: dirty pages of all file mappings, then all blocks of the
+/// filesystem, and flush the disk. What was written is put to `counts` as `[blocks, pages]`,
+/// unless it's null.
+pub fn sys_sync(counts: *mut [usize; 2]) -> isize {
+    let token = task::current_user_token();
+    if !counts.is_null()
+        && !mm::user_accessible(token, counts as usize, size_of::<[usize; 2]>(), true)
+    {
+        return EFAULT;
+    }
+    let (blocks, pages) = task::sync_all();
+    if !counts.is_null() {
+        bail_exit!(mm::write_user_obj(token, counts, &[blocks, pages]));
+    }
+    0
+}
+
+/// not in posix, counters of the block device put to `stats`, for benchmarks
+pub fn sys_block_stats(stats: *mut BlockDeviceStats) -> isize {
+    let token = task::current_user_token();
+    if !mm::user_accessible(token, stats as usize, size_of::<BlockDeviceStats>(), true) {
+        return EFAULT;
+    }
+    bail_exit!(mm::write_user_obj(token, stats, &BLOCK_DEVICE.stats()));
+    0
+}
+
+/// `nsec` of `sys_utimensat` times: set to the current time, or leave alone
+const UTIME_NOW: u64 = (1 << 30) - 1;
+const UTIME_OMIT: u64 = (1 << 30) - 2;
+
+/// Set atime and mtime of `path`, given as `times[0]` and `times[1]`; both become the current
+/// time if `times` is null. ctime is the current time after.
+pub fn sys_utimensat(fd: isize, path: *const u8, times: *const [TimeSpec; 2]) -> isize {
+    let proc = task::current_process();
+    let token = proc.inner_exclusive_access().get_user_token();
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
+
+    let base = bail_exit!(base_path(fd, &path, false, false, &proc));
+    let inode = bail_exit!(base.resolve(&path)).inode();
+    let now = timer::get_wall_time_ns();
+    let times = if times.is_null() {
+        [Some(now); 2]
+    } else {
+        let mut resolved = [None; 2];
+        for (t, spec) in resolved
+            .iter_mut()
+            .zip(bail_exit!(mm::read_user_obj(token, times)).iter())
+        {
+            *t = match spec.nsec {
+                UTIME_NOW => Some(now),
+                UTIME_OMIT => None,
+                nsec if nsec < TimeSpec::NS_PER_SEC => Some(spec.as_ns()),
+                _ => return EINVAL,
+            };
         }
-        _ => return -1,
+        resolved
     };
+    inode.set_times(times[0], times[1]);
+    0
+}
+
+/// `path` relative to cwd, for syscalls changing what's in its inode
+fn inode_at_cwd(path: *const u8, proc: &Arc<ProcessControlBlock>) -> Result<Arc<Inode>, isize> {
+    let token = proc.inner_exclusive_access().get_user_token();
+    let path = mm::translated_str(token, path, PATH_MAX)?;
+    Ok(base_path(AT_FDCWD, &path, false, false, proc)?
+        .resolve(&path)?
+        .inode())
+}
+
+/// Set permission bits of `path` to `mode`, only its owner or root may.
+pub fn sys_chmod(path: *const u8, mode: usize) -> isize {
+    let proc = task::current_process();
+    let inode = bail_exit!(inode_at_cwd(path, &proc));
+    let cred = proc.inner_exclusive_access().cred;
+    if !cred.is_root() && inode.owner().0 != cred.uid {
+        return -1;
+    }
+    inode.chmod((mode & 0o7777) as u16);
+    0
+}
+
+/// `uid`/`gid` of `sys_chown` left alone
+const ID_UNCHANGED: usize = usize::MAX;
+
+/// Set owner of `path`, `ID_UNCHANGED` keeps one as is. Only root may.
+pub fn sys_chown(path: *const u8, uid: usize, gid: usize) -> isize {
+    let proc = task::current_process();
+    if !proc.inner_exclusive_access().cred.is_root() {
+        return -1;
+    }
+    let inode = bail_exit!(inode_at_cwd(path, &proc));
+    let id = |id: usize| match id {
+        ID_UNCHANGED => Ok(None),
+        id => u16::try_from(id).map(Some).map_err(|_| EINVAL),
+    };
+    let (uid, gid) = (bail_exit!(id(uid)), bail_exit!(id(gid)));
+    inode.chown(uid, gid);
+    0
+}
+
+/// `sys_flock` operations, one of the first three, `LOCK_NB` may be or'ed in
+const LOCK_SH: usize = 1;
+const LOCK_EX: usize = 2;
+const LOCK_UN: usize = 8;
+const LOCK_NB: usize = 4;
+
+/// Take, convert or release the advisory lock on regular file `fd`. A conflicting lock is
+/// waited for to go, unless `LOCK_NB`.
+pub fn sys_flock(fd: isize, operation: usize) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    drop(inner);
+    drop(proc);
+    let file = bail_exit!(file.downcast_arc::<OSInode>().ok_or(-1));
+    let kind = match operation & !LOCK_NB {
+        LOCK_SH => LockKind::Shared,
+        LOCK_EX => LockKind::Exclusive,
+        LOCK_UN => {
+            file.unlock();
+            return 0;
+        }
+        _ => return EINVAL,
+    };
+    while !file.try_lock(kind) {
+        if operation & LOCK_NB != 0 {
+            return EAGAIN;
+        }
+        if task::current_has_deliverable_signal() {
+            return EINTR;
+        }
+        task::suspend_current_and_run_next();
+    }
+    0
+}
+
+/// `sys_fcntl` commands
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_GETLK: usize = 5;
+const F_SETLK: usize = 6;
+const F_SETLKW: usize = 7;
+/// bytes a pipe buffers
+const F_GETPIPE_SZ: usize = 1032;
+/// fd flag of `F_GETFD`/`F_SETFD`
+const FD_CLOEXEC: usize = 1;
+
+/// `l_type` of `Flock`
+const F_RDLCK: i16 = 0;
+const F_WRLCK: i16 = 1;
+const F_UNLCK: i16 = 2;
+
+/// Record lock taken from and given back to user
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Flock {
+    pub l_type: i16,
+    /// `SEEK_*` `l_start` is relative to
+    pub l_whence: i16,
+    pub l_start: isize,
+    /// up to the end of file if 0, bytes before `l_start` if negative
+    pub l_len: isize,
+    /// holder of the conflicting lock, by `F_GETLK`
+    pub l_pid: i32,
+}
+
+/// Fd flags of `fd` by `F_GETFD`/`F_SETFD`, `FD_CLOEXEC` the only one. Status flags of `fd`
+/// by `F_GETFL`/`F_SETFL`, only `O_NONBLOCK` may be changed. Bytes a pipe buffers by
+/// `F_GETPIPE_SZ`.
+///
+/// Byte-range record locks of regular file `fd`, held by the calling process. They're
+/// released once it closes any fd of the file, or exits.
+pub fn sys_fcntl(fd: isize, cmd: usize, arg: usize) -> isize {
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    let fd = fd as usize;
+    match cmd {
+        F_GETFD => return inner.fd_cloexec.contains(&fd) as isize,
+        F_SETFD => {
+            inner.set_cloexec(fd, arg & FD_CLOEXEC != 0);
+            return 0;
+        }
+        F_GETFL => {
+            let access = match (file.readable(), file.writable()) {
+                (true, true) => OpenFlags::RDRW,
+                (false, true) => OpenFlags::WRONLY,
+                _ => OpenFlags::RDONLY,
+            };
+            return (access | inner.fd_status(fd)).bits() as isize;
+        }
+        F_SETFL => {
+            inner.set_fd_status(fd, OpenFlags::from_bits_truncate(arg as u32));
+            return 0;
+        }
+        _ => {}
+    }
+    drop(inner);
+    if cmd == F_GETPIPE_SZ {
+        return file
+            .downcast_arc::<Pipe>()
+            .map_or(-1, |pipe| pipe.capacity() as isize);
+    }
+    let pid = proc.getpid();
+    drop(proc);
+    let file = bail_exit!(file.downcast_arc::<OSInode>().ok_or(-1));
+    if !matches!(cmd, F_GETLK | F_SETLK | F_SETLKW) {
+        return EINVAL;
+    }
+
+    let ptr = arg as *mut Flock;
+    let mut flock = bail_exit!(mm::read_user_obj(token, ptr));
+    let kind = match flock.l_type {
+        F_RDLCK => Some(LockKind::Shared),
+        F_WRLCK => Some(LockKind::Exclusive),
+        F_UNLCK => None,
+        _ => return EINVAL,
+    };
+    let base = bail_exit!(file.whence_offset(flock.l_whence as usize).ok_or(EINVAL));
+    let start = bail_exit!(base.checked_add_signed(flock.l_start).ok_or(EINVAL));
+    let (start, end) = match flock.l_len {
+        0 => (start, usize::MAX),
+        len if len > 0 => (start, start.saturating_add(len as usize)),
+        len => (
+            bail_exit!(start.checked_add_signed(len).ok_or(EINVAL)),
+            start,
+        ),
+    };
+    let node = VfsNode::of(&file.clone_inner_inode());
+
+    if cmd == F_GETLK {
+        let lock = RecordLock {
+            pid,
+            start,
+            end,
+            kind: bail_exit!(kind.ok_or(EINVAL)),
+        };
+        match record_conflict(node, &lock) {
+            Some(v) => {
+                flock.l_type = match v.kind {
+                    LockKind::Shared => F_RDLCK,
+                    LockKind::Exclusive => F_WRLCK,
+                };
+                flock.l_whence = fs::SEEK_SET as i16;
+                flock.l_start = v.start as isize;
+                flock.l_len = if v.end == usize::MAX {
+                    0
+                } else {
+                    (v.end - v.start) as isize
+                };
+                flock.l_pid = v.pid as i32;
+            }
+            None => flock.l_type = F_UNLCK,
+        }
+        bail_exit!(mm::write_user_obj(token, ptr, &flock));
+        return 0;
+    }
+
+    let ret = loop {
+        if set_record_lock(node, pid, start, end, kind) {
+            break 0;
+        }
+        // only taking a lock may conflict
+        let kind = kind.unwrap();
+        if cmd == F_SETLK {
+            break EAGAIN;
+        }
+        let lock = RecordLock {
+            pid,
+            start,
+            end,
+            kind,
+        };
+        if !wait_record_lock(node, lock) {
+            break EDEADLK;
+        }
+        if task::current_has_deliverable_signal() {
+            break EINTR;
+        }
+        task::suspend_current_and_run_next();
+    };
+    unwait_record_lock(pid);
+    ret
+}
+
+pub fn sys_fstat(fd: isize, ptr: *mut Stat) -> isize {
+    let proc = task::current_process();
+    let task_inner = proc.inner_exclusive_access();
+
+    // fd must exist
+    let file = bail_exit!(task_inner.resolve_fd(fd));
+    let inode = bail_exit!(file.downcast_arc::<OSInode>().ok_or(-1)).clone_inner_inode();
 
     let ino = inode.inode_id();
     let mode = if inode.is_dir() {
@@ -297,61 +1131,116 @@ pub fn sys_fstat(fd: usize, ptr: *mut Stat) -> isize {
         StatMode::FILE
     } else {
         StatMode::NULL
-    };
+    } | StatMode::from_bits_truncate(inode.mode() as u32);
     let size = inode.get_size();
     let nlink = inode.nlink();
-    let stat = Stat::new(ino as u64, mode, nlink, size as u64);
-
-    let dst_vs = mm::translated_byte_buffer(
-        task_inner.get_user_token(),
-        ptr as *const u8,
-        core::mem::size_of::<Stat>(),
+    let stat = Stat::new(
+        fs::dev_of(&inode),
+        ino as u64,
+        mode,
+        nlink,
+        inode.owner(),
+        size as u64,
+        inode.times(),
     );
-    let s_ptr = (&stat as *const Stat) as *const u8;
-    for (i, dst) in dst_vs.into_iter().enumerate() {
-        let len = dst.len();
-        unsafe {
-            let src = core::slice::from_raw_parts(s_ptr.wrapping_add(i * len), len);
-            dst.copy_from_slice(src);
-        }
-    }
+
+    bail_exit!(mm::write_user_obj(task_inner.get_user_token(), ptr, &stat));
     0
 }
 
-pub fn sys_pipe(pipe: *mut usize) -> isize {
+/// Pipe buffering `size` bytes rounded up to pages, or `PIPE_SIZE` if 0. `flags` may have
+/// `O_NONBLOCK` for both ends.
+pub fn sys_pipe2(pipe: *mut usize, flags: u32, size: usize) -> isize {
+    let flags = bail_exit!(OpenFlags::from_bits(flags).ok_or(EINVAL));
+    if !(OpenFlags::STATUS | OpenFlags::CLOEXEC).contains(flags) {
+        return EINVAL;
+    }
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
+    let capacity = match size {
+        0 => fs::PIPE_SIZE,
+        size if size <= fs::PIPE_MAX_SIZE => (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE,
+        _ => return EINVAL,
+    };
     let proc = task::current_process();
+    let token = proc.inner_exclusive_access().get_user_token();
+    // checked before the fds are taken, not to leave them open for nobody
+    let fds = pipe as *mut [usize; 2];
+    if !mm::user_accessible(token, fds as usize, size_of::<[usize; 2]>(), true) {
+        return EFAULT;
+    }
     let mut inner = proc.inner_exclusive_access();
-    let token = inner.get_user_token();
-    let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
+    let (pipe_read, pipe_write) = make_pipe(capacity);
+    let read_fd = bail_exit!(inner.alloc_fd());
     inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
+    inner.set_fd_status(read_fd, flags);
+    inner.set_cloexec(read_fd, cloexec);
+    let write_fd = match inner.alloc_fd() {
+        Ok(fd) => fd,
+        Err(err) => {
+            inner.close_fd(read_fd);
+            return err;
+        }
+    };
     inner.fd_table[write_fd] = Some(pipe_write);
-    *mm::translated_refmut(token, pipe) = read_fd;
-    *mm::translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    inner.set_fd_status(write_fd, flags);
+    inner.set_cloexec(write_fd, cloexec);
+    bail_exit!(mm::write_user_obj(token, fds, &[read_fd, write_fd]));
     0
 }
 
-pub fn sys_dup(fd: usize) -> isize {
+pub fn sys_dup(fd: isize) -> isize {
     let proc = task::current_process();
     let mut inner = proc.inner_exclusive_access();
-    let file = match inner.fd_table.get(fd) {
-        Some(Some(file)) => file.clone(),
-        _ => return -1,
+    let file = bail_exit!(inner.resolve_fd(fd));
+    let new_fd = bail_exit!(inner.alloc_fd());
+    inner.fd_table[new_fd] = Some(file);
+    // status flags are the fd's own from now on
+    let status = inner.fd_status(fd as usize);
+    inner.set_fd_status(new_fd, status);
+    new_fd as isize
+}
+
+/// Duplicate `old_fd` to `new_fd` exactly, closing what `new_fd` was first. `O_CLOEXEC` is the
+/// only flag, and `new_fd` may not be `old_fd`.
+pub fn sys_dup3(old_fd: isize, new_fd: isize, flags: u32) -> isize {
+    let flags = bail_exit!(OpenFlags::from_bits(flags).ok_or(EINVAL));
+    if !OpenFlags::CLOEXEC.contains(flags) || old_fd == new_fd {
+        return EINVAL;
+    }
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(old_fd));
+    // so the fd table won't grow without bound
+    let new_fd = match usize::try_from(new_fd) {
+        Ok(new_fd) if new_fd < inner.rlimits.nofile.cur => new_fd,
+        _ => return EBADF,
     };
-    let new_fd = inner.alloc_fd();
+    if let Some(file) = inner.close_fd(new_fd) {
+        fs::file_closed(proc.getpid(), file);
+    }
+    if new_fd >= inner.fd_table.len() {
+        inner.fd_table.resize_with(new_fd + 1, || None);
+    }
     inner.fd_table[new_fd] = Some(file);
+    let status = inner.fd_status(old_fd as usize);
+    inner.set_fd_status(new_fd, status);
+    inner.set_cloexec(new_fd, flags.contains(OpenFlags::CLOEXEC));
     new_fd as isize
 }
 
 /// The max length of inode name
 const NAME_LENGTH_LIMIT: usize = 27;
+/// Entries were this big before they had `ino`, what callers passing no size get
+const DIRENT_V1_SIZE: usize = 32;
+
 #[repr(C, align(32))]
 #[derive(Clone, Default)]
 pub struct Dirent {
     pub ftype: FileType,
     pub name: [u8; NAME_LENGTH_LIMIT],
     pub next_offset: u32,
+    /// past the fields of the first version, which is all of them it gets
+    pub ino: u64,
 }
 
 bitflags! {
@@ -360,49 +1249,69 @@ bitflags! {
         const UNKNOWN = 0;
         const DIR = 1 << 0;
         const REG = 1 << 1;
+        const LNK = 1 << 2;
     }
 }
 
-pub fn sys_getdents(fd: usize, ptr: *mut Dirent, len: usize) -> isize {
+/// Read direntries of directory `fd` into the `len` ones at `ptr`, from where the last call
+/// left off, `lseek` to 0 starts over. Returns how many were read, 0 at the end. Entries
+/// created or removed meanwhile may or may not be read, all others are read exactly once.
+///
+/// The upper 32 bits of `len` are the size of an entry as the caller has it, 0 for the first
+/// version without `ino`. `EINVAL` for a size neither version has.
+pub fn sys_getdents(fd: isize, ptr: *mut u8, len: usize) -> isize {
+    let entry_size = match len >> 32 {
+        0 => DIRENT_V1_SIZE,
+        size if size == size_of::<Dirent>() => size,
+        _ => return EINVAL,
+    };
+    let len = len & 0xffff_ffff;
     let proc = task::current_process();
     let inner = proc.inner_exclusive_access();
     let token = inner.get_user_token();
 
-    let file = match inner.fd_table.get(fd) {
-        Some(Some(file)) => {
-            let file_clone = file.clone();
-            match file_clone.downcast_arc::<OSInode>() {
-                Some(os_inode) => os_inode.clone_inner_inode(),
-                _ => return -1,
-            }
-        }
-        _ => return -1,
-    };
-    drop(inner); // MUST drop here, coz `file.dirents` causes block read, when non-blocking, it'll schedule out w/ RefMut held!
+    let file = bail_exit!(inner.resolve_fd(fd));
+    let file = bail_exit!(file.downcast_arc::<OSInode>().ok_or(-1));
+    drop(inner); // MUST drop here, coz reading direntries causes block read, when non-blocking, it'll schedule out w/ RefMut held!
     if !file.is_dir() {
         return -1;
     }
+    // checked before any is read, they'd be skipped otherwise
+    if !mm::user_accessible(token, ptr as usize, len * entry_size, true) {
+        return EFAULT;
+    }
 
-    let cursor = mm::translate_ref(token, unsafe { ptr.add(len - 1) }).next_offset;
-    let dirents = file.dirents(cursor);
-    let nread = len.min(dirents.len());
-    for i in 0..nread {
-        let ename = dirents[i].0.as_bytes();
-        let inode = &dirents[i].1;
-        let ftype = if inode.is_dir() {
+    let dir = file.clone_inner_inode();
+    let mut nread = 0;
+    while nread < len {
+        let Some((dirent, next_offset)) = file.next_dirent() else {
+            break;
+        };
+        let child = dir.child(dirent.inode_number());
+        let ftype = if child.is_dir() {
             FileType::DIR
-        } else if inode.is_file() {
+        } else if child.is_file() {
             FileType::REG
+        } else if child.is_symlink() {
+            FileType::LNK
         } else {
             FileType::UNKNOWN
         };
+        let ename = dirent.name().as_bytes();
         let mut name = [0u8; NAME_LENGTH_LIMIT];
-        name[..ename.len()].copy_from_slice(&ename[..]);
-        *mm::translated_refmut(token, unsafe { ptr.add(i) }) = Dirent {
+        name[..ename.len()].copy_from_slice(ename);
+        let entry = Dirent {
             ftype,
             name,
-            next_offset: cursor + i as u32 + 1,
+            next_offset: next_offset as u32,
+            ino: dirent.inode_number() as u64,
+        };
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&entry as *const Dirent as *const u8, entry_size)
         };
+        let dst = ptr.wrapping_add(nread * entry_size);
+        bail_exit!(mm::copy_to_user(token, dst, bytes));
+        nread += 1;
     }
     nread as isize
 }
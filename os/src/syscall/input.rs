@@ -1,5 +1,15 @@
 use crate::drivers::KEYBOARD_DEVICE;
 
+use super::{syscalls, SyscallEntry};
+
+const SYSCALL_EVENT_GET: usize = 3000;
+const SYSCALL_KEY_PRESSED: usize = 3001;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_EVENT_GET => sys_event_get(),
+    SYSCALL_KEY_PRESSED => sys_key_pressed(),
+};
+
 pub fn sys_event_get() -> isize {
     let kb = KEYBOARD_DEVICE.clone();
     if !kb.is_empty() {
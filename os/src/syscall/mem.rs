@@ -1,14 +1,51 @@
-use core::cell::OnceCell;
+use core::{cell::OnceCell, mem::size_of};
 
-use alloc::format;
+use alloc::{format, vec::Vec};
 use bitflags::bitflags;
+use easy_fs::block_cache_stats;
 
 use crate::{
+    cast::DowncastArc,
     fs::{File, OSInode},
-    mm::{MapPermission, VPNRange, VirtAddr},
+    mm::{self, MapPermission, SharedMemory, VPNRange, VirtAddr, VirtPageNum},
     task::{self, FileMapping, MMapReserve, MMapType, MapRange},
 };
 
+use super::{
+    bail_exit,
+    errno::{EFAULT, EINVAL, ENOMEM},
+    syscalls, unpack_args, SyscallEntry,
+};
+
+const SYSCALL_SHM_OPEN: usize = 194;
+const SYSCALL_SHM_MAP: usize = 196;
+const SYSCALL_SHM_UNMAP: usize = 197;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_MADVISE: usize = 233;
+const SYSCALL_FREE_FRAMES: usize = 2000;
+const SYSCALL_MEMINFO: usize = 2003;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_SHM_OPEN => sys_shm_open(id, len),
+    SYSCALL_SHM_MAP => sys_shm_map(fd, prot),
+    SYSCALL_SHM_UNMAP => sys_shm_unmap(start),
+    SYSCALL_BRK => sys_brk(addr),
+    SYSCALL_MUNMAP => sys_munmap(start, len),
+    SYSCALL_MMAP => sys_mmap(start, len, packed) {
+        let [prot, flags, fd, offset] = bail_exit!(unpack_args(packed as *const usize));
+        sys_mmap(start, len, prot, flags, fd, offset)
+    },
+    SYSCALL_MPROTECT => sys_mprotect(start, len, prot),
+    SYSCALL_MSYNC => sys_msync(start, len, flags),
+    SYSCALL_MADVISE => sys_madvise(start, len, advice),
+    SYSCALL_FREE_FRAMES => sys_free_frames(),
+    SYSCALL_MEMINFO => sys_meminfo(info),
+};
+
 const VA_MAX: usize = usize::MAX;
 
 bitflags! {
@@ -16,6 +53,8 @@ bitflags! {
         const MAP_ANON = 0; // not mapping file, but memory area
         const MAP_FILE = 1 << 0; // mapping file, but no memory area
         const MAP_FIXED = 1 << 1; // map to fixed addr, can be used together with either of above
+        /// with MAP_FILE, writes are private to the process and never reach the file
+        const MAP_PRIVATE = 1 << 2;
     }
 }
 
@@ -23,9 +62,9 @@ bitflags! {
 ///
 /// `prot` arrange as (hi->lo) xwr;
 ///
-/// `flags` MAP_ANON, MAP_FILE(using `fd` and `offset`), MAP_FIXED(using `start`);
+/// `flags` MAP_ANON, MAP_FILE(using `fd` and `offset`), MAP_FIXED(using `start`), MAP_PRIVATE;
 ///
-/// `fd` should `>2`;
+/// `fd` should be open on a regular file;
 ///
 /// `offset` ref to `start`;
 pub fn sys_mmap(
@@ -33,7 +72,7 @@ pub fn sys_mmap(
     len: usize,
     prot: usize,
     flags: usize,
-    fd: usize,
+    fd: isize,
     offset: usize,
 ) -> isize {
     // flags
@@ -48,14 +87,10 @@ pub fn sys_mmap(
         }
     }
 
-    // prot
-    // only last 3 bits allowed
-    // no perm specified
-    if prot & !0x7 != 0 || prot & 0x7 == 0 {
-        return -1;
-    }
-
-    let map_perm = MapPermission::from_bits_truncate((prot << 1) as u8) | MapPermission::U;
+    let map_perm = match map_perm_of(prot) {
+        Some(v) => v,
+        None => return -1,
+    };
     if mmap_flags.contains(MMapFlags::MAP_FILE) {
         do_mmap_file(start, len, map_perm, mmap_flags, fd, offset)
     } else {
@@ -63,71 +98,87 @@ pub fn sys_mmap(
     }
 }
 
+/// Permission of user pages `prot` (xwr) asks for
+fn map_perm_of(prot: usize) -> Option<MapPermission> {
+    // only last 3 bits allowed
+    // no perm specified
+    if prot & !0x7 != 0 || prot & 0x7 == 0 {
+        return None;
+    }
+    Some(MapPermission::from_bits_truncate((prot << 1) as u8) | MapPermission::U)
+}
+
 fn do_mmap_file(
     start: usize,
     len: usize,
     map_perm: MapPermission,
     mmap_flags: MMapFlags,
-    fd: usize,
+    fd: isize,
     offset: usize,
 ) -> isize {
-    use crate::cast::DowncastArc;
-
-    if fd <= 2 {
-        return -1;
-    }
-
     let proc = task::current_process();
     let mut inner = proc.inner_exclusive_access();
-
     // if fd open
-    let fp = match inner.fd_table.get(fd) {
-        Some(Some(v)) => v.clone(),
-        _ => return -1,
-    };
+    let fp = bail_exit!(inner.resolve_fd(fd));
+    if !inner.mmap_within_limit(len) {
+        return ENOMEM;
+    }
     let inode = match fp.downcast_arc::<OSInode>() {
         Some(v) if v.is_file() => v, // must be regular file
         _ => return -1,
     };
-    // check fd perm consistancy with map_perm
-    if map_perm.contains(MapPermission::R) && !inode.readable()
-        || map_perm.contains(MapPermission::W) && !inode.writable()
-    {
+    // check fd perm consistancy with map_perm, stores to a private mapping never reach the file
+    let private = mmap_flags.contains(MMapFlags::MAP_PRIVATE);
+    let shared_write = map_perm.contains(MapPermission::W) && !private;
+    if map_perm.contains(MapPermission::R) && !inode.readable() {
         return -1;
     }
-    // check file size
+    if shared_write {
+        bail_exit!(inode.check_write());
+    }
+    // may reach past the end of file, pages wholly beyond it raise SIGBUS once touched
     let file = inode.clone_inner_inode();
-    let file_size = file.get_size();
     drop(inode);
-    if len > file_size || offset > file_size - len {
-        return -1;
-    }
 
     // mapped vpns: [start_vpn, end_vpn]
-    let start_va = if mmap_flags.contains(MMapFlags::MAP_FIXED) {
+    let fixed = mmap_flags.contains(MMapFlags::MAP_FIXED);
+    let start_va = if fixed {
         VirtAddr::from(start)
     } else {
-        inner.mmap_va_allocator.alloc(len)
+        match inner.mmap_va_allocator.alloc(len) {
+            Some(va) => va,
+            None => return -1, // mmap area full
+        }
     };
     let start_vpn = start_va.floor();
     let end_vpn = VirtAddr::from(start_va.0 + len).ceil();
     let vpn_range = VPNRange::new(start_vpn, end_vpn);
     // check availability
     if !inner.vpn_range_free(vpn_range) {
+        if !fixed {
+            inner.mmap_va_allocator.dealloc(vpn_range);
+        }
         return -1;
     }
+    if fixed {
+        inner.mmap_va_allocator.take(vpn_range);
+    }
 
     // lazy mapping
-    inner.mmap_mapped.push(MMapReserve {
-        range: vpn_range,
-        perm: map_perm,
-        ty: MMapType::File,
-    });
-    match inner.find_file_mapping(&file) {
-        Some(m) => m.ranges.push(MapRange::new(start_va.0, len, offset)),
+    inner.mmap_mapped.insert(
+        start_vpn,
+        MMapReserve {
+            range: vpn_range,
+            perm: map_perm,
+            ty: MMapType::File,
+            image: false,
+        },
+    );
+    match inner.find_file_mapping(&file).filter(|_| !private) {
+        Some(m) => m.add_range(MapRange::new(start_va.0, len, offset), shared_write),
         _ => {
-            let mut m = FileMapping::new_empty(file, inner.memory_set.token());
-            m.ranges.push(MapRange::new(start_va.0, len, offset));
+            let mut m = FileMapping::new_empty(file, inner.memory_set.token(), private);
+            m.add_range(MapRange::new(start_va.0, len, offset), shared_write);
             inner.file_mappings.push(m);
         }
     }
@@ -144,26 +195,43 @@ fn do_mmap_memory(
 ) -> isize {
     let proc = task::current_process();
     let mut inner = proc.inner_exclusive_access();
+    if !inner.mmap_within_limit(len) {
+        return ENOMEM;
+    }
 
-    let start_va = if mmap_flags.contains(MMapFlags::MAP_FIXED) {
+    let fixed = mmap_flags.contains(MMapFlags::MAP_FIXED);
+    let start_va = if fixed {
         VirtAddr::from(start)
     } else {
-        inner.mmap_va_allocator.alloc(len)
+        match inner.mmap_va_allocator.alloc(len) {
+            Some(va) => va,
+            None => return -1, // mmap area full
+        }
     };
     let start_vpn = start_va.floor();
     let end_vpn = VirtAddr::from(start_va.0 + len).ceil();
     let vpn_range = VPNRange::new(start_vpn, end_vpn);
     // check availability
     if !inner.vpn_range_free(vpn_range) {
+        if !fixed {
+            inner.mmap_va_allocator.dealloc(vpn_range);
+        }
         return -1;
     }
+    if fixed {
+        inner.mmap_va_allocator.take(vpn_range);
+    }
 
     // lazy mapping
-    inner.mmap_mapped.push(MMapReserve {
-        range: vpn_range,
-        perm: map_perm,
-        ty: MMapType::Memory,
-    });
+    inner.mmap_mapped.insert(
+        start_vpn,
+        MMapReserve {
+            range: vpn_range,
+            perm: map_perm,
+            ty: MMapType::Memory,
+            image: false,
+        },
+    );
 
     let start_va: VirtAddr = start_vpn.into();
     start_va.0 as isize
@@ -186,28 +254,20 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
 
     // 1. find in mmap_mapped
     // NOT allow partially unmap!
-    let idx = match inner.mmap_mapped.iter().position(|v| v.range == vpn_range) {
-        Some(v) => v,
+    // we `get` but not `remove` here, coz unmap process may fail, we keep atomic
+    let MMapReserve { ty, .. } = match inner.mmap_mapped.get(&start_vpn) {
+        Some(v) if v.range == vpn_range => v.clone(),
         _ => return -1, // not mapped before
     };
-    // we `get` but not `remove` here, coz unmap process may fail, we keep atomic
-    let MMapReserve { ty, .. } = inner.mmap_mapped[idx].clone();
 
     match ty {
         // 2.1 unmap if mem
-        MMapType::Memory => {
-            for vpn in vpn_range {
-                match inner.memory_set.translate(vpn) {
-                    Some(pte) if !pte.is_valid() => {
-                        return -1; // not valid, what happend?!
-                    }
-                    Some(_) => inner.memory_set.unmap(vpn),
-                    _ => {
-                        // no entry due to lazy alloc, which means this vpn has never been used
-                    }
-                }
-            }
-        }
+        // the whole range is mapped as one area on first touch, if never touched there is none
+        MMapType::Memory => inner.memory_set.remove_area_with_start_vpn(start_vpn),
+        // it's brk's to shrink
+        MMapType::Heap => return -1,
+        // shm_unmap's
+        MMapType::Shm => return -1,
         // 2.2 complex if file
         MMapType::File => {
             // we can only find ONE range in ONE file_mapping here
@@ -245,8 +305,324 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
         }
     }
 
+    inner.fault_stats.tlb_flushes += 1;
+
     // 3. remove from mmap_mapped
-    inner.mmap_mapped.remove(idx);
+    inner.mmap_mapped.remove(&start_vpn);
+    inner.mmap_va_allocator.dealloc(vpn_range);
+
+    0
+}
+
+bitflags! {
+    pub struct MSyncFlags: u32 {
+        /// written back to the block cache only
+        const MS_ASYNC = 1 << 0;
+        /// pages are dropped after, read from the file again on the next touch
+        const MS_INVALIDATE = 1 << 1;
+        /// and the file made durable, as fsync
+        const MS_SYNC = 1 << 2;
+    }
+}
+
+/// Write back the dirty pages of the file mapping in `[start, start + len)`, which must lie
+/// within what one mmap call mapped; pages of private mappings are never written. Returns
+/// how many pages were written, `ENOMEM` if the range isn't mapped that way.
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    let flags = match MSyncFlags::from_bits(flags as u32) {
+        Some(v) if !v.contains(MSyncFlags::MS_ASYNC | MSyncFlags::MS_SYNC) => v,
+        _ => return EINVAL,
+    };
+    if start & 0xfff != 0 || VA_MAX - len <= start {
+        return EINVAL;
+    }
+    let vpn_range = VPNRange::new(
+        VirtAddr::from(start).floor(),
+        VirtAddr::from(start + len).ceil(),
+    );
+
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let mapping = match inner
+        .file_mappings
+        .iter_mut()
+        .find(|f| f.ranges.iter().any(|r| r.contains_range(&vpn_range)))
+    {
+        Some(v) => v,
+        None => return ENOMEM,
+    };
+    let mut dropped = Vec::new();
+    let invalidate = flags.contains(MSyncFlags::MS_INVALIDATE);
+    let pages = mapping.sync_range(vpn_range, invalidate, &mut dropped);
+    let file = mapping.file().clone();
+    // after sync_range, which checks D in the page table
+    if !dropped.is_empty() {
+        inner.fault_stats.tlb_flushes += 1;
+    }
+    for vpn in dropped {
+        inner.memory_set.unmap(vpn);
+    }
+    drop(inner);
+    if flags.contains(MSyncFlags::MS_SYNC) {
+        file.sync();
+    }
+    pages as isize
+}
+
+/// `advice` of `sys_madvise`, those below are hints taken and ignored
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+
+/// Advise how `[start, start + len)` is used, which must lie within what one mmap call
+/// reserved, or the heap. With `MADV_DONTNEED` its pages are released, the reservation
+/// stays: anonymous pages fault in zeroed, file pages are read again. Dirty pages of a
+/// shared file mapping are kept, as what's stored to them reaches the file only by them.
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    if start & 0xfff != 0 || VA_MAX - len <= start {
+        return EINVAL;
+    }
+    match advice {
+        MADV_DONTNEED => {}
+        0..=MADV_WILLNEED => return 0,
+        _ => return EINVAL,
+    }
+    let vpn_range = VPNRange::new(
+        VirtAddr::from(start).floor(),
+        VirtAddr::from(start + len).ceil(),
+    );
+
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let ty = match inner.mmap_reserve_of(vpn_range.get_start()) {
+        Some(v) if v.range.get_end() >= vpn_range.get_end() => v.ty,
+        _ => return ENOMEM,
+    };
+    match ty {
+        MMapType::Memory => inner.memory_set.release(vpn_range),
+        // each page touched is an area of its own
+        MMapType::Heap => {
+            for vpn in vpn_range {
+                inner.memory_set.remove_area_with_start_vpn(vpn);
+            }
+        }
+        MMapType::File => {
+            let mut dropped = Vec::new();
+            for mapping in inner.file_mappings.iter_mut() {
+                let private = mapping.is_private();
+                mapping.drop_range(vpn_range, private, &mut dropped);
+            }
+            for vpn in dropped {
+                inner.memory_set.unmap(vpn);
+            }
+        }
+        // its frames are the segment's, not this process'
+        MMapType::Shm => return EINVAL,
+    }
+    inner.fault_stats.tlb_flushes += 1;
+    0
+}
+
+/// Change the protection of `[start, start + len)` to `prot`, arranged as in mmap. The range
+/// must be what one mmap call or exec reserved, or a whole area of the program like a
+/// writable ELF segment, and not the heap. Pages mapped so far get their flags rewritten, the
+/// rest fault in with `prot`.
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    if start & 0xfff != 0 || len == 0 || VA_MAX - len <= start {
+        return -1;
+    }
+    let map_perm = match map_perm_of(prot) {
+        Some(v) => v,
+        None => return -1,
+    };
+
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let inner = &mut *inner;
+    let start_vpn = VirtAddr::from(start).floor();
+    let end_vpn = VirtAddr::from(start + len).ceil();
+    let vpn_range = VPNRange::new(start_vpn, end_vpn);
 
+    match inner.mmap_mapped.get_mut(&start_vpn) {
+        // NOT allow partially protect, as munmap
+        Some(reserve) if reserve.range == vpn_range => {
+            match reserve.ty {
+                // the whole range is mapped as one area on first touch, if never touched there
+                // is none
+                MMapType::Memory => {
+                    inner.memory_set.protect_area(vpn_range, map_perm);
+                }
+                // brk keeps it rw
+                MMapType::Heap => return -1,
+                // all of it is mapped
+                MMapType::Shm => inner.memory_set.protect(vpn_range, map_perm),
+                MMapType::File => {
+                    let mapping = match inner
+                        .file_mappings
+                        .iter()
+                        .find(|v| v.ranges.iter().any(|r| r.contains_range(&vpn_range)))
+                    {
+                        Some(v) => v,
+                        None => return -1,
+                    };
+                    let mut page_perm = map_perm;
+                    if mapping.is_private() {
+                        // pages stay read-only, a store copies them if still shared since fork
+                        page_perm.remove(MapPermission::W);
+                    } else if map_perm.contains(MapPermission::W) {
+                        bail_exit!(mapping.check_write());
+                    }
+                    inner.memory_set.protect(vpn_range, page_perm);
+                }
+            }
+            reserve.perm = map_perm;
+        }
+        Some(_) => return -1,
+        None => {
+            if inner.mmap_reserve_of(start_vpn).is_some()
+                || !inner.memory_set.protect_area(vpn_range, map_perm)
+            {
+                return -1;
+            }
+        }
+    }
+
+    // stale translations are flushed on the way back to user
+    inner.fault_stats.tlb_flushes += 1;
+    0
+}
+
+/// Open shared memory segment `id` as a fd, a new one of `len` bytes zeroed if nobody has it
+/// open or mapped. `EINVAL` if `len` is 0 or more than the one open has.
+pub fn sys_shm_open(id: usize, len: usize) -> isize {
+    let shm = bail_exit!(mm::shm_open(id, len));
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let fd = bail_exit!(inner.alloc_fd());
+    inner.fd_table[fd] = Some(shm);
+    fd as isize
+}
+
+/// Map all of the segment open as `fd` with `prot`, arranged as in mmap, where the kernel
+/// decides. Returns where, forks map it there too. The frames are those of every other
+/// mapping of it, they're mapped at once.
+pub fn sys_shm_map(fd: isize, prot: usize) -> isize {
+    let map_perm = match map_perm_of(prot) {
+        Some(v) => v,
+        None => return -1,
+    };
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let file = bail_exit!(inner.resolve_fd(fd));
+    let shm = bail_exit!(file.downcast_arc::<SharedMemory>().ok_or(EINVAL));
+    let len = shm.size();
+    if !inner.mmap_within_limit(len) {
+        return ENOMEM;
+    }
+    let start_va = match inner.mmap_va_allocator.alloc(len) {
+        Some(va) => va,
+        None => return -1, // mmap area full
+    };
+    let start_vpn = start_va.floor();
+    let vpn_range = VPNRange::new(start_vpn, VirtAddr::from(start_va.0 + len).ceil());
+    if !inner.vpn_range_free(vpn_range) {
+        inner.mmap_va_allocator.dealloc(vpn_range);
+        return -1;
+    }
+
+    inner.mmap_mapped.insert(
+        start_vpn,
+        MMapReserve {
+            range: vpn_range,
+            perm: map_perm,
+            ty: MMapType::Shm,
+            image: false,
+        },
+    );
+    for (i, ppn) in shm.ppns().enumerate() {
+        inner
+            .memory_set
+            .map(VirtPageNum(start_vpn.0 + i), ppn, map_perm);
+    }
+    inner.shm_mappings.insert(start_vpn, shm);
+    start_va.0 as isize
+}
+
+/// Unmap the segment shm_map mapped at `start`, its frames are freed if that was the last
+/// mapping and no fd has it open.
+pub fn sys_shm_unmap(start: usize) -> isize {
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    let start_vpn = VirtAddr::from(start).floor();
+    if start & 0xfff != 0 || inner.shm_mappings.remove(&start_vpn).is_none() {
+        return EINVAL;
+    }
+    let vpn_range = inner.mmap_mapped.remove(&start_vpn).unwrap().range;
+    for vpn in vpn_range {
+        inner.memory_set.unmap(vpn);
+    }
+    inner.mmap_va_allocator.dealloc(vpn_range);
+    inner.fault_stats.tlb_flushes += 1;
+    0
+}
+
+/// Move the program break to `addr`, returns where it is afterwards: unmoved if `addr` is 0,
+/// below the start of the heap, or it can't go that far.
+pub fn sys_brk(addr: usize) -> isize {
+    let proc = task::current_process();
+    let mut inner = proc.inner_exclusive_access();
+    if addr != 0 {
+        inner.set_brk(addr);
+    }
+    inner.brk as isize
+}
+
+/// Number of free physical frames, for leak checks in tests
+pub fn sys_free_frames() -> isize {
+    mm::frame_stats().free as isize
+}
+
+/// Usage of memory `sys_meminfo` puts to user
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemInfo {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    /// kernel heap in bytes, see `mm::HeapStats`
+    pub heap_total: usize,
+    pub heap_allocated: usize,
+    pub heap_requested: usize,
+    /// blocks the block cache may hold, holds, and holds modified
+    pub cache_capacity: usize,
+    pub cache_cached: usize,
+    pub cache_dirty: usize,
+    /// pages of the calling process mapped to a frame
+    pub rss_pages: usize,
+}
+
+/// not in posix, usage of frames, the kernel heap, the block cache and the resident pages of
+/// the caller put to `info`
+pub fn sys_meminfo(info: *mut MemInfo) -> isize {
+    let proc = task::current_process();
+    let inner = proc.inner_exclusive_access();
+    let token = inner.get_user_token();
+    if !mm::user_accessible(token, info as usize, size_of::<MemInfo>(), true) {
+        return EFAULT;
+    }
+    let frames = mm::frame_stats();
+    let heap = mm::heap_stats();
+    let cache = block_cache_stats();
+    let meminfo = MemInfo {
+        total_frames: frames.total,
+        free_frames: frames.free,
+        heap_total: heap.total,
+        heap_allocated: heap.allocated,
+        heap_requested: heap.requested,
+        cache_capacity: cache.capacity,
+        cache_cached: cache.cached,
+        cache_dirty: cache.dirty,
+        rss_pages: inner.memory_set.resident_pages(),
+    };
+    drop(inner);
+    bail_exit!(mm::write_user_obj(token, info, &meminfo));
     0
 }
@@ -1,3 +1,9 @@
+//! Syscalls are dispatched by a table: each module registers the ones it handles with
+//! `syscalls!`, naming the args each takes from the registers. Ids nobody registered get
+//! ENOSYS, and whatever is done on every syscall happens in `syscall` alone, like tracing
+//! them all with `LOG=trace`.
+
+pub mod errno;
 mod fs;
 mod input;
 mod mem;
@@ -6,58 +12,10 @@ mod process;
 mod sync;
 mod thread;
 
-use fs::*;
-use input::*;
-use mem::*;
-use net::*;
-use process::*;
-use sync::*;
-use thread::*;
-
-const SYSCALL_GETCWD: usize = 17;
-const SYSCALL_DUP: usize = 24;
-const SYSCALL_CONNECT: usize = 29;
-const SYSCALL_LISTEN: usize = 30;
-const SYSCALL_ACCEPT: usize = 31;
-const SYSCALL_MKDIRAT: usize = 34;
-const SYSCALL_UNLINKAT: usize = 35;
-const SYSCALL_LINKAT: usize = 37;
-const SYSCALL_CHDIR: usize = 49;
-const SYSCALL_OPENAT: usize = 56;
-const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_PIPE: usize = 59;
-const SYSCALL_GETDENTS: usize = 61;
-const SYSCALL_READ: usize = 63;
-const SYSCALL_WRITE: usize = 64;
-const SYSCALL_FSTAT: usize = 80;
-const SYSCALL_EXIT: usize = 93;
-const SYSCALL_SLEEP: usize = 101;
-const SYSCALL_YIELD: usize = 124;
-const SYSCALL_KILL: usize = 129;
-const SYSCALL_SIGACTION: usize = 134;
-const SYSCALL_SIGPROCMASK: usize = 135;
-const SYSCALL_SIGRETURN: usize = 139;
-const SYSCALL_GET_TIME: usize = 169;
-const SYSCALL_GETPID: usize = 172;
-const SYSCALL_MUNMAP: usize = 215;
-const SYSCALL_FORK: usize = 220;
-const SYSCALL_EXEC: usize = 221;
-const SYSCALL_MMAP: usize = 222;
-const SYSCALL_WAITPID: usize = 260;
-const SYSCALL_THREAD_CREATE: usize = 1000;
-const SYSCALL_GETTID: usize = 1001;
-const SYSCALL_WAITTID: usize = 1002;
-const SYSCALL_MUTEX_CREATE: usize = 1010;
-const SYSCALL_MUTEX_LOCK: usize = 1011;
-const SYSCALL_MUTEX_UNLOCK: usize = 1012;
-const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
-const SYSCALL_SEMAPHORE_UP: usize = 1021;
-const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
-const SYSCALL_CONDVAR_CREATE: usize = 1030;
-const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
-const SYSCALL_CONDVAR_WAIT: usize = 1032;
-const SYSCALL_EVENT_GET: usize = 3000;
-const SYSCALL_KEY_PRESSED: usize = 3001;
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::sync::atomic::Ordering;
+use errno::ENOSYS;
+use lazy_static::lazy_static;
 
 macro_rules! bail_exit {
     ($e:expr) => {
@@ -69,76 +27,101 @@ macro_rules! bail_exit {
 }
 pub(crate) use bail_exit;
 
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
-    match syscall_id {
-        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
-        SYSCALL_DUP => sys_dup(args[0]),
-        SYSCALL_CONNECT => sys_connect(args[0] as _, args[1] as _, args[2] as _),
-        SYSCALL_LISTEN => sys_listen(args[0] as _),
-        SYSCALL_ACCEPT => sys_accept(args[0] as _),
-        SYSCALL_MKDIRAT => sys_mkdirat(args[0] as isize, args[1] as *const u8),
-        SYSCALL_UNLINKAT => sys_unlinkat(args[0] as isize, args[1] as *const u8),
-        SYSCALL_LINKAT => sys_linkat(args[0] as isize, args[1] as *const u8, args[2] as *const u8),
-        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
-        SYSCALL_OPENAT => sys_openat(args[0] as isize, args[1] as *const u8, args[2] as u32),
-        SYSCALL_CLOSE => sys_close(args[0]),
-        SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
-        SYSCALL_GETDENTS => sys_getdents(args[0], args[1] as *mut _, args[2]),
-        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_FSTAT => sys_fstat(args[0] as usize, args[1] as *mut Stat),
-        SYSCALL_EXIT => sys_exit(args[0] as i32),
-        SYSCALL_SLEEP => sys_sleep(args[0]),
-        SYSCALL_YIELD => sys_yield(),
-        SYSCALL_KILL => sys_kill(args[0], args[1] as i32),
-        SYSCALL_SIGACTION => sys_sigaction(args[0] as i32, args[1] as *const _, args[2] as *mut _),
-        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
-        SYSCALL_SIGRETURN => sys_sigreturn(),
-        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut _),
-        SYSCALL_GETPID => sys_getpid(),
-        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
-        SYSCALL_FORK => sys_fork(),
-        SYSCALL_EXEC => sys_exec(args[0] as *const u8, args[1] as *const usize),
-        SYSCALL_MMAP => {
-            let (start, len) = (args[0], args[1]);
-            let [prot, flags, fd, offset] = unpack_args(args[2] as *const usize);
-            sys_mmap(start, len, prot, flags, fd, offset)
-        }
-        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
-        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
-        SYSCALL_GETTID => sys_gettid(),
-        SYSCALL_WAITTID => sys_waittid(args[0]) as isize,
-        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] == 1),
-        SYSCALL_MUTEX_LOCK => sys_mutex_lock(args[0]),
-        SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(args[0]),
-        SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
-        SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
-        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
-        SYSCALL_CONDVAR_CREATE => sys_condvar_create(),
-        SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
-        SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
-        SYSCALL_EVENT_GET => sys_event_get(),
-        SYSCALL_KEY_PRESSED => sys_key_pressed(),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
-    }
+/// Entries of the syscall table, each one
+/// - `SYSCALL_ID => sys_handler(arg, ..)`: args taken from the registers in order, and cast to
+///   what `sys_handler` takes
+/// - `SYSCALL_ID => sys_handler(arg, ..) { .. }`: the block translates the args instead
+macro_rules! syscalls {
+    ($($id:ident => $handler:ident($($arg:ident),*) $($body:block)?),* $(,)?) => {
+        &[$(
+            $crate::syscall::SyscallEntry {
+                id: $id,
+                name: stringify!($handler),
+                args: &[$(stringify!($arg)),*],
+                handler: |regs| {
+                    let [$($arg,)* ..] = regs;
+                    $crate::syscall::syscalls!(@call $handler($($arg),*) $($body)?)
+                },
+            }
+        ),*]
+    };
+    (@call $handler:ident($($arg:ident),*)) => {
+        $handler($($arg as _),*)
+    };
+    (@call $handler:ident($($arg:ident),*) $body:block) => {
+        $body
+    };
 }
+pub(crate) use syscalls;
 
-fn unpack_args<const N: usize>(args_ptr: *const usize) -> [usize; N] {
-    let total: usize = N * core::mem::size_of::<usize>();
-
-    let token = crate::task::current_user_token();
-    let mut ret = [0; N];
+/// One slot of the syscall table
+pub struct SyscallEntry {
+    pub id: usize,
+    /// of the handler, `sys_` and all
+    pub name: &'static str,
+    /// names of the args it takes, from the first register on
+    pub args: &'static [&'static str],
+    handler: fn([usize; 3]) -> isize,
+}
 
-    // turn [usize] into [u8]
-    let ptr = args_ptr as *const u8;
-    let vs = crate::mm::translated_byte_buffer(token, ptr, total);
+lazy_static! {
+    /// entries of all modules by id
+    static ref SYSCALL_TABLE: BTreeMap<usize, &'static SyscallEntry> = {
+        let mut table = BTreeMap::new();
+        let modules = [
+            fs::SYSCALLS,
+            input::SYSCALLS,
+            mem::SYSCALLS,
+            net::SYSCALLS,
+            process::SYSCALLS,
+            sync::SYSCALLS,
+            thread::SYSCALLS,
+        ];
+        for entry in modules.into_iter().flatten() {
+            if let Some(dup) = table.insert(entry.id, entry) {
+                panic!("syscall {} taken by both {} and {}", entry.id, dup.name, entry.name);
+            }
+        }
+        table
+    };
+}
 
-    for (i, slice) in vs.into_iter().enumerate() {
-        let len = slice.len();
-        let dst_ptr = ret.as_mut_ptr() as *mut u8;
-        let dst = unsafe { core::slice::from_raw_parts_mut(dst_ptr.wrapping_add(i * len), len) };
-        dst.copy_from_slice(slice);
+pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+    let entry = match SYSCALL_TABLE.get(&syscall_id) {
+        Some(entry) => entry,
+        None => return ENOSYS,
+    };
+    // the thread isn't held across, exit never returns to drop it
+    let in_syscall = |v| {
+        crate::task::current_task()
+            .unwrap()
+            .in_syscall
+            .store(v, Ordering::Relaxed)
+    };
+    in_syscall(true);
+    let ret = (entry.handler)(args);
+    in_syscall(false);
+    if log::log_enabled!(log::Level::Trace) {
+        trace(entry, &args, ret);
     }
-
     ret
 }
+
+/// `[pid:tid thread] name(arg=value, ..) = ret` of a syscall returned, exit never does
+fn trace(entry: &SyscallEntry, args: &[usize; 3], ret: isize) {
+    let thread = crate::task::current_task().unwrap().label();
+    let args: Vec<String> = entry
+        .args
+        .iter()
+        .zip(args)
+        .map(|(name, value)| format!("{}={:#x}", name, value))
+        .collect();
+    let name = entry.name.trim_start_matches("sys_");
+    log::trace!("[{}] {}({}) = {}", thread, name, args.join(", "), ret);
+}
+
+/// Args of a syscall taking more than the registers hold, packed in user memory at `args_ptr`
+fn unpack_args<const N: usize>(args_ptr: *const usize) -> Result<[usize; N], isize> {
+    let token = crate::task::current_user_token();
+    crate::mm::read_user_obj(token, args_ptr as *const [usize; N])
+}
@@ -2,19 +2,38 @@ use alloc::sync::Arc;
 use lose_net_stack::IPv4;
 
 use crate::{
+    cast::DowncastArc,
     net::{
         net_interrupt_handler,
         port_table::{accept, listen, port_acceptable, PortFd},
+        socket::{get_sockopt, set_sockopt},
+        tcp::TCP,
         udp::UDP,
     },
     task::{current_process, current_task, current_trap_cx},
 };
 
+use super::{bail_exit, syscalls, SyscallEntry};
+
+const SYSCALL_CONNECT: usize = 29;
+const SYSCALL_LISTEN: usize = 30;
+const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_CONNECT => sys_connect(raddr, lport, rport),
+    SYSCALL_LISTEN => sys_listen(port),
+    SYSCALL_ACCEPT => sys_accept(port_idx),
+    SYSCALL_SETSOCKOPT => sys_setsockopt(fd, opt, val),
+    SYSCALL_GETSOCKOPT => sys_getsockopt(fd, opt),
+};
+
 /// udp only
 pub fn sys_connect(raddr: u32, lport: u16, rport: u16) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
+    let fd = bail_exit!(inner.alloc_fd());
     let udp_node = UDP::new(IPv4::from_u32(raddr), lport, rport);
     inner.fd_table[fd] = Some(Arc::new(udp_node));
     fd as isize
@@ -28,8 +47,9 @@ pub fn sys_listen(port: u16) -> isize {
 
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
+    // the port is given up with it if there's no fd for it
     let port_fd = PortFd::new(port_idx);
+    let fd = bail_exit!(inner.alloc_fd());
     inner.fd_table[fd] = Some(Arc::new(port_fd));
     port_idx as isize // port index NOT fd
 }
@@ -48,3 +68,30 @@ pub fn sys_accept(port_idx: usize) -> isize {
     let trap_cx = current_trap_cx();
     trap_cx.x[10] as isize
 }
+
+/// socket index behind a udp/tcp fd, -1 if `fd` is open on something else
+fn sock_idx_of(fd: isize) -> Result<usize, isize> {
+    let process = current_process();
+    let file = process.inner_exclusive_access().resolve_fd(fd)?;
+    if let Some(udp) = file.clone().downcast_arc::<UDP>() {
+        return Ok(udp.sock_idx);
+    }
+    file.downcast_arc::<TCP>().map(|tcp| tcp.sock_idx).ok_or(-1)
+}
+
+/// opt: SO_SNDBUF/SO_RCVBUF in bytes, SO_NONBLOCK 0/1
+pub fn sys_setsockopt(fd: isize, opt: usize, val: usize) -> isize {
+    let idx = bail_exit!(sock_idx_of(fd));
+    match set_sockopt(idx, opt, val) {
+        Some(()) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_getsockopt(fd: isize, opt: usize) -> isize {
+    let idx = bail_exit!(sock_idx_of(fd));
+    match get_sockopt(idx, opt) {
+        Some(v) => v as isize,
+        _ => -1,
+    }
+}
@@ -1,13 +1,79 @@
 use alloc::{sync::Arc, vec::Vec};
+use core::mem::size_of;
 
 use crate::{
-    fs,
-    mm::{self, translate_ref},
+    cast::DowncastArc,
+    config::{ARGC_MAX, PATH_MAX},
+    fs, mm,
     task::*,
     timer,
 };
 
-use super::bail_exit;
+use super::{
+    bail_exit,
+    errno::{E2BIG, EFAULT, EINTR, EINVAL, EPERM, ESRCH},
+    fs::TimeSpec,
+    syscalls, unpack_args, SyscallEntry,
+};
+
+const SYSCALL_EXIT: usize = 93;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_YIELD: usize = 124;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGSUSPEND: usize = 133;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGPENDING: usize = 136;
+const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_FORK: usize = 220;
+const SYSCALL_EXEC: usize = 221;
+const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_TCGETPGRP: usize = 1050;
+const SYSCALL_TCSETPGRP: usize = 1051;
+const SYSCALL_PROCESS_STATS: usize = 2002;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_EXIT => sys_exit(exit_code),
+    SYSCALL_CLOCK_GETTIME => sys_clock_gettime(clock_id, ts),
+    SYSCALL_YIELD => sys_yield(),
+    SYSCALL_KILL => sys_kill(pid, signum),
+    SYSCALL_SIGSUSPEND => sys_sigsuspend(mask),
+    SYSCALL_SIGACTION => sys_sigaction(signum, action_ptr, old_action_ptr),
+    SYSCALL_SIGPROCMASK => sys_sigprocmask(how, set, old_set),
+    SYSCALL_SIGPENDING => sys_sigpending(),
+    SYSCALL_SIGRETURN => sys_sigreturn(),
+    SYSCALL_SETGID => sys_setgid(gid),
+    SYSCALL_SETUID => sys_setuid(uid),
+    SYSCALL_TIMES => sys_times(tms),
+    SYSCALL_SETPGID => sys_setpgid(pid, pgid),
+    SYSCALL_GETPGID => sys_getpgid(pid),
+    SYSCALL_GETRUSAGE => sys_getrusage(who, usage),
+    SYSCALL_GET_TIME => sys_get_time(ts),
+    SYSCALL_GETPID => sys_getpid(),
+    SYSCALL_GETUID => sys_getuid(),
+    SYSCALL_GETGID => sys_getgid(),
+    SYSCALL_FORK => sys_fork(),
+    SYSCALL_EXEC => sys_exec(path, args),
+    SYSCALL_WAITPID => sys_waitpid(pid, exit_code_ptr),
+    SYSCALL_PRLIMIT64 => sys_prlimit64(pid, resource, packed) {
+        let [new_limit, old_limit] = bail_exit!(unpack_args(packed as *const usize));
+        sys_prlimit64(pid, resource, new_limit as _, old_limit as _)
+    },
+    SYSCALL_TCGETPGRP => sys_tcgetpgrp(fd),
+    SYSCALL_TCSETPGRP => sys_tcsetpgrp(fd, pgid),
+    SYSCALL_PROCESS_STATS => sys_process_stats(stats),
+};
 
 /// task exits and submit an exit code
 pub fn sys_exit(exit_code: i32) -> ! {
@@ -29,25 +95,136 @@ pub struct TimeVal {
 }
 
 /// get time
+impl TimeVal {
+    fn from_us(us: usize) -> Self {
+        Self {
+            sec: us / 1_000_000,
+            usec: us % 1_000_000,
+        }
+    }
+}
+
 pub fn sys_get_time(ts: *mut TimeVal) -> isize {
-    let us = timer::get_time_us();
-    let dst_vs = mm::translated_byte_buffer(
-        current_user_token(),
-        ts as *const u8,
-        core::mem::size_of::<TimeVal>(),
-    );
-    let ts = TimeVal {
-        sec: us / 1_000_000,
-        usec: us % 1_000_000,
+    let tv = TimeVal::from_us(timer::get_time_us());
+    bail_exit!(mm::write_user_obj(current_user_token(), ts, &tv));
+    0
+}
+
+/// `clock_id` of `sys_clock_gettime`: wall time since the epoch, or time since boot
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+/// Time of clock `clock_id` put to `ts`, as precise as the timer counts
+pub fn sys_clock_gettime(clock_id: usize, ts: *mut TimeSpec) -> isize {
+    let ns = match clock_id {
+        CLOCK_REALTIME => timer::get_wall_time_ns(),
+        CLOCK_MONOTONIC => timer::get_time_ns(),
+        _ => return EINVAL,
     };
-    let ts_ptr = (&ts as *const TimeVal) as *const u8;
-    for (i, dst) in dst_vs.into_iter().enumerate() {
-        let len = dst.len();
-        unsafe {
-            let src = core::slice::from_raw_parts(ts_ptr.wrapping_add(i * len), len);
-            dst.copy_from_slice(src);
+    let token = current_user_token();
+    if !mm::user_accessible(token, ts as usize, size_of::<TimeSpec>(), true) {
+        return EFAULT;
+    }
+    bail_exit!(mm::write_user_obj(token, ts, &TimeSpec::from_ns(ns)));
+    0
+}
+
+/// all in us
+#[repr(C)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+/// Times of the caller and of its reaped children, returns the current time in us.
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let tms_val = Tms {
+        utime: inner.user_time,
+        stime: inner.kernel_time,
+        cutime: inner.cutime,
+        cstime: inner.cstime,
+    };
+    let token = inner.memory_set.token();
+    drop(inner);
+    bail_exit!(mm::write_user_obj(token, tms, &tms_val));
+    timer::get_time_us() as isize
+}
+
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+
+/// only the times and page faults are tracked
+#[repr(C)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    pub minflt: usize,
+    pub majflt: usize,
+    /// not in posix, changes to live mappings that flushed the tlb
+    pub tlb_flushes: usize,
+}
+
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let (utime, stime, faults) = match who {
+        RUSAGE_SELF => (inner.user_time, inner.kernel_time, inner.fault_stats),
+        RUSAGE_CHILDREN => (inner.cutime, inner.cstime, inner.cfault_stats),
+        _ => return EINVAL,
+    };
+    let token = inner.memory_set.token();
+    drop(inner);
+    let usage_val = RUsage {
+        utime: TimeVal::from_us(utime),
+        stime: TimeVal::from_us(stime),
+        minflt: faults.minor,
+        majflt: faults.major,
+        tlb_flushes: faults.tlb_flushes,
+    };
+    bail_exit!(mm::write_user_obj(token, usage, &usage_val));
+    0
+}
+
+/// Limit of `resource` of process `pid`, 0 for the caller, goes to `old_limit` and becomes
+/// `new_limit`, either may be null. Limits of other users' processes are root's to touch.
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    let process = current_process();
+    let (token, cred) = {
+        let inner = process.inner_exclusive_access();
+        (inner.get_user_token(), inner.cred)
+    };
+    let target = if pid == 0 || pid == process.getpid() {
+        process
+    } else {
+        bail_exit!(pid2process(pid).ok_or(ESRCH))
+    };
+    let mut inner = target.inner_exclusive_access();
+    if !cred.is_root() && inner.cred.uid != cred.uid {
+        return EPERM;
+    }
+    let old = bail_exit!(inner.rlimits.get(resource).ok_or(EINVAL));
+    if !new_limit.is_null() {
+        let limit = bail_exit!(mm::read_user_obj(token, new_limit));
+        if limit.cur > limit.max {
+            return EINVAL;
+        }
+        if !inner.rlimits.set(resource, limit, cred.is_root()) {
+            return EPERM;
         }
     }
+    drop(inner);
+    if !old_limit.is_null() {
+        bail_exit!(mm::write_user_obj(token, old_limit, &old));
+    }
     0
 }
 
@@ -56,9 +233,128 @@ pub fn sys_getpid() -> isize {
     proc.getpid() as isize
 }
 
+pub fn sys_getuid() -> isize {
+    current_process().inner_exclusive_access().cred.uid as isize
+}
+
+pub fn sys_getgid() -> isize {
+    current_process().inner_exclusive_access().cred.gid as isize
+}
+
+/// Act as user `uid` from now on, only root may become someone else. Once not root, there's
+/// no way back.
+pub fn sys_setuid(uid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match u16::try_from(uid) {
+        Ok(uid) if inner.cred.is_root() || uid == inner.cred.uid => {
+            inner.cred.uid = uid;
+            0
+        }
+        Ok(_) => -1,
+        Err(_) => EINVAL,
+    }
+}
+
+/// Act as group `gid` from now on, only root may join another.
+pub fn sys_setgid(gid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match u16::try_from(gid) {
+        Ok(gid) if inner.cred.is_root() || gid == inner.cred.gid => {
+            inner.cred.gid = gid;
+            0
+        }
+        Ok(_) => -1,
+        Err(_) => EINVAL,
+    }
+}
+
+/// `pid` 0 is the caller, `pgid` 0 makes the target lead a new group of its own. Only the
+/// caller or one of its children can be moved, and only into a group on the same terminal.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    let process = current_process();
+    let target = if pid == 0 || pid == process.getpid() {
+        process
+    } else {
+        let inner = process.inner_exclusive_access();
+        let child = inner.children.iter().find(|p| p.getpid() == pid).cloned();
+        match child {
+            Some(child) => child,
+            None => return ESRCH,
+        }
+    };
+    let pgid = if pgid == 0 { target.getpid() } else { pgid };
+    let tty = match target.inner_exclusive_access().tty.clone() {
+        Some(tty) => tty,
+        None => return -1,
+    };
+    if pgid != target.getpid() && !group_on_tty(pgid, &tty) {
+        return -1;
+    }
+    target.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    let process = if pid == 0 {
+        current_process()
+    } else {
+        match pid2process(pid) {
+            Some(p) => p,
+            None => return ESRCH,
+        }
+    };
+    let pgid = process.inner_exclusive_access().pgid;
+    pgid as isize
+}
+
+/// foreground group of the terminal behind `fd`
+pub fn sys_tcgetpgrp(fd: isize) -> isize {
+    bail_exit!(controlling_tty(fd)).foreground() as isize
+}
+
+/// Hand the terminal behind `fd` to group `pgid`, which must be on it.
+pub fn sys_tcsetpgrp(fd: isize, pgid: usize) -> isize {
+    let tty = bail_exit!(controlling_tty(fd));
+    if !group_on_tty(pgid, &tty) {
+        return -1;
+    }
+    tty.set_foreground(pgid);
+    0
+}
+
+/// some process of group `pgid` has `tty` as its controlling terminal
+fn group_on_tty(pgid: usize, tty: &Arc<fs::Tty>) -> bool {
+    pgid2processes(pgid).iter().any(|p| {
+        p.inner_exclusive_access()
+            .tty
+            .as_ref()
+            .is_some_and(|t| Arc::ptr_eq(t, tty))
+    })
+}
+
+/// terminal behind `fd`, -1 unless it is the caller's controlling one
+fn controlling_tty(fd: isize) -> Result<Arc<fs::Tty>, isize> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.resolve_fd(fd)?;
+    let tty = match file.clone().downcast_arc::<fs::Stdin>() {
+        Some(stdin) => stdin.0.clone(),
+        None => file.downcast_arc::<fs::Stdout>().ok_or(-1)?.0.clone(),
+    };
+    match inner.tty.as_ref() {
+        Some(own) if Arc::ptr_eq(own, &tty) => Ok(tty),
+        _ => Err(-1),
+    }
+}
+
 pub fn sys_fork() -> isize {
     let curr_proc = current_process();
-    let new_proc = curr_proc.fork();
+    let new_proc = match curr_proc.fork(&current_task().unwrap()) {
+        Some(v) => v,
+        None => return -1,
+    };
     let new_pid = new_proc.pid.0;
     // modify trap context of new_task, because it returns immediately after switching
     let inner = new_proc.inner_exclusive_access();
@@ -72,23 +368,47 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let proc = current_process();
-    let token = proc.inner_exclusive_access().get_user_token();
-    let path = mm::translated_str(token, path);
+    let (token, stack_limit) = {
+        let inner = proc.inner_exclusive_access();
+        (inner.get_user_token(), inner.rlimits.stack.cur)
+    };
+    let path = bail_exit!(mm::translated_str(token, path, PATH_MAX));
     let mut args_vec = Vec::new();
+    // argv and strings are pushed on the new user stack, they have to fit with room to spare
+    let mut args_size = size_of::<usize>();
     loop {
-        let arg_str_ptr = *mm::translate_ref(token, args);
+        if !mm::user_accessible(token, args as usize, size_of::<usize>(), false) {
+            return EFAULT;
+        }
+        let arg_str_ptr = bail_exit!(mm::read_user_obj(token, args));
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(mm::translated_str(token, arg_str_ptr as *const u8));
+        if args_vec.len() == ARGC_MAX {
+            return E2BIG;
+        }
+        let arg = bail_exit!(mm::translated_str(
+            token,
+            arg_str_ptr as *const u8,
+            PATH_MAX
+        ));
+        args_size += size_of::<usize>() + arg.len() + 1;
+        if args_size > stack_limit / 2 {
+            return E2BIG;
+        }
+        args_vec.push(arg);
         unsafe {
             args = args.add(1);
         }
     }
     if let Some(elf_inode) = fs::open_file(&path, fs::OpenFlags::RDONLY) {
-        let elf_data = elf_inode.read_all();
+        let exe = elf_inode.clone_inner_inode();
+        // anything wrong with the file shows before the old image is torn down
+        let image = bail_exit!(load_elf(&exe));
         let argc = args_vec.len();
-        proc.exec(elf_data.as_slice(), args_vec);
+        let task = current_task().unwrap();
+        proc.exec(&task, exe, image, args_vec);
+        task.set_name(path.rsplit('/').next().unwrap());
         // !!return argc because cx.x[10] will be covered with it later
         argc as isize
     } else {
@@ -98,8 +418,16 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
 
 /// If there is not a child process whose pid is same as given, return -1.
 /// Else if there is a child process but it is still running, return -2.
+/// The exit code goes to `exit_code_ptr` unless it's null, `EFAULT` before any child is
+/// reaped if it can't.
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     let proc = current_process();
+    let token = proc.inner_exclusive_access().get_user_token();
+    if !exit_code_ptr.is_null()
+        && !mm::user_accessible(token, exit_code_ptr as usize, size_of::<i32>(), true)
+    {
+        return EFAULT;
+    }
     let mut inner = proc.inner_exclusive_access();
 
     // find arbitrary child (given `pid: -1`) OR child identified by `pid`
@@ -121,30 +449,99 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     let p = inner.children.remove(idx);
     assert_eq!(Arc::strong_count(&p), 1);
     let child_pid = p.getpid();
-    let exit_code = p.inner_exclusive_access().exit_code;
+    let child_inner = p.inner_exclusive_access();
+    let exit_code = child_inner.exit_code;
+    // the child's times would go with it
+    inner.cutime += child_inner.user_time + child_inner.cutime;
+    inner.cstime += child_inner.kernel_time + child_inner.cstime;
+    inner.cfault_stats.add(&child_inner.fault_stats);
+    inner.cfault_stats.add(&child_inner.cfault_stats);
+    drop(child_inner);
     // set exit_code
-    *mm::translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+    if !exit_code_ptr.is_null() {
+        bail_exit!(mm::write_user_obj(token, exit_code_ptr, &exit_code));
+    }
     child_pid as isize
 }
 
 pub fn sys_kill(pid: usize, signum: i32) -> isize {
+    if signum as usize > MAX_SIG {
+        return -1;
+    }
     let flag = bail_exit!(SignalFlags::from_bits(1 << signum).ok_or(-1));
     let proc = bail_exit!(pid2process(pid).ok_or(-1));
-    let mut inner = proc.inner_exclusive_access();
-    if inner.signals.contains(flag) {
-        return -1;
+    if raise_signal(&proc, flag) {
+        0
+    } else {
+        -1
     }
-    inner.signals.insert(flag);
-    0
 }
 
-pub fn sys_sigprocmask(mask: u32) -> isize {
-    let new_mask = bail_exit!(SignalFlags::from_bits(mask).ok_or(-1));
+/// `how` of sigprocmask: add `set` to the mask
+const SIG_BLOCK: usize = 0;
+/// remove `set` from the mask
+const SIG_UNBLOCK: usize = 1;
+/// replace the mask with `set`
+const SIG_SETMASK: usize = 2;
+
+/// Change the mask by `how` unless `set` is null, the mask before goes to `old_set` if given.
+pub fn sys_sigprocmask(how: usize, set: *const u32, old_set: *mut u32) -> isize {
+    let token = current_user_token();
     let task = bail_exit!(current_task().ok_or(-1));
     let mut inner = task.inner_exclusive_access();
     let old_mask = inner.signal_processor.signal_mask;
-    inner.signal_processor.signal_mask = new_mask;
-    old_mask.bits() as isize
+    if !set.is_null() {
+        let set = bail_exit!(mm::read_user_obj(token, set));
+        let set = bail_exit!(SignalFlags::from_bits(set).ok_or(-1));
+        let mask = match how {
+            SIG_BLOCK => old_mask | set,
+            SIG_UNBLOCK => old_mask - set,
+            SIG_SETMASK => set,
+            _ => return EINVAL,
+        };
+        // not blockable, quietly left out
+        inner.signal_processor.signal_mask = mask - (SignalFlags::SIGKILL | SignalFlags::SIGSTOP);
+    }
+    if !old_set.is_null() {
+        bail_exit!(mm::write_user_obj(token, old_set, &old_mask.bits()));
+    }
+    0
+}
+
+/// Wait with `mask` in place for a signal to act on, the old mask is back once it's been
+/// handled.
+pub fn sys_sigsuspend(mask: u32) -> isize {
+    let mask = bail_exit!(SignalFlags::from_bits(mask).ok_or(-1));
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let old_mask = core::mem::replace(&mut inner.signal_processor.signal_mask, mask);
+    inner.signal_processor.saved_mask = Some(old_mask);
+    drop(inner);
+    while !current_has_deliverable_signal() {
+        task.inner_exclusive_access().signal_processor.suspended = true;
+        block_current_and_run_next();
+    }
+    EINTR
+}
+
+/// Signals raised but held back by the mask.
+pub fn sys_sigpending() -> isize {
+    let task = bail_exit!(current_task().ok_or(-1));
+    let signals = task
+        .process
+        .upgrade()
+        .unwrap()
+        .inner_exclusive_access()
+        .signals;
+    let inner = task.inner_exclusive_access();
+    let blocked = inner
+        .signal_processor
+        .frames
+        .iter()
+        .fold(inner.signal_processor.signal_mask, |acc, frame| {
+            acc | frame.blocked
+        });
+    (signals & blocked).bits() as isize
 }
 
 fn check_sigaction_error(signal: SignalFlags, action: usize, old_action: usize) -> bool {
@@ -175,23 +572,35 @@ pub fn sys_sigaction(
         let inner = process.inner_exclusive_access();
         inner.get_user_token()
     };
+    let action = bail_exit!(mm::read_user_obj(token, action_ptr));
     let mut inner = task.inner_exclusive_access();
 
     let prev_action = inner.signal_processor.signal_actions.get_action(signum);
-    *mm::translated_refmut(token, old_action_ptr) = prev_action;
+    bail_exit!(mm::write_user_obj(token, old_action_ptr, &prev_action));
     inner
         .signal_processor
         .signal_actions
-        .set_action(signum, *translate_ref(token, action_ptr));
+        .set_action(signum, action);
     0
 }
 
 pub fn sys_sigreturn() -> isize {
     let task = bail_exit!(current_task().ok_or(-1));
     let mut inner = task.inner_exclusive_access();
-    inner.signal_processor.signal_handling = None;
-    // restore trap_cx
+    // not in a handler
+    let frame = bail_exit!(inner.signal_processor.frames.pop().ok_or(-1));
+    // restore trap_cx of whatever the handler interrupted, maybe an outer handler
     let trap_cx = inner.get_trap_cx();
-    *trap_cx = inner.signal_processor.trap_cx_backup.take().unwrap();
+    *trap_cx = frame.trap_cx;
     trap_cx.x[10] as isize
 }
+
+/// not in posix, counters of processes put to `stats`, for benchmarks and stress tests
+pub fn sys_process_stats(stats: *mut ProcessStats) -> isize {
+    let token = current_user_token();
+    if !mm::user_accessible(token, stats as usize, size_of::<ProcessStats>(), true) {
+        return EFAULT;
+    }
+    bail_exit!(mm::write_user_obj(token, stats, &process_stats()));
+    0
+}
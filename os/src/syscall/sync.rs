@@ -1,16 +1,97 @@
 use alloc::sync::Arc;
+use core::arch::asm;
 
 use crate::{
-    sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore},
+    sync::{Barrier, Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore},
     task, timer,
 };
 
+use super::{
+    errno::{EBUSY, EINTR, EINVAL},
+    syscalls, SyscallEntry,
+};
+
+const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_MEMBARRIER: usize = 283;
+const SYSCALL_MUTEX_CREATE: usize = 1010;
+const SYSCALL_MUTEX_LOCK: usize = 1011;
+const SYSCALL_MUTEX_UNLOCK: usize = 1012;
+const SYSCALL_MUTEX_DESTROY: usize = 1013;
+const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
+const SYSCALL_SEMAPHORE_UP: usize = 1021;
+const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
+const SYSCALL_SEMAPHORE_DESTROY: usize = 1023;
+const SYSCALL_CONDVAR_CREATE: usize = 1030;
+const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
+const SYSCALL_CONDVAR_WAIT: usize = 1032;
+const SYSCALL_CONDVAR_DESTROY: usize = 1033;
+const SYSCALL_BARRIER_CREATE: usize = 1040;
+const SYSCALL_BARRIER_WAIT: usize = 1041;
+const SYSCALL_BARRIER_DESTROY: usize = 1042;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_SLEEP => sys_sleep(ms),
+    SYSCALL_MEMBARRIER => sys_membarrier(cmd, flags),
+    SYSCALL_MUTEX_CREATE => sys_mutex_create(blocking) { sys_mutex_create(blocking == 1) },
+    SYSCALL_MUTEX_LOCK => sys_mutex_lock(mutex_id),
+    SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(mutex_id),
+    SYSCALL_MUTEX_DESTROY => sys_mutex_destroy(mutex_id),
+    SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(res_count),
+    SYSCALL_SEMAPHORE_UP => sys_semaphore_up(sem_id),
+    SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(sem_id),
+    SYSCALL_SEMAPHORE_DESTROY => sys_semaphore_destroy(sem_id),
+    SYSCALL_CONDVAR_CREATE => sys_condvar_create(),
+    SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(condvar_id),
+    SYSCALL_CONDVAR_WAIT => sys_condvar_wait(condvar_id, mutex_id),
+    SYSCALL_CONDVAR_DESTROY => sys_condvar_destroy(condvar_id),
+    SYSCALL_BARRIER_CREATE => sys_barrier_create(count),
+    SYSCALL_BARRIER_WAIT => sys_barrier_wait(barrier_id),
+    SYSCALL_BARRIER_DESTROY => sys_barrier_destroy(barrier_id),
+};
+
 pub fn sys_sleep(ms: usize) -> isize {
     let expire_ms = timer::get_time_ms() + ms;
     let task = task::current_task().unwrap();
     timer::add_timer(expire_ms, task);
     task::block_current_and_run_next();
-    0
+    // the timer only wakes us once due, anyone else had to cancel it first
+    if timer::get_time_ms() < expire_ms {
+        EINTR
+    } else {
+        0
+    }
+}
+
+/// `cmd` of membarrier: mask of the commands supported
+const MEMBARRIER_CMD_QUERY: usize = 0;
+/// all threads of all processes pass a barrier
+const MEMBARRIER_CMD_GLOBAL: usize = 1 << 0;
+/// threads of the calling process pass a barrier
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED: usize = 1 << 3;
+/// nothing to register for, taken for what it asks
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: usize = 1 << 4;
+
+/// Every thread `cmd` covers has passed a full memory barrier once it returns, so fast paths
+/// of user sync may get by with compiler barriers alone. There's a single hart, the other
+/// threads run on it too and see its accesses in program order, so fencing it is all it takes;
+/// with more harts, the others would be IPI'ed to fence as well.
+pub fn sys_membarrier(cmd: usize, flags: usize) -> isize {
+    if flags != 0 {
+        return EINVAL;
+    }
+    match cmd {
+        MEMBARRIER_CMD_QUERY => {
+            (MEMBARRIER_CMD_GLOBAL
+                | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+                | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) as isize
+        }
+        MEMBARRIER_CMD_GLOBAL | MEMBARRIER_CMD_PRIVATE_EXPEDITED => {
+            unsafe { asm!("fence rw, rw") };
+            0
+        }
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED => 0,
+        _ => EINVAL,
+    }
 }
 
 pub fn sys_mutex_create(blocking: bool) -> isize {
@@ -56,6 +137,20 @@ pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
     0
 }
 
+pub fn sys_mutex_destroy(mutex_id: usize) -> isize {
+    let process = task::current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.mutex_list.get(mutex_id) {
+        Some(Some(v)) if v.busy() => EBUSY,
+        Some(Some(_)) => {
+            // free slot for reuse
+            process_inner.mutex_list[mutex_id] = None;
+            0
+        }
+        _ => EINVAL, // mutex not exist
+    }
+}
+
 pub fn sys_semaphore_create(res_count: usize) -> isize {
     let process = task::current_process();
     let mut process_inner = process.inner_exclusive_access();
@@ -99,6 +194,19 @@ pub fn sys_semaphore_down(sem_id: usize) -> isize {
     0
 }
 
+pub fn sys_semaphore_destroy(sem_id: usize) -> isize {
+    let process = task::current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.semaphore_list.get(sem_id) {
+        Some(Some(v)) if v.busy() => EBUSY,
+        Some(Some(_)) => {
+            process_inner.semaphore_list[sem_id] = None;
+            0
+        }
+        _ => EINVAL, // sem not exist
+    }
+}
+
 pub fn sys_condvar_create() -> isize {
     let process = task::current_process();
     let mut process_inner = process.inner_exclusive_access();
@@ -141,3 +249,58 @@ pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     cv.wait(mutex);
     0
 }
+
+pub fn sys_condvar_destroy(condvar_id: usize) -> isize {
+    let process = task::current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.condvar_list.get(condvar_id) {
+        Some(Some(v)) if v.busy() => EBUSY,
+        Some(Some(_)) => {
+            process_inner.condvar_list[condvar_id] = None;
+            0
+        }
+        _ => EINVAL, // cv not exist
+    }
+}
+
+pub fn sys_barrier_create(count: usize) -> isize {
+    if count == 0 {
+        return -1;
+    }
+    let process = task::current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let barrier = Some(Arc::new(Barrier::new(count)));
+    if let Some(idx) = process_inner.barrier_list.iter().position(|v| v.is_none()) {
+        process_inner.barrier_list[idx] = barrier;
+        idx as isize
+    } else {
+        process_inner.barrier_list.push(barrier);
+        process_inner.barrier_list.len() as isize - 1
+    }
+}
+
+/// 1 for the last thread to arrive, 0 for the others
+pub fn sys_barrier_wait(barrier_id: usize) -> isize {
+    let process = task::current_process();
+    let process_inner = process.inner_exclusive_access();
+    let barrier = match process_inner.barrier_list.get(barrier_id) {
+        Some(Some(v)) => v.clone(),
+        _ => return -1, // barrier not exist
+    };
+    drop(process_inner);
+    drop(process);
+    barrier.wait() as isize
+}
+
+pub fn sys_barrier_destroy(barrier_id: usize) -> isize {
+    let process = task::current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    match process_inner.barrier_list.get(barrier_id) {
+        Some(Some(v)) if v.busy() => EBUSY,
+        Some(Some(_)) => {
+            process_inner.barrier_list[barrier_id] = None;
+            0
+        }
+        _ => EINVAL, // barrier not exist
+    }
+}
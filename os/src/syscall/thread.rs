@@ -2,30 +2,47 @@ use alloc::sync::Arc;
 
 use crate::{
     mm,
-    task::{self, add_task, TaskControlBlock},
+    task::{self, add_task, block_current_and_run_next, TaskControlBlock, THREAD_NAME_MAX},
     trap::{trap_handler, TrapContext},
 };
 
+use super::{
+    bail_exit,
+    errno::{EDEADLK, ERANGE, ESRCH},
+    syscalls, SyscallEntry,
+};
+
+const SYSCALL_THREAD_CREATE: usize = 1000;
+const SYSCALL_GETTID: usize = 1001;
+const SYSCALL_WAITTID: usize = 1002;
+const SYSCALL_SET_THREAD_NAME: usize = 1003;
+const SYSCALL_GET_THREAD_NAME: usize = 1004;
+
+pub(super) static SYSCALLS: &[SyscallEntry] = syscalls! {
+    SYSCALL_THREAD_CREATE => sys_thread_create(entry, arg),
+    SYSCALL_GETTID => sys_gettid(),
+    SYSCALL_WAITTID => sys_waittid(tid) { sys_waittid(tid) as isize },
+    SYSCALL_SET_THREAD_NAME => sys_set_thread_name(tid, name),
+    SYSCALL_GET_THREAD_NAME => sys_get_thread_name(tid, buf, len),
+};
+
 // create_thread(void *func_ptr, void *arg);
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     let task = task::current_task().unwrap();
     let process = task.process.upgrade().unwrap();
+    // let go of the TCB before the new one borrows the process
+    let (ustack_base, name) = {
+        let inner = task.inner_exclusive_access();
+        (inner.res.as_ref().unwrap().ustack_base, inner.name.clone())
+    };
     // create new TCB
-    let new_task = Arc::new(TaskControlBlock::new(
-        process.clone(),
-        task.inner_exclusive_access()
-            .res
-            .as_ref()
-            .unwrap()
-            .ustack_base,
-        true,
-    ));
+    let new_task = Arc::new(TaskControlBlock::new(process.clone(), ustack_base, true));
+    // named after its creator until it's named otherwise
+    new_task.inner_exclusive_access().name = name;
     // add new thread to scheduler
     add_task(new_task.clone());
 
-    let new_task_inner = new_task.inner_exclusive_access();
-    let new_task_res = new_task_inner.res.as_ref().unwrap();
-    let new_task_id = new_task_res.tid;
+    let new_task_id = new_task.inner_exclusive_access().res.as_ref().unwrap().tid;
 
     // add new thread to process
     let mut process_inner = process.inner_exclusive_access();
@@ -34,7 +51,10 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         tasks.push(None);
     }
     tasks[new_task_id] = Some(new_task.clone()); // when it's A recycled id, then old TaskUserRes is finally dropped
+    drop(process_inner);
 
+    let new_task_inner = new_task.inner_exclusive_access();
+    let new_task_res = new_task_inner.res.as_ref().unwrap();
     let new_task_trap_cx = new_task_inner.get_trap_cx();
     *new_task_trap_cx = TrapContext::app_init_context(
         entry,
@@ -58,31 +78,70 @@ pub fn sys_gettid() -> isize {
         .tid as isize
 }
 
+/// Block until thread `tid` of the current process exits, return its exit code. The thread is
+/// recycled once every waiter has collected the code, later calls get `ESRCH`.
 pub fn sys_waittid(tid: usize) -> i32 {
     let task = task::current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
     // a thread cannot wait for itself
-    if task_inner.res.as_ref().unwrap().tid == tid {
-        return -1;
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == tid {
+        return EDEADLK as i32;
     }
-    // sometimes thread run-and-exit(sys_exit) too fast, TaskUserRes got dropped as well as tid
-    // so new created thread reuses the tid
-    // say we want to create th-1,2,3, after 1,2 created, before 3 create, 1,2 fin and sys_exit
-    // so th-3 reuses (maybe) 2; then waittid(1) -> ok with exit1, waittid(2)(we want 2 but actually 3) we got exit3 here,
-    // wattid(2), we want 3 but exit3 already returned and dealloc, so this waittid(2) got None -> -1
-    // conclusion: we should not recycle tid too early, or until wait success or process exit
-    let exit_code = match process_inner.tasks.get(tid) {
-        Some(Some(t)) => t.inner_exclusive_access().exit_code.clone(),
-        _ => return -1, // tid not exist
+    // tids index this process's own tasks, so no way to reach another process's thread
+    let target = match process.inner_exclusive_access().tasks.get(tid) {
+        Some(Some(t)) => t.clone(),
+        _ => return ESRCH as i32, // tid not exist, or collected already
     };
-    if let Some(exit_code) = exit_code {
-        // dealloc exited thread
+    let mut target_inner = target.inner_exclusive_access();
+    if target_inner.exit_code.is_none() {
+        target_inner.waiters += 1;
+        target_inner.join_queue.push_back(task);
+        drop(target_inner);
+        block_current_and_run_next();
+        target_inner = target.inner_exclusive_access();
+        target_inner.waiters -= 1;
+    }
+    let exit_code = target_inner.exit_code.unwrap();
+    if target_inner.waiters == 0 {
+        drop(target_inner);
+        // dealloc exited thread: kstack goes with the last Arc, tid is free for reuse
+        let mut process_inner = process.inner_exclusive_access();
         process_inner.tasks[tid] = None;
         process_inner.dealloc_tid(tid);
-        exit_code
-    } else {
-        -2
     }
+    exit_code
+}
+
+/// Thread `tid` of the current process, `ESRCH` if there's none
+fn thread_of_current(tid: usize) -> Result<Arc<TaskControlBlock>, isize> {
+    let process = task::current_process();
+    let inner = process.inner_exclusive_access();
+    match inner.tasks.get(tid) {
+        Some(Some(task)) => Ok(task.clone()),
+        _ => Err(ESRCH),
+    }
+}
+
+/// Name thread `tid` of the current process `name`, at most `THREAD_NAME_MAX` bytes or it's
+/// `ENAMETOOLONG`
+pub fn sys_set_thread_name(tid: usize, name: *const u8) -> isize {
+    let token = task::current_user_token();
+    let name = bail_exit!(mm::translated_str(token, name, THREAD_NAME_MAX));
+    bail_exit!(thread_of_current(tid)).set_name(&name);
+    0
+}
+
+/// Copy the name of thread `tid` of the current process to `buf`, returns its length. ERANGE if
+/// it takes more than `len` bytes with the trailing nul.
+pub fn sys_get_thread_name(tid: usize, buf: *mut u8, len: usize) -> isize {
+    let token = task::current_user_token();
+    let task = bail_exit!(thread_of_current(tid));
+    let mut name = task.inner_exclusive_access().name.clone().into_bytes();
+    if name.len() + 1 > len {
+        return ERANGE;
+    }
+    let name_len = name.len();
+    name.push(0);
+    bail_exit!(mm::copy_to_user(token, buf, &name));
+    name_len as isize
 }
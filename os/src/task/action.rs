@@ -1,17 +1,33 @@
+use bitflags::bitflags;
+
 use super::{SignalFlags, MAX_SIG};
 
+/// `SignalAction::handler` values that aren't handlers
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    pub struct SignalActionFlags: u32 {
+        /// the signal isn't blocked while its own handler runs
+        const SA_NODEFER = 1 << 30;
+    }
+}
+
 #[repr(C, align(16))]
 #[derive(Debug, Clone, Copy)]
 pub struct SignalAction {
     pub handler: usize,
+    /// blocked while the handler runs
     pub mask: SignalFlags,
+    pub flags: SignalActionFlags,
 }
 
 impl Default for SignalAction {
     fn default() -> Self {
         Self {
-            handler: 0,
+            handler: SIG_DFL,
             mask: SignalFlags::from_bits_truncate(40), // SIGTRAP | SIGQUIT
+            flags: SignalActionFlags::empty(),
         }
     }
 }
@@ -22,11 +38,6 @@ pub struct SignalActions {
 }
 
 impl SignalActions {
-    pub fn is_masked(&self, signum: usize, signal: SignalFlags) -> bool {
-        assert!(signum <= MAX_SIG);
-        self.table[signum].mask.contains(signal)
-    }
-
     pub fn get_handler(&self, signum: usize) -> usize {
         assert!(signum <= MAX_SIG);
         self.table[signum].handler
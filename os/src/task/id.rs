@@ -1,12 +1,12 @@
 use alloc::{
+    collections::BTreeSet,
     sync::{Arc, Weak},
-    vec::Vec,
 };
 use lazy_static::lazy_static;
 
 use crate::{
     config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE},
-    mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE},
+    mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE},
     sync::UPIntrFreeCell,
 };
 
@@ -17,22 +17,29 @@ lazy_static! {
         unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
     static ref KSTACK_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
         unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
+    static ref PROCESS_STATS: UPIntrFreeCell<ProcessStats> =
+        unsafe { UPIntrFreeCell::new(ProcessStats::default()) };
 }
+/// Ids from 0 up, the lowest freed one reused first. Freeing the highest one in use hands it
+/// back along with the free ones right below, so churn leaves no more ids around than were
+/// ever alive at once.
 pub struct RecycleAllocator {
+    /// ids at or above are fresh
     current: usize,
-    recycled: Vec<usize>,
+    /// freed ones below `current`, never its predecessor
+    recycled: BTreeSet<usize>,
 }
 impl RecycleAllocator {
     pub fn new() -> Self {
         Self {
             current: 0,
-            recycled: Vec::new(),
+            recycled: BTreeSet::new(),
         }
     }
 
     pub fn alloc(&mut self) -> usize {
-        match self.recycled.pop() {
-            Some(v) => v.into(),
+        match self.recycled.pop_first() {
+            Some(v) => v,
             _ => {
                 let v = self.current;
                 self.current += 1;
@@ -43,27 +50,44 @@ impl RecycleAllocator {
 
     pub fn dealloc(&mut self, id: usize) {
         assert!(id < self.current);
-        assert!(
-            self.recycled.iter().find(|&v| v == &id).is_none(),
-            "id {} has been deallocated!",
-            id
-        );
-        self.recycled.push(id);
+        assert!(self.recycled.insert(id), "id {} has been deallocated!", id);
+        while self.recycled.last() == Some(&(self.current - 1)) {
+            self.recycled.pop_last();
+            self.current -= 1;
+        }
     }
 }
 
-pub const IDLE_PID: usize = 0;
+/// Counters of processes, as pids held: zombies count until they're reaped
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessStats {
+    pub live: usize,
+    /// most ever live at once
+    pub peak: usize,
+    /// since boot
+    pub created: usize,
+}
+
+pub fn process_stats() -> ProcessStats {
+    *PROCESS_STATS.exclusive_access()
+}
 
 pub struct PidHandle(pub usize);
 
 /// allocate pid
 pub fn pid_alloc() -> PidHandle {
+    let mut stats = PROCESS_STATS.exclusive_access();
+    stats.live += 1;
+    stats.peak = stats.peak.max(stats.live);
+    stats.created += 1;
     PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
 }
 
 impl Drop for PidHandle {
     fn drop(&mut self) {
         PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+        PROCESS_STATS.exclusive_access().live -= 1;
     }
 }
 
@@ -125,11 +149,11 @@ pub struct TaskUserRes {
     pub process: Weak<ProcessControlBlock>,
 }
 
-fn trap_cx_bottom_from_tid(tid: usize) -> usize {
+pub fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT - tid * PAGE_SIZE
 }
 
-fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
+pub fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
     ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
 }
 
@@ -170,6 +194,8 @@ impl TaskUserRes {
             trap_cx_top.into(),
             MapPermission::R | MapPermission::W,
         );
+        #[cfg(feature = "isolation_audit")]
+        inner.memory_set.audit_user();
     }
 
     fn dealloc_user_res(&self) {
@@ -187,6 +213,18 @@ impl TaskUserRes {
             .remove_area_with_start_vpn(trap_cx_bottom_va.into());
     }
 
+    /// Remove trap_cx (and ustack if `with_ustack`) of this thread from a copied
+    /// `memory_set` it does not live in.
+    pub fn unmap_from(&self, memory_set: &mut MemorySet, with_ustack: bool) {
+        if with_ustack {
+            let ustack_bottom_va: VirtAddr =
+                ustack_bottom_from_tid(self.ustack_base, self.tid).into();
+            memory_set.remove_area_with_start_vpn(ustack_bottom_va.into());
+        }
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
+        memory_set.remove_area_with_start_vpn(trap_cx_bottom_va.into());
+    }
+
     pub fn trap_cx_user_va(&self) -> usize {
         trap_cx_bottom_from_tid(self.tid)
     }
@@ -213,8 +251,8 @@ impl TaskUserRes {
 
 impl Drop for TaskUserRes {
     fn drop(&mut self) {
-        // we should not recycle tid too earlyeven thread exited, see sys_waittid comments
-        // self.dealloc_tid();
+        // tid outlives the res, it's recycled by the sys_waittid collecting the exit code,
+        // otherwise a new thread could reuse it and hand its exit code to the old one's waiter
         self.dealloc_user_res();
     }
 }
@@ -0,0 +1,176 @@
+//! Init lives in the kernel: pid 0 is a process without threads that adopts orphans and reaps
+//! them, and keeps a user program running on every terminal, like getty would. The program is
+//! chosen by the kernel boot args:
+//!
+//! - `init=<program>`: what to run, `user_shell` by default
+//! - `respawn=0`: let it exit instead of starting it over, shut down when all have exited
+//! - `ro`: mount the root filesystem read-only, nothing may write to its files
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    fs::{self, TTYS},
+    sbi::shutdown,
+    sync::UPIntrFreeCell,
+};
+
+use super::{load_elf, sync_all, ProcessControlBlock, INITPROC};
+
+/// run once when the configured program is missing, e.g. usertests packed as `initproc`
+const FALLBACK_PROGRAM: &str = "initproc";
+
+pub struct InitConfig {
+    pub program: String,
+    pub respawn: bool,
+    pub read_only: bool,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            program: "user_shell".to_string(),
+            respawn: true,
+            read_only: false,
+        }
+    }
+}
+
+impl InitConfig {
+    pub fn parse(bootargs: &str) -> Self {
+        let mut config = Self::default();
+        for arg in bootargs.split_whitespace() {
+            match arg.split_once('=') {
+                Some(("init", program)) => config.program = program.to_string(),
+                Some(("respawn", v)) => config.respawn = v != "0",
+                None if arg == "ro" => config.read_only = true,
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+struct InitState {
+    config: InitConfig,
+    /// pid of the program running on each terminal
+    pids: Vec<Option<usize>>,
+}
+
+lazy_static! {
+    static ref INIT_STATE: UPIntrFreeCell<InitState> = unsafe {
+        UPIntrFreeCell::new(InitState {
+            config: InitConfig::default(),
+            pids: Vec::new(),
+        })
+    };
+}
+
+pub fn start(bootargs: Option<String>) {
+    let mut config = bootargs
+        .map(|args| InitConfig::parse(&args))
+        .unwrap_or_default();
+    fs::set_read_only(config.read_only);
+    let mut terminals = TTYS.len();
+    if fs::open_file(&config.program, fs::OpenFlags::RDONLY).is_none() {
+        println!(
+            "KERN: no {}, falling back to {}",
+            config.program, FALLBACK_PROGRAM
+        );
+        config = InitConfig {
+            program: FALLBACK_PROGRAM.to_string(),
+            respawn: false,
+            read_only: config.read_only,
+        };
+        // one shot, on the kernel console only
+        terminals = 1;
+    }
+    println!(
+        "KERN: init runs {} on {} terminal(s), respawn {}, read-only {}",
+        config.program, terminals, config.respawn, config.read_only
+    );
+    let mut state = INIT_STATE.exclusive_access();
+    state.config = config;
+    state.pids = vec![None; terminals];
+    drop(state);
+    for index in 0..terminals {
+        spawn(index);
+    }
+}
+
+/// Start the program on terminal `index`, in a session of its own.
+fn spawn(index: usize) {
+    let mut state = INIT_STATE.exclusive_access();
+    let inode = fs::open_file(&state.config.program, fs::OpenFlags::RDONLY)
+        .expect("nothing to run as init");
+    let program = &state.config.program;
+    let name = program.rsplit('/').next().unwrap();
+    let exe = inode.clone_inner_inode();
+    let image = load_elf(&exe).expect("init program can't be run");
+    let process = ProcessControlBlock::new(name, exe, image, &TTYS[index]);
+    process.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+    INITPROC
+        .inner_exclusive_access()
+        .children
+        .push(process.clone());
+    state.pids[index] = Some(process.getpid());
+}
+
+/// Process `pid` is exiting, start the program over if init runs it on some terminal.
+pub fn on_exit(pid: usize, exit_code: i32) {
+    let mut state = INIT_STATE.exclusive_access();
+    let index = match state.pids.iter().position(|&p| p == Some(pid)) {
+        Some(index) => index,
+        None => return,
+    };
+    state.pids[index] = None;
+    if !state.config.respawn {
+        println!(
+            "KERN: {} on tty{} exited with exit_code {}",
+            state.config.program, index, exit_code
+        );
+        if state.pids.iter().all(Option::is_none) {
+            println!("KERN: nothing left to run, shutting down");
+            let (blocks, pages) = sync_all();
+            println!("KERN: synced {} blocks, {} mapped pages", blocks, pages);
+            shutdown(exit_code != 0);
+        }
+        return;
+    }
+    println!(
+        "KERN: {} on tty{} exited with exit_code {}, respawning",
+        state.config.program, index, exit_code
+    );
+    drop(state);
+    spawn(index);
+}
+
+/// Reap zombie children of init. Must run off their kernel stacks, i.e. from the idle loop.
+pub fn reap_orphans() {
+    let mut inner = INITPROC.inner_exclusive_access();
+    if !inner
+        .children
+        .iter()
+        .any(|p| p.inner_exclusive_access().is_zombie())
+    {
+        return;
+    }
+    let (zombies, alive): (Vec<_>, Vec<_>) = inner
+        .children
+        .drain(..)
+        .partition(|p| p.inner_exclusive_access().is_zombie());
+    inner.children = alive;
+    drop(inner);
+    for zombie in zombies {
+        log::info!(
+            "init reaped pid {} with exit_code {}",
+            zombie.getpid(),
+            zombie.inner_exclusive_access().exit_code
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use alloc::{
     collections::{btree_map::BTreeMap, vec_deque::VecDeque},
     sync::Arc,
+    vec::Vec,
 };
 use lazy_static::lazy_static;
 
-use crate::sync::UPIntrFreeCell;
+use crate::sync::{LockLevel, UPIntrFreeCell};
 
 use super::{
     process::ProcessControlBlock,
@@ -13,7 +14,7 @@ use super::{
 
 lazy_static! {
     pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
-        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
+        unsafe { UPIntrFreeCell::with_level(TaskManager::new(), LockLevel::TaskManager) };
     pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
         unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
 }
@@ -34,20 +35,20 @@ impl TaskManager {
         self.ready_queue.push_back(task);
     }
 
+    /// Tasks whose user res is gone were terminated while queued or blocked somewhere
+    /// (process exit, exec), drop them here instead of running them.
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        while let Some(task) = self.ready_queue.pop_front() {
+            if task.inner_exclusive_access().res.is_some() {
+                return Some(task);
+            }
+        }
+        None
     }
 
-    // pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-    //     if let Some((id, _)) = self
-    //         .ready_queue
-    //         .iter()
-    //         .enumerate()
-    //         .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
-    //     {
-    //         self.ready_queue.remove(id);
-    //     }
-    // }
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, task));
+    }
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -58,6 +59,10 @@ pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     TASK_MANAGER.exclusive_access().fetch()
 }
 
+pub fn remove_task(task: &Arc<TaskControlBlock>) {
+    TASK_MANAGER.exclusive_access().remove(task);
+}
+
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     let mut inner = task.inner_exclusive_access();
     inner.task_status = TaskStatus::Ready;
@@ -65,6 +70,17 @@ pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     add_task(task);
 }
 
+/// Make all of `tasks` ready, taking the ready queue once.
+pub fn wakeup_tasks(tasks: Vec<Arc<TaskControlBlock>>) {
+    for task in tasks.iter() {
+        task.inner_exclusive_access().task_status = TaskStatus::Ready;
+    }
+    let mut manager = TASK_MANAGER.exclusive_access();
+    for task in tasks {
+        manager.add(task);
+    }
+}
+
 // pub fn remove_task(task: Arc<TaskControlBlock>) {
 //     TASK_MANAGER.exclusive_access().remove(task);
 // }
@@ -77,6 +93,21 @@ pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     PID2PCB.exclusive_access().get(&pid).map(Arc::clone)
 }
 
+/// live processes in group `pgid`
+pub fn pgid2processes(pgid: usize) -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB
+        .exclusive_access()
+        .values()
+        .filter(|p| p.inner_exclusive_access().pgid == pgid)
+        .cloned()
+        .collect()
+}
+
+/// all live processes
+pub fn processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.exclusive_access().values().cloned().collect()
+}
+
 pub fn remove_from_pid2process(pid: usize) {
     if PID2PCB.exclusive_access().remove(&pid).is_none() {
         panic!("cannot find pid {} in pid2task!", pid);
@@ -1,45 +1,230 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::Level;
+use riscv::register::scause::Exception;
 
-use crate::{config::PAGE_SIZE, mm::VirtAddr};
+use crate::{
+    config::PAGE_SIZE,
+    mm::{make_room, MapPermission, SwapSlot, VirtAddr, VirtPageNum},
+};
 
-use super::{processor, MMapReserve};
+use super::{
+    manager::PID2PCB, process::ProcessControlBlockInner, processor, MMapReserve, SignalFlags,
+};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum MMapType {
     Memory,
     File,
+    /// heap up to the program break, moved by brk rather than unmapped
+    Heap,
+    /// a shared memory segment, mapped whole by shm_map and unmapped by shm_unmap
+    Shm,
+}
+
+/// What the faulting access tried to do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultAccess {
+    Read,
+    Write,
+    Exec,
+}
+
+impl From<Exception> for FaultAccess {
+    fn from(cause: Exception) -> Self {
+        match cause {
+            Exception::LoadFault | Exception::LoadPageFault => Self::Read,
+            Exception::StoreFault | Exception::StorePageFault => Self::Write,
+            _ => Self::Exec,
+        }
+    }
+}
+
+impl FaultAccess {
+    /// permission the access needs
+    fn permission(self) -> MapPermission {
+        match self {
+            Self::Read => MapPermission::R,
+            Self::Write => MapPermission::W,
+            Self::Exec => MapPermission::X,
+        }
+    }
+}
+
+/// Page faults and TLB flushes of a process
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FaultStats {
+    /// resolved without i/o: lazy anonymous pages, copy on write, file pages already in memory,
+    /// pages swapped out but not written yet
+    pub minor: usize,
+    /// file pages read from disk, and pages read back from swap
+    pub major: usize,
+    /// changes to live mappings that need stale translations flushed
+    pub tlb_flushes: usize,
+}
+
+impl FaultStats {
+    pub fn add(&mut self, other: &FaultStats) {
+        self.minor += other.minor;
+        self.major += other.major;
+        self.tlb_flushes += other.tlb_flushes;
+    }
 }
 
 /// Try to handle page fault caused by demand paging
-/// Returns whether this page fault is fixed
-pub fn handle_page_fault(fault_addr: usize) -> bool {
-    let fault_va: VirtAddr = fault_addr.into();
-    let fault_vpn = fault_va.floor();
+/// Returns the signal to raise if this page fault can't be fixed
+pub fn handle_page_fault(fault_addr: usize, access: FaultAccess) -> Result<(), SignalFlags> {
     let process = processor::current_process();
+    let fault_vpn = VirtAddr::from(fault_addr).floor();
+    // room first with nothing borrowed, pages of this process may go too
+    let frames = frames_to_page_in(&process.inner_exclusive_access(), fault_vpn);
+    make_room(frames);
     let mut inner = process.inner_exclusive_access();
+    page_in(&mut inner, fault_addr.into(), access)
+}
+
+/// Frames paging in `vpn` may take at most: the whole reserve of anonymous memory is mapped
+/// on first touch, and page tables on top
+fn frames_to_page_in(inner: &ProcessControlBlockInner, vpn: VirtPageNum) -> usize {
+    let pages = match inner.mmap_reserve_of(vpn) {
+        Some(MMapReserve {
+            range,
+            ty: MMapType::Memory,
+            ..
+        }) if inner.memory_set.area_of(vpn).is_none() => range.get_end().0 - range.get_start().0,
+        _ => 1,
+    };
+    // a leaf table per 512 pages, and the ones above
+    pages + pages / 512 + 3
+}
+
+/// pid of the process a page was last swapped out of, the next comes from those after it
+static SWAP_CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// Take a page out of some process to swap, going round them by pid. Those borrowed or with a
+/// thread in a syscall are passed over, the kernel may be using their pages. The slot to write
+/// it to, `None` if no page can go.
+pub fn swap_out_page() -> Option<Arc<SwapSlot>> {
+    let mut processes: Vec<_> = PID2PCB
+        .try_exclusive_access()?
+        .iter()
+        .map(|(pid, process)| (*pid, process.clone()))
+        .collect();
+    let cursor = SWAP_CURSOR.load(Ordering::Relaxed);
+    let after = processes.partition_point(|(pid, _)| *pid <= cursor);
+    processes.rotate_left(after);
+    for (pid, process) in processes {
+        let mut inner = match process.inner_try_exclusive_access() {
+            Some(inner) => inner,
+            None => continue,
+        };
+        let busy = inner
+            .tasks
+            .iter()
+            .flatten()
+            .any(|task| task.in_syscall.load(Ordering::Relaxed));
+        if busy {
+            continue;
+        }
+        if let Some(slot) = inner.memory_set.swap_out() {
+            // stale translations are flushed on the way back to user
+            inner.fault_stats.tlb_flushes += 1;
+            SWAP_CURSOR.store(pid, Ordering::Relaxed);
+            return Some(slot);
+        }
+    }
+    None
+}
+
+/// Page in `vpn` of the current process for the kernel to access on its behalf, provided
+/// it's the space of `token`. False if it can't be, or the process is borrowed by whoever
+/// asks. No room is made, who asks may hold anything: frames come from those kept free.
+pub fn fault_in(token: usize, vpn: VirtPageNum, write: bool) -> bool {
+    let process = match processor::try_current_task().and_then(|task| task.process.upgrade()) {
+        Some(process) => process,
+        None => return false,
+    };
+    let mut inner = match process.inner_try_exclusive_access() {
+        Some(inner) if inner.get_user_token() == token => inner,
+        _ => return false,
+    };
+    let access = if write {
+        FaultAccess::Write
+    } else {
+        FaultAccess::Read
+    };
+    page_in(&mut inner, vpn.into(), access).is_ok()
+}
+
+fn page_in(
+    inner: &mut ProcessControlBlockInner,
+    fault_va: VirtAddr,
+    access: FaultAccess,
+) -> Result<(), SignalFlags> {
+    let fault_vpn = fault_va.floor();
+
+    // whether the access is allowed is up to the fault it takes once the page is back
+    if let Some(read) = inner.memory_set.swap_in(fault_vpn) {
+        if read {
+            inner.fault_stats.major += 1;
+        } else {
+            inner.fault_stats.minor += 1;
+        }
+        return Ok(());
+    }
+
+    let MMapReserve {
+        range, perm, ty, ..
+    } = match inner.mmap_reserve_of(fault_vpn) {
+        Some(v) => v.clone(),
+        _ => return Err(SignalFlags::SIGSEGV),
+    };
+    // mapping it anyway would only fault again, and for a store into a read-only page
+    // the frame would be allocated for nothing
+    if !perm.contains(access.permission()) {
+        return Err(SignalFlags::SIGSEGV);
+    }
 
     match inner.memory_set.translate(fault_vpn) {
         // already mapped
         // 如果上一次page_fault被处理了, 这里pte就是valid, 所以返回false
         // 换句话说这次page_fault不是缺页, 而是其他异常, 比如读写权限问题
-        Some(pte) if pte.is_valid() => return false,
+        // except for a store to a private file page shared since fork, which is copied now
+        Some(pte) if pte.is_valid() => {
+            let mapping = match ty {
+                MMapType::File if access == FaultAccess::Write => inner
+                    .file_mappings
+                    .iter_mut()
+                    .find(|v| v.is_private() && v.contains_va(&fault_va)),
+                _ => None,
+            };
+            let ppn = mapping
+                .and_then(|v| v.copy_on_write(fault_va))
+                .ok_or(SignalFlags::SIGSEGV)?;
+            inner.memory_set.remap(fault_vpn, ppn, perm);
+            inner.fault_stats.minor += 1;
+            inner.fault_stats.tlb_flushes += 1;
+            return Ok(());
+        }
         _ => {}
     }
 
-    let MMapReserve { range, perm, ty } = match inner
-        .mmap_mapped
-        .iter()
-        .find(|v| v.range.contains(fault_vpn))
-    {
-        Some(v) => v.clone(),
-        _ => return false,
-    };
-
     match ty {
         MMapType::Memory => {
-            let start_va = range.get_start().into();
-            let end_va = range.get_end().into();
+            // a page madvise released, the rest of the area is still there
+            if !inner.memory_set.map_released(fault_vpn) {
+                let start_va = range.get_start().into();
+                let end_va = range.get_end().into();
+                inner.memory_set.insert_framed_area(start_va, end_va, perm);
+            }
+            inner.fault_stats.minor += 1;
+        }
+        // a page at a time, so the break can move back over part of it
+        MMapType::Heap => {
+            let start_va: VirtAddr = fault_vpn.into();
+            let end_va = VirtAddr::from(start_va.0 + PAGE_SIZE);
             inner.memory_set.insert_framed_area(start_va, end_va, perm);
+            inner.fault_stats.minor += 1;
         }
         MMapType::File => {
             // check file_mappings
@@ -49,9 +234,15 @@ pub fn handle_page_fault(fault_addr: usize) -> bool {
                 .find(|v| v.contains_va(&fault_va))
             {
                 Some(v) => v,
-                _ => return false,
+                _ => return Err(SignalFlags::SIGSEGV),
             };
             let file = Arc::clone(mapping.file());
+            // the mapping may reach past the end of file, or the file shrank since: the part of
+            // the last page past the end reads zeros, pages wholly beyond are a bus error
+            let file_size = file.get_size();
+            if mapping.file_offset(fault_va).unwrap() >= file_size {
+                return Err(SignalFlags::SIGBUS);
+            }
 
             // phys frame allocated
             let (ppn, range, is_shared) = mapping.map(fault_va).unwrap();
@@ -59,17 +250,57 @@ pub fn handle_page_fault(fault_addr: usize) -> bool {
             inner.memory_set.map(fault_vpn, ppn, perm);
 
             // load file
-            if !is_shared {
-                let file_size = file.get_size();
+            if is_shared {
+                inner.fault_stats.minor += 1;
+            } else {
                 let file_offset = range.file_offset(fault_vpn);
-                assert!(file_offset < file_size);
-
                 let read_len = PAGE_SIZE.min(file_size - file_offset);
                 let buf = &mut ppn.get_bytes_array()[..read_len];
                 file.read_at(file_offset, buf);
+                inner.fault_stats.major += 1;
             }
         }
+        // mapped whole from the start, the access isn't allowed
+        MMapType::Shm => return Err(SignalFlags::SIGSEGV),
     }
 
-    true
+    Ok(())
+}
+
+/// Log what's known about a user page fault `handle_page_fault` gave up on: who, where and
+/// what is mapped there. Only with `LOG` at warn or below.
+pub fn report_page_fault(cause: Exception, fault_addr: usize, sepc: usize) {
+    if !log::log_enabled!(Level::Warn) {
+        return;
+    }
+    let access = FaultAccess::from(cause);
+    let task = processor::current_task().unwrap();
+    let tid = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .map_or(usize::MAX, |res| res.tid);
+    let process = processor::current_process();
+    let inner = process.inner_exclusive_access();
+    let fault_vpn = VirtAddr::from(fault_addr).floor();
+    log::warn!(
+        "[kernel] pid {} tid {}: {:?} of {:#x} at sepc {:#x}, {:?}",
+        process.getpid(),
+        tid,
+        access,
+        fault_addr,
+        sepc,
+        cause
+    );
+    match inner.memory_set.translate(fault_vpn) {
+        Some(pte) if pte.is_valid() => log::warn!("  pte: {:?}", pte.flags()),
+        _ => log::warn!("  pte: not mapped"),
+    }
+    match inner.memory_set.area_of(fault_vpn) {
+        Some(area) => log::warn!("  area: {:?}", area),
+        None => log::warn!("  area: none"),
+    }
+    if let Some(reserve) = inner.mmap_reserve_of(fault_vpn) {
+        log::warn!("  mmap: {:?}", reserve);
+    }
 }
@@ -1,39 +1,41 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 pub use context::TaskContext;
+use core::sync::atomic::Ordering;
 use id::TaskUserRes;
 use lazy_static::lazy_static;
 use manager::remove_from_pid2process;
 
-use crate::fs;
-
 mod action;
 mod context;
 mod id;
+mod init;
 mod manager;
 mod mem;
 mod process;
 mod processor;
+mod rlimit;
 mod signal;
 mod switch;
 mod task;
 
 pub use action::*;
-pub use manager::{add_task, pid2process, wakeup_task};
+pub use id::{process_stats, ProcessStats};
+pub use manager::{add_task, pgid2processes, pid2process, processes, wakeup_task, wakeup_tasks};
 pub use mem::*;
-pub use process::{FileMapping, MMapReserve, MapRange, ProcessControlBlock};
+pub use process::{
+    load_elf, FileMapping, MMapReserve, MapRange, ProcessControlBlock, ProgramImage,
+};
 pub use processor::{
     current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
-    run_tasks, schedule, user_time_end, user_time_start,
+    run_tasks, schedule, try_current_task, user_time_end, user_time_start,
 };
-pub use signal::{SignalFlags, MAX_SIG};
-pub use task::{TaskControlBlock, TaskStatus};
+pub use rlimit::*;
+pub use signal::{DefaultAction, SignalFlags, MAX_SIG};
+pub use task::{SignalFrame, TaskControlBlock, TaskStatus, MAX_SIGNAL_NESTING, THREAD_NAME_MAX};
 
 lazy_static! {
-    pub static ref INITPROC: Arc<ProcessControlBlock> = {
-        let inode = fs::open_file("initproc", fs::OpenFlags::RDONLY).unwrap();
-        let elf = inode.read_all();
-        ProcessControlBlock::new(&elf)
-    };
+    /// pid 0, the reaper, see `init`
+    pub static ref INITPROC: Arc<ProcessControlBlock> = ProcessControlBlock::new_bare();
 }
 
 pub fn suspend_current_and_run_next() {
@@ -42,10 +44,6 @@ pub fn suspend_current_and_run_next() {
     // 当仅有一个任务的时候, suspend_current_and_run_next 的效果是会继续执行这个任务
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
-    // {
-    //     let process = task.process.upgrade().unwrap();
-    //     process.inner_exclusive_access().kernel_time += processor::refresh_stop_watch();
-    // }
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
 
@@ -57,12 +55,6 @@ pub fn suspend_current_and_run_next() {
 pub fn block_current_task() -> *mut TaskContext {
     let task = processor::take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
-    // {
-    //     let process = task.process.upgrade().unwrap();
-    //     println!("@block_current_task process.inner_exclusive_access");
-    //     process.inner_exclusive_access().kernel_time += processor::refresh_stop_watch();
-    //     println!("@block_current_task done process.inner_exclusive_access");
-    // }
     task_inner.task_status = TaskStatus::Blocked;
     &mut task_inner.task_cx as *mut TaskContext
 }
@@ -81,31 +73,34 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     let tid = task_inner.res.as_ref().unwrap().tid;
     // set exit_code
     task_inner.exit_code = Some(exit_code);
-    // dealloc user res
-    task_inner.res = None;
+    // the syscall it exits in never returns
+    task.in_syscall.store(false, Ordering::Relaxed);
+    let res = task_inner.res.take();
+    let joiners: Vec<_> = task_inner.join_queue.drain(..).collect();
     drop(task_inner);
+    // dealloc user res, it borrows the process so not while the thread is borrowed
+    drop(res);
     drop(task);
+    for joiner in joiners {
+        wakeup_task(joiner);
+    }
     // terminate process if it's main thread
     if tid == 0 {
         let pid = process.getpid();
-        if pid == id::IDLE_PID {
-            println!(
-                "[kernel] Idle process exit with exit_code {} ...",
-                exit_code
-            );
-            if exit_code != 0 {
-                crate::sbi::shutdown(true)
-            } else {
-                crate::sbi::shutdown(false)
-            }
-        }
         // must remove from pid2task, else sys_wait will see this task ref_count not 1
         remove_from_pid2process(pid);
         let mut process_inner = process.inner_exclusive_access();
-        // curr_task完成
-        // process_inner.kernel_time += processor::refresh_stop_watch();
         process_inner.is_zombie = true;
         process_inner.exit_code = exit_code;
+        #[cfg(feature = "fault_stats")]
+        {
+            let stats = process_inner.fault_stats;
+            println!(
+                "KERN: pid {} exited, minor faults {}, major faults {}, tlb flushes {}",
+                pid, stats.minor, stats.major, stats.tlb_flushes
+            );
+        }
+        let parent = process_inner.parent.as_ref().and_then(|p| p.upgrade());
         // access initproc TCB exclusively
         {
             let mut initproc_inner = INITPROC.inner_exclusive_access();
@@ -118,7 +113,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         let mut recycle_res = Vec::<TaskUserRes>::new();
         for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
             let task = task.as_ref().unwrap();
-            // remove_inactive_task(task.clone());
+            // sleepers would be kept around until their timer expires
+            crate::timer::cancel_timer(task);
             let mut task_inner = task.inner_exclusive_access();
             if let Some(res) = task_inner.res.take() {
                 recycle_res.push(res);
@@ -132,19 +128,39 @@ pub fn exit_current_and_run_next(exit_code: i32) {
 
         let mut process_inner = process.inner_exclusive_access();
         process_inner.children.clear();
+        // drop fd's, and the record locks held through them
+        process_inner.fd_table.clear();
+        process_inner.fd_status.clear();
+        process_inner.fd_cloexec.clear();
+        crate::fs::release_record_locks(pid, None);
+        crate::fs::unwait_record_lock(pid);
+        process_inner.clear_mappings();
         // deallocate user space
         process_inner.memory_set.recycle_data_pages();
-        // drop fd's
-        process_inner.fd_table.clear();
-        // write back dirty pages
-        for mapping in &process_inner.file_mappings {
-            mapping.sync();
+        // threads blocked on sync primitives are referenced by their wait queues only,
+        // release them along with the primitives
+        for mutex in process_inner.mutex_list.drain(..).flatten() {
+            mutex.clear_waiters();
+        }
+        for sem in process_inner.semaphore_list.drain(..).flatten() {
+            sem.clear_waiters();
+        }
+        for cv in process_inner.condvar_list.drain(..).flatten() {
+            cv.clear_waiters();
+        }
+        for barrier in process_inner.barrier_list.drain(..).flatten() {
+            barrier.clear_waiters();
         }
         // recycle all threads except main thread, coz it's currently executing!
         // see: https://github.com/rcore-os/rCore-Tutorial-Book-v3/issues/136#issuecomment-1955838457
         while process_inner.tasks.len() > 1 {
             process_inner.tasks.pop();
         }
+        drop(process_inner);
+        if let Some(parent) = parent {
+            raise_signal(&parent, SignalFlags::SIGCHLD);
+        }
+        init::on_exit(pid, exit_code);
     }
     drop(process);
     // we do not have to save task context
@@ -158,14 +174,91 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     processor::schedule(&mut _unused as *mut TaskContext);
 }
 
-pub fn add_initproc() {
+/// Start init with kernel boot args
+pub fn add_initproc(bootargs: Option<String>) {
     let _init = INITPROC.clone();
+    init::start(bootargs);
+}
+
+/// Write back dirty pages of the file mappings of all processes, then all the filesystem has
+/// cached, and make it durable. Returns (blocks, pages) written.
+pub fn sync_all() -> (usize, usize) {
+    let pages = processes()
+        .iter()
+        .map(|process| {
+            let inner = process.inner_exclusive_access();
+            inner
+                .file_mappings
+                .iter()
+                .map(FileMapping::sync)
+                .sum::<usize>()
+        })
+        .sum();
+    // pages go through the block cache, so they're written before it's synced
+    let blocks = crate::fs::sync_all();
+    (blocks, pages)
+}
+
+/// Raise `signal` on `process`, false if it's pending already. Tasks that would act on it are
+/// woken from sleep or sigsuspend, so it's seen now.
+pub fn raise_signal(process: &Arc<ProcessControlBlock>, signal: SignalFlags) -> bool {
+    let mut inner = process.inner_exclusive_access();
+    if inner.signals.contains(signal) {
+        return false;
+    }
+    inner.signals.insert(signal);
+    let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+    drop(inner);
+    for task in tasks {
+        let suspended = {
+            let mut task_inner = task.inner_exclusive_access();
+            let signal_processor = &mut task_inner.signal_processor;
+            if !signal_processor.is_deliverable(signal) {
+                continue;
+            }
+            core::mem::take(&mut signal_processor.suspended)
+        };
+        // a sleeper is ours unless the timer got there first
+        if suspended || crate::timer::cancel_timer(&task) {
+            wakeup_task(task);
+        }
+    }
+    true
 }
 
+/// Any signal pending the current task would act on.
+pub fn current_has_deliverable_signal() -> bool {
+    let task = current_task().unwrap();
+    let signals = task
+        .process
+        .upgrade()
+        .unwrap()
+        .inner_exclusive_access()
+        .signals;
+    let inner = task.inner_exclusive_access();
+    (0..=MAX_SIG)
+        .map(|signum| SignalFlags::from_bits_truncate(1 << signum))
+        .any(|signal| signals.contains(signal) && inner.signal_processor.is_deliverable(signal))
+}
+
+/// Raise `signal` on a fault of the current task. Blocking or ignoring it would only fault
+/// again, so it is unblocked and an ignoring disposition reset to default, as Linux does.
 pub fn current_add_signal(signal: SignalFlags) {
     let process = current_process();
-    let mut inner = process.inner_exclusive_access();
-    inner.signals |= signal;
+    process.inner_exclusive_access().signals |= signal;
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let signal_processor = &mut task_inner.signal_processor;
+    signal_processor.signal_mask.remove(signal);
+    for frame in signal_processor.frames.iter_mut() {
+        frame.blocked.remove(signal);
+    }
+    let signum = signal.bits().trailing_zeros() as usize;
+    if signal_processor.handler_for_action(signum) == SIG_IGN {
+        signal_processor
+            .signal_actions
+            .set_action(signum, SignalAction::default());
+    }
 }
 
 pub fn current_handle_signals() {
@@ -174,12 +267,15 @@ pub fn current_handle_signals() {
         let (frozen, killed) = {
             let task = current_task().unwrap();
             let inner = task.inner_exclusive_access();
-            (inner.signal_processor.frozen, inner.signal_processor.killed)
+            (
+                inner.signal_processor.frozen,
+                inner.signal_processor.killed.is_some(),
+            )
         };
         // abort signal handling when:
         // 1. current process got SIGCONT (OR it's never been SIGSTOP-ed), which means this
         // task done handling pending signals and can move on
-        // 2. current process got SIGKILL and this task be killed
+        // 2. current process got a terminating signal and this task be killed
         if !frozen || killed {
             break;
         }
@@ -188,6 +284,12 @@ pub fn current_handle_signals() {
         // again (via `suspend_current_and_run_next`)
         suspend_current_and_run_next();
     }
+    // sigsuspend is over, whatever woke it has been dealt with
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    if let Some(mask) = inner.signal_processor.saved_mask.take() {
+        inner.signal_processor.signal_mask = mask;
+    }
 }
 
 fn check_pending_signals() {
@@ -204,56 +306,66 @@ fn check_pending_signals() {
         if !signals.contains(signal) {
             continue;
         }
+        // SIGKILL and SIGSTOP can be neither blocked nor caught
+        let catchable = !matches!(signal, SignalFlags::SIGKILL | SignalFlags::SIGSTOP);
         let inner = task.inner_exclusive_access();
         // masked by task
-        if inner.signal_processor.is_global_masked(signal) {
+        if catchable && inner.signal_processor.is_global_masked(signal) {
             continue;
         }
         // masked by handling signal action
-        if inner.signal_processor.is_handling_masked(signal) {
+        if catchable && inner.signal_processor.is_handling_masked(signal) {
             continue;
         }
+        let handler = if catchable {
+            inner.signal_processor.handler_for_action(signum)
+        } else {
+            SIG_DFL
+        };
         drop(inner);
         drop(task);
-        if matches!(
-            signal,
-            SignalFlags::SIGKILL
-                | SignalFlags::SIGSTOP
-                | SignalFlags::SIGCONT
-                | SignalFlags::SIGDEF
-        ) {
-            // kernel signal
-            call_kernel_signal_handler(signal);
-        } else {
-            // user signal
-            call_user_signal_handler(signum, signal);
-            return;
+        // a stopped process continues whatever the disposition
+        if signal == SignalFlags::SIGCONT {
+            set_frozen(false);
+        }
+        match handler {
+            SIG_IGN => {
+                current_process().inner_exclusive_access().signals ^= signal;
+            }
+            SIG_DFL => call_kernel_signal_handler(signum, signal),
+            _ => {
+                call_user_signal_handler(signum, signal);
+                return;
+            }
         }
     }
 }
 
-fn call_kernel_signal_handler(signal: SignalFlags) {
+fn set_frozen(frozen: bool) {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    process_inner.tasks_for_each(|task| {
+        task.inner_exclusive_access().signal_processor.frozen = frozen;
+    });
+}
+
+/// default action of `signal`
+fn call_kernel_signal_handler(signum: usize, signal: SignalFlags) {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
-    match signal {
-        SignalFlags::SIGSTOP => {
-            process_inner.tasks_for_each(|task| {
-                let mut task_inner = task.inner_exclusive_access();
-                task_inner.signal_processor.frozen = true;
-            });
-            process_inner.signals ^= SignalFlags::SIGSTOP;
+    match signal.default_action() {
+        DefaultAction::Stop => {
+            drop(process_inner);
+            set_frozen(true);
+            process.inner_exclusive_access().signals ^= signal;
         }
-        SignalFlags::SIGCONT => {
-            process_inner.tasks_for_each(|task| {
-                let mut task_inner = task.inner_exclusive_access();
-                task_inner.signal_processor.frozen = false;
-            });
-            process_inner.signals ^= SignalFlags::SIGCONT;
+        DefaultAction::Cont | DefaultAction::Ignore => {
+            process_inner.signals ^= signal;
         }
-        _ => {
+        DefaultAction::Term | DefaultAction::Core => {
             process_inner.tasks_for_each(|task| {
                 let mut task_inner = task.inner_exclusive_access();
-                task_inner.signal_processor.killed = true;
+                task_inner.signal_processor.killed = Some(signum);
             });
         }
     }
@@ -261,31 +373,40 @@ fn call_kernel_signal_handler(signal: SignalFlags) {
 
 fn call_user_signal_handler(signum: usize, signal: SignalFlags) {
     let task = current_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
-    let handler = task_inner.signal_processor.handler_for_action(signum);
-    if handler == 0 {
+    // nested too deep, stays pending until some handler returns
+    if task.inner_exclusive_access().signal_processor.frames.len() == MAX_SIGNAL_NESTING {
         return;
     }
-    // handle flag
+    // handle flag, the process goes before the thread in the lock order
     let process = task.process.upgrade().unwrap();
     process.inner_exclusive_access().signals ^= signal;
-    task_inner.signal_processor.signal_handling = Some(signum);
-    // backup trapframe
+    let mut task_inner = task.inner_exclusive_access();
+    let handler = task_inner.signal_processor.handler_for_action(signum);
+    let action = task_inner
+        .signal_processor
+        .signal_actions
+        .get_action(signum);
+    let mut blocked = action.mask;
+    if !action.flags.contains(SignalActionFlags::SA_NODEFER) {
+        blocked |= signal;
+    }
+    // backup trapframe, a handler interrupted has its own one already
     let trap_cx = task_inner.get_trap_cx();
-    task_inner.signal_processor.trap_cx_backup = Some(*trap_cx);
+    let frame = SignalFrame {
+        trap_cx: *trap_cx,
+        blocked,
+    };
+    task_inner.signal_processor.frames.push(frame);
     // modify trapframe
     trap_cx.sepc = handler;
     // put args (a0)
     trap_cx.x[10] = signum;
 }
 
+/// Exit code and message if a signal killed the current task.
 pub fn check_signals_error_of_current() -> Option<(i32, &'static str)> {
-    let process = current_process();
-    let inner = process.inner_exclusive_access();
-    inner.signals.check_error()
+    let task = current_task().unwrap();
+    let signum = task.inner_exclusive_access().signal_processor.killed?;
+    let signal = SignalFlags::from_bits_truncate(1 << signum);
+    Some((-(signum as i32), signal.death_message()))
 }
-
-// pub fn remove_inactive_task(task: Arc<TaskControlBlock>) {
-//     remove_task(task.clone());
-//     remove_timer(&task);
-// }
@@ -1,27 +1,30 @@
-use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::{btree_map::BTreeMap, BTreeSet};
 use alloc::string::String;
 use alloc::vec;
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use easy_fs::Inode;
+use easy_fs::{Inode, InodeReader};
 
 use crate::cast::DowncastArc;
-use crate::config::{MMAP_AREA_BASE, PAGE_SIZE};
-use crate::fs::{File, OSInode, Stdin, Stdout, ROOT_INODE};
+use crate::config::{BRK_AREA_BASE, MMAP_AREA_BASE, MMAP_AREA_END, PAGE_SIZE};
+use crate::fs::{
+    check_write, file_closed, Cred, File, FsPath, OSInode, OpenFlags, Stdin, Stdout, Tty, VfsNode,
+};
 use crate::mm::{
-    frame_alloc, translated_refmut, FrameTracker, MapPermission, MemorySet, PageTable, PhysPageNum,
-    VPNRange, VirtAddr, VirtPageNum, KERNEL_SPACE,
+    frame_alloc, make_room, translated_refmut, ElfSegment, FrameTracker, MapPermission, MemorySet,
+    PageTable, PhysPageNum, SharedMemory, VPNRange, VirtAddr, VirtPageNum, KERNEL_SPACE,
 };
-use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::sync::{Barrier, Condvar, LockLevel, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::syscall::errno::{EBADF, EMFILE, ENOEXEC};
 use crate::trap::{trap_handler, TrapContext};
 
 use super::id::RecycleAllocator;
-use super::id::{pid_alloc, PidHandle};
-use super::manager::insert_into_pid2process;
+use super::id::{pid_alloc, trap_cx_bottom_from_tid, ustack_bottom_from_tid, PidHandle};
+use super::manager::{insert_into_pid2process, remove_task};
 use super::task::TaskControlBlock;
-use super::{add_task, MMapType, SignalFlags};
+use super::{add_task, FaultStats, MMapType, RLimits, SignalFlags};
 
 /// PCB
 pub struct ProcessControlBlock {
@@ -38,52 +41,133 @@ pub struct ProcessControlBlockInner {
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub exit_code: i32,
     pub fd_table: Vec<Option<Arc<dyn File>>>,
+    /// status flags of fds, those without any aren't in
+    pub fd_status: BTreeMap<usize, OpenFlags>,
+    /// fds closed on exec
+    pub fd_cloexec: BTreeSet<usize>,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    pub barrier_list: Vec<Option<Arc<Barrier>>>,
     pub signals: SignalFlags,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
 
     // mmap
-    pub mmap_mapped: Vec<MMapReserve>,
+    /// program file the image was loaded from, its read-only segments are paged in from it
+    pub exe: Option<Arc<Inode>>,
+    /// keyed by start vpn of the reserved range
+    pub mmap_mapped: BTreeMap<VirtPageNum, MMapReserve>,
     pub mmap_va_allocator: VirtAddressAllocator,
     pub file_mappings: Vec<FileMapping>,
+    /// shared memory segments mapped whole, keyed by start vpn of the range reserved
+    pub shm_mappings: BTreeMap<VirtPageNum, Arc<SharedMemory>>,
+    /// program break, the heap from `BRK_AREA_BASE` up to it is reserved in `mmap_mapped`
+    pub brk: usize,
 
     // cwd
-    pub cwd: Arc<Inode>,
+    /// where relative paths go from, as walked to: getcwd gives it, a rename above moves it.
+    /// `None` once the dir is removed.
+    pub cwd: Option<FsPath>,
+    /// who it acts as on files
+    pub cred: Cred,
+    pub rlimits: RLimits,
+
+    // job control
+    /// controlling terminal, `None` for init
+    pub tty: Option<Arc<Tty>>,
+    pub pgid: usize,
 
-    // time stats
-    #[allow(unused)]
+    // time stats, in us
     pub user_time: usize,
-    #[allow(unused)]
     pub kernel_time: usize,
+    /// user/kernel time of reaped children, and of what they reaped in turn
+    pub cutime: usize,
+    pub cstime: usize,
+
+    // fault stats
+    pub fault_stats: FaultStats,
+    /// of reaped children, like `cutime`
+    pub cfault_stats: FaultStats,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct MMapReserve {
     pub range: VPNRange,
     pub perm: MapPermission,
     pub ty: MMapType,
+    /// a segment of the program image rather than what mmap gave, RLIMIT_AS leaves it out
+    pub image: bool,
 }
 
+/// First-fit allocator over the mmap area, freed ranges are reused.
 #[derive(Clone)]
 pub struct VirtAddressAllocator {
-    curr_va: VirtAddr,
+    /// free vpn ranges, start -> end (exclusive), never adjacent
+    free: BTreeMap<VirtPageNum, VirtPageNum>,
+    /// [base, end) of the area
+    area: VPNRange,
 }
 
 impl VirtAddressAllocator {
-    pub fn new(base: usize) -> Self {
-        Self {
-            curr_va: base.into(),
+    pub fn new(base: usize, end: usize) -> Self {
+        let area = VPNRange::new(VirtAddr::from(base).ceil(), VirtAddr::from(end).floor());
+        let mut free = BTreeMap::new();
+        free.insert(area.get_start(), area.get_end());
+        Self { free, area }
+    }
+
+    /// at least 1 page, so allocated `start` is always 4k aligned; `None` if area is full
+    pub fn alloc(&mut self, len: usize) -> Option<VirtAddr> {
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let pages = pages.max(1);
+        let (&l, &r) = self.free.iter().find(|(l, r)| r.0 - l.0 >= pages)?;
+        self.free.remove(&l);
+        if r.0 - l.0 > pages {
+            self.free.insert(VirtPageNum(l.0 + pages), r);
+        }
+        Some(l.into())
+    }
+
+    /// Give back `range`, the part outside of the area is ignored.
+    pub fn dealloc(&mut self, range: VPNRange) {
+        let mut l = range.get_start().max(self.area.get_start());
+        let mut r = range.get_end().min(self.area.get_end());
+        if l >= r {
+            return;
+        }
+        self.take(VPNRange::new(l, r));
+        // merge with neighbours
+        if let Some((&pl, &pr)) = self.free.range(..l).next_back() {
+            if pr == l {
+                self.free.remove(&pl);
+                l = pl;
+            }
         }
+        if let Some(nr) = self.free.remove(&r) {
+            r = nr;
+        }
+        self.free.insert(l, r);
     }
 
-    pub fn alloc(&mut self, len: usize) -> VirtAddr {
-        let start = self.curr_va;
-        let end: VirtAddr = (start.0 + len).into();
-        self.curr_va = end.ceil().into(); // at least 1 page, so allocated `start` is always 4k aligned
-        start
+    /// Mark `range` used, e.g. for `MAP_FIXED`.
+    pub fn take(&mut self, range: VPNRange) {
+        let (l, r) = (range.get_start(), range.get_end());
+        let overlapped: Vec<_> = self
+            .free
+            .range(..r)
+            .filter(|(_, &fr)| fr > l)
+            .map(|(&fl, &fr)| (fl, fr))
+            .collect();
+        for (fl, fr) in overlapped {
+            self.free.remove(&fl);
+            if fl < l {
+                self.free.insert(fl, l);
+            }
+            if r < fr {
+                self.free.insert(r, fr);
+            }
+        }
     }
 }
 
@@ -109,8 +193,9 @@ impl MapRange {
         }
     }
 
+    /// the last page is mapped whole even if `len` ends in the middle of it
     pub fn contains_va(&self, va: &VirtAddr) -> bool {
-        &self.start <= va && va < &self.end
+        self.start.floor() <= va.floor() && va.floor() < self.end.ceil()
     }
 
     pub fn contains_range(&self, rng: &VPNRange) -> bool {
@@ -133,33 +218,61 @@ pub struct FileMapping {
     /// 1. fd which is open can be closed at any time, but mapping holds;
     /// 2. mmap stdin/stdout is meaningless
     file: Arc<Inode>,
-    /// same file can be open multiple times, we merge those mappings
+    /// same file can be open multiple times, we merge those mappings (private ones aren't)
     pub ranges: Vec<MapRange>,
-    /// manages allocated phys frames
-    frames: Vec<FrameTracker>,
-    /// file_offset -> ppn
-    map: BTreeMap<usize, (VirtPageNum, PhysPageNum)>,
+    /// file_offset -> frame, shared with the forked processes until one of them writes to a
+    /// private mapping
+    map: BTreeMap<usize, (VirtPageNum, Arc<FrameTracker>)>,
+    /// `MAP_PRIVATE`: writes are never written back to file, and are copied on write after fork
+    private: bool,
+    /// some range was mapped shared and writable through a fd that passed `check_write`
+    writable: bool,
     /// only used for translate vpn, to find pte and check dirty bit
     // TODO: we need slim version, for example, `frames` field is not needed
     pt: PageTable,
 }
 
 impl FileMapping {
-    pub fn new_empty(file: Arc<Inode>, token: usize) -> Self {
+    pub fn new_empty(file: Arc<Inode>, token: usize, private: bool) -> Self {
         Self {
             file,
             ranges: Vec::new(),
-            frames: Vec::new(),
             map: BTreeMap::new(),
+            private,
+            writable: false,
             pt: PageTable::from_token(token),
         }
     }
 
+    /// Add `range` mapped by one mmap call, `writable` if checked for storing to the file
+    pub fn add_range(&mut self, range: MapRange, writable: bool) {
+        self.ranges.push(range);
+        self.writable |= writable;
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    /// If stores to a shared mapping may reach the file, as `check_write` of the fd it was
+    /// mapped through
+    pub fn check_write(&self) -> Result<(), isize> {
+        check_write(self.writable)
+    }
+
     /// if exist range contains `va`
     pub fn contains_va(&self, va: &VirtAddr) -> bool {
         self.ranges.iter().any(|range| range.contains_va(va))
     }
 
+    /// Offset in file of the page `va` is in
+    pub fn file_offset(&self, va: VirtAddr) -> Option<usize> {
+        self.ranges
+            .iter()
+            .find(|range| range.contains_va(&va))
+            .map(|range| range.file_offset(va.floor()))
+    }
+
     /// Create mapping for given virtual address
     pub fn map(&mut self, va: VirtAddr) -> Option<(PhysPageNum, MapRange, bool)> {
         let vpn = va.floor();
@@ -175,13 +288,13 @@ impl FileMapping {
                 // 3.1 ppn already, 该情况发生在一个进程多次调用mmap映射一个文件, 且file[offset..offset+len]部分有重叠
                 // 如:进程A调用mmap -> 访问某个映射的va -> page_fault触发map -> self.map分配实际物理frame
                 // 进程A再次调用mmap -> 访问va(这段虚地址和上面不重叠, 但是对应文件的同一个位置) -> page_fault触发map -> self.map发现已经分配过了
-                Some(&(_, ppn)) => (ppn, true),
+                Some((_, frame)) => (frame.ppn, true),
                 // 3.2 allocate new ppn
                 _ => {
                     let frame = frame_alloc().unwrap();
                     let ppn = frame.ppn;
-                    self.frames.push(frame); // frames managed by FileMapping, not MemorySet
-                    self.map.insert(offset, (vpn, ppn));
+                    // frames managed by FileMapping, not MemorySet
+                    self.map.insert(offset, (vpn, Arc::new(frame)));
                     (ppn, false)
                 }
             };
@@ -191,61 +304,124 @@ impl FileMapping {
         None
     }
 
-    /// Write back all dirty pages
-    pub fn sync(&self) {
+    /// Frame for a store to the mapped `va` of a private mapping, the one mapped now unless
+    /// it's still shared since fork, then a copy of it replaces it
+    pub fn copy_on_write(&mut self, va: VirtAddr) -> Option<PhysPageNum> {
+        let offset = self.file_offset(va)?;
+        let (_, frame) = self.map.get_mut(&offset)?;
+        if Arc::strong_count(frame) > 1 {
+            let copy = frame_alloc().unwrap();
+            copy.ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            *frame = Arc::new(copy);
+        }
+        Some(frame.ppn)
+    }
+
+    /// Write back all dirty pages, nothing for a private mapping, or one the file may not be
+    /// written through (any more, on a read-only mount). Returns how many pages were written.
+    pub fn sync(&self) -> usize {
+        if self.private || check_write(self.writable).is_err() {
+            return 0;
+        }
+        let file_size = self.file.get_size();
+        self.map
+            .iter()
+            .filter(|(offset, (vpn, frame))| {
+                self.pt.translate(*vpn).unwrap().is_dirty()
+                    && self.write_page(**offset, frame, file_size)
+            })
+            .count()
+    }
+
+    /// `sync` of the pages mapped in `vpn_range` alone, those written turn clean. With
+    /// `invalidate` the pages of it are dropped after, to be read from the file again on next
+    /// touch; their vpns are left for the caller to unmap. Returns how many pages were written.
+    pub fn sync_range(
+        &mut self,
+        vpn_range: VPNRange,
+        invalidate: bool,
+        dropped: &mut Vec<VirtPageNum>,
+    ) -> usize {
+        if self.private || check_write(self.writable).is_err() {
+            return 0;
+        }
         let file_size = self.file.get_size();
-        for (&offset, &(vpn, ppn)) in &self.map {
-            // find dirty page
-            let pte = self.pt.translate(vpn).unwrap();
-            if !pte.is_dirty() {
+        let mut pages = 0;
+        for (&offset, (vpn, frame)) in &self.map {
+            if !vpn_range.contains(*vpn) || !self.pt.translate(*vpn).unwrap().is_dirty() {
                 continue;
             }
-            if offset >= file_size {
-                continue;
+            if self.write_page(offset, frame, file_size) {
+                self.pt.clear_dirty(*vpn);
+                pages += 1;
             }
-            let va_len = self
-                .ranges
-                .iter()
-                .map(|r| {
-                    if r.offset <= offset && offset < r.offset + r.len {
-                        PAGE_SIZE.min(r.offset + r.len - offset)
-                    } else {
-                        0
-                    }
-                })
-                .max()
-                .unwrap();
-            let write_len = va_len.min(file_size - offset);
-            self.file
-                .write_at(offset, &ppn.get_bytes_array()[..write_len]);
-        }
-    }
-
-    fn copy_to_user(&self, memory_set: &mut MemorySet) -> Self {
-        let mut map = BTreeMap::new();
-        let mut frames = Vec::new();
-
-        for (&offset, &(vpn, orig_ppn)) in &self.map {
-            let frame = frame_alloc().unwrap();
-            let ppn = frame.ppn;
-            frames.push(frame);
-            // map vpn
-            let orig_pte = self.pt.translate(vpn).unwrap();
-            let map_perm = MapPermission::from_bits_truncate(orig_pte.flags().bits());
-            memory_set.map(vpn, ppn, map_perm);
-            // copy data
-            ppn.get_bytes_array()
-                .copy_from_slice(orig_ppn.get_bytes_array());
-            // track in map
-            map.insert(offset, (vpn, ppn));
+        }
+        if invalidate {
+            self.drop_range(vpn_range, true, dropped);
+        }
+        pages
+    }
+
+    /// Drop the pages mapped in `vpn_range`, to be read from the file again on next touch.
+    /// Those dirty stay unless `dirty`, what was stored to them is lost then. Their vpns are
+    /// left in `dropped` for the caller to unmap.
+    pub fn drop_range(&mut self, vpn_range: VPNRange, dirty: bool, dropped: &mut Vec<VirtPageNum>) {
+        let pt = &self.pt;
+        self.map.retain(|_, (vpn, _)| {
+            if !vpn_range.contains(*vpn) || (!dirty && pt.translate(*vpn).unwrap().is_dirty()) {
+                return true;
+            }
+            dropped.push(*vpn);
+            false
+        });
+    }
+
+    /// Write the page mapped at file `offset` in `frame` back, up to the end of the ranges
+    /// mapping it and of the file, which it needn't grow
+    fn write_page(&self, offset: usize, frame: &FrameTracker, file_size: usize) -> bool {
+        if offset >= file_size {
+            return false;
+        }
+        let va_len = self
+            .ranges
+            .iter()
+            .map(|r| {
+                if r.offset <= offset && offset < r.offset + r.len {
+                    PAGE_SIZE.min(r.offset + r.len - offset)
+                } else {
+                    0
+                }
+            })
+            .max()
+            .unwrap();
+        let write_len = va_len.min(file_size - offset);
+        self.file
+            .write_at(offset, &frame.ppn.get_bytes_array()[..write_len])
+            .is_ok()
+    }
+
+    /// Mapping of the child forked into `child`, frames mapped so far are shared instead of
+    /// copied. Those of a private mapping turn read-only on both sides, a store copies them.
+    fn fork(&self, parent: &mut MemorySet, child: &mut MemorySet) -> Self {
+        for (vpn, frame) in self.map.values() {
+            let pte = self.pt.translate(*vpn).unwrap();
+            let mut map_perm = MapPermission::from_bits_truncate(pte.flags().bits());
+            if self.private {
+                map_perm.remove(MapPermission::W);
+                parent.remap(*vpn, frame.ppn, map_perm);
+            }
+            child.map(*vpn, frame.ppn, map_perm);
         }
 
         Self {
             file: self.file.clone(),
             ranges: self.ranges.clone(),
-            map,
-            pt: PageTable::from_token(memory_set.token()),
-            frames,
+            map: self.map.clone(),
+            private: self.private,
+            writable: self.writable,
+            pt: PageTable::from_token(child.token()),
         }
     }
 
@@ -263,13 +439,60 @@ impl ProcessControlBlockInner {
         self.is_zombie
     }
 
-    pub fn alloc_fd(&mut self) -> usize {
-        match self.fd_table.iter().position(Option::is_none) {
+    /// File open as `fd`, `EBADF` if it's negative, past the fd table or closed
+    pub fn resolve_fd(&self, fd: isize) -> Result<Arc<dyn File>, isize> {
+        usize::try_from(fd)
+            .ok()
+            .and_then(|fd| self.fd_table.get(fd)?.clone())
+            .ok_or(EBADF)
+    }
+
+    /// Status flags of `fd`, as F_GETFL gets them
+    pub fn fd_status(&self, fd: usize) -> OpenFlags {
+        self.fd_status
+            .get(&fd)
+            .copied()
+            .unwrap_or(OpenFlags::empty())
+    }
+
+    pub fn set_fd_status(&mut self, fd: usize, flags: OpenFlags) {
+        let flags = flags & OpenFlags::STATUS;
+        if flags.is_empty() {
+            self.fd_status.remove(&fd);
+        } else {
+            self.fd_status.insert(fd, flags);
+        }
+    }
+
+    /// Lowest fd free, `EMFILE` if it's not below RLIMIT_NOFILE
+    pub fn alloc_fd(&mut self) -> Result<usize, isize> {
+        let fd = match self.fd_table.iter().position(Option::is_none) {
             Some(fd) => fd,
-            _ => {
-                self.fd_table.push(None);
-                self.fd_table.len() - 1
-            }
+            _ => self.fd_table.len(),
+        };
+        if fd >= self.rlimits.nofile.cur {
+            return Err(EMFILE);
+        }
+        if fd == self.fd_table.len() {
+            self.fd_table.push(None);
+        }
+        Ok(fd)
+    }
+
+    /// Take the file of `fd` out of the fd table along with its status flags, None if it's
+    /// not open
+    pub fn close_fd(&mut self, fd: usize) -> Option<Arc<dyn File>> {
+        let file = self.fd_table.get_mut(fd)?.take()?;
+        self.fd_status.remove(&fd);
+        self.fd_cloexec.remove(&fd);
+        Some(file)
+    }
+
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) {
+        if cloexec {
+            self.fd_cloexec.insert(fd);
+        } else {
+            self.fd_cloexec.remove(&fd);
         }
     }
 
@@ -289,96 +512,330 @@ impl ProcessControlBlockInner {
         self.tasks[tid].as_ref().unwrap().clone()
     }
 
+    /// If anything of it is on filesystem `fs`: an open file, a file mapped, cwd or the program
+    pub fn uses_fs(&self, fs: usize) -> bool {
+        let open = self.fd_table.iter().flatten().any(|file| {
+            file.clone()
+                .downcast_arc::<OSInode>()
+                .map_or(false, |file| file.clone_inner_inode().fs_id() == fs)
+        });
+        open || self.file_mappings.iter().any(|v| v.file.fs_id() == fs)
+            || self
+                .cwd
+                .as_ref()
+                .map_or(false, |cwd| cwd.inode().fs_id() == fs)
+            || self.exe.as_ref().map_or(false, |exe| exe.fs_id() == fs)
+    }
+
+    /// Shared mapping of `file` to merge a new one into
     pub fn find_file_mapping(&mut self, file: &Arc<Inode>) -> Option<&mut FileMapping> {
-        let inode_id = file.inode_id();
+        let node = VfsNode::of(file);
         self.file_mappings
             .iter_mut()
-            .find(|v| v.file.inode_id() == inode_id)
+            .find(|v| !v.private && VfsNode::of(&v.file) == node)
     }
 
-    pub fn copy_file_mappings(&self, new_memory_set: &mut MemorySet) -> Vec<FileMapping> {
+    /// Write back dirty pages of the file mappings, before the page table (dirty bits) is
+    /// gone, then release their frames along with the reservations of all lazy areas and the
+    /// shared memory mapped.
+    pub fn clear_mappings(&mut self) {
+        for mapping in &self.file_mappings {
+            mapping.sync();
+        }
+        self.file_mappings.clear();
+        self.shm_mappings.clear();
+        self.mmap_mapped.clear();
+    }
+
+    /// Reserve the `segments` of program `exe` in the memory set in place, each a private
+    /// mapping of its own paged in on demand.
+    pub fn map_image(&mut self, exe: Arc<Inode>, segments: Vec<ElfSegment>) {
+        for segment in segments {
+            let range = VPNRange::new(segment.start_va.floor(), segment.end_va.ceil());
+            self.mmap_mapped.insert(
+                range.get_start(),
+                MMapReserve {
+                    range,
+                    perm: segment.perm,
+                    ty: MMapType::File,
+                    image: true,
+                },
+            );
+            let mut mapping = FileMapping::new_empty(exe.clone(), self.memory_set.token(), true);
+            mapping.add_range(
+                MapRange::new(segment.start_va.0, segment.file_size, segment.offset),
+                false,
+            );
+            self.file_mappings.push(mapping);
+        }
+        self.exe = Some(exe);
+    }
+
+    pub fn fork_file_mappings(&mut self, new_memory_set: &mut MemorySet) -> Vec<FileMapping> {
+        let memory_set = &mut self.memory_set;
+        // pages of private mappings turn read-only in the parent
+        if self
+            .file_mappings
+            .iter()
+            .any(|v| v.private && !v.map.is_empty())
+        {
+            self.fault_stats.tlb_flushes += 1;
+        }
         self.file_mappings
             .iter()
-            .map(|v| v.copy_to_user(new_memory_set))
+            .map(|v| v.fork(memory_set, new_memory_set))
             .collect()
     }
 
+    /// Map the shared memory segments into `new_memory_set` of a child where they are here,
+    /// the same frames
+    pub fn fork_shm_mappings(
+        &self,
+        new_memory_set: &mut MemorySet,
+    ) -> BTreeMap<VirtPageNum, Arc<SharedMemory>> {
+        for (start_vpn, shm) in &self.shm_mappings {
+            let perm = self.mmap_mapped[start_vpn].perm;
+            for (i, ppn) in shm.ppns().enumerate() {
+                new_memory_set.map(VirtPageNum(start_vpn.0 + i), ppn, perm);
+            }
+        }
+        self.shm_mappings.clone()
+    }
+
     /// if `vpn_range` is free to (map), currently 2 places to check:
     /// 1. `mmap_mapped_ranges` any overlapping range
     /// 2. hard-coded mappings (like `from_elf, from_existed_user`)
     pub fn vpn_range_free(&self, vpn_range: VPNRange) -> bool {
-        // 1. cmp with mmap marked ranges
-        for v in &self.mmap_mapped {
-            if vpn_range.overlap_with(&v.range) {
+        // 1. cmp with mmap marked ranges, only the last one starting before end may overlap
+        if let Some((_, v)) = self.mmap_mapped.range(..vpn_range.get_end()).next_back() {
+            if v.range.get_end() > vpn_range.get_start() {
                 return false;
             }
         }
 
         // 2. cmp with already hard-coded regions (like `from_elf`)
-        for vpn in vpn_range {
-            match self.memory_set.translate(vpn) {
-                Some(pte) if pte.is_valid() => return false,
-                _ => {}
+        !self.memory_set.overlaps(vpn_range)
+    }
+
+    /// if `len` bytes more of mmap areas stay within RLIMIT_AS
+    pub fn mmap_within_limit(&self, len: usize) -> bool {
+        let reserved: usize = self
+            .mmap_mapped
+            .values()
+            .filter(|v| !v.image)
+            .map(|v| v.range.get_end().0 - v.range.get_start().0)
+            .sum();
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        (reserved + pages).saturating_mul(PAGE_SIZE) <= self.rlimits.address_space.cur
+    }
+
+    /// Move the program break to `brk` unless it's below the heap, or the heap would run into
+    /// another mapping or past RLIMIT_AS. Pages past a lowered break are unmapped.
+    pub fn set_brk(&mut self, brk: usize) -> bool {
+        if !(BRK_AREA_BASE..=MMAP_AREA_BASE).contains(&brk) {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(BRK_AREA_BASE).floor();
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(brk).ceil();
+        if new_end > old_end {
+            let grown = VPNRange::new(old_end, new_end);
+            if !self.vpn_range_free(grown)
+                || !self.mmap_within_limit((new_end.0 - old_end.0) * PAGE_SIZE)
+            {
+                return false;
+            }
+        } else if new_end < old_end {
+            // each page touched is an area of its own
+            for vpn in VPNRange::new(new_end, old_end) {
+                self.memory_set.remove_area_with_start_vpn(vpn);
             }
+            self.fault_stats.tlb_flushes += 1;
         }
-
+        self.mmap_mapped.insert(
+            start_vpn,
+            MMapReserve {
+                range: VPNRange::new(start_vpn, new_end),
+                perm: MapPermission::R | MapPermission::W | MapPermission::U,
+                ty: MMapType::Heap,
+                image: false,
+            },
+        );
+        self.brk = brk;
         true
     }
 
+    /// mmap reservation containing `vpn`
+    pub fn mmap_reserve_of(&self, vpn: VirtPageNum) -> Option<&MMapReserve> {
+        self.mmap_mapped
+            .range(..=vpn)
+            .next_back()
+            .map(|(_, v)| v)
+            .filter(|v| v.range.contains(vpn))
+    }
+
     pub fn tasks_for_each(&self, f: impl Fn(&Arc<TaskControlBlock>)) {
         for t in self.tasks.iter().flatten() {
             f(t)
         }
     }
+
+    /// Take `task` off the wait queues of the sync primitives, it's been killed
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        for mutex in self.mutex_list.iter().flatten() {
+            mutex.remove_waiter(task);
+        }
+        for sem in self.semaphore_list.iter().flatten() {
+            sem.remove_waiter(task);
+        }
+        for cv in self.condvar_list.iter().flatten() {
+            cv.remove_waiter(task);
+        }
+        for barrier in self.barrier_list.iter().flatten() {
+            barrier.remove_waiter(task);
+        }
+    }
+}
+
+/// A program read in by `load_elf`, for a process to switch to
+pub struct ProgramImage {
+    memory_set: MemorySet,
+    /// segments left to be paged in from the file
+    segments: Vec<ElfSegment>,
+    ustack_base: usize,
+    entry_point: usize,
+}
+
+/// User space of the program in `exe` with the segments that can't be paged in from it read
+/// in already, the others are left for `map_image`. ENOEXEC if it's no elf that can be run.
+pub fn load_elf(exe: &Inode) -> Result<ProgramImage, isize> {
+    let size = exe.get_size();
+    // the program headers follow the elf header, mostly in the first page
+    let mut headers = vec![0u8; PAGE_SIZE.min(size)];
+    InodeReader::new(exe, 0).read(&mut headers);
+    let headers_len = MemorySet::elf_headers_len(&headers)?;
+    if headers_len > size {
+        return Err(ENOEXEC);
+    }
+    if headers_len > headers.len() {
+        headers.resize(headers_len, 0);
+        InodeReader::new(exe, 0).read(&mut headers);
+    }
+    let (mut memory_set, segments, ustack_base, entry_point) = MemorySet::from_elf(&headers)?;
+    if segments.iter().any(|segment| {
+        segment
+            .offset
+            .checked_add(segment.file_size)
+            .map_or(true, |end| end > size)
+    }) {
+        return Err(ENOEXEC);
+    }
+    let (lazy, eager): (Vec<_>, Vec<_>) = segments.into_iter().partition(ElfSegment::lazy);
+    let pages = eager
+        .iter()
+        .map(|segment| segment.end_va.ceil().0 - segment.start_va.floor().0)
+        .sum();
+    make_room(pages);
+    for segment in eager {
+        let mut data = vec![0u8; segment.file_size];
+        InodeReader::new(exe, segment.offset).read(&mut data);
+        memory_set.load_segment(&segment, &data);
+    }
+    Ok(ProgramImage {
+        memory_set,
+        segments: lazy,
+        ustack_base,
+        entry_point,
+    })
 }
 
 impl ProcessControlBlock {
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
-        // alloc pid & kernel stack in kernel space
+    /// Process without address space, threads or files, and not in pid2process either.
+    pub fn new_bare() -> Arc<Self> {
+        // alloc pid
         let pid_handle = pid_alloc();
-        let process = Arc::new(Self {
+        let pgid = pid_handle.0;
+        Arc::new(Self {
             pid: pid_handle,
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    // mmap
-                    mmap_mapped: Vec::new(),
-                    mmap_va_allocator: VirtAddressAllocator::new(MMAP_AREA_BASE),
-                    file_mappings: Vec::new(),
-                    // cwd
-                    cwd: ROOT_INODE.clone(),
-                    // time
-                    user_time: 0,
-                    kernel_time: 0,
-                })
+                UPIntrFreeCell::with_level(
+                    ProcessControlBlockInner {
+                        is_zombie: false,
+                        memory_set: MemorySet::new_bare(),
+                        parent: None,
+                        children: Vec::new(),
+                        exit_code: 0,
+                        fd_table: Vec::new(),
+                        fd_status: BTreeMap::new(),
+                        fd_cloexec: BTreeSet::new(),
+                        mutex_list: Vec::new(),
+                        semaphore_list: Vec::new(),
+                        condvar_list: Vec::new(),
+                        barrier_list: Vec::new(),
+                        signals: SignalFlags::empty(),
+                        tasks: Vec::new(),
+                        task_res_allocator: RecycleAllocator::new(),
+                        // mmap
+                        exe: None,
+                        mmap_mapped: BTreeMap::new(),
+                        mmap_va_allocator: VirtAddressAllocator::new(MMAP_AREA_BASE, MMAP_AREA_END),
+                        file_mappings: Vec::new(),
+                        shm_mappings: BTreeMap::new(),
+                        brk: BRK_AREA_BASE,
+                        // cwd
+                        cwd: Some(FsPath::root()),
+                        cred: Cred::ROOT,
+                        rlimits: RLimits::DEFAULT,
+                        // job control
+                        tty: None,
+                        pgid,
+                        // time
+                        user_time: 0,
+                        kernel_time: 0,
+                        cutime: 0,
+                        cstime: 0,
+                        // fault stats
+                        fault_stats: FaultStats::default(),
+                        cfault_stats: FaultStats::default(),
+                    },
+                    LockLevel::Process,
+                )
             },
-        });
+        })
+    }
+
+    /// New session on `tty`: the process leads its own group, which gets the terminal. Its
+    /// main thread is called `name`, running `image` of program `exe`.
+    pub fn new(name: &str, exe: Arc<Inode>, image: ProgramImage, tty: &Arc<Tty>) -> Arc<Self> {
+        // memory_set with elf program headers/trampoline/trap context/user stack
+        let ProgramImage {
+            memory_set,
+            segments,
+            ustack_base,
+            entry_point,
+        } = image;
+        let process = Self::new_bare();
+        let mut process_inner = process.inner_exclusive_access();
+        process_inner.memory_set = memory_set;
+        process_inner.map_image(exe, segments);
+        process_inner.fd_table = vec![
+            // 0 -> stdin
+            Some(Arc::new(Stdin(tty.clone()))),
+            // 1 -> stdout
+            Some(Arc::new(Stdout(tty.clone()))),
+            // 2 -> stderr
+            Some(Arc::new(Stdout(tty.clone()))),
+        ];
+        process_inner.tty = Some(tty.clone());
+        tty.set_foreground(process_inner.pgid);
+        drop(process_inner);
         // create main thread
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&process),
             ustack_base,
             true,
         ));
+        task.set_name(name);
         // prepare trap_cx of main thread
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
@@ -402,19 +859,64 @@ impl ProcessControlBlock {
         process
     }
 
+    #[track_caller]
     pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
+    /// `None` rather than a panic if it's borrowed already
+    pub fn inner_try_exclusive_access(&self) -> Option<UPIntrRefMut<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 
-    pub fn fork(self: &Arc<ProcessControlBlock>) -> Arc<ProcessControlBlock> {
+    /// Only the calling thread `caller` is duplicated, it becomes the main thread of child
+    /// while keeping its user stack in place. `None` if `caller` has exited already.
+    pub fn fork(
+        self: &Arc<ProcessControlBlock>,
+        caller: &Arc<TaskControlBlock>,
+    ) -> Option<Arc<ProcessControlBlock>> {
+        let caller_inner = caller.inner_exclusive_access();
+        let caller_res = caller_inner.res.as_ref()?;
+        let (tid, ustack_base) = (caller_res.tid, caller_res.ustack_base());
+        let trap_cx_ppn = caller_inner.trap_cx_ppn;
+        let signal_processor = caller_inner.signal_processor.fork();
+        let name = caller_inner.name.clone();
+        drop(caller_inner);
+
+        // room for the copy with nothing borrowed yet
+        let pages = self.inner_exclusive_access().memory_set.framed_pages();
+        make_room(pages);
         let mut parent_inner = self.inner_exclusive_access();
-        assert_eq!(parent_inner.thread_count(), 1);
         // copy parent's user space: including trampoline/ustack's/trap_cx's
         let mut memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        // drop every other thread's ustack, and all trap_cx's
+        for task in parent_inner.tasks.iter().flatten() {
+            if let Some(res) = task.inner_exclusive_access().res.as_ref() {
+                res.unmap_from(&mut memory_set, res.tid != tid);
+            }
+        }
+        // trap_cx of caller goes to tid 0's place
+        let trap_cx_bottom = trap_cx_bottom_from_tid(0);
+        memory_set.insert_framed_area(
+            trap_cx_bottom.into(),
+            (trap_cx_bottom + PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::W,
+        );
+        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom.into();
+        memory_set
+            .translate(trap_cx_bottom_va.into())
+            .unwrap()
+            .ppn()
+            .get_bytes_array()
+            .copy_from_slice(trap_cx_ppn.get_bytes_array());
+        // so that tid 0's ustack is exactly caller's
+        let child_ustack_base = ustack_bottom_from_tid(ustack_base, tid);
+        #[cfg(feature = "isolation_audit")]
+        memory_set.audit_user();
         // alloc pid
         let pid_handle = pid_alloc();
         // copy fd table
@@ -434,34 +936,54 @@ impl ProcessControlBlock {
             }
         }
         // copy file mapping
-        let file_mappings = parent_inner.copy_file_mappings(&mut memory_set);
+        let file_mappings = parent_inner.fork_file_mappings(&mut memory_set);
+        let shm_mappings = parent_inner.fork_shm_mappings(&mut memory_set);
         // construct TCB
         let child = Arc::new(Self {
             pid: pid_handle,
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                    mutex_list: Vec::new(),     // not inherit mutex
-                    semaphore_list: Vec::new(), // not inherit sem
-                    condvar_list: Vec::new(),   // not inherit cv
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    // mmap
-                    mmap_mapped: parent_inner.mmap_mapped.clone(),
-                    mmap_va_allocator: parent_inner.mmap_va_allocator.clone(),
-                    file_mappings,
-                    // cwd
-                    cwd: parent_inner.cwd.clone(),
-                    // time
-                    user_time: 0,
-                    kernel_time: 0,
-                })
+                UPIntrFreeCell::with_level(
+                    ProcessControlBlockInner {
+                        is_zombie: false,
+                        memory_set,
+                        parent: Some(Arc::downgrade(self)),
+                        children: Vec::new(),
+                        exit_code: 0,
+                        fd_table: new_fd_table,
+                        fd_status: parent_inner.fd_status.clone(),
+                        fd_cloexec: parent_inner.fd_cloexec.clone(),
+                        mutex_list: Vec::new(),     // not inherit mutex
+                        semaphore_list: Vec::new(), // not inherit sem
+                        condvar_list: Vec::new(),   // not inherit cv
+                        barrier_list: Vec::new(),   // not inherit barrier
+                        signals: SignalFlags::empty(),
+                        tasks: Vec::new(),
+                        task_res_allocator: RecycleAllocator::new(),
+                        // mmap
+                        exe: parent_inner.exe.clone(),
+                        mmap_mapped: parent_inner.mmap_mapped.clone(),
+                        mmap_va_allocator: parent_inner.mmap_va_allocator.clone(),
+                        file_mappings,
+                        shm_mappings,
+                        brk: parent_inner.brk,
+                        // cwd
+                        cwd: parent_inner.cwd.clone(),
+                        cred: parent_inner.cred,
+                        rlimits: parent_inner.rlimits,
+                        // job control
+                        tty: parent_inner.tty.clone(),
+                        pgid: parent_inner.pgid,
+                        // time
+                        user_time: 0,
+                        kernel_time: 0,
+                        cutime: 0,
+                        cstime: 0,
+                        // fault stats
+                        fault_stats: FaultStats::default(),
+                        cfault_stats: FaultStats::default(),
+                    },
+                    LockLevel::Process,
+                )
             },
         });
         // add to parent
@@ -469,17 +991,16 @@ impl ProcessControlBlock {
         // create main thread of child
         let task = Arc::new(TaskControlBlock::new(
             child.clone(),
-            parent_inner
-                .get_task(0)
-                .inner_exclusive_access()
-                .res
-                .as_ref()
-                .unwrap()
-                .ustack_base(),
+            child_ustack_base,
             // here we do not allocate trap_cx or ustack again
             // but mention that we allocate a new kstack here
             false,
         ));
+        // dispositions, mask and name go along
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.signal_processor = signal_processor;
+        task_inner.name = name;
+        drop(task_inner);
         // attach thread to child
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(task.clone()));
@@ -492,24 +1013,91 @@ impl ProcessControlBlock {
         insert_into_pid2process(child.getpid(), child.clone());
         // schedule child's main thread
         add_task(task);
-        child
+        Some(child)
     }
 
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) {
-        assert_eq!(self.inner_exclusive_access().thread_count(), 1);
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+    /// Replace the image by `image` of program `exe`, loaded and checked by `load_elf` before
+    /// as nothing here can fail. `caller` carries on as the main thread (tid 0) of the new one
+    /// and every other thread is terminated.
+    pub fn exec(
+        &self,
+        caller: &Arc<TaskControlBlock>,
+        exe: Arc<Inode>,
+        image: ProgramImage,
+        args: Vec<String>,
+    ) {
+        let mut process_inner = self.inner_exclusive_access();
+        let siblings: Vec<_> = process_inner
+            .tasks
+            .drain(..)
+            .flatten()
+            .filter(|task| !Arc::ptr_eq(task, caller))
+            .collect();
+        // those blocked on a lock or the like of the process are never handed it
+        for task in siblings.iter() {
+            process_inner.remove_waiter(task);
+        }
+        process_inner.tasks.push(Some(caller.clone()));
+        process_inner.task_res_allocator = RecycleAllocator::new();
+        let tid = process_inner.alloc_tid();
+        let cloexec: Vec<usize> = process_inner.fd_cloexec.iter().copied().collect();
+        let closed: Vec<_> = cloexec
+            .into_iter()
+            .filter_map(|fd| process_inner.close_fd(fd))
+            .collect();
+        drop(process_inner);
+        for file in closed {
+            file_closed(self.getpid(), file);
+        }
+        // siblings may be ready, sleeping or blocked in some wait queue, the former two are pulled
+        // out here, as they were off the sync primitives above, those blocked elsewhere (a pipe,
+        // a socket) are dropped by the scheduler once woken up as they have no res left
+        let mut recycle_res = Vec::new();
+        for task in siblings.iter() {
+            remove_task(task);
+            crate::timer::cancel_timer(task);
+            let mut task_inner = task.inner_exclusive_access();
+            task_inner.exit_code = Some(-1);
+            // they may be waiting on each other
+            task_inner.join_queue.clear();
+            if let Some(res) = task_inner.res.take() {
+                recycle_res.push(res);
+            }
+        }
+        // dealloc_user_res() borrows process inner, and has to run on the old memory set
+        recycle_res.clear();
+        drop(siblings);
+
+        let ProgramImage {
+            memory_set,
+            segments,
+            ustack_base,
+            entry_point,
+        } = image;
         let new_token = memory_set.token();
-        // substitutes
-        self.inner_exclusive_access().memory_set = memory_set;
-        let task = self.inner_exclusive_access().get_task(0);
-        let mut task_inner = task.inner_exclusive_access();
-        // modify ustack
-        task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
+        // substitutes, mappings and the heap start over empty
+        let mut process_inner = self.inner_exclusive_access();
+        process_inner.clear_mappings();
+        process_inner.mmap_va_allocator = VirtAddressAllocator::new(MMAP_AREA_BASE, MMAP_AREA_END);
+        process_inner.brk = BRK_AREA_BASE;
+        process_inner.memory_set = memory_set;
+        process_inner.map_image(exe, segments);
+        drop(process_inner);
+        let task = caller;
+        // becomes main thread, modify ustack. The res borrows the process, so it's out of the
+        // thread meanwhile.
+        let mut res = task.inner_exclusive_access().res.take().unwrap();
+        res.tid = tid;
+        res.ustack_base = ustack_base;
         // allow user res, after we can get ppn from it
-        task_inner.res.as_ref().unwrap().alloc_user_res();
+        res.alloc_user_res();
+        let trap_cx_ppn = res.trap_cx_ppn();
+        let mut user_sp = res.ustack_top();
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.res = Some(res);
         // get ppn from res, and set back to task
-        task_inner.trap_cx_ppn = task_inner.res.as_ref().unwrap().trap_cx_ppn();
-        let mut user_sp = task_inner.res.as_ref().unwrap().ustack_top();
+        task_inner.trap_cx_ppn = trap_cx_ppn;
+        task_inner.signal_processor.exec();
 
         // push arguments on user stack
         // +1 is last 0, indicate end of args
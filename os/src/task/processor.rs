@@ -3,11 +3,14 @@ use core::arch::asm;
 use alloc::sync::Arc;
 use lazy_static::lazy_static;
 
-use crate::{sync::UPIntrFreeCell, trap::TrapContext};
+use crate::{
+    sync::{LockLevel, UPIntrFreeCell},
+    trap::TrapContext,
+};
 
 use super::{
     context::TaskContext,
-    manager,
+    init, manager,
     switch::__switch,
     task::{TaskControlBlock, TaskStatus},
     ProcessControlBlock,
@@ -15,7 +18,9 @@ use super::{
 
 lazy_static! {
     // 在单核CPU环境下, 我们仅创建单个 Processor 的全局实例
-    pub static ref PROCESSOR: UPIntrFreeCell<Processor> = unsafe { UPIntrFreeCell::new(Processor::new()) };
+    pub static ref PROCESSOR: UPIntrFreeCell<Processor> = unsafe {
+        UPIntrFreeCell::with_level(Processor::new(), LockLevel::Processor)
+    };
 }
 
 pub struct Processor {
@@ -67,6 +72,11 @@ pub fn current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSOR.exclusive_access().current()
 }
 
+/// Like `current_task`, but `None` rather than a panic if the processor is borrowed
+pub fn try_current_task() -> Option<Arc<TaskControlBlock>> {
+    PROCESSOR.try_exclusive_access()?.current()
+}
+
 pub fn current_process() -> Arc<ProcessControlBlock> {
     current_task().unwrap().process.upgrade().unwrap()
 }
@@ -107,14 +117,17 @@ pub fn current_kstack_top() -> usize {
 /// 从 idle 控制流通过任务调度切换到某个任务开始执行
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
         if let Some(task) = manager::fetch_task() {
-            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            let process = task.process.clone();
             // access coming task TCB exclusively
             let next_task_cx_ptr = task.inner.exclusive_session(|task_inner| {
                 task_inner.task_status = TaskStatus::Running;
                 &task_inner.task_cx as *const TaskContext
             });
+            log::trace!("switch to [{}]", task.label());
+            // the processor comes last in the lock order
+            let mut processor = PROCESSOR.exclusive_access();
+            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // Arc<TaskControlBlock> 形式的任务从TaskManager流动到了处Processor
             processor.current = Some(task);
             // 开始记录时间
@@ -125,8 +138,16 @@ pub fn run_tasks() {
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // kernel time until it switched away, blocked or exited
+            if let Some(process) = process.upgrade() {
+                process.inner_exclusive_access().kernel_time += refresh_stop_watch();
+            }
+            // back on idle stack, no zombie's kernel stack in use
+            init::reap_orphans();
         } else {
-            // no available task
+            // no available task, so no timer trap from user mode either: keep the
+            // network going from here
+            crate::net::timer_tick();
         }
     }
 }
@@ -141,23 +162,22 @@ pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
 }
 
 /// stop_watch <- now, return time of `last stop` until `now`
-#[allow(unused)]
 pub fn refresh_stop_watch() -> usize {
     PROCESSOR.exclusive_access().refresh_stop_watch()
 }
 
 pub fn user_time_start() {
-    // let process = current_process();
-    // let mut inner = process.inner_exclusive_access();
-    // // 到user_time_start为止都是kernel_time, 故累加
-    // // 隐含另一个意思, 从现在开始是user_time
-    // inner.kernel_time += refresh_stop_watch();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    // 到user_time_start为止都是kernel_time, 故累加
+    // 隐含另一个意思, 从现在开始是user_time
+    inner.kernel_time += refresh_stop_watch();
 }
 
 pub fn user_time_end() {
-    // let process = current_process();
-    // let mut inner = process.inner_exclusive_access();
-    // // 类似上面, 到user_time_end为止都是user_time, 故累加
-    // // 隐含另一个意思, 从现在开始是kernel_time
-    // inner.user_time += refresh_stop_watch();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    // 类似上面, 到user_time_end为止都是user_time, 故累加
+    // 隐含另一个意思, 从现在开始是kernel_time
+    inner.user_time += refresh_stop_watch();
 }
@@ -0,0 +1,74 @@
+//! Resource limits of a process, inherited on fork and kept across exec. Each has a soft
+//! limit enforced and a hard one the soft may be raised up to, only root may raise the hard.
+
+use crate::config::{FD_MAX, USER_STACK_SIZE};
+
+/// Size of a stack in bytes: stacks don't grow, so the hard limit is what each gets, and the
+/// soft bounds what exec may push onto a new one
+pub const RLIMIT_STACK: usize = 3;
+/// Bound of fds, the lowest one it may not open
+pub const RLIMIT_NOFILE: usize = 7;
+/// Bytes of mmap areas, reserved ones counted whether touched or not
+pub const RLIMIT_AS: usize = 9;
+
+/// No limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Soft and hard limit of a resource, as prlimit takes and gives them
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+impl RLimit {
+    const fn new(limit: usize) -> Self {
+        Self {
+            cur: limit,
+            max: limit,
+        }
+    }
+}
+
+/// Limits of the resources a process consumes
+#[derive(Clone, Copy, Debug)]
+pub struct RLimits {
+    pub stack: RLimit,
+    pub nofile: RLimit,
+    pub address_space: RLimit,
+}
+
+impl RLimits {
+    pub const DEFAULT: Self = Self {
+        stack: RLimit::new(USER_STACK_SIZE),
+        nofile: RLimit::new(FD_MAX),
+        address_space: RLimit::new(RLIM_INFINITY),
+    };
+
+    /// Limit of `resource`, `None` if unknown
+    pub fn get(&self, resource: usize) -> Option<RLimit> {
+        match resource {
+            RLIMIT_STACK => Some(self.stack),
+            RLIMIT_NOFILE => Some(self.nofile),
+            RLIMIT_AS => Some(self.address_space),
+            _ => None,
+        }
+    }
+
+    /// Set limit of `resource` to `limit`, its soft within its hard, on behalf of root or not.
+    /// False if it may not: raising the hard limit by others, or past what the kernel can give.
+    pub fn set(&mut self, resource: usize, limit: RLimit, root: bool) -> bool {
+        let (old, ceiling) = match resource {
+            RLIMIT_STACK => (&mut self.stack, USER_STACK_SIZE),
+            RLIMIT_NOFILE => (&mut self.nofile, FD_MAX),
+            RLIMIT_AS => (&mut self.address_space, RLIM_INFINITY),
+            _ => return false,
+        };
+        if limit.max > ceiling || (limit.max > old.max && !root) {
+            return false;
+        }
+        *old = limit;
+        true
+    }
+}
@@ -39,22 +39,48 @@ bitflags! {
     }
 }
 
+/// What a signal does to a process when nobody handles it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Term,
+    Ignore,
+    Stop,
+    Cont,
+    /// terminate, dumping core if we had cores to dump
+    Core,
+}
+
 impl SignalFlags {
-    pub fn check_error(&self) -> Option<(i32, &'static str)> {
-        if self.contains(Self::SIGINT) {
-            Some((-2, "Killed, SIGINT=2"))
-        } else if self.contains(Self::SIGILL) {
-            Some((-4, "Illegal Instruction, SIGILL=4"))
-        } else if self.contains(Self::SIGABRT) {
-            Some((-6, "Aborted, SIGABRT=6"))
-        } else if self.contains(Self::SIGFPE) {
-            Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
-        } else if self.contains(Self::SIGKILL) {
-            Some((-9, "Killed, SIGKILL=9"))
-        } else if self.contains(Self::SIGSEGV) {
-            Some((-11, "Segmentation Fault, SIGSEGV=11"))
-        } else {
-            None
+    pub fn default_action(self) -> DefaultAction {
+        match self {
+            Self::SIGQUIT
+            | Self::SIGILL
+            | Self::SIGTRAP
+            | Self::SIGABRT
+            | Self::SIGBUS
+            | Self::SIGFPE
+            | Self::SIGSEGV
+            | Self::SIGXCPU
+            | Self::SIGXFSZ
+            | Self::SIGSYS => DefaultAction::Core,
+            // signal 0 only probes whether the target exists
+            Self::SIGDEF | Self::SIGCHLD | Self::SIGURG | Self::SIGWINCH => DefaultAction::Ignore,
+            Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU => DefaultAction::Stop,
+            Self::SIGCONT => DefaultAction::Cont,
+            _ => DefaultAction::Term,
+        }
+    }
+
+    /// why a process killed by it died
+    pub fn death_message(self) -> &'static str {
+        match self {
+            Self::SIGINT => "Killed, SIGINT=2",
+            Self::SIGILL => "Illegal Instruction, SIGILL=4",
+            Self::SIGABRT => "Aborted, SIGABRT=6",
+            Self::SIGFPE => "Erroneous Arithmetic Operation, SIGFPE=8",
+            Self::SIGKILL => "Killed, SIGKILL=9",
+            Self::SIGSEGV => "Segmentation Fault, SIGSEGV=11",
+            _ => "Terminated by signal",
         }
     }
 }
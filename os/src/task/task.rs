@@ -1,8 +1,15 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    collections::VecDeque,
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::AtomicBool;
 
 use crate::{
     mm::PhysPageNum,
-    sync::{UPIntrFreeCell, UPIntrRefMut},
+    sync::{LockLevel, UPIntrFreeCell, UPIntrRefMut},
     trap::TrapContext,
 };
 
@@ -10,14 +17,20 @@ use super::{
     context::TaskContext,
     id::{kstack_alloc, KernelStack, TaskUserRes},
     process::ProcessControlBlock,
-    SignalActions, SignalFlags,
+    DefaultAction, SignalAction, SignalActions, SignalFlags, SIG_DFL, SIG_IGN,
 };
 
+/// Longest name of a thread in bytes
+pub const THREAD_NAME_MAX: usize = 15;
+
 pub struct TaskControlBlock {
     // immutable
     pub process: Weak<ProcessControlBlock>,
     pub kstack: KernelStack,
     // mutable
+    /// in a syscall, the user memory it translated may be in use until it returns so none of
+    /// the process's pages go to swap
+    pub in_syscall: AtomicBool,
     pub inner: UPIntrFreeCell<TaskControlBlockInner>,
 }
 
@@ -35,19 +48,50 @@ impl TaskControlBlock {
         Self {
             process: process_weak,
             kstack,
+            in_syscall: AtomicBool::new(false),
             inner: unsafe {
-                UPIntrFreeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_stop),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                    signal_processor: SignalProcessor::new(),
-                })
+                UPIntrFreeCell::with_level(
+                    TaskControlBlockInner {
+                        res: Some(res),
+                        trap_cx_ppn,
+                        task_cx: TaskContext::goto_trap_return(kstack_stop),
+                        task_status: TaskStatus::Ready,
+                        exit_code: None,
+                        join_queue: VecDeque::new(),
+                        waiters: 0,
+                        signal_processor: SignalProcessor::new(),
+                        name: String::new(),
+                    },
+                    LockLevel::Task,
+                )
             },
         }
     }
 
+    /// Name the thread `name`, cut short to `THREAD_NAME_MAX` bytes
+    pub fn set_name(&self, name: &str) {
+        let mut end = name.len().min(THREAD_NAME_MAX);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.inner_exclusive_access().name = String::from(&name[..end]);
+    }
+
+    /// `pid:tid name` for logs, what can't be told right now shows as `?`. Doesn't panic
+    /// however the thread is borrowed.
+    pub fn label(&self) -> String {
+        let known = |v: Option<usize>| v.map_or(String::from("?"), |v| v.to_string());
+        let pid = self.process.upgrade().map(|p| p.getpid());
+        let inner = self.inner.try_exclusive_access();
+        let tid = inner
+            .as_ref()
+            .and_then(|inner| inner.res.as_ref())
+            .map(|res| res.tid);
+        let name = inner.as_ref().map_or("?", |inner| inner.name.as_str());
+        format!("{}:{} {}", known(pid), known(tid), name)
+    }
+
+    #[track_caller]
     pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
@@ -65,7 +109,13 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    /// threads blocked in waittid on this one, woken up on exit
+    pub join_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// waittid callers yet to collect `exit_code`, the last one recycles the tid
+    pub waiters: usize,
     pub signal_processor: SignalProcessor,
+    /// shown in logs and panics, the program's unless set otherwise
+    pub name: String,
 }
 
 impl TaskControlBlockInner {
@@ -78,52 +128,100 @@ impl TaskControlBlockInner {
     // }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TaskStatus {
     Ready,
     Running,
     Blocked,
 }
 
+/// handlers nest at most this deep, signals arriving beyond stay pending
+pub const MAX_SIGNAL_NESTING: usize = 8;
+
+/// What a signal handler interrupted, `sigreturn` goes back there.
+pub struct SignalFrame {
+    pub trap_cx: TrapContext,
+    /// blocked until the handler returns
+    pub blocked: SignalFlags,
+}
+
 pub struct SignalProcessor {
-    // current handling signal(signum)
-    pub signal_handling: Option<usize>,
     // global mask
     pub signal_mask: SignalFlags,
     // (re)action of signals
     pub signal_actions: SignalActions,
-    // status killed
-    pub killed: bool,
+    // status killed, by which signal
+    pub killed: Option<usize>,
     // status frozen
     pub frozen: bool,
-    // backup trap_cx (when handling signal)
-    pub trap_cx_backup: Option<TrapContext>,
+    // handlers running, innermost last
+    pub frames: Vec<SignalFrame>,
+    // mask to restore once sigsuspend is done
+    pub saved_mask: Option<SignalFlags>,
+    // blocked in sigsuspend
+    pub suspended: bool,
 }
 
 impl SignalProcessor {
     pub fn new() -> Self {
         Self {
-            signal_handling: None,
             signal_mask: SignalFlags::empty(),
             signal_actions: SignalActions::default(),
-            killed: false,
+            killed: None,
             frozen: false,
-            trap_cx_backup: None,
+            frames: Vec::new(),
+            saved_mask: None,
+            suspended: false,
         }
     }
 
+    /// Child of fork inherits dispositions and mask.
+    pub fn fork(&self) -> Self {
+        Self {
+            signal_mask: self.signal_mask,
+            signal_actions: self.signal_actions.clone(),
+            ..Self::new()
+        }
+    }
+
+    /// Handlers are gone with the old image, ignored signals stay ignored.
+    pub fn exec(&mut self) {
+        for action in self.signal_actions.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+        self.frames.clear();
+    }
+
     pub fn is_global_masked(&self, signal: SignalFlags) -> bool {
         self.signal_mask.contains(signal)
     }
 
+    /// blocked by any of the handlers running
     pub fn is_handling_masked(&self, signal: SignalFlags) -> bool {
-        match &self.signal_handling {
-            Some(signum) => self.signal_actions.is_masked(*signum, signal),
-            _ => false,
-        }
+        self.frames
+            .iter()
+            .any(|frame| frame.blocked.contains(signal))
     }
 
     pub fn handler_for_action(&self, signum: usize) -> usize {
         self.signal_actions.get_handler(signum)
     }
+
+    /// `signal` would be acted on right now: neither blocked nor ignored.
+    pub fn is_deliverable(&self, signal: SignalFlags) -> bool {
+        if matches!(signal, SignalFlags::SIGKILL | SignalFlags::SIGSTOP) {
+            return true;
+        }
+        if self.is_global_masked(signal) || self.is_handling_masked(signal) {
+            return false;
+        }
+        let signum = signal.bits().trailing_zeros() as usize;
+        match self.handler_for_action(signum) {
+            SIG_IGN => false,
+            SIG_DFL => signal.default_action() != DefaultAction::Ignore,
+            _ => true,
+        }
+    }
 }
@@ -1,5 +1,4 @@
-use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
-use core::cmp::Ordering;
+use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
 use riscv::register::time;
 
@@ -11,6 +10,7 @@ use crate::{
 
 const MS_PER_SEC: usize = 1000;
 const US_PER_SEC: usize = 1_000_000;
+const NS_PER_SEC: u64 = 1_000_000_000;
 const TICKS_PER_SEC: usize = 100; // 10ms/tick
 
 /// read the `mtime` register
@@ -19,7 +19,6 @@ pub fn get_time() -> usize {
 }
 
 /// get current time in ms
-#[allow(unused)]
 pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MS_PER_SEC)
 }
@@ -29,61 +28,115 @@ pub fn get_time_us() -> usize {
     time::read() / (CLOCK_FREQ / US_PER_SEC)
 }
 
+/// get time since boot in ns
+pub fn get_time_ns() -> u64 {
+    let (ticks, freq) = (time::read() as u64, CLOCK_FREQ as u64);
+    // ticks * NS_PER_SEC alone overflows after some minutes
+    ticks / freq * NS_PER_SEC + ticks % freq * NS_PER_SEC / freq
+}
+
+lazy_static! {
+    /// wall time at boot in ns, read from the RTC once; boards without one start at the epoch
+    static ref BOOT_TIME_NS: u64 = crate::board::rtc_ns()
+        .map(|ns| ns.saturating_sub(get_time_ns()))
+        .unwrap_or(0);
+}
+
+/// get wall time in ns since the epoch
+pub fn get_wall_time_ns() -> u64 {
+    *BOOT_TIME_NS + get_time_ns()
+}
+
 /// set the next timer interrupt
 pub fn set_next_trigger() {
     crate::sbi::set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
 
+/// one timer interrupt
+const MS_PER_TICK: usize = MS_PER_SEC / TICKS_PER_SEC;
+/// sleeps further out than one revolution just stay in their slot for more rounds
+const WHEEL_SLOTS: usize = 64;
+
 lazy_static! {
-    static ref TIMERS: UPIntrFreeCell<BinaryHeap<TimerCondVar>> =
-        unsafe { UPIntrFreeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+    static ref SLEEP_QUEUE: UPIntrFreeCell<SleepQueue> =
+        unsafe { UPIntrFreeCell::new(SleepQueue::new()) };
 }
 
-pub struct TimerCondVar {
-    pub expire_ms: usize,
-    pub task: Arc<TaskControlBlock>,
+struct Sleeper {
+    expire_ms: usize,
+    task: Arc<TaskControlBlock>,
 }
-impl PartialEq for TimerCondVar {
-    fn eq(&self, other: &Self) -> bool {
-        self.expire_ms == other.expire_ms
-    }
+
+/// Tasks blocked in `sleep`, on a hashed timer wheel: slot `t % WHEEL_SLOTS` holds the ones
+/// expiring during tick `t`. They are off the ready queue until they expire or get cancelled,
+/// whichever takes them out of here first wakes them.
+struct SleepQueue {
+    slots: [Vec<Sleeper>; WHEEL_SLOTS],
+    /// tick the wheel was last turned to, its slot may hold sleepers due later in that tick
+    tick: usize,
 }
-impl Eq for TimerCondVar {}
-impl PartialOrd for TimerCondVar {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let a = -(self.expire_ms as isize);
-        let b = -(other.expire_ms as isize);
-        Some(a.cmp(&b))
+
+impl SleepQueue {
+    fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| Vec::new()),
+            tick: get_time_ms() / MS_PER_TICK,
+        }
     }
-}
-impl Ord for TimerCondVar {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+
+    fn push(&mut self, expire_ms: usize, task: Arc<TaskControlBlock>) {
+        // slots behind the wheel are only seen again a revolution later
+        let tick = (expire_ms / MS_PER_TICK).max(self.tick);
+        self.slots[tick % WHEEL_SLOTS].push(Sleeper { expire_ms, task });
+    }
+
+    /// Turn the wheel to `now_ms`, moving the sleepers due into `due`.
+    fn expire(&mut self, now_ms: usize, due: &mut Vec<Arc<TaskControlBlock>>) {
+        let now = now_ms / MS_PER_TICK;
+        // one revolution visits every slot
+        let last = now.min(self.tick + WHEEL_SLOTS - 1);
+        for tick in self.tick..=last {
+            let slot = &mut self.slots[tick % WHEEL_SLOTS];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].expire_ms <= now_ms {
+                    due.push(slot.swap_remove(i).task);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.tick = self.tick.max(now);
+    }
+
+    fn cancel(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        for slot in self.slots.iter_mut() {
+            if let Some(i) = slot.iter().position(|s| Arc::ptr_eq(&s.task, task)) {
+                slot.swap_remove(i);
+                return true;
+            }
+        }
+        false
     }
 }
 
+/// `task` is about to block until `expire_ms`.
 pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
-    let mut timers = TIMERS.exclusive_access();
-    timers.push(TimerCondVar { expire_ms, task });
+    SLEEP_QUEUE.exclusive_access().push(expire_ms, task);
+}
+
+/// Take `task` out of the sleep queue, false if it's not sleeping (anymore). Only a `true`
+/// entitles the caller to wake it up, the timer may have done so already.
+pub fn cancel_timer(task: &Arc<TaskControlBlock>) -> bool {
+    SLEEP_QUEUE.exclusive_access().cancel(task)
 }
 
 pub fn check_timer() {
-    let current_ms = get_time_ms();
-    let mut timers = TIMERS.exclusive_access();
-    while let Some(timer) = timers.peek() {
-        if timer.expire_ms <= current_ms {
-            // wakeup task
-            task::wakeup_task(timer.task.clone());
-            timers.pop();
-        } else {
-            // stop early coz heap is ordered
-            break;
-        }
+    let mut due = Vec::new();
+    SLEEP_QUEUE
+        .exclusive_access()
+        .expire(get_time_ms(), &mut due);
+    if !due.is_empty() {
+        task::wakeup_tasks(due);
     }
 }
-
-// pub fn remove_timer(task: &Arc<TaskControlBlock>) {
-//     let mut timers = TIMERS.exclusive_access();
-//     let rm_ptr = Arc::as_ptr(&task);
-//     timers.retain(|v| Arc::as_ptr(&v.task) != rm_ptr);
-// }
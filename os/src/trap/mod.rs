@@ -59,6 +59,9 @@ fn disable_supervisor_interrupt() {
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
     crate::task::user_time_end();
+    // user memory is only accessed through page table walks, never with SUM set
+    #[cfg(feature = "isolation_audit")]
+    assert!(!sstatus::read().sum(), "SUM set while trapping from user");
     let scause = scause::read();
     let stval = stval::read();
 
@@ -74,19 +77,18 @@ pub fn trap_handler() -> ! {
             let cx = crate::task::current_trap_cx();
             cx.x[10] = ret as usize;
         }
-        scause::Trap::Exception(Exception::StoreFault)
-        | scause::Trap::Exception(Exception::LoadFault)
-        | scause::Trap::Exception(Exception::InstructionFault)
-        | scause::Trap::Exception(Exception::InstructionPageFault)
-        | scause::Trap::Exception(Exception::StorePageFault)
-        | scause::Trap::Exception(Exception::LoadPageFault) => {
-            if !crate::task::handle_page_fault(stval) {
-                // log::error!("[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
-                //     scause.cause(),
-                //     stval,
-                //     crate::task::current_trap_cx().sepc
-                // );
-                crate::task::current_add_signal(SignalFlags::SIGSEGV);
+        scause::Trap::Exception(
+            cause @ (Exception::StoreFault
+            | Exception::LoadFault
+            | Exception::InstructionFault
+            | Exception::InstructionPageFault
+            | Exception::StorePageFault
+            | Exception::LoadPageFault),
+        ) => {
+            if let Err(signal) = crate::task::handle_page_fault(stval, cause.into()) {
+                let sepc = crate::task::current_trap_cx().sepc;
+                crate::task::report_page_fault(cause, stval, sepc);
+                crate::task::current_add_signal(signal);
             }
         }
         scause::Trap::Exception(Exception::IllegalInstruction) => {
@@ -102,10 +104,13 @@ pub fn trap_handler() -> ! {
         scause::Trap::Interrupt(Interrupt::SupervisorTimer) => {
             crate::timer::set_next_trigger();
             crate::timer::check_timer();
+            crate::net::timer_tick();
             crate::task::suspend_current_and_run_next();
         }
         scause::Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
+            // input may have made fds ready
+            crate::fs::notify_poll();
         }
         _ => {
             panic!(
@@ -163,18 +168,42 @@ pub fn trap_return() -> ! {
 }
 
 #[no_mangle]
-pub fn trap_from_kernel(_trap_cx: &TrapContext) {
+pub fn trap_from_kernel(trap_cx: &TrapContext) {
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
         scause::Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
+            // input may have made fds ready
+            crate::fs::notify_poll();
         }
         scause::Trap::Interrupt(Interrupt::SupervisorTimer) => {
             crate::timer::set_next_trigger();
             crate::timer::check_timer();
             // do not schedule now
         }
+        scause::Trap::Exception(
+            Exception::LoadFault
+            | Exception::StoreFault
+            | Exception::InstructionFault
+            | Exception::LoadPageFault
+            | Exception::StorePageFault
+            | Exception::InstructionPageFault,
+        ) => {
+            let sepc = trap_cx.sepc;
+            let (symbol, offset) = match crate::trace::find_symbol_with_addr(sepc) {
+                Some((addr, name)) => (name, sepc - addr),
+                None => ("??", 0),
+            };
+            panic!(
+                "{:?} in kernel at {:#x} <{}+{:#x}>, stval = {:#x}!",
+                scause.cause(),
+                sepc,
+                symbol,
+                offset,
+                stval
+            );
+        }
         _ => {
             panic!(
                 "Unsupported trap from kernel: {:?}, stval = {:#x}!",
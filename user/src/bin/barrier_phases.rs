@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{barrier_create, barrier_destroy, barrier_wait, exit, thread_create, waittid};
+
+const THREAD_NUM: usize = 4;
+const PHASES: usize = 3;
+
+static mut BARRIER_ID: usize = 0;
+/// threads done with each phase
+static DONE: [AtomicUsize; PHASES] = [const { AtomicUsize::new(0) }; PHASES];
+/// last arrivals per phase, must be exactly one
+static LEADERS: [AtomicUsize; PHASES] = [const { AtomicUsize::new(0) }; PHASES];
+
+fn worker(id: usize) -> ! {
+    let barrier_id = unsafe { BARRIER_ID };
+    for phase in 0..PHASES {
+        println!("thread {} in phase {}", id, phase);
+        DONE[phase].fetch_add(1, Ordering::SeqCst);
+        if barrier_wait(barrier_id) == 1 {
+            LEADERS[phase].fetch_add(1, Ordering::SeqCst);
+        }
+        // nobody gets here before everyone finished this phase
+        assert_eq!(DONE[phase].load(Ordering::SeqCst), THREAD_NUM);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        BARRIER_ID = barrier_create(THREAD_NUM) as usize;
+    }
+    let v: Vec<_> = (0..THREAD_NUM)
+        .map(|i| thread_create(worker as usize, i))
+        .collect();
+    for tid in v {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    for leaders in LEADERS.iter() {
+        assert_eq!(leaders.load(Ordering::SeqCst), 1);
+    }
+    assert_eq!(barrier_destroy(unsafe { BARRIER_ID }), 0);
+    println!("barrier_phases passed!");
+    0
+}
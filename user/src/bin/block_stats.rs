@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{block_stats, close, fsync, open, unlink, write, OpenFlags};
+
+const FILE: &str = "block_stats\0";
+const BLOCK_SZ: usize = 512;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let before = block_stats();
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let data = [b'b'; 8 * BLOCK_SZ];
+    assert_eq!(write(fd, &data), data.len() as isize);
+    // written back at once, adjacent data blocks may go in one request
+    assert_eq!(fsync(fd), 0);
+    close(fd);
+    unlink(FILE);
+
+    let after = block_stats();
+    let requests = after.requests - before.requests;
+    let blocks = after.blocks - before.blocks;
+    assert!(requests > 0);
+    assert!(blocks >= requests);
+    println!(
+        "{} blocks in {} requests, {} merged, mean depth {}, mean latency {}us",
+        blocks,
+        requests,
+        after.merged - before.merged,
+        (after.depth_sum - before.depth_sum) / requests,
+        (after.latency_us - before.latency_us) / requests
+    );
+    println!("block_stats passed!");
+    0
+}
@@ -7,22 +7,33 @@ extern crate alloc;
 
 use user_lib::{close, open, read, OpenFlags};
 
+/// stopped reading stdin in the background, read again
+const EINTR: isize = -4;
+
+/// `cat [file]`: stdin without a file, until the end of input
 #[no_mangle]
 pub fn main(argc: usize, argv: &[&str]) -> i32 {
-    assert_eq!(argc, 2);
-    let fd = open(argv[1], OpenFlags::RDONLY);
-    if fd == -1 {
-        panic!("Error occured when opening file");
-    }
-    let fd = fd as usize;
+    assert!(argc <= 2);
+    let fd = if argc == 2 {
+        let fd = open(argv[1], OpenFlags::RDONLY);
+        if fd == -1 {
+            panic!("Error occured when opening file");
+        }
+        fd as usize
+    } else {
+        0
+    };
     let mut buf = [0u8; 256];
     loop {
-        let size = read(fd, &mut buf) as usize;
-        if size == 0 {
-            break;
-        }
+        let size = match read(fd, &mut buf) {
+            EINTR => continue,
+            size if size <= 0 => break,
+            size => size as usize,
+        };
         print!("{}", core::str::from_utf8(&buf[..size]).unwrap());
     }
-    close(fd);
+    if fd != 0 {
+        close(fd);
+    }
     0
 }
@@ -0,0 +1,173 @@
+#![no_std]
+#![no_main]
+
+//! `dd [if=<file>] [of=<file>] [bs=512] [count=16] [skip=0] [seek=0] [mode=seq|rand]
+//! [conv=fsync]`: copy `count` blocks of `bs` bytes, from block `skip` of `if` on to block
+//! `seek` of `of` on, and report how fast. Zeros are read without `if`, what's read is
+//! dropped without `of`. `mode=rand` goes through the same blocks in random order,
+//! `conv=fsync` has `of` written back before the clock stops. Sizes take a `k` or `m`.
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{format, vec};
+use user_lib::{block_stats, close, fsync, get_time_ns, open, pread, pwrite, OpenFlags};
+
+struct Args<'a> {
+    input: Option<&'a str>,
+    output: Option<&'a str>,
+    bs: usize,
+    count: usize,
+    skip: usize,
+    seek: usize,
+    random: bool,
+    fsync: bool,
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, unit) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 1024),
+        b'm' | b'M' => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.parse::<usize>().ok().map(|n| n * unit)
+}
+
+fn parse_args<'a>(argv: &[&'a str]) -> Option<Args<'a>> {
+    let mut args = Args {
+        input: None,
+        output: None,
+        bs: 512,
+        count: 16,
+        skip: 0,
+        seek: 0,
+        random: false,
+        fsync: false,
+    };
+    for arg in &argv[1..] {
+        let (key, value) = arg.split_once('=')?;
+        match key {
+            "if" => args.input = Some(value),
+            "of" => args.output = Some(value),
+            "bs" => args.bs = parse_size(value).filter(|&bs| bs > 0)?,
+            "count" => args.count = parse_size(value)?,
+            "skip" => args.skip = parse_size(value)?,
+            "seek" => args.seek = parse_size(value)?,
+            "mode" => args.random = value == "rand",
+            "conv" => args.fsync = value == "fsync",
+            _ => return None,
+        }
+    }
+    Some(args)
+}
+
+/// xorshift, the same blocks in the same order every run
+struct Rand(u64);
+
+impl Rand {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn open_file(path: &str, flags: OpenFlags) -> Option<usize> {
+    let fd = open(format!("{}\0", path).as_str(), flags);
+    if fd < 0 {
+        println!("dd: cannot open {}", path);
+        return None;
+    }
+    Some(fd as usize)
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let Some(args) = parse_args(&argv[..argc]) else {
+        println!(
+            "usage: dd [if=] [of=] [bs=] [count=] [skip=] [seek=] [mode=seq|rand] [conv=fsync]"
+        );
+        return -1;
+    };
+    let input = match args.input.map(|path| open_file(path, OpenFlags::RDONLY)) {
+        Some(None) => return -1,
+        fd => fd.flatten(),
+    };
+    let output = match args
+        .output
+        .map(|path| open_file(path, OpenFlags::CREATE | OpenFlags::WRONLY))
+    {
+        Some(None) => return -1,
+        fd => fd.flatten(),
+    };
+
+    let mut buf = vec![0u8; args.bs];
+    let mut rand = Rand(0x2545_f491_4f6c_dd1d);
+    let stats = block_stats();
+    let start = get_time_ns();
+    let (mut records, mut bytes) = (0, 0);
+    for i in 0..args.count {
+        let block = if args.random {
+            rand.next() as usize % args.count
+        } else {
+            i
+        };
+        let len = match input {
+            Some(fd) => pread(fd, &mut buf, (args.skip + block) * args.bs),
+            None => args.bs as isize,
+        };
+        if len < 0 {
+            println!("dd: read error at block {}", args.skip + block);
+            return -1;
+        }
+        // end of input, a random block past it is just skipped
+        if len == 0 {
+            if args.random {
+                continue;
+            }
+            break;
+        }
+        let len = len as usize;
+        if let Some(fd) = output {
+            if pwrite(fd, &buf[..len], (args.seek + block) * args.bs) != len as isize {
+                println!("dd: write error at block {}", args.seek + block);
+                return -1;
+            }
+        }
+        records += 1;
+        bytes += len;
+    }
+    if let (Some(fd), true) = (output, args.fsync) {
+        fsync(fd);
+    }
+    let ns = (get_time_ns() - start).max(1) as usize;
+    let after = block_stats();
+    for fd in [input, output].into_iter().flatten() {
+        close(fd);
+    }
+
+    let us = ns / 1000;
+    println!(
+        "{} records, {} bytes in {}.{:03} ms, {} KiB/s, {} IOPS",
+        records,
+        bytes,
+        us / 1000,
+        us % 1000,
+        bytes as u64 * 1_000_000_000 / 1024 / ns as u64,
+        records as u64 * 1_000_000_000 / ns as u64
+    );
+    let requests = after.requests - stats.requests;
+    if requests > 0 {
+        println!(
+            "block device: {} requests, {} blocks, {} merged, mean depth {}, mean latency {} us",
+            requests,
+            after.blocks - stats.blocks,
+            after.merged - stats.merged,
+            (after.depth_sum - stats.depth_sum) / requests,
+            (after.latency_us - stats.latency_us) / requests
+        );
+    }
+    0
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{close, exec, open, read, unlink, write, OpenFlags};
+
+const E2BIG: isize = -7;
+const ENOEXEC: isize = -8;
+const EFAULT: isize = -14;
+
+const FILE: &str = "exec_badargs\0";
+
+/// `FILE` holding `data`
+fn create(data: &[u8]) {
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, data), data.len() as isize);
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // argument pointing nowhere
+    let bad_arg = [0x10 as *const u8, core::ptr::null::<u8>()];
+    assert_eq!(exec("hello_world\0", &bad_arg), EFAULT);
+
+    // argv itself in the trampoline page, only its address is ever used here
+    let trampoline = usize::MAX & !0xfff;
+    let bad_argv = unsafe { core::slice::from_raw_parts(trampoline as *const *const u8, 1) };
+    assert_eq!(exec("hello_world\0", bad_argv), EFAULT);
+
+    // more arguments than exec takes
+    let mut too_many: Vec<*const u8> = (0..1000).map(|_| "x\0".as_ptr()).collect();
+    too_many.push(core::ptr::null::<u8>());
+    assert_eq!(exec("hello_world\0", &too_many), E2BIG);
+
+    // no elf at all, then an elf header whose program headers are cut off, this one
+    // carries on either way
+    let argv = [core::ptr::null::<u8>()];
+    create(b"#!/bin/sh\necho hello\n");
+    assert_eq!(exec(FILE, &argv), ENOEXEC);
+    let mut head = [0u8; 64];
+    let fd = open("hello_world\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(read(fd as usize, &mut head), head.len() as isize);
+    close(fd as usize);
+    create(&head);
+    assert_eq!(exec(FILE, &argv), ENOEXEC);
+    assert_eq!(unlink(FILE), 0);
+
+    println!("exec_badargs passed!");
+    0
+}
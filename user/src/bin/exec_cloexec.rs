@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{
+    dup, exec, exit, fcntl, fork, open, pipe2, wait, OpenFlags, FD_CLOEXEC, F_GETFD, F_SETFD,
+};
+
+const PROG: &str = "exec_cloexec\0";
+
+/// run as `exec_cloexec <closed fd> <kept fd>` after exec
+fn check_exec(closed: &str, kept: &str) -> i32 {
+    let closed: usize = closed.parse().unwrap();
+    let kept: usize = kept.parse().unwrap();
+    assert_eq!(fcntl(closed, F_GETFD, 0), -9);
+    assert_eq!(fcntl(kept, F_GETFD, 0), 0);
+    0
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc == 3 {
+        return check_exec(argv[1], argv[2]);
+    }
+
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe2(&mut pipe_fd, OpenFlags::CLOEXEC, 0), 0);
+    let [read_end, write_end] = pipe_fd;
+    assert_eq!(fcntl(read_end, F_GETFD, 0), FD_CLOEXEC as isize);
+    assert_eq!(fcntl(write_end, F_GETFD, 0), FD_CLOEXEC as isize);
+    // a dup isn't close-on-exec
+    let kept = dup(read_end) as usize;
+    assert_eq!(fcntl(kept, F_GETFD, 0), 0);
+    assert_eq!(fcntl(write_end, F_SETFD, 0), 0);
+    assert_eq!(fcntl(write_end, F_GETFD, 0), 0);
+    assert_eq!(fcntl(write_end, F_SETFD, FD_CLOEXEC), 0);
+    let file = open(PROG, OpenFlags::RDONLY | OpenFlags::CLOEXEC);
+    assert!(file > 0);
+    assert_eq!(fcntl(file as usize, F_GETFD, 0), FD_CLOEXEC as isize);
+
+    for closed in [read_end, write_end, file as usize] {
+        let pid = fork();
+        if pid == 0 {
+            // kept across fork
+            assert_eq!(fcntl(closed, F_GETFD, 0), FD_CLOEXEC as isize);
+            let closed = format!("{}\0", closed);
+            let kept = format!("{}\0", kept);
+            let args = [
+                PROG.as_ptr(),
+                closed.as_ptr(),
+                kept.as_ptr(),
+                core::ptr::null::<u8>(),
+            ];
+            exec(PROG, &args);
+            exit(-1);
+        }
+        let mut exit_code = 0;
+        assert_eq!(wait(&mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    println!("exec_cloexec passed!");
+    0
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+//! Read-only data of the program is paged in from its file as it's read rather than at exec,
+//! and a forked child shares what was read so far.
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, free_frames, getrusage, waitpid, RUsage, RUSAGE_SELF};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 64;
+
+/// read-only, in a segment paged in on demand
+static TABLE: [u8; PAGES * PAGE_SIZE] = [0x5a; PAGES * PAGE_SIZE];
+
+fn majflt() -> usize {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    usage.majflt
+}
+
+/// Sum of a byte of each page of the table
+fn touch() -> usize {
+    (0..PAGES)
+        .map(|i| unsafe { (&TABLE[i * PAGE_SIZE] as *const u8).read_volatile() } as usize)
+        .sum()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let frames = free_frames();
+    let faults = majflt();
+    assert_eq!(touch(), PAGES * 0x5a);
+    // each page read from the file on its first touch
+    assert!(majflt() - faults >= PAGES);
+    assert!(frames - free_frames() >= PAGES as isize);
+    // nothing left to page in
+    let faults = majflt();
+    assert_eq!(touch(), PAGES * 0x5a);
+    assert_eq!(majflt(), faults);
+
+    // the child has the pages read already
+    let pid = fork();
+    if pid == 0 {
+        let faults = majflt();
+        assert_eq!(touch(), PAGES * 0x5a);
+        exit(if majflt() == faults { 0 } else { 1 });
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    println!("exec_lazy passed!");
+    0
+}
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exec, exit, fork, mutex_blocking_create, mutex_lock, sleep, thread_create, wait, yield_,
+};
+
+pub fn spinner() -> ! {
+    loop {
+        yield_();
+    }
+}
+
+pub fn sleeper() -> ! {
+    sleep(1000);
+    exit(1)
+}
+
+/// blocks on the mutex `id` the main thread holds
+pub fn locker(id: usize) -> ! {
+    mutex_lock(id);
+    exit(1)
+}
+
+pub fn executor() -> ! {
+    exec("hello_world\0", &[core::ptr::null::<u8>()]);
+    println!("exec failed!");
+    exit(-1)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        thread_create(spinner as usize, 0);
+        thread_create(sleeper as usize, 0);
+        let id = mutex_blocking_create() as usize;
+        mutex_lock(id);
+        thread_create(locker as usize, id);
+        // exec from a thread other than main, siblings must be gone afterwards
+        thread_create(executor as usize, 0);
+        spinner()
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(pid, wait(&mut exit_code));
+    assert_eq!(exit_code, 0);
+    println!("exec_threads passed!");
+    0
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, dup, dup2, dup3, fcntl, flock, fstat, fsync, ftruncate, getdents, getsockopt, lseek,
+    mkdirat, mmap, open, openat, poll, pread, punch_hole, pwrite, read, readv, setsockopt,
+    tcgetpgrp, tcsetpgrp, write, writev, Dirent, MMapFlags, OpenFlags, PollFd, Stat, F_GETFD,
+    F_GETFL, F_SETFL, LOCK_EX, POLLIN, POLLNVAL, SO_RCVBUF,
+};
+
+/// Bad file descriptor
+const EBADF: isize = -9;
+const PAGE_SIZE: usize = 4096;
+const PROT_R: usize = 0b001;
+
+/// fds no process may have open or dup to, as the registers carry them
+const BAD_FDS: [usize; 8] = [
+    usize::MAX,
+    -2isize as usize,
+    isize::MIN as usize,
+    isize::MAX as usize,
+    // -1 of a 32-bit int zero-extended
+    u32::MAX as usize,
+    1 << 40,
+    1 << 20,
+    1024,
+];
+
+/// Every syscall taking an fd gets EBADF for `fd`, and changes nothing
+fn check_bad(fd: usize) {
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, &mut buf), EBADF);
+    assert_eq!(write(fd, b"x"), EBADF);
+    assert_eq!(readv(fd, &mut [&mut buf]), EBADF);
+    assert_eq!(writev(fd, &[b"x"]), EBADF);
+    assert_eq!(pread(fd, &mut buf, 0), EBADF);
+    assert_eq!(pwrite(fd, b"x", 0), EBADF);
+    assert_eq!(lseek(fd, 0, 0), EBADF);
+    assert_eq!(ftruncate(fd, 0), EBADF);
+    assert_eq!(punch_hole(fd, 0, 1), EBADF);
+    assert_eq!(fsync(fd), EBADF);
+    assert_eq!(flock(fd, LOCK_EX), EBADF);
+    assert_eq!(fcntl(fd, F_GETFD, 0), EBADF);
+    assert_eq!(fcntl(fd, F_GETFL, 0), EBADF);
+    assert_eq!(fcntl(fd, F_SETFL, 0), EBADF);
+    assert_eq!(fstat(fd, &mut Stat::default()), EBADF);
+    assert_eq!(getdents(fd, &mut [Dirent::default()]), EBADF);
+    assert_eq!(dup(fd), EBADF);
+    assert_eq!(dup2(fd, fd), EBADF);
+    assert_eq!(dup2(fd, 0), EBADF);
+    assert_eq!(
+        mmap(0, PAGE_SIZE, PROT_R, MMapFlags::MAP_FILE, fd, 0),
+        EBADF
+    );
+    assert_eq!(openat(fd, "badfd\0", OpenFlags::RDONLY), EBADF);
+    assert_eq!(mkdirat(fd, "badfd\0"), EBADF);
+    assert_eq!(tcgetpgrp(fd), EBADF);
+    assert_eq!(tcsetpgrp(fd, 0), EBADF);
+    assert_eq!(setsockopt(fd, SO_RCVBUF, 0), EBADF);
+    assert_eq!(getsockopt(fd, SO_RCVBUF), EBADF);
+    assert_eq!(close(fd), EBADF);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for fd in BAD_FDS {
+        check_bad(fd);
+        assert_eq!(dup2(0, fd), EBADF);
+        assert_eq!(dup3(0, fd, OpenFlags::CLOEXEC), EBADF);
+    }
+    // one closed within the fd table
+    let fd = open("/\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(close(fd), 0);
+    check_bad(fd);
+    // stdin is still there
+    assert_eq!(fcntl(0, F_GETFD, 0), 0);
+
+    // a negative one is skipped, a bad one is POLLNVAL
+    let mut fds = [
+        PollFd {
+            fd: -1,
+            events: POLLIN,
+            revents: 0,
+        },
+        PollFd {
+            fd: i32::MAX,
+            events: POLLIN,
+            revents: 0,
+        },
+        PollFd::new(fd, POLLIN),
+    ];
+    assert_eq!(poll(&mut fds, Some(0)), 2);
+    assert_eq!(fds[0].revents, 0);
+    assert_eq!(fds[1].revents, POLLNVAL);
+    assert_eq!(fds[2].revents, POLLNVAL);
+
+    println!("filetest_badfd passed!");
+    0
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, getcwd, mkdir, open, rename, rmdir, OpenFlags};
+
+const ENOENT: isize = -2;
+const ERANGE: isize = -34;
+
+fn assert_cwd(expected: &str) {
+    let mut path = [0u8; 64];
+    assert_eq!(getcwd(&mut path), 0);
+    let len = path.iter().position(|&b| b == 0).unwrap();
+    assert_eq!(core::str::from_utf8(&path[..len]).unwrap(), expected);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(chdir("/\0"), 0);
+    assert_cwd("/");
+    assert_eq!(mkdir("cwd_a/b/c\0"), 0);
+
+    // walked a name at a time
+    assert_eq!(chdir("cwd_a/./b/c\0"), 0);
+    assert_cwd("/cwd_a/b/c");
+    assert_eq!(chdir("..\0"), 0);
+    assert_cwd("/cwd_a/b");
+    assert_eq!(chdir("../../cwd_a/b/c/..\0"), 0);
+    assert_cwd("/cwd_a/b");
+    // however it's spelled
+    assert_eq!(chdir("/../cwd_a//b/c/./..//\0"), 0);
+    assert_cwd("/cwd_a/b");
+    assert_eq!(chdir("/cwd_a\0"), 0);
+    assert_cwd("/cwd_a");
+    assert_eq!(chdir("nowhere\0"), -1);
+    assert_cwd("/cwd_a");
+    let mut short = [0u8; 4];
+    assert_eq!(getcwd(&mut short), ERANGE);
+
+    // moved along with a dir above it
+    assert_eq!(chdir("b\0"), 0);
+    assert_eq!(rename("/cwd_a\0", "/cwd_z\0"), 0);
+    assert_cwd("/cwd_z/b");
+    assert_eq!(chdir("c\0"), 0);
+    assert_cwd("/cwd_z/b/c");
+
+    // removed from under it
+    assert_eq!(rmdir("/cwd_z/b/c\0"), 0);
+    let mut path = [0u8; 64];
+    assert_eq!(getcwd(&mut path), ENOENT);
+    assert!(open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY) < 0);
+    assert_eq!(chdir("..\0"), -1);
+    // but it may go somewhere else by absolute path
+    assert_eq!(chdir("/cwd_z\0"), 0);
+    assert_cwd("/cwd_z");
+
+    assert_eq!(rmdir("b\0"), 0);
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(rmdir("cwd_z\0"), 0);
+    println!("filetest_cwd passed!");
+    0
+}
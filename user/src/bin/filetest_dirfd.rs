@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chdir, close, fchdir, getcwd, linkat, mkdir, mkdirat, open, openat, read, renameat, rmdir,
+    unlinkat, write, OpenFlags,
+};
+
+const ENOENT: isize = -2;
+const ENOTDIR: isize = -20;
+
+fn assert_cwd(expected: &str) {
+    let mut path = [0u8; 64];
+    assert_eq!(getcwd(&mut path), 0);
+    let len = path.iter().position(|&b| b == 0).unwrap();
+    assert_eq!(core::str::from_utf8(&path[..len]).unwrap(), expected);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(mkdir("dirfd_a/b\0"), 0);
+    assert_eq!(mkdir("dirfd_c\0"), 0);
+    let fd = open("dirfd_a/b\0", OpenFlags::RDRW);
+    assert!(fd > 0);
+    let fd = fd as usize;
+
+    // everything relative goes from the dir, not cwd
+    let file = openat(fd, "f\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(file > 0);
+    assert_eq!(write(file as usize, b"dirfd"), 5);
+    close(file as usize);
+    assert_eq!(mkdirat(fd, "d/e\0"), 0);
+    assert_eq!(linkat(fd, "f\0", "d/g\0"), 0);
+    assert_eq!(renameat(fd, "d/g\0", "../h\0"), 0);
+    let file = open("/dirfd_a/h\0", OpenFlags::RDONLY);
+    assert!(file > 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(file as usize, &mut buf), 5);
+    assert_eq!(&buf[..5], b"dirfd");
+    close(file as usize);
+
+    // .. goes up from it, as far as root
+    let file = openat(
+        fd,
+        "../../dirfd_c/x\0",
+        OpenFlags::CREATE | OpenFlags::WRONLY,
+    );
+    assert!(file > 0);
+    close(file as usize);
+    assert_eq!(unlinkat(fd, "../../dirfd_c/x\0", false), 0);
+    assert_eq!(openat(fd, "../../dirfd_c/x\0", OpenFlags::RDONLY), ENOENT);
+    // absolute paths don't care
+    assert_eq!(unlinkat(fd, "/dirfd_a/h\0", false), 0);
+
+    // a file is no dir to go from
+    let file = openat(fd, "f\0", OpenFlags::RDONLY);
+    assert!(file > 0);
+    assert_eq!(openat(file as usize, "f\0", OpenFlags::RDONLY), ENOTDIR);
+    assert_eq!(fchdir(file as usize), ENOTDIR);
+    close(file as usize);
+
+    // cwd is where the fd is
+    assert_eq!(fchdir(fd), 0);
+    assert_cwd("/dirfd_a/b");
+    assert_eq!(unlinkat(fd, "f\0", false), 0);
+    assert_eq!(unlinkat(fd, "d/e\0", true), 0);
+    assert_eq!(unlinkat(fd, "d\0", true), 0);
+    close(fd);
+
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(rmdir("dirfd_a/b\0"), 0);
+    assert_eq!(rmdir("dirfd_a\0"), 0);
+    assert_eq!(rmdir("dirfd_c\0"), 0);
+    println!("filetest_dirfd passed!");
+    0
+}
@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dup2, open, pipe, read, unlink, write, OpenFlags};
+
+const FILE: &str = "filetest_dup2\0";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let [read_end, write_end] = pipe_fd;
+
+    // beyond the fds open so far
+    assert_eq!(dup2(write_end, 10), 10);
+    assert_eq!(write(10, b"dup"), 3);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(read_end, &mut buf), 3);
+    assert_eq!(&buf[..3], b"dup");
+
+    // onto an open fd, which is closed first: once the other write end is gone, it's EOF
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(dup2(fd, 10), 10);
+    close(write_end);
+    assert_eq!(read(read_end, &mut buf), 0);
+    assert_eq!(write(10, b"file"), 4);
+    close(10);
+    close(fd);
+
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buf), 4);
+    assert_eq!(&buf[..4], b"file");
+    assert_eq!(dup2(fd, fd), fd as isize);
+    close(fd);
+    assert_eq!(dup2(fd, fd), -9);
+    assert_eq!(dup2(fd, 10), -9);
+    close(read_end);
+
+    unlink(FILE);
+    println!("filetest_dup2 passed!");
+    0
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, flock, fork, lseek, open, read, sleep, unlink, waitpid, write, OpenFlags, LOCK_EX,
+    LOCK_NB, LOCK_SH, LOCK_UN, SEEK_END, SEEK_SET,
+};
+
+const FILE: &str = "filetest_flock\0";
+const EAGAIN: isize = -11;
+
+fn open_file() -> usize {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    fd as usize
+}
+
+/// append `bytes` a byte at a time, giving others the chance to cut in
+fn append(fd: usize, bytes: &[u8]) {
+    for b in bytes {
+        lseek(fd, 0, SEEK_END);
+        write(fd, core::slice::from_ref(b));
+        sleep(1);
+    }
+}
+
+fn wait_ok(pid: isize) {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // shared locks get along, an exclusive one keeps everyone else out
+    let a = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW | OpenFlags::TRUNC);
+    assert!(a > 0);
+    let a = a as usize;
+    let b = open_file();
+    assert_eq!(flock(a, LOCK_SH), 0);
+    assert_eq!(flock(b, LOCK_SH | LOCK_NB), 0);
+    assert_eq!(flock(a, LOCK_EX | LOCK_NB), EAGAIN);
+    assert_eq!(flock(b, LOCK_UN), 0);
+    assert_eq!(flock(a, LOCK_EX | LOCK_NB), 0);
+    assert_eq!(flock(b, LOCK_SH | LOCK_NB), EAGAIN);
+    assert_eq!(flock(a, 0), -22);
+
+    // writers across processes take turns
+    let pid = fork();
+    if pid == 0 {
+        let fd = open_file();
+        assert_eq!(flock(fd, LOCK_EX | LOCK_NB), EAGAIN);
+        // waits for the parent to be done
+        assert_eq!(flock(fd, LOCK_EX), 0);
+        append(fd, b"child");
+        close(fd);
+        exit(0);
+    }
+    sleep(10);
+    append(a, b"parent");
+    // closing releases
+    close(a);
+    wait_ok(pid);
+    let mut buf = [0u8; 16];
+    lseek(b, 0, SEEK_SET);
+    assert_eq!(read(b, &mut buf), 11);
+    assert_eq!(&buf[..11], b"parentchild");
+
+    // so does exiting
+    let pid = fork();
+    if pid == 0 {
+        let fd = open_file();
+        assert_eq!(flock(fd, LOCK_EX), 0);
+        exit(0);
+    }
+    wait_ok(pid);
+    assert_eq!(flock(b, LOCK_EX | LOCK_NB), 0);
+    close(b);
+
+    unlink(FILE);
+    println!("filetest_flock passed!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fsync, open, pipe, read, unlink, write, OpenFlags};
+
+const FILE: &str = "filetest_fsync\0";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let data = [b'f'; 1500];
+    assert_eq!(write(fd, &data), data.len() as isize);
+    assert_eq!(fsync(fd), 0);
+    close(fd);
+
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 2048];
+    assert_eq!(read(fd, &mut buf), data.len() as isize);
+    assert_eq!(&buf[..data.len()], &data);
+    close(fd);
+
+    // nothing to write back
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(fsync(pipe_fd[0]), -22);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    assert_eq!(fsync(fd), -9);
+
+    unlink(FILE);
+    println!("filetest_fsync passed!");
+    0
+}
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use user_lib::{
+    close, fstat, getdents, mkdir, open, rewinddir, rmdir, syscall::syscall, unlink, Dirent,
+    FileType, OpenFlags, Stat,
+};
+
+const FILES: usize = 20;
+/// files unlinked while the dir is read, one read already and one not yet
+const REMOVED: [usize; 2] = [2, 10];
+const SYSCALL_GETDENTS: usize = 61;
+/// of an entry before it had `ino`
+const DIRENT_V1_SIZE: usize = 32;
+
+/// Names of all entries of dir `fd` from where it's at, a few at a time
+fn read_all(fd: usize) -> Vec<String> {
+    let mut entries = vec![Dirent::default(); 3];
+    let mut names = Vec::new();
+    let mut last_offset = 0;
+    loop {
+        let n = getdents(fd, &mut entries);
+        assert!(n >= 0);
+        if n == 0 {
+            break;
+        }
+        for entry in &entries[..n as usize] {
+            assert!(entry.next_offset > last_offset);
+            last_offset = entry.next_offset;
+            names.push(String::from(entry.name()));
+        }
+    }
+    names
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("getdents_d\0"), 0);
+    assert_eq!(mkdir("getdents_d/sub\0"), 0);
+    for i in 0..FILES {
+        let fd = open(
+            format!("getdents_d/f{}\0", i).as_str(),
+            OpenFlags::CREATE | OpenFlags::WRONLY,
+        );
+        assert!(fd > 0);
+        close(fd as usize);
+    }
+
+    let fd = open("getdents_d\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let names = read_all(fd);
+    // ".", "..", "sub" and the files, each once
+    assert_eq!(names.len(), FILES + 3);
+    for i in 0..FILES {
+        let name = format!("f{}", i);
+        assert_eq!(names.iter().filter(|n| **n == name).count(), 1);
+    }
+    // at the end it stays there
+    assert!(read_all(fd).is_empty());
+
+    // and starts over once rewound
+    assert_eq!(rewinddir(fd), 0);
+    assert_eq!(read_all(fd), names);
+
+    let mut entry = [Dirent::default()];
+    assert_eq!(rewinddir(fd), 0);
+    while getdents(fd, &mut entry) == 1 {
+        let expected = if entry[0].name().starts_with('f') {
+            FileType::REG
+        } else {
+            FileType::DIR
+        };
+        assert!(entry[0].ftype == expected);
+        // the inode the name links to
+        let path = format!("getdents_d/{}\0", entry[0].name());
+        let file = open(path.as_str(), OpenFlags::RDONLY);
+        assert!(file > 0);
+        let mut stat = Stat::default();
+        assert_eq!(fstat(file as usize, &mut stat), 0);
+        assert_eq!(entry[0].ino, stat.ino);
+        close(file as usize);
+    }
+
+    // callers of the first version pass no size, and get entries that small
+    let mut v1 = [0xffu8; 3 * DIRENT_V1_SIZE];
+    assert_eq!(rewinddir(fd), 0);
+    assert_eq!(
+        syscall(SYSCALL_GETDENTS, [fd, v1.as_mut_ptr() as usize, 2]),
+        2
+    );
+    assert_eq!(&v1[1..3], b".\0");
+    assert_eq!(&v1[DIRENT_V1_SIZE + 1..DIRENT_V1_SIZE + 4], b"..\0");
+    assert!(v1[2 * DIRENT_V1_SIZE..].iter().all(|&b| b == 0xff));
+    // sizes neither version has are refused
+    let bad = 2 | (48 << 32);
+    assert_eq!(
+        syscall(SYSCALL_GETDENTS, [fd, v1.as_mut_ptr() as usize, bad]),
+        -22
+    );
+
+    // removed and created halfway through, the others are still read once
+    assert_eq!(rewinddir(fd), 0);
+    let mut entries = vec![Dirent::default(); 9];
+    assert_eq!(getdents(fd, &mut entries), 9);
+    let mut names: Vec<String> = entries.iter().map(|e| String::from(e.name())).collect();
+    for i in REMOVED {
+        assert_eq!(unlink(format!("getdents_d/f{}\0", i).as_str()), 0);
+    }
+    let new = open("getdents_d/g0\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(new > 0);
+    close(new as usize);
+    names.extend(read_all(fd));
+    for i in (0..FILES).filter(|i| !REMOVED.contains(i)) {
+        let name = format!("f{}", i);
+        assert_eq!(names.iter().filter(|n| **n == name).count(), 1);
+    }
+    close(fd);
+
+    for i in (0..FILES).filter(|i| !REMOVED.contains(i)) {
+        assert_eq!(unlink(format!("getdents_d/f{}\0", i).as_str()), 0);
+    }
+    assert_eq!(unlink("getdents_d/g0\0"), 0);
+    assert_eq!(rmdir("getdents_d/sub\0"), 0);
+    assert_eq!(rmdir("getdents_d\0"), 0);
+    println!("filetest_getdents passed!");
+    0
+}
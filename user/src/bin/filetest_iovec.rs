@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, pipe, read, readv, unlink, writev, OpenFlags};
+
+const FILE: &str = "filetest_iovec\0";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // empty ones are skipped
+    assert_eq!(writev(fd, &[b"hello", b"", b", ", b"world"]), 12);
+    assert_eq!(writev(fd, &[]), 0);
+    close(fd);
+
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    let (mut a, mut b, mut c) = ([0u8; 3], [0u8; 4], [0u8; 8]);
+    assert_eq!(readv(fd, &mut [&mut a, &mut b, &mut c]), 12);
+    assert_eq!(&a, b"hel");
+    assert_eq!(&b, b"lo, ");
+    assert_eq!(&c[..5], b"world");
+    // nothing written through a fd opened read-only
+    assert_eq!(writev(fd, &[b"x"]), -1);
+    close(fd);
+    assert_eq!(unlink(FILE), 0);
+
+    // a pipe gets them as one write
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(writev(pipe_fd[1], &[b"ab", b"cd"]), 4);
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(pipe_fd[0], &mut buf), 4);
+    assert_eq!(&buf[..4], b"abcd");
+    close(pipe_fd[0]);
+    println!("filetest_iovec passed!");
+    0
+}
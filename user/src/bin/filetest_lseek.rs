@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, pipe, read, unlink, write, OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+const EINVAL: isize = -22;
+const ESPIPE: isize = -29;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let path = "files\0";
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, b"0123456789");
+
+    let mut buf = [0u8; 4];
+    assert_eq!(lseek(fd, 2, SEEK_SET), 2);
+    assert_eq!(read(fd, &mut buf), 4);
+    assert_eq!(&buf, b"2345");
+    assert_eq!(lseek(fd, -3, SEEK_CUR), 3);
+    assert_eq!(read(fd, &mut buf[..2]), 2);
+    assert_eq!(&buf[..2], b"34");
+    assert_eq!(lseek(fd, -1, SEEK_END), 9);
+    assert_eq!(read(fd, &mut buf), 1);
+    assert_eq!(buf[0], b'9');
+
+    // overwrite in the middle, and write past the end leaving a hole of zeros
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    write(fd, b"ab");
+    assert_eq!(lseek(fd, 12, SEEK_SET), 12);
+    write(fd, b"z");
+    assert_eq!(lseek(fd, 0, SEEK_END), 13);
+    let mut all = [0xffu8; 16];
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(read(fd, &mut all), 13);
+    assert_eq!(&all[..13], b"ab23456789\0\0z");
+
+    assert_eq!(lseek(fd, -1, SEEK_SET), EINVAL);
+    assert_eq!(lseek(fd, 0, 3), EINVAL);
+    close(fd);
+    assert_eq!(unlink(path), 0);
+
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), ESPIPE);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    println!("filetest_lseek passed!");
+    0
+}
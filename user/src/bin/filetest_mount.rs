@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chdir, close, fstat, getcwd, link, mkdir, mount, open, read, rename, rmdir, umount, unlink,
+    write, OpenFlags, Stat,
+};
+
+/// No such file or directory
+const ENOENT: isize = -2;
+/// Device or resource busy
+const EBUSY: isize = -16;
+/// Cross-device link
+const EXDEV: isize = -18;
+/// Invalid argument
+const EINVAL: isize = -22;
+
+fn assert_cwd(expected: &str) {
+    let mut path = [0u8; 64];
+    assert_eq!(getcwd(&mut path), 0);
+    let len = path.iter().position(|&b| b == 0).unwrap();
+    assert_eq!(core::str::from_utf8(&path[..len]).unwrap(), expected);
+}
+
+fn dev_of(fd: isize) -> u64 {
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd as usize, &mut stat), 0);
+    stat.dev
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("/mount_d\0"), 0);
+    match mount("/dev/vdb\0", "/mount_d\0") {
+        ENOENT => {
+            println!("filetest_mount skipped, no second disk");
+            assert_eq!(rmdir("/mount_d\0"), 0);
+            return 0;
+        }
+        ret => assert_eq!(ret, 0),
+    }
+    assert_eq!(mount("/dev/vdb\0", "/mount_d\0"), EBUSY);
+
+    // paths go on in the filesystem mounted, and `..` gets out of it
+    let fd = open("/mount_d/f\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"mounted"), 7);
+    let root = open("/\0", OpenFlags::RDONLY);
+    assert!(dev_of(fd) != dev_of(root));
+    close(root as usize);
+    assert_eq!(chdir("/mount_d\0"), 0);
+    assert_cwd("/mount_d");
+    assert_eq!(chdir("..\0"), 0);
+    assert_cwd("/");
+
+    // nothing links or moves across, and the dir mounted on stays
+    assert_eq!(link("/mount_d/f\0", "/mount_f\0"), EXDEV);
+    assert_eq!(rename("/mount_d/f\0", "/mount_f\0"), EXDEV);
+    assert_eq!(rmdir("/mount_d\0"), EBUSY);
+    assert_eq!(rename("/mount_d\0", "/mount_e\0"), EBUSY);
+
+    // busy while open or a cwd
+    assert_eq!(umount("/mount_d\0"), EBUSY);
+    close(fd as usize);
+    assert_eq!(chdir("/mount_d\0"), 0);
+    assert_eq!(umount("/mount_d\0"), EBUSY);
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(umount("/mount_d\0"), 0);
+    assert_eq!(umount("/mount_d\0"), EINVAL);
+    assert!(open("/mount_d/f\0", OpenFlags::RDONLY) < 0);
+
+    // what was written is there once mounted again
+    assert_eq!(mount("/dev/vdb\0", "/mount_d\0"), 0);
+    let fd = open("/mount_d/f\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd as usize, &mut buf), 7);
+    assert_eq!(&buf[..7], b"mounted");
+    close(fd as usize);
+    assert_eq!(unlink("/mount_d/f\0"), 0);
+    assert_eq!(umount("/mount_d\0"), 0);
+
+    assert_eq!(rmdir("/mount_d\0"), 0);
+    println!("filetest_mount passed!");
+    0
+}
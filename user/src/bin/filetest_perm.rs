@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chmod, chown, close, exit, fork, fstat, getgid, getuid, mkdir, open, rmdir, setgid, setuid,
+    unlink, waitpid, OpenFlags, Stat, StatMode,
+};
+
+const PRIVATE: &str = "perm_private\0";
+const SHARED: &str = "perm_shared\0";
+const DIR: &str = "perm_dir\0";
+
+fn create(path: &str) {
+    let fd = open(
+        path,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    close(fd as usize);
+}
+
+fn can_open(path: &str, flags: OpenFlags) -> bool {
+    let fd = open(path, flags);
+    if fd < 0 {
+        return false;
+    }
+    close(fd as usize);
+    true
+}
+
+fn stat_of(path: &str) -> Stat {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd as usize, &mut stat), 0);
+    close(fd as usize);
+    stat
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // root sets things up, and is let into everything
+    assert_eq!((getuid(), getgid()), (0, 0));
+    create(PRIVATE);
+    create(SHARED);
+    assert_eq!(mkdir(DIR), 0);
+    let stat = stat_of(PRIVATE);
+    assert_eq!(
+        stat.mode,
+        StatMode::FILE | StatMode::from_bits_truncate(0o644)
+    );
+    assert_eq!(chmod(PRIVATE, 0o600), 0);
+    assert_eq!(chown(SHARED, Some(1000), None), 0);
+    let stat = stat_of(SHARED);
+    assert_eq!((stat.uid, stat.gid), (1000, 0));
+    assert_eq!(stat_of(PRIVATE).mode.bits() & 0o7777, 0o600);
+
+    let pid = fork();
+    if pid == 0 {
+        // group first, there's no changing it once not root
+        assert_eq!(setgid(100), 0);
+        assert_eq!(setuid(1000), 0);
+        assert_eq!((getuid(), getgid()), (1000, 100));
+        assert_eq!(setuid(0), -1);
+
+        assert!(!can_open(PRIVATE, OpenFlags::RDONLY));
+        assert!(can_open(SHARED, OpenFlags::RDRW));
+        // owner only
+        assert_eq!(chmod(PRIVATE, 0o666), -1);
+        assert_eq!(chmod(SHARED, 0o400), 0);
+        assert!(!can_open(SHARED, OpenFlags::WRONLY));
+        assert!(!can_open(SHARED, OpenFlags::RDONLY | OpenFlags::TRUNC));
+        assert!(can_open(SHARED, OpenFlags::RDONLY));
+        // root only
+        assert_eq!(chown(SHARED, Some(0), None), -1);
+
+        // dirs of root aren't writable by others
+        assert!(!can_open(
+            "perm_dir/f\0",
+            OpenFlags::CREATE | OpenFlags::WRONLY
+        ));
+        assert_eq!(mkdir("perm_dir/d\0"), -1);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // given to 1000, a file it creates there is its own
+    assert_eq!(chown(DIR, Some(1000), Some(100)), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(setgid(100), 0);
+        assert_eq!(setuid(1000), 0);
+        create("perm_dir/f\0");
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    let stat = stat_of("perm_dir/f\0");
+    assert_eq!((stat.uid, stat.gid), (1000, 100));
+
+    assert_eq!(unlink("perm_dir/f\0"), 0);
+    assert_eq!(rmdir(DIR), 0);
+    assert_eq!(unlink(PRIVATE), 0);
+    assert_eq!(unlink(SHARED), 0);
+    println!("filetest_perm passed!");
+    0
+}
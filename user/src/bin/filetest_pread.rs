@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, lseek, open, pipe, pread, pwrite, thread_create, unlink, waittid, write,
+    OpenFlags, SEEK_CUR,
+};
+
+const ESPIPE: isize = -29;
+const FILE: &str = "filetest_pread\0";
+const CONTENT: &[u8] = b"0123456789abcdefghij";
+/// reads per thread
+const ROUNDS: usize = 200;
+
+/// Read its own half of the file over and over, while another thread reads the other half
+fn reader(fd: usize, half: usize) -> ! {
+    let offset = half * CONTENT.len() / 2;
+    let mut buf = [0u8; CONTENT.len() / 2];
+    for _ in 0..ROUNDS {
+        assert_eq!(pread(fd, &mut buf, offset), buf.len() as isize);
+        assert_eq!(&buf, &CONTENT[offset..offset + buf.len()]);
+    }
+    exit(0)
+}
+
+fn reader0(fd: usize) -> ! {
+    reader(fd, 0)
+}
+
+fn reader1(fd: usize) -> ! {
+    reader(fd, 1)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, CONTENT), CONTENT.len() as isize);
+
+    // neither moves the offset, still at the end
+    let mut buf = [0u8; 4];
+    assert_eq!(pread(fd, &mut buf, 2), 4);
+    assert_eq!(&buf, b"2345");
+    assert_eq!(pwrite(fd, b"XY", 4), 2);
+    assert_eq!(pread(fd, &mut buf, 2), 4);
+    assert_eq!(&buf, b"23XY");
+    assert_eq!(pread(fd, &mut buf, CONTENT.len() - 1), 1);
+    assert_eq!(pread(fd, &mut buf, CONTENT.len()), 0);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), CONTENT.len() as isize);
+    assert_eq!(pwrite(fd, b"45", 4), 2);
+
+    // threads sharing the fd don't race for its offset
+    let tids = [
+        thread_create(reader0 as usize, fd),
+        thread_create(reader1 as usize, fd),
+    ];
+    for tid in tids {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    close(fd);
+
+    // only through a fd open for writing
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    assert_eq!(pwrite(fd, b"X", 0), -1);
+    close(fd);
+    assert_eq!(unlink(FILE), 0);
+
+    // nor on what can't seek
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(pwrite(pipe_fd[1], b"X", 0), ESPIPE);
+    assert_eq!(pread(pipe_fd[0], &mut buf, 0), ESPIPE);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("filetest_pread passed!");
+    0
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chown, close, fallocate, getquota, lseek, mmap, msync, munmap, open, pipe, pread, punch_hole,
+    pwrite, setquota, unlink, MMapFlags, MSyncFlags, OpenFlags, Quota, FALLOC_FL_PUNCH_HOLE,
+    SEEK_END,
+};
+
+const FILE: &str = "filetest_punch\0";
+const PAGE_SIZE: usize = 4096;
+const BLOCK_SZ: usize = 512;
+const LEN: usize = 4 * PAGE_SIZE;
+/// rw
+const PROT: usize = 0b011;
+/// not open for writing
+const EPERM: isize = -1;
+const EINVAL: isize = -22;
+const ESPIPE: isize = -29;
+const EOPNOTSUPP: isize = -95;
+
+fn byte_at(fd: usize, offset: usize) -> u8 {
+    let mut buf = [0xffu8; 1];
+    assert_eq!(pread(fd, &mut buf, offset), 1);
+    buf[0]
+}
+
+/// Blocks held by uid 1000, the owner of the file, as its quota counts them
+fn blocks() -> u32 {
+    let mut quota = Quota::default();
+    assert_eq!(getquota(FILE, 1000, &mut quota), 0);
+    quota.blocks
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(pwrite(fd, &[b'a'; LEN], 0), LEN as isize);
+    assert_eq!(chown(FILE, Some(1000), None), 0);
+    let loose = Quota {
+        block_hard: 1 << 20,
+        ..Quota::default()
+    };
+    assert_eq!(setquota(FILE, 1000, &loose), 0);
+    let full = blocks();
+
+    // a mapping made before keeps what it read of the hole
+    let old = mmap(0, LEN, PROT, MMapFlags::MAP_FILE, fd, 0);
+    assert!(old > 0);
+    let old = old as usize;
+    assert_eq!(
+        unsafe { ((old + PAGE_SIZE) as *const u8).read_volatile() },
+        b'a'
+    );
+
+    assert_eq!(fallocate(fd, FALLOC_FL_PUNCH_HOLE, 0, 1), EOPNOTSUPP);
+    assert_eq!(fallocate(fd, 0, 0, 1), EOPNOTSUPP);
+    assert_eq!(punch_hole(fd, 0, 0), EINVAL);
+
+    // page 1 and a bit each side: the blocks all in it go, the edges are zeroed
+    let (start, end) = (PAGE_SIZE - 100, 2 * PAGE_SIZE + 100);
+    assert_eq!(punch_hole(fd, start, end - start), 0);
+    assert_eq!(
+        blocks(),
+        full - (end / BLOCK_SZ - start.div_ceil(BLOCK_SZ)) as u32
+    );
+    assert_eq!(lseek(fd, 0, SEEK_END), LEN as isize);
+    assert_eq!(byte_at(fd, start - 1), b'a');
+    assert_eq!(byte_at(fd, start), 0);
+    assert_eq!(byte_at(fd, end - 1), 0);
+    assert_eq!(byte_at(fd, end), b'a');
+
+    let page = |base: usize, i: usize| (base + i * PAGE_SIZE) as *mut u8;
+    assert_eq!(unsafe { page(old, 1).read_volatile() }, b'a');
+    assert_eq!(
+        msync(page(old, 1) as usize, PAGE_SIZE, MSyncFlags::MS_INVALIDATE),
+        0
+    );
+    assert_eq!(unsafe { page(old, 1).read_volatile() }, 0);
+    assert_eq!(munmap(old, LEN), 0);
+
+    // a fresh mapping reads zeros, and a page stored to fills the hole under it
+    let new = mmap(0, LEN, PROT, MMapFlags::MAP_FILE, fd, 0);
+    assert!(new > 0);
+    let new = new as usize;
+    assert_eq!(
+        unsafe { page(new, 1).add(PAGE_SIZE - 1).read_volatile() },
+        0
+    );
+    assert_eq!(
+        unsafe { page(new, 0).add(PAGE_SIZE - 1).read_volatile() },
+        0
+    );
+    unsafe { page(new, 1).add(10).write_volatile(b'm') };
+    assert_eq!(
+        msync(page(new, 1) as usize, PAGE_SIZE, MSyncFlags::MS_SYNC),
+        1
+    );
+    assert_eq!(munmap(new, LEN), 0);
+    assert_eq!(blocks(), full);
+    assert_eq!(byte_at(fd, PAGE_SIZE + 10), b'm');
+    assert_eq!(byte_at(fd, PAGE_SIZE + 11), 0);
+
+    // to the end and past it, the size still stays
+    assert_eq!(punch_hole(fd, 3 * PAGE_SIZE, isize::MAX as usize), 0);
+    assert_eq!(blocks(), full - (PAGE_SIZE / BLOCK_SZ) as u32);
+    assert_eq!(lseek(fd, 0, SEEK_END), LEN as isize);
+    assert_eq!(byte_at(fd, LEN - 1), 0);
+    assert_eq!(punch_hole(fd, 3 * PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(blocks(), full - (PAGE_SIZE / BLOCK_SZ) as u32);
+
+    let rd = open(FILE, OpenFlags::RDONLY);
+    assert!(rd > 0);
+    assert_eq!(punch_hole(rd as usize, 0, 1), EPERM);
+    close(rd as usize);
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(punch_hole(pipe_fd[1], 0, 1), ESPIPE);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    close(fd);
+    assert_eq!(setquota(FILE, 1000, &Quota::default()), 0);
+    assert_eq!(unlink(FILE), 0);
+    println!("filetest_punch passed!");
+    0
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chown, close, exit, fork, ftruncate, getquota, lseek, mkdir, open, rmdir, setgid, setquota,
+    setuid, unlink, waitpid, write, OpenFlags, Quota, SEEK_END,
+};
+
+const DIR: &str = "quota_dir\0";
+const BLOCK_SZ: usize = 512;
+
+/// Operation not permitted
+const EPERM: isize = -1;
+/// No such process, no quota here
+const ESRCH: isize = -3;
+/// Disk quota exceeded
+const EDQUOT: isize = -122;
+
+fn quota_of(uid: u16) -> Quota {
+    let mut quota = Quota::default();
+    assert_eq!(getquota(DIR, uid, &mut quota), 0);
+    quota
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir(DIR), 0);
+    assert_eq!(chown(DIR, Some(1000), Some(100)), 0);
+    let mut quota = Quota::default();
+    assert_eq!(getquota(DIR, 1000, &mut quota), ESRCH);
+
+    // counted from what it has when given limits, the dir at least
+    let loose = Quota {
+        block_hard: 1 << 20,
+        ..Quota::default()
+    };
+    assert_eq!(setquota(DIR, 1000, &loose), 0);
+    let base = quota_of(1000);
+    assert!(base.blocks >= 1 && base.inodes >= 1);
+    let limits = Quota {
+        block_hard: base.blocks + 3,
+        inode_soft: base.inodes + 1,
+        inode_hard: base.inodes + 2,
+        ..Quota::default()
+    };
+    assert_eq!(setquota(DIR, 1000, &limits), 0);
+
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(setgid(100), 0);
+        assert_eq!(setuid(1000), 0);
+        // its own quota only, and no setting it
+        assert_eq!(quota_of(1000).block_hard, base.blocks + 3);
+        assert_eq!(getquota(DIR, 0, &mut quota), EPERM);
+        assert_eq!(setquota(DIR, 1000, &loose), EPERM);
+
+        // past the soft limit for a grace, never past the hard one
+        let fd = open("quota_dir/a\0", OpenFlags::CREATE | OpenFlags::RDWR);
+        assert!(fd > 0);
+        let b = open("quota_dir/b\0", OpenFlags::CREATE | OpenFlags::RDWR);
+        assert!(b > 0);
+        close(b as usize);
+        assert_ne!(quota_of(1000).inode_grace, 0);
+        assert_eq!(
+            open("quota_dir/c\0", OpenFlags::CREATE | OpenFlags::RDWR),
+            EDQUOT
+        );
+        assert_eq!(mkdir("quota_dir/d\0"), EDQUOT);
+
+        let fd = fd as usize;
+        assert_eq!(ftruncate(fd, 3 * BLOCK_SZ), 0);
+        assert_eq!(ftruncate(fd, 3 * BLOCK_SZ + 1), EDQUOT);
+        lseek(fd, 0, SEEK_END);
+        assert_eq!(write(fd, b"x"), 0);
+        close(fd);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    let quota = quota_of(1000);
+    assert_eq!(
+        (quota.blocks, quota.inodes),
+        (base.blocks + 3, base.inodes + 2)
+    );
+
+    // root isn't held to it, and what's handed over goes with it
+    let fd = open("quota_dir/r\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, &[0u8; BLOCK_SZ]), BLOCK_SZ as isize);
+    close(fd as usize);
+    assert_eq!(quota_of(1000).blocks, base.blocks + 3);
+    assert_eq!(chown("quota_dir/r\0", Some(1000), None), 0);
+    let quota = quota_of(1000);
+    assert_eq!(
+        (quota.blocks, quota.inodes),
+        (base.blocks + 4, base.inodes + 3)
+    );
+
+    for file in ["quota_dir/a\0", "quota_dir/b\0", "quota_dir/r\0"] {
+        assert_eq!(unlink(file), 0);
+    }
+    let quota = quota_of(1000);
+    assert_eq!((quota.blocks, quota.inodes), (base.blocks, base.inodes));
+    assert_eq!(rmdir(DIR), 0);
+    // none left to tell which filesystem, the cwd is the same one
+    assert_eq!(setquota(".\0", 1000, &Quota::default()), 0);
+    assert_eq!(getquota(".\0", 1000, &mut Quota::default()), ESRCH);
+    println!("filetest_quota passed!");
+    0
+}
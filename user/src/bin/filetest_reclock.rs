@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fcntl, fork, getpid, open, sleep, unlink, waitpid, Flock, OpenFlags, F_GETLK,
+    F_RDLCK, F_SETLK, F_SETLKW, F_UNLCK, F_WRLCK,
+};
+
+const FILE: &str = "filetest_reclock\0";
+const EAGAIN: isize = -11;
+const EDEADLK: isize = -35;
+
+fn open_file() -> usize {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    fd as usize
+}
+
+fn lock(fd: usize, cmd: usize, l_type: i16, start: isize, len: isize) -> isize {
+    let mut flock = Flock::new(l_type, start, len);
+    fcntl(fd, cmd, &mut flock as *mut _ as usize)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open_file();
+    assert_eq!(lock(fd, F_SETLK, F_WRLCK, 0, 10), 0);
+    // own locks never conflict, a read lock in the middle splits it
+    assert_eq!(lock(fd, F_SETLK, F_RDLCK, 4, 2), 0);
+
+    let parent = getpid();
+    let pid = fork();
+    if pid == 0 {
+        let fd = open_file();
+        assert_eq!(lock(fd, F_SETLK, F_RDLCK, 3, 2), EAGAIN);
+        assert_eq!(lock(fd, F_SETLK, F_RDLCK, 4, 2), 0);
+        assert_eq!(lock(fd, F_SETLK, F_WRLCK, 10, 10), 0);
+
+        let mut flock = Flock::new(F_WRLCK, 0, 0);
+        assert_eq!(fcntl(fd, F_GETLK, &mut flock as *mut _ as usize), 0);
+        assert_eq!(flock.l_type, F_WRLCK);
+        assert_eq!((flock.l_start, flock.l_len), (0, 4));
+        assert_eq!(flock.l_pid as isize, parent);
+
+        // waits for the parent, who then waits for [10, 20) of ours
+        assert_eq!(lock(fd, F_SETLKW, F_WRLCK, 0, 10), 0);
+        exit(0);
+    }
+    sleep(20);
+    assert_eq!(lock(fd, F_SETLKW, F_WRLCK, 10, 10), EDEADLK);
+    // let the child through
+    assert_eq!(lock(fd, F_SETLK, F_UNLCK, 0, 0), 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // gone with the child
+    assert_eq!(lock(fd, F_SETLK, F_WRLCK, 0, 0), 0);
+    close(fd);
+    unlink(FILE);
+    println!("filetest_reclock passed!");
+    0
+}
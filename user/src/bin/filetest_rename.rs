@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, mkdir, open, read, rename, rmdir, unlink, write, OpenFlags};
+
+/// No such file or directory
+const ENOENT: isize = -2;
+/// Invalid argument
+const EINVAL: isize = -22;
+
+fn create(path: &str, content: &[u8]) {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, content);
+    close(fd as usize);
+}
+
+fn content_of(path: &str, buf: &mut [u8]) -> Option<usize> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let len = read(fd as usize, buf);
+    close(fd as usize);
+    Some(len as usize)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 16];
+    create("rename_a\0", b"hello");
+
+    // same dir
+    assert_eq!(rename("rename_a\0", "rename_b\0"), 0);
+    assert!(content_of("rename_a\0", &mut buf).is_none());
+    assert_eq!(content_of("rename_b\0", &mut buf), Some(5));
+    assert_eq!(&buf[..5], b"hello");
+
+    // into a dir, then back out over an existing file
+    assert_eq!(mkdir("rename_d\0"), 0);
+    assert_eq!(rename("rename_b\0", "rename_d/c\0"), 0);
+    assert!(content_of("rename_b\0", &mut buf).is_none());
+    create("rename_e\0", b"world!");
+    assert_eq!(rename("rename_d/c\0", "rename_e\0"), 0);
+    assert_eq!(content_of("rename_e\0", &mut buf), Some(5));
+    assert_eq!(&buf[..5], b"hello");
+
+    // missing source, missing parent, dir under itself
+    assert_eq!(rename("rename_x\0", "rename_y\0"), ENOENT);
+    assert_eq!(rename("rename_e\0", "rename_x/e\0"), ENOENT);
+    assert_eq!(rename("rename_d\0", "rename_d/d\0"), EINVAL);
+
+    assert_eq!(unlink("rename_e\0"), 0);
+    assert_eq!(rmdir("rename_d\0"), 0);
+    println!("filetest_rename passed!");
+    0
+}
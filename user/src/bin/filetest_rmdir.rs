@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, link, mkdir, open, rmdir, unlink, OpenFlags};
+
+/// No such file or directory
+const ENOENT: isize = -2;
+/// Not a directory
+const ENOTDIR: isize = -20;
+/// Is a directory
+const EISDIR: isize = -21;
+/// Directory not empty
+const ENOTEMPTY: isize = -39;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("rmdir_d\0"), 0);
+    let fd = open("rmdir_d/f\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+
+    // not empty, not via unlink, not a dir
+    assert_eq!(rmdir("rmdir_d\0"), ENOTEMPTY);
+    assert_eq!(unlink("rmdir_d\0"), EISDIR);
+    assert_eq!(rmdir("rmdir_d/f\0"), ENOTDIR);
+
+    // files may be hard linked, dirs not
+    assert_eq!(link("rmdir_d/f\0", "rmdir_g\0"), 0);
+    assert_eq!(unlink("rmdir_g\0"), 0);
+    assert_eq!(link("rmdir_d\0", "rmdir_l\0"), -1);
+    assert!(open("rmdir_l\0", OpenFlags::RDONLY) < 0);
+
+    assert_eq!(unlink("rmdir_d/f\0"), 0);
+    assert_eq!(rmdir("rmdir_d\0"), 0);
+    assert_eq!(open("rmdir_d\0", OpenFlags::RDONLY), ENOENT);
+    assert_eq!(rmdir("rmdir_d\0"), ENOENT);
+    // the name is free for a new one
+    assert_eq!(mkdir("rmdir_d\0"), 0);
+    assert_eq!(rmdir("rmdir_d\0"), 0);
+    println!("filetest_rmdir passed!");
+    0
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, mmap, munmap, open, sync, unlink, write, MMapFlags, OpenFlags};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "filetest_sync\0";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &[b'a'; PAGE_SIZE]), PAGE_SIZE as isize);
+    let (blocks, _) = sync();
+    assert!(blocks > 0);
+    // nothing left to write
+    assert_eq!(sync(), (0, 0));
+
+    // stores to a shared mapping are written back too
+    let base = mmap(0, PAGE_SIZE, PROT, MMapFlags::MAP_FILE, fd, 0);
+    assert!(base > 0);
+    unsafe { (base as *mut u8).write_volatile(b'm') };
+    let (blocks, pages) = sync();
+    assert_eq!(pages, 1);
+    assert!(blocks > 0);
+    assert_eq!(munmap(base as usize, PAGE_SIZE), 0);
+    close(fd);
+
+    unlink(FILE);
+    println!("filetest_sync passed!");
+    0
+}
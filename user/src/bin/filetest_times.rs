@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fstat, open, unlink, utimensat, write, OpenFlags, Stat, TimeSpec, UTIME_NOW, UTIME_OMIT,
+};
+
+const FILE: &str = "filetest_times\0";
+
+fn stat_of(path: &str) -> Stat {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd as usize, &mut stat), 0);
+    close(fd as usize);
+    stat
+}
+
+fn ts(sec: u64, nsec: u64) -> TimeSpec {
+    TimeSpec { sec, nsec }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let created = stat_of(FILE);
+    assert_eq!(write(fd as usize, b"hello"), 5);
+    close(fd as usize);
+    let written = stat_of(FILE);
+    assert!(written.mtime >= created.mtime);
+    assert!(written.ctime >= written.mtime);
+
+    // explicit times, ctime still moves to now
+    assert_eq!(utimensat(FILE, Some(&[ts(1, 2), ts(3, 4)])), 0);
+    let set = stat_of(FILE);
+    assert_eq!((set.atime, set.mtime), (ts(1, 2), ts(3, 4)));
+    assert!(set.ctime >= written.ctime);
+
+    // omit keeps the old one
+    assert_eq!(utimensat(FILE, Some(&[ts(0, UTIME_OMIT), ts(5, 0)])), 0);
+    let set = stat_of(FILE);
+    assert_eq!((set.atime, set.mtime), (ts(1, 2), ts(5, 0)));
+    assert_eq!(
+        utimensat(FILE, Some(&[ts(0, UTIME_NOW), ts(0, UTIME_OMIT)])),
+        0
+    );
+    let set = stat_of(FILE);
+    assert!(set.atime >= written.ctime);
+    assert_eq!(set.mtime, ts(5, 0));
+
+    // both now
+    assert_eq!(utimensat(FILE, None), 0);
+    let set = stat_of(FILE);
+    assert!(set.mtime >= written.ctime);
+
+    // bad nsec, missing file
+    assert_eq!(
+        utimensat(FILE, Some(&[ts(0, 1_000_000_000), ts(0, 0)])),
+        -22
+    );
+    assert_eq!(utimensat("filetest_times_x\0", None), -1);
+
+    unlink(FILE);
+    println!("filetest_times passed!");
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, ftruncate, open, read, unlink, write, OpenFlags, Stat};
+
+/// File too large
+const EFBIG: isize = -27;
+
+fn size_of(fd: usize) -> usize {
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    stat.size as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let path = "filet\0";
+    let data = [b'x'; 1000];
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, &data);
+    assert_eq!(size_of(fd), 1000);
+    assert_eq!(ftruncate(fd, 100), 0);
+    assert_eq!(size_of(fd), 100);
+    // grows back with zeros
+    assert_eq!(ftruncate(fd, 600), 0);
+    assert_eq!(size_of(fd), 600);
+    // past what a file holds, left as it was
+    assert_eq!(ftruncate(fd, 1 << 30), EFBIG);
+    assert_eq!(size_of(fd), 600);
+    close(fd);
+
+    let fd = open(path, OpenFlags::RDONLY) as usize;
+    let mut buf = [0xffu8; 1000];
+    assert_eq!(read(fd, &mut buf), 600);
+    assert!(buf[..100].iter().all(|&b| b == b'x'));
+    assert!(buf[100..600].iter().all(|&b| b == 0));
+    // not open for writing
+    assert_eq!(ftruncate(fd, 0), -1);
+    close(fd);
+    assert_eq!(unlink(path), 0);
+
+    println!("filetest_truncate passed!");
+    0
+}
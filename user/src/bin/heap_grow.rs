@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{exit, fork, sbrk, waitpid, SIGSEGV};
+
+const PAGE_SIZE: usize = 4096;
+/// far more than the static heap holds
+const LEN: usize = 256 * 1024;
+
+/// Store to `addr` in a child, return its exit code.
+fn store_in_child(addr: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        unsafe { (addr as *mut usize).write_volatile(1) };
+        exit(0)
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the break moves by pages faulted in on first touch
+    let base = sbrk(0);
+    assert!(base > 0);
+    let base = base as usize;
+    assert_eq!(sbrk(2 * PAGE_SIZE as isize), base as isize);
+    assert_eq!(sbrk(0), (base + 2 * PAGE_SIZE) as isize);
+    for page in [base, base + PAGE_SIZE] {
+        unsafe { (page as *mut usize).write_volatile(page) };
+        assert_eq!(unsafe { (page as *const usize).read_volatile() }, page);
+    }
+    assert_eq!(store_in_child(base + PAGE_SIZE), 0);
+    // what's given back is gone
+    assert_eq!(sbrk(-(PAGE_SIZE as isize)), (base + 2 * PAGE_SIZE) as isize);
+    assert_eq!(store_in_child(base + PAGE_SIZE), -SIGSEGV);
+    assert_eq!(unsafe { (base as *const usize).read_volatile() }, base);
+    assert_eq!(sbrk(-(PAGE_SIZE as isize)), (base + PAGE_SIZE) as isize);
+    assert_eq!(sbrk(0), base as isize);
+    // not below where it started
+    assert_eq!(sbrk(-1), -1);
+
+    // the allocator grows the heap by itself
+    let v: Vec<usize> = (0..LEN / core::mem::size_of::<usize>()).collect();
+    assert!(sbrk(0) as usize >= base + LEN);
+    let pid = fork();
+    if pid == 0 {
+        // and a child gets a copy of it
+        for (i, &x) in v.iter().enumerate() {
+            assert_eq!(x, i);
+        }
+        exit(0)
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("heap_grow passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, getpgid, getpid, setpgid, sleep, tcgetpgrp, tcsetpgrp, waitpid};
+
+const ESRCH: isize = -3;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pgid = getpgid(0);
+    // the job we run in owns the terminal
+    assert_eq!(tcgetpgrp(0), pgid);
+
+    let pid = fork();
+    if pid == 0 {
+        sleep(100);
+        return 0;
+    }
+    // inherited
+    assert_eq!(getpgid(pid as usize), pgid);
+    // own group, then back
+    assert_eq!(setpgid(pid as usize, 0), 0);
+    assert_eq!(getpgid(pid as usize), pid);
+    assert_eq!(setpgid(pid as usize, pgid as usize), 0);
+    assert_eq!(getpgid(pid as usize), pgid);
+    // no such group
+    assert_eq!(setpgid(pid as usize, 0x7fff_ffff), -1);
+    // not a child
+    assert_eq!(setpgid(0x7fff_ffff, 0), ESRCH);
+
+    // hand the terminal over and take it back
+    assert_eq!(setpgid(pid as usize, 0), 0);
+    assert_eq!(tcsetpgrp(0, pid as usize), 0);
+    assert_eq!(tcgetpgrp(1), pid);
+    assert_eq!(tcsetpgrp(0, pgid as usize), 0);
+    assert_eq!(tcsetpgrp(0, 0x7fff_ffff), -1);
+
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(getpgid(getpid() as usize), pgid);
+    println!("job_control passed!");
+    0
+}
@@ -8,14 +8,15 @@ use user_lib::{exit, getdents, open, Dirent, FileType, OpenFlags};
 extern crate user_lib;
 extern crate alloc;
 
+/// `ls [-i] [dir]`: entries of `dir`, cwd if not given, `-i` with their inode numbers
 #[no_mangle]
 fn main(argc: usize, argv: &[&str]) -> i32 {
-    let path = if argc == 1 {
-        ".\0"
-    } else if argc == 2 {
-        argv[1]
-    } else {
-        panic!("wrong number of args!");
+    let inodes = argc > 1 && argv[1] == "-i";
+    let args = &argv[1 + inodes as usize..];
+    let path = match args {
+        [] => ".\0",
+        [path] => *path,
+        _ => panic!("wrong number of args!"),
     };
     let fd = open(path, OpenFlags::RDONLY);
     if fd == -1 {
@@ -26,9 +27,8 @@ fn main(argc: usize, argv: &[&str]) -> i32 {
     const BUF_SIZE: usize = 16;
     let mut total = 0usize;
     let mut entries = vec![Dirent::default(); BUF_SIZE];
-    let mut n = BUF_SIZE;
     loop {
-        n = match getdents(fd as usize, &mut entries.as_mut_slice()[..n]) {
+        let n = match getdents(fd as usize, &mut entries) {
             -1 => {
                 println!("Error read dir {}", path);
                 exit(-1)
@@ -42,8 +42,12 @@ fn main(argc: usize, argv: &[&str]) -> i32 {
             let color_code = match entry.ftype {
                 FileType::DIR => 94,
                 FileType::REG => 0,
+                FileType::LNK => 96,
                 _ => panic!("unknown file type {}", entry.name()),
             };
+            if inodes {
+                print!("{:>6} ", entry.ino);
+            }
             print_color(format_args!("{}\n", entry.name()), color_code);
         }
     }
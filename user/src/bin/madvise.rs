@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, free_frames, madvise, mmap, munmap, open, pwrite, unlink, waitpid,
+    MMapFlags, OpenFlags, MADV_DONTNEED,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const PAGES: usize = 16;
+const LEN: usize = PAGES * PAGE_SIZE;
+const FILE: &str = "madvise\0";
+const ENOMEM: isize = -12;
+
+fn page(base: usize, i: usize) -> *mut usize {
+    (base + i * PAGE_SIZE) as *mut usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // anonymous: frames go back, the pages read zero after
+    let base = mmap(0, LEN, PROT, MMapFlags::MAP_ANON, 0, 0);
+    assert!(base > 0);
+    let base = base as usize;
+    for i in 0..PAGES {
+        unsafe { page(base, i).write_volatile(i + 1) };
+    }
+    let free = free_frames();
+    assert_eq!(
+        madvise(base + PAGE_SIZE, (PAGES - 2) * PAGE_SIZE, MADV_DONTNEED),
+        0
+    );
+    assert!(free_frames() >= free + (PAGES - 2) as isize);
+    assert_eq!(unsafe { page(base, 0).read_volatile() }, 1);
+    assert_eq!(unsafe { page(base, PAGES - 1).read_volatile() }, PAGES);
+    for i in 1..PAGES - 1 {
+        assert_eq!(unsafe { page(base, i).read_volatile() }, 0);
+    }
+    // a child gets the holes as they are
+    unsafe { page(base, 1).write_volatile(42) };
+    assert_eq!(madvise(base + 2 * PAGE_SIZE, PAGE_SIZE, MADV_DONTNEED), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(unsafe { page(base, 1).read_volatile() }, 42);
+        assert_eq!(unsafe { page(base, 2).read_volatile() }, 0);
+        exit(0)
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // not past what was mapped
+    assert_eq!(madvise(base, LEN + PAGE_SIZE, MADV_DONTNEED), ENOMEM);
+    assert_eq!(munmap(base, LEN), 0);
+
+    // a private file mapping reads the file again
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(pwrite(fd, &[b'f'; PAGE_SIZE], 0), PAGE_SIZE as isize);
+    let private = mmap(
+        0,
+        PAGE_SIZE,
+        PROT,
+        MMapFlags::MAP_FILE | MMapFlags::MAP_PRIVATE,
+        fd,
+        0,
+    );
+    assert!(private > 0);
+    let private = private as *mut u8;
+    unsafe { private.write_volatile(b'p') };
+    assert_eq!(madvise(private as usize, PAGE_SIZE, MADV_DONTNEED), 0);
+    assert_eq!(unsafe { private.read_volatile() }, b'f');
+    assert_eq!(munmap(private as usize, PAGE_SIZE), 0);
+    close(fd);
+    unlink(FILE);
+
+    println!("madvise passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{meminfo, mmap, munmap, MMapFlags};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 8;
+const PROT_RW: usize = 0b011;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let before = meminfo();
+    assert!(before.free_frames <= before.total_frames);
+    assert!(before.heap_requested <= before.heap_allocated);
+    assert!(before.heap_allocated <= before.heap_total);
+    assert!(before.cache_cached <= before.cache_capacity);
+    assert!(before.rss_pages > 0);
+
+    // pages touched are resident, and count no more once unmapped
+    let start = mmap(0, PAGES * PAGE_SIZE, PROT_RW, MMapFlags::MAP_ANON, 0, 0);
+    assert!(start > 0);
+    let start = start as usize;
+    for i in 0..PAGES {
+        unsafe { ((start + i * PAGE_SIZE) as *mut usize).write_volatile(i) };
+    }
+    let touched = meminfo();
+    assert!(touched.rss_pages >= before.rss_pages + PAGES);
+    assert!(touched.free_frames + PAGES <= before.free_frames);
+    assert_eq!(munmap(start, PAGES * PAGE_SIZE), 0);
+    assert!(meminfo().rss_pages + PAGES <= touched.rss_pages);
+
+    let info = meminfo();
+    println!(
+        "MemTotal:     {:>8} kB",
+        info.total_frames * PAGE_SIZE / 1024
+    );
+    println!(
+        "MemFree:      {:>8} kB",
+        info.free_frames * PAGE_SIZE / 1024
+    );
+    println!("KernelHeap:   {:>8} kB", info.heap_total / 1024);
+    println!("HeapUsed:     {:>8} kB", info.heap_allocated / 1024);
+    println!("HeapAsked:    {:>8} kB", info.heap_requested / 1024);
+    println!(
+        "BlockCache:   {:>8} blocks of {}, {} dirty",
+        info.cache_cached, info.cache_capacity, info.cache_dirty
+    );
+    println!("VmRSS:        {:>8} kB", info.rss_pages * PAGE_SIZE / 1024);
+    println!("meminfo passed!");
+    0
+}
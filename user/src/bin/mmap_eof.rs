@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, fstat, mmap, munmap, open, read, unlink, waitpid, write, MMapFlags,
+    OpenFlags, Stat, SIGBUS,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "mmap_eof\0";
+/// one page and a bit
+const FILE_SIZE: usize = PAGE_SIZE + 100;
+/// mapped: twice the pages the file has
+const LEN: usize = 4 * PAGE_SIZE;
+
+fn map(fd: usize) -> *mut u8 {
+    let base = mmap(0, LEN, PROT, MMapFlags::MAP_FILE, fd, 0);
+    assert!(base > 0);
+    base as *mut u8
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, &[b'x'; FILE_SIZE]);
+
+    let base = map(fd);
+    unsafe {
+        assert_eq!(base.add(FILE_SIZE - 1).read_volatile(), b'x');
+        // rest of the last page is zeros, writes there stay out of the file
+        assert_eq!(base.add(FILE_SIZE).read_volatile(), 0);
+        assert_eq!(base.add(2 * PAGE_SIZE - 1).read_volatile(), 0);
+        base.add(FILE_SIZE - 1).write_volatile(b'y');
+        base.add(FILE_SIZE).write_volatile(b'z');
+    }
+    assert_eq!(munmap(base as usize, LEN), 0);
+    let mut stat = Stat::new();
+    fstat(fd, &mut stat);
+    assert_eq!(stat.size as usize, FILE_SIZE);
+    close(fd);
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; FILE_SIZE + 1];
+    assert_eq!(read(fd, &mut buf), FILE_SIZE as isize);
+    assert_eq!(buf[FILE_SIZE - 1], b'y');
+    close(fd);
+
+    // pages wholly past the end
+    for page in [2, 3] {
+        let pid = fork();
+        if pid == 0 {
+            let fd = open(FILE, OpenFlags::RDRW) as usize;
+            let base = map(fd);
+            unsafe { base.add(page * PAGE_SIZE).read_volatile() };
+            exit(0);
+        }
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, -SIGBUS);
+    }
+
+    unlink(FILE);
+    println!("mmap_eof passed!");
+    0
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, free_frames, mmap, open, unlink, wait, waitpid, write, MMapFlags, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 8;
+const ROUNDS: usize = 16;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "mmap_stress\0";
+
+fn touch(base: usize, round: usize) {
+    for i in 0..PAGES {
+        unsafe {
+            *((base + i * PAGE_SIZE) as *mut usize) += round;
+        }
+    }
+}
+
+/// Map anon memory & a file, touch all of it, fork once more and exit without munmap.
+fn child(round: usize) -> ! {
+    let len = PAGES * PAGE_SIZE;
+    let anon = mmap(0, len, PROT, MMapFlags::MAP_ANON, 0, 0);
+    assert!(anon > 0);
+    touch(anon as usize, round);
+    let fd = open(FILE, OpenFlags::RDRW);
+    assert!(fd > 0);
+    let file = mmap(0, len, PROT, MMapFlags::MAP_FILE, fd as usize, 0);
+    assert!(file > 0);
+    touch(file as usize, round);
+    if fork() == 0 {
+        touch(anon as usize, round);
+        touch(file as usize, round);
+        exit(0)
+    }
+    assert!(wait(&mut 0) > 0);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    let page = [0u8; PAGE_SIZE];
+    for _ in 0..PAGES {
+        write(fd as usize, &page);
+    }
+    close(fd as usize);
+
+    let baseline = free_frames();
+    for round in 0..ROUNDS {
+        let pid = fork();
+        if pid == 0 {
+            child(round);
+        }
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+        // everything of the child and grandchild is reaped by now
+        assert_eq!(free_frames(), baseline, "frames leaked in round {}", round);
+    }
+    unlink(FILE);
+    println!("mmap_exit_stress passed!");
+    0
+}
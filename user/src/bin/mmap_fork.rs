@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, mmap, munmap, open, read, unlink, waitpid, write, MMapFlags, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "mmap_fork\0";
+const LEN: usize = 2 * PAGE_SIZE;
+
+fn map(fd: usize, flags: MMapFlags) -> *mut u8 {
+    let base = mmap(0, LEN, PROT, MMapFlags::MAP_FILE | flags, fd, 0);
+    assert!(base > 0);
+    base as *mut u8
+}
+
+/// run `f` in a child and wait for it to exit with 0
+fn in_child(f: impl FnOnce()) {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, &[b'a'; LEN]);
+
+    let shared = map(fd, MMapFlags::empty());
+    let private = map(fd, MMapFlags::MAP_PRIVATE);
+    unsafe {
+        shared.write_volatile(b'p');
+        private.write_volatile(b'p');
+    }
+
+    // shared: both see what the other writes
+    in_child(|| unsafe {
+        assert_eq!(shared.read_volatile(), b'p');
+        shared.add(1).write_volatile(b'c');
+    });
+    assert_eq!(unsafe { shared.add(1).read_volatile() }, b'c');
+
+    // private: the child gets its own copy on write, pages untouched before fork included
+    in_child(|| unsafe {
+        assert_eq!(private.read_volatile(), b'p');
+        private.write_volatile(b'c');
+        assert_eq!(private.read_volatile(), b'c');
+        assert_eq!(private.add(PAGE_SIZE).read_volatile(), b'a');
+        private.add(PAGE_SIZE).write_volatile(b'c');
+    });
+    unsafe {
+        assert_eq!(private.read_volatile(), b'p');
+        assert_eq!(private.add(PAGE_SIZE).read_volatile(), b'a');
+        // the only user left, writable again in place
+        private.write_volatile(b'q');
+        assert_eq!(private.read_volatile(), b'q');
+        private.add(2).write_volatile(b'z');
+    }
+
+    // only what went through the shared mapping reaches the file
+    assert_eq!(munmap(private as usize, LEN), 0);
+    assert_eq!(munmap(shared as usize, LEN), 0);
+    close(fd);
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 3];
+    assert_eq!(read(fd, &mut buf), 3);
+    assert_eq!(&buf, b"pca");
+    close(fd);
+
+    unlink(FILE);
+    println!("mmap_fork passed!");
+    0
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, mmap, msync, munmap, open, pread, pwrite, unlink, MMapFlags, MSyncFlags, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "mmap_msync\0";
+const LEN: usize = 2 * PAGE_SIZE;
+const EINVAL: isize = -22;
+const ENOMEM: isize = -12;
+
+fn byte_at(fd: usize, offset: usize) -> u8 {
+    let mut buf = [0u8; 1];
+    assert_eq!(pread(fd, &mut buf, offset), 1);
+    buf[0]
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW | OpenFlags::TRUNC);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(pwrite(fd, &[b'a'; LEN], 0), LEN as isize);
+
+    let base = mmap(0, LEN, PROT, MMapFlags::MAP_FILE, fd, 0);
+    assert!(base > 0);
+    let base = base as usize;
+    let page = |i: usize| (base + i * PAGE_SIZE) as *mut u8;
+    unsafe {
+        page(0).write_volatile(b'b');
+        page(1).write_volatile(b'b');
+    }
+
+    // only the pages asked for are written, and they're clean after
+    assert_eq!(msync(base, PAGE_SIZE, MSyncFlags::MS_SYNC), 1);
+    assert_eq!(byte_at(fd, 0), b'b');
+    assert_eq!(byte_at(fd, PAGE_SIZE), b'a');
+    assert_eq!(msync(base, PAGE_SIZE, MSyncFlags::MS_SYNC), 0);
+    assert_eq!(msync(base, LEN, MSyncFlags::MS_ASYNC), 1);
+    assert_eq!(byte_at(fd, PAGE_SIZE), b'b');
+
+    // dropped pages are read from the file again
+    assert_eq!(pwrite(fd, b"c", 0), 1);
+    assert_eq!(unsafe { page(0).read_volatile() }, b'b');
+    assert_eq!(msync(base, PAGE_SIZE, MSyncFlags::MS_INVALIDATE), 0);
+    assert_eq!(unsafe { page(0).read_volatile() }, b'c');
+
+    assert_eq!(
+        msync(base, LEN, MSyncFlags::MS_ASYNC | MSyncFlags::MS_SYNC),
+        EINVAL
+    );
+    assert_eq!(msync(base + 1, PAGE_SIZE, MSyncFlags::MS_SYNC), EINVAL);
+    assert_eq!(msync(base + LEN, PAGE_SIZE, MSyncFlags::MS_SYNC), ENOMEM);
+    assert_eq!(munmap(base, LEN), 0);
+
+    // a private mapping never reaches the file
+    let private = mmap(
+        0,
+        LEN,
+        PROT,
+        MMapFlags::MAP_FILE | MMapFlags::MAP_PRIVATE,
+        fd,
+        0,
+    );
+    assert!(private > 0);
+    unsafe { (private as *mut u8).write_volatile(b'p') };
+    assert_eq!(msync(private as usize, LEN, MSyncFlags::MS_SYNC), 0);
+    assert_eq!(byte_at(fd, 0), b'c');
+    assert_eq!(munmap(private as usize, LEN), 0);
+
+    close(fd);
+    unlink(FILE);
+    println!("mmap_msync passed!");
+    0
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, waitpid, MMapFlags, SIGSEGV};
+
+const PAGE_SIZE: usize = 4096;
+const PROT_R: usize = 0b001;
+const PROT_W: usize = 0b010;
+const PROT_X: usize = 0b100;
+
+/// Run `f` on a fresh anonymous page mapped with `prot` in a child, return its exit code.
+fn on_page(prot: usize, f: fn(usize)) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        let page = mmap(0, PAGE_SIZE, prot, MMapFlags::MAP_ANON, 0, 0);
+        assert!(page > 0);
+        f(page as usize);
+        exit(0)
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+fn load(page: usize) {
+    assert_eq!(unsafe { (page as *const usize).read_volatile() }, 0);
+}
+
+fn store(page: usize) {
+    unsafe { (page as *mut usize).write_volatile(1) };
+}
+
+fn jump(page: usize) {
+    let f: fn() = unsafe { core::mem::transmute(page) };
+    f();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // first touch is what the reservation allows
+    assert_eq!(on_page(PROT_R, load), 0);
+    assert_eq!(on_page(PROT_R | PROT_W, store), 0);
+    // or it isn't
+    assert_eq!(on_page(PROT_R, store), -SIGSEGV);
+    assert_eq!(on_page(PROT_R, jump), -SIGSEGV);
+    assert_eq!(on_page(PROT_X, load), -SIGSEGV);
+    assert_eq!(on_page(PROT_X, store), -SIGSEGV);
+    println!("mmap_prot passed!");
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, ftruncate, mmap, munmap, open, read, unlink, write, MMapFlags, OpenFlags};
+
+const PAGE_SIZE: usize = 4096;
+const PROT_R: usize = 0b001;
+const PROT_W: usize = 0b010;
+const FILE: &str = "mmap_rdonly\0";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, &[b'x'; PAGE_SIZE]), PAGE_SIZE as isize);
+    close(fd as usize);
+
+    let fd = open(FILE, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // nothing changes the file through a fd opened read-only
+    assert_eq!(write(fd, b"y"), -1);
+    assert_eq!(ftruncate(fd, 0), -1);
+    let flags = MMapFlags::MAP_FILE;
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_R | PROT_W, flags, fd, 0), -1);
+    // but it may be mapped writable privately, stores stay in the process
+    let flags = MMapFlags::MAP_FILE | MMapFlags::MAP_PRIVATE;
+    let base = mmap(0, PAGE_SIZE, PROT_R | PROT_W, flags, fd, 0);
+    assert!(base > 0);
+    let page = base as *mut u8;
+    unsafe {
+        assert_eq!(page.read_volatile(), b'x');
+        page.write_volatile(b'y');
+        assert_eq!(page.read_volatile(), b'y');
+    }
+    assert_eq!(munmap(base as usize, PAGE_SIZE), 0);
+    // and shared read-only
+    let base = mmap(0, PAGE_SIZE, PROT_R, MMapFlags::MAP_FILE, fd, 0);
+    assert!(base > 0);
+    assert_eq!(unsafe { (base as *const u8).read_volatile() }, b'x');
+    assert_eq!(munmap(base as usize, PAGE_SIZE), 0);
+    close(fd);
+
+    let fd = open(FILE, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; PAGE_SIZE];
+    assert_eq!(read(fd, &mut buf), PAGE_SIZE as isize);
+    assert!(buf.iter().all(|&b| b == b'x'));
+    close(fd);
+    assert_eq!(unlink(FILE), 0);
+    println!("mmap_rdonly passed!");
+    0
+}
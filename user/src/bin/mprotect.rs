@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{exit, fork, mmap, mprotect, munmap, waitpid, MMapFlags, SIGSEGV};
+
+const PAGE_SIZE: usize = 4096;
+const PROT_R: usize = 0b001;
+const PROT_W: usize = 0b010;
+const PROT_X: usize = 0b100;
+/// `ret`
+const RET: u32 = 0x0000_8067;
+
+/// Run `f` on `page` in a child, return its exit code.
+fn in_child(page: usize, f: fn(usize)) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f(page);
+        exit(0)
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+fn store(page: usize) {
+    unsafe { (page as *mut usize).write_volatile(2) };
+}
+
+fn load(page: usize) -> usize {
+    unsafe { (page as *const usize).read_volatile() }
+}
+
+fn map(len: usize) -> usize {
+    let start = mmap(0, len, PROT_R | PROT_W, MMapFlags::MAP_ANON, 0, 0);
+    assert!(start > 0);
+    start as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a page written turns read-only, forks too, and writable again
+    let page = map(PAGE_SIZE);
+    unsafe { (page as *mut usize).write_volatile(1) };
+    assert_eq!(mprotect(page, PAGE_SIZE, PROT_R), 0);
+    assert_eq!(load(page), 1);
+    assert_eq!(in_child(page, store), -SIGSEGV);
+    assert_eq!(mprotect(page, PAGE_SIZE, PROT_R | PROT_W), 0);
+    assert_eq!(in_child(page, store), 0);
+    store(page);
+    assert_eq!(load(page), 2);
+    assert_eq!(munmap(page, PAGE_SIZE), 0);
+
+    // so does one never touched
+    let page = map(PAGE_SIZE);
+    assert_eq!(mprotect(page, PAGE_SIZE, PROT_R), 0);
+    assert_eq!(in_child(page, store), -SIGSEGV);
+    assert_eq!(load(page), 0);
+    assert_eq!(munmap(page, PAGE_SIZE), 0);
+
+    // code written, then run
+    let page = map(PAGE_SIZE);
+    unsafe {
+        (page as *mut u32).write_volatile(RET);
+        asm!("fence.i");
+    }
+    assert_eq!(mprotect(page, PAGE_SIZE, PROT_R | PROT_X), 0);
+    let f: fn() = unsafe { core::mem::transmute(page) };
+    f();
+    assert_eq!(in_child(page, store), -SIGSEGV);
+    assert_eq!(munmap(page, PAGE_SIZE), 0);
+
+    // the whole of one mapping or nothing
+    let start = map(2 * PAGE_SIZE);
+    assert_eq!(mprotect(start, PAGE_SIZE, PROT_R), -1);
+    assert_eq!(mprotect(start + PAGE_SIZE, PAGE_SIZE, PROT_R), -1);
+    assert_eq!(mprotect(start + 1, 2 * PAGE_SIZE, PROT_R), -1);
+    assert_eq!(mprotect(start, 2 * PAGE_SIZE, 0), -1);
+    assert_eq!(mprotect(start, 2 * PAGE_SIZE, PROT_W << 2), -1);
+    assert_eq!(munmap(start, 2 * PAGE_SIZE), 0);
+    assert_eq!(mprotect(start, 2 * PAGE_SIZE, PROT_R), -1);
+    println!("mprotect passed!");
+    0
+}
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, sleep, write, OpenFlags};
+
+/// usage: pcapdump <output> [msecs]
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: pcapdump <output> [msecs]");
+        return -1;
+    }
+    let msecs: usize = if argc > 2 {
+        argv[2].parse().unwrap()
+    } else {
+        1000
+    };
+
+    // capture starts on open
+    let dev = open("/dev/pcap0", OpenFlags::RDONLY);
+    if dev < 0 {
+        println!("can't open /dev/pcap0");
+        return -1;
+    }
+    let dev = dev as usize;
+    sleep(msecs);
+
+    let out = open(
+        argv[1],
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    if out < 0 {
+        println!("can't open {}", argv[1]);
+        close(dev);
+        return -1;
+    }
+    let out = out as usize;
+
+    let mut buf = [0u8; 512];
+    let mut total = 0;
+    loop {
+        let n = read(dev, &mut buf);
+        if n <= 0 {
+            break;
+        }
+        write(out, &buf[..n as usize]);
+        total += n as usize;
+    }
+    close(out);
+    close(dev);
+    println!("{} bytes captured to {}", total, argv[1]);
+    0
+}
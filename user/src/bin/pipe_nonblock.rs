@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fcntl, pipe, read, write, OpenFlags, F_GETFL, F_GETPIPE_SZ, F_SETFL};
+
+/// Try again
+const EAGAIN: isize = -11;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let [rfd, wfd] = pipe_fd;
+
+    // empty pipe: EAGAIN instead of waiting for a writer
+    assert_eq!(fcntl(rfd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    let flags = OpenFlags::from_bits_truncate(fcntl(rfd, F_GETFL, 0) as u32);
+    assert!(flags.contains(OpenFlags::NONBLOCK));
+    let mut buf = [0u8; 16];
+    assert_eq!(read(rfd, &mut buf), EAGAIN);
+
+    // what's there now, not the whole buffer
+    assert_eq!(write(wfd, b"abc"), 3);
+    assert_eq!(read(rfd, &mut buf), 3);
+    assert_eq!(&buf[..3], b"abc");
+
+    // full pipe: the write end takes what fits, then EAGAIN
+    assert_eq!(fcntl(wfd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    let chunk = [b'x'; 100];
+    let mut total = 0;
+    loop {
+        let n = write(wfd, &chunk);
+        if n == EAGAIN {
+            break;
+        }
+        assert!(n > 0);
+        total += n as usize;
+    }
+    assert_eq!(total, fcntl(wfd, F_GETPIPE_SZ, 0) as usize);
+
+    // cleared again, the flag is gone
+    assert_eq!(fcntl(rfd, F_SETFL, 0), 0);
+    let flags = OpenFlags::from_bits_truncate(fcntl(rfd, F_GETFL, 0) as u32);
+    assert!(!flags.contains(OpenFlags::NONBLOCK));
+    assert_eq!(read(rfd, &mut buf), buf.len() as isize);
+
+    close(rfd);
+    close(wfd);
+    println!("pipe_nonblock passed!");
+    0
+}
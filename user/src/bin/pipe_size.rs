@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fcntl, pipe, pipe2, read, write, OpenFlags, F_GETFL, F_GETPIPE_SZ};
+
+/// Try again
+const EAGAIN: isize = -11;
+/// Invalid argument
+const EINVAL: isize = -22;
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a page unless asked for more
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), 0);
+    assert_eq!(fcntl(fds[0], F_GETPIPE_SZ, 0), PAGE_SIZE as isize);
+    assert_eq!(fcntl(fds[1], F_GETPIPE_SZ, 0), PAGE_SIZE as isize);
+    close(fds[0]);
+    close(fds[1]);
+    // not a pipe
+    assert_eq!(fcntl(1, F_GETPIPE_SZ, 0), -1);
+
+    // rounded up to pages, both ends non-blocking
+    assert_eq!(pipe2(&mut fds, OpenFlags::NONBLOCK, PAGE_SIZE + 1), 0);
+    let [rfd, wfd] = fds;
+    assert_eq!(fcntl(rfd, F_GETPIPE_SZ, 0), 2 * PAGE_SIZE as isize);
+    for fd in fds {
+        let flags = OpenFlags::from_bits_truncate(fcntl(fd, F_GETFL, 0) as u32);
+        assert!(flags.contains(OpenFlags::NONBLOCK));
+    }
+    let mut buf = [0u8; 256];
+    assert_eq!(read(rfd, &mut buf), EAGAIN);
+    let chunk = [b'x'; 1000];
+    let mut total = 0;
+    loop {
+        match write(wfd, &chunk) {
+            EAGAIN => break,
+            n => total += n as usize,
+        }
+    }
+    assert_eq!(total, 2 * PAGE_SIZE);
+    close(rfd);
+    close(wfd);
+
+    // too large, or flags other than NONBLOCK
+    assert_eq!(pipe2(&mut fds, OpenFlags::empty(), 17 * PAGE_SIZE), EINVAL);
+    assert_eq!(pipe2(&mut fds, OpenFlags::CREATE, 0), EINVAL);
+
+    println!("pipe_size passed!");
+    0
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+
+use user_lib::{close, exit, fork, pipe, read, sleep, waitpid, write};
+
+const READERS: usize = 3;
+
+/// Readers blocked on an empty pipe woken for a byte each or the end of file, and a writer
+/// blocked on a full pipe until a slow reader makes room.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut fds = [0usize; 2];
+    pipe(&mut fds);
+    let [rfd, wfd] = fds;
+
+    // one more than there are bytes, it only gets the end of file
+    let mut pids = [0isize; READERS + 1];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            close(wfd);
+            let mut buf = [0u8; 1];
+            exit(read(rfd, &mut buf) as i32);
+        }
+    }
+    close(rfd);
+    // all of them blocked by now
+    sleep(20);
+    assert_eq!(write(wfd, b"abc"), READERS as isize);
+    close(wfd);
+    let mut total = 0;
+    for pid in pids {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert!(exit_code == 0 || exit_code == 1);
+        total += exit_code;
+    }
+    assert_eq!(total, READERS as i32);
+
+    pipe(&mut fds);
+    let [rfd, wfd] = fds;
+    let pid = fork();
+    if pid == 0 {
+        close(wfd);
+        let mut buf = [0u8; 1024];
+        let mut total = 0;
+        loop {
+            sleep(5);
+            match read(rfd, &mut buf) {
+                0 => break,
+                n => total += n as usize,
+            }
+        }
+        exit(total as i32);
+    }
+    close(rfd);
+    // a few times what the pipe buffers
+    let data = vec![b'x'; 3 * 4096];
+    assert_eq!(write(wfd, &data), data.len() as isize);
+    close(wfd);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, data.len() as i32);
+
+    println!("pipe_waiters passed!");
+    0
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, get_time, pipe, poll, read, sleep, waitpid, write, PollFd, POLLIN, POLLNVAL,
+    POLLOUT,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut a = [0usize; 2];
+    let mut b = [0usize; 2];
+    pipe(&mut a);
+    pipe(&mut b);
+
+    // nothing written yet, but room to write
+    let mut fds = [PollFd::new(a[0], POLLIN), PollFd::new(b[0], POLLIN)];
+    assert_eq!(poll(&mut fds, Some(0)), 0);
+    let mut fds = [PollFd::new(a[1], POLLIN | POLLOUT)];
+    assert_eq!(poll(&mut fds, Some(0)), 1);
+    assert_eq!(fds[0].revents, POLLOUT);
+
+    // times out, not before it's due
+    let start = get_time();
+    let mut fds = [PollFd::new(a[0], POLLIN)];
+    assert_eq!(poll(&mut fds, Some(30)), 0);
+    assert!(get_time() - start >= 30);
+
+    let pid = fork();
+    if pid == 0 {
+        close(a[0]);
+        close(b[0]);
+        sleep(50);
+        write(b[1], b"x");
+        sleep(50);
+        // end of file on `a`
+        close(a[1]);
+        exit(0);
+    }
+    close(a[1]);
+    close(b[1]);
+
+    // woken by the write to `b` only
+    let mut fds = [PollFd::new(a[0], POLLIN), PollFd::new(b[0], POLLIN)];
+    assert_eq!(poll(&mut fds, None), 1);
+    assert_eq!(fds[0].revents, 0);
+    assert_eq!(fds[1].revents, POLLIN);
+    let mut buf = [0u8; 1];
+    assert_eq!(read(b[0], &mut buf), 1);
+
+    // the last write end gone, `a` reads 0 now
+    let mut fds = [PollFd::new(a[0], POLLIN)];
+    assert_eq!(poll(&mut fds, None), 1);
+    assert_eq!(fds[0].revents, POLLIN);
+    assert_eq!(read(a[0], &mut buf), 0);
+
+    // closed fds are reported, negative ones skipped
+    close(a[0]);
+    let mut fds = [PollFd::new(a[0], POLLIN), PollFd::new(b[0], POLLIN)];
+    fds[1].fd = -1;
+    assert_eq!(poll(&mut fds, Some(0)), 1);
+    assert_eq!(fds[0].revents, POLLNVAL);
+    assert_eq!(fds[1].revents, 0);
+
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    close(b[0]);
+    println!("poll_pipe passed!");
+    0
+}
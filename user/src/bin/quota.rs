@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{format, string::String};
+use user_lib::{clock_gettime, exit, getquota, getuid, Quota, TimeSpec, CLOCK_REALTIME};
+
+/// A limit, - for none
+fn limit(limit: u32) -> String {
+    match limit {
+        0 => String::from("-"),
+        limit => format!("{}", limit),
+    }
+}
+
+/// What's left of a grace by wall time `now`
+fn grace(grace: u64, now: u64) -> String {
+    match grace {
+        0 => String::from("-"),
+        grace if grace <= now => String::from("none"),
+        grace => format!("{}s", (grace - now) / 1_000_000_000),
+    }
+}
+
+/// Usage and limits of a uid, the caller's by default, on the filesystem of cwd
+#[no_mangle]
+fn main(argc: usize, argv: &[&str]) -> i32 {
+    let uid = match (argc, argv.get(1).map(|uid| uid.parse())) {
+        (1, _) => getuid() as u16,
+        (2, Some(Ok(uid))) => uid,
+        _ => {
+            println!("usage: quota [uid]");
+            exit(-1);
+        }
+    };
+    let mut quota = Quota::default();
+    match getquota(".\0", uid, &mut quota) {
+        0 => {}
+        -3 => {
+            println!("no quota for uid {}", uid);
+            return 0;
+        }
+        e => {
+            println!("Error getquota of uid {}: {}", uid, e);
+            exit(-1);
+        }
+    }
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_REALTIME, &mut ts);
+    let now = ts.sec * 1_000_000_000 + ts.nsec;
+
+    println!("uid {}", uid);
+    println!("        used\tsoft\thard\tgrace");
+    println!(
+        "blocks  {}\t{}\t{}\t{}",
+        quota.blocks,
+        limit(quota.block_soft),
+        limit(quota.block_hard),
+        grace(quota.block_grace, now)
+    );
+    println!(
+        "inodes  {}\t{}\t{}\t{}",
+        quota.inodes,
+        limit(quota.inode_soft),
+        limit(quota.inode_hard),
+        grace(quota.inode_grace, now)
+    );
+    0
+}
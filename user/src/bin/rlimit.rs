@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, dup, dup3, exec, exit, fork, getpid, getrlimit, mmap, munmap, pipe, prlimit, setrlimit,
+    setuid, waitpid, MMapFlags, OpenFlags, RLimit, RLIMIT_AS, RLIMIT_NOFILE, RLIMIT_STACK,
+    RLIM_INFINITY,
+};
+
+const PAGE_SIZE: usize = 4096;
+const PROT_RW: usize = 0b011;
+const NOFILE: usize = 8;
+
+fn limit_of(resource: usize) -> RLimit {
+    let mut limit = RLimit::default();
+    assert_eq!(getrlimit(resource, &mut limit), 0);
+    limit
+}
+
+fn nofile() {
+    let limit = limit_of(RLIMIT_NOFILE);
+    assert_eq!(limit.cur, limit.max);
+    // soft above hard, hard above what there is
+    let bad = RLimit {
+        cur: limit.max + 1,
+        max: limit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &bad), -22);
+    let bad = RLimit {
+        cur: limit.max,
+        max: limit.max + 1,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &bad), -1);
+
+    let low = RLimit {
+        cur: NOFILE,
+        max: limit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &low), 0);
+    let mut last = 0;
+    loop {
+        match dup(0) {
+            -24 => break,
+            fd => {
+                assert!(fd > 0 && (fd as usize) < NOFILE);
+                last = fd as usize;
+            }
+        }
+    }
+    assert_eq!(last, NOFILE - 1);
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), -24);
+    assert_eq!(dup3(0, NOFILE, OpenFlags::empty()), -9);
+    // a free one below the limit is taken again
+    close(last);
+    assert_eq!(dup(0), last as isize);
+
+    // a child gets them too
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(limit_of(RLIMIT_NOFILE), low);
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for fd in 3..NOFILE {
+        close(fd);
+    }
+}
+
+fn address_space() {
+    assert_eq!(limit_of(RLIMIT_AS).cur, RLIM_INFINITY);
+    let limit = RLimit {
+        cur: 4 * PAGE_SIZE,
+        max: RLIM_INFINITY,
+    };
+    assert_eq!(setrlimit(RLIMIT_AS, &limit), 0);
+    assert_eq!(
+        mmap(0, 8 * PAGE_SIZE, PROT_RW, MMapFlags::MAP_ANON, 0, 0),
+        -12
+    );
+    let start = mmap(0, 4 * PAGE_SIZE, PROT_RW, MMapFlags::MAP_ANON, 0, 0);
+    assert!(start > 0);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_RW, MMapFlags::MAP_ANON, 0, 0), -12);
+    // room again once unmapped
+    assert_eq!(munmap(start as usize, 4 * PAGE_SIZE), 0);
+    let start = mmap(0, PAGE_SIZE, PROT_RW, MMapFlags::MAP_ANON, 0, 0);
+    assert!(start > 0);
+    assert_eq!(munmap(start as usize, PAGE_SIZE), 0);
+}
+
+fn stack() {
+    let limit = limit_of(RLIMIT_STACK);
+    // stacks don't grow, no more than they have
+    let bigger = RLimit {
+        cur: limit.max * 2,
+        max: limit.max * 2,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &bigger), -1);
+    let small = RLimit {
+        cur: 64,
+        max: limit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &small), 0);
+    let arg = "an argument longer than half of the stack limit\0";
+    let args = [arg.as_ptr(), core::ptr::null()];
+    assert_eq!(exec("rlimit\0", &args), -7);
+    assert_eq!(setrlimit(RLIMIT_STACK, &limit), 0);
+}
+
+fn hard_limit() {
+    let parent = getpid() as usize;
+    let pid = fork();
+    if pid == 0 {
+        let limit = limit_of(RLIMIT_NOFILE);
+        assert_eq!(setuid(1), 0);
+        // parent's limits are root's now
+        let mut old = RLimit::default();
+        assert_eq!(prlimit(parent, RLIMIT_NOFILE, None, Some(&mut old)), -1);
+        let lower = RLimit {
+            cur: NOFILE,
+            max: NOFILE,
+        };
+        assert_eq!(setrlimit(RLIMIT_NOFILE, &lower), 0);
+        // lowered for good
+        assert_eq!(setrlimit(RLIMIT_NOFILE, &limit), -1);
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    nofile();
+    address_space();
+    stack();
+    hard_limit();
+    println!("rlimit passed!");
+    0
+}
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, getrusage, mmap, munmap, open, unlink, waitpid, write, MMapFlags, OpenFlags,
+    RUsage, RUSAGE_CHILDREN, RUSAGE_SELF,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const FILE: &str = "rusage_faults\0";
+
+fn usage(who: isize) -> RUsage {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(who, &mut usage), 0);
+    usage
+}
+
+/// Usage of self before and after `f` runs a second time: pages of the program it goes through
+/// are paged in by the first, so only what `f` does itself is counted
+fn usage_around(f: impl Fn()) -> (RUsage, RUsage) {
+    f();
+    let before = usage(RUSAGE_SELF);
+    f();
+    (before, usage(RUSAGE_SELF))
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // anonymous memory: minor, unmapping flushes
+    let (before, after) = usage_around(|| {
+        let base = mmap(0, PAGE_SIZE, PROT, MMapFlags::MAP_ANON, 0, 0);
+        assert!(base > 0);
+        unsafe { (base as *mut u8).write_volatile(1) };
+        assert_eq!(munmap(base as usize, PAGE_SIZE), 0);
+    });
+    assert!(after.minflt > before.minflt);
+    assert_eq!(after.majflt, before.majflt);
+    assert!(after.tlb_flushes > before.tlb_flushes);
+
+    // file pages: major, read from disk
+    let (before, after) = usage_around(|| {
+        let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDRW);
+        assert!(fd > 0);
+        let fd = fd as usize;
+        write(fd, &[b'a'; PAGE_SIZE]);
+        let base = mmap(0, PAGE_SIZE, PROT, MMapFlags::MAP_FILE, fd, 0);
+        assert!(base > 0);
+        assert_eq!(unsafe { (base as *const u8).read_volatile() }, b'a');
+        assert_eq!(munmap(base as usize, PAGE_SIZE), 0);
+        close(fd);
+        unlink(FILE);
+    });
+    assert_eq!(after.majflt, before.majflt + 1);
+
+    // a child's faults add to the children's once reaped
+    let children = usage(RUSAGE_CHILDREN);
+    let pid = fork();
+    if pid == 0 {
+        let base = mmap(0, PAGE_SIZE, PROT, MMapFlags::MAP_ANON, 0, 0);
+        assert!(base > 0);
+        unsafe { (base as *mut u8).write_volatile(1) };
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(usage(RUSAGE_CHILDREN).minflt > children.minflt);
+
+    println!("rusage_faults passed!");
+    0
+}
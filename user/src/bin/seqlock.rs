@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exit, membarrier, thread_create, waittid, yield_, SeqLock, MEMBARRIER_CMD_GLOBAL,
+    MEMBARRIER_CMD_PRIVATE_EXPEDITED, MEMBARRIER_CMD_QUERY,
+};
+
+const WRITES: usize = 2000;
+const READERS: usize = 3;
+
+/// a value and its complement, a torn read shows as a pair that doesn't match
+static PAIR: SeqLock<(usize, usize)> = SeqLock::new((0, !0));
+static DONE: AtomicBool = AtomicBool::new(false);
+
+fn writer() -> ! {
+    for i in 1..=WRITES {
+        PAIR.write(|pair| {
+            pair.0 = i;
+            // a reader switched in right here gets to see half a write
+            if i % 64 == 0 {
+                yield_();
+            }
+            pair.1 = !i;
+        });
+    }
+    DONE.store(true, Ordering::Release);
+    exit(0)
+}
+
+fn reader() -> ! {
+    let mut last = 0;
+    while !DONE.load(Ordering::Acquire) {
+        let (a, b) = PAIR.read();
+        assert_eq!(b, !a);
+        // writes are seen in order
+        assert!(a >= last);
+        last = a;
+    }
+    assert_eq!(PAIR.read(), (WRITES, !WRITES));
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let supported = membarrier(MEMBARRIER_CMD_QUERY, 0);
+    assert!(supported > 0);
+    for cmd in [MEMBARRIER_CMD_GLOBAL, MEMBARRIER_CMD_PRIVATE_EXPEDITED] {
+        assert_ne!(supported as usize & cmd, 0);
+        assert_eq!(membarrier(cmd, 0), 0);
+    }
+    assert_eq!(membarrier(MEMBARRIER_CMD_GLOBAL, 1), -22);
+
+    let mut tids = Vec::new();
+    for _ in 0..READERS {
+        tids.push(thread_create(reader as usize, 0));
+    }
+    tids.push(thread_create(writer as usize, 0));
+    for tid in tids {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    println!("seqlock passed!");
+    0
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+//! A shared memory segment is the same memory in parent and child, mapped by fork or opened by
+//! id, and its frames are freed once nobody has it.
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, free_frames, munmap, shm_map, shm_open, shm_unmap, waitpid};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+const ID: usize = 0x5348_4d;
+/// Invalid argument
+const EINVAL: isize = -22;
+
+fn words(start: usize) -> &'static mut [usize] {
+    unsafe { core::slice::from_raw_parts_mut(start as *mut usize, 2 * PAGE_SIZE / 8) }
+}
+
+fn child(inherited: usize) -> ! {
+    // the same id maps the same frames, elsewhere
+    let fd = shm_open(ID, PAGE_SIZE);
+    assert!(fd > 0);
+    let start = shm_map(fd as usize, PROT);
+    assert!(start > 0);
+    let start = start as usize;
+    assert_ne!(start, inherited);
+    let (mine, theirs) = (words(start), words(inherited));
+    assert_eq!(mine[0], 1);
+    assert_eq!(mine[PAGE_SIZE / 8], 2);
+    mine[1] = 3;
+    assert_eq!(theirs[1], 3);
+    theirs[PAGE_SIZE / 8 + 1] = 4;
+    assert_eq!(shm_unmap(start), 0);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = shm_open(ID, 2 * PAGE_SIZE);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // the one open is smaller
+    assert_eq!(shm_open(ID, 3 * PAGE_SIZE), EINVAL);
+    assert_eq!(shm_open(ID + 1, 0), EINVAL);
+
+    let start = shm_map(fd, PROT);
+    assert!(start > 0);
+    let start = start as usize;
+    let shared = words(start);
+    assert!(shared.iter().all(|&w| w == 0));
+    shared[0] = 1;
+    shared[PAGE_SIZE / 8] = 2;
+
+    let pid = fork();
+    if pid == 0 {
+        child(start);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(shared[1], 3);
+    assert_eq!(shared[PAGE_SIZE / 8 + 1], 4);
+
+    // it's shm_unmap's, and only where it's mapped
+    assert_eq!(munmap(start, 2 * PAGE_SIZE), -1);
+    assert_eq!(shm_unmap(start + PAGE_SIZE), EINVAL);
+    assert_eq!(shm_unmap(start), 0);
+    assert_eq!(shm_unmap(start), EINVAL);
+    // the fd keeps it
+    let frames = free_frames();
+    let again = shm_map(fd, PROT);
+    assert!(again > 0);
+    assert_eq!(words(again as usize)[1], 3);
+    assert_eq!(shm_unmap(again as usize), 0);
+    assert_eq!(close(fd), 0);
+    assert!(free_frames() >= frames + 2);
+
+    // nobody has it, the id is a new one
+    let fd = shm_open(ID, PAGE_SIZE) as usize;
+    let start = shm_map(fd, PROT) as usize;
+    assert_eq!(words(start)[0], 0);
+    assert_eq!(shm_unmap(start), 0);
+    assert_eq!(close(fd), 0);
+    println!("shm_share passed!");
+    0
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::*;
+
+fn on_usr2() {
+    sigreturn();
+}
+
+/// install `handler` for `signum`, the previous one is returned
+fn set_handler(signum: i32, handler: usize) -> usize {
+    let action = SignalAction {
+        handler,
+        ..SignalAction::default()
+    };
+    let mut old = SignalAction::default();
+    assert_eq!(sigaction(signum, Some(&action), Some(&mut old)), 0);
+    old.handler
+}
+
+/// re-exec'd by the child below
+fn after_exec() -> i32 {
+    // ignored stays ignored, the handler is gone with the old image
+    assert_eq!(set_handler(SIGUSR1, SIG_IGN), SIG_IGN);
+    assert_eq!(set_handler(SIGUSR2, SIG_DFL), SIG_DFL);
+    kill(getpid() as usize, SIGUSR1);
+    0
+}
+
+#[no_mangle]
+pub fn main(argc: usize, _argv: &[&str]) -> i32 {
+    if argc > 1 {
+        return after_exec();
+    }
+
+    // ignored, nothing happens
+    assert_eq!(set_handler(SIGUSR1, SIG_IGN), SIG_DFL);
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    // ignored by default
+    assert_eq!(kill(getpid() as usize, SIGCHLD), 0);
+    yield_();
+
+    // handler and ignore are both inherited by fork, exec keeps only the latter
+    set_handler(SIGUSR2, on_usr2 as usize);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(set_handler(SIGUSR2, on_usr2 as usize), on_usr2 as usize);
+        assert_eq!(set_handler(SIGUSR1, SIG_IGN), SIG_IGN);
+        let args = [
+            "sig_disposition\0".as_ptr(),
+            "exec\0".as_ptr(),
+            core::ptr::null(),
+        ];
+        exec("sig_disposition\0", &args);
+        exit(-1);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // back to default, which terminates
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(set_handler(SIGUSR1, SIG_DFL), SIG_IGN);
+        kill(getpid() as usize, SIGUSR1);
+        loop {
+            yield_();
+        }
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -SIGUSR1);
+
+    println!("sig_disposition passed!");
+    0
+}
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::*;
+
+static mut LOG: [usize; 8] = [0; 8];
+static mut LOG_LEN: usize = 0;
+static mut ENTERED: usize = 0;
+
+fn log(v: usize) {
+    unsafe {
+        LOG[LOG_LEN] = v;
+        LOG_LEN += 1;
+    }
+}
+
+/// (log, len), and start over
+fn take_log() -> ([usize; 8], usize) {
+    unsafe {
+        let len = LOG_LEN;
+        LOG_LEN = 0;
+        ENTERED = 0;
+        (LOG, len)
+    }
+}
+
+fn on_usr2() {
+    log(2);
+    sigreturn();
+}
+
+/// SIGUSR2 raised in here is handled right away, on top of this handler
+fn on_usr1() {
+    log(1);
+    kill(getpid() as usize, SIGUSR2);
+    log(3);
+    sigreturn();
+}
+
+/// re-raises SIGUSR1 on the first entry, logs `10 * n` on entry and `10 * n + 1` on exit
+fn on_usr1_again() {
+    let n = unsafe {
+        ENTERED += 1;
+        ENTERED
+    };
+    log(10 * n);
+    if n == 1 {
+        kill(getpid() as usize, SIGUSR1);
+    }
+    log(10 * n + 1);
+    sigreturn();
+}
+
+fn install(signum: i32, handler: usize, flags: SignalActionFlags) {
+    let mut action = SignalAction::default();
+    let mut old = SignalAction::default();
+    action.handler = handler;
+    action.flags = flags;
+    assert!(sigaction(signum, Some(&action), Some(&mut old)) >= 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a local living across both handlers must survive the nested sigreturns
+    let canary = getpid() * 7;
+
+    install(SIGUSR1, on_usr1 as usize, SignalActionFlags::empty());
+    install(SIGUSR2, on_usr2 as usize, SignalActionFlags::empty());
+    kill(getpid() as usize, SIGUSR1);
+    let (log, len) = take_log();
+    assert_eq!(&log[..len], &[1, 2, 3]);
+
+    // deferred: the second one runs after the first returned
+    install(SIGUSR1, on_usr1_again as usize, SignalActionFlags::empty());
+    kill(getpid() as usize, SIGUSR1);
+    let (log, len) = take_log();
+    assert_eq!(&log[..len], &[10, 11, 20, 21]);
+
+    // not deferred: it nests
+    install(
+        SIGUSR1,
+        on_usr1_again as usize,
+        SignalActionFlags::SA_NODEFER,
+    );
+    kill(getpid() as usize, SIGUSR1);
+    let (log, len) = take_log();
+    assert_eq!(&log[..len], &[10, 20, 21, 11]);
+
+    // nothing to return from
+    assert_eq!(sigreturn(), -1);
+    assert_eq!(canary, getpid() * 7);
+    println!("sig_nested passed!");
+    0
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::*;
+
+const EINVAL: isize = -22;
+
+static mut HANDLED: usize = 0;
+
+fn on_usr1() {
+    vstore!(HANDLED, vload!(HANDLED) + 1);
+    sigreturn();
+}
+
+fn mask() -> u32 {
+    let mut old = 0;
+    assert_eq!(sigprocmask(SIG_BLOCK, None, Some(&mut old)), 0);
+    old
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: on_usr1 as usize,
+        ..SignalAction::default()
+    };
+    let mut old_action = SignalAction::default();
+    assert_eq!(sigaction(SIGUSR1, Some(&action), Some(&mut old_action)), 0);
+    let usr1 = SignalFlags::SIGUSR1.bits() as u32;
+    let usr2 = SignalFlags::SIGUSR2.bits() as u32;
+
+    // blocked around a critical section, delivered on the way out
+    let mut old = u32::MAX;
+    assert_eq!(sigprocmask(SIG_BLOCK, Some(&usr1), Some(&mut old)), 0);
+    assert_eq!(old, 0);
+    assert_eq!(sigprocmask(SIG_BLOCK, Some(&usr2), None), 0);
+    assert_eq!(mask(), usr1 | usr2);
+    kill(getpid() as usize, SIGUSR1);
+    assert_eq!(vload!(HANDLED), 0);
+    assert_eq!(sigpending(), usr1 as isize);
+    assert_eq!(sigprocmask(SIG_UNBLOCK, Some(&usr1), None), 0);
+    assert_eq!(vload!(HANDLED), 1);
+    assert_eq!(mask(), usr2);
+
+    // SIGKILL and SIGSTOP never make it into the mask
+    let unblockable = (SignalFlags::SIGKILL | SignalFlags::SIGSTOP).bits() as u32;
+    assert_eq!(
+        sigprocmask(SIG_SETMASK, Some(&(unblockable | usr1)), None),
+        0
+    );
+    assert_eq!(mask(), usr1);
+
+    assert_eq!(sigprocmask(3, Some(&usr1), None), EINVAL);
+    assert_eq!(sigprocmask(SIG_SETMASK, Some(&0), None), 0);
+    assert_eq!(mask(), 0);
+
+    println!("sig_procmask passed!");
+    0
+}
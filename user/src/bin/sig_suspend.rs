@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::*;
+
+const EINTR: isize = -4;
+
+static mut CHILDREN_EXITED: usize = 0;
+
+fn on_chld() {
+    unsafe {
+        CHILDREN_EXITED += 1;
+    }
+    sigreturn();
+}
+
+fn exited() -> usize {
+    vload!(CHILDREN_EXITED)
+}
+
+/// fork a child exiting after `ms`, and wait for its SIGCHLD the race-free way
+fn wait_child(ms: usize) {
+    let before = exited();
+    let blocked = SignalFlags::SIGCHLD.bits() as u32;
+    // SIGCHLD can't slip in between the check and the wait below
+    sigprocmask(SIG_SETMASK, Some(&blocked), None);
+    let pid = fork();
+    if pid == 0 {
+        sleep(ms);
+        exit(0);
+    }
+    while exited() == before {
+        assert_eq!(sigsuspend(0), EINTR);
+    }
+    // mask is back
+    let mut mask = 0;
+    sigprocmask(SIG_BLOCK, None, Some(&mut mask));
+    assert_eq!(mask, blocked);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: on_chld as usize,
+        ..SignalAction::default()
+    };
+    let mut old = SignalAction::default();
+    assert_eq!(sigaction(SIGCHLD, Some(&action), Some(&mut old)), 0);
+
+    // child is gone before we get to wait
+    sigprocmask(SIG_BLOCK, Some(&(SignalFlags::SIGCHLD.bits() as u32)), None);
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    sleep(100);
+    assert_eq!(exited(), 0);
+    assert_eq!(sigpending(), SignalFlags::SIGCHLD.bits() as isize);
+    assert_eq!(sigsuspend(0), EINTR);
+    assert_eq!(exited(), 1);
+    assert_eq!(sigpending(), 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+
+    // child exits while we are suspended
+    wait_child(0);
+    wait_child(100);
+    assert_eq!(exited(), 3);
+
+    println!("sig_suspend passed!");
+    0
+}
@@ -73,7 +73,7 @@ fn user_sig_test_restore() {
 }
 
 fn kernel_sig_test_ignore() {
-    sigprocmask(SignalFlags::SIGSTOP.bits() as u32);
+    sigprocmask(SIG_BLOCK, Some(&(SignalFlags::SIGSTOP.bits() as u32)), None);
     if kill(getpid() as usize, SignalFlags::SIGSTOP.bits()) < 0 {
         println!("kill faild\n");
         exit(-1);
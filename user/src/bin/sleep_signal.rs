@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::*;
+
+static mut HANDLED_AT: isize = 0;
+
+fn on_usr1() {
+    unsafe {
+        HANDLED_AT = get_time();
+    }
+    sigreturn();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        let mut action = SignalAction::default();
+        let mut old = SignalAction::default();
+        action.handler = on_usr1 as usize;
+        assert!(sigaction(SIGUSR1, Some(&action), Some(&mut old)) >= 0);
+        let start = get_time();
+        sleep(500);
+        let handled_at = unsafe { HANDLED_AT };
+        // the signal woke the sleep up right away, and sleep carried on afterwards
+        assert!(handled_at > 0 && handled_at - start < 400);
+        assert!(get_time() - start >= 500);
+        exit(0);
+    }
+    sleep(100);
+    assert_eq!(kill(pid as usize, SIGUSR1), 0);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("sleep_signal passed!");
+    0
+}
@@ -0,0 +1,136 @@
+#![no_std]
+#![no_main]
+
+//! `spawn_bench [rounds]`: mean latency of fork+exit, fork+exec and thread_create+waittid
+//! over `rounds` (1000) of each. The first run records them in `spawn_bench.base`, later
+//! ones exit with 1 if any got more than twice as slow as recorded. Remove the file to
+//! record anew.
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use user_lib::{
+    close, exec, exit, fork, get_time_ns, open, process_stats, read, thread_create, waitpid,
+    waittid, write, OpenFlags,
+};
+
+const PROG: &str = "spawn_bench\0";
+const BASELINE: &str = "spawn_bench.base\0";
+const NAMES: [&str; 3] = ["fork+exit", "fork+exec", "thread_create+waittid"];
+/// slower than the baseline by this factor is a regression
+const TOLERANCE: u64 = 2;
+
+fn fork_exit() {
+    let pid = fork();
+    if pid == 0 {
+        exit(0)
+    }
+    assert_eq!(waitpid(pid as usize, &mut 0), pid);
+}
+
+fn fork_exec() {
+    let pid = fork();
+    if pid == 0 {
+        let args = [PROG.as_ptr(), "exit\0".as_ptr(), core::ptr::null()];
+        exec(PROG, &args);
+        panic!("exec {} failed", PROG);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+fn worker() -> ! {
+    exit(0)
+}
+
+fn thread_join() {
+    let tid = thread_create(worker as usize, 0);
+    assert!(tid > 0);
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+/// mean ns of `f`
+fn measure(rounds: usize, f: fn()) -> u64 {
+    let start = get_time_ns();
+    for _ in 0..rounds {
+        f();
+    }
+    (get_time_ns() - start) / rounds as u64
+}
+
+fn read_baseline() -> Option<Vec<u64>> {
+    let fd = open(BASELINE, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut buf = [0u8; 128];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    let text = core::str::from_utf8(&buf[..len.max(0) as usize]).ok()?;
+    let baseline: Vec<u64> = text
+        .split_whitespace()
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    Some(baseline).filter(|v| v.len() == NAMES.len())
+}
+
+fn write_baseline(results: &[u64]) {
+    let fd = open(
+        BASELINE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    let text: Vec<String> = results.iter().map(|ns| format!("{}", ns)).collect();
+    write(fd as usize, text.join(" ").as_bytes());
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    // exec'ed by fork_exec
+    if argc == 2 && argv[1] == "exit" {
+        return 0;
+    }
+    let rounds = match argv.get(1).map(|v| v.parse::<usize>()) {
+        None => 1000,
+        Some(Ok(rounds)) if rounds > 0 => rounds,
+        _ => {
+            println!("usage: spawn_bench [rounds]");
+            return -1;
+        }
+    };
+
+    let before = process_stats();
+    let results = [
+        measure(rounds, fork_exit),
+        measure(rounds, fork_exec),
+        measure(rounds, thread_join),
+    ];
+    let after = process_stats();
+    assert_eq!(after.live, before.live);
+
+    let baseline = read_baseline();
+    let mut regressed = false;
+    for (i, (name, ns)) in NAMES.iter().zip(results).enumerate() {
+        match &baseline {
+            Some(baseline) if ns > baseline[i] * TOLERANCE => {
+                println!("{}: {} ns, regressed from {} ns", name, ns, baseline[i]);
+                regressed = true;
+            }
+            Some(baseline) => println!("{}: {} ns, baseline {} ns", name, ns, baseline[i]),
+            None => println!("{}: {} ns", name, ns),
+        }
+    }
+    println!(
+        "{} processes created, peak {} at once",
+        after.created - before.created,
+        after.peak
+    );
+    if baseline.is_none() {
+        write_baseline(&results);
+    }
+    regressed as i32
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, free_frames, process_stats, thread_create, wait, waitpid, waittid};
+
+/// children alive at once
+const WIDTH: usize = 32;
+/// threads of each child, each forks a grandchild
+const THREADS: usize = 4;
+const ROUNDS: usize = 8;
+
+fn worker() -> ! {
+    let pid = fork();
+    if pid == 0 {
+        exit(0)
+    }
+    assert_eq!(waitpid(pid as usize, &mut 0), pid);
+    exit(0)
+}
+
+fn child() -> ! {
+    let mut tids = [0; THREADS];
+    for tid in tids.iter_mut() {
+        let new = thread_create(worker as usize, 0);
+        assert!(new > 0);
+        *tid = new as usize;
+    }
+    for tid in tids {
+        assert_eq!(waittid(tid), 0);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let frames = free_frames();
+    let before = process_stats();
+    for round in 0..ROUNDS {
+        for _ in 0..WIDTH {
+            let pid = fork();
+            assert!(pid >= 0, "fork failed in round {}", round);
+            if pid == 0 {
+                child();
+            }
+        }
+        for _ in 0..WIDTH {
+            let mut exit_code = 0;
+            assert!(wait(&mut exit_code) > 0);
+            assert_eq!(exit_code, 0);
+        }
+    }
+    let after = process_stats();
+    // all reaped, nothing leaked
+    assert_eq!(after.live, before.live);
+    assert!(after.created - before.created >= ROUNDS * WIDTH * (1 + THREADS));
+    assert!(after.peak >= before.live + WIDTH);
+    assert_eq!(free_frames(), frames);
+    println!(
+        "spawn_stress passed, {} processes, peak {} at once!",
+        after.created - before.created,
+        after.peak
+    );
+    0
+}
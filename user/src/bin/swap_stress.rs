@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+//! Touch more anonymous pages than there are frames free: the first ones go to swap to make
+//! room, and come back as they were when touched again.
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{free_frames, getrusage, mmap, munmap, MMapFlags, RUsage, RUSAGE_SELF};
+
+const PAGE_SIZE: usize = 4096;
+/// rw
+const PROT: usize = 0b011;
+/// pages of each mmap
+const CHUNK: usize = 16;
+/// pages touched past those free
+const EXTRA: usize = 256;
+
+fn majflt() -> usize {
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    usage.majflt
+}
+
+/// Page `i` of those touched, in the chunks one after another
+fn page(chunks: &[usize], i: usize) -> *mut usize {
+    (chunks[i / CHUNK] + i % CHUNK * PAGE_SIZE) as *mut usize
+}
+
+/// The first and the last word of each page tell which it is
+fn fill(page: *mut usize, i: usize) {
+    unsafe {
+        page.write_volatile(i);
+        page.add(PAGE_SIZE / 8 - 1).write_volatile(!i);
+    }
+}
+
+fn check(page: *mut usize, i: usize) {
+    unsafe {
+        assert_eq!(page.read_volatile(), i, "page {} lost", i);
+        assert_eq!(page.add(PAGE_SIZE / 8 - 1).read_volatile(), !i);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let free = free_frames();
+    assert!(free > 0);
+    let pages = (free as usize + EXTRA).next_multiple_of(CHUNK);
+    let mut chunks = Vec::with_capacity(pages / CHUNK);
+    for _ in 0..pages / CHUNK {
+        let base = mmap(0, CHUNK * PAGE_SIZE, PROT, MMapFlags::MAP_ANON, 0, 0);
+        assert!(base > 0);
+        chunks.push(base as usize);
+    }
+    for i in 0..pages {
+        fill(page(&chunks, i), i);
+    }
+    println!(
+        "swap_stress: {} pages touched, {} frames were free",
+        pages, free
+    );
+
+    // the oldest went out first, reading them back is i/o
+    let before = majflt();
+    for i in 0..EXTRA {
+        check(page(&chunks, i), i);
+    }
+    assert!(majflt() > before);
+    // written again, out and in once more
+    for i in 0..EXTRA {
+        fill(page(&chunks, i), i + 1);
+    }
+    for i in pages - EXTRA..pages {
+        check(page(&chunks, i), i);
+    }
+    for i in 0..EXTRA {
+        check(page(&chunks, i), i + 1);
+    }
+
+    for &base in chunks.iter() {
+        assert_eq!(munmap(base, CHUNK * PAGE_SIZE), 0);
+    }
+    // frames of pages in memory and slots of those out are all back
+    assert!(free_frames() + CHUNK as isize >= free);
+    println!("swap_stress passed!");
+    0
+}
@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::sync;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let (blocks, pages) = sync();
+    println!("synced {} blocks, {} mapped pages", blocks, pages);
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    condvar_create, condvar_destroy, exit, mutex_blocking_create, mutex_destroy, mutex_lock,
+    mutex_unlock, semaphore_acquire, semaphore_create, semaphore_destroy, semaphore_release, sleep,
+    thread_create, waittid,
+};
+
+const EBUSY: isize = -16;
+const EINVAL: isize = -22;
+
+static mut SEM_ID: usize = 0;
+
+pub fn waiter() -> ! {
+    semaphore_acquire(unsafe { SEM_ID });
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // locked mutex can't go
+    let mutex_id = mutex_blocking_create() as usize;
+    mutex_lock(mutex_id);
+    assert_eq!(mutex_destroy(mutex_id), EBUSY);
+    mutex_unlock(mutex_id);
+    assert_eq!(mutex_destroy(mutex_id), 0);
+    assert_eq!(mutex_destroy(mutex_id), EINVAL);
+    // slot is reused
+    assert_eq!(mutex_blocking_create() as usize, mutex_id);
+
+    // neither can a semaphore someone is blocked on
+    let sem_id = semaphore_create(0) as usize;
+    unsafe {
+        SEM_ID = sem_id;
+    }
+    let tid = thread_create(waiter as usize, 0);
+    sleep(100);
+    assert_eq!(semaphore_destroy(sem_id), EBUSY);
+    semaphore_release(sem_id);
+    waittid(tid as usize);
+    assert_eq!(semaphore_destroy(sem_id), 0);
+
+    let condvar_id = condvar_create() as usize;
+    assert_eq!(condvar_destroy(condvar_id), 0);
+    assert_eq!(condvar_destroy(condvar_id), EINVAL);
+    assert_eq!(semaphore_destroy(usize::MAX), EINVAL);
+    assert_eq!(condvar_create() as usize, condvar_id);
+
+    println!("sync_destroy passed!");
+    0
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, getpid};
+
+const ROUNDS: usize = 100_000;
+
+/// Round trip cost of the cheapest syscall: user space maps nothing of the kernel but the
+/// trampoline and trap contexts, so every syscall switches `satp` (and flushes the TLB) twice.
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    for _ in 0..ROUNDS {
+        getpid();
+    }
+    let elapsed = (get_time() - start) as usize;
+    println!(
+        "syscall_bench: {} getpid in {}ms, {}ns per syscall",
+        ROUNDS,
+        elapsed,
+        elapsed * 1_000_000 / ROUNDS
+    );
+    0
+}
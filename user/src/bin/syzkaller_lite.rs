@@ -0,0 +1,326 @@
+#![no_std]
+#![no_main]
+
+//! Random syscalls, biased toward fds open and pointers mapped, each program of them run by a
+//! child of its own. The seed of a program is printed before it runs: if the kernel goes down,
+//! the last one printed did it, and `syzkaller_lite 1 <seed> -v` runs it again printing each
+//! call before it's made.
+//!
+//! Calls reaching outside the child (kill, fork, exec, the filesystem by path, sockets) aren't
+//! made, nor those blocking for good that no signal cuts short (locks, pipes). A child still
+//! running after `TIMEOUT_NS` is killed.
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{format, vec};
+use user_lib::{
+    close, dup, dup2, exit, fork, get_time_ns, kill, open, sleep, syscall::syscall, unlink,
+    waitpid_n, write, OpenFlags, SIGKILL,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// syscalls each program makes
+const CALLS: usize = 64;
+/// a program has this long to finish, and as long again once killed
+const TIMEOUT_NS: u64 = 500_000_000;
+/// where a child logs to, out of the range of fds fuzzed
+const LOG_FD: usize = 100;
+/// read-only fd 1 of each child
+const FILE: &str = "syzkaller_lite.tmp\0";
+
+/// ids made on purpose, with names to log
+const SYSCALLS: &[(usize, &str)] = &[
+    (17, "getcwd"),
+    (24, "dup"),
+    (25, "fcntl"),
+    (32, "flock"),
+    (46, "ftruncate"),
+    (47, "fallocate"),
+    (49, "chdir"),
+    (50, "fchdir"),
+    (57, "close"),
+    (61, "getdents"),
+    (62, "lseek"),
+    (63, "read"),
+    (64, "write"),
+    (65, "readv"),
+    (66, "writev"),
+    (67, "pread64"),
+    (68, "pwrite64"),
+    (73, "ppoll"),
+    (80, "fstat"),
+    (81, "sync"),
+    (82, "fsync"),
+    (88, "utimensat"),
+    (93, "exit"),
+    (101, "sleep"),
+    (113, "clock_gettime"),
+    (124, "yield"),
+    (133, "sigsuspend"),
+    (134, "sigaction"),
+    (135, "sigprocmask"),
+    (136, "sigpending"),
+    (139, "sigreturn"),
+    (144, "setgid"),
+    (146, "setuid"),
+    (153, "times"),
+    (155, "getpgid"),
+    (165, "getrusage"),
+    (169, "get_time"),
+    (172, "getpid"),
+    (174, "getuid"),
+    (176, "getgid"),
+    (208, "setsockopt"),
+    (209, "getsockopt"),
+    (214, "brk"),
+    (215, "munmap"),
+    (222, "mmap"),
+    (226, "mprotect"),
+    (227, "msync"),
+    (233, "madvise"),
+    (260, "waitpid"),
+    (283, "membarrier"),
+    (1000, "thread_create"),
+    (1001, "gettid"),
+    (1002, "waittid"),
+    (1003, "set_thread_name"),
+    (1004, "get_thread_name"),
+    (1010, "mutex_create"),
+    (1012, "mutex_unlock"),
+    (1013, "mutex_destroy"),
+    (1020, "semaphore_create"),
+    (1021, "semaphore_up"),
+    (1023, "semaphore_destroy"),
+    (1030, "condvar_create"),
+    (1031, "condvar_signal"),
+    (1033, "condvar_destroy"),
+    (1040, "barrier_create"),
+    (1042, "barrier_destroy"),
+    (1050, "tcgetpgrp"),
+    (1060, "dup3"),
+    (2000, "free_frames"),
+    (2001, "block_stats"),
+    (2002, "process_stats"),
+    (2003, "meminfo"),
+];
+
+/// ids never made, random ones included
+const DENIED: &[usize] = &[
+    29,   // connect
+    30,   // listen
+    31,   // accept, spins in the kernel until a connection comes
+    34,   // mkdirat
+    35,   // unlinkat
+    37,   // linkat
+    38,   // renameat
+    39,   // umount2
+    40,   // mount
+    53,   // chmod
+    54,   // chown
+    56,   // openat
+    59,   // pipe2, a read of the empty pipe blocks for good
+    60,   // quotactl
+    129,  // kill
+    154,  // setpgid
+    220,  // fork
+    221,  // exec
+    261,  // prlimit64, of other processes too
+    1011, // mutex_lock
+    1022, // semaphore_down
+    1032, // condvar_wait
+    1041, // barrier_wait
+    1051, // tcsetpgrp
+];
+
+/// random ids go up to this, past all there are
+const MAX_ID: usize = 2100;
+
+/// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // zero is a fixed point
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) as usize
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.next() % n
+    }
+}
+
+fn pick_id(rng: &mut Rng) -> usize {
+    if rng.below(8) != 0 {
+        return SYSCALLS[rng.below(SYSCALLS.len())].0;
+    }
+    // unknown ones are ENOSYS
+    loop {
+        let id = rng.below(MAX_ID);
+        if !DENIED.contains(&id) {
+            return id;
+        }
+    }
+}
+
+/// `scratch`: of the pages pointer args point into
+fn pick_arg(rng: &mut Rng, scratch: usize) -> usize {
+    match rng.below(8) {
+        // fds, flags, lengths
+        0 | 1 => rng.below(8),
+        // valid, the kernel may write there
+        2 | 3 => scratch + rng.below(2 * PAGE_SIZE),
+        4 => {
+            let edges = [
+                0,
+                // read-only
+                main as usize,
+                // runs off the end
+                scratch + 2 * PAGE_SIZE - 1,
+                // just past sv39 user space
+                1 << 38,
+                // the kernel, as mapped and as it is
+                0xffff_ffc0_8020_0000,
+                0x8020_0000,
+                usize::MAX,
+                usize::MAX - PAGE_SIZE + 1,
+            ];
+            edges[rng.below(edges.len())]
+        }
+        5 => 1 << rng.below(64),
+        _ => rng.next(),
+    }
+}
+
+fn name_of(id: usize) -> &'static str {
+    match SYSCALLS.iter().find(|(i, _)| *i == id) {
+        Some((_, name)) => name,
+        None => "?",
+    }
+}
+
+fn log(line: &str) {
+    write(LOG_FD, line.as_bytes());
+}
+
+/// Fds 0 the root, 1 `FILE` and 2 the root again, then the calls of program `seed`
+fn run(seed: u64, verbose: bool) -> ! {
+    assert_eq!(dup2(1, LOG_FD), LOG_FD as isize);
+    for fd in 0..3 {
+        close(fd);
+    }
+    assert_eq!(open("/\0", OpenFlags::RDONLY), 0);
+    assert_eq!(open(FILE, OpenFlags::RDONLY), 1);
+    assert_eq!(dup(0), 2);
+
+    let mut rng = Rng::new(seed);
+    // random bytes, nul now and then to end strings
+    let mut scratch = vec![0u8; 2 * PAGE_SIZE];
+    for byte in scratch.iter_mut() {
+        *byte = match rng.below(16) {
+            0 => 0,
+            _ => rng.next() as u8,
+        };
+    }
+    let scratch = scratch.leak().as_mut_ptr() as usize;
+    for _ in 0..CALLS {
+        let id = pick_id(&mut rng);
+        let args = [
+            pick_arg(&mut rng, scratch),
+            pick_arg(&mut rng, scratch),
+            pick_arg(&mut rng, scratch),
+        ];
+        if verbose {
+            log(&format!(
+                "{}({}, {:#x}, {:#x}, {:#x})",
+                name_of(id),
+                id,
+                args[0],
+                args[1],
+                args[2]
+            ));
+        }
+        let ret = syscall(id, args);
+        if verbose {
+            log(&format!(" = {}\n", ret));
+        }
+    }
+    exit(0)
+}
+
+/// Reap `pid` if it exits within `TIMEOUT_NS`, how it exited doesn't matter
+fn reap(pid: usize) -> bool {
+    let start = get_time_ns();
+    let mut exit_code = 0;
+    while get_time_ns() - start < TIMEOUT_NS {
+        match waitpid_n(pid, &mut exit_code) {
+            -2 => sleep(10),
+            ret => {
+                assert_eq!(ret, pid as isize);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let programs = argv.get(1).map(|v| v.parse::<u64>());
+    let seed = argv.get(2).map(|v| v.parse::<u64>());
+    let (programs, seed) = match (programs, seed) {
+        (None, _) => (64, get_time_ns()),
+        (Some(Ok(programs)), None) => (programs, get_time_ns()),
+        (Some(Ok(programs)), Some(Ok(seed))) => (programs, seed),
+        _ => {
+            println!("usage: syzkaller_lite [programs] [seed] [-v]");
+            return -1;
+        }
+    };
+    let verbose = argc > 3 && argv[3] == "-v";
+
+    let fd = open(
+        FILE,
+        OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC,
+    );
+    assert!(fd > 0);
+    write(fd as usize, b"syzkaller_lite\n");
+    close(fd as usize);
+
+    let (mut exited, mut killed, mut stuck) = (0, 0, 0);
+    for seed in seed..seed + programs {
+        println!("syzkaller_lite: program seed {}", seed);
+        let pid = fork();
+        assert!(pid >= 0);
+        if pid == 0 {
+            run(seed, verbose);
+        }
+        let pid = pid as usize;
+        if reap(pid) {
+            exited += 1;
+            continue;
+        }
+        kill(pid, SIGKILL);
+        // blocked where signals don't reach otherwise, init reaps it if it ever exits
+        if reap(pid) {
+            killed += 1;
+        } else {
+            stuck += 1;
+        }
+    }
+    unlink(FILE);
+    println!(
+        "syzkaller_lite: {} programs, {} exited, {} killed, {} stuck",
+        programs, exited, killed, stuck
+    );
+    println!("syzkaller_lite passed!");
+    0
+}
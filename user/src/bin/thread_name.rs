@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exit, fork, get_thread_name, gettid, set_thread_name, thread_create, waitpid, waittid, yield_,
+    THREAD_NAME_MAX,
+};
+
+static NAMED: AtomicBool = AtomicBool::new(false);
+
+/// Name of thread `tid`, checked to fit the buffer
+fn name_of(tid: usize, buf: &mut [u8; THREAD_NAME_MAX + 1]) -> &str {
+    let len = get_thread_name(tid, buf);
+    assert!(len >= 0);
+    core::str::from_utf8(&buf[..len as usize]).unwrap()
+}
+
+fn worker() -> ! {
+    let mut buf = [0u8; THREAD_NAME_MAX + 1];
+    while !NAMED.load(Ordering::Acquire) {
+        yield_();
+    }
+    assert_eq!(name_of(gettid() as usize, &mut buf), "worker");
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; THREAD_NAME_MAX + 1];
+    // named after the program
+    assert_eq!(name_of(0, &mut buf), "thread_name");
+
+    // a new thread gets its creator's name, until named otherwise
+    let tid = thread_create(worker as usize, 0) as usize;
+    assert_eq!(name_of(tid, &mut buf), "thread_name");
+    assert_eq!(set_thread_name(tid, "worker\0"), 0);
+    assert_eq!(name_of(tid, &mut buf), "worker");
+    NAMED.store(true, Ordering::Release);
+    assert_eq!(waittid(tid), 0);
+
+    // too long to keep, to give back, no such thread
+    assert_eq!(set_thread_name(0, "a name longer than that\0"), -36);
+    assert_eq!(get_thread_name(0, &mut buf[..4]), -34);
+    assert_eq!(set_thread_name(tid + 8, "nobody\0"), -3);
+
+    // a forked child goes by the name of the thread that forked
+    assert_eq!(set_thread_name(0, "parent\0"), 0);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(name_of(0, &mut buf), "parent");
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("thread_name passed!");
+    0
+}
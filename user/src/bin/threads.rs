@@ -6,7 +6,7 @@ extern crate user_lib;
 extern crate alloc;
 
 use alloc::vec::Vec;
-use user_lib::{exit, thread_create, waittid};
+use user_lib::{exit, gettid, thread_create, waittid};
 
 pub fn thread_a() -> ! {
     for _ in 0..3 {
@@ -39,6 +39,10 @@ pub fn main() -> i32 {
         let exit_code = waittid(*tid as usize);
         println!("\nthread#{} exited with code {}", tid, exit_code);
     }
+    // collected already
+    assert_eq!(waittid(v[0] as usize), -3);
+    // waiting for itself
+    assert_eq!(waittid(gettid() as usize), -35);
     println!("main thread exited.");
     0
 }
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, free_frames, thread_create, waittid};
+
+/// threads alive at once
+const BATCH: usize = 16;
+const ROUNDS: usize = 1250;
+
+fn worker(round: usize) -> ! {
+    exit(round as i32)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut baseline = 0;
+    let mut tids = [0; BATCH];
+    for round in 0..ROUNDS {
+        for tid in tids.iter_mut() {
+            let new = thread_create(worker as usize, round);
+            assert!(new > 0, "thread_create failed in round {}", round);
+            // freed tids come back, they don't pile up
+            assert!(new as usize <= BATCH, "tid {} in round {}", new, round);
+            *tid = new as usize;
+        }
+        for &tid in tids.iter() {
+            assert_eq!(waittid(tid), round as isize);
+        }
+        // the first round leaves page table nodes for the stacks behind, nothing after
+        if round == 0 {
+            baseline = free_frames();
+        }
+    }
+    assert_eq!(free_frames(), baseline);
+    println!("threads_churn passed, {} threads!", ROUNDS * BATCH);
+    0
+}
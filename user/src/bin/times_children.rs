@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getrusage, times, waitpid, RUsage, Tms, RUSAGE_CHILDREN, RUSAGE_SELF,
+};
+
+/// burn cpu in user mode for about `ms`
+fn spin(ms: isize) {
+    let start = get_time();
+    while get_time() - start < ms {
+        core::hint::spin_loop();
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    spin(20);
+    let mut before = Tms::default();
+    times(&mut before);
+    assert!(before.utime > 0);
+    assert_eq!(before.cutime, 0);
+
+    let pid = fork();
+    if pid == 0 {
+        spin(100);
+        // grandchild time goes to the child once reaped, then on to us
+        let pid = fork();
+        if pid == 0 {
+            spin(100);
+            exit(0);
+        }
+        let mut exit_code: i32 = 0;
+        waitpid(pid as usize, &mut exit_code);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    let mut after = Tms::default();
+    times(&mut after);
+    // both spins, give or take timer slack
+    let children = after.cutime + after.cstime;
+    assert!(children >= 150_000, "children time {}us", children);
+
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut usage), 0);
+    assert_eq!(usage.utime.sec * 1_000_000 + usage.utime.usec, after.cutime);
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    assert_eq!(getrusage(1, &mut usage), -22);
+    println!("times_children passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use user_lib::{
+    exit, fork, read, setpgid, sigaction, sigreturn, waitpid, SignalAction, SIGTTIN, SIG_IGN,
+};
+
+/// stopped or handled SIGTTIN
+const EINTR: isize = -4;
+/// SIGTTIN ignored
+const EIO: isize = -5;
+
+static TTIN: AtomicBool = AtomicBool::new(false);
+
+fn on_ttin() {
+    TTIN.store(true, Ordering::SeqCst);
+    sigreturn();
+}
+
+/// Read stdin from a new process group, not the terminal's foreground one
+fn read_in_background(handler: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(setpgid(0, 0), 0);
+        let mut action = SignalAction::default();
+        action.handler = handler;
+        assert!(sigaction(SIGTTIN, Some(&action), None) >= 0);
+        let mut buf = [0u8; 1];
+        let ret = read(0, &mut buf);
+        exit(match ret {
+            EINTR if TTIN.load(Ordering::SeqCst) => 1,
+            EIO => 2,
+            _ => 0,
+        });
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // SIGTTIN sent to the reader, not bytes given to it
+    assert_eq!(read_in_background(on_ttin as usize), 1);
+    // nobody to stop, an error instead
+    assert_eq!(read_in_background(SIG_IGN), 2);
+    println!("tty_background passed!");
+    0
+}
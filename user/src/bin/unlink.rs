@@ -1,7 +1,7 @@
 #![no_std]
 #![no_main]
 
-use user_lib::{exit, fstat, open, unlink, OpenFlags, Stat, StatMode};
+use user_lib::{exit, fstat, open, rmdir, unlink, OpenFlags, Stat, StatMode};
 
 #[macro_use]
 extern crate user_lib;
@@ -23,13 +23,18 @@ fn main(argc: usize, argv: &[&str]) -> i32 {
     }
 
     const DIRENT_SZ: usize = 32;
-    if stat.mode == StatMode::DIR && stat.size as usize > 2 * DIRENT_SZ {
+    if stat.mode.contains(StatMode::DIR) && stat.size as usize > 2 * DIRENT_SZ {
         // 2 dirent: . and ..
         println!("Directory not empty!");
         exit(-1);
     }
 
-    if unlink(path) == -1 {
+    let removed = if stat.mode.contains(StatMode::DIR) {
+        rmdir(path)
+    } else {
+        unlink(path)
+    };
+    if removed == -1 {
         println!("Error unlink {}", path);
         exit(-1);
     }
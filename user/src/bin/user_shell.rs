@@ -6,9 +6,12 @@ extern crate alloc;
 #[macro_use]
 extern crate user_lib;
 
-use alloc::{collections::btree_set::BTreeSet, string::String, vec::Vec};
+use alloc::{collections::btree_set::BTreeSet, format, string::String, vec::Vec};
 use user_lib::{
-    chdir, close, console::getchar, dup, exec, fork, getcwd, open, pipe, waitpid, OpenFlags,
+    chdir, close,
+    console::{getchar, EOT},
+    dup2, exec, fork, getcwd, getdents, getpgid, open, pipe2, setpgid, tcsetpgrp, times, waitpid,
+    Dirent, FileType, OpenFlags, Tms,
 };
 
 const BS: u8 = 0x08;
@@ -74,7 +77,7 @@ impl ProcessArguments {
 }
 
 struct Completer {
-    // candidates
+    // candidates for the command
     can: BTreeSet<String>,
 }
 
@@ -93,50 +96,83 @@ impl Completer {
         self.can.extend(iter);
     }
 
-    pub fn hint_for(&self, s: &str) -> Vec<&String> {
-        self.can.iter().filter(|v| v.starts_with(s)).collect()
+    /// Completions of `s`: a command if it's the first word of the line, else a path, the
+    /// entries of the dir it's in so far (dirs end with `/` to go on with)
+    pub fn hint_for(&self, s: &str, first: bool) -> Vec<String> {
+        if first && !s.contains('/') {
+            return self
+                .can
+                .iter()
+                .filter(|v| v.starts_with(s))
+                .cloned()
+                .collect();
+        }
+        let (dir, name) = match s.rfind('/') {
+            Some(i) => s.split_at(i + 1),
+            None => ("", s),
+        };
+        let path = if dir.is_empty() { "." } else { dir };
+        dir_entries(path)
+            .into_iter()
+            .filter(|(entry, _)| entry.starts_with(name))
+            .map(|(entry, ftype)| {
+                let slash = if ftype == FileType::DIR { "/" } else { "" };
+                format!("{}{}{}", dir, entry, slash)
+            })
+            .collect()
     }
 }
 
-fn root_bin() -> Vec<String> {
-    use user_lib::{getdents, Dirent, FileType};
+/// Entries of dir `path` but `.` and `..`, with their types. Empty if it can't be read.
+fn dir_entries(path: &str) -> Vec<(String, FileType)> {
     let mut v = Vec::new();
-
-    let fd = open("/\0", OpenFlags::RDONLY);
-    if fd == -1 {
+    let fd = open(format!("{}\0", path).as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
         return v;
     }
 
     const BUF_SIZE: usize = 16;
     let mut entries = alloc::vec![Dirent::default(); BUF_SIZE];
-    let mut n = BUF_SIZE;
     loop {
-        n = match getdents(fd as usize, &mut entries.as_mut_slice()[..n]) {
-            -1 | 0 => break,
-            v => v as usize,
+        let n = match getdents(fd as usize, &mut entries) {
+            n if n <= 0 => break,
+            n => n as usize,
         };
-        for i in 0..n {
-            let entry = &entries[i];
-            if entry.ftype == FileType::REG {
-                v.push(String::from(entry.name()));
+        for entry in &entries[..n] {
+            match entry.name() {
+                "." | ".." => {}
+                name => v.push((String::from(name), entry.ftype)),
             }
         }
     }
+    close(fd as usize);
     v
 }
 
+fn root_bin() -> Vec<String> {
+    dir_entries("/")
+        .into_iter()
+        .filter(|(_, ftype)| *ftype == FileType::REG)
+        .map(|(name, _)| name)
+        .collect()
+}
+
 #[no_mangle]
 fn main() -> i32 {
     println!("Rust user shell");
     // completer
     let mut comp = Completer::new();
     comp.load_root();
-    comp.load_ad_hoc([String::from("cd"), String::from("pwd")]);
+    comp.load_ad_hoc([
+        String::from("cd"),
+        String::from("pwd"),
+        String::from("time"),
+    ]);
     let mut line: String = String::new();
     let mut comp_leftover: Option<u8> = None;
     loop {
         line.clear();
-        print!("{}", PROMPT);
+        print_prompt();
         'repl: loop {
             let c = match comp_leftover.take() {
                 Some(v) => v,
@@ -148,7 +184,8 @@ fn main() -> i32 {
                         Some(v) if !v.is_empty() => String::from(v),
                         _ => continue 'repl,
                     };
-                    let hints = comp.hint_for(&par_input);
+                    let first = line.split_ascii_whitespace().count() == 1;
+                    let hints = comp.hint_for(&par_input, first);
                     if hints.is_empty() {
                         continue 'repl;
                     }
@@ -177,6 +214,13 @@ fn main() -> i32 {
                 LF | CR => {
                     println!("");
                     let input = line.trim();
+                    // `time <job>`: report how long the job took
+                    let (timed, input) = match input.strip_prefix("time") {
+                        Some(rest) if rest.is_empty() || rest.starts_with(' ') => {
+                            (true, rest.trim())
+                        }
+                        _ => (false, input),
+                    };
                     if input.is_empty() {
                         break 'repl;
                     }
@@ -239,7 +283,7 @@ fn main() -> i32 {
                     if !valid {
                         println!("Invalid command: Inputs/Outputs cannot be correctly binded!");
                         line.clear();
-                        print!("{}", PROMPT);
+                        print_prompt();
                         continue;
                     }
                     // create pipes
@@ -247,16 +291,22 @@ fn main() -> i32 {
                     if !process_arguments_list.is_empty() {
                         for _ in 0..process_arguments_list.len() - 1 {
                             let mut pipe_fd = [0usize; 2];
-                            pipe(&mut pipe_fd);
+                            // only the ends dup'ed to 0 and 1 are left to the progs
+                            pipe2(&mut pipe_fd, OpenFlags::CLOEXEC, 0);
                             pipes_fd.push(pipe_fd);
                         }
                     }
+                    let mut start = Tms::default();
+                    let start_us = times(&mut start);
                     let mut children = Vec::new();
+                    // the whole pipeline is one job, led by its first process
+                    let mut job_pgid = 0;
                     for (i, process_args) in process_arguments_list.iter().enumerate() {
                         // fork & exec
                         let pid = fork();
                         // child process
                         if pid == 0 {
+                            setpgid(0, job_pgid);
                             let input = &process_args.input;
                             let output = &process_args.output;
                             let args = &process_args.args;
@@ -269,8 +319,7 @@ fn main() -> i32 {
                                     return -4;
                                 }
                                 let input_fd = input_fd as usize;
-                                close(0); // close stdin
-                                assert_eq!(dup(input_fd), 0); // dup to 0
+                                assert_eq!(dup2(input_fd, 0), 0);
                                 close(input_fd);
                             }
                             // redirect output
@@ -284,26 +333,18 @@ fn main() -> i32 {
                                     return -4;
                                 }
                                 let output_fd = output_fd as usize;
-                                close(1); // close stdout
-                                assert_eq!(dup(output_fd), 1); // dup to 1
+                                assert_eq!(dup2(output_fd, 1), 1);
                                 close(output_fd);
                             }
                             // recv input from prev prog
                             if i > 0 {
-                                close(0);
                                 let read_end = pipes_fd[i - 1][0];
-                                assert_eq!(dup(read_end), 0);
+                                assert_eq!(dup2(read_end, 0), 0);
                             }
                             // send output to next prog
                             if i < process_arguments_list.len() - 1 {
-                                close(1);
                                 let write_end = pipes_fd[i][1];
-                                assert_eq!(dup(write_end), 1);
-                            }
-                            // close all pipe ends inherited from parent process
-                            for pipe_fd in pipes_fd.iter() {
-                                close(pipe_fd[0]);
-                                close(pipe_fd[1]);
+                                assert_eq!(dup2(write_end, 1), 1);
                             }
                             // exec
                             if exec(&args[0], args_addr.as_slice()) == -1 {
@@ -314,6 +355,12 @@ fn main() -> i32 {
                         }
                         // shell process
                         else {
+                            // both sides set it, whoever runs first
+                            setpgid(pid as usize, job_pgid);
+                            if job_pgid == 0 {
+                                job_pgid = pid as usize;
+                                tcsetpgrp(0, job_pgid);
+                            }
                             children.push(pid);
                         }
                     }
@@ -329,6 +376,15 @@ fn main() -> i32 {
                         assert_eq!(pid, exit_pid);
                         // println!("[shell] Process: pid={} exit_code={}", pid, exit_code);
                     }
+                    // take the terminal back
+                    tcsetpgrp(0, getpgid(0) as usize);
+                    if timed {
+                        let mut end = Tms::default();
+                        let end_us = times(&mut end);
+                        println!("real\t{}", format_us((end_us - start_us) as usize));
+                        println!("user\t{}", format_us(end.cutime - start.cutime));
+                        println!("sys\t{}", format_us(end.cstime - start.cstime));
+                    }
                     break 'repl;
                 }
                 BS | DL => {
@@ -337,6 +393,12 @@ fn main() -> i32 {
                         line.pop();
                     }
                 }
+                // Ctrl-D logs out on an empty line only
+                EOT if line.is_empty() => {
+                    println!("");
+                    return 0;
+                }
+                EOT => {}
                 _ => {
                     print!("{}", c as char);
                     line.push(c as char);
@@ -346,6 +408,11 @@ fn main() -> i32 {
     }
 }
 
+/// `1.234567s`
+fn format_us(us: usize) -> String {
+    format!("{}.{:06}s", us / 1_000_000, us % 1_000_000)
+}
+
 fn clear_console_ch(n: usize) {
     for _ in 0..n {
         print!("{}", BS as char); // move cursor back
@@ -355,6 +422,24 @@ fn clear_console_ch(n: usize) {
 }
 
 const UNKNOWN_BUILTIN: &'static str = "unknown builtin command!";
+/// Absolute path of cwd, `None` if it's removed
+fn cwd() -> Option<String> {
+    let mut path = [0; 256];
+    if getcwd(&mut path[..]) < 0 {
+        return None;
+    }
+    let len = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+    core::str::from_utf8(&path[..len]).ok().map(String::from)
+}
+
+/// Prompt led by cwd, which may be removed from under the shell
+fn print_prompt() {
+    match cwd() {
+        Some(path) => print!("{} {}", path, PROMPT),
+        _ => print!("(deleted) {}", PROMPT),
+    }
+}
+
 const WRONG_NUM_ARGS: &'static str = "wrong number of args!";
 fn exec_builtin(cmd: &str, args: &[String]) -> Result<(), &'static str> {
     match cmd.trim_end_matches('\0') {
@@ -368,13 +453,10 @@ fn exec_builtin(cmd: &str, args: &[String]) -> Result<(), &'static str> {
                 return Err("Error cd");
             }
         }
-        "pwd" => {
-            let mut path = [0; 256];
-            if getcwd(&mut path[..]) == -1 {
-                return Err("Error pwd");
-            }
-            println!("{}", core::str::from_utf8(&path).unwrap());
-        }
+        "pwd" => match cwd() {
+            Some(path) => println!("{}", path),
+            _ => return Err("Error pwd"),
+        },
         _ => return Err(UNKNOWN_BUILTIN),
     }
     Ok(())
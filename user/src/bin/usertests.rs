@@ -9,25 +9,84 @@ extern crate user_lib;
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
+    ("filetest_badfd\0", "\0", "\0", "\0", 0),
+    ("filetest_cwd\0", "\0", "\0", "\0", 0),
+    ("filetest_dirfd\0", "\0", "\0", "\0", 0),
+    ("filetest_dup2\0", "\0", "\0", "\0", 0),
+    ("filetest_flock\0", "\0", "\0", "\0", 0),
+    ("filetest_fsync\0", "\0", "\0", "\0", 0),
+    ("filetest_getdents\0", "\0", "\0", "\0", 0),
+    ("filetest_iovec\0", "\0", "\0", "\0", 0),
+    ("filetest_lseek\0", "\0", "\0", "\0", 0),
+    ("filetest_mount\0", "\0", "\0", "\0", 0),
+    ("filetest_perm\0", "\0", "\0", "\0", 0),
+    ("filetest_pread\0", "\0", "\0", "\0", 0),
+    ("filetest_quota\0", "\0", "\0", "\0", 0),
+    ("filetest_punch\0", "\0", "\0", "\0", 0),
+    ("filetest_reclock\0", "\0", "\0", "\0", 0),
+    ("filetest_rename\0", "\0", "\0", "\0", 0),
+    ("filetest_rmdir\0", "\0", "\0", "\0", 0),
     ("filetest_simple\0", "\0", "\0", "\0", 0),
+    ("filetest_sync\0", "\0", "\0", "\0", 0),
+    ("filetest_times\0", "\0", "\0", "\0", 0),
+    ("filetest_truncate\0", "\0", "\0", "\0", 0),
+    ("barrier_phases\0", "\0", "\0", "\0", 0),
+    ("block_stats\0", "\0", "\0", "\0", 0),
     ("cat\0", "filea\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
+    ("dd\0", "if=filea\0", "mode=rand\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
+    ("exec_badargs\0", "\0", "\0", "\0", 0),
+    ("exec_cloexec\0", "\0", "\0", "\0", 0),
+    ("exec_lazy\0", "\0", "\0", "\0", 0),
+    ("exec_threads\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
     ("forktest_simple\0", "\0", "\0", "\0", 0),
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
+    ("heap_grow\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
+    ("job_control\0", "\0", "\0", "\0", 0),
+    ("madvise\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("meminfo\0", "\0", "\0", "\0", 0),
+    ("mmap_eof\0", "\0", "\0", "\0", 0),
+    ("mmap_exit_stress\0", "\0", "\0", "\0", 0),
+    ("mmap_fork\0", "\0", "\0", "\0", 0),
+    ("mmap_msync\0", "\0", "\0", "\0", 0),
+    ("mmap_prot\0", "\0", "\0", "\0", 0),
+    ("mmap_rdonly\0", "\0", "\0", "\0", 0),
+    ("mprotect\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
+    ("pipe_nonblock\0", "\0", "\0", "\0", 0),
+    ("pipe_size\0", "\0", "\0", "\0", 0),
+    ("pipe_waiters\0", "\0", "\0", "\0", 0),
     ("pipetest\0", "\0", "\0", "\0", 0),
+    ("poll_pipe\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
+    ("rlimit\0", "\0", "\0", "\0", 0),
+    ("rusage_faults\0", "\0", "\0", "\0", 0),
+    ("seqlock\0", "\0", "\0", "\0", 0),
+    ("shm_share\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("sleep_signal\0", "\0", "\0", "\0", 0),
+    ("spawn_stress\0", "\0", "\0", "\0", 0),
+    ("swap_stress\0", "\0", "\0", "\0", 0),
+    ("sync_destroy\0", "\0", "\0", "\0", 0),
+    ("syzkaller_lite\0", "32\0", "1\0", "\0", 0),
+    ("thread_name\0", "\0", "\0", "\0", 0),
+    ("threads_churn\0", "\0", "\0", "\0", 0),
+    ("times_children\0", "\0", "\0", "\0", 0),
+    ("tty_background\0", "\0", "\0", "\0", 0),
+    ("sig_disposition\0", "\0", "\0", "\0", 0),
+    ("sig_nested\0", "\0", "\0", "\0", 0),
+    ("sig_procmask\0", "\0", "\0", "\0", 0),
     ("sig_simple\0", "\0", "\0", "\0", 0),
     ("sig_simple2\0", "\0", "\0", "\0", 0),
+    ("sig_suspend\0", "\0", "\0", "\0", 0),
     ("sig_tests\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
 ];
@@ -39,6 +98,9 @@ static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("store_fault\0", "\0", "\0", "\0", -11),
 ];
 
+/// exit with 0 unless slower than they recorded on their first run
+static BENCHES: &[(&str, &str, &str, &str, i32)] = &[("spawn_bench\0", "1000\0", "\0", "\0", 0)];
+
 use user_lib::{exec, fork, waitpid};
 
 fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
@@ -100,6 +162,15 @@ fn run_tests(tests: &[(&str, &str, &str, &str, i32)]) -> i32 {
 pub fn main() -> i32 {
     let succ_num = run_tests(SUCC_TESTS);
     let err_num = run_tests(FAIL_TESTS);
+    // timings vary by host, a regression is recorded but doesn't fail the run
+    let bench_num = run_tests(BENCHES);
+    if bench_num != BENCHES.len() as i32 {
+        println!(
+            "\x1b[33mUsertests: {} of {} benchmarks regressed\x1b[0m",
+            BENCHES.len() as i32 - bench_num,
+            BENCHES.len()
+        );
+    }
     if succ_num == SUCC_TESTS.len() as i32 && err_num == FAIL_TESTS.len() as i32 {
         println!(
             "{} of sueecssed apps, {} of failed apps run correctly. \nUsertests passed!",
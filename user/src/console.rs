@@ -30,8 +30,20 @@ macro_rules! println {
     }
 }
 
+/// Ctrl-D, what `getchar` returns at the end of input
+pub const EOT: u8 = 0x04;
+/// stopped reading in the background, or a handler ran
+const EINTR: isize = -4;
+
+/// A byte of stdin, `EOT` at the end of input or on an error. A background job is stopped by SIGTTIN and
+/// reads again once continued.
 pub fn getchar() -> u8 {
     let mut c = [0u8; 1];
-    crate::read(STDIN, &mut c);
-    c[0]
+    loop {
+        match crate::read(STDIN, &mut c) {
+            EINTR => continue,
+            n if n <= 0 => return EOT,
+            _ => return c[0],
+        }
+    }
 }
@@ -0,0 +1,50 @@
+//! The heap, a static pool to start with and grown by `sbrk` once it runs out
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+
+use buddy_system_allocator::LockedHeap;
+
+use crate::sbrk;
+
+const USER_HEAP_SIZE: usize = 0x4000; // 16K
+/// the heap grows by multiples of it
+const HEAP_GROWTH: usize = 0x4000;
+
+// locate at .bss
+static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+
+pub struct Heap(LockedHeap);
+
+#[global_allocator]
+static HEAP: Heap = Heap(LockedHeap::empty());
+
+pub fn init() {
+    unsafe {
+        HEAP.0
+            .lock()
+            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // blocks are aligned to their size, twice the size surely holds one wherever it starts
+        let size = layout.size().max(layout.align()).next_power_of_two() * 2;
+        let size = (size + HEAP_GROWTH - 1) / HEAP_GROWTH * HEAP_GROWTH;
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -4,26 +4,20 @@
 
 use alloc::vec::Vec;
 use bitflags::bitflags;
-use buddy_system_allocator::LockedHeap;
 use syscall::*;
 
 extern crate alloc;
 
 #[macro_use]
 pub mod console;
+mod heap;
 mod lang_item;
 mod net;
 pub use net::*;
+mod seqlock;
+pub use seqlock::SeqLock;
 pub mod syscall;
 
-const USER_HEAP_SIZE: usize = 0x4000; // 16K
-
-// locate at .bss
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
-
-#[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
-
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
@@ -32,10 +26,7 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
+    heap::init();
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         // read argv[i], which is ptr
@@ -91,6 +82,10 @@ bitflags! {
         const RDRW = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// read/write of the fd fail with -11 (EAGAIN) instead of waiting
+        const NONBLOCK = 1 << 11;
+        /// the fd is closed on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
@@ -123,6 +118,71 @@ pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
 
+/// `struct iovec` of readv/writev, one of the buffers
+#[repr(C)]
+pub struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// Read file `fd` to `bufs` one after another in one go, returns the bytes read in all.
+pub fn readv(fd: usize, bufs: &mut [&mut [u8]]) -> isize {
+    let iov: Vec<IoVec> = bufs
+        .iter_mut()
+        .map(|buf| IoVec {
+            base: buf.as_mut_ptr() as usize,
+            len: buf.len(),
+        })
+        .collect();
+    sys_readv(fd, &iov)
+}
+
+/// Write `bufs` one after another to file `fd` in one go, returns the bytes written in all.
+pub fn writev(fd: usize, bufs: &[&[u8]]) -> isize {
+    let iov: Vec<IoVec> = bufs
+        .iter()
+        .map(|buf| IoVec {
+            base: buf.as_ptr() as usize,
+            len: buf.len(),
+        })
+        .collect();
+    sys_writev(fd, &iov)
+}
+
+/// `events`/`revents` of `PollFd`: ready to read, to write, or fd not open (`revents` only)
+pub const POLLIN: i16 = 0x1;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLNVAL: i16 = 0x20;
+
+/// `struct pollfd` of ppoll, one of the fds waited on
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    /// skipped if negative
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+impl PollFd {
+    pub fn new(fd: usize, events: i16) -> Self {
+        Self {
+            fd: fd as i32,
+            events,
+            revents: 0,
+        }
+    }
+}
+
+/// Wait until any of `fds` is ready for its `events`, for `timeout_ms` at most or forever
+/// if `None`. Returns how many fds got `revents`, 0 on timeout, -4 (EINTR) on a signal.
+pub fn poll(fds: &mut [PollFd], timeout_ms: Option<usize>) -> isize {
+    let timeout = timeout_ms.map(|ms| TimeSpec {
+        sec: (ms / 1000) as u64,
+        nsec: (ms % 1000 * 1_000_000) as u64,
+    });
+    sys_ppoll(fds, timeout.as_ref())
+}
+
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code)
 }
@@ -151,8 +211,104 @@ pub fn get_time() -> isize {
     }
 }
 
+/// `clock` of `clock_gettime`: wall time since the epoch, or time since boot
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// Time of `clock` to the precision of the timer, -22 (EINVAL) for an unknown one
+pub fn clock_gettime(clock: usize, ts: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock, ts)
+}
+
+/// Time since boot in ns, for measuring
+pub fn get_time_ns() -> u64 {
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    ts.sec * 1_000_000_000 + ts.nsec
+}
+
+/// sleep cut short by a signal
+const EINTR: isize = -4;
+
+/// Sleep for `ms`, carrying on after a signal cut it short.
 pub fn sleep(ms: usize) {
-    let _ = sys_sleep(ms);
+    let end = get_time() + ms as isize;
+    let mut left = ms as isize;
+    while left > 0 && sys_sleep(left as usize) == EINTR {
+        left = end - get_time();
+    }
+}
+
+/// user/kernel time of the caller and of its reaped children, in us
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+/// Fill `tms`, returns the current time in us.
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms)
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    pub minflt: usize,
+    pub majflt: usize,
+    pub tlb_flushes: usize,
+}
+
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage)
+}
+
+/// `resource` of `prlimit`: bytes of a stack, exec's args take at most half of it
+pub const RLIMIT_STACK: usize = 3;
+/// fds open, the lowest one that can't be
+pub const RLIMIT_NOFILE: usize = 7;
+/// bytes of mmap areas
+pub const RLIMIT_AS: usize = 9;
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Soft limit enforced, and the hard one it may be raised up to
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+/// Limit of `resource` of process `pid` (0 for the caller) to `old_limit` and from `new_limit`,
+/// -1 (EPERM) raising the hard limit but by root.
+pub fn prlimit(
+    pid: usize,
+    resource: usize,
+    new_limit: Option<&RLimit>,
+    old_limit: Option<&mut RLimit>,
+) -> isize {
+    sys_prlimit64(
+        pid,
+        resource,
+        new_limit.map_or(core::ptr::null(), |l| l),
+        old_limit.map_or(core::ptr::null_mut(), |l| l),
+    )
+}
+
+pub fn getrlimit(resource: usize, limit: &mut RLimit) -> isize {
+    prlimit(0, resource, None, Some(limit))
+}
+
+pub fn setrlimit(resource: usize, limit: &RLimit) -> isize {
+    prlimit(0, resource, Some(limit), None)
 }
 
 bitflags! {
@@ -160,6 +316,7 @@ bitflags! {
         const MAP_ANON = 0;
         const MAP_FILE = 1 << 0;
         const MAP_FIXED = 1 << 1;
+        const MAP_PRIVATE = 1 << 2;
     }
 }
 
@@ -178,10 +335,185 @@ pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+bitflags! {
+    pub struct MSyncFlags: u32 {
+        const MS_ASYNC = 1 << 0;
+        const MS_INVALIDATE = 1 << 1;
+        const MS_SYNC = 1 << 2;
+    }
+}
+
+/// Write back the dirty pages of a file mapping in `[start, start + len)`, within what one
+/// mmap call mapped. Returns how many were written, -12 if the range isn't mapped so.
+pub fn msync(start: usize, len: usize, flags: MSyncFlags) -> isize {
+    sys_msync(start, len, flags.bits as usize)
+}
+
+pub const MADV_DONTNEED: usize = 4;
+
+/// Advise the kernel how `[start, start + len)` is used, within what one mmap call mapped or
+/// the heap. `MADV_DONTNEED` gives its pages back: anonymous ones read zero after, file ones
+/// what's in the file.
+pub fn madvise(start: usize, len: usize, advice: usize) -> isize {
+    sys_madvise(start, len, advice)
+}
+
+/// Change the protection of what one mmap call mapped, or a whole segment of the program, to
+/// `prot` (xwr as mmap takes it). -1 if it's part of one, or the heap.
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+
+/// Open shared memory segment `id` as a fd, a new one of `len` bytes zeroed if nobody has it
+/// open or mapped. -22 (EINVAL) if `len` is 0 or more than the one open has.
+pub fn shm_open(id: usize, len: usize) -> isize {
+    sys_shm_open(id, len)
+}
+
+/// Map all of the segment open as `fd` with `prot` (xwr as mmap takes it), returns where.
+/// Forks have it mapped at the same place.
+pub fn shm_map(fd: usize, prot: usize) -> isize {
+    sys_shm_map(fd, prot)
+}
+
+/// Unmap the segment mapped at `start`, it's gone once nobody has it open or mapped
+pub fn shm_unmap(start: usize) -> isize {
+    sys_shm_unmap(start)
+}
+
+/// Move the program break to `addr`, returns where it is afterwards; 0 only tells.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+/// Move the program break by `increment`, returns where it was before, -1 if it can't move
+/// that far.
+pub fn sbrk(increment: isize) -> isize {
+    let old = sys_brk(0);
+    if increment == 0 {
+        return old;
+    }
+    let new = match old.checked_add(increment) {
+        Some(new) if new > 0 => new,
+        _ => return -1,
+    };
+    if sys_brk(new as usize) != new {
+        return -1;
+    }
+    old
+}
+
+/// free physical frames in kernel, for leak checks
+pub fn free_frames() -> isize {
+    sys_free_frames()
+}
+
+/// Counters of the block device since boot, all zero if it keeps none
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockStats {
+    /// requests sent to the device
+    pub requests: usize,
+    /// blocks read or written by them
+    pub blocks: usize,
+    /// blocks sent along with the one before in a single request
+    pub merged: usize,
+    /// requests sent but not completed
+    pub in_flight: usize,
+    pub max_in_flight: usize,
+    /// requests in flight summed up each time one is sent, the mean queue depth over `requests`
+    pub depth_sum: usize,
+    /// time from sending requests to their completion summed up, and the longest, in us
+    pub latency_us: usize,
+    pub max_latency_us: usize,
+}
+
+pub fn block_stats() -> BlockStats {
+    let mut stats = BlockStats::default();
+    sys_block_stats(&mut stats);
+    stats
+}
+
+/// Counters of processes since boot, zombies count as live until they're reaped
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessStats {
+    pub live: usize,
+    /// most ever live at once
+    pub peak: usize,
+    pub created: usize,
+}
+
+pub fn process_stats() -> ProcessStats {
+    let mut stats = ProcessStats::default();
+    sys_process_stats(&mut stats);
+    stats
+}
+
+/// Usage of memory in the kernel, and the pages of this process in memory
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemInfo {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    /// kernel heap in bytes: all of it, taken by blocks handed out, and asked for by them
+    pub heap_total: usize,
+    pub heap_allocated: usize,
+    pub heap_requested: usize,
+    /// blocks the block cache may hold, holds, and holds modified
+    pub cache_capacity: usize,
+    pub cache_cached: usize,
+    pub cache_dirty: usize,
+    /// resident set of the caller, in pages
+    pub rss_pages: usize,
+}
+
+pub fn meminfo() -> MemInfo {
+    let mut info = MemInfo::default();
+    sys_meminfo(&mut info);
+    info
+}
+
 pub fn getpid() -> isize {
     sys_getpid()
 }
 
+pub fn getuid() -> isize {
+    sys_getuid()
+}
+
+pub fn getgid() -> isize {
+    sys_getgid()
+}
+
+/// Act as `uid`, -1 unless root or already it. Root given up is for good.
+pub fn setuid(uid: u16) -> isize {
+    sys_setuid(uid as usize)
+}
+
+/// Act as group `gid`, -1 unless root or already in it.
+pub fn setgid(gid: u16) -> isize {
+    sys_setgid(gid as usize)
+}
+
+/// `pid`/`pgid` 0 mean the caller/the target's own pid
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+
+/// foreground process group of the terminal behind `fd`
+pub fn tcgetpgrp(fd: usize) -> isize {
+    sys_tcgetpgrp(fd)
+}
+
+pub fn tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    sys_tcsetpgrp(fd, pgid)
+}
+
 pub fn fork() -> isize {
     sys_fork()
 }
@@ -216,6 +548,8 @@ pub fn waitpid_n(pid: usize, exit_code: &mut i32) -> isize {
     sys_waitpid(pid as isize, exit_code)
 }
 
+/// Absolute path of cwd to `path` with a trailing nul, -34 (ERANGE) if it doesn't fit, -2
+/// (ENOENT) if cwd is removed.
 pub fn getcwd(path: &mut [u8]) -> isize {
     sys_getcwd(path)
 }
@@ -232,14 +566,96 @@ pub fn chdir(path: &str) -> isize {
     sys_chdir(path)
 }
 
+/// Make the dir open as `fd` cwd, -20 (ENOTDIR) if it's no dir
+pub fn fchdir(fd: usize) -> isize {
+    sys_fchdir(fd as isize)
+}
+
+/// Set permission bits of `path`, -1 unless its owner or root.
+pub fn chmod(path: &str, mode: u16) -> isize {
+    sys_chmod(path, mode as usize)
+}
+
+/// Set owner of `path`, `None` keeps one as is. -1 unless root.
+pub fn chown(path: &str, uid: Option<u16>, gid: Option<u16>) -> isize {
+    let id = |id: Option<u16>| id.map_or(usize::MAX, |id| id as usize);
+    sys_chown(path, id(uid), id(gid))
+}
+
+const AT_REMOVEDIR: usize = 0x200;
 pub fn unlink(path: &str) -> isize {
-    sys_unlinkat(AT_FDCWD, path)
+    sys_unlinkat(AT_FDCWD, path, 0)
+}
+
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR)
+}
+
+/// `unlink`, or `rmdir` if `dir`, of `path` relative to the dir open as `fd`
+pub fn unlinkat(fd: usize, path: &str, dir: bool) -> isize {
+    let flags = if dir { AT_REMOVEDIR } else { 0 };
+    sys_unlinkat(fd as isize, path, flags)
 }
 
 pub fn link(oldpath: &str, newpath: &str) -> isize {
     sys_linkat(AT_FDCWD, oldpath, newpath)
 }
 
+/// `link` with both paths relative to the dir open as `fd`
+pub fn linkat(fd: usize, oldpath: &str, newpath: &str) -> isize {
+    sys_linkat(fd as isize, oldpath, newpath)
+}
+
+pub fn rename(oldpath: &str, newpath: &str) -> isize {
+    sys_renameat(AT_FDCWD, oldpath, newpath)
+}
+
+/// `rename` with both paths relative to the dir open as `fd`
+pub fn renameat(fd: usize, oldpath: &str, newpath: &str) -> isize {
+    sys_renameat(fd as isize, oldpath, newpath)
+}
+
+/// Mount the easy-fs on disk `source`, like "/dev/vdb\0", on dir `target`. Root only
+pub fn mount(source: &str, target: &str) -> isize {
+    sys_mount(source, target, "easy-fs\0")
+}
+
+/// Unmount what's mounted on `target`, -16 (EBUSY) while anything is open or in use on it
+pub fn umount(target: &str) -> isize {
+    sys_umount2(target, 0)
+}
+
+/// Usage and limits of a uid on a filesystem, a limit of 0 is none. Graces are wall time in
+/// ns, by when usage over the soft limit must be back under it; 0 if it's not over.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub blocks: u32,
+    pub inodes: u32,
+    pub block_soft: u32,
+    pub block_hard: u32,
+    pub inode_soft: u32,
+    pub inode_hard: u32,
+    pub block_grace: u64,
+    pub inode_grace: u64,
+}
+
+/// `quotactl` commands, of user quotas
+const Q_GETQUOTA: usize = 0x800007 << 8;
+const Q_SETQUOTA: usize = 0x800008 << 8;
+
+/// Quota of `uid` on the filesystem of `path`, -3 (ESRCH) if it has none. Root may get
+/// anyone's, others their own.
+pub fn getquota(path: &str, uid: u16, quota: &mut Quota) -> isize {
+    sys_quotactl(Q_GETQUOTA, path, uid as usize, quota as *mut _ as usize)
+}
+
+/// Give `uid` the limits of `limits` on the filesystem of `path`, none at all takes its quota
+/// off. Root only
+pub fn setquota(path: &str, uid: u16, limits: &Quota) -> isize {
+    sys_quotactl(Q_SETQUOTA, path, uid as usize, limits as *const _ as usize)
+}
+
 #[repr(C)]
 #[derive(Default)]
 pub struct Stat {
@@ -247,8 +663,12 @@ pub struct Stat {
     pub ino: u64,
     pub mode: StatMode,
     pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
     pub size: u64,
-    pad: [u64; 6],
+    pub atime: TimeSpec,
+    pub mtime: TimeSpec,
+    pub ctime: TimeSpec,
 }
 impl Stat {
     pub fn new() -> Self {
@@ -256,6 +676,23 @@ impl Stat {
     }
 }
 
+/// Wall time since the epoch, or since boot of `CLOCK_MONOTONIC`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeSpec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+/// `nsec` of `utimensat` times: set to the current time, or leave alone
+pub const UTIME_NOW: u64 = (1 << 30) - 1;
+pub const UTIME_OMIT: u64 = (1 << 30) - 2;
+
+/// Set atime and mtime of `path` to `times`, or both to the current time if `None`.
+pub fn utimensat(path: &str, times: Option<&[TimeSpec; 2]>) -> isize {
+    sys_utimensat(AT_FDCWD, path, times)
+}
+
 bitflags! {
     #[derive(Default)]
     pub struct StatMode: u32 {
@@ -264,21 +701,163 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// permission bits, as many of them as are set
+        const PERM  = 0o7777;
+    }
+}
+
+/// `whence` of `lseek`
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// Move the offset of file `fd`, returns the new one.
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+
+/// Read file `fd` at `offset`, its offset stays, -29 (ESPIPE) if it can't seek.
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread64(fd, buf, offset)
+}
+
+/// Write file `fd` at `offset`, its offset stays, -29 (ESPIPE) if it can't seek.
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    sys_pwrite64(fd, buf, offset)
+}
+
+/// Resize file `fd` to `len` bytes, growing with zeros.
+pub fn ftruncate(fd: usize, len: usize) -> isize {
+    sys_ftruncate(fd, len)
+}
+
+pub const FALLOC_FL_KEEP_SIZE: usize = 0x01;
+pub const FALLOC_FL_PUNCH_HOLE: usize = 0x02;
+
+/// Manipulate the space of file `fd` from `offset` to `offset + len`, only a hole may be
+/// punched: `mode` FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, -95 (EOPNOTSUPP) otherwise.
+pub fn fallocate(fd: usize, mode: usize, offset: usize, len: usize) -> isize {
+    sys_fallocate(fd, mode, offset, len)
+}
+
+/// Punch a hole of `len` bytes at `offset` in file `fd`, it reads as zeros and its blocks
+/// are freed. The size stays.
+pub fn punch_hole(fd: usize, offset: usize, len: usize) -> isize {
+    fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, offset, len)
+}
+
+/// Make what's written to file `fd` durable, -22 (EINVAL) if it's not a file.
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+
+/// Make everything written durable, files and shared mappings of them of all processes.
+/// Returns (blocks, pages) written.
+pub fn sync() -> (usize, usize) {
+    let mut counts = [0; 2];
+    sys_sync(&mut counts);
+    (counts[0], counts[1])
+}
+
+/// `operation` of `flock`, `LOCK_NB` or'ed in fails with -11 (EAGAIN) instead of waiting
+pub const LOCK_SH: usize = 1;
+pub const LOCK_EX: usize = 2;
+pub const LOCK_NB: usize = 4;
+pub const LOCK_UN: usize = 8;
+
+/// Take, convert or release the advisory lock on file `fd`, held until `fd` and its dups
+/// are all closed.
+pub fn flock(fd: usize, operation: usize) -> isize {
+    sys_flock(fd, operation)
+}
+
+/// `cmd` of `fcntl`, on fd flags: `FD_CLOEXEC` as `arg`/return
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const FD_CLOEXEC: usize = 1;
+/// `cmd` of `fcntl`, on status flags of the fd: the `OpenFlags` bits as `arg`/return
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+/// `cmd` of `fcntl`, on record locks with a `&mut Flock` as `arg`
+pub const F_GETLK: usize = 5;
+pub const F_SETLK: usize = 6;
+pub const F_SETLKW: usize = 7;
+/// `cmd` of `fcntl`, bytes a pipe buffers
+pub const F_GETPIPE_SZ: usize = 1032;
+
+/// `l_type` of `Flock`
+pub const F_RDLCK: i16 = 0;
+pub const F_WRLCK: i16 = 1;
+pub const F_UNLCK: i16 = 2;
+
+/// Byte-range record lock
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flock {
+    pub l_type: i16,
+    pub l_whence: i16,
+    pub l_start: isize,
+    /// 0 for up to the end of file
+    pub l_len: isize,
+    pub l_pid: i32,
+}
+
+impl Flock {
+    /// `l_type` on `len` bytes from `start`
+    pub fn new(l_type: i16, start: isize, len: isize) -> Self {
+        Self {
+            l_type,
+            l_whence: SEEK_SET as i16,
+            l_start: start,
+            l_len: len,
+            l_pid: 0,
+        }
     }
 }
 
+/// Status flags by `F_GETFL`/`F_SETFL`, only `NONBLOCK` may be set.
+///
+/// Record locks: `F_SETLK` fails with -11 (EAGAIN) on a conflict, `F_SETLKW`
+/// waits instead, or fails with -35 (EDEADLK) if that'd never end.
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
+
 pub fn fstat(fd: usize, stat: &mut Stat) -> isize {
     sys_fstat(fd, stat)
 }
 
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
-    sys_pipe(pipe_fd)
+    sys_pipe2(pipe_fd, 0, 0)
+}
+
+/// Pipe with `NONBLOCK` and `CLOEXEC` on both ends if in `flags`, buffering `size` bytes
+/// rounded up to pages or a page if 0, -22 (EINVAL) above 16 pages.
+pub fn pipe2(pipe_fd: &mut [usize], flags: OpenFlags, size: usize) -> isize {
+    sys_pipe2(pipe_fd, flags.bits(), size)
 }
 
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
 
+/// Duplicate `old_fd` to exactly `new_fd`, closing what `new_fd` was first. The duplicate isn't
+/// close-on-exec. Nothing changes if they're the same, `old_fd` is returned if it's open.
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        return match sys_fcntl(old_fd, F_GETFL, 0) {
+            err if err < 0 => err,
+            _ => new_fd as isize,
+        };
+    }
+    sys_dup3(old_fd, new_fd, 0)
+}
+
+/// dup2 with `flags`, `O_CLOEXEC` the only one, and `new_fd` may not be `old_fd`
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlags) -> isize {
+    sys_dup3(old_fd, new_fd, flags.bits)
+}
+
 pub fn kill(pid: usize, signum: i32) -> isize {
     sys_kill(pid, signum)
 }
@@ -316,22 +895,36 @@ pub const SIGIO: i32 = 29;
 pub const SIGPWR: i32 = 30;
 pub const SIGSYS: i32 = 31;
 
+/// `SignalAction::handler` values that aren't handlers
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
 #[repr(C, align(16))]
 #[derive(Debug, Clone, Copy)]
 pub struct SignalAction {
     pub handler: usize,
+    /// blocked while the handler runs
     pub mask: SignalFlags,
+    pub flags: SignalActionFlags,
 }
 
 impl Default for SignalAction {
     fn default() -> Self {
         Self {
-            handler: 0,
+            handler: SIG_DFL,
             mask: SignalFlags::empty(),
+            flags: SignalActionFlags::empty(),
         }
     }
 }
 
+bitflags! {
+    pub struct SignalActionFlags: u32 {
+        /// the signal isn't blocked while its own handler runs
+        const SA_NODEFER = 1 << 30;
+    }
+}
+
 bitflags! {
     pub struct SignalFlags: i32 {
         const SIGDEF = 1; // Default signal handling
@@ -381,8 +974,27 @@ pub fn sigaction(
     )
 }
 
-pub fn sigprocmask(mask: u32) -> isize {
-    sys_sigprocmask(mask)
+/// `how` of `sigprocmask`
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+pub fn sigprocmask(how: usize, set: Option<&u32>, old_set: Option<&mut u32>) -> isize {
+    sys_sigprocmask(
+        how,
+        set.map_or(core::ptr::null(), |s| s),
+        old_set.map_or(core::ptr::null_mut(), |s| s),
+    )
+}
+
+/// Wait with `mask` in place until a signal is handled, always -4 (EINTR).
+pub fn sigsuspend(mask: u32) -> isize {
+    sys_sigsuspend(mask)
+}
+
+/// Signals raised while blocked, as `SignalFlags` bits.
+pub fn sigpending() -> isize {
+    sys_sigpending()
 }
 
 pub fn sigreturn() -> isize {
@@ -398,6 +1010,8 @@ pub struct Dirent {
     pub ftype: FileType,
     pub name: [u8; NAME_LENGTH_LIMIT],
     pub next_offset: u32,
+    /// inode number, as `Stat::ino`
+    pub ino: u64,
 }
 
 bitflags! {
@@ -406,6 +1020,7 @@ bitflags! {
         const UNKNOWN = 0;
         const DIR = 1 << 0;
         const REG = 1 << 1;
+        const LNK = 1 << 2;
     }
 }
 
@@ -419,10 +1034,35 @@ impl Dirent {
     }
 }
 
+/// Read entries of directory `fd` from where the last call left off, returns how many, 0 at
+/// the end. Entries created or removed in between may or may not be read, but all others are
+/// read exactly once: `next_offset` of an entry stays where it is.
 pub fn getdents(fd: usize, entries: &mut [Dirent]) -> isize {
     sys_getdents(fd, entries)
 }
 
+/// Have `getdents` of directory `fd` start over from the first entry.
+pub fn rewinddir(fd: usize) -> isize {
+    match lseek(fd, 0, SEEK_SET) {
+        0 => 0,
+        _ => -1,
+    }
+}
+
+/// `cmd` of `membarrier`: mask of the commands supported
+pub const MEMBARRIER_CMD_QUERY: usize = 0;
+/// all threads of all processes pass a full memory barrier
+pub const MEMBARRIER_CMD_GLOBAL: usize = 1 << 0;
+/// threads of the caller pass a full memory barrier
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED: usize = 1 << 3;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: usize = 1 << 4;
+
+/// Have the threads `cmd` covers pass a memory barrier, pairs with compiler barriers on their
+/// side. `flags` must be 0.
+pub fn membarrier(cmd: usize, flags: usize) -> isize {
+    sys_membarrier(cmd, flags)
+}
+
 pub fn thread_create(entry: usize, arg: usize) -> isize {
     sys_thread_create(entry, arg)
 }
@@ -432,14 +1072,23 @@ pub fn gettid() -> isize {
 }
 
 pub fn waittid(tid: usize) -> isize {
-    loop {
-        match sys_waittid(tid) {
-            -2 => {
-                yield_();
-            }
-            exit_code => return exit_code,
-        }
-    }
+    sys_waittid(tid)
+}
+
+/// Longest name of a thread in bytes
+pub const THREAD_NAME_MAX: usize = 15;
+
+/// Name thread `tid` of the caller's process `name` (nul terminated), -36 (ENAMETOOLONG) if
+/// it's longer than `THREAD_NAME_MAX`, -3 (ESRCH) if there's no such thread. Threads start
+/// with the program's name, new ones with their creator's.
+pub fn set_thread_name(tid: usize, name: &str) -> isize {
+    sys_set_thread_name(tid, name)
+}
+
+/// Name of thread `tid` to `buf` with a trailing nul, returns its length; -34 (ERANGE) if it
+/// doesn't fit.
+pub fn get_thread_name(tid: usize, buf: &mut [u8]) -> isize {
+    sys_get_thread_name(tid, buf)
 }
 
 pub fn mutex_create() -> isize {
@@ -458,6 +1107,10 @@ pub fn mutex_unlock(mutex_id: usize) -> isize {
     sys_mutex_unlock(mutex_id)
 }
 
+pub fn mutex_destroy(mutex_id: usize) -> isize {
+    sys_mutex_destroy(mutex_id)
+}
+
 pub fn semaphore_create(res_count: usize) -> isize {
     sys_semaphore_create(res_count)
 }
@@ -470,6 +1123,10 @@ pub fn semaphore_release(sem_id: usize) -> isize {
     sys_semaphore_up(sem_id)
 }
 
+pub fn semaphore_destroy(sem_id: usize) -> isize {
+    sys_semaphore_destroy(sem_id)
+}
+
 pub fn condvar_create() -> isize {
     sys_condvar_create()
 }
@@ -481,3 +1138,19 @@ pub fn condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
 pub fn condvar_signal(condvar_id: usize) -> isize {
     sys_condvar_signal(condvar_id)
 }
+
+pub fn condvar_destroy(condvar_id: usize) -> isize {
+    sys_condvar_destroy(condvar_id)
+}
+
+pub fn barrier_create(count: usize) -> isize {
+    sys_barrier_create(count)
+}
+
+pub fn barrier_wait(barrier_id: usize) -> isize {
+    sys_barrier_wait(barrier_id)
+}
+
+pub fn barrier_destroy(barrier_id: usize) -> isize {
+    sys_barrier_destroy(barrier_id)
+}
@@ -11,3 +11,17 @@ pub fn listen(sport: u16) -> isize {
 pub fn accept(socket_fd: usize) -> isize {
     sys_accept(socket_fd)
 }
+
+pub const SO_SNDBUF: usize = 7;
+pub const SO_RCVBUF: usize = 8;
+pub const SO_NONBLOCK: usize = 0o4000;
+/// read/write on a non-blocking socket that would block
+pub const EAGAIN: isize = -11;
+
+pub fn setsockopt(fd: usize, opt: usize, val: usize) -> isize {
+    sys_setsockopt(fd, opt, val)
+}
+
+pub fn getsockopt(fd: usize, opt: usize) -> isize {
+    sys_getsockopt(fd, opt)
+}
@@ -0,0 +1,66 @@
+//! Sequence lock: readers never block the writer, they retry when a write got in the way.
+//! The sequence is odd while a write is in progress, and bumped again once it's done.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// `T` shared among threads, read by copying it out
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// A copy of the data no write tore, retried until one got through untouched
+    pub fn read(&self) -> T {
+        loop {
+            let start = self.seq.load(Ordering::Acquire);
+            if start & 1 == 1 {
+                spin_loop();
+                continue;
+            }
+            // may race with a write, it's only kept if the sequence says there was none
+            let data = unsafe { self.data.get().read_volatile() };
+            // the copy is done before the sequence is checked again
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == start {
+                return data;
+            }
+        }
+    }
+
+    /// Change the data by `f`, writers take turns
+    pub fn write(&self, f: impl FnOnce(&mut T)) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq & 1 == 1 {
+                spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(now) => seq = now,
+            }
+        }
+        // readers see the sequence odd before any of the data changes
+        fence(Ordering::Release);
+        let mut data = unsafe { self.data.get().read_volatile() };
+        f(&mut data);
+        unsafe { self.data.get().write_volatile(data) };
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+}
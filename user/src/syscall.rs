@@ -1,51 +1,112 @@
 use core::arch::asm;
 
-use crate::{Dirent, SignalAction, Stat, TimeVal};
+use crate::{
+    BlockStats, Dirent, IoVec, MemInfo, PollFd, ProcessStats, RLimit, RUsage, SignalAction, Stat,
+    TimeSpec, TimeVal, Tms,
+};
 
 const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
 const SYSCALL_CONNECT: usize = 29;
 const SYSCALL_LISTEN: usize = 30;
 const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_FLOCK: usize = 32;
 const SYSCALL_MKDIRAT: usize = 34;
 const SYSCALL_UNLINKAT: usize = 35;
 const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_FTRUNCATE: usize = 46;
+const SYSCALL_FALLOCATE: usize = 47;
 const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_FCHDIR: usize = 50;
+const SYSCALL_CHMOD: usize = 53;
+const SYSCALL_CHOWN: usize = 54;
 const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_PIPE: usize = 59;
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_QUOTACTL: usize = 60;
 const SYSCALL_GETDENTS: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_READV: usize = 65;
+const SYSCALL_WRITEV: usize = 66;
+const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
+const SYSCALL_PPOLL: usize = 73;
 const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
+const SYSCALL_FSYNC: usize = 82;
+const SYSCALL_UTIMENSAT: usize = 88;
+const SYSCALL_DUP3: usize = 1060;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGSUSPEND: usize = 133;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGPENDING: usize = 136;
 const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_SHM_OPEN: usize = 194;
+const SYSCALL_SHM_MAP: usize = 196;
+const SYSCALL_SHM_UNMAP: usize = 197;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_MEMBARRIER: usize = 283;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
+const SYSCALL_SET_THREAD_NAME: usize = 1003;
+const SYSCALL_GET_THREAD_NAME: usize = 1004;
 const SYSCALL_MUTEX_CREATE: usize = 1010;
 const SYSCALL_MUTEX_LOCK: usize = 1011;
 const SYSCALL_MUTEX_UNLOCK: usize = 1012;
+const SYSCALL_MUTEX_DESTROY: usize = 1013;
 const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
 const SYSCALL_SEMAPHORE_UP: usize = 1021;
 const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
+const SYSCALL_SEMAPHORE_DESTROY: usize = 1023;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
-
-fn syscall(id: usize, args: [usize; 3]) -> isize {
+const SYSCALL_CONDVAR_DESTROY: usize = 1033;
+const SYSCALL_BARRIER_CREATE: usize = 1040;
+const SYSCALL_BARRIER_WAIT: usize = 1041;
+const SYSCALL_BARRIER_DESTROY: usize = 1042;
+const SYSCALL_TCGETPGRP: usize = 1050;
+const SYSCALL_TCSETPGRP: usize = 1051;
+const SYSCALL_FREE_FRAMES: usize = 2000;
+const SYSCALL_BLOCK_STATS: usize = 2001;
+const SYSCALL_PROCESS_STATS: usize = 2002;
+const SYSCALL_MEMINFO: usize = 2003;
+
+/// Raw `ecall`, for ids and args no wrapper takes, like fuzzing them
+pub fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
     unsafe {
         asm!("ecall",
@@ -94,6 +155,39 @@ pub fn sys_write(fd: usize, buf: &[u8]) -> isize {
     syscall!(SYSCALL_WRITE, fd, buf.as_ptr() as usize, buf.len())
 }
 
+pub fn sys_readv(fd: usize, iov: &[IoVec]) -> isize {
+    syscall!(SYSCALL_READV, fd, iov.as_ptr() as usize, iov.len())
+}
+
+pub fn sys_writev(fd: usize, iov: &[IoVec]) -> isize {
+    syscall!(SYSCALL_WRITEV, fd, iov.as_ptr() as usize, iov.len())
+}
+
+pub fn sys_pread64(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    let packed_args = [buf.len(), offset];
+    syscall!(
+        SYSCALL_PREAD64,
+        fd,
+        buf.as_mut_ptr() as usize,
+        packed_args.as_ptr() as usize
+    )
+}
+
+pub fn sys_pwrite64(fd: usize, buf: &[u8], offset: usize) -> isize {
+    let packed_args = [buf.len(), offset];
+    syscall!(
+        SYSCALL_PWRITE64,
+        fd,
+        buf.as_ptr() as usize,
+        packed_args.as_ptr() as usize
+    )
+}
+
+pub fn sys_ppoll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> isize {
+    let timeout = timeout.map_or(0, |t| t as *const _ as usize);
+    syscall!(SYSCALL_PPOLL, fds.as_mut_ptr() as usize, fds.len(), timeout)
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall!(SYSCALL_EXIT, exit_code as usize);
     panic!("sys_exit never return!");
@@ -103,6 +197,18 @@ pub fn sys_yield() -> isize {
     syscall!(SYSCALL_YIELD)
 }
 
+pub fn sys_times(tms: &mut Tms) -> isize {
+    syscall!(SYSCALL_TIMES, tms as *mut _ as usize)
+}
+
+pub fn sys_getrusage(who: isize, usage: &mut RUsage) -> isize {
+    syscall!(SYSCALL_GETRUSAGE, who as usize, usage as *mut _ as usize)
+}
+
+pub fn sys_clock_gettime(clock_id: usize, ts: &mut TimeSpec) -> isize {
+    syscall!(SYSCALL_CLOCK_GETTIME, clock_id, ts as *mut _ as usize)
+}
+
 pub fn sys_get_time(ts: &mut TimeVal) -> isize {
     syscall!(SYSCALL_GET_TIME, ts as *const _ as usize)
 }
@@ -119,14 +225,90 @@ pub fn sys_mmap(
     syscall!(SYSCALL_MMAP, start, len, packed_args.as_ptr() as usize)
 }
 
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall!(SYSCALL_MPROTECT, start, len, prot)
+}
+
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    syscall!(SYSCALL_MSYNC, start, len, flags)
+}
+
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    syscall!(SYSCALL_MADVISE, start, len, advice)
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall!(SYSCALL_BRK, addr)
+}
+
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall!(SYSCALL_MUNMAP, start, len)
 }
 
+pub fn sys_shm_open(id: usize, len: usize) -> isize {
+    syscall!(SYSCALL_SHM_OPEN, id, len)
+}
+
+pub fn sys_shm_map(fd: usize, prot: usize) -> isize {
+    syscall!(SYSCALL_SHM_MAP, fd, prot)
+}
+
+pub fn sys_shm_unmap(start: usize) -> isize {
+    syscall!(SYSCALL_SHM_UNMAP, start)
+}
+
+pub fn sys_free_frames() -> isize {
+    syscall!(SYSCALL_FREE_FRAMES)
+}
+
+pub fn sys_block_stats(stats: &mut BlockStats) -> isize {
+    syscall!(SYSCALL_BLOCK_STATS, stats as *mut _ as usize)
+}
+
+pub fn sys_process_stats(stats: &mut ProcessStats) -> isize {
+    syscall!(SYSCALL_PROCESS_STATS, stats as *mut _ as usize)
+}
+
+pub fn sys_meminfo(info: &mut MemInfo) -> isize {
+    syscall!(SYSCALL_MEMINFO, info as *mut _ as usize)
+}
+
 pub fn sys_getpid() -> isize {
     syscall!(SYSCALL_GETPID)
 }
 
+pub fn sys_getuid() -> isize {
+    syscall!(SYSCALL_GETUID)
+}
+
+pub fn sys_getgid() -> isize {
+    syscall!(SYSCALL_GETGID)
+}
+
+pub fn sys_setuid(uid: usize) -> isize {
+    syscall!(SYSCALL_SETUID, uid)
+}
+
+pub fn sys_setgid(gid: usize) -> isize {
+    syscall!(SYSCALL_SETGID, gid)
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    syscall!(SYSCALL_SETPGID, pid, pgid)
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    syscall!(SYSCALL_GETPGID, pid)
+}
+
+pub fn sys_tcgetpgrp(fd: usize) -> isize {
+    syscall!(SYSCALL_TCGETPGRP, fd)
+}
+
+pub fn sys_tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    syscall!(SYSCALL_TCSETPGRP, fd, pgid)
+}
+
 pub fn sys_fork() -> isize {
     syscall!(SYSCALL_FORK)
 }
@@ -139,6 +321,21 @@ pub fn sys_waitpid(pid: isize, xstatus: &mut i32) -> isize {
     syscall!(SYSCALL_WAITPID, pid as usize, xstatus as *mut _ as usize)
 }
 
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    let packed_args = [new_limit as usize, old_limit as usize];
+    syscall!(
+        SYSCALL_PRLIMIT64,
+        pid,
+        resource,
+        packed_args.as_ptr() as usize
+    )
+}
+
 pub fn sys_getcwd(path: &mut [u8]) -> isize {
     syscall!(SYSCALL_GETCWD, path.as_mut_ptr() as usize, path.len())
 }
@@ -151,8 +348,20 @@ pub fn sys_chdir(path: &str) -> isize {
     syscall!(SYSCALL_CHDIR, path.as_ptr() as usize)
 }
 
-pub fn sys_unlinkat(fd: isize, path: &str) -> isize {
-    syscall!(SYSCALL_UNLINKAT, fd as usize, path.as_ptr() as usize)
+pub fn sys_fchdir(fd: isize) -> isize {
+    syscall!(SYSCALL_FCHDIR, fd as usize)
+}
+
+pub fn sys_chmod(path: &str, mode: usize) -> isize {
+    syscall!(SYSCALL_CHMOD, path.as_ptr() as usize, mode)
+}
+
+pub fn sys_chown(path: &str, uid: usize, gid: usize) -> isize {
+    syscall!(SYSCALL_CHOWN, path.as_ptr() as usize, uid, gid)
+}
+
+pub fn sys_unlinkat(fd: isize, path: &str, flags: usize) -> isize {
+    syscall!(SYSCALL_UNLINKAT, fd as usize, path.as_ptr() as usize, flags)
 }
 
 pub fn sys_linkat(fd: isize, oldpath: &str, newpath: &str) -> isize {
@@ -164,18 +373,98 @@ pub fn sys_linkat(fd: isize, oldpath: &str, newpath: &str) -> isize {
     )
 }
 
+pub fn sys_renameat(fd: isize, oldpath: &str, newpath: &str) -> isize {
+    syscall!(
+        SYSCALL_RENAMEAT,
+        fd as usize,
+        oldpath.as_ptr() as usize,
+        newpath.as_ptr() as usize
+    )
+}
+
+pub fn sys_umount2(target: &str, flags: usize) -> isize {
+    syscall!(SYSCALL_UMOUNT2, target.as_ptr() as usize, flags)
+}
+
+pub fn sys_mount(source: &str, target: &str, fstype: &str) -> isize {
+    syscall!(
+        SYSCALL_MOUNT,
+        source.as_ptr() as usize,
+        target.as_ptr() as usize,
+        fstype.as_ptr() as usize
+    )
+}
+
+pub fn sys_quotactl(cmd: usize, path: &str, id: usize, addr: usize) -> isize {
+    let packed_args = [id, addr];
+    syscall!(
+        SYSCALL_QUOTACTL,
+        cmd,
+        path.as_ptr() as usize,
+        packed_args.as_ptr() as usize
+    )
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    syscall!(SYSCALL_LSEEK, fd, offset as usize, whence)
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
+    syscall!(SYSCALL_FTRUNCATE, fd, len)
+}
+
+pub fn sys_fallocate(fd: usize, mode: usize, offset: usize, len: usize) -> isize {
+    let packed_args = [offset, len];
+    syscall!(SYSCALL_FALLOCATE, fd, mode, packed_args.as_ptr() as usize)
+}
+
+pub fn sys_sync(counts: &mut [usize; 2]) -> isize {
+    syscall!(SYSCALL_SYNC, counts as *mut _ as usize)
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    syscall!(SYSCALL_FSYNC, fd)
+}
+
+pub fn sys_utimensat(fd: isize, path: &str, times: Option<&[TimeSpec; 2]>) -> isize {
+    let times = times.map_or(0, |t| t as *const _ as usize);
+    syscall!(
+        SYSCALL_UTIMENSAT,
+        fd as usize,
+        path.as_ptr() as usize,
+        times
+    )
+}
+
+pub fn sys_flock(fd: usize, operation: usize) -> isize {
+    syscall!(SYSCALL_FLOCK, fd, operation)
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall!(SYSCALL_FCNTL, fd, cmd, arg)
+}
+
 pub fn sys_fstat(fd: usize, stat: &mut Stat) -> isize {
     syscall!(SYSCALL_FSTAT, fd as usize, stat as *mut _ as usize)
 }
 
-pub fn sys_pipe(pipe: &mut [usize]) -> isize {
-    syscall!(SYSCALL_PIPE, pipe.as_mut_ptr() as usize)
+pub fn sys_pipe2(pipe: &mut [usize], flags: u32, size: usize) -> isize {
+    syscall!(
+        SYSCALL_PIPE2,
+        pipe.as_mut_ptr() as usize,
+        flags as usize,
+        size
+    )
 }
 
 pub fn sys_dup(fd: usize) -> isize {
     syscall!(SYSCALL_DUP, fd)
 }
 
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall!(SYSCALL_DUP3, old_fd, new_fd, flags as usize)
+}
+
 pub fn sys_kill(pid: usize, signum: i32) -> isize {
     syscall!(SYSCALL_KILL, pid, signum as usize)
 }
@@ -193,20 +482,30 @@ pub fn sys_sigaction(
     )
 }
 
-pub fn sys_sigprocmask(mask: u32) -> isize {
-    syscall!(SYSCALL_SIGPROCMASK, mask as usize)
+pub fn sys_sigprocmask(how: usize, set: *const u32, old_set: *mut u32) -> isize {
+    syscall!(SYSCALL_SIGPROCMASK, how, set as usize, old_set as usize)
+}
+
+pub fn sys_sigsuspend(mask: u32) -> isize {
+    syscall!(SYSCALL_SIGSUSPEND, mask as usize)
+}
+
+pub fn sys_sigpending() -> isize {
+    syscall!(SYSCALL_SIGPENDING)
 }
 
 pub fn sys_sigreturn() -> isize {
     syscall!(SYSCALL_SIGRETURN)
 }
 
+/// The size of an entry goes in the upper half of the count, the kernel fills in as much
 pub fn sys_getdents(fd: usize, entries: &mut [Dirent]) -> isize {
+    let len = entries.len().min(u32::MAX as usize);
     syscall!(
         SYSCALL_GETDENTS,
         fd,
         entries.as_mut_ptr() as usize,
-        entries.len()
+        len | (core::mem::size_of::<Dirent>() << 32)
     )
 }
 
@@ -222,10 +521,27 @@ pub fn sys_waittid(tid: usize) -> isize {
     syscall!(SYSCALL_WAITTID, tid)
 }
 
+pub fn sys_set_thread_name(tid: usize, name: &str) -> isize {
+    syscall!(SYSCALL_SET_THREAD_NAME, tid, name.as_ptr() as usize)
+}
+
+pub fn sys_get_thread_name(tid: usize, buf: &mut [u8]) -> isize {
+    syscall!(
+        SYSCALL_GET_THREAD_NAME,
+        tid,
+        buf.as_mut_ptr() as usize,
+        buf.len()
+    )
+}
+
 pub fn sys_sleep(ms: usize) -> isize {
     syscall!(SYSCALL_SLEEP, ms)
 }
 
+pub fn sys_membarrier(cmd: usize, flags: usize) -> isize {
+    syscall!(SYSCALL_MEMBARRIER, cmd, flags)
+}
+
 pub fn sys_mutex_create(blocking: bool) -> isize {
     let arg = if blocking { 1 } else { 0 };
     syscall!(SYSCALL_MUTEX_CREATE, arg)
@@ -239,6 +555,10 @@ pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
     syscall!(SYSCALL_MUTEX_UNLOCK, mutex_id)
 }
 
+pub fn sys_mutex_destroy(mutex_id: usize) -> isize {
+    syscall!(SYSCALL_MUTEX_DESTROY, mutex_id)
+}
+
 pub fn sys_semaphore_create(res_count: usize) -> isize {
     syscall!(SYSCALL_SEMAPHORE_CREATE, res_count)
 }
@@ -251,6 +571,10 @@ pub fn sys_semaphore_down(sem_id: usize) -> isize {
     syscall!(SYSCALL_SEMAPHORE_DOWN, sem_id)
 }
 
+pub fn sys_semaphore_destroy(sem_id: usize) -> isize {
+    syscall!(SYSCALL_SEMAPHORE_DESTROY, sem_id)
+}
+
 pub fn sys_condvar_create() -> isize {
     syscall!(SYSCALL_CONDVAR_CREATE)
 }
@@ -263,6 +587,22 @@ pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     syscall!(SYSCALL_CONDVAR_WAIT, condvar_id, mutex_id)
 }
 
+pub fn sys_condvar_destroy(condvar_id: usize) -> isize {
+    syscall!(SYSCALL_CONDVAR_DESTROY, condvar_id)
+}
+
+pub fn sys_barrier_create(count: usize) -> isize {
+    syscall!(SYSCALL_BARRIER_CREATE, count)
+}
+
+pub fn sys_barrier_wait(barrier_id: usize) -> isize {
+    syscall!(SYSCALL_BARRIER_WAIT, barrier_id)
+}
+
+pub fn sys_barrier_destroy(barrier_id: usize) -> isize {
+    syscall!(SYSCALL_BARRIER_DESTROY, barrier_id)
+}
+
 pub fn sys_connect(dest: u32, sport: u16, dport: u16) -> isize {
     syscall!(
         SYSCALL_CONNECT,
@@ -280,3 +620,11 @@ pub fn sys_listen(sport: u16) -> isize {
 pub fn sys_accept(socket_fd: usize) -> isize {
     syscall!(SYSCALL_ACCEPT, socket_fd)
 }
+
+pub fn sys_setsockopt(fd: usize, opt: usize, val: usize) -> isize {
+    syscall!(SYSCALL_SETSOCKOPT, fd, opt, val)
+}
+
+pub fn sys_getsockopt(fd: usize, opt: usize) -> isize {
+    syscall!(SYSCALL_GETSOCKOPT, fd, opt)
+}